@@ -0,0 +1,52 @@
+use std::fmt::Write;
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rsgrad::procar::Procar;
+
+
+// Collinear PROCAR with LORBIT=11 layout
+fn generate_procar(nkpts: usize, nbands: usize, nions: usize) -> String {
+    let orbitals = ["s", "py", "pz", "px", "dxy", "dyz", "dz2", "dxz", "x2-y2"];
+    let mut ret = String::new();
+    writeln!(ret, "PROCAR lm decomposed").unwrap();
+    writeln!(ret, "# of k-points:  {:3}         # of bands:  {:3}         # of ions:  {:3}\n",
+             nkpts, nbands, nions).unwrap();
+
+    for ik in 0 .. nkpts {
+        writeln!(ret, " k-point  {:3} :    {:.8} 0.00000000 0.00000000     weight = {:.8}\n",
+                 ik + 1, ik as f64 / nkpts as f64, 1.0 / nkpts as f64).unwrap();
+        for ib in 0 .. nbands {
+            writeln!(ret, "band  {:3} # energy  {:12.8} # occ.  {:.8}\n",
+                     ib + 1, ib as f64 * 0.1 - 5.0, if ib < nbands / 2 { 1.0 } else { 0.0 }).unwrap();
+            writeln!(ret, "ion      s     py     pz     px    dxy    dyz    dz2    dxz  x2-y2    tot").unwrap();
+            for ii in 0 .. nions {
+                write!(ret, "{:5} ", ii + 1).unwrap();
+                for io in 0 .. orbitals.len() {
+                    write!(ret, " {:.3}", ((ii + io + ib) % 7) as f64 * 0.01).unwrap();
+                }
+                writeln!(ret, "  0.100").unwrap();
+            }
+            write!(ret, "tot  ").unwrap();
+            for _ in 0 .. orbitals.len() {
+                write!(ret, "  0.100").unwrap();
+            }
+            writeln!(ret, "  0.900\n").unwrap();
+        }
+    }
+    ret
+}
+
+
+fn bench_procar(c: &mut Criterion) {
+    let input = generate_procar(40, 100, 20);
+    c.bench_function("parse PROCAR 40 k-points 100 bands 20 ions", |b| {
+        b.iter(|| Procar::parse(&input))
+    });
+}
+
+
+criterion_group!(benches, bench_procar);
+criterion_main!(benches);
@@ -0,0 +1,309 @@
+use std::fmt;
+use std::io;
+use std::fs;
+use std::path::Path;
+use std::collections::HashMap;
+use serde::Serialize;
+use colored::Colorize;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    Mat33,
+};
+use crate::potcar::Potcar;
+use crate::summary::BandGap;
+use crate::format::ELEMENTS;
+use crate::fscorr::_reciprocal;
+
+
+/// Tags of INCAR, keys are in uppercase and values are kept as written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Incar(pub HashMap<String, String>);
+
+impl Incar {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Comments start with '#' or '!', and multiple tags in one line are separated by ';'.
+    pub fn parse(input: &str) -> Self {
+        let tags = input.lines()
+            .map(|l| l.split(['#', '!']).next().unwrap_or_default())
+            .flat_map(|l| l.split(';'))
+            .filter_map(|t| {
+                let (k, v) = t.split_once('=')?;
+                Some((k.trim().to_uppercase(), v.trim().to_string()))
+            })
+            .filter(|(k, _)| !k.is_empty())
+            .collect();
+        Self(tags)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.get(key)?.split_whitespace().next()?.replace(['d', 'D'], "e").parse().ok()
+    }
+
+    pub fn get_i32(&self, key: &str) -> Option<i32> {
+        self.get(key)?.split_whitespace().next()?.parse().ok()
+    }
+
+    /// Logical values like ".TRUE.", "T" and "False".
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        let v = self.get(key)?.trim_start_matches('.').to_uppercase();
+        match v.chars().next()? {
+            'T' => Some(true),
+            'F' => Some(false),
+            _ => None,
+        }
+    }
+}
+
+
+/// Subdivisions of the regular k-mesh in KPOINTS, None for explicit lists and line mode.
+/// Fully automatic meshes ("Auto" with the length l) follow N_i = max(1, l*|b_i| + 0.5).
+pub fn kpoints_mesh(input: &str, cell: &Mat33<f64>) -> Option<[usize; 3]> {
+    let lines = input.lines().collect::<Vec<_>>();
+    if lines.get(1)?.split_whitespace().next()?.parse::<i32>().ok()? != 0 {
+        return None;
+    }
+    let style = lines.get(2)?.trim().chars().next()?.to_ascii_uppercase();
+    let numbers = lines.get(3)?
+        .split_whitespace()
+        .map(|x| x.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()?;
+    match style {
+        'G' | 'M' if numbers.len() >= 3 => Some([numbers[0] as usize, numbers[1] as usize, numbers[2] as usize]),
+        'A' if !numbers.is_empty() => Some(_mesh_of_spacing(cell, 2.0 * std::f64::consts::PI / numbers[0], false)),
+        _ => None,
+    }
+}
+
+// N_i = max(1, ceil(|b_i| / spacing)) as KSPACING, or rounded as fully automatic KPOINTS
+fn _mesh_of_spacing(cell: &Mat33<f64>, spacing: f64, ceil: bool) -> [usize; 3] {
+    let recip = _reciprocal(cell);
+    [0, 1, 2].map(|i| {
+        let b = (recip[i][0].powi(2) + recip[i][1].powi(2) + recip[i][2].powi(2)).sqrt();
+        let n = if ceil { (b / spacing).ceil() } else { (b / spacing + 0.5).floor() };
+        (n as usize).max(1)
+    })
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Severity {
+    Warning,
+    Note,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditItem {
+    pub severity : Severity,
+    pub tag      : String,  // the INCAR tag or input file concerned
+    pub message  : String,
+}
+
+
+/// Cross-checks of the inputs of a finished calculation, i.e. INCAR, POTCAR and KPOINTS against
+/// the structure and band gap in OUTCAR. Missing INCAR tags take the VASP defaults, and the
+/// checks of missing files are skipped.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditReport {
+    pub gap   : Option<f64>,
+    pub items : Vec<AuditItem>,
+}
+
+impl AuditReport {
+    pub fn new(outcar: &Outcar, incar: &Incar, potcar: Option<&Potcar>, kpoints: Option<&str>) -> Self {
+        let mut items = vec![];
+        let mut push = |severity: Severity, tag: &str, message: String| {
+            items.push(AuditItem { severity, tag: tag.to_string(), message });
+        };
+
+        let nions = outcar.nions as usize;
+        let cell = outcar.ion_iters.last().map_or(outcar.cell, |it| it.cell);
+        let gap = _band_gap(outcar);
+        let metallic = gap.is_some_and(|g| g < 0.05);
+        let relaxing = incar.get_i32("NSW").unwrap_or(0) > 0 && (1 ..= 3).contains(&incar.get_i32("IBRION").unwrap_or(-1));
+        let df = outcar.ion_types.iter().filter(|s| _has_df_electrons(s)).cloned().collect::<Vec<_>>();
+
+        // Cutoff energy
+        if let Some(potcar) = potcar {
+            if potcar.symbols() != outcar.ion_types {
+                push(Severity::Warning, "POTCAR", format!("Elements {:?} differ from {:?} of the structure",
+                                                          potcar.symbols(), outcar.ion_types));
+            }
+            let enmax = potcar.0.iter().map(|h| h.enmax).fold(0.0, f64::max);
+            let encut = incar.get_f64("ENCUT").unwrap_or(enmax);
+            let isif = incar.get_i32("ISIF").unwrap_or(if relaxing { 2 } else { 0 });
+            if encut < enmax {
+                push(Severity::Warning, "ENCUT", format!("{} eV is below the largest ENMAX {} eV of POTCAR", encut, enmax));
+            } else if relaxing && isif >= 3 && encut < 1.3 * enmax {
+                push(Severity::Warning, "ENCUT", format!("{} eV is below 1.3 x ENMAX = {:.0} eV, the cell relaxation suffers from Pulay stress",
+                                                         encut, 1.3 * enmax));
+            }
+        }
+
+        // Smearing against the band gap
+        let ismear = incar.get_i32("ISMEAR").unwrap_or(1);
+        let sigma = incar.get_f64("SIGMA").unwrap_or(0.2);
+        match gap {
+            Some(g) if !metallic && ismear > 0 => push(Severity::Warning, "ISMEAR",
+                format!("Methfessel-Paxton smearing (ISMEAR = {}) is wrong for systems with a gap ({:.3} eV), use 0 or -5", ismear, g)),
+            Some(_) if metallic && ismear == -5 && relaxing => push(Severity::Warning, "ISMEAR",
+                "Tetrahedron method (ISMEAR = -5) gives inaccurate forces of metals, use 1 or 2 for relaxations".to_string()),
+            _ => {},
+        }
+        if let Some(it) = outcar.ion_iters.last() {
+            let ts = (it.toten - it.toten_z).abs() * 2.0 / nions as f64 * 1000.0;
+            if ismear >= -1 && ts > 1.0 {
+                push(Severity::Warning, "SIGMA", format!("Smearing energy is {:.2} meV/atom (> 1 meV/atom) with SIGMA = {}, decrease it", ts, sigma));
+            }
+        }
+
+        // K-point density as the length N_i * |a_i|, vacuum directions with one k-point are skipped
+        let mesh = match (kpoints, incar.get_f64("KSPACING")) {
+            (Some(k), _) => kpoints_mesh(k, &cell),
+            (None, Some(spacing)) => Some(_mesh_of_spacing(&cell, spacing, true)),
+            (None, None) => Some(_mesh_of_spacing(&cell, 0.5, true)),
+        };
+        if let Some(mesh) = mesh {
+            let threshold = if metallic { 20.0 } else { 10.0 };
+            let lengths = [0, 1, 2].map(|i| {
+                let a = (cell[i][0].powi(2) + cell[i][1].powi(2) + cell[i][2].powi(2)).sqrt();
+                (a, mesh[i] as f64 * a)
+            });
+            let sparse = (0 .. 3).filter(|&i| lengths[i].1 < threshold && !(mesh[i] == 1 && lengths[i].0 > 12.0)).collect::<Vec<_>>();
+            if !sparse.is_empty() {
+                push(Severity::Warning, "KPOINTS",
+                     format!("Mesh {:?} gives N*|a| = {} Angstrom, below {} Angstrom for {} systems", mesh,
+                             sparse.iter().map(|&i| format!("{:.1}", lengths[i].1)).collect::<Vec<_>>().join(", "),
+                             threshold, if metallic { "metallic" } else { "gapped" }));
+            }
+        }
+
+        // Projection operators in real space
+        let lreal = incar.get("LREAL").map_or("F".to_string(), |v| v.trim_start_matches('.').to_uppercase());
+        let lreal_false = lreal.starts_with('F');
+        if nions <= 20 && !lreal_false {
+            push(Severity::Warning, "LREAL", format!("Real space projection is inaccurate for small cells of {} atoms, use .FALSE.", nions));
+        } else if nions > 20 && lreal_false {
+            push(Severity::Note, "LREAL", format!("LREAL = Auto speeds up cells of {} atoms, keep .FALSE. for the final accurate energies", nions));
+        }
+
+        // Aspherical contributions inside the PAW spheres
+        if !df.is_empty() && incar.get_bool("LASPH") != Some(true) {
+            let functional = ["METAGGA", "LDAU", "LHFCALC"].iter()
+                .filter(|k| incar.get(k).is_some_and(|v| !v.trim_start_matches('.').to_uppercase().starts_with('F')))
+                .cloned()
+                .collect::<Vec<_>>();
+            let reason = if functional.is_empty() { String::new() } else { format!(", required with {}", functional.join(" and ")) };
+            push(Severity::Warning, "LASPH", format!("Not set to .TRUE. with d/f elements {}{}", df.join(" "), reason));
+        }
+
+        // Convergence of the electronic and ionic steps
+        let nelm = incar.get_i32("NELM").unwrap_or(60);
+        let unconverged = outcar.ion_iters.iter()
+            .enumerate()
+            .filter(|(_, it)| it.nscf >= nelm)
+            .map(|(i, _)| (i + 1).to_string())
+            .collect::<Vec<_>>();
+        if !unconverged.is_empty() {
+            push(Severity::Warning, "NELM", format!("SCF reached NELM = {} in ionic step(s) {}, the electronic steps may not be converged",
+                                                    nelm, unconverged.join(" ")));
+        }
+        let nsw = incar.get_i32("NSW").unwrap_or(0);
+        if relaxing && outcar.ion_iters.len() as i32 >= nsw {
+            push(Severity::Warning, "NSW", format!("Relaxation stopped at NSW = {} steps, it may not be converged", nsw));
+        }
+
+        // Spin polarization
+        if outcar.ispin == 2 {
+            let mag = outcar.ion_iters.last().and_then(|it| it.magmom.as_ref()).and_then(|m| m.first()).cloned();
+            if let Some(m) = mag.filter(|m| m.abs() < 0.01) {
+                push(Severity::Note, "ISPIN", format!("Total magnetization is only {:.4} muB, ISPIN = 1 may be enough", m));
+            }
+        }
+
+        Self { gap, items }
+    }
+}
+
+impl Tabular for AuditReport {
+    fn headers(&self) -> Vec<String> {
+        ["severity", "tag", "message"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.items.iter()
+            .map(|i| vec![format!("{:?}", i.severity), i.tag.clone(), i.message.clone()])
+            .collect()
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.gap {
+            Some(g) => writeln!(f, "{}", format!("# Band gap {:.4} eV from OUTCAR", g).bright_green())?,
+            None => writeln!(f, "{}", "# Band gap not found in OUTCAR".bright_green())?,
+        }
+        if self.items.is_empty() {
+            return writeln!(f, "  No problems found.");
+        }
+        for item in self.items.iter() {
+            let severity = match item.severity {
+                Severity::Warning => "Warning".bright_yellow(),
+                Severity::Note => "Note   ".normal(),
+            };
+            writeln!(f, "  {} {:>8}: {}", severity, item.tag, item.message)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn _band_gap(outcar: &Outcar) -> Option<f64> {
+    if outcar.eigvals.is_empty() { return None; }
+    let maxocc = if outcar.ispin == 1 && !outcar.lsorbit { 2.0 } else { 1.0 };
+    let occupied = outcar.occupations.iter().filter(|o| **o > 0.5 * maxocc).count();
+    if occupied == 0 || occupied == outcar.occupations.len() { return None; }
+    Some(BandGap::from_eigenvalues(&outcar.eigvals, &outcar.occupations, outcar.ispin as usize,
+                                   outcar.nkpts as usize, outcar.nbands as usize, maxocc).gap)
+}
+
+// Transition metals, lanthanides and actinides
+fn _has_df_electrons(symbol: &str) -> bool {
+    let z = ELEMENTS.iter().position(|e| *e == symbol).unwrap_or(0);
+    (21 ..= 30).contains(&z) || (39 ..= 48).contains(&z) || (57 ..= 80).contains(&z) || z >= 89
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inputs() {
+        let incar = Incar::parse("SYSTEM = test\n  encut = 520 # cutoff\nISMEAR = 0; SIGMA = 0.05\nLASPH = .TRUE.\nLREAL = Auto ! projection\n");
+        assert_eq!(incar.get_f64("ENCUT"), Some(520.0));
+        assert_eq!(incar.get_i32("ISMEAR"), Some(0));
+        assert_eq!(incar.get_f64("SIGMA"), Some(0.05));
+        assert_eq!(incar.get_bool("LASPH"), Some(true));
+        assert_eq!(incar.get("LREAL"), Some("Auto"));
+        assert_eq!(incar.get("NSW"), None);
+
+        let cell = [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 20.0]];
+        assert_eq!(kpoints_mesh("mesh\n0\nGamma\n 6 6 1\n0 0 0\n", &cell), Some([6, 6, 1]));
+        assert_eq!(kpoints_mesh("mesh\n0\nAuto\n 20\n", &cell), Some([5, 5, 1]));
+        assert_eq!(kpoints_mesh("path\n10\nLine-mode\nReciprocal\n", &cell), None);
+        assert_eq!(_mesh_of_spacing(&cell, 0.5, true), [4, 4, 1]);
+
+        assert!(_has_df_electrons("Fe"));
+        assert!(_has_df_electrons("Ce"));
+        assert!(!_has_df_electrons("O"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::fs;
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use log::info;
+use crate::traits::Tabular;
+use crate::format::Structure;
+use crate::outcar::MatX3;
+
+
+// Contents of ACF.dat written by the Bader charge analysis code of Henkelman group
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acf {
+    pub positions     : MatX3<f64>,  // cartesian coordinates in A
+    pub charges       : Vec<f64>,    // number of electrons in each Bader volume
+    pub min_dists     : Vec<f64>,
+    pub volumes       : Vec<f64>,    // in A^3
+    pub vacuum_charge : f64,
+    pub nelectrons    : f64,
+}
+
+impl Acf {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    pub fn parse(context: &str) -> Self {
+        // "    1    0.0000    0.0000    0.0000    6.8523    1.2345    12.3456"
+        let rows = context.lines()
+            .filter(|l| l.trim_start().starts_with(|c: char| c.is_ascii_digit()))
+            .map(|l| {
+                l.split_whitespace()
+                 .skip(1)
+                 .map(|x| x.parse::<f64>().expect("Cannot parse ACF.dat as float values"))
+                 .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+        assert!(!rows.is_empty(), "No atom found in ACF.dat");
+        assert!(rows.iter().all(|r| r.len() >= 6), "Incomplete rows in ACF.dat");
+
+        let parse_tail = |key: &str| -> f64 {
+            context.lines()
+                .find(|l| l.contains(key))
+                .and_then(|l| l.split(':').nth(1))
+                .map(|x| x.trim().parse::<f64>().expect("Cannot parse ACF.dat as float values"))
+                .unwrap_or(0.0)
+        };
+
+        Self {
+            positions: rows.iter().map(|r| [r[0], r[1], r[2]]).collect(),
+            charges: rows.iter().map(|r| r[3]).collect(),
+            min_dists: rows.iter().map(|r| r[4]).collect(),
+            volumes: rows.iter().map(|r| r[5]).collect(),
+            vacuum_charge: parse_tail("VACUUM CHARGE"),
+            nelectrons: parse_tail("NUMBER OF ELECTRONS"),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BaderCharge {
+    pub index      : usize,  // starts from 1
+    pub symbol     : String,
+    pub charge     : f64,    // electrons in the Bader volume
+    pub zval       : f64,
+    pub net_charge : f64,    // zval - charge, positive for cations
+    pub volume     : f64,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpeciesCharge {
+    pub symbol     : String,
+    pub nions      : usize,
+    pub net_charge : f64,    // averaged over the ions of this species
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BaderCharges {
+    pub ions    : Vec<BaderCharge>,
+    pub species : Vec<SpeciesCharge>,
+    #[serde(skip)]
+    structure   : Structure,
+}
+
+impl BaderCharges {
+    /// `zvals` are the valence electrons of each species in POSCAR, in the same order as POTCAR.
+    pub fn new(acf: &Acf, structure: Structure, zvals: &[f64]) -> Self {
+        let symbols = structure.symbols();
+        assert_eq!(acf.charges.len(), symbols.len(), "Inconsistent ion numbers from ACF.dat and POSCAR");
+        assert_eq!(zvals.len(), structure.ion_types.len(), "Inconsistent species numbers from POTCAR and POSCAR");
+
+        let ion_zvals = zvals.iter()
+            .zip(structure.ions_per_type.iter())
+            .flat_map(|(z, n)| vec![*z; *n as usize])
+            .collect::<Vec<f64>>();
+
+        let ions = symbols.into_iter()
+            .enumerate()
+            .map(|(i, symbol)| BaderCharge {
+                index: i + 1,
+                symbol,
+                charge: acf.charges[i],
+                zval: ion_zvals[i],
+                net_charge: ion_zvals[i] - acf.charges[i],
+                volume: acf.volumes[i],
+            })
+            .collect::<Vec<_>>();
+
+        let mut species: Vec<SpeciesCharge> = vec![];
+        for ion in ions.iter() {
+            match species.iter_mut().find(|s| s.symbol == ion.symbol) {
+                Some(s) => {
+                    s.nions += 1;
+                    s.net_charge += ion.net_charge;
+                },
+                None => species.push(SpeciesCharge { symbol: ion.symbol.clone(), nions: 1, net_charge: ion.net_charge }),
+            }
+        }
+        species.iter_mut().for_each(|s| s.net_charge /= s.nions as f64);
+
+        Self {
+            ions,
+            species,
+            structure,
+        }
+    }
+
+    /// Extended XYZ with the Bader charges and net charges as per-atom properties,
+    /// which can be read by ASE and OVITO.
+    pub fn save_as_extxyz(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving Bader charges to {:?} ...", path.as_ref());
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+
+        let lattice = self.structure.cell.iter()
+            .flat_map(|v| v.iter())
+            .map(|x| format!("{:.8}", x))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(f, "{}", self.ions.len())?;
+        writeln!(f, r#"Lattice="{}" Properties=species:S:1:pos:R:3:bader_charge:R:1:net_charge:R:1 pbc="T T T""#, lattice)?;
+        for (ion, p) in self.ions.iter().zip(self.structure.car_pos.iter()) {
+            writeln!(f, "{:4} {:15.8} {:15.8} {:15.8} {:10.4} {:10.4}",
+                     ion.symbol, p[0], p[1], p[2], ion.charge, ion.net_charge)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tabular for BaderCharges {
+    fn headers(&self) -> Vec<String> {
+        ["index", "symbol", "charge", "zval", "net_charge", "volume"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.ions.iter()
+            .map(|ion| vec![
+                ion.index.to_string(),
+                ion.symbol.clone(),
+                format!("{:.4}", ion.charge),
+                format!("{:.2}", ion.zval),
+                format!("{:.4}", ion.net_charge),
+                format!("{:.4}", ion.volume),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for BaderCharges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "  #Ion Elem    Charge   ZVAL  NetCharge  Volume/A3".bright_green())?;
+        for ion in self.ions.iter() {
+            writeln!(f, "  {:4} {:>4} {:9.4} {:6.2} {} {:10.4}",
+                     ion.index, ion.symbol, ion.charge, ion.zval,
+                     format!("{:10.4}", ion.net_charge).bright_yellow(), ion.volume)?;
+        }
+
+        writeln!(f, "{}", "# Averaged net charges per species".bright_green())?;
+        for s in self.species.iter() {
+            writeln!(f, "  {:>4} x {:<3} {:10.4}", s.symbol, s.nions, s.net_charge)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACF: &str = r#"    #         X           Y           Z       CHARGE      MIN DIST   ATOMIC VOL
+ --------------------------------------------------------------------------------
+    1    1.9097     -0.0000      2.5599      6.4000      1.1000     25.0000
+    2    0.9548      1.6538      5.7154      6.4000      1.1000     25.0000
+    3    2.8645     -1.6538      4.1379     12.2000      1.0000     10.0000
+ --------------------------------------------------------------------------------
+    VACUUM CHARGE:               0.0000
+    VACUUM VOLUME:               0.0000
+    NUMBER OF ELECTRONS:        25.0000
+"#;
+
+    #[test]
+    fn test_parse_acf() {
+        let acf = Acf::parse(ACF);
+        assert_eq!(acf.charges, vec![6.4, 6.4, 12.2]);
+        assert_eq!(acf.positions[2], [2.8645, -1.6538, 4.1379]);
+        assert_eq!(acf.volumes[2], 10.0);
+        assert_eq!(acf.nelectrons, 25.0);
+    }
+
+    #[test]
+    fn test_bader_charges() {
+        let structure = Structure {
+            cell: [[2.86, -1.65, 0.0], [0.0, 3.31, 0.0], [0.0, 0.0, 23.0]],
+            ion_types: vec!["Se".to_string(), "V".to_string()],
+            ions_per_type: vec![2, 1],
+            car_pos: vec![[0.0; 3]; 3],
+            frac_pos: vec![[0.0; 3]; 3],
+        };
+        let charges = BaderCharges::new(&Acf::parse(ACF), structure, &[6.0, 13.0]);
+        assert!((charges.ions[0].net_charge - (-0.4)).abs() < 1E-10);
+        assert!((charges.ions[2].net_charge - 0.8).abs() < 1E-10);
+        assert_eq!(charges.species.len(), 2);
+        assert!((charges.species[0].net_charge - (-0.4)).abs() < 1E-10);
+        assert_eq!(charges.rows()[2], vec!["3", "V", "12.2000", "13.00", "0.8000", "10.0000"]);
+    }
+}
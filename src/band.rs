@@ -0,0 +1,1319 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use colored::Colorize;
+use serde_json::json;
+use log::{
+    info,
+    warn,
+};
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use crate::procar::Procar;
+use crate::selection::Selection;
+use crate::dos::_selected_weight;
+use crate::outcar::Mat33;
+use crate::plot::Plot;
+use crate::settings::Settings;
+use crate::template::{
+    ConfigTemplate,
+    TemplateContext,
+};
+use crate::format::Structure;
+use crate::traits::Tabular;
+use crate::progress::Progress;
+use crate::wavecar::Wavecar;
+use crate::wavdiff::{
+    _common_gvectors,
+    _overlap,
+};
+use crate::pymatgen::{
+    band_structure_to_pymatgen,
+    save_pymatgen_json,
+};
+
+
+// Configuration of `rsgrad band` to compare several calculations, e.g.
+//
+// trace  = true     # optional
+// ewidth = 1.0      # optional
+// select = ["Fe:d", "O:p"]  # optional, fat bands of the selections
+//
+// [[bands]]
+// label   = "PBE"
+// procar  = "pbe/PROCAR"
+// outcar  = "pbe/OUTCAR"     # optional, read E-fermi from it if `efermi` is not given
+// poscar  = "pbe/POSCAR"     # optional, defaults to the POSCAR next to PROCAR
+// kpoints = "pbe/KPOINTS"    # optional, defaults to the KPOINTS next to PROCAR
+// wavecar = "pbe/WAVECAR"    # optional, traces the bands by wavefunction overlaps
+// scissor = 1.2              # optional, shifts the states above E-fermi in eV
+//
+// [[bands]]
+// label   = "HSE"
+// procar  = "hse/PROCAR"
+// efermi  = 5.1234
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BandConfig {
+    #[serde(default = "BandConfig::default_trace")]
+    pub trace  : bool,
+    #[serde(default = "BandConfig::default_ewidth")]
+    pub ewidth : f64,
+    #[serde(default)]
+    pub select : Vec<String>,  // shorthand selections of fat bands like "Fe:d", for all entries
+    pub bands  : Vec<BandEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BandEntry {
+    pub label   : String,
+    pub procar  : PathBuf,
+    #[serde(default)]
+    pub outcar  : Option<PathBuf>,
+    #[serde(default)]
+    pub poscar  : Option<PathBuf>,
+    #[serde(default)]
+    pub kpoints : Option<PathBuf>,
+    #[serde(default)]
+    pub wavecar : Option<PathBuf>,
+    #[serde(default)]
+    pub efermi  : Option<f64>,
+    #[serde(default)]
+    pub scissor : f64,
+}
+
+impl BandConfig {
+    fn default_trace() -> bool { true }
+    fn default_ewidth() -> f64 { 1.0 }
+
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        toml::from_str(&context)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+impl ConfigTemplate for BandConfig {
+    fn template(ctx: &TemplateContext) -> String {
+        let (atoms, orbits) = ctx.example_selection();
+        let select = if orbits.is_empty() { atoms } else { format!("{}:{}", atoms, orbits) };
+        format!(r#"# Configuration of `rsgrad band`, several calculations are overlaid with aligned Fermi levels
+trace  = true           # trace the bands through crossings by the similarity of projections
+ewidth = 1.0            # energy difference in eV costing the same as dissimilar projections
+select = ["{}"]         # shorthand selections of fat bands, e.g. "Fe:d", "O z>10:p"
+
+# Atoms are selected by indices starting from 1, ranges like "1..4", negative indices,
+# element symbols, or spatial filters like "z>10", "fz<0.5" and "layer:1..2".
+{}
+[[bands]]
+label   = "PBE"
+procar  = "./PROCAR"
+# outcar  = "./OUTCAR"    # E-fermi is read from it if `efermi` is not given, defaults to the one next to PROCAR
+# poscar  = "./POSCAR"    # for k-path distances, defaults to the one next to PROCAR
+# kpoints = "./KPOINTS"   # line-mode KPOINTS for labels, defaults to the one next to PROCAR
+# wavecar = "./WAVECAR"   # traces the bands by wavefunction overlaps instead of projections
+# efermi  = 0.0
+# scissor = 0.0           # shifts the states above E-fermi in eV
+
+# [[bands]]
+# label   = "HSE"
+# procar  = "hse/PROCAR"
+"#, select, ctx.comments())
+    }
+}
+
+impl BandEntry {
+    // Files in the same directory of PROCAR
+    fn _sibling(&self, name: &str) -> PathBuf {
+        self.procar.with_file_name(name)
+    }
+
+    pub fn outcar(&self) -> PathBuf {
+        self.outcar.clone().unwrap_or_else(|| self._sibling("OUTCAR"))
+    }
+
+    pub fn poscar(&self) -> PathBuf {
+        self.poscar.clone().unwrap_or_else(|| self._sibling("POSCAR"))
+    }
+
+    pub fn kpoints(&self) -> PathBuf {
+        self.kpoints.clone().unwrap_or_else(|| self._sibling("KPOINTS"))
+    }
+}
+
+
+// Cosine similarity of the projections onto all ions and orbitals of two states
+fn _similarity(procar: &Procar, icomp: usize, ik1: usize, ib1: usize, ik2: usize, ib2: usize) -> f64 {
+    let p1 = procar.band_projections(icomp, ik1, ib1);
+    let p2 = procar.band_projections(icomp, ik2, ib2);
+    let dot = p1.iter().zip(p2.iter()).map(|(a, b)| a * b).sum::<f64>();
+    let norm = (p1.iter().map(|a| a * a).sum::<f64>() * p2.iter().map(|b| b * b).sum::<f64>()).sqrt();
+    if norm < 1E-12 { 0.0 } else { dot / norm }
+}
+
+
+// Minimum cost assignment of a square matrix by the Hungarian algorithm, returns the column
+// assigned to each row.
+fn _assign(cost: &[Vec<f64>]) -> Vec<usize> {
+    let n = cost.len();
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; n + 1];
+    let mut p = vec![0usize; n + 1];  // row assigned to each column, 1-based, 0 for none
+    let mut way = vec![0usize; n + 1];
+
+    for i in 1 ..= n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1 ..= n {
+                if used[j] { continue; }
+                let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                if cur < minv[j] {
+                    minv[j] = cur;
+                    way[j] = j0;
+                }
+                if minv[j] < delta {
+                    delta = minv[j];
+                    j1 = j;
+                }
+            }
+            for j in 0 ..= n {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 { break; }
+        }
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 { break; }
+        }
+    }
+
+    let mut ret = vec![0usize; n];
+    for j in 1 ..= n {
+        ret[p[j] - 1] = j - 1;
+    }
+    ret
+}
+
+
+// Accumulated distances along the k-path, `recip` converts the fractional coordinates to
+// Cartesian ones if given.
+fn _kpath_distances(kpoints: &[[f64; 3]], recip: Option<&Mat33<f64>>) -> Vec<f64> {
+    let to_cart = |k: &[f64; 3]| -> [f64; 3] {
+        match recip {
+            Some(b) => [
+                k[0] * b[0][0] + k[1] * b[1][0] + k[2] * b[2][0],
+                k[0] * b[0][1] + k[1] * b[1][1] + k[2] * b[2][1],
+                k[0] * b[0][2] + k[1] * b[1][2] + k[2] * b[2][2],
+            ],
+            None => *k,
+        }
+    };
+    let mut kdist = vec![0.0f64; kpoints.len()];
+    for ik in 1 .. kpoints.len() {
+        let (a, b) = (to_cart(&kpoints[ik - 1]), to_cart(&kpoints[ik]));
+        let d = ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt();
+        kdist[ik] = kdist[ik - 1] + d;
+    }
+    kdist
+}
+
+
+/// Segments of the k-path in line-mode KPOINTS, each contains `npoints` k-points including both
+/// ends, and the labels of its start and end points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KpathSegments {
+    pub npoints : usize,
+    pub labels  : Vec<[String; 2]>,
+}
+
+impl KpathSegments {
+    /// Returns None if the file doesn't exist or is not in line mode.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    pub fn parse(context: &str) -> Option<Self> {
+        let lines = context.lines().collect::<Vec<_>>();
+        if lines.len() < 6 || !lines[2].trim_start().starts_with(['L', 'l']) { return None; }
+        let npoints = lines[1].split_whitespace().next()?.parse::<usize>().ok()?;
+
+        // "0.0 0.0 0.0 ! G", the label may be absent
+        let ends = lines[4 ..].iter()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.split_once('!').map_or("", |(_, label)| label.trim()))
+            .map(_pretty_label)
+            .collect::<Vec<String>>();
+        if ends.len() < 2 { return None; }
+
+        let labels = ends.chunks_exact(2)
+            .map(|c| [c[0].clone(), c[1].clone()])
+            .collect();
+        Some(Self { npoints, labels })
+    }
+
+    /// Continuous path through the high symmetry points with equal number of k-points per segment,
+    /// e.g. "G X M G".
+    pub fn from_labels(labels: &[String], nkpts: usize) -> Option<Self> {
+        let nseg = labels.len().checked_sub(1).filter(|&n| n > 0)?;
+        if !nkpts.is_multiple_of(nseg) { return None; }
+        Some(Self {
+            npoints: nkpts / nseg,
+            labels: labels.windows(2).map(|w| [_pretty_label(&w[0]), _pretty_label(&w[1])]).collect(),
+        })
+    }
+
+    pub fn nkpts(&self) -> usize {
+        self.npoints * self.labels.len()
+    }
+
+    /// K-point indices and labels of the segment ends, "X|Y" for discontinuous jumps.
+    pub fn ticks(&self) -> Vec<(usize, String)> {
+        let mut ret = vec![(0, self.labels[0][0].clone())];
+        for (i, w) in self.labels.windows(2).enumerate() {
+            let label = if w[0][1] == w[1][0] { w[0][1].clone() } else { format!("{}|{}", w[0][1], w[1][0]) };
+            ret.push(((i + 1) * self.npoints - 1, label));
+        }
+        let last = self.labels.last().unwrap();
+        ret.push((self.nkpts() - 1, last[1].clone()));
+        ret
+    }
+}
+
+pub(crate) fn _pretty_label(label: &str) -> String {
+    match label.trim().to_uppercase().as_str() {
+        "G" | "GAMMA" | "\\GAMMA" | "Γ" => "Γ".to_string(),
+        _ => label.trim().to_string(),
+    }
+}
+
+
+/// Band structure along the k-path of PROCAR, where the bands can be traced through crossings.
+///
+/// The eigenvalues at each k-point are sorted by energy in PROCAR, so crossing bands swap
+/// their indices. Tracing connects the states of neighbouring k-points by the similarity of
+/// their atomic and orbital projections, penalized by the energy difference, and solves the
+/// assignment for all the bands at once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandStructure {
+    pub kpoints : Vec<[f64; 3]>,      // fractional coordinates along the path
+    pub kdist   : Vec<f64>,           // accumulated distance along the path, in 1/Angstrom with 2pi
+    pub efermi  : f64,
+    pub eigvals : Vec<Vec<Vec<f64>>>, // [nspin][nbands][nkpts], relative to E-fermi
+    pub order   : Vec<Vec<Vec<usize>>>, // [nspin][nkpts][nbands], original band index of each traced band
+    pub ticks   : Vec<(usize, String)>, // k-point indices and labels of high symmetry points
+    pub projections : Vec<(String, Vec<Vec<Vec<f64>>>)>,  // (label, [nspin][nbands][nkpts]) fat-band weights
+}
+
+impl BandStructure {
+    /// `recip` is the reciprocal lattice vectors (rows), fractional distances are used if None.
+    pub fn from_procar(procar: &Procar, efermi: f64, recip: Option<&Mat33<f64>>) -> Self {
+        let order = (0 .. procar.nspin)
+            .map(|_| vec![(0 .. procar.nbands).collect::<Vec<usize>>(); procar.nkpts])
+            .collect();
+
+        let mut ret = Self {
+            kpoints: procar.kpoints.clone(),
+            kdist: _kpath_distances(&procar.kpoints, recip),
+            efermi,
+            eigvals: vec![],
+            order,
+            ticks: vec![],
+            projections: vec![],
+        };
+        ret._update_eigvals(procar);
+        ret
+    }
+
+    /// Band structure of eigenvalues given as [nspin][nkpts][nbands] along `kpoints`, e.g. from
+    /// Wannier interpolation. The bands are kept in the energy ordering.
+    pub fn from_eigvals(kpoints: &[[f64; 3]], eigvals: &[Vec<Vec<f64>>], efermi: f64, recip: Option<&Mat33<f64>>) -> Self {
+        let nbands = eigvals.first().and_then(|e| e.first()).map_or(0, |e| e.len());
+        Self {
+            kpoints: kpoints.to_vec(),
+            kdist: _kpath_distances(kpoints, recip),
+            efermi,
+            eigvals: eigvals.iter()
+                .map(|spin| {
+                    (0 .. nbands)
+                        .map(|ib| spin.iter().map(|eigs| eigs[ib] - efermi).collect())
+                        .collect()
+                })
+                .collect(),
+            order: eigvals.iter()
+                .map(|spin| vec![(0 .. nbands).collect::<Vec<usize>>(); spin.len()])
+                .collect(),
+            ticks: vec![],
+            projections: vec![],
+        }
+    }
+
+    /// Labels the high symmetry points, and removes the jumps of k-path distances between
+    /// discontinuous segments. Ignored if the number of k-points doesn't match.
+    pub fn with_kpath(mut self, kpath: &KpathSegments) -> Self {
+        let nkpts = self.kdist.len();
+        if kpath.nkpts() != nkpts {
+            warn!("K-path has {} k-points, but {} found in PROCAR, labels are ignored.", kpath.nkpts(), nkpts);
+            return self;
+        }
+
+        let mut incs = self.kdist.windows(2).map(|w| w[1] - w[0]).collect::<Vec<f64>>();
+        for i in 1 .. kpath.labels.len() {
+            incs[i * kpath.npoints - 1] = 0.0;
+        }
+        for (i, inc) in incs.into_iter().enumerate() {
+            self.kdist[i + 1] = self.kdist[i] + inc;
+        }
+        self.ticks = kpath.ticks();
+        self
+    }
+
+    /// `ewidth` in eV sets the energy penalty, a pair of states with the energy difference of
+    /// `ewidth` costs the same as completely dissimilar projections.
+    pub fn trace(self, procar: &Procar, ewidth: f64) -> Self {
+        let nbands = procar.nbands;
+        self._trace_by(procar, ewidth, |ispin, ik| {
+            let icomp = if procar.lncl { 0 } else { ispin };
+            Ok((0 .. nbands)
+                .map(|ib1| (0 .. nbands).map(|ib2| _similarity(procar, icomp, ik - 1, ib1, ik, ib2)).collect())
+                .collect())
+        }).unwrap()
+    }
+
+    /// Traces the bands by the overlaps |<u_nk|u_mk'>|^2 of the periodic parts of wavefunctions
+    /// at neighbouring k-points instead of the projections, which tell apart the states of the
+    /// same atomic characters. WAVECAR should come from the same calculation as PROCAR, its
+    /// k-points are matched by coordinates, thus the SCF k-points of hybrid functional
+    /// calculations are skipped. Only the bands of two k-points are held in memory.
+    pub fn trace_with_wavecar(self, procar: &Procar, wav: &Wavecar, ewidth: f64) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if wav.nspin != procar.nspin || wav.nbands != procar.nbands {
+            return Err(err(format!("WAVECAR of ISPIN = {} and NBANDS = {} doesn't match PROCAR of ISPIN = {} and NBANDS = {}",
+                                   wav.nspin, wav.nbands, procar.nspin, procar.nbands)));
+        }
+        let ikpts = wav.match_kpoints(&procar.kpoints)
+            .ok_or_else(|| err("K-points of PROCAR are not found in WAVECAR".to_string()))?;
+
+        // Bands of the previous k-point are kept for the next step
+        type Bands = Vec<(Vec<[i32; 3]>, Vec<Vec<Complex<f64>>>)>;
+        let read = |ispin: usize, ik: usize| -> Bands {
+            let gvecs = wav.gvectors(ikpts[ik]);
+            (0 .. wav.nbands).into_par_iter()
+                .map(|ib| wav.read_spinors(ispin, ikpts[ik], ib, &gvecs))
+                .collect()
+        };
+        let mut cache: Option<((usize, usize), Bands)> = None;
+        let progress = Progress::new("Tracing bands by WAVECAR", procar.nspin * procar.nkpts.saturating_sub(1));
+
+        let ret = self._trace_by(procar, ewidth, |ispin, ik| {
+            let prev = match cache.take() {
+                Some((key, bands)) if key == (ispin, ik - 1) => bands,
+                _ => read(ispin, ik - 1),
+            };
+            let next = read(ispin, ik);
+            let pairs = _common_gvectors(&prev[0].0, &next[0].0);
+            let overlaps = prev.par_iter()
+                .map(|(_, c1)| next.iter().map(|(_, c2)| _overlap(c1, c2, &pairs).norm_sqr()).collect())
+                .collect();
+            cache = Some(((ispin, ik), next));
+            progress.inc(1);
+            Ok(overlaps)
+        });
+        progress.finish();
+        ret
+    }
+
+    // Traces the bands by the similarities of states at neighbouring k-points, given as
+    // [nbands][nbands] between the bands of k-points ik-1 and ik in the energy ordering.
+    fn _trace_by<F>(mut self, procar: &Procar, ewidth: f64, mut similarity: F) -> io::Result<Self>
+    where F: FnMut(usize, usize) -> io::Result<Vec<Vec<f64>>> {
+        assert!(ewidth > 0.0, "Energy width of band tracing should be positive");
+        let nbands = procar.nbands;
+        for ispin in 0 .. procar.nspin {
+            for ik in 1 .. procar.nkpts {
+                let sim = similarity(ispin, ik)?;
+                let prev = self.order[ispin][ik - 1].clone();
+                let cost = prev.iter()
+                    .map(|&ib1| {
+                        let e1 = procar.eigval(ispin, ik - 1, ib1);
+                        (0 .. nbands)
+                            .map(|ib2| {
+                                let de = (procar.eigval(ispin, ik, ib2) - e1).abs();
+                                1.0 - sim[ib1][ib2] + de / ewidth
+                            })
+                            .collect::<Vec<f64>>()
+                    })
+                    .collect::<Vec<_>>();
+                self.order[ispin][ik] = _assign(&cost);
+            }
+        }
+        self._update_eigvals(procar);
+        Ok(self)
+    }
+
+    /// Projection weights of each band onto the selections, i.e. the fat bands. Call it after
+    /// `trace`, the weights follow the band order at the time of calling.
+    pub fn with_projections(mut self, procar: &Procar, selections: &[Selection]) -> Self {
+        self.projections = selections.iter()
+            .map(|sel| {
+                let weights = self.order.iter()
+                    .enumerate()
+                    .map(|(ispin, order)| {
+                        let icomp = if procar.lncl { 0 } else { ispin };
+                        (0 .. procar.nbands)
+                            .map(|ib| {
+                                order.iter()
+                                    .enumerate()
+                                    .map(|(ik, o)| _selected_weight(procar, icomp, ik, o[ib], sel))
+                                    .collect()
+                            })
+                            .collect()
+                    })
+                    .collect();
+                (sel.label.clone(), weights)
+            })
+            .collect();
+        self
+    }
+
+    /// Fat bands of arbitrary weights given per state as [nspin][nkpts][nbands] in the band order
+    /// of PROCAR, e.g. the electron and hole weights of excitons. Call it after `trace`.
+    pub fn with_weights(mut self, label: &str, weights: &[Vec<Vec<f64>>]) -> Self {
+        let weights = self.order.iter()
+            .zip(weights.iter())
+            .map(|(order, ws)| {
+                (0 .. ws.first().map_or(0, |w| w.len()))
+                    .map(|ib| {
+                        order.iter()
+                            .zip(ws.iter())
+                            .map(|(o, w)| w[o[ib]])
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        self.projections.push((label.to_string(), weights));
+        self
+    }
+
+    fn _update_eigvals(&mut self, procar: &Procar) {
+        self.eigvals = self.order.iter()
+            .enumerate()
+            .map(|(ispin, order)| {
+                (0 .. procar.nbands)
+                    .map(|ib| {
+                        order.iter()
+                            .enumerate()
+                            .map(|(ik, o)| procar.eigval(ispin, ik, o[ib]) - self.efermi)
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    /// VBM and CBM of each spin channel along the path.
+    pub fn band_edges(&self) -> SpinEdges {
+        let edge = |ispin: usize, occupied: bool| -> Option<BandEdge> {
+            self.eigvals[ispin].iter()
+                .enumerate()
+                .flat_map(|(ib, b)| b.iter().enumerate().map(move |(ik, e)| BandEdge { energy: *e, ik, ib }))
+                .filter(|e| (e.energy <= 0.0) == occupied)
+                .fold(None, |acc: Option<BandEdge>, e| match acc {
+                    Some(a) if (occupied && a.energy >= e.energy) || (!occupied && a.energy <= e.energy) => Some(a),
+                    _ => Some(e),
+                })
+        };
+        let nspin = self.eigvals.len();
+        SpinEdges {
+            vbm: (0 .. nspin).map(|ispin| edge(ispin, true)).collect(),
+            cbm: (0 .. nspin).map(|ispin| edge(ispin, false)).collect(),
+            nocc: self.eigvals.iter()
+                .map(|bands| bands.iter().flatten().filter(|e| **e <= 0.0).count())
+                .collect(),
+        }
+    }
+
+    /// Mean and maximum distances in eV from each band energy below `emax` (relative to E-fermi)
+    /// to the closest one of `reference` at the same k-point, e.g. to check Wannier interpolated
+    /// bands against the DFT ones. None if the k-points don't match.
+    pub fn deviation_from(&self, reference: &BandStructure, emax: f64) -> Option<(f64, f64)> {
+        if self.kdist.len() != reference.kdist.len() { return None; }
+        let nspin = self.eigvals.len().min(reference.eigvals.len());
+        let devs = (0 .. nspin)
+            .flat_map(|ispin| {
+                self.eigvals[ispin].iter()
+                    .flat_map(move |b| b.iter().enumerate())
+                    .filter(|(_, e)| **e <= emax)
+                    .map(move |(ik, e)| {
+                        reference.eigvals[ispin].iter()
+                            .map(|r| (r[ik] - e).abs())
+                            .fold(f64::INFINITY, f64::min)
+                    })
+            })
+            .collect::<Vec<f64>>();
+        if devs.is_empty() { return None; }
+        let max = devs.iter().cloned().fold(0.0, f64::max);
+        Some((devs.iter().sum::<f64>() / devs.len() as f64, max))
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        self.save_as_named_txt(path, "band.txt")
+    }
+
+    /// Saves as `BandStructureSymmLine` of pymatgen in JSON, see `band_structure_to_pymatgen`.
+    pub fn save_as_pymatgen(&self, path: &(impl AsRef<Path> + ?Sized), name: &str, structure: &Structure) -> io::Result<()> {
+        let fname = _prepare_fname(path, name)?;
+        save_pymatgen_json(&band_structure_to_pymatgen(self, structure), &fname)
+    }
+
+    pub fn save_as_named_txt(&self, path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<()> {
+        let fname = _prepare_fname(path, name)?;
+        info!("Saving band structure to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        let nspin = self.eigvals.len();
+        writeln!(f, "# E-fermi = {:.6} eV, energies are relative to E-fermi", self.efermi)?;
+        if !self.ticks.is_empty() {
+            let ticks = self.ticks.iter()
+                .map(|(ik, label)| format!("{}@{:.5}", label, self.kdist[*ik]))
+                .collect::<Vec<_>>();
+            writeln!(f, "# High symmetry points: {}", ticks.join(" "))?;
+        }
+        write!(f, "# {:>10}", "kdist")?;
+        for ispin in 0 .. nspin {
+            for ib in 0 .. self.eigvals[ispin].len() {
+                write!(f, " {:>10}", format!("b{}{}", ib + 1, _spin_suffix(nspin, ispin)))?;
+            }
+        }
+        writeln!(f)?;
+
+        for (ik, k) in self.kdist.iter().enumerate() {
+            write!(f, "  {:10.5}", k)?;
+            for bands in self.eigvals.iter() {
+                for b in bands.iter() {
+                    write!(f, " {:10.5}", b[ik])?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the weights of each projection in the layout of band.txt, named like
+    /// `<prefix>_<label>.txt`.
+    pub fn save_projections_as_txt(&self, path: &(impl AsRef<Path> + ?Sized), prefix: &str) -> io::Result<()> {
+        let nspin = self.eigvals.len();
+        for (label, weights) in self.projections.iter() {
+            let fname = _prepare_fname(path, &format!("{}_{}.txt", prefix, _safe_name(label)))?;
+            info!("Saving fat-band weights of {:?} to {:?} ...", label, &fname);
+            let mut f = fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(&fname)?;
+
+            writeln!(f, "# Projection weights of {}", label)?;
+            write!(f, "# {:>10}", "kdist")?;
+            for (ispin, ws) in weights.iter().enumerate() {
+                for ib in 0 .. ws.len() {
+                    write!(f, " {:>10}", format!("b{}{}", ib + 1, _spin_suffix(nspin, ispin)))?;
+                }
+            }
+            writeln!(f)?;
+
+            for (ik, k) in self.kdist.iter().enumerate() {
+                write!(f, "  {:10.5}", k)?;
+                for ws in weights.iter() {
+                    for w in ws.iter() {
+                        write!(f, " {:10.5}", w[ik])?;
+                    }
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the k-path, all the band energies and all the projection weights into one file
+    /// with labelled columns, one row per k-point, for replotting in gnuplot or Origin. The
+    /// columns are separated by commas for CSV, or by white spaces otherwise.
+    pub fn save_as_wide(&self, path: &(impl AsRef<Path> + ?Sized), name: &str, csv: bool) -> io::Result<()> {
+        let fname = _prepare_fname(path, name)?;
+        info!("Saving band structure in wide format to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        let nspin = self.eigvals.len();
+        let mut headers = vec!["kdist".to_string(), "klabel".to_string()];
+        let mut columns: Vec<&Vec<f64>> = vec![];
+        for (ispin, bands) in self.eigvals.iter().enumerate() {
+            for (ib, b) in bands.iter().enumerate() {
+                headers.push(format!("E_b{}{}", ib + 1, _spin_suffix(nspin, ispin)));
+                columns.push(b);
+            }
+        }
+        for (label, weights) in self.projections.iter() {
+            for (ispin, ws) in weights.iter().enumerate() {
+                for (ib, w) in ws.iter().enumerate() {
+                    headers.push(format!("{}_b{}{}", _safe_name(label), ib + 1, _spin_suffix(nspin, ispin)));
+                    columns.push(w);
+                }
+            }
+        }
+
+        let klabel = |ik: usize| -> String {
+            self.ticks.iter()
+                .find(|(i, _)| *i == ik)
+                .map(|(_, l)| l.clone())
+                .unwrap_or_else(|| "-".to_string())
+        };
+
+        if csv {
+            writeln!(f, "{}", headers.join(","))?;
+            for (ik, k) in self.kdist.iter().enumerate() {
+                write!(f, "{:.5},{}", k, klabel(ik))?;
+                for c in columns.iter() {
+                    write!(f, ",{:.5}", c[ik])?;
+                }
+                writeln!(f)?;
+            }
+        } else {
+            writeln!(f, "# E-fermi = {:.6} eV, energies are relative to E-fermi", self.efermi)?;
+            write!(f, "# {:>10} {:>6}", headers[0], headers[1])?;
+            for h in headers[2..].iter() {
+                write!(f, " {:>10}", h)?;
+            }
+            writeln!(f)?;
+            for (ik, k) in self.kdist.iter().enumerate() {
+                write!(f, "  {:10.5} {:>6}", k, klabel(ik))?;
+                for c in columns.iter() {
+                    write!(f, " {:10.5}", c[ik])?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Layout with the Fermi level and the high symmetry points
+    fn _layout(&self) -> serde_json::Value {
+        let mut shapes = vec![json!({
+            "type": "line", "xref": "paper", "yref": "y",
+            "x0": 0.0, "x1": 1.0, "y0": 0.0, "y1": 0.0,
+            "line": {"dash": "dash", "color": "gray", "width": 1},
+        })];
+        shapes.extend(self.ticks.iter().map(|(ik, _)| json!({
+            "type": "line", "xref": "x", "yref": "paper",
+            "x0": self.kdist[*ik], "x1": self.kdist[*ik], "y0": 0.0, "y1": 1.0,
+            "line": {"color": "gray", "width": 1},
+        })));
+        let xaxis = if self.ticks.is_empty() {
+            json!({"title": "k-path", "showticklabels": false})
+        } else {
+            json!({
+                "tickvals": self.ticks.iter().map(|(ik, _)| self.kdist[*ik]).collect::<Vec<f64>>(),
+                "ticktext": self.ticks.iter().map(|(_, label)| label.clone()).collect::<Vec<String>>(),
+            })
+        };
+
+        json!({
+            "title": "Band structure",
+            "xaxis": xaxis,
+            "yaxis": {"title": "E-Ef (eV)"},
+            "showlegend": false,
+            "shapes": shapes,
+        })
+    }
+
+    // Spin down bands are plotted with dashed lines.
+    fn _plot(&self) -> Plot {
+        let mut plot = Plot::new().layout(self._layout());
+
+        for (ispin, bands) in self.eigvals.iter().enumerate() {
+            for (ib, b) in bands.iter().enumerate() {
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": format!("band {}{}", ib + 1, if ispin == 1 { " down" } else { "" }),
+                    "x": self.kdist,
+                    "y": b,
+                    "line": {"dash": if ispin == 1 { "dash" } else { "solid" }},
+                }));
+            }
+        }
+
+        // Fat bands as markers sized by the weights, bands are separated by nulls
+        for (isel, (label, weights)) in self.projections.iter().enumerate() {
+            for (ispin, ws) in weights.iter().enumerate() {
+                let (mut x, mut y, mut size) = (vec![], vec![], vec![]);
+                for (b, w) in self.eigvals[ispin].iter().zip(ws.iter()) {
+                    x.extend(self.kdist.iter().map(|k| Some(*k)).chain([None]));
+                    y.extend(b.iter().map(|e| Some(*e)).chain([None]));
+                    size.extend(w.iter().map(|w| w.max(0.0) * FATBAND_SIZE).chain([0.0]));
+                }
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "markers",
+                    "name": format!("{}{}", label, if ispin == 1 { " down" } else { "" }),
+                    "x": x,
+                    "y": y,
+                    "marker": {
+                        "size": size,
+                        "color": Settings::global().plot.color(isel, &COLORS),
+                        "opacity": 0.6,
+                    },
+                }));
+            }
+        }
+        if !self.projections.is_empty() {
+            plot.layout["showlegend"] = json!(true);
+        }
+
+        // VBM and CBM of the majority and minority spins
+        if self.eigvals.len() == 2 {
+            let edges = self.band_edges();
+            let majority = edges.majority();
+            for ispin in 0 .. 2 {
+                let spin = if ispin == 0 { "up" } else { "down" };
+                let role = if ispin == majority { "majority" } else { "minority" };
+                let points = vec![("VBM", edges.vbm[ispin]), ("CBM", edges.cbm[ispin])].into_iter()
+                    .filter_map(|(name, e)| e.map(|e| (name, e)))
+                    .collect::<Vec<_>>();
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "markers+text",
+                    "name": format!("edges {} ({})", spin, role),
+                    "x": points.iter().map(|(_, e)| self.kdist[e.ik]).collect::<Vec<_>>(),
+                    "y": points.iter().map(|(_, e)| e.energy).collect::<Vec<_>>(),
+                    "text": points.iter().map(|(name, _)| format!("{} {}", name, spin)).collect::<Vec<_>>(),
+                    "textposition": points.iter().map(|(name, _)| if *name == "VBM" { "bottom center" } else { "top center" }).collect::<Vec<_>>(),
+                    "marker": {
+                        "size": 10,
+                        "symbol": if ispin == majority { "circle" } else { "diamond" },
+                        "color": if ispin == majority { COLORS[1] } else { COLORS[0] },
+                    },
+                }));
+            }
+        }
+
+        plot
+    }
+
+    /// Spin down bands are plotted with dashed lines.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        self.save_as_named_html(path, "band.html")
+    }
+
+    pub fn save_as_named_html(&self, path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<()> {
+        let fname = _prepare_fname(path, name)?;
+        self._plot().save_html(&fname)
+    }
+
+    /// Saves the plot as a static image, see `Plot::save_image`.
+    pub fn save_as_image(&self, path: &(impl AsRef<Path> + ?Sized), dpi: f64) -> io::Result<()> {
+        self._plot().save_image(path, dpi)
+    }
+}
+
+
+/// Band edge of one spin channel, the energy is relative to E-fermi.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BandEdge {
+    pub energy : f64,
+    pub ik     : usize,
+    pub ib     : usize,  // index of the traced band
+}
+
+
+/// Band edges of each spin channel, the states at or below E-fermi are occupied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpinEdges {
+    pub vbm  : Vec<Option<BandEdge>>,  // [nspin]
+    pub cbm  : Vec<Option<BandEdge>>,  // [nspin]
+    pub nocc : Vec<usize>,             // [nspin], number of occupied states along the path
+}
+
+impl SpinEdges {
+    /// Band gap of one spin channel, None if all the states are at one side.
+    pub fn gap(&self, ispin: usize) -> Option<f64> {
+        Some(self.cbm[ispin]?.energy - self.vbm[ispin]?.energy)
+    }
+
+    /// Spin-flip gaps CBM(down) - VBM(up) and CBM(up) - VBM(down), ISPIN=2 only.
+    pub fn spin_flip_gaps(&self) -> Option<[f64; 2]> {
+        if self.vbm.len() != 2 { return None; }
+        Some([self.cbm[1]?.energy - self.vbm[0]?.energy,
+              self.cbm[0]?.energy - self.vbm[1]?.energy])
+    }
+
+    /// The spin channel with more occupied states.
+    pub fn majority(&self) -> usize {
+        if self.nocc.len() == 2 && self.nocc[1] > self.nocc[0] { 1 } else { 0 }
+    }
+}
+
+
+// Splitting of one spin-degenerate state of the calculation without SOC, energies relative to
+// E-fermi of each calculation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SocSplitting {
+    pub ik        : usize,
+    pub label     : String,
+    pub band      : usize,  // index of the state without SOC in energy ordering, starting from 1
+    pub nonsoc    : f64,
+    pub lower     : f64,    // the pair of states with SOC
+    pub upper     : f64,
+    pub splitting : f64,    // upper - lower
+    pub shift     : f64,    // center of the pair - nonsoc
+}
+
+
+/// SOC induced splittings at the selected k-points. The states without SOC, counted twice for
+/// ISPIN=1, are matched one-to-one to the states with SOC in energy ordering at each k-point,
+/// thus the calculations should share the same k-points and the number of bands with SOC
+/// should be doubled.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SocComparison(pub Vec<SocSplitting>);
+
+impl SocComparison {
+    /// `kpoints` are (index, label) of the k-points, only the states without SOC within `window`
+    /// eV around E-fermi are reported.
+    pub fn new(nonsoc: &BandStructure, soc: &BandStructure, kpoints: &[(usize, String)], window: f64) -> Self {
+        let sorted = |bs: &BandStructure, ik: usize, twice: bool| {
+            let mut v = bs.eigvals.iter()
+                .flat_map(|spin| spin.iter().map(|b| b[ik]))
+                .flat_map(|e| if twice { vec![e, e] } else { vec![e] })
+                .collect::<Vec<f64>>();
+            v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            v
+        };
+        let twice = nonsoc.eigvals.len() == 1;
+
+        let mut ret = vec![];
+        for (ik, label) in kpoints.iter() {
+            if *ik >= nonsoc.kdist.len() || *ik >= soc.kdist.len() {
+                continue;
+            }
+            let e0 = sorted(nonsoc, *ik, twice);
+            let e1 = sorted(soc, *ik, false);
+            for (i, (pair0, pair1)) in e0.chunks_exact(2).zip(e1.chunks_exact(2)).enumerate() {
+                let nonsoc = (pair0[0] + pair0[1]) / 2.0;
+                if nonsoc.abs() > window {
+                    continue;
+                }
+                ret.push(SocSplitting {
+                    ik: *ik,
+                    label: label.clone(),
+                    band: if twice { i + 1 } else { 2 * i + 1 },
+                    nonsoc,
+                    lower: pair1[0],
+                    upper: pair1[1],
+                    splitting: pair1[1] - pair1[0],
+                    shift: (pair1[0] + pair1[1]) / 2.0 - nonsoc,
+                });
+            }
+        }
+        Self(ret)
+    }
+}
+
+impl Tabular for SocComparison {
+    fn headers(&self) -> Vec<String> {
+        ["kpoint", "label", "band", "nonsoc", "lower", "upper", "splitting", "shift"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|s| vec![
+                (s.ik + 1).to_string(),
+                s.label.clone(),
+                s.band.to_string(),
+                s.nonsoc.to_string(),
+                s.lower.to_string(),
+                s.upper.to_string(),
+                s.splitting.to_string(),
+                s.shift.to_string(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for SocComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# SOC splittings, energies relative to E-fermi of each calculation".bright_green())?;
+        writeln!(f, "{}", format!("  {:>7} {:>6} {:>5} {:>10} {:>10} {:>10} {:>13} {:>10}",
+                                  "K-point", "Label", "Band", "NoSOC(eV)", "Lower(eV)", "Upper(eV)", "Splitting(eV)", "Shift(eV)").bright_green())?;
+        for s in self.0.iter() {
+            writeln!(f, "  {:7} {:>6} {:5} {:10.4} {:10.4} {:10.4} {} {:10.4}",
+                     s.ik + 1, s.label, s.band, s.nonsoc, s.lower, s.upper,
+                     format!("{:13.4}", s.splitting).bright_yellow(), s.shift)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Marker size of fat bands with the weight of 1
+const FATBAND_SIZE: f64 = 12.0;
+
+const COLORS: [&str; 8] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+// Overlays several band structures with their own Fermi levels at zero. The k-path distances
+// are rescaled to the first one, whose high symmetry points are labelled.
+fn _comparison_plot(bands: &[(String, BandStructure)]) -> Plot {
+    assert!(!bands.is_empty(), "No band structures to compare");
+    let reference = &bands[0].1;
+    let kmax = *reference.kdist.last().unwrap();
+
+    let mut layout = reference._layout();
+    layout["showlegend"] = json!(true);
+    let mut plot = Plot::new().layout(layout);
+
+    for (icalc, (label, bs)) in bands.iter().enumerate() {
+        let scale = match bs.kdist.last() {
+            Some(k) if *k > 0.0 => kmax / k,
+            _ => 1.0,
+        };
+        let kdist = bs.kdist.iter().map(|k| k * scale).collect::<Vec<f64>>();
+        let color = Settings::global().plot.color(icalc, &COLORS);
+
+        for (ispin, eigs) in bs.eigvals.iter().enumerate() {
+            for (ib, b) in eigs.iter().enumerate() {
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": label,
+                    "legendgroup": label,
+                    "showlegend": ispin == 0 && ib == 0,
+                    "x": kdist,
+                    "y": b,
+                    "line": {"color": color, "dash": if ispin == 1 { "dash" } else { "solid" }},
+                }));
+            }
+        }
+    }
+
+    plot
+}
+
+/// Overlays several band structures with their own Fermi levels at zero. The k-path distances
+/// are rescaled to the first one, whose high symmetry points are labelled.
+pub fn save_comparison_html(bands: &[(String, BandStructure)], path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+    let fname = _prepare_fname(path, "band.html")?;
+    _comparison_plot(bands).save_html(&fname)
+}
+
+/// Saves the overlaid band structures as a static image, see `Plot::save_image`.
+pub fn save_comparison_image(bands: &[(String, BandStructure)], path: &(impl AsRef<Path> + ?Sized), dpi: f64) -> io::Result<()> {
+    _comparison_plot(bands).save_image(path, dpi)
+}
+
+
+fn _spin_suffix(nspin: usize, ispin: usize) -> &'static str {
+    match (nspin, ispin) {
+        (1, _) => "",
+        (_, 0) => "_up",
+        _      => "_dn",
+    }
+}
+
+
+// Labels like "Fe:d" or "1..4" in file names and column headers
+fn _safe_name(label: &str) -> String {
+    label.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_")
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::RawSelection;
+    use crate::wavecar::{
+        _gvectors,
+        _ngrid,
+    };
+    use crate::wavecar::tests::_write_wavecar;
+
+    #[test]
+    fn test_band_config() {
+        let input = r#"
+[[bands]]
+label  = "PBE"
+procar = "pbe/PROCAR"
+
+[[bands]]
+label  = "HSE"
+procar = "hse/PROCAR"
+outcar = "hse/OUTCAR.scf"
+efermi = 5.0
+"#;
+        let config: BandConfig = toml::from_str(input).unwrap();
+        assert!(config.trace);
+        assert_eq!(config.ewidth, 1.0);
+        assert_eq!(config.bands[0].poscar(), PathBuf::from("pbe/POSCAR"));
+        assert_eq!(config.bands[1].outcar(), PathBuf::from("hse/OUTCAR.scf"));
+        assert_eq!(config.bands[1].efermi, Some(5.0));
+        assert_eq!(config.bands[1].scissor, 0.0);
+
+        let config: BandConfig = toml::from_str(&BandConfig::template(&TemplateContext::default())).unwrap();
+        assert_eq!(config.select, vec!["1"]);
+        assert_eq!(config.bands[0].procar, PathBuf::from("./PROCAR"));
+    }
+
+    #[test]
+    fn test_kpath_segments() {
+        let input = r#"k-path
+  3
+Line-mode
+Reciprocal
+  0.0 0.0 0.0 ! \Gamma
+  0.5 0.0 0.0 ! X
+
+  0.5 0.0 0.0 ! X
+  0.5 0.5 0.0 ! M
+
+  0.0 0.0 0.5 ! Z
+  0.0 0.0 0.0 ! G
+"#;
+        let kpath = KpathSegments::parse(input).unwrap();
+        assert_eq!(kpath.npoints, 3);
+        assert_eq!(kpath.labels[0], ["Γ".to_string(), "X".to_string()]);
+        assert_eq!(kpath.ticks(), vec![(0, "Γ".to_string()), (2, "X".to_string()),
+                                       (5, "M|Z".to_string()), (8, "Γ".to_string())]);
+        assert_eq!(KpathSegments::parse("Automatic\n0\nGamma\n4 4 4\n0 0 0\n"), None);
+
+        let labels = ["G", "X", "M"].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let kpath = KpathSegments::from_labels(&labels, 6).unwrap();
+        assert_eq!(kpath.ticks(), vec![(0, "Γ".to_string()), (2, "X".to_string()), (5, "M".to_string())]);
+        assert_eq!(KpathSegments::from_labels(&labels, 7), None);
+    }
+
+    #[test]
+    fn test_assign() {
+        let cost = vec![vec![4.0, 1.0, 3.0],
+                        vec![2.0, 0.0, 5.0],
+                        vec![3.0, 2.0, 2.0]];
+        assert_eq!(_assign(&cost), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_trace_crossing() {
+        // An s-like band going up crosses a d-like band going down between k-points 2 and 3
+        let input = r#"PROCAR lm decomposed
+# of k-points:    3         # of bands:   2         # of ions:   1
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 0.33333333
+
+band     1 # energy   -1.00000000 # occ.  1.00000000
+
+ion      s      d    tot
+    1  0.900  0.100  1.000
+tot    0.900  0.100  1.000
+
+band     2 # energy    1.00000000 # occ.  0.00000000
+
+ion      s      d    tot
+    1  0.100  0.900  1.000
+tot    0.100  0.900  1.000
+
+ k-point     2 :    0.25000000 0.00000000 0.00000000     weight = 0.33333333
+
+band     1 # energy   -0.20000000 # occ.  1.00000000
+
+ion      s      d    tot
+    1  0.850  0.150  1.000
+tot    0.850  0.150  1.000
+
+band     2 # energy    0.20000000 # occ.  0.00000000
+
+ion      s      d    tot
+    1  0.150  0.850  1.000
+tot    0.150  0.850  1.000
+
+ k-point     3 :    0.50000000 0.00000000 0.00000000     weight = 0.33333333
+
+band     1 # energy   -0.60000000 # occ.  1.00000000
+
+ion      s      d    tot
+    1  0.100  0.900  1.000
+tot    0.100  0.900  1.000
+
+band     2 # energy    0.60000000 # occ.  0.00000000
+
+ion      s      d    tot
+    1  0.900  0.100  1.000
+tot    0.900  0.100  1.000
+
+"#;
+        let procar = Procar::parse(input);
+        let bands = BandStructure::from_procar(&procar, 0.0, None);
+        assert_eq!(bands.kdist, vec![0.0, 0.25, 0.5]);
+        assert_eq!(bands.eigvals[0][0], vec![-1.0, -0.2, -0.6]);
+
+        let kpath = KpathSegments::from_labels(&["G".to_string(), "X".to_string()], 3).unwrap();
+        let bands = bands.with_kpath(&kpath).trace(&procar, 2.0);
+        assert_eq!(bands.ticks[1], (2, "X".to_string()));
+        assert_eq!(bands.order[0][2], vec![1, 0]);
+        assert_eq!(bands.eigvals[0][0], vec![-1.0, -0.2, 0.6]);
+        assert_eq!(bands.eigvals[0][1], vec![1.0, 0.2, -0.6]);
+
+        // Fat bands follow the traced bands
+        let sel = RawSelection::from_shorthand("1:s").parse(procar.nions, &procar.orbitals);
+        let bands = bands.with_projections(&procar, &[sel]);
+        assert_eq!(bands.projections[0].0, "1:s");
+        assert_eq!(bands.projections[0].1[0][0], vec![0.9, 0.85, 0.9]);
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        bands.save_as_wide(dir.path(), "band_wide.csv", true).unwrap();
+        let csv = fs::read_to_string(dir.path().join("band_wide.csv")).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "kdist,klabel,E_b1,E_b2,1_s_b1,1_s_b2");
+        assert_eq!(lines[3], "0.50000,X,0.60000,-0.60000,0.90000,0.10000");
+    }
+
+    #[test]
+    fn test_trace_with_wavecar() {
+        // Two bands of the same character crossing between k-points 2 and 3, only the
+        // wavefunctions tell them apart: the plane waves at G = 0 and G = (1, 0, 0).
+        let kpoints = vec![[0.0, 0.0, 0.0], [0.25, 0.0, 0.0], [0.5, 0.0, 0.0]];
+        let eigvals = vec![-1.0, 1.0, -0.2, 0.2, -0.6, 0.6];
+        let procar = Procar {
+            nkpts: 3,
+            nbands: 2,
+            nions: 1,
+            nspin: 1,
+            lncl: false,
+            orbitals: vec!["s".to_string()],
+            kpoints: kpoints.clone(),
+            weights: vec![1.0 / 3.0; 3],
+            eigvals: eigvals.clone(),
+            occupations: vec![1.0, 0.0, 1.0, 0.0, 1.0, 0.0],
+            projections: vec![1.0; 6],
+            phases: None,
+        };
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        let cell = [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]];
+        let plane_wave = |k: &[f64; 3], g: [i32; 3]| {
+            _gvectors(&cell, 60.0, _ngrid(&cell, 60.0), k, None).iter()
+                .map(|x| if *x == g { Complex::new(1.0, 0.0) } else { Complex::new(0.0, 0.0) })
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = ([0, 0, 0], [1, 0, 0]);
+        let coeffs = vec![kpoints.iter()
+            .zip([(a, b), (a, b), (b, a)])
+            .map(|(k, (g1, g2))| vec![plane_wave(k, g1), plane_wave(k, g2)])
+            .collect::<Vec<_>>()];
+        let eigs = vec![eigvals.chunks(2).map(|c| c.to_vec()).collect::<Vec<_>>()];
+        _write_wavecar(&path, 45210, 60.0, &cell, &kpoints, &eigs, &coeffs);
+        let wav = Wavecar::from_file(&path).unwrap();
+
+        // The energies connect the lower bands, but the wavefunctions connect the crossing ones
+        let bands = BandStructure::from_procar(&procar, 0.0, None);
+        assert_eq!(bands.clone().trace(&procar, 1.0).order[0][2], vec![0, 1]);
+        let traced = bands.clone().trace_with_wavecar(&procar, &wav, 1.0).unwrap();
+        assert_eq!(traced.order[0][2], vec![1, 0]);
+        assert_eq!(traced.eigvals[0][0], vec![-1.0, -0.2, 0.6]);
+
+        let mut other = procar.clone();
+        other.kpoints[1] = [0.3, 0.0, 0.0];
+        assert!(bands.trace_with_wavecar(&other, &wav, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_spin_edges() {
+        let bands = BandStructure {
+            kpoints: vec![[0.0; 3], [0.25, 0.0, 0.0], [0.5, 0.0, 0.0]],
+            kdist: vec![0.0, 0.5, 1.0],
+            efermi: 0.0,
+            eigvals: vec![
+                vec![vec![-1.0, -0.5, -0.8], vec![1.0, 0.8, 1.2], vec![2.0, 2.0, 2.0]],
+                vec![vec![-1.2, -1.0, -0.3], vec![0.6, 0.9, 0.7], vec![2.0, 2.0, 2.0]],
+            ],
+            order: vec![],
+            ticks: vec![],
+            projections: vec![],
+        };
+        let edges = bands.band_edges();
+        assert_eq!(edges.vbm[0], Some(BandEdge { energy: -0.5, ik: 1, ib: 0 }));
+        assert_eq!(edges.cbm[1], Some(BandEdge { energy: 0.6, ik: 0, ib: 1 }));
+        assert!((edges.gap(0).unwrap() - 1.3).abs() < 1E-10);
+        assert!((edges.gap(1).unwrap() - 0.9).abs() < 1E-10);
+
+        let flip = edges.spin_flip_gaps().unwrap();
+        assert!((flip[0] - 1.1).abs() < 1E-10);
+        assert!((flip[1] - 1.1).abs() < 1E-10);
+        assert_eq!(edges.majority(), 0);
+    }
+
+    #[test]
+    fn test_from_eigvals() {
+        let kpoints = vec![[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]];
+        let dft = BandStructure::from_eigvals(&kpoints, &[vec![vec![-1.0, 1.0], vec![-0.5, 0.5]]], 0.5, None);
+        assert_eq!(dft.kdist, vec![0.0, 0.5]);
+        assert_eq!(dft.eigvals[0], vec![vec![-1.5, -1.0], vec![0.5, 0.0]]);
+
+        let wannier = BandStructure::from_eigvals(&kpoints, &[vec![vec![-0.9], vec![-0.6]]], 0.5, None);
+        let (mean, max) = wannier.deviation_from(&dft, 2.0).unwrap();
+        assert!((mean - 0.1).abs() < 1E-10);
+        assert!((max - 0.1).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_soc_comparison() {
+        let kpoints = vec![[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]];
+        let nonsoc = BandStructure::from_eigvals(&kpoints, &[vec![vec![-1.0, 0.5], vec![-1.2, 2.0]]], 0.0, None);
+        let soc = BandStructure::from_eigvals(&kpoints, &[vec![vec![-1.1, -0.8, 0.5, 0.5], vec![-1.3, -1.1, 2.0, 2.0]]], 0.1, None);
+        let cmp = SocComparison::new(&nonsoc, &soc, &[(0, "G".to_string()), (1, "X".to_string())], 1.5);
+        assert_eq!(cmp.0.len(), 3);
+        assert_eq!((cmp.0[0].label.as_str(), cmp.0[0].band), ("G", 1));
+        assert!((cmp.0[0].splitting - 0.3).abs() < 1E-10);
+        assert!((cmp.0[0].shift + 0.05).abs() < 1E-10);
+        assert_eq!(cmp.0[1].splitting, 0.0);
+        assert_eq!((cmp.0[2].label.as_str(), cmp.0[2].band), ("X", 1));
+        assert!((cmp.0[2].splitting - 0.2).abs() < 1E-10);
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+
+
+/// Band edges of one slab relative to its vacuum level.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AlignedEdges {
+    pub label  : String,
+    pub vacuum : f64,  // vacuum level in eV, the maximum of planar averaged LOCPOT
+    pub vbm    : f64,  // relative to the vacuum level
+    pub cbm    : f64,
+}
+
+impl AlignedEdges {
+    /// `vbm` and `cbm` are the absolute band edges from OUTCAR.
+    pub fn new(label: &str, vacuum: f64, vbm: f64, cbm: f64) -> Self {
+        Self {
+            label: label.to_string(),
+            vacuum,
+            vbm: vbm - vacuum,
+            cbm: cbm - vacuum,
+        }
+    }
+
+    pub fn gap(&self) -> f64 {
+        (self.cbm - self.vbm).max(0.0)
+    }
+
+    /// Ionization potential, i.e. the energy to move an electron from the VBM to the vacuum.
+    pub fn ionization_potential(&self) -> f64 {
+        -self.vbm
+    }
+
+    pub fn electron_affinity(&self) -> f64 {
+        -self.cbm
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AlignmentType {
+    Straddling,  // type I, one gap lies within the other
+    Staggered,   // type II
+    Broken,      // type III, the gaps don't overlap
+}
+
+impl AlignmentType {
+    pub fn classify(a: &AlignedEdges, b: &AlignedEdges) -> Self {
+        if a.cbm < b.vbm || b.cbm < a.vbm {
+            Self::Broken
+        } else if (a.vbm <= b.vbm && b.cbm <= a.cbm) || (b.vbm <= a.vbm && a.cbm <= b.cbm) {
+            Self::Straddling
+        } else {
+            Self::Staggered
+        }
+    }
+}
+
+impl fmt::Display for AlignmentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Straddling => "I (straddling)",
+            Self::Staggered  => "II (staggered)",
+            Self::Broken     => "III (broken gap)",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+
+/// Band offsets of the second material relative to the first one, in eV.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BandOffset {
+    pub first  : usize,
+    pub second : usize,
+    pub dvbm   : f64,
+    pub dcbm   : f64,
+    pub kind   : AlignmentType,
+}
+
+
+/// Band edges of several slab calculations aligned to the vacuum levels, with the band
+/// offsets of every pair.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BandAlignment {
+    pub edges   : Vec<AlignedEdges>,
+    pub offsets : Vec<BandOffset>,
+}
+
+impl BandAlignment {
+    pub fn new(edges: Vec<AlignedEdges>) -> Self {
+        let mut offsets = vec![];
+        for i in 0 .. edges.len() {
+            for j in i + 1 .. edges.len() {
+                offsets.push(BandOffset {
+                    first: i,
+                    second: j,
+                    dvbm: edges[j].vbm - edges[i].vbm,
+                    dcbm: edges[j].cbm - edges[i].cbm,
+                    kind: AlignmentType::classify(&edges[i], &edges[j]),
+                });
+            }
+        }
+        Self {
+            edges,
+            offsets,
+        }
+    }
+
+    /// Valence and conduction bands are drawn as bars below the VBM and above the CBM.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let labels = self.edges.iter().map(|e| e.label.clone()).collect::<Vec<_>>();
+        let vbms = self.edges.iter().map(|e| e.vbm).collect::<Vec<f64>>();
+        let cbms = self.edges.iter().map(|e| e.cbm).collect::<Vec<f64>>();
+        let bottom = vbms.iter().cloned().fold(f64::INFINITY, f64::min) - 1.0;
+        let top = cbms.iter().cloned().fold(f64::NEG_INFINITY, f64::max).min(0.0) + 1.0;
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Band alignment",
+                "yaxis": {"title": "E - E_vac (eV)"},
+                "barmode": "overlay",
+                "shapes": [{
+                    "type": "line", "xref": "paper", "yref": "y", "x0": 0.0, "x1": 1.0, "y0": 0.0, "y1": 0.0,
+                    "line": {"color": "gray", "dash": "dash", "width": 1},
+                }],
+            }));
+        plot.add_trace(json!({
+            "type": "bar",
+            "name": "VB",
+            "x": labels,
+            "base": vec![bottom; vbms.len()],
+            "y": vbms.iter().map(|v| v - bottom).collect::<Vec<f64>>(),
+            "text": vbms.iter().map(|v| format!("{:.2}", v)).collect::<Vec<_>>(),
+            "textposition": "inside",
+            "marker": {"color": "#1f77b4"},
+        }));
+        plot.add_trace(json!({
+            "type": "bar",
+            "name": "CB",
+            "x": labels,
+            "base": cbms,
+            "y": cbms.iter().map(|c| (top - c).max(0.2)).collect::<Vec<f64>>(),
+            "text": cbms.iter().map(|c| format!("{:.2}", c)).collect::<Vec<_>>(),
+            "textposition": "inside",
+            "marker": {"color": "#d62728"},
+        }));
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for BandAlignment {
+    fn headers(&self) -> Vec<String> {
+        ["label", "vacuum", "vbm", "cbm", "gap", "ionization_potential", "electron_affinity"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    // Band offsets are only shown in the text output
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.edges.iter()
+            .map(|e| vec![
+                e.label.clone(),
+                format!("{:.4}", e.vacuum),
+                format!("{:.4}", e.vbm),
+                format!("{:.4}", e.cbm),
+                format!("{:.4}", e.gap()),
+                format!("{:.4}", e.ionization_potential()),
+                format!("{:.4}", e.electron_affinity()),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for BandAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.edges.iter().map(|e| e.label.len()).max().unwrap_or(0).max(5);
+        writeln!(f, "{}", format!("# {:<width$} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                                  "Label", "E_vac/eV", "VBM/eV", "CBM/eV", "Gap/eV", "IP/eV", "EA/eV", width = width).bright_green())?;
+        for e in self.edges.iter() {
+            writeln!(f, "  {:<width$} {:10.4} {} {} {:10.4} {:10.4} {:10.4}",
+                     e.label, e.vacuum, format!("{:10.4}", e.vbm).bright_yellow(), format!("{:10.4}", e.cbm).bright_yellow(),
+                     e.gap(), e.ionization_potential(), e.electron_affinity(), width = width)?;
+        }
+
+        if !self.offsets.is_empty() {
+            writeln!(f, "{}", format!("# {:<w$} {:>10} {:>10}  Type", "Pair", "dVBM/eV", "dCBM/eV", w = 2 * width + 4).bright_green())?;
+            for o in self.offsets.iter() {
+                let pair = format!("{} -> {}", self.edges[o.first].label, self.edges[o.second].label);
+                writeln!(f, "  {:<w$} {:10.4} {:10.4}  {}", pair, o.dvbm, o.dcbm, o.kind, w = 2 * width + 4)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_alignment() {
+        let a = AlignedEdges::new("A", 4.0, -2.0, 1.0);  // VBM -6, CBM -3
+        assert_eq!((a.vbm, a.cbm, a.gap(), a.ionization_potential()), (-6.0, -3.0, 3.0, 6.0));
+
+        let b = AlignedEdges::new("B", 0.0, -5.0, -4.0);
+        let c = AlignedEdges::new("C", 0.0, -5.0, -2.0);
+        let d = AlignedEdges::new("D", 0.0, -2.5, -1.0);
+        assert_eq!(AlignmentType::classify(&a, &b), AlignmentType::Straddling);
+        assert_eq!(AlignmentType::classify(&a, &c), AlignmentType::Staggered);
+        assert_eq!(AlignmentType::classify(&a, &d), AlignmentType::Broken);
+
+        let align = BandAlignment::new(vec![a, b, c]);
+        assert_eq!(align.offsets.len(), 3);
+        assert_eq!((align.offsets[0].dvbm, align.offsets[0].dcbm), (1.0, -1.0));
+        assert_eq!(align.offsets[2].first, 1);
+        assert_eq!(align.rows()[0][5], "6.0000");
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use colored::Colorize;
+use serde::{
+    Serialize,
+    Serializer,
+};
+use crate::traits::Tabular;
+use crate::procar::Procar;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandCharacter {
+    pub ispin         : usize,    // starts from 1
+    pub ikpoint       : usize,    // starts from 1
+    pub iband         : usize,    // starts from 1
+    pub energy        : f64,      // relative to E-fermi
+    pub contributions : Vec<(usize, usize, f64)>,  // (ion index, orbital index, weight in percent), indices start from 0
+}
+
+
+#[derive(Clone)]
+pub struct BandCharacterTable {
+    _data    : Vec<BandCharacter>,
+    kpoints  : Vec<(usize, [f64; 3])>,
+    labels   : Vec<String>,
+    orbitals : Vec<String>,
+    ntop     : usize,
+}
+
+impl BandCharacterTable {
+    /// `ikpoints` start from 1, only the bands lying in [emin, emax] relative to
+    /// `efermi` are selected. `labels` are the names of ions shown in the table.
+    pub fn from_procar(procar: &Procar, ikpoints: &[usize], emin: f64, emax: f64,
+                       efermi: f64, labels: Vec<String>) -> Self {
+        assert_eq!(labels.len(), procar.nions, "Inconsistent ion numbers from PROCAR and ion labels");
+        let norbits = procar.norbits();
+
+        let mut data = vec![];
+        for &ik in ikpoints.iter() {
+            assert!(1 <= ik && ik <= procar.nkpts, "K-point index out of bound.");
+            for ispin in 0 .. procar.nspin {
+                for ib in 0 .. procar.nbands {
+                    let energy = procar.eigval(ispin, ik - 1, ib) - efermi;
+                    if energy < emin || energy > emax { continue; }
+
+                    // Non-collinear PROCAR: only the 'tot' component is considered
+                    let proj = procar.band_projections(ispin, ik - 1, ib);
+                    let total = proj.iter().sum::<f64>();
+                    if total <= 0.0 { continue; }
+
+                    let mut contributions = proj.iter()
+                        .enumerate()
+                        .map(|(i, w)| (i / norbits, i % norbits, w / total * 100.0))
+                        .collect::<Vec<_>>();
+                    contributions.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+
+                    data.push(BandCharacter {
+                        ispin: ispin + 1,
+                        ikpoint: ik,
+                        iband: ib + 1,
+                        energy,
+                        contributions,
+                    });
+                }
+            }
+        }
+
+        Self {
+            _data: data,
+            kpoints: ikpoints.iter().map(|&ik| (ik, procar.kpoints[ik - 1])).collect(),
+            labels,
+            orbitals: procar.orbitals.clone(),
+            ntop: 3,
+        }
+    }
+
+    pub fn ntop(mut self, ntop: usize) -> Self {
+        self.ntop = ntop;
+        self
+    }
+
+    pub fn data(&self) -> &[BandCharacter] {
+        &self._data
+    }
+}
+
+impl BandCharacterTable {
+    // The `ntop` dominant contributions labeled as "<ion>-<orbital>"
+    fn _top_contributions(&self, bc: &BandCharacter) -> Vec<(String, f64)> {
+        bc.contributions.iter()
+            .take(self.ntop)
+            .map(|(iion, iorb, w)| (format!("{}-{}", self.labels[*iion], self.orbitals[*iorb]), *w))
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct _BandCharacterRow {
+    ispin     : usize,
+    ikpoint   : usize,
+    iband     : usize,
+    energy    : f64,
+    character : Vec<(String, f64)>,
+}
+
+impl Serialize for BandCharacterTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self._data.iter()
+            .map(|bc| _BandCharacterRow {
+                ispin: bc.ispin,
+                ikpoint: bc.ikpoint,
+                iband: bc.iband,
+                energy: bc.energy,
+                character: self._top_contributions(bc),
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl Tabular for BandCharacterTable {
+    fn headers(&self) -> Vec<String> {
+        ["ispin", "ikpoint", "iband", "energy", "character"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self._data.iter()
+            .map(|bc| vec![
+                bc.ispin.to_string(),
+                bc.ikpoint.to_string(),
+                bc.iband.to_string(),
+                format!("{:.4}", bc.energy),
+                self._top_contributions(bc).iter()
+                    .map(|(l, w)| format!("{}:{:.1}", l, w))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for BandCharacterTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (ik, k) in self.kpoints.iter() {
+            writeln!(f, "{}", format!("# K-point #{:<4} ({:9.5} {:9.5} {:9.5})", ik, k[0], k[1], k[2]).bright_yellow())?;
+            writeln!(f, "{}", "  Spin  #Band   E-Ef/eV  Character".bright_green())?;
+
+            for bc in self._data.iter().filter(|bc| bc.ikpoint == *ik) {
+                let mut line = format!("  {:4}  {:5} {:9.4} ", bc.ispin, bc.iband, bc.energy);
+                line += &self._top_contributions(bc).iter()
+                    .map(|(l, w)| format!(" {:>10} {:5.1}%", l, w))
+                    .collect::<Vec<_>>()
+                    .join("");
+                writeln!(f, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _generate_procar() -> Procar {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    1         # of bands:   2         # of ions:   2
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -5.00000000 # occ.  1.00000000
+
+ion      s      p      d    tot
+    1  0.100  0.200  0.000  0.300
+    2  0.300  0.000  0.200  0.500
+tot    0.400  0.200  0.200  0.800
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s      p      d    tot
+    1  0.000  0.500  0.000  0.500
+    2  0.000  0.000  0.300  0.300
+tot    0.000  0.500  0.300  0.800
+
+"#;
+        Procar::parse(input)
+    }
+
+    #[test]
+    fn test_band_character() {
+        let procar = _generate_procar();
+        let labels = vec!["Ti1".to_string(), "O2".to_string()];
+        let table = BandCharacterTable::from_procar(&procar, &[1], -10.0, 0.0, -1.0, labels);
+
+        assert_eq!(table.data().len(), 1);
+        let bc = &table.data()[0];
+        assert_eq!((bc.ispin, bc.ikpoint, bc.iband), (1, 1, 1));
+        assert_eq!(bc.energy, -4.0);
+        assert_eq!(bc.contributions[0].0, 1);
+        assert_eq!(bc.contributions[0].1, 0);
+        assert!((bc.contributions[0].2 - 37.5).abs() < 1E-8);
+    }
+
+    #[test]
+    #[should_panic(expected = "K-point index out of bound.")]
+    fn test_band_character_fail() {
+        let procar = _generate_procar();
+        let labels = vec!["Ti1".to_string(), "O2".to_string()];
+        BandCharacterTable::from_procar(&procar, &[2], -10.0, 0.0, 0.0, labels);
+    }
+}
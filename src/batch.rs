@@ -0,0 +1,166 @@
+use std::fmt;
+use std::io;
+use std::panic;
+use std::path::PathBuf;
+use serde::Serialize;
+use rayon::prelude::*;
+use colored::Colorize;
+use log::warn;
+use crate::outcar::Outcar;
+use crate::summary::Summary;
+use crate::traits::Tabular;
+use crate::progress::Progress;
+
+
+/// Glob patterns like "calc_*" are expanded, other items are kept as is. Only directories
+/// are returned.
+pub fn expand_dirs(patterns: &[String]) -> Vec<PathBuf> {
+    let mut ret = expand_paths(patterns);
+    ret.retain(|p| p.is_dir());
+    ret
+}
+
+
+/// Glob patterns like "calc_*" are expanded, other items are kept as is. The returned paths
+/// are sorted and deduplicated, but not checked for existence.
+pub fn expand_paths(patterns: &[String]) -> Vec<PathBuf> {
+    let mut ret = vec![];
+    for p in patterns.iter() {
+        if p.contains(['*', '?', '[']) {
+            match glob::glob(p) {
+                Ok(paths) => ret.extend(paths.filter_map(Result::ok)),
+                Err(e) => warn!("Invalid glob pattern {:?}: {}", p, e),
+            }
+        } else {
+            ret.push(PathBuf::from(p));
+        }
+    }
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatchRecord<T> {
+    pub dir   : PathBuf,
+    pub error : Option<String>,
+    #[serde(flatten)]
+    pub data  : Option<T>,
+}
+
+
+/// Parses `outcar` in each directory in parallel. Failures of single directories, including
+/// the panics raised by the parsers, are recorded instead of aborting the whole batch.
+pub fn run_batch<T: Summary + Send>(dirs: &[PathBuf], outcar: &str) -> Vec<BatchRecord<T>> {
+    let progress = Progress::new("Processing directories", dirs.len());
+    let records = dirs.par_iter()
+        .map(|dir| {
+            let fname = dir.join(outcar);
+            let result = panic::catch_unwind(|| -> io::Result<T> {
+                let outcar = Outcar::from_file(&fname)?;
+                Ok(T::from_outcar(&outcar, dir))
+            });
+
+            let (data, error) = match result {
+                Ok(Ok(data)) => (Some(data), None),
+                Ok(Err(e)) => (None, Some(e.to_string())),
+                Err(e) => {
+                    let msg = e.downcast_ref::<String>().cloned()
+                        .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                        .unwrap_or_else(|| "Unknown error".to_string());
+                    (None, Some(msg))
+                },
+            };
+            if let Some(e) = error.as_ref() {
+                warn!("Failed to process {:?}: {}", &fname, e);
+            }
+            progress.inc(1);
+
+            BatchRecord {
+                dir: dir.clone(),
+                error,
+                data,
+            }
+        })
+        .collect();
+    progress.finish();
+    records
+}
+
+
+/// Results of all the directories, one row per directory. Directories failed to be processed
+/// are kept with empty values and the error messages.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatchTable<T>(pub Vec<BatchRecord<T>>);
+
+impl<T: Summary> Tabular for BatchTable<T> {
+    fn headers(&self) -> Vec<String> {
+        std::iter::once("dir")
+            .chain(T::csv_header())
+            .chain(std::iter::once("error"))
+            .map(|h| h.to_string())
+            .collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let nvalues = T::csv_header().len();
+        self.0.iter()
+            .map(|r| {
+                let mut row = vec![r.dir.display().to_string()];
+                match r.data.as_ref() {
+                    Some(d) => row.extend(d.csv_row()),
+                    None => row.extend(vec![String::new(); nvalues]),
+                }
+                row.push(r.error.clone().unwrap_or_default());
+                row
+            })
+            .collect()
+    }
+}
+
+impl<T: Summary> fmt::Display for BatchTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers = self.headers();
+        let rows = self.rows();
+        let widths = headers.iter().enumerate()
+            .map(|(i, h)| rows.iter().map(|r| r[i].len()).chain(std::iter::once(h.len())).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+        let line = |row: &[String]| row.iter().zip(widths.iter())
+            .map(|(v, w)| format!("{:>w$}", v, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        writeln!(f, "{}", line(&headers).bright_green())?;
+        for (r, row) in self.0.iter().zip(rows.iter()) {
+            match r.error {
+                Some(_) => writeln!(f, "{}", line(row).bright_red())?,
+                None    => writeln!(f, "{}", line(row))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::MagSummary;
+
+    #[test]
+    fn test_batch_record_json() {
+        let r = BatchRecord {
+            dir: PathBuf::from("calc_1"),
+            error: None,
+            data: Some(MagSummary { ispin: 2, lsorbit: false, magmom: vec![0.6] }),
+        };
+        assert_eq!(serde_json::to_string(&r).unwrap(),
+                   r#"{"dir":"calc_1","error":null,"ispin":2,"lsorbit":false,"magmom":[0.6]}"#);
+
+        let failed = BatchRecord { dir: PathBuf::from("calc_2"), error: Some("No such file".to_string()), data: None };
+        let table = BatchTable(vec![r, failed]);
+        assert_eq!(table.headers(), ["dir", "ispin", "lsorbit", "magmom", "error"]);
+        assert_eq!(table.rows()[1], ["calc_2", "", "", "", "No such file"]);
+    }
+}
@@ -0,0 +1,286 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::collections::BTreeMap;
+use colored::Colorize;
+use serde::Serialize;
+use rayon::prelude::*;
+use crate::traits::Tabular;
+use crate::format::{
+    Structure,
+    Trajectory,
+};
+use crate::neighbor::{
+    Cutoffs,
+    CoordinationAnalysis,
+};
+
+
+// Filters out the changes of the series lasting less than `persist` frames, e.g. the bonds
+// flickering around the cutoff by thermal vibrations. A change is dated back to the first frame
+// of the persisting run, and the unconfirmed change at the end of the series is ignored.
+fn _debounce<T: Copy + PartialEq>(raw: &[T], persist: usize) -> Vec<T> {
+    let mut ret = raw.to_vec();
+    let mut state = match raw.first() {
+        Some(x) => *x,
+        None => return ret,
+    };
+    let mut i = 0;
+    while i < raw.len() {
+        let run = raw[i ..].iter().take_while(|x| **x == raw[i]).count();
+        if raw[i] != state && run >= persist {
+            state = raw[i];
+        }
+        ret[i .. i + run].iter_mut().for_each(|x| *x = state);
+        i += run;
+    }
+    ret
+}
+
+
+// Distance of the nearest periodic images of two atoms, exact for cells not too skewed
+fn _distance(structure: &Structure, i: usize, j: usize) -> f64 {
+    let d = [0, 1, 2].map(|k| {
+        let x = structure.frac_pos[j][k] - structure.frac_pos[i][k];
+        x - x.round()
+    });
+    (0 .. 3).map(|l| (0 .. 3).map(|k| d[k] * structure.cell[k][l]).sum::<f64>().powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BondEvent {
+    pub frame    : usize,         // starts from 1
+    pub time     : f64,           // in fs
+    pub kind     : String,        // "form" or "break"
+    pub atoms    : [usize; 2],    // starts from 1
+    pub symbols  : [String; 2],
+    pub distance : f64,           // at the frame of the event
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CoordinationChange {
+    pub index   : usize,          // starts from 1
+    pub symbol  : String,
+    pub initial : usize,
+    pub last    : usize,
+    pub changes : usize,          // change points of the filtered coordination number
+}
+
+
+/// Coordination numbers of the selected atoms over the frames of MD, and the bond forming and
+/// breaking events between them and all the other atoms. The bonds are judged by the pair
+/// cutoffs like `rsgrad neigh`, and the bond states and coordination numbers changing for less
+/// than `persist` frames are filtered out as thermal fluctuations before the change points are
+/// detected.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BondTracking {
+    pub persist : usize,
+    pub atoms   : Vec<usize>,          // starts from 1
+    pub symbols : Vec<String>,
+    pub times   : Vec<f64>,            // in fs
+    pub cn      : Vec<Vec<usize>>,     // raw coordination numbers, [natoms][nframes]
+    pub changes : Vec<CoordinationChange>,  // only the atoms whose coordination changes
+    pub events  : Vec<BondEvent>,      // sorted by time
+}
+
+impl BondTracking {
+    /// `iatoms` start from 0, `dt` is the time between frames in fs.
+    pub fn new(traj: &Trajectory, iatoms: &[usize], cutoffs: &Cutoffs, dt: f64, persist: usize) -> io::Result<Self> {
+        let first = traj.0.first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty trajectory"))?;
+        let all_symbols = first.symbols();
+        let nframes = traj.0.len();
+        let persist = persist.max(1);
+
+        // bonded pairs (i, j) with i < j of each frame, and the coordination numbers
+        let frames = traj.0.par_iter()
+            .map(|s| {
+                let analysis = CoordinationAnalysis::new(s, iatoms, cutoffs);
+                let mut pairs = analysis.atoms.iter()
+                    .flat_map(|c| c.bonds.iter()
+                              .filter(move |b| b.index != c.index - 1)
+                              .map(move |b| ((c.index - 1).min(b.index), (c.index - 1).max(b.index))))
+                    .collect::<Vec<_>>();
+                pairs.sort_unstable();
+                pairs.dedup();
+                let cn = analysis.atoms.iter().map(|c| c.cn()).collect::<Vec<usize>>();
+                (pairs, cn)
+            })
+            .collect::<Vec<_>>();
+
+        let cn = (0 .. iatoms.len())
+            .map(|ia| frames.iter().map(|(_, cn)| cn[ia]).collect::<Vec<usize>>())
+            .collect::<Vec<_>>();
+
+        let mut history: BTreeMap<(usize, usize), Vec<bool>> = BTreeMap::new();
+        for (it, (pairs, _)) in frames.iter().enumerate() {
+            for p in pairs.iter() {
+                history.entry(*p).or_insert_with(|| vec![false; nframes])[it] = true;
+            }
+        }
+
+        let mut events = vec![];
+        for ((i, j), raw) in history.iter() {
+            let filtered = _debounce(raw, persist);
+            for (it, w) in filtered.windows(2).enumerate() {
+                if w[0] == w[1] {
+                    continue;
+                }
+                let frame = it + 1;
+                events.push(BondEvent {
+                    frame: frame + 1,
+                    time: frame as f64 * dt,
+                    kind: if w[1] { "form" } else { "break" }.to_string(),
+                    atoms: [i + 1, j + 1],
+                    symbols: [all_symbols[*i].clone(), all_symbols[*j].clone()],
+                    distance: _distance(&traj.0[frame], *i, *j),
+                });
+            }
+        }
+        events.sort_by_key(|e| (e.frame, e.atoms));
+
+        let changes = iatoms.iter()
+            .zip(cn.iter())
+            .filter_map(|(i, series)| {
+                let filtered = _debounce(series, persist);
+                let changes = filtered.windows(2).filter(|w| w[0] != w[1]).count();
+                (changes > 0).then(|| CoordinationChange {
+                    index: i + 1,
+                    symbol: all_symbols[*i].clone(),
+                    initial: filtered[0],
+                    last: filtered[nframes - 1],
+                    changes,
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            persist,
+            atoms: iatoms.iter().map(|i| i + 1).collect(),
+            symbols: iatoms.iter().map(|i| all_symbols[*i].clone()).collect(),
+            times: (0 .. nframes).map(|i| i as f64 * dt).collect(),
+            cn,
+            changes,
+            events,
+        })
+    }
+
+    /// Saves the raw coordination numbers of each frame as columns of the selected atoms.
+    pub fn save_cn(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut s = String::new();
+        write!(s, "# {:>6} {:>10}", "frame", "time/fs").unwrap();
+        for (i, sym) in self.atoms.iter().zip(self.symbols.iter()) {
+            write!(s, " {:>7}", format!("{}{}", sym, i)).unwrap();
+        }
+        writeln!(s).unwrap();
+        for (it, t) in self.times.iter().enumerate() {
+            write!(s, "  {:6} {:10.2}", it + 1, t).unwrap();
+            for series in self.cn.iter() {
+                write!(s, " {:7}", series[it]).unwrap();
+            }
+            writeln!(s).unwrap();
+        }
+        fs::write(path, s)
+    }
+}
+
+impl Tabular for BondTracking {
+    fn headers(&self) -> Vec<String> {
+        ["frame", "time", "event", "atom_a", "atom_b", "distance"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.events.iter()
+            .map(|e| vec![
+                e.frame.to_string(),
+                format!("{:.2}", e.time),
+                e.kind.clone(),
+                format!("{}{}", e.symbols[0], e.atoms[0]),
+                format!("{}{}", e.symbols[1], e.atoms[1]),
+                format!("{:.4}", e.distance),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for BondTracking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# {} atoms tracked over {} frames, changes shorter than {} frames are filtered",
+                                  self.atoms.len(), self.times.len(), self.persist).bright_green())?;
+        if self.events.is_empty() {
+            return writeln!(f, "{}", "# No bond forming or breaking events found".bright_green());
+        }
+        writeln!(f, "{}", format!("  {:>7} {:>10} {:>6} {:>7} {:>7} {:>10}",
+                                  "Frame", "Time/fs", "Event", "Atom A", "Atom B", "Distance/A").bright_green())?;
+        for e in self.events.iter() {
+            let kind = if e.kind == "break" { e.kind.bright_red() } else { e.kind.bright_yellow() };
+            writeln!(f, "  {:7} {:10.2} {:>6} {:>7} {:>7} {:10.4}",
+                     e.frame, e.time, kind,
+                     format!("{}{}", e.symbols[0], e.atoms[0]),
+                     format!("{}{}", e.symbols[1], e.atoms[1]), e.distance)?;
+        }
+
+        writeln!(f, "{}", "# Coordination numbers changed".bright_green())?;
+        writeln!(f, "{}", format!("  {:>4} {:>4} {:>8} {:>8} {:>8}", "#Ion", "Elem", "Initial", "Last", "Changes").bright_green())?;
+        for c in self.changes.iter() {
+            writeln!(f, "  {:4} {:>4} {:8} {} {:8}",
+                     c.index, c.symbol, c.initial, format!("{:8}", c.last).bright_yellow(), c.changes)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce() {
+        let raw = [4, 4, 5, 4, 4, 3, 3, 3, 4, 3, 3];
+        assert_eq!(_debounce(&raw, 3), vec![4, 4, 4, 4, 4, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(_debounce(&raw, 1), raw.to_vec());
+        // unconfirmed at the end
+        assert_eq!(_debounce(&[true, true, false], 2), vec![true, true, true]);
+    }
+
+    #[test]
+    fn test_bond_tracking() {
+        // H2 dissociating at frame 6, with a flicker at frame 3
+        let distances = [0.74, 0.76, 1.0, 0.75, 0.8, 1.2, 1.5, 1.8, 2.2, 2.5];
+        let frames = distances.iter()
+            .map(|d| {
+                let car_pos = vec![[1.0, 1.0, 1.0], [1.0 + d, 1.0, 1.0]];
+                Structure {
+                    cell: [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]],
+                    ion_types: vec!["H".to_string()],
+                    ions_per_type: vec![2],
+                    frac_pos: car_pos.iter().map(|p| [p[0] / 10.0, p[1] / 10.0, p[2] / 10.0]).collect(),
+                    car_pos,
+                }
+            })
+            .collect();
+        let traj = Trajectory(frames);
+        let cutoffs = Cutoffs { scale: 1.2, pairs: vec!["H-H=0.9".parse().unwrap()] };
+
+        let tracking = BondTracking::new(&traj, &[0], &cutoffs, 0.5, 2).unwrap();
+        assert_eq!(tracking.cn[0], vec![1, 1, 0, 1, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(tracking.events.len(), 1);
+        let e = &tracking.events[0];
+        assert_eq!((e.frame, e.kind.as_str(), e.atoms), (6, "break", [1, 2]));
+        assert!((e.time - 2.5).abs() < 1E-10);
+        assert!((e.distance - 1.2).abs() < 1E-10);
+        assert_eq!(tracking.changes.len(), 1);
+        assert_eq!((tracking.changes[0].initial, tracking.changes[0].last), (1, 0));
+
+        // without the filter the flicker counts
+        let tracking = BondTracking::new(&traj, &[0], &cutoffs, 0.5, 1).unwrap();
+        assert_eq!(tracking.events.len(), 3);
+    }
+}
@@ -0,0 +1,238 @@
+use std::io;
+use std::io::{
+    Read,
+    Write,
+};
+use std::fs;
+use std::env;
+use std::time::UNIX_EPOCH;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use memmap2::Mmap;
+use serde::{
+    Serialize,
+    Deserialize,
+    de::DeserializeOwned,
+};
+use log::{
+    info,
+    warn,
+};
+use crate::timing::{
+    self,
+    Stage,
+};
+
+
+// Opt-in cache of parsed files, enabled by `--cache` or a nonempty `RSGRAD_CACHE` other than "0".
+//
+// The parsed data is serialized in CBOR to `.rsgrad-cache/<file name>.<kind>.cbor` next to the
+// source file, after a header with the size and hash of the source. The cache is used only if
+// the source is unchanged, and rewritten otherwise. Bump `CACHE_VERSION` once the layout of any
+// cached type changes.
+//
+// Hashing the whole WAVECAR would take longer than parsing its headers, thus the large binary
+// files are stamped by the size, the modification time and the hash of the first `STAMP_BYTES`
+// instead, see `load_or_parse_stamped`.
+pub const CACHE_DIR: &str = ".rsgrad-cache";
+const CACHE_VERSION: u32 = 2;
+
+const STAMP_BYTES: usize = 1 << 20;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct CacheHeader {
+    version : u32,
+    kind    : String,
+    len     : u64,
+    hash    : u64,
+}
+
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) ||
+        env::var("RSGRAD_CACHE").map(|v| !v.is_empty() && v != "0").unwrap_or(false)
+}
+
+
+pub fn cache_path(source: &Path, kind: &str) -> PathBuf {
+    let dir = match source.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let fname = source.file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    dir.join(CACHE_DIR).join(format!("{}.{}.cbor", fname, kind))
+}
+
+
+/// Size and 64-bit FNV-1a hash of the file contents.
+pub fn file_hash(path: &Path) -> io::Result<(u64, u64)> {
+    let f = fs::File::open(path)?;
+    let len = f.metadata()?.len();
+    if len == 0 {
+        return Ok((0, 0xcbf29ce484222325));
+    }
+    // The mapped file is only read
+    let mmap = unsafe { Mmap::map(&f)? };
+    let hash = mmap.iter().fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+    Ok((len, hash))
+}
+
+
+/// Size and 64-bit FNV-1a hash of the modification time and the first `STAMP_BYTES` of the file.
+pub fn file_stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let f = fs::File::open(path)?;
+    let meta = f.metadata()?;
+    let mtime = meta.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut head = Vec::with_capacity(STAMP_BYTES);
+    f.take(STAMP_BYTES as u64).read_to_end(&mut head)?;
+    let hash = mtime.to_le_bytes().iter()
+        .chain(head.iter())
+        .fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+    Ok((meta.len(), hash))
+}
+
+
+/// Parses `path` by `parse`, or reads the cached result if the cache is enabled and the file
+/// is unchanged. Broken or stale caches are rebuilt, failing to write the cache is not an error.
+pub fn load_or_parse<T, F>(path: &Path, kind: &str, parse: F) -> io::Result<T>
+where T: Serialize + DeserializeOwned,
+      F: FnOnce(&Path) -> io::Result<T> {
+    timing::timed(Stage::Parse, || {
+        if enabled() {
+            _load_or_parse(path, kind, file_hash, parse)
+        } else {
+            parse(path)
+        }
+    })
+}
+
+
+/// Same as `load_or_parse`, with the file checked by `file_stamp` instead of the full hash.
+pub fn load_or_parse_stamped<T, F>(path: &Path, kind: &str, parse: F) -> io::Result<T>
+where T: Serialize + DeserializeOwned,
+      F: FnOnce(&Path) -> io::Result<T> {
+    timing::timed(Stage::Parse, || {
+        if enabled() {
+            _load_or_parse(path, kind, file_stamp, parse)
+        } else {
+            parse(path)
+        }
+    })
+}
+
+
+fn _load_or_parse<T, F>(path: &Path, kind: &str, stamp: fn(&Path) -> io::Result<(u64, u64)>, parse: F) -> io::Result<T>
+where T: Serialize + DeserializeOwned,
+      F: FnOnce(&Path) -> io::Result<T> {
+    let (len, hash) = stamp(path)?;
+    let header = CacheHeader { version: CACHE_VERSION, kind: kind.to_string(), len, hash };
+    let cpath = cache_path(path, kind);
+
+    if let Some(data) = _read_cache(&cpath, &header) {
+        info!("Read cached {} from {:?}", kind, &cpath);
+        return Ok(data);
+    }
+
+    let data = parse(path)?;
+    match _write_cache(&cpath, &header, &data) {
+        Ok(_) => info!("Cached {} to {:?}", kind, &cpath),
+        Err(e) => warn!("Cannot write cache {:?}: {}", &cpath, e),
+    }
+    Ok(data)
+}
+
+
+fn _read_cache<T: DeserializeOwned>(cpath: &Path, header: &CacheHeader) -> Option<T> {
+    let f = fs::File::open(cpath).ok()?;
+    let mut de = serde_cbor::Deserializer::from_reader(io::BufReader::new(f));
+    let cached = CacheHeader::deserialize(&mut de).ok()?;
+    if &cached != header {
+        info!("Cache {:?} is outdated, the source file is parsed again.", cpath);
+        return None;
+    }
+    T::deserialize(&mut de)
+        .map_err(|e| warn!("Broken cache {:?}: {}", cpath, e))
+        .ok()
+}
+
+
+fn _write_cache<T: Serialize>(cpath: &Path, header: &CacheHeader, data: &T) -> io::Result<()> {
+    if let Some(dir) = cpath.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let to_io = |e: serde_cbor::Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+
+    // Written to a temporary file first, so interrupted runs don't leave broken caches
+    let tmp = cpath.with_extension("cbor.tmp");
+    let mut f = io::BufWriter::new(fs::File::create(&tmp)?);
+    serde_cbor::to_writer(&mut f, header).map_err(to_io)?;
+    serde_cbor::to_writer(&mut f, data).map_err(to_io)?;
+    f.flush()?;
+    drop(f);
+    fs::rename(&tmp, cpath)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_load_or_parse() {
+        let dir = TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("DATA");
+        fs::write(&path, "1 2 3").unwrap();
+        let parse = |p: &Path| -> io::Result<Vec<f64>> {
+            Ok(fs::read_to_string(p)?.split_whitespace().map(|x| x.parse().unwrap()).collect())
+        };
+
+        assert_eq!(_load_or_parse(&path, "data", file_hash, parse).unwrap(), vec![1.0, 2.0, 3.0]);
+        assert!(cache_path(&path, "data").is_file());
+
+        // Unchanged source, the cache is used
+        let fail = |_: &Path| -> io::Result<Vec<f64>> { panic!("Cache not used") };
+        assert_eq!(_load_or_parse(&path, "data", file_hash, fail).unwrap(), vec![1.0, 2.0, 3.0]);
+
+        // Changed source, parsed again
+        fs::write(&path, "4 5").unwrap();
+        assert_eq!(_load_or_parse(&path, "data", file_hash, parse).unwrap(), vec![4.0, 5.0]);
+        assert_eq!(_load_or_parse(&path, "data", file_hash, fail).unwrap(), vec![4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_load_or_parse_stamped() {
+        let dir = TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        fs::write(&path, "1 2 3").unwrap();
+        let parse = |p: &Path| -> io::Result<Vec<f64>> {
+            Ok(fs::read_to_string(p)?.split_whitespace().map(|x| x.parse().unwrap()).collect())
+        };
+        let fail = |_: &Path| -> io::Result<Vec<f64>> { panic!("Cache not used") };
+
+        assert_eq!(_load_or_parse(&path, "wavecar", file_stamp, parse).unwrap(), vec![1.0, 2.0, 3.0]);
+        assert_eq!(_load_or_parse(&path, "wavecar", file_stamp, fail).unwrap(), vec![1.0, 2.0, 3.0]);
+
+        // Same size, changed head
+        fs::write(&path, "4 5 6").unwrap();
+        assert_eq!(_load_or_parse(&path, "wavecar", file_stamp, parse).unwrap(), vec![4.0, 5.0, 6.0]);
+        assert_ne!(file_stamp(&path).unwrap(), file_hash(&path).unwrap());
+    }
+}
@@ -0,0 +1,632 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use regex::Regex;
+use itertools::Itertools;
+use rayon::prelude::*;
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use log::info;
+use vasp_poscar::Poscar;
+use crate::outcar::Mat33;
+use crate::progress::Progress;
+use crate::timing::{
+    self,
+    Stage,
+};
+use crate::format::{
+    Structure,
+    ELEMENTS,
+    _calc_inv_3x3,
+    _car_to_frac,
+};
+use crate::summary::_volume;
+use crate::constants::BOHR;
+
+
+
+// Volumetric data in VASP format, e.g. CHGCAR, LOCPOT, PARCHG and ELFCAR.
+//
+// The data are stored block by block, the meaning of each block depends on the file:
+// CHGCAR of ISPIN=2 contains total density and magnetization density, LOCPOT of ISPIN=2
+// contains the potentials of spin up and spin down channels. Gaussian cube files are converted
+// into the same representation with a single block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChargeDensity {
+    pub pos    : Structure,
+    pub ngrid  : [usize; 3],
+    pub blocks : Vec<Vec<f64>>,  // [nblock][ngz][ngy][ngx], x runs fastest
+}
+
+impl ChargeDensity {
+    /// Files with the ".cube" extension are read as Gaussian cube files, see `parse_cube`.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        timing::timed(Stage::Parse, || {
+            let input = fs::read_to_string(path)?;
+            if path.as_ref().extension().is_some_and(|e| e.eq_ignore_ascii_case("cube")) {
+                Ok(Self::parse_cube(&input))
+            } else {
+                Ok(Self::parse(&input))
+            }
+        })
+    }
+
+    pub fn parse(input: &str) -> Self {
+        // The structure part ends with a blank line
+        let hpos = Regex::new(r"\n[ \t]*\r?\n").unwrap()
+            .find(input)
+            .expect("Cannot find the end of structure in volumetric data")
+            .start();
+        let poscar = Poscar::from_reader(&input.as_bytes()[..hpos])
+            .expect("Cannot parse the structure in volumetric data");
+        let pos = Structure::from(poscar);
+
+        let parse_grid = |line: &str| -> Option<[usize; 3]> {
+            let v = line.split_whitespace()
+                .map(|x| x.parse::<usize>().ok())
+                .collect::<Option<Vec<usize>>>()?;
+            if v.len() == 3 { Some([v[0], v[1], v[2]]) } else { None }
+        };
+
+        let mut lines = input[hpos..].lines().filter(|l| !l.trim().is_empty());
+        let ngrid = lines.next()
+            .and_then(parse_grid)
+            .expect("Cannot parse the grid size of volumetric data");
+        let npoints = ngrid.iter().product::<usize>();
+
+        let mut blocks = vec![];
+        loop {
+            let mut block = Vec::<f64>::with_capacity(npoints);
+            while block.len() < npoints {
+                let line = lines.next().expect("Unexpected end of volumetric data");
+                block.extend(line.split_ascii_whitespace()
+                             .map(|x| x.parse::<f64>().expect("Cannot parse volumetric data as float value")));
+            }
+            assert_eq!(block.len(), npoints, "Inconsistent number of values in volumetric data");
+            blocks.push(block);
+
+            // Augmentation occupancies and magnetic moments are skipped until the next grid line
+            if !lines.any(|l| parse_grid(l) == Some(ngrid)) {
+                break;
+            }
+        }
+
+        Self {
+            pos,
+            ngrid,
+            blocks,
+        }
+    }
+
+    /// Parses Gaussian cube files, e.g. written by CP2K, Quantum ESPRESSO or Gaussian. The
+    /// values are taken as densities in e/Bohr^3 and scaled by the cell volume as in CHGCAR,
+    /// lengths are converted from Bohr to Angstrom and the origin is shifted to zero.
+    pub fn parse_cube(input: &str) -> Self {
+        let mut lines = input.lines().skip(2);
+        let mut next_numbers = || -> Vec<f64> {
+            lines.next()
+                .expect("Unexpected end of cube file")
+                .split_whitespace()
+                .map(|x| x.parse::<f64>().expect("Cannot parse cube file header"))
+                .collect()
+        };
+
+        let header = next_numbers();
+        let natoms = header[0] as i64;
+        let origin = [header[1], header[2], header[3]];
+
+        // Number of voxels and the voxel vectors, in Angstrom if the number is negative
+        let mut ngrid = [0usize; 3];
+        let mut cell = [[0.0f64; 3]; 3];
+        for i in 0 .. 3 {
+            let v = next_numbers();
+            let scale = if v[0] < 0.0 { 1.0 } else { BOHR };
+            ngrid[i] = v[0].abs() as usize;
+            cell[i] = [0, 1, 2].map(|j| v[j + 1] * scale * ngrid[i] as f64);
+        }
+
+        let mut numbers = vec![];
+        let mut car_pos = vec![];
+        for _ in 0 .. natoms.abs() {
+            let v = next_numbers();
+            numbers.push(v[0] as usize);
+            car_pos.push([0, 1, 2].map(|j| (v[j + 2] - origin[j]) * BOHR));
+        }
+        if natoms < 0 {
+            next_numbers();   // orbital indices of molecular orbital cubes
+        }
+
+        // Consecutive atoms of the same element are grouped as in POSCAR
+        let symbols = numbers.iter()
+            .map(|&z| ELEMENTS.get(z).copied().unwrap_or("X").to_string())
+            .collect::<Vec<String>>();
+        let mut ion_types = vec![];
+        let mut ions_per_type = vec![];
+        for (s, group) in &symbols.iter().group_by(|s| s.as_str()) {
+            ion_types.push(s.to_string());
+            ions_per_type.push(group.count() as i32);
+        }
+        let frac_pos = _car_to_frac(&cell, &car_pos);
+        let pos = Structure {
+            cell,
+            ion_types,
+            ions_per_type,
+            car_pos,
+            frac_pos,
+        };
+
+        // z runs fastest in cube files, but x runs fastest in CHGCAR
+        let npoints = ngrid.iter().product::<usize>();
+        let values = lines
+            .flat_map(|l| l.split_whitespace())
+            .take(npoints)
+            .map(|x| x.parse::<f64>().expect("Cannot parse volumetric data as float value"))
+            .collect::<Vec<f64>>();
+        assert_eq!(values.len(), npoints, "Unexpected end of volumetric data in cube file");
+
+        let volume = _volume(&cell) / BOHR.powi(3);
+        let mut block = vec![0.0f64; npoints];
+        for ix in 0 .. ngrid[0] {
+            for iy in 0 .. ngrid[1] {
+                for iz in 0 .. ngrid[2] {
+                    block[ix + ngrid[0] * (iy + ngrid[1] * iz)] = values[(ix * ngrid[1] + iy) * ngrid[2] + iz] * volume;
+                }
+            }
+        }
+
+        Self {
+            pos,
+            ngrid,
+            blocks: vec![block],
+        }
+    }
+
+    pub fn cell(&self) -> &Mat33<f64> {
+        &self.pos.cell
+    }
+
+    pub fn npoints(&self) -> usize {
+        self.ngrid.iter().product()
+    }
+
+    // Index of grid point in each block, all the indices start from 0
+    pub fn index(&self, ix: usize, iy: usize, iz: usize) -> usize {
+        assert!(ix < self.ngrid[0] && iy < self.ngrid[1] && iz < self.ngrid[2], "Index out of bound.");
+        ix + self.ngrid[0] * (iy + self.ngrid[1] * iz)
+    }
+
+    /// Average of the `iblock`th block over the planes perpendicular to lattice vector `axis`,
+    /// where `axis` is 0, 1 or 2 for a, b and c respectively.
+    pub fn planar_average(&self, iblock: usize, axis: usize) -> Vec<f64> {
+        assert!(axis < 3, "Axis should be 0, 1 or 2");
+        let block = &self.blocks[iblock];
+        let n = self.ngrid[axis];
+        let mut ret = vec![0.0f64; n];
+
+        for iz in 0 .. self.ngrid[2] {
+            for iy in 0 .. self.ngrid[1] {
+                for ix in 0 .. self.ngrid[0] {
+                    let i = [ix, iy, iz][axis];
+                    ret[i] += block[self.index(ix, iy, iz)];
+                }
+            }
+        }
+
+        let nplane = (self.npoints() / n) as f64;
+        ret.iter_mut().for_each(|v| *v /= nplane);
+        ret
+    }
+
+    /// Indices of the grid points within `radius` (in Angstrom) to `center` (in fractional
+    /// coordinates), periodic images are taken into account.
+    pub fn sphere_indices(&self, center: &[f64; 3], radius: f64) -> Vec<usize> {
+        let cell = self.cell();
+        let inv = _calc_inv_3x3(cell);
+        let ngrid = self.ngrid;
+
+        // Half widths of the box containing the sphere, in grid points
+        let nbox = (0 .. 3)
+            .map(|i| {
+                let b = (inv[0][i] * inv[0][i] + inv[1][i] * inv[1][i] + inv[2][i] * inv[2][i]).sqrt();
+                (radius * b * ngrid[i] as f64).ceil() as i64
+            })
+            .collect::<Vec<i64>>();
+        let g0 = (0 .. 3).map(|i| (center[i] * ngrid[i] as f64).round() as i64).collect::<Vec<i64>>();
+
+        let mut ret = vec![];
+        for dz in -nbox[2] ..= nbox[2] {
+            for dy in -nbox[1] ..= nbox[1] {
+                for dx in -nbox[0] ..= nbox[0] {
+                    let g = [g0[0] + dx, g0[1] + dy, g0[2] + dz];
+                    let frac = [
+                        g[0] as f64 / ngrid[0] as f64 - center[0],
+                        g[1] as f64 / ngrid[1] as f64 - center[1],
+                        g[2] as f64 / ngrid[2] as f64 - center[2],
+                    ];
+                    let c = (0 .. 3)
+                        .map(|j| frac[0] * cell[0][j] + frac[1] * cell[1][j] + frac[2] * cell[2][j])
+                        .collect::<Vec<f64>>();
+                    if (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt() > radius { continue; }
+                    ret.push(self.index(g[0].rem_euclid(ngrid[0] as i64) as usize,
+                                        g[1].rem_euclid(ngrid[1] as i64) as usize,
+                                        g[2].rem_euclid(ngrid[2] as i64) as usize));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Value of the `iblock`th block at fractional coordinates `frac` by trilinear interpolation,
+    /// the data is periodic thus any coordinates are accepted.
+    pub fn interpolate(&self, iblock: usize, frac: &[f64; 3]) -> f64 {
+        let block = &self.blocks[iblock];
+        let mut i0 = [0usize; 3];
+        let mut i1 = [0usize; 3];
+        let mut t = [0.0f64; 3];
+        for a in 0 .. 3 {
+            let n = self.ngrid[a];
+            let x = frac[a].rem_euclid(1.0) * n as f64;
+            let fl = x.floor();
+            i0[a] = (fl as usize) % n;
+            i1[a] = (i0[a] + 1) % n;
+            t[a] = x - fl;
+        }
+
+        let mut ret = 0.0;
+        for corner in 0 .. 8 {
+            let (cx, cy, cz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let w = [(cx, 0), (cy, 1), (cz, 2)].iter()
+                .map(|&(c, a)| if c == 0 { 1.0 - t[a] } else { t[a] })
+                .product::<f64>();
+            let ix = if cx == 0 { i0[0] } else { i1[0] };
+            let iy = if cy == 0 { i0[1] } else { i1[1] };
+            let iz = if cz == 0 { i0[2] } else { i1[2] };
+            ret += w * block[self.index(ix, iy, iz)];
+        }
+        ret
+    }
+
+    /// Resamples all the blocks onto a new grid by Fourier interpolation, i.e. the trigonometric
+    /// interpolant of the data is sampled at the new grid points. The result is exact for
+    /// band-limited data, and the total charge (the average of values) is kept.
+    pub fn resample(&self, ngrid: [usize; 3]) -> Self {
+        assert!(ngrid.iter().all(|&n| n > 0), "Grid size should be positive");
+
+        // Number of 1D FFT lines of each block, the axes are resampled one by one
+        let mut shape = self.ngrid;
+        let mut nlines = 0;
+        for axis in 0 .. 3 {
+            if shape[axis] != ngrid[axis] {
+                nlines += shape.iter().product::<usize>() / shape[axis];
+            }
+            shape[axis] = ngrid[axis];
+        }
+        let progress = Progress::new("Resampling", nlines * self.blocks.len());
+
+        let blocks = self.blocks.iter()
+            .map(|block| {
+                let mut data = block.clone();
+                let mut shape = self.ngrid;
+                for axis in 0 .. 3 {
+                    data = _resample_axis(&data, shape, axis, ngrid[axis], &progress);
+                    shape[axis] = ngrid[axis];
+                }
+                data
+            })
+            .collect();
+        progress.finish();
+
+        Self {
+            pos: self.pos.clone(),
+            ngrid,
+            blocks,
+        }
+    }
+
+    /// Writes the structure and all the blocks in CHGCAR format, the augmentation occupancies
+    /// are not included.
+    pub fn save_as_vasp(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving volumetric data to {:?} ...", path.as_ref());
+        let mut f = io::BufWriter::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)?);
+
+        let poscar = vasp_poscar::Builder::new()
+            .comment("Generated by rsgrad")
+            .scale(vasp_poscar::ScaleLine::Factor(1.0))
+            .lattice_vectors(&self.pos.cell)
+            .group_symbols(self.pos.ion_types.clone())
+            .group_counts(self.pos.ions_per_type.iter().map(|&n| n as usize))
+            .positions(vasp_poscar::Coords::Frac(self.pos.frac_pos.clone()))
+            .build()
+            .unwrap();
+        write!(f, "{:.9}", poscar)?;
+
+        for block in self.blocks.iter() {
+            writeln!(f)?;
+            writeln!(f, "{:5}{:5}{:5}", self.ngrid[0], self.ngrid[1], self.ngrid[2])?;
+            for chunk in block.chunks(5) {
+                for x in chunk.iter() {
+                    write!(f, " {}", _fortran_e(*x))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the `iblock`th block as Gaussian cube file, the values are divided by the volume in
+    /// Bohr^3 as the inverse of `parse_cube`.
+    pub fn save_as_cube(&self, iblock: usize, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving volumetric data to {:?} ...", path.as_ref());
+        let mut f = io::BufWriter::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)?);
+
+        writeln!(f, "Generated by rsgrad")?;
+        writeln!(f, "Block {} of the volumetric data, z runs fastest", iblock + 1)?;
+        writeln!(f, "{:5} {:12.6} {:12.6} {:12.6}", self.pos.car_pos.len(), 0.0, 0.0, 0.0)?;
+        for (n, v) in self.ngrid.iter().zip(self.pos.cell.iter()) {
+            let v = v.map(|x| x / BOHR / *n as f64);
+            writeln!(f, "{:5} {:12.6} {:12.6} {:12.6}", n, v[0], v[1], v[2])?;
+        }
+        let symbols = self.pos.ion_types.iter()
+            .zip(self.pos.ions_per_type.iter())
+            .flat_map(|(s, &n)| std::iter::repeat_n(s, n as usize));
+        for (s, p) in symbols.zip(self.pos.car_pos.iter()) {
+            let z = ELEMENTS.iter().position(|e| e == s).unwrap_or(0);
+            writeln!(f, "{:5} {:12.6} {:12.6} {:12.6} {:12.6}", z, 0.0, p[0] / BOHR, p[1] / BOHR, p[2] / BOHR)?;
+        }
+
+        let volume = _volume(&self.pos.cell) / BOHR.powi(3);
+        let [nx, ny, nz] = self.ngrid;
+        let block = &self.blocks[iblock];
+        for ix in 0 .. nx {
+            for iy in 0 .. ny {
+                let line = (0 .. nz).map(|iz| block[ix + nx * (iy + ny * iz)] / volume).collect::<Vec<f64>>();
+                for chunk in line.chunks(6) {
+                    for x in chunk.iter() {
+                        write!(f, " {:13.5E}", x)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// Resamples the lines along `axis` of data in `shape` (x runs fastest) to `m` points
+fn _resample_axis(data: &[f64], shape: [usize; 3], axis: usize, m: usize, progress: &Progress) -> Vec<f64> {
+    let n = shape[axis];
+    if n == m {
+        return data.to_vec();
+    }
+
+    let stride = [1, shape[0], shape[0] * shape[1]][axis];
+    let mut new_shape = shape;
+    new_shape[axis] = m;
+    let new_stride = [1, new_shape[0], new_shape[0] * new_shape[1]][axis];
+
+    // Starting points of the lines: all the indices except the one along `axis`
+    let (i, j) = match axis { 0 => (1, 2), 1 => (0, 2), _ => (0, 1) };
+    let starts = (0 .. shape[j])
+        .flat_map(|b| (0 .. shape[i]).map(move |a| (a, b)))
+        .map(|(a, b)| {
+            let mut idx = [0usize; 3];
+            idx[i] = a;
+            idx[j] = b;
+            (idx[0] + shape[0] * (idx[1] + shape[1] * idx[2]),
+             idx[0] + new_shape[0] * (idx[1] + new_shape[1] * idx[2]))
+        })
+        .collect::<Vec<_>>();
+
+    let mut planner = FftPlanner::<f64>::new();
+    let fft = planner.plan_fft_forward(n);
+    let ifft = planner.plan_fft_inverse(m);
+
+    let lines = starts.par_iter()
+        .map(|(start, _)| {
+            let mut buf = (0 .. n)
+                .map(|k| Complex::new(data[start + k * stride], 0.0))
+                .collect::<Vec<_>>();
+            fft.process(&mut buf);
+            let mut buf = _resample_spectrum(&buf, m);
+            ifft.process(&mut buf);
+            progress.inc(1);
+            buf.into_iter().map(|c| c.re / n as f64).collect::<Vec<f64>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut ret = vec![0.0f64; new_shape.iter().product()];
+    for ((_, start), line) in starts.iter().zip(lines) {
+        for (k, x) in line.into_iter().enumerate() {
+            ret[start + k * new_stride] = x;
+        }
+    }
+    ret
+}
+
+
+// Maps the DFT coefficients of `n` points to `m` points by their frequencies. The Nyquist
+// component of even `n` is split into +n/2 and -n/2 halves, and the frequencies out of the
+// new band are folded back, which is exactly the sampling of trigonometric interpolant.
+fn _resample_spectrum(spectrum: &[Complex<f64>], m: usize) -> Vec<Complex<f64>> {
+    let n = spectrum.len() as isize;
+    let m = m as isize;
+    let mut ret = vec![Complex::new(0.0, 0.0); m as usize];
+    for (k, c) in spectrum.iter().enumerate() {
+        let k = k as isize;
+        if n % 2 == 0 && k == n / 2 {
+            ret[(k).rem_euclid(m) as usize] += c * 0.5;
+            ret[(-k).rem_euclid(m) as usize] += c * 0.5;
+        } else {
+            let f = if k <= n / 2 { k } else { k - n };
+            ret[f.rem_euclid(m) as usize] += c;
+        }
+    }
+    ret
+}
+
+
+// Fortran style scientific notation used by VASP, e.g. "0.12345678901E+01"
+fn _fortran_e(x: f64) -> String {
+    if x == 0.0 {
+        return "0.00000000000E+00".to_string();
+    }
+    let s = format!("{:.10E}", x.abs());  // "1.2345678901E0"
+    let (mantissa, exp) = s.split_once('E').unwrap();
+    let exp = exp.parse::<i32>().unwrap() + 1;
+    let digits = mantissa.replace('.', "");
+    format!("{}0.{}E{}{:02}", if x < 0.0 { "-" } else { "" }, digits, if exp < 0 { "-" } else { "+" }, exp.abs())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCPOT_SAMPLE: &str = r#"unknown system
+   1.00000000000000
+     4.000000    0.000000    0.000000
+     0.000000    4.000000    0.000000
+     0.000000    0.000000    8.000000
+   H
+     1
+Direct
+  0.000000  0.000000  0.000000
+
+    2    2    4
+ 0.10000000000E+01 0.10000000000E+01 0.10000000000E+01 0.10000000000E+01 0.20000000000E+01
+ 0.20000000000E+01 0.20000000000E+01 0.20000000000E+01 0.30000000000E+01 0.30000000000E+01
+ 0.30000000000E+01 0.30000000000E+01 0.40000000000E+01 0.40000000000E+01 0.50000000000E+01
+ 0.50000000000E+01
+augmentation occupancies   1   2
+  0.1000000E+00  0.2000000E+00
+    2    2    4
+ 0.10000000000E+00 0.10000000000E+00 0.10000000000E+00 0.10000000000E+00 0.20000000000E+00
+ 0.20000000000E+00 0.20000000000E+00 0.20000000000E+00 0.30000000000E+00 0.30000000000E+00
+ 0.30000000000E+00 0.30000000000E+00 0.40000000000E+00 0.40000000000E+00 0.40000000000E+00
+ 0.40000000000E+00
+"#;
+
+    #[test]
+    fn test_parse() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        assert_eq!(chg.ngrid, [2, 2, 4]);
+        assert_eq!(chg.blocks.len(), 2);
+        assert_eq!(chg.pos.ion_types, vec!["H"]);
+        assert_eq!(chg.cell()[2], [0.0, 0.0, 8.0]);
+        assert_eq!(chg.blocks[0][chg.index(1, 1, 3)], 5.0);
+    }
+
+    #[test]
+    fn test_planar_average() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        assert_eq!(chg.planar_average(0, 2), vec![1.0, 2.0, 3.0, 4.5]);
+        assert_eq!(chg.planar_average(1, 2), vec![0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(chg.planar_average(0, 0), vec![2.625, 2.625]);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        assert!((chg.interpolate(0, &[0.0, 0.0, 0.125]) - 1.5).abs() < 1E-10);
+        assert!((chg.interpolate(0, &[0.0, 0.25, 0.75]) - 4.5).abs() < 1E-10);
+        assert!((chg.interpolate(0, &[0.0, 0.0, 0.875]) - 2.5).abs() < 1E-10);
+        assert!((chg.interpolate(0, &[-1.0, 0.0, 0.25]) - 2.0).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_parse_cube() {
+        let mut input = String::from("Cube file from CP2K\nElectron density\n");
+        input += "    3    0.500000    0.000000    0.000000\n";
+        input += "    2    1.000000    0.000000    0.000000\n";
+        input += "    2    0.000000    1.000000    0.000000\n";
+        input += "    2    0.000000    0.000000    1.000000\n";
+        input += "    8    0.000000    1.500000    1.000000    1.000000\n";
+        input += "    1    0.000000    1.500000    0.000000    0.000000\n";
+        input += "    1    0.000000    0.500000    1.000000    0.000000\n";
+        // z runs fastest, the value encodes the indices
+        for ix in 0 .. 2 {
+            for iy in 0 .. 2 {
+                input += &format!(" {} {}\n", ix * 100 + iy * 10, ix * 100 + iy * 10 + 1);
+            }
+        }
+
+        let chg = ChargeDensity::parse_cube(&input);
+        assert_eq!(chg.ngrid, [2, 2, 2]);
+        assert_eq!(chg.pos.ion_types, vec!["O", "H"]);
+        assert_eq!(chg.pos.ions_per_type, vec![1, 2]);
+        assert!((chg.cell()[0][0] - 2.0 * BOHR).abs() < 1E-10);
+        assert!((chg.pos.frac_pos[0][0] - 0.5).abs() < 1E-10);
+        assert!((chg.pos.frac_pos[2][0]).abs() < 1E-10);
+
+        // Scaled by the volume of 8 Bohr^3
+        assert_eq!(chg.blocks[0][chg.index(1, 0, 1)], 101.0 * 8.0);
+        assert_eq!(chg.blocks[0][chg.index(0, 1, 0)], 10.0 * 8.0);
+    }
+
+    #[test]
+    fn test_save_as_cube() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("LOCPOT.cube");
+        chg.save_as_cube(1, &path).unwrap();
+        let cube = ChargeDensity::from_file(&path).unwrap();
+        assert_eq!(cube.ngrid, chg.ngrid);
+        assert_eq!(cube.pos.ion_types, chg.pos.ion_types);
+        assert_eq!(cube.pos.ions_per_type, chg.pos.ions_per_type);
+        assert!(cube.cell().iter().flatten().zip(chg.cell().iter().flatten()).all(|(a, b)| (a - b).abs() < 1E-5));
+        assert!(cube.pos.frac_pos.iter().flatten().zip(chg.pos.frac_pos.iter().flatten()).all(|(a, b)| (a - b).abs() < 1E-5));
+        assert!(cube.blocks[0].iter().zip(chg.blocks[1].iter()).all(|(a, b)| (a - b).abs() <= 1E-4 * b.abs().max(1E-6)));
+    }
+
+    #[test]
+    fn test_resample() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        let fine = chg.resample([4, 3, 8]);
+        assert_eq!(fine.blocks[0].len(), 4 * 3 * 8);
+
+        // Original grid points are kept, and resampling back is exact
+        assert!((fine.blocks[1][fine.index(2, 0, 6)] - chg.blocks[1][chg.index(1, 0, 3)]).abs() < 1E-10);
+        let back = fine.resample([2, 2, 4]);
+        assert!(back.blocks[0].iter().zip(chg.blocks[0].iter()).all(|(x, y)| (x - y).abs() < 1E-10));
+
+        let avg = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        assert!((avg(&fine.blocks[0]) - avg(&chg.blocks[0])).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_fortran_e() {
+        assert_eq!(_fortran_e(1.0), "0.10000000000E+01");
+        assert_eq!(_fortran_e(-0.00123), "-0.12300000000E-02");
+        assert_eq!(_fortran_e(0.0), "0.00000000000E+00");
+    }
+
+    #[test]
+    fn test_save_as_vasp() {
+        let chg = ChargeDensity::parse(LOCPOT_SAMPLE);
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("CHGCAR");
+        chg.save_as_vasp(&path).unwrap();
+        let saved = ChargeDensity::from_file(&path).unwrap();
+        assert_eq!(saved.ngrid, chg.ngrid);
+        assert_eq!(saved.blocks, chg.blocks);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected end of volumetric data")]
+    fn test_parse_fail() {
+        let input = LOCPOT_SAMPLE.lines().take(13).collect::<Vec<_>>().join("\n");
+        ChargeDensity::parse(&input);
+    }
+}
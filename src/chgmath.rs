@@ -0,0 +1,238 @@
+use std::collections::BTreeSet;
+use rayon::prelude::*;
+
+
+/// Arithmetic expression over volumetric data, e.g. "A - 0.5*(B+C)".
+///
+/// The grammar supports numbers, variables named by one uppercase letter, `+ - * / ^`,
+/// parentheses, unary minus and the functions `abs`, `sqrt`, `exp`, `ln`, `min` and `max`.
+/// `^` is right associative and binds tighter than unary minus, i.e. "-A^2" is "-(A^2)".
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Var(char),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parser = Parser { chars: input.chars().filter(|c| !c.is_whitespace()).collect(), pos: 0 };
+        let expr = parser.expr()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("Unexpected '{}' at position {} of \"{}\"", parser.chars[parser.pos], parser.pos + 1, input));
+        }
+        Ok(expr)
+    }
+
+    /// Variables in alphabetical order.
+    pub fn variables(&self) -> Vec<char> {
+        let mut vars = BTreeSet::new();
+        self._collect_variables(&mut vars);
+        vars.into_iter().collect()
+    }
+
+    fn _collect_variables(&self, vars: &mut BTreeSet<char>) {
+        match self {
+            Expr::Number(_) => {},
+            Expr::Var(c) => { vars.insert(*c); },
+            Expr::Neg(e) => e._collect_variables(vars),
+            Expr::Binary(_, a, b) => {
+                a._collect_variables(vars);
+                b._collect_variables(vars);
+            },
+            Expr::Call(_, args) => args.iter().for_each(|a| a._collect_variables(vars)),
+        }
+    }
+
+    /// Evaluates the expression with the variables looked up by `value`.
+    pub fn eval(&self, value: &impl Fn(char) -> f64) -> f64 {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Var(c) => value(*c),
+            Expr::Neg(e) => -e.eval(value),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(value), b.eval(value));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _   => a.powf(b),
+                }
+            },
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|a| a.eval(value)).collect::<Vec<f64>>();
+                match name.as_str() {
+                    "abs"  => args[0].abs(),
+                    "sqrt" => args[0].sqrt(),
+                    "exp"  => args[0].exp(),
+                    "ln"   => args[0].ln(),
+                    "min"  => args.iter().cloned().fold(f64::INFINITY, f64::min),
+                    _      => args.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                }
+            },
+        }
+    }
+
+    /// Evaluates the expression point by point over grids of the same size, the variables are
+    /// indexed by `vars` in the order of `grids`. No intermediate grids are allocated.
+    pub fn eval_grids(&self, vars: &[char], grids: &[&[f64]]) -> Vec<f64> {
+        assert_eq!(vars.len(), grids.len(), "Inconsistent number of variables and grids");
+        let npoints = grids.first().map_or(0, |g| g.len());
+        assert!(grids.iter().all(|g| g.len() == npoints), "Grids of different sizes");
+        (0 .. npoints)
+            .into_par_iter()
+            .map(|i| self.eval(&|c| {
+                let ivar = vars.iter().position(|v| *v == c).expect("Undefined variable");
+                grids[ivar][i]
+            }))
+            .collect()
+    }
+}
+
+
+struct Parser {
+    chars : Vec<char>,
+    pos   : usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() {
+            Some(x) if x == c => { self.pos += 1; Ok(()) },
+            Some(x) => Err(format!("Expected '{}' but found '{}' at position {}", c, x, self.pos + 1)),
+            None => Err(format!("Expected '{}' but the expression ended", c)),
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    // unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => { self.pos += 1; Ok(Expr::Neg(Box::new(self.unary()?))) },
+            Some('+') => { self.pos += 1; self.unary() },
+            _ => self.power(),
+        }
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    // atom := number | variable | function '(' expr (',' expr)* ')' | '(' expr ')'
+    fn atom(&mut self) -> Result<Expr, String> {
+        let c = self.peek().ok_or_else(|| "Unexpected end of the expression".to_string())?;
+        if c == '(' {
+            self.pos += 1;
+            let e = self.expr()?;
+            self.expect(')')?;
+            return Ok(e);
+        }
+
+        if c.is_ascii_digit() || c == '.' {
+            let start = self.pos;
+            while let Some(c) = self.peek() {
+                let exponent_sign = (c == '+' || c == '-')
+                    && matches!(self.chars.get(self.pos.wrapping_sub(1)), Some('e' | 'E'));
+                if c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+            let s = self.chars[start .. self.pos].iter().collect::<String>();
+            return s.parse::<f64>()
+                .map(Expr::Number)
+                .map_err(|_| format!("Invalid number \"{}\" at position {}", s, start + 1));
+        }
+
+        if c.is_ascii_alphabetic() {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                self.pos += 1;
+            }
+            let name = self.chars[start .. self.pos].iter().collect::<String>();
+            if name.len() == 1 && c.is_ascii_uppercase() {
+                return Ok(Expr::Var(c));
+            }
+
+            let nargs = match name.as_str() {
+                "abs" | "sqrt" | "exp" | "ln" => 1,
+                "min" | "max" => 2,
+                _ => return Err(format!("Unknown variable or function \"{}\" at position {}, variables are single uppercase letters", name, start + 1)),
+            };
+            self.expect('(')?;
+            let mut args = vec![self.expr()?];
+            while self.peek() == Some(',') {
+                self.pos += 1;
+                args.push(self.expr()?);
+            }
+            self.expect(')')?;
+            if (nargs == 1 && args.len() != 1) || args.len() < nargs {
+                return Err(format!("Function \"{}\" takes {} argument{}", name, nargs, if nargs > 1 { "s or more" } else { "" }));
+            }
+            return Ok(Expr::Call(name, args));
+        }
+
+        Err(format!("Unexpected '{}' at position {}", c, self.pos + 1))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expr() {
+        let expr = Expr::parse("A - 0.5*(B+C)").unwrap();
+        assert_eq!(expr.variables(), vec!['A', 'B', 'C']);
+        let value = |c: char| match c { 'A' => 4.0, 'B' => 1.0, _ => 3.0 };
+        assert_eq!(expr.eval(&value), 2.0);
+
+        assert_eq!(Expr::parse("-A^2 + 2^3^2 - 1.5e-1*4").unwrap().eval(&value), -16.0 + 512.0 - 0.6);
+        assert_eq!(Expr::parse("max(A, B, C) / abs(-2) + sqrt(B)").unwrap().eval(&value), 3.0);
+        assert_eq!(Expr::parse("2 * -B").unwrap().eval(&value), -2.0);
+
+        assert!(Expr::parse("A + ").is_err());
+        assert!(Expr::parse("(A + B").is_err());
+        assert!(Expr::parse("A B").is_err());
+        assert!(Expr::parse("foo(A)").is_err());
+        assert!(Expr::parse("sqrt(A, B)").is_err());
+
+        let a = [1.0, 2.0, 3.0];
+        let b = [0.5, 0.5, 0.5];
+        assert_eq!(Expr::parse("A*B - B").unwrap().eval_grids(&['A', 'B'], &[&a, &b]), vec![0.0, 0.5, 1.0]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::chgcar::ChargeDensity;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SphereCharge {
+    pub index  : usize,     // starts from 1
+    pub symbol : String,
+    pub radius : f64,       // in Angstrom
+    pub charge : f64,       // electrons enclosed in the sphere
+    pub magmom : Vec<f64>,  // empty for ISPIN=1, 1 component for ISPIN=2 and 3 for non-collinear
+}
+
+
+/// Charge and magnetization integrated within spheres centered on atoms, like the
+/// "total charge" and "magnetization" sections in OUTCAR with RWIGS set.
+///
+/// Spheres of neighbouring atoms may overlap or leave gaps, so the sum is not the total
+/// charge of the cell. This is a quick but rough alternative to Bader analysis.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SphereCharges(pub Vec<SphereCharge>);
+
+impl SphereCharges {
+    /// `iatoms` are the selected atom indices starting from 0, and `radii` are the sphere
+    /// radii of each species in POSCAR.
+    pub fn new(chg: &ChargeDensity, iatoms: &[usize], radii: &[f64]) -> Self {
+        assert_eq!(radii.len(), chg.pos.ion_types.len(), "Inconsistent numbers of radii and species");
+
+        let symbols = chg.pos.symbols();
+        let ion_radii = radii.iter()
+            .zip(chg.pos.ions_per_type.iter())
+            .flat_map(|(r, n)| vec![*r; *n as usize])
+            .collect::<Vec<f64>>();
+
+        // CHGCAR stores rho * V_cell, the integral is simply the average over enclosed points
+        let npoints = chg.npoints() as f64;
+        let nmag = match chg.blocks.len() {
+            2 => 1,
+            4 => 3,
+            _ => 0,
+        };
+
+        let ret = iatoms.iter()
+            .map(|&i| {
+                let indices = chg.sphere_indices(&chg.pos.frac_pos[i], ion_radii[i]);
+                let integrate = |block: &[f64]| indices.iter().map(|&j| block[j]).sum::<f64>() / npoints;
+                SphereCharge {
+                    index: i + 1,
+                    symbol: symbols[i].clone(),
+                    radius: ion_radii[i],
+                    charge: integrate(&chg.blocks[0]),
+                    magmom: chg.blocks[1 .. 1 + nmag].iter().map(|b| integrate(b)).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Self(ret)
+    }
+
+    fn _magmom_names(&self) -> Vec<&'static str> {
+        match self.0.first().map(|s| s.magmom.len()) {
+            Some(1) => vec!["mag"],
+            Some(3) => vec!["mx", "my", "mz"],
+            _ => vec![],
+        }
+    }
+}
+
+impl Tabular for SphereCharges {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["index", "symbol", "radius", "charge"];
+        ret.extend(self._magmom_names());
+        ret.into_iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|s| {
+                let mut row = vec![
+                    s.index.to_string(),
+                    s.symbol.clone(),
+                    format!("{:.3}", s.radius),
+                    format!("{:.4}", s.charge),
+                ];
+                row.extend(s.magmom.iter().map(|m| format!("{:.4}", m)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SphereCharges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut header = "  #Ion Elem  Radius/A    Charge".to_string();
+        header += &self._magmom_names().iter().map(|s| format!("{:>10}", s)).collect::<String>();
+        writeln!(f, "{}", header.bright_green())?;
+
+        for s in self.0.iter() {
+            let magmom = s.magmom.iter().map(|m| format!("{:10.4}", m)).collect::<String>();
+            writeln!(f, "  {:4} {:>4} {:9.3} {}{}",
+                     s.index, s.symbol, s.radius, format!("{:9.4}", s.charge).bright_yellow(), magmom)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Structure;
+
+    #[test]
+    fn test_sphere_charges() {
+        // Each grid point holds 1 electron, and grid spacing is 1 A
+        let chg = ChargeDensity {
+            pos: Structure {
+                cell: [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]],
+                ion_types: vec!["Se".to_string(), "V".to_string()],
+                ions_per_type: vec![1, 1],
+                car_pos: vec![[0.0; 3], [2.0; 3]],
+                frac_pos: vec![[0.0; 3], [0.5; 3]],
+            },
+            ngrid: [4, 4, 4],
+            blocks: vec![vec![64.0; 64], vec![-32.0; 64]],
+        };
+
+        let charges = SphereCharges::new(&chg, &[0, 1], &[0.5, 1.0]);
+        assert_eq!(charges.0[0].charge, 1.0);
+        assert_eq!(charges.0[1].charge, 7.0);
+        assert_eq!(charges.0[1].magmom, vec![-3.5]);
+        assert_eq!(charges.headers(), vec!["index", "symbol", "radius", "charge", "mag"]);
+
+        let charges = SphereCharges::new(&chg, &[1], &[0.5, 1.8]);
+        assert_eq!(charges.rows(), vec![vec!["2", "V", "1.800", "27.0000", "-13.5000"]]);
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use log::info;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+// One bond in COHPCAR.lobster, e.g. "No.1:Fe1->O2(1.98765)" or "No.3:Fe1[3dxy]->O2[2px](1.98765)"
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Interaction {
+    pub index    : usize,   // starts from 1, the same as LOBSTER
+    pub label    : String,  // e.g. "Fe1->O2"
+    pub atom1    : String,
+    pub atom2    : String,
+    pub distance : f64,     // in Angstrom
+}
+
+impl Interaction {
+    fn parse(line: &str, index: usize) -> Self {
+        let re = Regex::new(r"^No\.\d+:([A-Za-z]+\d+)(\[[^\]]*\])?->([A-Za-z]+\d+)(\[[^\]]*\])?\(([-0-9.]+)\)")
+            .unwrap();
+        let line = line.trim();
+        match re.captures(line) {
+            Some(c) => Self {
+                index,
+                label: format!("{}{}->{}{}", &c[1], c.get(2).map_or("", |m| m.as_str()),
+                               &c[3], c.get(4).map_or("", |m| m.as_str())),
+                atom1: c[1].to_string(),
+                atom2: c[3].to_string(),
+                distance: c[5].parse().expect("Cannot parse bond length in COHPCAR"),
+            },
+            None => Self {
+                index,
+                label: line.to_string(),
+                atom1: String::new(),
+                atom2: String::new(),
+                distance: f64::NAN,
+            },
+        }
+    }
+
+    /// `pair` is like "Fe1-O2" or "Fe-O", where the element symbols match all the atoms of
+    /// this element. The order of the two atoms is ignored.
+    pub fn matches(&self, pair: &str) -> bool {
+        let (a, b) = match pair.split_once('-') {
+            Some(x) => x,
+            None => return false,
+        };
+        let is_match = |pat: &str, atom: &str| {
+            pat == atom || (!pat.is_empty() && pat.chars().all(|c| c.is_ascii_alphabetic())
+                            && atom.trim_end_matches(|c: char| c.is_ascii_digit()) == pat)
+        };
+        (is_match(a, &self.atom1) && is_match(b, &self.atom2)) ||
+        (is_match(b, &self.atom1) && is_match(a, &self.atom2))
+    }
+}
+
+
+/// Crystal orbital Hamilton (or overlap) populations written by LOBSTER, energies are relative to
+/// E-fermi already.
+///
+/// pCOHP and IpCOHP are indexed by `[ispin][iinteraction][ienergy]`, the average over all the bonds
+/// is not kept.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Cohpcar {
+    pub is_coop      : bool,
+    pub nspin        : usize,
+    pub efermi       : f64,
+    pub energies     : Vec<f64>,
+    pub interactions : Vec<Interaction>,
+    pub cohp         : Vec<Vec<Vec<f64>>>,
+    pub icohp        : Vec<Vec<Vec<f64>>>,
+}
+
+impl Cohpcar {
+    /// COOPCAR is recognized by the file name.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        let is_coop = path.as_ref()
+            .file_name()
+            .map(|f| f.to_string_lossy().to_uppercase().contains("COOP"))
+            .unwrap_or(false);
+        Ok(Self::parse(&context, is_coop))
+    }
+
+    pub fn parse(context: &str, is_coop: bool) -> Self {
+        let mut lines = context.lines();
+        lines.next();  // title
+        let params = lines.next()
+            .expect("Unexpected end of COHPCAR")
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        assert!(params.len() >= 6, "Invalid parameter line of COHPCAR");
+
+        let ncols = params[0].parse::<usize>().expect("Cannot parse number of COHPs in COHPCAR");  // including the average
+        let nspin = params[1].parse::<usize>().expect("Cannot parse ISPIN in COHPCAR");
+        let nedos = params[2].parse::<usize>().expect("Cannot parse number of energy points in COHPCAR");
+        let efermi = params[5].parse::<f64>().expect("Cannot parse E-fermi in COHPCAR");
+        let nbonds = ncols - 1;
+
+        lines.next();  // "Average"
+        let interactions = (0 .. nbonds)
+            .map(|i| Interaction::parse(lines.next().expect("Unexpected end of COHPCAR"), i + 1))
+            .collect::<Vec<_>>();
+
+        let mut energies = Vec::with_capacity(nedos);
+        let mut cohp = vec![vec![Vec::with_capacity(nedos); nbonds]; nspin];
+        let mut icohp = cohp.clone();
+        for line in lines.filter(|l| !l.trim().is_empty()).take(nedos) {
+            let v = line.split_whitespace()
+                .map(|x| x.parse::<f64>().expect("Cannot parse COHPCAR as float values"))
+                .collect::<Vec<f64>>();
+            assert_eq!(v.len(), 1 + nspin * ncols * 2, "Inconsistent number of columns in COHPCAR");
+
+            energies.push(v[0]);
+            for ispin in 0 .. nspin {
+                let beg = 1 + ispin * ncols * 2 + 2;  // skips the average
+                for (ib, x) in v[beg .. beg + nbonds * 2].chunks(2).enumerate() {
+                    cohp[ispin][ib].push(x[0]);
+                    icohp[ispin][ib].push(x[1]);
+                }
+            }
+        }
+
+        Self {
+            is_coop,
+            nspin,
+            efermi,
+            energies,
+            interactions,
+            cohp,
+            icohp,
+        }
+    }
+
+    /// Returns the indices of interactions, starting from 0, matched by any of the `pairs`.
+    pub fn select_pairs(&self, pairs: &[String]) -> Vec<usize> {
+        self.interactions.iter()
+            .enumerate()
+            .filter(|(_, b)| pairs.iter().any(|p| b.matches(p)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Integrated value at E-fermi, linearly interpolated
+    fn _icohp_at_efermi(&self, ispin: usize, ib: usize) -> f64 {
+        let e = &self.energies;
+        let y = &self.icohp[ispin][ib];
+        match e.iter().position(|x| *x >= 0.0) {
+            Some(0) => y[0],
+            Some(i) => y[i - 1] + (y[i] - y[i - 1]) * (0.0 - e[i - 1]) / (e[i] - e[i - 1]),
+            None => *y.last().unwrap(),
+        }
+    }
+
+    fn _name(&self) -> &'static str {
+        if self.is_coop { "COOP" } else { "COHP" }
+    }
+}
+
+
+/// Selected interactions of COHPCAR, with the integrated values up to E-fermi.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CohpSelection<'a> {
+    #[serde(skip)]
+    pub cohpcar      : &'a Cohpcar,
+    #[serde(skip)]
+    pub selected     : Vec<usize>,
+    pub interactions : Vec<Interaction>,
+    pub icohp        : Vec<Vec<f64>>,  // [iselected][ispin]
+}
+
+impl<'a> CohpSelection<'a> {
+    pub fn new(cohpcar: &'a Cohpcar, selected: Vec<usize>) -> Self {
+        let icohp = selected.iter()
+            .map(|&ib| (0 .. cohpcar.nspin).map(|ispin| cohpcar._icohp_at_efermi(ispin, ib)).collect())
+            .collect();
+        let interactions = selected.iter().map(|&ib| cohpcar.interactions[ib].clone()).collect();
+        Self {
+            cohpcar,
+            selected,
+            interactions,
+            icohp,
+        }
+    }
+
+    // Sums of the selected interactions, [ispin][ienergy]
+    fn _sum(&self) -> Vec<Vec<f64>> {
+        let c = self.cohpcar;
+        (0 .. c.nspin)
+            .map(|ispin| {
+                (0 .. c.energies.len())
+                    .map(|ie| self.selected.iter().map(|&ib| c.cohp[ispin][ib][ie]).sum())
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let c = self.cohpcar;
+        let fname = _prepare_fname(path, &format!("{}.txt", c._name().to_lowercase()))?;
+        info!("Saving {} to {:?} ...", c._name(), &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        let suffix = |ispin: usize| -> &str {
+            match (c.nspin, ispin) {
+                (1, _) => "",
+                (_, 0) => "_up",
+                _      => "_dn",
+            }
+        };
+
+        write!(f, "# {:>10}", "E-Ef/eV")?;
+        for ispin in 0 .. c.nspin {
+            write!(f, " {:>16}", format!("sum{}", suffix(ispin)))?;
+        }
+        for &ib in self.selected.iter() {
+            for ispin in 0 .. c.nspin {
+                write!(f, " {:>16}", format!("{}{}", c.interactions[ib].label, suffix(ispin)))?;
+            }
+        }
+        writeln!(f)?;
+
+        let sum = self._sum();
+        for (ie, e) in c.energies.iter().enumerate() {
+            write!(f, "  {:10.5}", e)?;
+            for s in sum.iter() {
+                write!(f, " {:16.6}", s[ie])?;
+            }
+            for &ib in self.selected.iter() {
+                for ispin in 0 .. c.nspin {
+                    write!(f, " {:16.6}", c.cohp[ispin][ib][ie])?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    /// -pCOHP is plotted such that bonding states are positive, COOP is plotted as is.
+    /// Spin down components are plotted with dashed lines.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let c = self.cohpcar;
+        let fname = _prepare_fname(path, &format!("{}.html", c._name().to_lowercase()))?;
+        let (sign, ytitle) = if c.is_coop { (1.0, "pCOOP") } else { (-1.0, "-pCOHP") };
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Crystal orbital {} population", if c.is_coop { "overlap" } else { "Hamilton" }),
+                "xaxis": {"title": "E-Ef (eV)"},
+                "yaxis": {"title": ytitle},
+                "shapes": [{
+                    "type": "line", "xref": "x", "yref": "paper",
+                    "x0": 0.0, "x1": 0.0, "y0": 0.0, "y1": 1.0,
+                    "line": {"dash": "dash", "color": "gray", "width": 1},
+                }],
+            }));
+
+        let mut add = |name: &str, ispin: usize, y: &[f64], color: Option<&str>| {
+            let suffix = match (c.nspin, ispin) {
+                (1, _) => "",
+                (_, 0) => " up",
+                _      => " down",
+            };
+            let mut line = json!({"dash": if ispin == 1 { "dash" } else { "solid" }});
+            if let Some(color) = color {
+                line["color"] = json!(color);
+            }
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("{}{}", name, suffix),
+                "x": c.energies,
+                "y": y.iter().map(|v| v * sign).collect::<Vec<f64>>(),
+                "line": line,
+            }));
+        };
+
+        if self.selected.len() > 1 {
+            for (ispin, s) in self._sum().iter().enumerate() {
+                add("sum", ispin, s, Some("gray"));
+            }
+        }
+        for &ib in self.selected.iter() {
+            for ispin in 0 .. c.nspin {
+                add(&c.interactions[ib].label, ispin, &c.cohp[ispin][ib], None);
+            }
+        }
+
+        plot.save_html(&fname)
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+impl Tabular for CohpSelection<'_> {
+    fn headers(&self) -> Vec<String> {
+        let name = format!("i{}", self.cohpcar._name().to_lowercase());
+        let mut ret = vec!["index".to_string(), "label".to_string(), "distance".to_string()];
+        match self.cohpcar.nspin {
+            1 => ret.push(name),
+            _ => ret.extend([format!("{}_up", name), format!("{}_dn", name), name]),
+        }
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.interactions.iter()
+            .zip(self.icohp.iter())
+            .map(|(b, ic)| {
+                let mut ret = vec![b.index.to_string(), b.label.clone(), format!("{:.5}", b.distance)];
+                if ic.len() > 1 {
+                    ret.extend(ic.iter().map(|x| format!("{:.5}", x)));
+                }
+                ret.push(format!("{:.5}", ic.iter().sum::<f64>()));
+                ret
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for CohpSelection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers = self.headers();
+        writeln!(f, "{}", format!("# Integrated {} up to E-fermi (eV)", self.cohpcar._name()).bright_green())?;
+        writeln!(f, "{}", format!("  {:>5}  {:<24} {:>9}{}", headers[0], headers[1], headers[2],
+                                   headers[3..].iter().map(|h| format!(" {:>10}", h)).collect::<String>()).bright_green())?;
+        for row in self.rows() {
+            let n = row.len();
+            writeln!(f, "  {:>5}  {:<24} {:>9}{} {}", row[0], row[1], row[2],
+                     row[3 .. n - 1].iter().map(|x| format!(" {:>10}", x)).collect::<String>(),
+                     format!("{:>10}", row[n - 1]).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COHPCAR: &str = r#"COHPCAR.lobster
+       3       2       4   -2.00000     1.00000     5.12345
+Average
+No.1:Fe1->O2(1.98000)
+No.2:Fe1[3dxy]->Fe3[3dxy](2.50000)
+  -2.00000  -0.1  -0.1  -0.2  -0.2  -0.3  -0.3  -0.1  -0.1  -0.2  -0.2  -0.4  -0.4
+  -1.00000  -0.2  -0.3  -0.4  -0.6  -0.5  -0.8  -0.2  -0.3  -0.4  -0.6  -0.6  -1.0
+   0.00000   0.1  -0.2   0.2  -0.4   0.3  -0.5   0.1  -0.2   0.2  -0.4   0.4  -0.6
+   1.00000   0.0  -0.2   0.0  -0.4   0.0  -0.5   0.0  -0.2   0.0  -0.4   0.0  -0.6
+"#;
+
+    #[test]
+    fn test_parse_cohpcar() {
+        let c = Cohpcar::parse(COHPCAR, false);
+        assert_eq!(c.nspin, 2);
+        assert_eq!(c.efermi, 5.12345);
+        assert_eq!(c.energies, vec![-2.0, -1.0, 0.0, 1.0]);
+        assert_eq!(c.interactions.len(), 2);
+        assert_eq!(c.interactions[1].label, "Fe1[3dxy]->Fe3[3dxy]");
+        assert_eq!(c.interactions[1].atom2, "Fe3");
+        assert_eq!(c.interactions[0].distance, 1.98);
+        assert_eq!(c.cohp[0][1], vec![-0.3, -0.5, 0.3, 0.0]);
+        assert_eq!(c.icohp[1][1], vec![-0.4, -1.0, -0.6, -0.6]);
+
+        assert_eq!(c.select_pairs(&["O-Fe1".to_string()]), vec![0]);
+        assert_eq!(c.select_pairs(&["Fe-Fe".to_string()]), vec![1]);
+        assert_eq!(c.select_pairs(&["Fe1-O3".to_string()]), Vec::<usize>::new());
+
+        let sel = CohpSelection::new(&c, vec![0, 1]);
+        assert_eq!(sel.icohp[1], vec![-0.5, -0.6]);
+        assert_eq!(sel.rows()[1], vec!["2", "Fe1[3dxy]->Fe3[3dxy]", "2.50000", "-0.50000", "-0.60000", "-1.10000"]);
+        let sum = sel._sum();
+        assert!(sum[0].iter().zip([-0.5, -0.9, 0.5, 0.0]).all(|(x, y)| (x - y).abs() < 1E-10));
+    }
+}
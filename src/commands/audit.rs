@@ -0,0 +1,77 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::potcar::Potcar;
+use crate::audit::{
+    Incar,
+    AuditReport,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Cross-checks the inputs of a finished calculation and reports the suspicious settings
+///
+/// INCAR, POTCAR and KPOINTS are checked against the structure and band gap in OUTCAR: ENCUT vs
+/// ENMAX of POTCAR, ISMEAR and SIGMA vs the band gap, k-point density vs the cell size, LREAL vs
+/// the number of atoms, LASPH with d/f elements, and the convergence of the electronic and
+/// ionic steps. Tags missing in INCAR take the VASP defaults, and the checks of missing files
+/// are skipped.
+pub struct Audit {
+    #[structopt(long, default_value = "./INCAR")]
+    /// Specify the INCAR file name
+    incar: PathBuf,
+
+    #[structopt(long, default_value = "./POTCAR")]
+    /// Specify the POTCAR file name
+    potcar: PathBuf,
+
+    #[structopt(long, default_value = "./KPOINTS")]
+    /// Specify the KPOINTS file name, KSPACING of INCAR is used if not found
+    kpoints: PathBuf,
+}
+
+impl OptProcess for Audit {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+
+        let incar_path = global.resolve(&self.incar);
+        let incar = match Incar::from_file(&incar_path) {
+            Ok(incar) => incar,
+            Err(e) => {
+                warn!("Cannot read {:?}: {}, all the tags take the VASP defaults.", &incar_path, e);
+                Incar::default()
+            },
+        };
+
+        let potcar_path = global.resolve(&self.potcar);
+        let potcar = if potcar_path.is_file() {
+            info!("Reading POTCAR file {:?} ...", &potcar_path);
+            Some(Potcar::from_file(&potcar_path)?)
+        } else {
+            warn!("{:?} not found, ENCUT is not checked.", &potcar_path);
+            None
+        };
+
+        let kpoints_path = global.resolve(&self.kpoints);
+        let kpoints = fs::read_to_string(&kpoints_path).ok();
+        if kpoints.is_none() {
+            info!("{:?} not found, the k-mesh follows KSPACING of INCAR.", &kpoints_path);
+        }
+
+        let report = AuditReport::new(&outcar, &incar, potcar.as_ref(), kpoints.as_deref());
+        print_formatted(&report, global.output_format)
+    }
+}
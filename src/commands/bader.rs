@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::bader::{
+    Acf,
+    BaderCharges,
+};
+use crate::potcar::Potcar;
+use crate::format::Structure;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Calculates the net atomic charges from the ACF.dat of Bader charge analysis
+pub struct Bader {
+    #[structopt(long, default_value = "./ACF.dat")]
+    /// Specify the ACF.dat file name
+    acf: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(long, default_value = "./POTCAR")]
+    /// Specify the POTCAR file name, where the valence electrons (ZVAL) are read
+    potcar: PathBuf,
+
+    #[structopt(long)]
+    /// Valence electrons of each species in POSCAR, POTCAR is not read if given
+    zval: Vec<f64>,
+
+    #[structopt(long)]
+    /// Saves the structure with Bader charges and net charges as extended XYZ file
+    extxyz: Option<PathBuf>,
+}
+
+impl OptProcess for Bader {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let acf_path = global.resolve(&self.acf);
+        let poscar_path = global.resolve(&self.poscar);
+        let potcar_path = global.resolve(&self.potcar);
+        info!("Reading ACF.dat file {:?} ...", &acf_path);
+        let acf = Acf::from_file(&acf_path)?;
+
+        info!("Reading POSCAR file {:?} ...", &poscar_path);
+        let structure = Structure::from(Poscar::from_path(&poscar_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+
+        let zvals = if self.zval.is_empty() {
+            info!("Reading POTCAR file {:?} ...", &potcar_path);
+            let potcar = Potcar::from_file(&potcar_path)?;
+            if potcar.symbols() != structure.ion_types {
+                warn!("Element symbols of POTCAR {:?} differ from POSCAR {:?}",
+                      potcar.symbols(), structure.ion_types);
+            }
+            potcar.zvals()
+        } else {
+            self.zval.clone()
+        };
+
+        let charges = BaderCharges::new(&acf, structure, &zvals);
+        if let Some(path) = self.extxyz.as_ref() {
+            charges.save_as_extxyz(path)?;
+        }
+        print_formatted(&charges, global.output_format)
+    }
+}
@@ -0,0 +1,552 @@
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::wavecar::Wavecar;
+use crate::format::Structure;
+use crate::fscorr::_reciprocal;
+use crate::outcar::{
+    Outcar,
+    Mat33,
+};
+use crate::selection::RawSelection;
+use crate::band::{
+    BandConfig,
+    BandEntry,
+    BandStructure,
+    KpathSegments,
+    SocComparison,
+    save_comparison_html,
+    save_comparison_image,
+};
+use crate::wannier::{
+    WannierHamiltonian,
+    WinKpath,
+};
+use crate::template::{
+    TemplateContext,
+    save_template,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Plots the band structure from PROCAR of a line-mode calculation, with bands traced through crossings
+///
+/// The bands at neighbouring k-points are connected by the similarity of their atomic and
+/// orbital projections, penalized by the energy difference, instead of the energy ordering.
+/// With `--wavecar`, the overlaps of wavefunctions at neighbouring k-points are used instead,
+/// which also tell apart the crossing bands of the same atomic characters.
+/// The k-path distances are calculated with the reciprocal lattice of POSCAR, or fractional
+/// coordinates if POSCAR is not available.
+///
+/// For hybrid functional band structures, where the SCF mesh with nonzero weights and the
+/// zero-weight band path are in the same PROCAR, only the zero-weight k-points are plotted.
+/// E-fermi is read from OUTCAR, which is determined by the SCF mesh, or the highest occupied
+/// state of the SCF mesh if OUTCAR is not available.
+///
+/// The high symmetry points are labelled by the line-mode KPOINTS file if present, or by
+/// `--kpoint-labels` for a continuous path with equal number of k-points per segment.
+///
+/// Several calculations can be overlaid in one plot with their Fermi levels aligned, by
+/// repeating `--procar` or by a TOML config file with `[[bands]]` entries. For repeated
+/// `--procar`, the OUTCAR, POSCAR and KPOINTS next to each PROCAR are used, unless `--outcar`
+/// is repeated as many times.
+///
+/// With `--soc-compare`, the two calculations given are taken as without and with spin-orbit
+/// coupling, in this order. Besides the overlaid plot, the SOC splittings of the states around
+/// E-fermi are reported at the high symmetry points, or the k-points of `--soc-kpoints`.
+///
+/// Fat bands of the atoms and orbitals selected by `--select` are saved per selection, and
+/// drawn as markers in the plot of a single calculation.
+///
+/// For ISPIN=2, the band gaps of each spin channel and the spin-flip gaps are reported, and the
+/// VBM and CBM of the majority and minority spins are marked in the plot.
+///
+/// Bands interpolated from the Wannier Hamiltonian given by `--wannier-hr` are overlaid on the
+/// band structure of a single calculation, at the k-points of PROCAR or along the k-path of
+/// `--wannier-win`, with the same E-fermi. The deviations from the VASP bands are reported if
+/// the k-points are shared.
+pub struct Band {
+    #[structopt(short, long)]
+    /// Specify the TOML config file, other options except `--kpoint-labels`, `--wide`,
+    /// `--no-html`, `--save-image`, `--dpi` and `--save-in` are ignored if given
+    config: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Generate a commented config template at the path of `--config` (./band.toml if not
+    /// given), with the orbitals of the first PROCAR and atoms of POSCAR found listed
+    gen_template: bool,
+
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file names, multiple calculations are overlaid
+    procar: Vec<PathBuf>,
+
+    #[structopt(long)]
+    /// Specify the OUTCAR file names for each PROCAR, where E-fermi is read
+    outcar: Vec<PathBuf>,
+
+    #[structopt(long)]
+    /// Legends of each calculation, the directories of PROCAR are used if not given
+    labels: Vec<String>,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, used to calculate the k-path distances
+    poscar: PathBuf,
+
+    #[structopt(long, default_value = "./KPOINTS")]
+    /// Specify the line-mode KPOINTS file name, where the labels of k-path are read
+    kpoints: PathBuf,
+
+    #[structopt(long)]
+    /// Labels of the high symmetry points, e.g. "G X M G", overrides the labels in KPOINTS
+    kpoint_labels: Vec<String>,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV for each PROCAR, read from OUTCAR if not given
+    efermi: Vec<f64>,
+
+    #[structopt(long, default_value = "0.0")]
+    /// Scissor operator in eV, rigidly shifts the states above E-fermi to correct the band gap
+    scissor: f64,
+
+    #[structopt(long)]
+    /// Don't trace the bands, keep the energy ordering of PROCAR
+    no_trace: bool,
+
+    #[structopt(long, default_value = "1.0")]
+    /// Energy difference in eV that costs the same as completely dissimilar projections when tracing
+    ewidth: f64,
+
+    #[structopt(long)]
+    /// Specify the WAVECAR file names for each PROCAR, the bands are traced by the overlaps of
+    /// wavefunctions at neighbouring k-points instead of the projections
+    wavecar: Vec<PathBuf>,
+
+    #[structopt(short, long)]
+    /// Shorthand selections of atoms and orbitals for fat bands, e.g. "Fe:d O:p 1..4:s", the
+    /// weights are saved as fatband_<label>.txt and drawn as markers
+    select: Option<String>,
+
+    #[structopt(long)]
+    /// Specify wannier90_hr.dat to overlay the Wannier interpolated bands, given twice for the
+    /// spin up and down channels of ISPIN=2, e.g. wannier90.up_hr.dat and wannier90.dn_hr.dat
+    wannier_hr: Vec<PathBuf>,
+
+    #[structopt(long)]
+    /// Specify wannier90.win, the bands are interpolated along its `kpoint_path` instead of the
+    /// k-points of PROCAR
+    wannier_win: Option<PathBuf>,
+
+    #[structopt(long, default_value = "2.0")]
+    /// Upper bound of the energies relative to E-fermi where the deviations of Wannier bands are
+    /// reported, in eV, usually the top of the frozen window
+    wannier_emax: f64,
+
+    #[structopt(long)]
+    /// Compare the two calculations without and with SOC, reporting the SOC splittings
+    soc_compare: bool,
+
+    #[structopt(long)]
+    /// K-points where the SOC splittings are reported, by labels of KPOINTS or indices starting
+    /// from 1, e.g. "K M 25". All the high symmetry points by default
+    soc_kpoints: Vec<String>,
+
+    #[structopt(long, default_value = "1.0")]
+    /// Only the states without SOC within this window around E-fermi are reported, in eV
+    soc_window: f64,
+
+    #[structopt(long, possible_values = &["txt", "csv"])]
+    /// Also save the k-path, all band energies and fat-band weights into one file with labelled
+    /// columns, in plain text or CSV format, for replotting in gnuplot or Origin
+    wide: Option<String>,
+
+    #[structopt(long)]
+    /// Also save the band structures as JSON of pymatgen's `BandStructureSymmLine`, POSCAR is
+    /// required for the reciprocal lattice
+    pymatgen: bool,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the band structure plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long)]
+    /// Also save the plot as a static image for publications, e.g. "band.svg" or "band.png",
+    /// the format is told by the extension
+    save_image: Option<PathBuf>,
+
+    #[structopt(long, default_value = "100")]
+    /// Resolution of the static image in dots per inch, the image is 6.4 x 4.8 inches
+    dpi: f64,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl Band {
+    fn _config_from_cli(&self, global: &GlobalOpts) -> io::Result<BandConfig> {
+        let n = self.procar.len();
+        let check = |name: &str, len: usize| {
+            if len == 0 || len == n { Ok(()) } else {
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Got {} {}, but {} PROCAR files", len, name, n)))
+            }
+        };
+        check("--outcar", self.outcar.len())?;
+        check("--labels", self.labels.len())?;
+        check("--efermi", self.efermi.len())?;
+        check("--wavecar", self.wavecar.len())?;
+
+        let bands = self.procar.iter()
+            .enumerate()
+            .map(|(i, procar)| {
+                let label = self.labels.get(i).cloned().unwrap_or_else(|| {
+                    procar.parent()
+                        .filter(|d| !d.as_os_str().is_empty())
+                        .map(|d| d.display().to_string())
+                        .unwrap_or_else(|| procar.display().to_string())
+                });
+                let single = n == 1;
+                BandEntry {
+                    label,
+                    procar: global.resolve(procar),
+                    outcar: self.outcar.get(i).map(|p| global.resolve(p)).or_else(|| if single { Some(global.input_path()) } else { None }),
+                    poscar: if single { Some(global.resolve(&self.poscar)) } else { None },
+                    kpoints: if single { Some(global.resolve(&self.kpoints)) } else { None },
+                    wavecar: self.wavecar.get(i).map(|p| global.resolve(p)),
+                    efermi: self.efermi.get(i).cloned(),
+                    scissor: self.scissor,
+                }
+            })
+            .collect();
+
+        Ok(BandConfig {
+            trace: !self.no_trace,
+            ewidth: self.ewidth,
+            select: self.select.as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|x| x.to_string())
+                .collect(),
+            bands,
+        })
+    }
+
+    fn _load_bands(&self, entry: &BandEntry, config: &BandConfig, global: &GlobalOpts) -> io::Result<Vec<(String, BandStructure)>> {
+        info!("Parsing PROCAR file {:?} ...", &entry.procar);
+        let mut procar = Procar::from_file(&entry.procar)?;
+
+        let izero = procar.zero_weight_kpoints();
+        let iscf = (0 .. procar.nkpts).filter(|ik| !izero.contains(ik)).collect::<Vec<_>>();
+        let efermi = match entry.efermi {
+            Some(e) => e,
+            None => {
+                let outcar = entry.outcar();
+                info!("Parsing input file {:?} ...", &outcar);
+                match Outcar::from_file(&outcar) {
+                    Ok(outcar) => outcar.efermi,
+                    Err(e) if !izero.is_empty() => {
+                        let efermi = procar.highest_occupied(&iscf)
+                            .expect("No occupied states found in the SCF k-points of PROCAR");
+                        warn!("Cannot read {:?}: {}, E-fermi is set to the highest occupied state {:.4} eV of SCF k-points.",
+                              &outcar, e, efermi);
+                        efermi
+                    },
+                    Err(e) => return Err(e),
+                }
+            },
+        };
+
+        if !izero.is_empty() {
+            info!("Found {} SCF k-points with nonzero weight, only the {} zero-weight k-points are plotted.",
+                  iscf.len(), izero.len());
+            procar = procar.select_kpoints(&izero);
+        }
+
+        let gap = procar.band_gap(efermi);
+        if entry.scissor != 0.0 {
+            procar.apply_scissor(efermi, entry.scissor);
+            info!("Scissor operator of {} eV applied to {:?}, band gap {:.4} eV -> {:.4} eV", entry.scissor,
+                  &entry.label, gap.unwrap_or(0.0), procar.band_gap(efermi).unwrap_or(0.0));
+        } else if let Some(gap) = gap {
+            info!("Band gap of {:?} along the path: {:.4} eV", &entry.label, gap);
+        }
+
+        let poscar = entry.poscar();
+        let structure = match Poscar::from_path(&poscar) {
+            Ok(poscar) => Some(Structure::from(poscar)),
+            Err(_) => {
+                warn!("Cannot read {:?}, k-path distances are in fractional coordinates.", &poscar);
+                None
+            },
+        };
+        let recip = structure.as_ref().map(|s| _reciprocal(&s.cell));
+
+        let kpath = if self.kpoint_labels.is_empty() {
+            KpathSegments::from_file(&entry.kpoints())
+        } else {
+            let labels = self.kpoint_labels.iter()
+                .flat_map(|l| l.split_whitespace().map(|x| x.to_string()))
+                .collect::<Vec<_>>();
+            let kpath = KpathSegments::from_labels(&labels, procar.nkpts);
+            if kpath.is_none() {
+                warn!("Cannot divide {} k-points into {} segments evenly, labels are ignored.",
+                      procar.nkpts, labels.len().saturating_sub(1));
+            }
+            kpath
+        };
+
+        let mut bands = BandStructure::from_procar(&procar, efermi, recip.as_ref());
+        if let Some(kpath) = kpath.as_ref() {
+            bands = bands.with_kpath(kpath);
+        }
+        if config.trace {
+            let traced = entry.wavecar.as_ref().and_then(|path| {
+                info!("Reading WAVECAR file {:?} ...", path);
+                Wavecar::from_file(path)
+                    .and_then(|wav| bands.clone().trace_with_wavecar(&procar, &wav, config.ewidth))
+                    .map_err(|e| warn!("Cannot trace the bands by {:?}: {}, the projections are used instead.", path, e))
+                    .ok()
+            });
+            bands = match traced {
+                Some(traced) => traced,
+                None => bands.trace(&procar, config.ewidth),
+            };
+        }
+        _report_spin_gaps(&entry.label, &bands);
+        if !config.select.is_empty() {
+            let selections = config.select.iter()
+                .map(|s| {
+                    let raw = RawSelection::from_shorthand(s);
+                    match structure.as_ref() {
+                        Some(structure) => Ok(raw.parse_in(structure, &procar.orbitals)),
+                        None if raw.needs_structure() => Err(io::Error::new(io::ErrorKind::NotFound,
+                            format!("Selection '{}' requires the structure, but {:?} cannot be read", s, &poscar))),
+                        None => Ok(raw.parse(procar.nions, &procar.orbitals)),
+                    }
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            bands = bands.with_projections(&procar, &selections);
+        }
+
+        let mut ret = vec![];
+        if !self.wannier_hr.is_empty() {
+            let wannier = self._load_wannier(global, &procar.kpoints, efermi, recip.as_ref(), kpath.as_ref())?;
+            if let Some((mean, max)) = wannier.deviation_from(&bands, self.wannier_emax) {
+                info!("Deviations of Wannier bands below E-fermi + {} eV: mean {:.4} eV, max {:.4} eV",
+                      self.wannier_emax, mean, max);
+            }
+            ret.push((entry.label.clone(), bands));
+            ret.push(("Wannier".to_string(), wannier));
+        } else {
+            ret.push((entry.label.clone(), bands));
+        }
+        Ok(ret)
+    }
+
+    fn _load_wannier(&self, global: &GlobalOpts, kpoints: &[[f64; 3]], efermi: f64,
+                     recip: Option<&Mat33<f64>>, kpath: Option<&KpathSegments>) -> io::Result<BandStructure> {
+        if self.wannier_hr.len() > 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Got {} --wannier-hr, at most 2 for the spin channels", self.wannier_hr.len())));
+        }
+
+        let win = match self.wannier_win.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Reading k-path from {:?} ...", &path);
+                Some(WinKpath::from_file(&path).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("No kpoint_path block found in {:?}", &path)))?)
+            },
+            None => None,
+        };
+        let kpoints = win.as_ref().map_or_else(|| kpoints.to_vec(), |w| w.kpoints());
+
+        let eigvals = self.wannier_hr.iter()
+            .map(|path| {
+                let path = global.resolve(path);
+                info!("Parsing Wannier Hamiltonian {:?} ...", &path);
+                let ham = WannierHamiltonian::from_file(&path)?;
+                info!("Interpolating {} Wannier bands at {} k-points ...", ham.num_wann, kpoints.len());
+                Ok(ham.interpolate(&kpoints))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if eigvals.iter().any(|e| e.first().map(|x| x.len()) != eigvals[0].first().map(|x| x.len())) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Wannier Hamiltonians of the two spins differ in size"));
+        }
+
+        let bands = BandStructure::from_eigvals(&kpoints, &eigvals, efermi, recip);
+        Ok(match (win.as_ref(), kpath) {
+            (Some(win), _) => bands.with_kpath(&win.to_segments()),
+            (None, Some(kpath)) => bands.with_kpath(kpath),
+            (None, None) => bands,
+        })
+    }
+
+    fn _compare_soc(&self, nonsoc: &BandStructure, soc: &BandStructure, global: &GlobalOpts) -> io::Result<()> {
+        if nonsoc.kdist.len() != soc.kdist.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Calculations without and with SOC have {} and {} k-points", nonsoc.kdist.len(), soc.kdist.len())));
+        }
+        let nstates = |bs: &BandStructure| bs.eigvals.iter().map(|s| s.len()).sum::<usize>();
+        let nonsoc_states = if nonsoc.eigvals.len() == 1 { 2 * nstates(nonsoc) } else { nstates(nonsoc) };
+        if soc.eigvals.len() != 1 || nstates(soc) != nonsoc_states {
+            warn!("The calculation with SOC has {} states per k-point while {} expected, is the order of calculations right?",
+                  nstates(soc), nonsoc_states);
+        }
+
+        // High symmetry points of the discontinuities like "X|M" are the two ends of the jump
+        let ticks = nonsoc.ticks.iter()
+            .flat_map(|(ik, l)| l.split('|').enumerate().map(move |(i, l)| (ik + i, l.to_string())).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let kpoints = if self.soc_kpoints.is_empty() {
+            let mut ret = Vec::<(usize, String)>::new();
+            for (ik, l) in ticks {
+                if !ret.iter().any(|(_, x)| *x == l) {
+                    ret.push((ik, l));
+                }
+            }
+            ret
+        } else {
+            self.soc_kpoints.iter()
+                .flat_map(|k| k.split_whitespace().map(|x| x.to_string()).collect::<Vec<_>>())
+                .map(|k| match k.parse::<usize>() {
+                    Ok(i) if i >= 1 => Ok((i - 1, ticks.iter().find(|(ik, _)| *ik == i - 1).map_or_else(|| "-".to_string(), |t| t.1.clone()))),
+                    _ => ticks.iter()
+                        .find(|(_, l)| l.eq_ignore_ascii_case(&k))
+                        .cloned()
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                            format!("K-point '{}' is neither an index nor a label of the k-path", k))),
+                })
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        if kpoints.is_empty() {
+            warn!("No high symmetry points labelled, specify the k-points by `--soc-kpoints`.");
+        }
+
+        let cmp = SocComparison::new(nonsoc, soc, &kpoints, self.soc_window);
+        print_formatted(&cmp, global.output_format)
+    }
+}
+
+// Band gaps of each spin channel and the spin-flip gaps, ISPIN=2 only
+fn _report_spin_gaps(label: &str, bands: &BandStructure) {
+    if bands.eigvals.len() != 2 { return; }
+    let edges = bands.band_edges();
+    let majority = edges.majority();
+    for ispin in 0 .. 2 {
+        let spin = if ispin == 0 { "up" } else { "down" };
+        let role = if ispin == majority { "majority" } else { "minority" };
+        match (edges.vbm[ispin], edges.cbm[ispin]) {
+            (Some(vbm), Some(cbm)) => info!("Spin {} ({}) of {:?}: gap {:.4} eV, VBM {:.4} eV at k-point {}, CBM {:.4} eV at k-point {}",
+                                            spin, role, label, cbm.energy - vbm.energy, vbm.energy, vbm.ik + 1, cbm.energy, cbm.ik + 1),
+            _ => info!("Spin {} ({}) of {:?}: all the states are at one side of E-fermi", spin, role, label),
+        }
+    }
+    if let Some([updn, dnup]) = edges.spin_flip_gaps() {
+        info!("Spin-flip gaps of {:?}: CBM(down) - VBM(up) {:.4} eV, CBM(up) - VBM(down) {:.4} eV", label, updn, dnup);
+    }
+}
+
+impl OptProcess for Band {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.gen_template {
+            let ctx = TemplateContext::inspect(&global.resolve(&self.procar[0]), &global.resolve(&self.poscar));
+            let path = global.resolve(self.config.as_deref().unwrap_or_else(|| Path::new("./band.toml")));
+            return save_template::<BandConfig>(&path, &ctx);
+        }
+
+        let config = match self.config.as_ref() {
+            Some(config) => {
+                let config = global.resolve(config);
+                info!("Reading config file {:?} ...", &config);
+                BandConfig::from_file(&config)?
+            },
+            None => self._config_from_cli(global)?,
+        };
+        if config.bands.is_empty() {
+            warn!("No band structures to plot!");
+            return Ok(());
+        }
+
+        if !self.wannier_hr.is_empty() && config.bands.len() > 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "Wannier bands can only be overlaid on a single calculation"));
+        }
+
+        if self.soc_compare && config.bands.len() != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("SOC comparison requires 2 calculations, without and with SOC, but got {}", config.bands.len())));
+        }
+
+        let mut bands = vec![];
+        for entry in config.bands.iter() {
+            bands.extend(self._load_bands(entry, &config, global)?);
+        }
+
+        if self.soc_compare {
+            self._compare_soc(&bands[0].1, &bands[1].1, global)?;
+        }
+
+        let wide = |bs: &BandStructure, prefix: &str| -> io::Result<()> {
+            match self.wide.as_deref() {
+                Some(ext) => bs.save_as_wide(&self.save_in, &format!("{}_wide.{}", prefix, ext), ext == "csv"),
+                None => Ok(()),
+            }
+        };
+
+        // Wannier bands come from the only calculation
+        let pymatgen = |i: usize, bs: &BandStructure, name: &str| -> io::Result<()> {
+            if !self.pymatgen { return Ok(()); }
+            let poscar = config.bands[i.min(config.bands.len() - 1)].poscar();
+            let structure = Structure::from(Poscar::from_path(&poscar).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                format!("Cannot read {:?} for the reciprocal lattice of pymatgen: {}", &poscar, e)))?);
+            bs.save_as_pymatgen(&self.save_in, name, &structure)
+        };
+
+        if bands.len() == 1 {
+            let bs = &bands[0].1;
+            bs.save_as_txt(&self.save_in)?;
+            pymatgen(0, bs, "band_pymatgen.json")?;
+            bs.save_projections_as_txt(&self.save_in, "fatband")?;
+            wide(bs, "band")?;
+            if !self.no_save_html {
+                bs.save_as_html(&self.save_in)?;
+            }
+            if let Some(image) = self.save_image.as_ref() {
+                bs.save_as_image(image, self.dpi)?;
+            }
+        } else {
+            for (i, (label, bs)) in bands.iter().enumerate() {
+                let prefix = format!("band_{}_{}", i + 1, label.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+                bs.save_as_named_txt(&self.save_in, &format!("{}.txt", prefix))?;
+                pymatgen(i, bs, &format!("{}_pymatgen.json", prefix))?;
+                bs.save_projections_as_txt(&self.save_in, &format!("{}_fatband", prefix))?;
+                wide(bs, &prefix)?;
+            }
+            if !self.no_save_html {
+                save_comparison_html(&bands, &self.save_in)?;
+            }
+            if let Some(image) = self.save_image.as_ref() {
+                save_comparison_image(&bands, image, self.dpi)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::chgcar::ChargeDensity;
+use crate::summary::{
+    Summary,
+    BandGap,
+};
+use crate::bandalign::{
+    AlignedEdges,
+    BandAlignment,
+};
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Aligns the band edges of several slab calculations to their vacuum levels
+///
+/// The VBM and CBM are read from OUTCAR, and the vacuum level is the maximum of the planar
+/// averaged LOCPOT in each directory. The ionization potentials, electron affinities and the
+/// band offsets of every pair are listed, and the offsets are classified as type I
+/// (straddling), II (staggered) or III (broken gap).
+pub struct Bandalign {
+    #[structopt(required = true)]
+    /// Directories of the slab calculations, glob patterns like "slab_*" are expanded
+    dirs: Vec<String>,
+
+    #[structopt(long)]
+    /// Labels of each calculation, the directory names are used if not given
+    labels: Vec<String>,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(long, default_value = "LOCPOT")]
+    /// Name of the LOCPOT file in each directory
+    locpot: String,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the vacuum layer
+    axis: String,
+
+    #[structopt(long)]
+    /// Saves the band alignment diagram as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Bandalign {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let dirs = batch::expand_dirs(&global.resolve_patterns(&self.dirs));
+        if dirs.is_empty() {
+            warn!("No directories found!");
+            return Ok(());
+        }
+        if !self.labels.is_empty() && self.labels.len() != dirs.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Got {} labels, but {} directories", self.labels.len(), dirs.len())));
+        }
+        let axis = match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            _   => 2,
+        };
+
+        info!("Parsing OUTCARs and LOCPOTs of {} calculations ...", dirs.len());
+        let edges = dirs.par_iter()
+            .enumerate()
+            .map(|(i, dir)| -> io::Result<AlignedEdges> {
+                let label = self.labels.get(i).cloned().unwrap_or_else(|| {
+                    dir.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_else(|| dir.display().to_string())
+                });
+
+                let outcar_path = dir.join(&self.outcar);
+                let outcar = Outcar::from_file(&outcar_path)?;
+                if outcar.eigvals.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Band energies not found in {:?}", &outcar_path)));
+                }
+                let gap = BandGap::from_outcar(&outcar, dir);
+
+                let vacuum = ChargeDensity::from_file(&dir.join(&self.locpot))?
+                    .planar_average(0, axis)
+                    .into_iter()
+                    .fold(f64::NEG_INFINITY, f64::max);
+                info!("Vacuum level of {:?}: {:.4} eV", &label, vacuum);
+                Ok(AlignedEdges::new(&label, vacuum, gap.vbm, gap.cbm))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let alignment = BandAlignment::new(edges);
+        if let Some(path) = self.html.as_ref() {
+            alignment.save_as_html(path)?;
+        }
+        print_formatted(&alignment, global.output_format)
+    }
+}
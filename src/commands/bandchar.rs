@@ -0,0 +1,93 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::bandchar::BandCharacterTable;
+use crate::format::Structure;
+use super::{
+    GlobalOpts,
+    _index_transform_helper,
+};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Prints the dominant atomic and orbital contributions of bands at selected k-points
+pub struct Bandchar {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(short = "k", long)]
+    /// Selects the k-points to operate.
+    ///
+    /// K-point indices start from '1', if '0' is given, all the k-points will be selected.
+    /// K-point indices can be negative, where negative index means counting reversely.
+    ikpoints: Vec<i32>,
+
+    #[structopt(long, default_value = "-2.0")]
+    /// Lower bound of the energy window relative to E-fermi, in eV
+    emin: f64,
+
+    #[structopt(long, default_value = "2.0")]
+    /// Upper bound of the energy window relative to E-fermi, in eV
+    emax: f64,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(short = "n", long, default_value = "3")]
+    /// Number of the dominant contributions listed for each band
+    ntop: usize,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, used to label the ions with element symbols
+    poscar: PathBuf,
+}
+
+impl OptProcess for Bandchar {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let procar_path = global.resolve(&self.procar);
+        let poscar_path = global.resolve(&self.poscar);
+        if self.ikpoints.is_empty() {
+            warn!("No k-points are selected to operate!");
+            return Ok(());
+        }
+
+        let efermi = match self.efermi {
+            Some(e) => e,
+            None => global.load_outcar()?.efermi,
+        };
+
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+
+        let labels = if let Ok(poscar) = Poscar::from_path(&poscar_path) {
+            Structure::from(poscar).symbols()
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| format!("{}{}", s, i+1))
+                .collect()
+        } else {
+            (1 ..= procar.nions).map(|i| format!("#{}", i)).collect()
+        };
+
+        let inds = _index_transform_helper(self.ikpoints.clone(), procar.nkpts);
+        let table = BandCharacterTable::from_procar(&procar, &inds, self.emin, self.emax, efermi, labels)
+            .ntop(self.ntop);
+        print_formatted(&table, global.output_format)
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    OutputFormat,
+    print_formatted,
+    save_formatted,
+};
+use crate::summary::{
+    Summary,
+    RlxSummary,
+    BandGap,
+    MagSummary,
+};
+use crate::batch::{
+    self,
+    BatchTable,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Runs one analysis in many calculation directories in parallel and collects the results
+///
+/// The results of all the directories are gathered into one table printed in the format given
+/// by `--output-format`, directories failed to be processed are kept in the table with error
+/// messages.
+pub struct Batch {
+    #[structopt(required = true)]
+    /// Directories to process, glob patterns like "calc_*" are expanded
+    dirs: Vec<String>,
+
+    #[structopt(short, long, default_value = "rlx", possible_values = &["rlx", "gap", "mag"])]
+    /// Analysis to run: relaxation summary, band gap or magnetization
+    analysis: String,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(short, long)]
+    /// Saves the table to this file instead of printing it, in JSON if `--output-format json`
+    /// is given, otherwise in CSV
+    output: Option<PathBuf>,
+}
+
+
+fn _batch_helper<T: Summary + Send>(dirs: &[PathBuf], outcar: &str, format: OutputFormat, output: Option<&Path>) -> io::Result<()> {
+    let records = batch::run_batch::<T>(dirs, outcar);
+    let nfailed = records.iter().filter(|r| r.error.is_some()).count();
+    if nfailed > 0 {
+        warn!("{} of {} directories failed to be processed.", nfailed, records.len());
+    }
+    let table = BatchTable(records);
+    match output {
+        Some(path) => save_formatted(&table, format, path),
+        None       => print_formatted(&table, format),
+    }
+}
+
+
+impl OptProcess for Batch {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let dirs = batch::expand_dirs(&global.resolve_patterns(&self.dirs));
+        if dirs.is_empty() {
+            warn!("No directories are selected to operate!");
+            return Ok(());
+        }
+        info!("Processing {} directories ...", dirs.len());
+
+        let format = global.output_format;
+        let output = self.output.as_deref();
+        match self.analysis.as_str() {
+            "rlx" => _batch_helper::<RlxSummary>(&dirs, &self.outcar, format, output),
+            "gap" => _batch_helper::<BandGap>(&dirs, &self.outcar, format, output),
+            _     => _batch_helper::<MagSummary>(&dirs, &self.outcar, format, output),
+        }
+    }
+}
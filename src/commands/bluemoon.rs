@@ -0,0 +1,85 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::report::{
+    Report,
+    Iconst,
+    BlueMoon,
+    save_report_as_html,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Analyzes REPORT of constrained MD and metadynamics, estimating the free energy gradients
+///
+/// The gradients of the constrained coordinates (STATUS = 0 in ICONST) are the blue moon
+/// ensemble averages <|Z|^(-1/2) (lambda + G kT)> / <|Z|^(-1/2)> of the "b_m>" lines, thus
+/// the free energy profile is obtained by integrating them over a series of runs at different
+/// values of the coordinates. The errors are estimated by block averaging.
+pub struct Bluemoon {
+    #[structopt(long, default_value = "./REPORT")]
+    /// Specify the REPORT file name
+    report: PathBuf,
+
+    #[structopt(long, default_value = "./ICONST")]
+    /// Specify the ICONST file name for the labels of the coordinates
+    iconst: PathBuf,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of MD steps skipped for equilibration
+    skip: usize,
+
+    #[structopt(long, default_value = "5")]
+    /// Number of blocks for the error estimation
+    nblocks: usize,
+
+    #[structopt(long)]
+    /// Saves the evolution of the coordinates and running averages of the gradients as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Bluemoon {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let report_path = global.resolve(&self.report);
+        info!("Reading REPORT file {:?} ...", &report_path);
+        let report = Report::from_file(&report_path)?;
+        if report.0.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("No MD step found in {:?}", &report_path)));
+        }
+
+        let iconst_path = global.resolve(&self.iconst);
+        let iconst = match Iconst::from_file(&iconst_path) {
+            Ok(iconst) => iconst,
+            Err(e) => {
+                warn!("Cannot read {:?}: {}, the coordinates are labeled by indices.", &iconst_path, e);
+                Iconst::default()
+            },
+        };
+
+        if self.skip >= report.0.len() {
+            warn!("All the {} MD steps are skipped.", report.0.len());
+        }
+        if let Some(path) = self.html.as_ref() {
+            save_report_as_html(&report, &iconst, path)?;
+        }
+
+        let bm = BlueMoon::new(&report, &iconst, self.skip, self.nblocks);
+        if bm.gradients.is_empty() {
+            warn!("No blue moon data (\"b_m>\" lines) found in REPORT, are there constrained coordinates in ICONST?");
+        }
+        print_formatted(&bm, global.output_format)
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Trajectory;
+use crate::selection::RawSelection;
+use crate::neighbor::{
+    Cutoffs,
+    PairCutoff,
+};
+use crate::bondevents::BondTracking;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Tracks the coordination numbers over the frames of XDATCAR and detects the bond forming and
+/// breaking events
+///
+/// The bonds of the selected atoms to all the atoms are judged by the pair cutoffs like `rsgrad
+/// neigh`. Bond states and coordination numbers changing for less than `--persist` frames are
+/// treated as thermal fluctuations around the cutoffs and filtered out, the remaining changes
+/// are reported as events with the frames, times and atoms involved. This helps to locate the
+/// rare reactive events of long AIMD runs.
+pub struct Bondevents {
+    #[structopt(long, default_value = "./XDATCAR")]
+    /// Specify the XDATCAR file
+    xdatcar: PathBuf,
+
+    #[structopt(short, long, default_value = "")]
+    /// Selected atoms, starting from 1, e.g. "1 3..5 -1" or "O", resolved on the first frame.
+    /// All atoms are selected if empty
+    atoms: String,
+
+    #[structopt(short, long)]
+    /// Bond cutoffs of element pairs in Angstrom, e.g. "O-H=1.3 O-O=0". The other pairs use
+    /// the covalent radii
+    cutoff: Option<String>,
+
+    #[structopt(long, default_value = "1.2")]
+    /// Scale of the sum of covalent radii used as the cutoff
+    scale: f64,
+
+    #[structopt(long, default_value = "1")]
+    /// Time between the frames of XDATCAR in fs, i.e. POTIM * NBLOCK
+    potim: f64,
+
+    #[structopt(long, default_value = "10")]
+    /// Minimum number of frames a change should last to be an event
+    persist: usize,
+
+    #[structopt(long)]
+    /// Saves the coordination numbers of the selected atoms in each frame as text
+    save_cn: Option<PathBuf>,
+}
+
+impl OptProcess for Bondevents {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let pairs = self.cutoff.as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|s| s.parse::<PairCutoff>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let cutoffs = Cutoffs { scale: self.scale, pairs };
+
+        let xdatcar = global.resolve(&self.xdatcar);
+        info!("Reading {:?} ...", &xdatcar);
+        let traj = Trajectory::from_xdatcar(&xdatcar)?;
+        let first = traj.0.first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No frames found in {:?}", &xdatcar)))?;
+        let iatoms = RawSelection::parse_iatoms_in(&self.atoms, first);
+        info!("Tracking {} atoms over {} frames ...", iatoms.len(), traj.0.len());
+
+        let tracking = BondTracking::new(&traj, &iatoms, &cutoffs, self.potim, self.persist)?;
+        if let Some(path) = self.save_cn.as_ref() {
+            info!("Writing coordination numbers to {:?} ...", path);
+            tracking.save_cn(path)?;
+        }
+        print_formatted(&tracking, global.output_format)
+    }
+}
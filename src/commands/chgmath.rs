@@ -0,0 +1,109 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::chgcar::ChargeDensity;
+use crate::chgmath::Expr;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Evaluates an arithmetic expression over volumetric data (CHGCAR, LOCPOT, etc.)
+///
+/// The files are bound to the variables A ~ F, e.g.
+/// `rsgrad chgmath "A - 0.5*(B+C)" -A CHGCAR_AB -B CHGCAR_A -C CHGCAR_B -o CHGCAR_diff`
+/// for the charge density difference. Numbers, `+ - * / ^`, parentheses and the functions
+/// `abs`, `sqrt`, `exp`, `ln`, `min` and `max` are supported.
+///
+/// All the files should share the same grid, resample them by `rsgrad chgresample` first if
+/// not. Each block (e.g. the total and magnetization densities of ISPIN=2) is evaluated
+/// separately, and the structure of the first variable is written into the output file.
+pub struct Chgmath {
+    /// The expression to evaluate, e.g. "A - 0.5*(B+C)"
+    expression: String,
+
+    #[structopt(short = "A")]
+    /// Volumetric data bound to A
+    a: Option<PathBuf>,
+
+    #[structopt(short = "B")]
+    /// Volumetric data bound to B
+    b: Option<PathBuf>,
+
+    #[structopt(short = "C")]
+    /// Volumetric data bound to C
+    c: Option<PathBuf>,
+
+    #[structopt(short = "D")]
+    /// Volumetric data bound to D
+    d: Option<PathBuf>,
+
+    #[structopt(short = "E")]
+    /// Volumetric data bound to E
+    e: Option<PathBuf>,
+
+    #[structopt(short = "F")]
+    /// Volumetric data bound to F
+    f: Option<PathBuf>,
+
+    #[structopt(short = "o", long, default_value = "./CHGCAR_math")]
+    /// Specify the output file name
+    output: PathBuf,
+}
+
+impl OptProcess for Chgmath {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let expr = Expr::parse(&self.expression)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let vars = expr.variables();
+        if vars.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No variables found in the expression"));
+        }
+
+        let files = [&self.a, &self.b, &self.c, &self.d, &self.e, &self.f];
+        let chgs = vars.iter()
+            .map(|v| {
+                let path = files.get((*v as u8 - b'A') as usize)
+                    .and_then(|p| p.as_ref())
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Variable {} is not bound to any file, specify it by -{}", v, v)))?;
+                let path = global.resolve(path);
+                info!("Parsing volumetric data file {:?} for {} ...", &path, v);
+                ChargeDensity::from_file(&path)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let first = &chgs[0];
+        if let Some((v, chg)) = vars.iter().zip(chgs.iter()).find(|(_, c)| c.ngrid != first.ngrid) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Grid of {} {:?} differs from {:?} of {}, resample it by `rsgrad chgresample` first",
+                        v, chg.ngrid, first.ngrid, vars[0])));
+        }
+        let nblocks = chgs.iter().map(|c| c.blocks.len()).min().unwrap();
+        if chgs.iter().any(|c| c.blocks.len() != nblocks) {
+            warn!("The files contain different numbers of blocks, only the first {} block(s) are evaluated.", nblocks);
+        }
+
+        info!("Evaluating \"{}\" over {} grid points ...", &self.expression, first.npoints());
+        let blocks = (0 .. nblocks)
+            .map(|iblock| {
+                let grids = chgs.iter().map(|c| c.blocks[iblock].as_slice()).collect::<Vec<_>>();
+                expr.eval_grids(&vars, &grids)
+            })
+            .collect();
+
+        ChargeDensity {
+            pos: first.pos.clone(),
+            ngrid: first.ngrid,
+            blocks,
+        }.save_as_vasp(&self.output)
+    }
+}
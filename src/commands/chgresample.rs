@@ -0,0 +1,50 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::chgcar::ChargeDensity;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Resamples the volumetric data (CHGCAR, LOCPOT, etc.) onto a new grid by Fourier interpolation
+///
+/// Useful for matching the grids of two calculations before taking their difference. The
+/// augmentation occupancies are not written into the output file.
+pub struct Chgresample {
+    #[structopt(long, default_value = "./CHGCAR")]
+    /// Specify the volumetric data file name, Gaussian cube files (*.cube) are also accepted
+    chgcar: PathBuf,
+
+    #[structopt(long, number_of_values = 3)]
+    /// New grid size NGX NGY NGZ, e.g. "--ngrid 120 120 240"
+    ngrid: Vec<usize>,
+
+    #[structopt(short = "o", long, default_value = "./CHGCAR_resampled")]
+    /// Specify the output file name
+    output: PathBuf,
+}
+
+impl OptProcess for Chgresample {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let chgcar_path = global.resolve(&self.chgcar);
+        if self.ngrid.len() != 3 {
+            warn!("The new grid size is required, e.g. \"--ngrid 120 120 240\"");
+            return Ok(());
+        }
+
+        info!("Parsing volumetric data file {:?} ...", &chgcar_path);
+        let chg = ChargeDensity::from_file(&chgcar_path)?;
+
+        let ngrid = [self.ngrid[0], self.ngrid[1], self.ngrid[2]];
+        info!("Resampling grid {:?} to {:?} ...", chg.ngrid, ngrid);
+        chg.resample(ngrid).save_as_vasp(&self.output)
+    }
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::chgcar::ChargeDensity;
+use crate::chgsphere::SphereCharges;
+use crate::potcar::Potcar;
+use crate::selection::RawSelection;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Integrates the charge and magnetization of CHGCAR within spheres centered on atoms
+///
+/// The radii are the RWIGS in POTCAR by default. Spheres may overlap or leave gaps, use
+/// Bader analysis for a partition of the whole cell.
+pub struct Chgsphere {
+    #[structopt(long, default_value = "./CHGCAR")]
+    /// Specify the CHGCAR file name
+    chgcar: PathBuf,
+
+    #[structopt(short = "a", long, default_value = "")]
+    /// Selected atoms, starting from 1, e.g. "1 3..5 -1". Element symbols and spatial filters
+    /// like "O z>10" or "layer:1" are also supported. All atoms are selected if empty
+    atoms: String,
+
+    #[structopt(short = "r", long)]
+    /// Sphere radii in Angstrom, one value for all species or one value per species.
+    /// POTCAR is not read if given
+    radius: Vec<f64>,
+
+    #[structopt(long, default_value = "./POTCAR")]
+    /// Specify the POTCAR file name, where the Wigner-Seitz radii (RWIGS) are read
+    potcar: PathBuf,
+}
+
+impl OptProcess for Chgsphere {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let chgcar_path = global.resolve(&self.chgcar);
+        let potcar_path = global.resolve(&self.potcar);
+        info!("Parsing volumetric data file {:?} ...", &chgcar_path);
+        let chg = ChargeDensity::from_file(&chgcar_path)?;
+        let ntypes = chg.pos.ion_types.len();
+
+        let radii = match self.radius.len() {
+            0 => {
+                info!("Reading POTCAR file {:?} ...", &potcar_path);
+                let potcar = Potcar::from_file(&potcar_path)?;
+                if potcar.symbols() != chg.pos.ion_types {
+                    warn!("Element symbols of POTCAR {:?} differ from CHGCAR {:?}",
+                          potcar.symbols(), chg.pos.ion_types);
+                }
+                potcar.rwigs()
+            },
+            1 => vec![self.radius[0]; ntypes],
+            n if n == ntypes => self.radius.clone(),
+            n => {
+                warn!("Got {} radii, but {} species found in CHGCAR", n, ntypes);
+                return Ok(());
+            },
+        };
+
+        let iatoms = RawSelection::parse_iatoms_in(&self.atoms, &chg.pos);
+        let charges = SphereCharges::new(&chg, &iatoms, &radii);
+        print_formatted(&charges, global.output_format)
+    }
+}
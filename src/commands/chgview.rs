@@ -0,0 +1,110 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::chgcar::ChargeDensity;
+use crate::summary::_volume;
+use crate::isosurface::{
+    Isosurface,
+    save_isosurface_html,
+};
+use crate::magfield::MagnetizationField;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Renders the isosurfaces of volumetric data (CHGCAR, PARCHG, etc.) as an interactive 3D plot in HTML
+///
+/// The data is divided by the cell volume, thus the isolevels are in e/Å^3 for CHGCAR and
+/// PARCHG. Large grids can be downsampled by `--stride` for a lighter HTML file.
+///
+/// With `--vectors`, the magnetization density of noncollinear CHGCAR (blocks 1 ~ 3) is drawn
+/// as cones instead, averaged over boxes of `--stride`^3 grid points. The vector field can also
+/// be saved for ParaView by `--vtk`.
+pub struct Chgview {
+    #[structopt(long, default_value = "./CHGCAR")]
+    /// Specify the volumetric data file name, Gaussian cube files (*.cube) are also accepted
+    chgcar: PathBuf,
+
+    #[structopt(long, default_value = "0.05")]
+    /// Isolevels in e/Å^3, multiple isosurfaces are drawn if repeated. Use negative values
+    /// for the magnetization or charge difference, e.g. "--isolevel 0.01 --isolevel -0.01"
+    isolevel: Vec<f64>,
+
+    #[structopt(long, default_value = "0")]
+    /// Index of the data block, starting from 0. 0 is the total charge, 1 is the magnetization
+    /// for ISPIN = 2 or the x component for noncollinear calculations
+    block: usize,
+
+    #[structopt(long, default_value = "1")]
+    /// Use every n-th grid point along each axis
+    stride: usize,
+
+    #[structopt(long)]
+    /// Draw the magnetization vectors (mx, my, mz) of noncollinear CHGCAR as cones
+    vectors: bool,
+
+    #[structopt(long, default_value = "0.1")]
+    /// Skip the vectors shorter than this fraction of the longest one in `--vectors` mode
+    cutoff: f64,
+
+    #[structopt(long)]
+    /// Also save the magnetization vector field as a legacy VTK file in `--vectors` mode
+    vtk: Option<PathBuf>,
+
+    #[structopt(short, long, default_value = "./chgview.html")]
+    /// Specify the output HTML file name
+    output: PathBuf,
+}
+
+impl OptProcess for Chgview {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let chgcar_path = global.resolve(&self.chgcar);
+        if self.stride == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Stride should be positive"));
+        }
+
+        info!("Parsing volumetric data file {:?} ...", &chgcar_path);
+        let chg = ChargeDensity::from_file(&chgcar_path)?;
+
+        if self.vectors {
+            let field = MagnetizationField::from_chgcar(&chg, self.stride)?;
+            if let Some(vtk) = self.vtk.as_ref() {
+                field.save_as_vtk(vtk)?;
+            }
+            return field.save_as_html(self.cutoff, &self.output);
+        }
+        if self.vtk.is_some() {
+            warn!("`--vtk` only works with `--vectors`, ignored.");
+        }
+
+        let block = chg.blocks.get(self.block)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Block {} not found, only {} blocks in {:?}", self.block, chg.blocks.len(), &chgcar_path)))?;
+
+        let volume = _volume(chg.cell());
+        let data = block.iter().map(|x| x / volume).collect::<Vec<f64>>();
+        let (min, max) = data.iter().fold((f64::MAX, f64::MIN), |(a, b), x| (a.min(*x), b.max(*x)));
+        info!("Data range of block {}: {:.6} ~ {:.6} e/Å^3", self.block, min, max);
+
+        let surfaces = self.isolevel.iter()
+            .map(|level| {
+                if *level <= min || *level >= max {
+                    warn!("Isolevel {} is out of the data range, no surface found.", level);
+                }
+                let s = Isosurface::from_grid(&data, chg.ngrid, chg.cell(), *level, self.stride);
+                info!("Isolevel {}: {} vertices, {} triangles", level, s.vertices.len(), s.triangles.len());
+                s
+            })
+            .collect::<Vec<_>>();
+
+        save_isosurface_html(&chg, &surfaces, &self.output)
+    }
+}
@@ -0,0 +1,87 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::chgcar::ChargeDensity;
+use crate::vtk::{
+    VolumetricArrays,
+    save_structure_vtk,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Exports volumetric data (CHGCAR, LOCPOT, etc.) and the structure as VTK files for ParaView
+///
+/// All the blocks of the files are written as named arrays into one file, e.g. "total" and
+/// "magnetization" of ISPIN = 2, or "up" and "down" with `--spin-resolved`. Arrays of multiple
+/// files are prefixed by the file names, thus the charge density difference from `rsgrad chgmath`
+/// can be loaded together with the densities. All the files should share the same grid.
+///
+/// The format follows the extension of `--output`: legacy VTK structured grid for "*.vtk", which
+/// works for any cell, or XML image data for "*.vti", which is much lighter but requires
+/// ParaView 5.10 or newer for non-orthogonal cells.
+pub struct Chgvtk {
+    #[structopt(default_value = "./CHGCAR")]
+    /// Volumetric data files, Gaussian cube files (*.cube) are also accepted
+    chgcars: Vec<PathBuf>,
+
+    #[structopt(short = "o", long, default_value = "./CHGCAR.vtk")]
+    /// Specify the output file name, "*.vtk" or "*.vti"
+    output: PathBuf,
+
+    #[structopt(long)]
+    /// Write the spin up and spin down densities instead of the total and magnetization ones of ISPIN = 2
+    spin_resolved: bool,
+
+    #[structopt(long)]
+    /// Divide the values by the cell volume, i.e. e/Å^3 for CHGCAR. Don't use it for LOCPOT
+    per_volume: bool,
+
+    #[structopt(long)]
+    /// Also save the atoms and unit cell as legacy VTK polydata
+    structure: Option<PathBuf>,
+}
+
+impl OptProcess for Chgvtk {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let chgs = self.chgcars.iter()
+            .map(|p| {
+                let path = global.resolve(p);
+                info!("Parsing volumetric data file {:?} ...", &path);
+                ChargeDensity::from_file(&path)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let first = &chgs[0];
+        if let Some((p, chg)) = self.chgcars.iter().zip(chgs.iter()).find(|(_, c)| c.ngrid != first.ngrid) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Grid of {:?} {:?} differs from {:?}, resample it by `rsgrad chgresample` first",
+                        p, chg.ngrid, first.ngrid)));
+        }
+
+        // Prefixed by the file names, or the full paths if the names collide
+        let mut prefixes = self.chgcars.iter()
+            .map(|p| p.file_name().map_or_else(|| p.to_string_lossy(), |f| f.to_string_lossy()).to_string())
+            .collect::<Vec<_>>();
+        if (1 .. prefixes.len()).any(|i| prefixes[.. i].contains(&prefixes[i])) {
+            prefixes = self.chgcars.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        }
+
+        let mut arrays = VolumetricArrays::new(first.pos.clone(), first.ngrid);
+        for (prefix, chg) in prefixes.iter().zip(chgs.iter()) {
+            let prefix = if chgs.len() > 1 { Some(prefix.as_str()) } else { None };
+            arrays.push_density(chg, prefix, self.spin_resolved, self.per_volume);
+        }
+        arrays.save(&self.output)?;
+
+        if let Some(path) = self.structure.as_ref() {
+            save_structure_vtk(&first.pos, path)?;
+        }
+        Ok(())
+    }
+}
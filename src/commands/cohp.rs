@@ -0,0 +1,88 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::cohp::{
+    Cohpcar,
+    CohpSelection,
+};
+use crate::selection::RawSelection;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Plots the projected COHP or COOP from COHPCAR.lobster or COOPCAR.lobster of LOBSTER
+///
+/// Energies are relative to E-fermi. The integrated values up to E-fermi are printed for the
+/// selected interactions, which are chosen by `--pairs` or `--interactions`. All interactions
+/// are selected if neither is given. COOP is recognized if the file name contains "COOP".
+pub struct Cohp {
+    #[structopt(long, default_value = "./COHPCAR.lobster")]
+    /// Specify the COHPCAR.lobster or COOPCAR.lobster file name
+    cohpcar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Selects interactions by atom pairs, e.g. "Fe1-O2 Fe-O", where an element symbol matches
+    /// all the atoms of this element
+    pairs: Option<String>,
+
+    #[structopt(short = "n", long)]
+    /// Selects interactions by the indices in COHPCAR, starting from 1, e.g. "1 3..5 -1"
+    interactions: Option<String>,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the COHP plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Cohp {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let cohpcar_path = global.resolve(&self.cohpcar);
+        info!("Parsing COHPCAR file {:?} ...", &cohpcar_path);
+        let cohpcar = Cohpcar::from_file(&cohpcar_path)?;
+        let nbonds = cohpcar.interactions.len();
+
+        let mut selected = vec![];
+        if let Some(pairs) = self.pairs.as_ref() {
+            let pairs = pairs.split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            selected.extend(cohpcar.select_pairs(&pairs));
+        }
+        if let Some(inters) = self.interactions.as_ref() {
+            selected.extend(RawSelection::parse_iatoms(inters, nbonds));
+        }
+        if self.pairs.is_none() && self.interactions.is_none() {
+            selected = (0 .. nbonds).collect();
+        }
+        selected.sort_unstable();
+        selected.dedup();
+
+        if selected.is_empty() {
+            warn!("No interactions are selected!");
+            return Ok(());
+        }
+
+        let selection = CohpSelection::new(&cohpcar, selected);
+        selection.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            selection.save_as_html(&self.save_in)?;
+        }
+        print_formatted(&selection, global.output_format)
+    }
+}
@@ -0,0 +1,112 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Trajectory;
+use crate::potcar::Potcar;
+use crate::diffusion::{
+    Conductivity as ConductivityReport,
+    parse_charges,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Estimates the ionic conductivity and the Haven ratio from the MSD of molecular dynamics
+///
+/// The trajectory of XDATCAR is unwrapped across the periodic boundaries and its center of
+/// mass drift is removed. The tracer diffusion coefficients of the charged species give the
+/// Nernst-Einstein conductivity, and the MSD of the total ionic displacement sum_i q_i r_i gives
+/// the conductivity with the ion-ion correlations. Their ratio is the Haven ratio. The errors are
+/// the standard errors over consecutive blocks of the trajectory.
+pub struct Conductivity {
+    #[structopt(long, default_value = "./XDATCAR")]
+    /// Specify the XDATCAR file
+    xdatcar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Ionic charges of the mobile species, e.g. "Li:1" or "Li:1 O:-2"
+    charges: String,
+
+    #[structopt(long)]
+    /// Time between the frames of XDATCAR in fs, i.e. POTIM * NBLOCK
+    potim: f64,
+
+    #[structopt(short, long)]
+    /// Temperature of the molecular dynamics in K
+    temperature: f64,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of the equilibration frames skipped
+    skip: usize,
+
+    #[structopt(long, default_value = "5")]
+    /// Number of blocks for the error estimation
+    nblocks: usize,
+
+    #[structopt(long, default_value = "0.1")]
+    /// Start of the linear fit of MSD, in fraction of the maximum lag time
+    fit_start: f64,
+
+    #[structopt(long, default_value = "0.5")]
+    /// End of the linear fit of MSD, in fraction of the maximum lag time
+    fit_end: f64,
+
+    #[structopt(long)]
+    /// Keeps the center of mass drift of the trajectory
+    keep_drift: bool,
+
+    #[structopt(long)]
+    /// Saves the MSD curves as HTML
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Conductivity {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let charges = parse_charges(&self.charges)?;
+        if charges.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No ionic charges specified"));
+        }
+
+        let xdatcar = global.resolve(&self.xdatcar);
+        info!("Reading {:?} ...", &xdatcar);
+        let skip = self.skip;
+        let mut traj = Trajectory::from_xdatcar(&xdatcar)?;
+        if skip >= traj.0.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Cannot skip {} frames, only {} frames found", skip, traj.0.len())));
+        }
+        traj.0.drain(.. skip);
+        info!("{} frames loaded", traj.0.len());
+
+        traj = traj.unwrapped();
+        if !self.keep_drift {
+            let potcar_path = global.resolve(&PathBuf::from("./POTCAR"));
+            let masses = Potcar::from_file(&potcar_path)
+                .and_then(|potcar| potcar.masses_of(&traj.0[0]))
+                .ok();
+            if masses.is_none() {
+                warn!("Cannot read the masses in {:?}, the drift is removed with equal weights.", &potcar_path);
+            }
+            traj = traj.without_com_drift(masses.as_deref());
+        }
+
+        let report = ConductivityReport::new(&traj, &charges, self.potim, self.temperature,
+                                             self.nblocks, (self.fit_start, self.fit_end))?;
+        if let Some(path) = self.html.as_ref() {
+            info!("Writing MSD to {:?} ...", path);
+            report.save_as_html(path)?;
+        }
+        print_formatted(&report, global.output_format)
+    }
+}
@@ -0,0 +1,69 @@
+use std::io;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::convtest::{
+    ConvParam,
+    ConvRecord,
+    ConvTest,
+};
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Checks the convergence of energy and forces with respect to ENCUT or k-point density
+///
+/// Each directory is one calculation of the test, e.g. "encut_300 encut_400 ..." or "kmesh_*".
+/// The calculation with the largest ENCUT or k-points per reciprocal atom (KPPRA, read from
+/// the automatic mesh in KPOINTS) is taken as the reference.
+pub struct Convtest {
+    #[structopt(required = true)]
+    /// Directories to process, glob patterns like "encut_*" are expanded
+    dirs: Vec<String>,
+
+    #[structopt(short, long, default_value = "encut", possible_values = &["encut", "kpoints"])]
+    /// Parameter under test
+    param: ConvParam,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(long, default_value = "1.0")]
+    /// Threshold of energy difference to the reference, in meV/atom
+    ethr: f64,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Threshold of force component difference to the reference, in eV/A
+    fthr: f64,
+}
+
+impl OptProcess for Convtest {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let dirs = batch::expand_dirs(&global.resolve_patterns(&self.dirs));
+        if dirs.len() < 2 {
+            warn!("At least 2 directories are required for the convergence test");
+            return Ok(());
+        }
+        info!("Processing {} directories ...", dirs.len());
+
+        let records = batch::run_batch::<ConvRecord>(&dirs, &self.outcar);
+        let nfailed = records.iter().filter(|r| r.error.is_some()).count();
+        if nfailed > 0 {
+            warn!("{} of {} directories failed to be processed and are skipped.", nfailed, records.len());
+        }
+
+        let conv = ConvTest::new(&records, self.param, self.ethr, self.fthr);
+        print_formatted(&conv, global.output_format)
+    }
+}
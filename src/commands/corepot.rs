@@ -0,0 +1,107 @@
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::chgcar::ChargeDensity;
+use crate::corepot::CorePotentials;
+use crate::selection::RawSelection;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the electrostatic potentials at ion cores, optionally compared with a reference calculation
+///
+/// The shifts of site potentials are aligned by either the averaged difference at bulk-like
+/// sites or the vacuum levels from LOCPOTs. Both calculations should have the same ion order.
+pub struct Corepot {
+    #[structopt(long)]
+    /// Specify the OUTCAR of the reference calculation
+    reference: Option<PathBuf>,
+
+    #[structopt(long, conflicts_with = "locpot")]
+    /// Bulk-like atoms used for the alignment, starting from 1, e.g. "1 3..5 -1"
+    align_atoms: Option<String>,
+
+    #[structopt(long, requires = "ref-locpot")]
+    /// Specify the LOCPOT of the input OUTCAR, the vacuum level is used for the alignment
+    locpot: Option<PathBuf>,
+
+    #[structopt(long, requires = "locpot")]
+    /// Specify the LOCPOT of the reference calculation
+    ref_locpot: Option<PathBuf>,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the vacuum layer
+    axis: String,
+}
+
+impl Corepot {
+    // Maximum of the planar averaged potential
+    fn vacuum_level(&self, path: &Path) -> io::Result<f64> {
+        info!("Parsing LOCPOT file {:?} ...", path);
+        let axis = match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            _ => 2,
+        };
+        let vac = ChargeDensity::from_file(path)?
+            .planar_average(0, axis)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        info!("Vacuum level of {:?}: {:.4} eV", path, vac);
+        Ok(vac)
+    }
+}
+
+impl OptProcess for Corepot {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        if outcar.core_pots.is_empty() {
+            warn!("Electrostatic potentials at ion cores not found in {:?}", &global.input_path());
+            return Ok(());
+        }
+
+        let symbols = outcar.ion_types.iter()
+            .zip(outcar.ions_per_type.iter())
+            .flat_map(|(s, n)| vec![s.clone(); *n as usize])
+            .collect::<Vec<_>>();
+        let mut pots = CorePotentials::new(&symbols, &outcar.core_pots);
+
+        if let Some(path) = self.reference.as_ref() {
+            let path = &global.resolve(path);
+            info!("Parsing reference OUTCAR {:?} ...", path);
+            let reference = Outcar::from_file(path)?;
+            if reference.core_pots.len() != outcar.core_pots.len() {
+                warn!("Inconsistent ion numbers of {:?} and {:?}", &global.input_path(), path);
+                return Ok(());
+            }
+
+            let alignment = if let Some(atoms) = self.align_atoms.as_ref() {
+                let iatoms = RawSelection::parse_iatoms(atoms, symbols.len());
+                CorePotentials::bulk_alignment(&outcar.core_pots, &reference.core_pots, &iatoms)
+            } else if let (Some(locpot), Some(ref_locpot)) = (self.locpot.as_ref(), self.ref_locpot.as_ref()) {
+                self.vacuum_level(&global.resolve(locpot))? - self.vacuum_level(&global.resolve(ref_locpot))?
+            } else {
+                warn!("Neither --align-atoms nor --locpot is given, the potentials are not aligned");
+                0.0
+            };
+            pots = pots.with_reference(&reference.core_pots, alignment);
+        }
+
+        print_formatted(&pots, global.output_format)
+    }
+}
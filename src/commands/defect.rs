@@ -0,0 +1,68 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::defect::{
+    DefectConfig,
+    DefectDiagram,
+};
+use crate::template::{
+    TemplateContext,
+    save_template,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Calculates the formation energies and transition levels of charged defects
+///
+/// Total energies of host and defects are read from OUTCARs, the chemical potentials, charge
+/// states and finite-size corrections are specified in the TOML config file. Potential alignment
+/// is applied if the LOCPOTs of host and defects are given. Makov-Payne or Freysoldt-Neugebauer-
+/// Van de Walle corrections are calculated if the `[correction]` table is present.
+pub struct Defect {
+    #[structopt(short, long, default_value = "./defect.toml")]
+    /// Specify the TOML config file
+    config: PathBuf,
+
+    #[structopt(long)]
+    /// Generate a commented config template at the path of `--config` instead, with the atoms
+    /// of POSCAR in the directory of `--input` filled in
+    gen_template: bool,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the formation energy plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Defect {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let config_path = global.resolve(&self.config);
+        if self.gen_template {
+            let dir = global.input_dir();
+            let ctx = TemplateContext::inspect(&dir.join("PROCAR"), &dir.join("POSCAR"));
+            return save_template::<DefectConfig>(&config_path, &ctx);
+        }
+
+        info!("Reading config file {:?} ...", &config_path);
+        let config = DefectConfig::from_file(&config_path)?;
+        let diagram = DefectDiagram::from_config(&config)?;
+        print_formatted(&diagram, global.output_format)?;
+        diagram.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            diagram.save_as_html(&self.save_in)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::{
+    Outcar,
+    Mat33,
+};
+use crate::summary::{
+    Summary,
+    BandGap,
+};
+use crate::selection::RawSelection;
+use crate::defpot::{
+    DeformationPotential,
+    StrainMode,
+};
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Fits the deformation potentials to the band edges from a series of strained calculations
+///
+/// The VBM and CBM are read from the band energies of each OUTCAR, and the strains are
+/// calculated from the cells of the last ionic step relative to the reference calculation.
+/// The deformation potentials are the slopes of linear fits of band edges to strains, in eV
+/// per unit strain, with the standard errors of the slopes.
+///
+/// The absolute band edges of periodic bulk calculations are not comparable across strains,
+/// align them to the averaged electrostatic potentials at the ion cores of `--align-atoms`
+/// to get the absolute deformation potentials. The gap deformation potential needs no
+/// alignment.
+pub struct Defpot {
+    #[structopt(required = true)]
+    /// Directories containing OUTCAR or OUTCAR files, glob patterns like "strain_*" are expanded
+    paths: Vec<String>,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(long, default_value = "hydrostatic", possible_values = &["hydrostatic", "a", "b", "c"])]
+    /// Strain mode, volumetric strain for hydrostatic or strain of the lattice vector for uniaxial
+    mode: StrainMode,
+
+    #[structopt(long)]
+    /// Directory or OUTCAR of the unstrained calculation, the first of `paths` if not given
+    reference: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Atoms whose core potentials are averaged as the reference energy of band edges, starting
+    /// from 1, e.g. "1 3..5 -1"
+    align_atoms: Option<String>,
+
+    #[structopt(long)]
+    /// Saves the band edges and fitted lines as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Defpot {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let to_outcar = |p: PathBuf| if p.is_dir() { p.join(&self.outcar) } else { p };
+        let paths = batch::expand_paths(&global.resolve_patterns(&self.paths))
+            .into_iter()
+            .map(to_outcar)
+            .collect::<Vec<_>>();
+        if paths.len() < 3 {
+            warn!("At least 3 calculations are required to fit the deformation potentials, got {}", paths.len());
+            return Ok(());
+        }
+
+        info!("Parsing {} OUTCARs ...", paths.len());
+        let data = paths.par_iter()
+            .map(|p| -> io::Result<(Mat33<f64>, f64, f64)> {
+                let outcar = Outcar::from_file(p)?;
+                let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{} in {:?}", msg, p));
+                let it = outcar.ion_iters.last().ok_or_else(|| invalid("No ionic step found"))?;
+                if outcar.eigvals.is_empty() {
+                    return Err(invalid("Band energies not found"));
+                }
+                let gap = BandGap::from_outcar(&outcar, p.parent().unwrap_or(p));
+
+                let align = match self.align_atoms.as_ref() {
+                    Some(atoms) => {
+                        if outcar.core_pots.is_empty() {
+                            return Err(invalid("Electrostatic potentials at ion cores not found"));
+                        }
+                        let iatoms = RawSelection::parse_iatoms(atoms, outcar.core_pots.len());
+                        iatoms.iter().map(|&i| outcar.core_pots[i]).sum::<f64>() / iatoms.len() as f64
+                    },
+                    None => 0.0,
+                };
+                Ok((it.cell, gap.vbm - align, gap.cbm - align))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let cell0 = match self.reference.as_ref() {
+            Some(p) => {
+                let p = to_outcar(global.resolve(p));
+                info!("Parsing reference OUTCAR {:?} ...", &p);
+                Outcar::from_file(&p)?
+                    .ion_iters.last()
+                    .map(|it| it.cell)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No ionic step found in {:?}", p)))?
+            },
+            None => data[0].0,
+        };
+
+        let strains = data.iter().map(|d| self.mode.strain(&d.0, &cell0)).collect::<Vec<f64>>();
+        let vbm = data.iter().map(|d| d.1).collect::<Vec<f64>>();
+        let cbm = data.iter().map(|d| d.2).collect::<Vec<f64>>();
+        if strains.iter().all(|s| (s - strains[0]).abs() < 1E-8) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("All the calculations have the same {} strain", self.mode)));
+        }
+
+        let dp = DeformationPotential::new(self.mode, &strains, &vbm, &cbm);
+        if let Some(path) = self.html.as_ref() {
+            dp.save_as_html(path)?;
+        }
+        print_formatted(&dp, global.output_format)
+    }
+}
@@ -0,0 +1,31 @@
+use std::io;
+use log::warn;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::dielec::DielecReport;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the static dielectric tensor, Born effective charges and piezoelectric tensor
+///
+/// OUTCAR of LEPSILON or LCALCEPS calculation is required, the ionic contributions are available
+/// with IBRION=5..8. Use "--output-format json" to export the full tensors.
+pub struct Dielec {}
+
+impl OptProcess for Dielec {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        if outcar.dielectric.is_none() {
+            warn!("Dielectric properties not found in {:?}, LEPSILON or LCALCEPS is required", &global.input_path());
+            return Ok(());
+        }
+        print_formatted(&DielecReport::from_outcar(&outcar), global.output_format)
+    }
+}
@@ -0,0 +1,431 @@
+use std::io;
+use std::collections::HashMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::procar::Procar;
+use crate::format::Structure;
+use crate::selection::RawSelection;
+use crate::dos::{
+    DosConfig,
+    DosPlotMode,
+    BandMoments,
+    BandMomentsTable,
+    ElectronCount,
+    ElectronCountTable,
+    XpsSpectrum,
+    count_electrons,
+    refine_efermi,
+    adaptive_sigmas,
+};
+use crate::fscorr::_reciprocal;
+use crate::pcoop::{
+    RawPair,
+    Pcoop,
+};
+use crate::template::{
+    TemplateContext,
+    save_template,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Calculates the total and projected density of states from PROCAR
+///
+/// The projections are selected either by a TOML config file with `[[pdos]]` entries
+/// containing `label`, `atoms` and `orbits` keys, or by `--atoms` and `--orbits` directly.
+/// Shorthand selections like "Fe:d" are given by `--select` or the `select` list of the config
+/// file. If the config file is given, `--procar`, `--poscar`, `--atoms`, `--orbits`, `--select`,
+/// `--efermi`, `--emin`, `--emax`, `--nedos`, `--sigma`, `--pairs`, `--scissor`, `--xps` and
+/// `--photon-energy` are ignored and read from the config file instead.
+///
+/// Atom pairs given by `--pairs` or `[[pairs]]` entries (with `label`, `atoms1`, `atoms2` and
+/// optional `orbits1`, `orbits2` keys) are analyzed as approximate crystal orbital overlap
+/// populations, saved alongside the DOS. Bonding and antibonding characters require the phase
+/// factors of LORBIT=12.
+///
+/// The valence-band XPS spectrum is simulated by weighting the projections of `--xps` with the
+/// photoionization cross sections per electron, and cutting off the empty states. It is saved
+/// as xps.txt in binding energies. The cross sections not given, like "Ti:d", are calculated at
+/// `--photon-energy` for the valence subshells (ns, np, (n-1)d and (n-2)f of the period) in the
+/// Hartree-Fock-Slater atoms, the model of the Yeh-Lindau tables, without relativistic effects.
+/// They are less accurate for the heavy elements and the valence subshells than for the core
+/// levels, see `photoion`, thus give the tabulated values when the precision matters.
+///
+/// The electrons below E-fermi counted from PROCAR are checked against NELECT of OUTCAR, and
+/// the DOS integrated on the energy grid against the exact one. Mismatches point to a wrong
+/// E-fermi or a grid too coarse for the smearing width. E-fermi can be refined by electron
+/// counting with `--refine-efermi`.
+///
+/// With `--adaptive`, the smearing width of each state follows the local band gradient, and
+/// `--sigma` becomes its upper bound. The other analyses still use the fixed `--sigma`.
+pub struct Dos {
+    #[structopt(short, long)]
+    /// Specify the TOML config file
+    config: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Generate a commented config template at the path of `--config` (./dos.toml if not
+    /// given), with the orbitals of PROCAR and atoms of POSCAR found listed
+    gen_template: bool,
+
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Selects the atoms to project, starts from 1. Ranges like "1..4", negative indices,
+    /// element symbols and spatial filters on POSCAR are supported, e.g. "1..4 -1 O",
+    /// "O z>10" or "layer:1..2". Empty input selects all the atoms
+    atoms: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the orbitals to project, e.g. "dxy dyz dz2 dxz x2-y2", or the groups "p", "d",
+    /// "t2g", "eg" and "f". Empty input selects all the orbitals
+    orbits: Option<String>,
+
+    #[structopt(short, long)]
+    /// Shorthand selections of atoms and orbitals separated by ':', one projection for each,
+    /// e.g. "Fe:d Fe:t2g O:p 1..4:s"
+    select: Option<String>,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, read if the atoms are selected by element symbols or
+    /// spatial filters
+    poscar: PathBuf,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(long, default_value = "-5.0")]
+    /// Lower bound of the energy window relative to E-fermi, in eV
+    emin: f64,
+
+    #[structopt(long, default_value = "5.0")]
+    /// Upper bound of the energy window relative to E-fermi, in eV
+    emax: f64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Number of grid points in the energy window
+    nedos: usize,
+
+    #[structopt(long, default_value = "0.05")]
+    /// Gaussian smearing width in eV
+    sigma: f64,
+
+    #[structopt(long)]
+    /// Adaptive smearing factor a, the width of each state is a * |dE/dk| * dk from the band
+    /// gradient across the neighbouring k-points, bounded by `--sigma-min` and `--sigma`. Flat
+    /// bands are sharper while dispersive bands stay smooth. Typical values are 0.3 ~ 1
+    adaptive: Option<f64>,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Lower bound of the adaptive smearing width in eV
+    sigma_min: f64,
+
+    #[structopt(long, default_value = "0.0")]
+    /// Scissor operator in eV, rigidly shifts the states above E-fermi to correct the band gap
+    scissor: f64,
+
+    #[structopt(long)]
+    /// Atom pairs for the approximate COOP analysis, e.g. "1:5..6 2:7", atoms at both sides
+    /// of ':' follow the syntax of `--atoms`
+    pairs: Option<String>,
+
+    #[structopt(long)]
+    /// Photoionization cross sections of shorthand selections to simulate the valence-band
+    /// XPS, e.g. "Ti:d=9.4E-5 O:p=6.6E-5" in Mb per electron, or "Ti:d O:p" to calculate them
+    /// at `--photon-energy`
+    xps: Option<String>,
+
+    #[structopt(long)]
+    /// Photon energy of XPS in eV for the cross sections not given by `--xps`, e.g. 1486.6 of
+    /// Al K-alpha
+    photon_energy: Option<f64>,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Tolerance of the electron count below E-fermi against NELECT of OUTCAR
+    nelect_tol: f64,
+
+    #[structopt(long)]
+    /// Refines E-fermi by counting the electrons of PROCAR up to NELECT of OUTCAR, with the
+    /// gaussian smearing of `--sigma`
+    refine_efermi: bool,
+
+    #[structopt(long)]
+    /// Prints the band center, width, skewness, kurtosis and filling of the selected
+    /// projections within the energy window, e.g. the d-band center
+    band_center: bool,
+
+    #[structopt(long, default_value = "mirror", possible_values = &["mirror", "split", "integrated"])]
+    /// How the DOS is plotted: "mirror" draws spin down with negative values, "split" draws
+    /// spin up and down in separate panels, and "integrated" draws the integrated DOS, saves it
+    /// as idos.txt and prints the number of electrons below E-fermi
+    mode: DosPlotMode,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the DOS plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long)]
+    /// Also save the plot as a static image for publications, e.g. "dos.svg" or "dos.png",
+    /// the format is told by the extension
+    save_image: Option<PathBuf>,
+
+    #[structopt(long, default_value = "100")]
+    /// Resolution of the static image in dots per inch, the image is 6.4 x 4.8 inches
+    dpi: f64,
+
+    #[structopt(long)]
+    /// Also save the DOS as JSON of pymatgen's `Dos` into dos_pymatgen.json
+    pymatgen: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl Dos {
+    // Compares the electrons below E-fermi with NELECT, returns the refined E-fermi if asked
+    fn _check_electrons(&self, procar: &Procar, efermi: f64, sigma: f64, nelect: f64) -> f64 {
+        if self.refine_efermi {
+            return match refine_efermi(procar, nelect, sigma) {
+                Some(refined) => {
+                    info!("E-fermi refined by electron counting: {:.4} eV -> {:.4} eV", efermi, refined);
+                    refined
+                },
+                None => {
+                    warn!("Not enough states in PROCAR to hold NELECT={} electrons, E-fermi is not refined.", nelect);
+                    efermi
+                },
+            };
+        }
+
+        let counted = count_electrons(procar, efermi, sigma);
+        if (counted - nelect).abs() > self.nelect_tol {
+            warn!("{:.4} electrons are found below E-fermi={:.4} eV, but NELECT={}. E-fermi may be wrong or the smearing differs from ISMEAR/SIGMA, try `--refine-efermi`.",
+                  counted, efermi, nelect);
+        } else {
+            info!("{:.4} electrons are found below E-fermi, consistent with NELECT={}.", counted, nelect);
+        }
+        efermi
+    }
+}
+
+impl OptProcess for Dos {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.gen_template {
+            let ctx = TemplateContext::inspect(&global.resolve(&self.procar), &global.resolve(&self.poscar));
+            let path = global.resolve(self.config.as_deref().unwrap_or_else(|| Path::new("./dos.toml")));
+            return save_template::<DosConfig>(&path, &ctx);
+        }
+
+        let config = match self.config.as_ref() {
+            Some(config) => {
+                let config = global.resolve(config);
+                info!("Reading config file {:?} ...", &config);
+                DosConfig::from_file(&config)?
+            },
+            None => {
+                let pdos = if self.atoms.is_some() || self.orbits.is_some() {
+                    let atoms = self.atoms.clone().unwrap_or_default();
+                    let orbits = self.orbits.clone().unwrap_or_default();
+                    let label = [atoms.trim(), orbits.trim()].iter()
+                        .filter(|x| !x.is_empty())
+                        .map(|x| x.replace(' ', "_"))
+                        .collect::<Vec<_>>()
+                        .join("-");
+                    vec![RawSelection::new(&label, &atoms, &orbits)]
+                } else { vec![] };
+                DosConfig {
+                    procar: global.resolve(&self.procar),
+                    outcar: global.input_path(),
+                    efermi: self.efermi,
+                    emin: self.emin,
+                    emax: self.emax,
+                    nedos: self.nedos,
+                    sigma: self.sigma,
+                    scissor: self.scissor,
+                    poscar: global.resolve(&self.poscar),
+                    pdos,
+                    select: self.select.as_deref()
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(|x| x.to_string())
+                        .collect(),
+                    pairs: self.pairs.as_deref()
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(RawPair::from_cli)
+                        .collect(),
+                    xps: self.xps.as_deref()
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(|x| x.to_string())
+                        .collect(),
+                    photon_energy: self.photon_energy,
+                }
+            },
+        };
+
+        // OUTCAR is optional if E-fermi is given, NELECT is read from it for validation
+        let outcar = if config.efermi.is_none() || config.outcar.is_file() {
+            info!("Parsing input file {:?} ...", &config.outcar);
+            Some(Outcar::from_file(&config.outcar)?)
+        } else { None };
+        let mut efermi = match config.efermi {
+            Some(e) => e,
+            None => outcar.as_ref().unwrap().efermi,
+        };
+
+        info!("Parsing PROCAR file {:?} ...", &config.procar);
+        let mut procar = Procar::from_file(&config.procar)?;
+        match outcar.as_ref().map(|o| o.nelect) {
+            Some(nelect) => efermi = self._check_electrons(&procar, efermi, config.sigma, nelect),
+            None if self.refine_efermi => warn!("NELECT is not available without OUTCAR, E-fermi is not refined."),
+            None => {},
+        }
+        if config.scissor != 0.0 {
+            let gap = procar.band_gap(efermi).unwrap_or(0.0);
+            procar.apply_scissor(efermi, config.scissor);
+            info!("Scissor operator of {} eV applied, band gap {:.4} eV -> {:.4} eV",
+                  config.scissor, gap, procar.band_gap(efermi).unwrap_or(0.0));
+        }
+        let raw_selections = config.selections();
+        let cross_sections = config.cross_sections()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let structure = if raw_selections.iter().any(|s| s.needs_structure())
+            || config.pairs.iter().any(|p| p.needs_structure())
+            || cross_sections.iter().any(|c| c.value.is_none() || c.selection.needs_structure()) {
+            info!("Reading POSCAR file {:?} to resolve the atom selections ...", &config.poscar);
+            let structure = Structure::from(Poscar::from_path(&config.poscar)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+            if structure.car_pos.len() != procar.nions {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Got {} atoms in {:?}, but {} in PROCAR", structure.car_pos.len(), &config.poscar, procar.nions)));
+            }
+            Some(structure)
+        } else { None };
+
+        let parse_selection = |s: &RawSelection| match structure.as_ref() {
+            Some(structure) => s.parse_in(structure, &procar.orbitals),
+            None => s.parse(procar.nions, &procar.orbitals),
+        };
+        let selections = raw_selections.iter()
+            .map(parse_selection)
+            .collect::<Vec<_>>();
+
+        let dos = match self.adaptive {
+            Some(a) => {
+                let recip = match structure.as_ref() {
+                    Some(s) => Some(_reciprocal(&s.cell)),
+                    None => Poscar::from_path(&config.poscar).ok()
+                        .map(|p| _reciprocal(&Structure::from(p).cell)),
+                };
+                if recip.is_none() {
+                    warn!("Cannot read {:?}, band gradients of adaptive smearing are in fractional coordinates.", &config.poscar);
+                }
+                let sigmas = adaptive_sigmas(&procar, recip.as_ref(), a, self.sigma_min, config.sigma);
+                info!("Adaptive smearing widths range from {:.4} to {:.4} eV.",
+                      sigmas.iter().cloned().fold(f64::INFINITY, f64::min),
+                      sigmas.iter().cloned().fold(f64::NEG_INFINITY, f64::max));
+                crate::dos::Dos::from_procar_with_sigmas(&procar, efermi, config.emin, config.emax,
+                                                         config.nedos, &sigmas, &selections)
+            },
+            None => crate::dos::Dos::from_procar(&procar, efermi, config.emin, config.emax,
+                                                 config.nedos, config.sigma, &selections),
+        };
+        if let Some(error) = dos.grid_integration_error() {
+            if error > self.nelect_tol {
+                warn!("The DOS integrated on the grid differs from the exact one by {:.4} electrons, the grid spacing {:.4} eV is too coarse for sigma={} eV, increase `--nedos` or `--sigma`.",
+                      error, (config.emax - config.emin) / (config.nedos - 1) as f64, config.sigma);
+            }
+        }
+        dos.save_as_txt(&self.save_in)?;
+        if self.pymatgen {
+            dos.save_as_pymatgen(&self.save_in)?;
+        }
+        if !self.no_save_html {
+            dos.save_as_html(&self.save_in, self.mode)?;
+        }
+        if let Some(image) = self.save_image.as_ref() {
+            dos.save_as_image(image, self.dpi, self.mode)?;
+        }
+        if self.mode == DosPlotMode::Integrated {
+            dos.save_integrated_as_txt(&self.save_in)?;
+            let counts = ElectronCount::from_procar(&procar, efermi, config.sigma, &selections);
+            print_formatted(&ElectronCountTable(counts), global.output_format)?;
+        }
+
+        if !cross_sections.is_empty() {
+            let xps_selections = cross_sections.iter()
+                .map(|c| parse_selection(&c.selection))
+                .collect::<Vec<_>>();
+            let symbols = structure.as_ref()
+                .map(|s| s.ion_types.iter()
+                    .zip(s.ions_per_type.iter())
+                    .flat_map(|(t, n)| vec![t.clone(); *n as usize])
+                    .collect::<Vec<_>>())
+                .unwrap_or_default();
+            let mut atoms = HashMap::new();
+            let values = cross_sections.iter()
+                .zip(xps_selections.iter())
+                .map(|(c, sel)| c.resolve(sel, &symbols, &procar.orbitals, config.photon_energy, &mut atoms))
+                .collect::<Result<Vec<f64>, String>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let xps_dos = crate::dos::Dos::from_procar(&procar, efermi, config.emin, config.emax,
+                                                       config.nedos, config.sigma, &xps_selections);
+            let xps = XpsSpectrum::from_dos(&xps_dos, &values, config.sigma);
+            xps.save_as_txt(&self.save_in)?;
+            if !self.no_save_html {
+                xps.save_as_html(&self.save_in)?;
+            }
+        }
+
+        if !config.pairs.is_empty() {
+            let pairs = config.pairs.iter()
+                .map(|p| match structure.as_ref() {
+                    Some(structure) => p.parse_in(structure, &procar.orbitals),
+                    None => p.parse(procar.nions, &procar.orbitals),
+                })
+                .collect::<Vec<_>>();
+            let pcoop = Pcoop::from_procar(&procar, efermi, config.emin, config.emax,
+                                           config.nedos, config.sigma, &pairs);
+            pcoop.save_as_txt(&self.save_in)?;
+            if !self.no_save_html {
+                pcoop.save_as_html(&self.save_in)?;
+            }
+            print_formatted(&pcoop, global.output_format)?;
+        }
+
+        if self.band_center {
+            if selections.is_empty() {
+                warn!("No projections are selected for band center analysis!");
+            } else {
+                let moments = selections.iter()
+                    .flat_map(|sel| BandMoments::from_procar(&procar, efermi, config.emin, config.emax, sel))
+                    .collect::<Vec<_>>();
+                print_formatted(&BandMomentsTable(moments), global.output_format)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use std::io;
+use log::warn;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::elastic::ElasticReport;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the elastic constants and polycrystalline moduli from OUTCAR of IBRION=6 and ISIF>=3
+///
+/// The "TOTAL ELASTIC MODULI" block is used, which includes the contributions from ionic relaxation.
+/// Bulk, shear and Young's moduli are in GPa.
+pub struct Elastic {}
+
+impl OptProcess for Elastic {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        match outcar.elastic.as_ref() {
+            Some(moduli) => print_formatted(&ElasticReport::from_outcar_moduli(moduli), global.output_format),
+            None => {
+                warn!("Elastic moduli not found in {:?}, IBRION=6 and ISIF>=3 are required", &global.input_path());
+                Ok(())
+            },
+        }
+    }
+}
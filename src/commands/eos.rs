@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::eos::{
+    EosKind,
+    EosReport,
+};
+use crate::summary::_volume;
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Fits the equations of state to the energies and volumes from a series of calculations
+///
+/// The energy(sigma->0) and cell volume of the last ionic step of each OUTCAR are used.
+/// Bulk modulus B0 is in GPa.
+pub struct Eos {
+    #[structopt(required = true)]
+    /// Directories containing OUTCAR or OUTCAR files, glob patterns like "vol_*" are expanded
+    paths: Vec<String>,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(long, possible_values = &["bm", "murnaghan", "vinet"])]
+    /// Equations of state to fit, all of them are fitted if not given
+    eos: Vec<EosKind>,
+
+    #[structopt(long)]
+    /// Saves the data points and fitted curves as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Eos {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let paths = batch::expand_paths(&global.resolve_patterns(&self.paths))
+            .into_iter()
+            .map(|p| if p.is_dir() { p.join(&self.outcar) } else { p })
+            .collect::<Vec<_>>();
+        if paths.len() < 4 {
+            warn!("At least 4 calculations are required to fit the equation of state, got {}", paths.len());
+            return Ok(());
+        }
+
+        info!("Parsing {} OUTCARs ...", paths.len());
+        let data = paths.par_iter()
+            .map(|p| -> io::Result<(f64, f64)> {
+                let outcar = Outcar::from_file(p)?;
+                let it = outcar.ion_iters.last()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                                  format!("No ionic step found in {:?}", p)))?;
+                Ok((_volume(&it.cell), it.toten_z))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let (volumes, energies): (Vec<f64>, Vec<f64>) = data.into_iter().unzip();
+
+        let kinds = if self.eos.is_empty() { EosKind::ALL.to_vec() } else { self.eos.clone() };
+        let report = EosReport::new(&volumes, &energies, &kinds);
+        if let Some(path) = self.html.as_ref() {
+            report.save_as_html(path)?;
+        }
+        print_formatted(&report, global.output_format)
+    }
+}
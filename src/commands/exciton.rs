@@ -0,0 +1,123 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::outcar::Outcar;
+use crate::format::Structure;
+use crate::fscorr::_reciprocal;
+use crate::band::BandStructure;
+use crate::exciton::BseFatband;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Maps the excitons of BSE calculations onto the bands as electron and hole fat bands
+///
+/// The eigenvectors are read from BSEFATBAND, and all the excitons are listed with their
+/// dominant transitions. For each exciton selected by `--index`, the squared amplitudes are
+/// summed onto the valence (hole) and conduction (electron) states of PROCAR, which should
+/// contain the k-points of the BSE calculation, e.g. from the preceding DFT or GW step. The
+/// k-points are matched by fractional coordinates and the bands by the closest eigenvalues.
+pub struct Exciton {
+    #[structopt(long, default_value = "./BSEFATBAND")]
+    /// Specify the BSEFATBAND file name
+    bsefatband: PathBuf,
+
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name, where the bands are read
+    procar: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, used to calculate the k-path distances
+    poscar: PathBuf,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(short, long, default_value = "1")]
+    /// Indices of the excitons to plot, starting from 1, e.g. "-i 1 2 3"
+    index: Vec<usize>,
+
+    #[structopt(long)]
+    /// Only list the excitons, don't read PROCAR or save the fat bands
+    list: bool,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the fat-band plots in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Exciton {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let bsefatband = global.resolve(&self.bsefatband);
+        info!("Parsing BSEFATBAND file {:?} ...", &bsefatband);
+        let bse = BseFatband::from_file(&bsefatband)?;
+        print_formatted(&bse, global.output_format)?;
+        if self.list {
+            return Ok(());
+        }
+
+        if let Some(i) = self.index.iter().find(|&&i| i == 0 || i > bse.excitons.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Exciton index {} out of range, {} excitons found", i, bse.excitons.len())));
+        }
+
+        let procar_path = global.resolve(&self.procar);
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+
+        let efermi = match self.efermi {
+            Some(e) => e,
+            None => {
+                let input = global.input_path();
+                info!("Parsing input file {:?} ...", &input);
+                Outcar::from_file(&input)?.efermi
+            },
+        };
+
+        let poscar = global.resolve(&self.poscar);
+        let recip = match Poscar::from_path(&poscar) {
+            Ok(poscar) => Some(_reciprocal(&Structure::from(poscar).cell)),
+            Err(_) => {
+                warn!("Cannot read {:?}, k-path distances are in fractional coordinates.", &poscar);
+                None
+            },
+        };
+
+        for &i in self.index.iter() {
+            let exciton = &bse.excitons[i - 1];
+            let (hole, electron, nunmatched) = exciton.band_weights(&procar);
+            if nunmatched > 0 {
+                warn!("{} of {} transitions of exciton #{} are at k-points not found in PROCAR, ignored.",
+                      nunmatched, exciton.transitions.len(), i);
+            }
+
+            let bands = BandStructure::from_procar(&procar, efermi, recip.as_ref())
+                .with_weights("hole", &hole)
+                .with_weights("electron", &electron);
+            let prefix = format!("exciton_{}", i);
+            bands.save_projections_as_txt(&self.save_in, &prefix)?;
+            if !self.no_save_html {
+                bands.save_as_named_html(&self.save_in, &format!("{}.html", prefix))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::format::Structure;
+use crate::fdphonon::{
+    DisplacementSet,
+    ForceSets,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Collects the forces of the displaced supercells into FORCE_SETS of phonopy
+///
+/// The displacements are read from "phonon_disp.json" written by `rsgrad phonondisp`, and the
+/// forces from OUTCAR in each run directory next to it. The forces of the first ionic step are
+/// taken, and the geometry is checked against the expected displaced supercell. The residual
+/// forces of the pristine supercell can be subtracted by `--residual`.
+pub struct Forcesets {
+    #[structopt(long, default_value = "./phonon/phonon_disp.json")]
+    /// Specify the displacements file written by `rsgrad phonondisp`
+    disp: PathBuf,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// OUTCAR file name in each run directory
+    outcar: String,
+
+    #[structopt(long)]
+    /// OUTCAR of the pristine supercell whose forces are subtracted
+    residual: Option<PathBuf>,
+
+    #[structopt(short, long, default_value = "FORCE_SETS")]
+    /// Output file name
+    output: PathBuf,
+}
+
+// Largest distance between the corresponding atoms of two structures of the same cell
+fn _max_deviation(a: &Structure, b: &Structure) -> f64 {
+    a.frac_pos.iter()
+        .zip(b.frac_pos.iter())
+        .map(|(x, y)| {
+            let d = [0, 1, 2].map(|k| {
+                let d = x[k] - y[k];
+                d - d.round()
+            });
+            (0 .. 3).map(|j| (0 .. 3).map(|k| d[k] * a.cell[k][j]).sum::<f64>().powi(2)).sum::<f64>().sqrt()
+        })
+        .fold(0.0, f64::max)
+}
+
+impl OptProcess for Forcesets {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let disp = global.resolve(&self.disp);
+        info!("Reading displacements in {:?} ...", &disp);
+        let set = DisplacementSet::from_json(&disp)?;
+        let root = disp.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let sposcar = Structure::from(Poscar::from_path(root.join("SPOSCAR"))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+
+        let mut forces = vec![];
+        for (i, d) in set.displacements.iter().enumerate() {
+            let path = root.join(&d.dir).join(&self.outcar);
+            info!("Reading forces in {:?} ...", &path);
+            let outcar = Outcar::from_file(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("Cannot read {:?}: {}", &path, e)))?;
+            let first = outcar.ion_iters.first()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No ionic steps found in {:?}", &path)))?;
+            if outcar.ion_iters.len() > 1 {
+                warn!("{} ionic steps found in {:?}, the forces of the first one are taken, set NSW = 0 or 1.", outcar.ion_iters.len(), &path);
+            }
+            if first.forces.len() == set.natoms {
+                let deviation = _max_deviation(&set.displaced(&sposcar, i), &outcar.get_structure_cloned(1));
+                if deviation > 1E-3 {
+                    warn!("The geometry in {:?} deviates from the displaced supercell by {:.4} A.", &path, deviation);
+                }
+            }
+            forces.push(first.forces.clone());
+        }
+
+        let residual = match self.residual.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Reading residual forces in {:?} ...", &path);
+                let outcar = Outcar::from_file(&path)?;
+                Some(outcar.ion_iters.first()
+                     .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No ionic steps found in {:?}", &path)))?
+                     .forces.clone())
+            },
+            None => None,
+        };
+
+        let sets = ForceSets::new(&set, forces, residual.as_deref())?;
+        info!("Writing {:?} ...", &self.output);
+        sets.save(&self.output)?;
+        print_formatted(&sets, global.output_format)
+    }
+}
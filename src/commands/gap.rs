@@ -0,0 +1,136 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::summary::{
+    Summary,
+    BandGap,
+};
+use crate::procar::Procar;
+use crate::format::Structure;
+use crate::fscorr::_reciprocal;
+use crate::skw::{
+    SkwFit,
+    BandExtremum,
+    InterpolatedGap,
+};
+use crate::symmetry::point_group;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the band gap, VBM and CBM from the band energies of current OUTCAR
+///
+/// With `--interpolate`, the highest valence and lowest conduction bands of the k-points in
+/// PROCAR are interpolated by the Shankland-Koelling-Wood scheme, symmetrized by the point group
+/// of POSCAR. The band edges are searched on a dense grid and refined off the grid, and the
+/// effective masses are calculated from the curvatures there. Irreducible meshes of symmetric
+/// runs are supported, and k-points with zero weights are excluded.
+pub struct Gap {
+    #[structopt(long)]
+    /// Locate the band edges by interpolating the bands of PROCAR off the explicit mesh
+    interpolate: bool,
+
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name for `--interpolate`
+    procar: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name for `--interpolate`, where the lattice and symmetry are read
+    poscar: PathBuf,
+
+    #[structopt(long, default_value = "5")]
+    /// Number of star functions per k-point in the interpolation
+    lpfac: usize,
+
+    #[structopt(long, default_value = "0.05")]
+    /// Spacing of the dense grid to search the band edges, in 1/Angstrom with 2pi
+    dk: f64,
+}
+
+impl Gap {
+    fn _interpolate(&self, global: &GlobalOpts, efermi: f64, mesh_gap: f64) -> io::Result<Option<InterpolatedGap>> {
+        let procar_path = global.resolve(&self.procar);
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let mut procar = Procar::from_file(&procar_path)?;
+        let izero = procar.zero_weight_kpoints();
+        if !izero.is_empty() {
+            let iscf = (0 .. procar.nkpts).filter(|ik| !izero.contains(ik)).collect::<Vec<_>>();
+            procar = procar.select_kpoints(&iscf);
+        }
+
+        let poscar_path = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &poscar_path);
+        let structure = Structure::from(Poscar::from_path(&poscar_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+        let ops = point_group(Some(&structure), 0.01);
+        info!("Found {} symmetry operations with time reversal, fitting {} k-points with {} stars ...",
+              ops.len(), procar.nkpts, procar.nkpts * self.lpfac);
+        let fit = SkwFit::new(&procar.kpoints, &structure.cell, &ops, self.lpfac)?;
+
+        let recip = _reciprocal(&structure.cell);
+        let dims = [0, 1, 2].map(|i| {
+            let b = (recip[i][0].powi(2) + recip[i][1].powi(2) + recip[i][2].powi(2)).sqrt();
+            ((b / self.dk).ceil() as usize).max(1)
+        });
+
+        let mut vbm: Option<BandExtremum> = None;
+        let mut cbm: Option<BandExtremum> = None;
+        for ispin in 0 .. procar.nspin {
+            let nocc = (0 .. procar.nkpts)
+                .map(|ik| (0 .. procar.nbands).filter(|&ib| procar.eigval(ispin, ik, ib) <= efermi).count())
+                .collect::<Vec<_>>();
+            if nocc.iter().any(|n| *n != nocc[0]) {
+                warn!("Spin {} crosses E-fermi, the system is metallic, skipped interpolating.", ispin + 1);
+                return Ok(None);
+            }
+            let nocc = nocc[0];
+            if nocc == 0 || nocc == procar.nbands {
+                warn!("All the states of spin {} are at one side of E-fermi, skipped interpolating.", ispin + 1);
+                return Ok(None);
+            }
+
+            for (ib, maximum) in [(nocc - 1, true), (nocc, false)] {
+                let energies = (0 .. procar.nkpts).map(|ik| procar.eigval(ispin, ik, ib)).collect::<Vec<_>>();
+                let band = fit.fit(&energies);
+                let (kpoint, energy) = band.extremum(maximum, dims, &procar.kpoints);
+                let masses = band.effective_masses(&kpoint, &structure.cell)
+                    .map(|m| if maximum { -m } else { m });
+                let edge = BandExtremum { ispin: ispin + 1, iband: ib + 1, kpoint, energy, masses };
+                if maximum {
+                    if vbm.as_ref().is_none_or(|v| v.energy < energy) { vbm = Some(edge); }
+                } else if cbm.as_ref().is_none_or(|c| c.energy > energy) {
+                    cbm = Some(edge);
+                }
+            }
+        }
+
+        Ok(Some(InterpolatedGap::new(vbm.unwrap(), cbm.unwrap(), mesh_gap)))
+    }
+}
+
+impl OptProcess for Gap {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        let gap = BandGap::from_outcar(&outcar, &global.input_dir());
+        print_formatted(&gap, global.output_format)?;
+
+        if self.interpolate {
+            if let Some(interpolated) = self._interpolate(global, outcar.efermi, gap.gap)? {
+                print_formatted(&interpolated, global.output_format)?;
+            }
+        }
+        Ok(())
+    }
+}
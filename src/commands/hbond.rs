@@ -0,0 +1,100 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Trajectory;
+use crate::hbond::{
+    HBondCriteria,
+    HBondAnalysis,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Counts the hydrogen bonds D-H...A in the frames of XDATCAR by geometric criteria
+///
+/// A hydrogen belongs to its nearest donor within `--dh`, and it forms a hydrogen bond with an
+/// acceptor if the donor-acceptor distance is shorter than `--da` and the angle H-D-A is smaller
+/// than `--angle`. The average counts of each kind of bonds, e.g. "O-H...O", and the lifetimes
+/// from the intermittent and continuous autocorrelation functions are reported. The per-frame
+/// counts are printed with `--output-format csv` or `json`.
+pub struct Hbond {
+    #[structopt(long, default_value = "./XDATCAR")]
+    /// Specify the XDATCAR file
+    xdatcar: PathBuf,
+
+    #[structopt(long, default_value = "1")]
+    /// Time between the frames of XDATCAR in fs, i.e. POTIM * NBLOCK
+    potim: f64,
+
+    #[structopt(long, default_value = "O")]
+    /// Donor species, separated by white spaces, e.g. "O N"
+    donors: String,
+
+    #[structopt(long, default_value = "O")]
+    /// Acceptor species, separated by white spaces, e.g. "O N F"
+    acceptors: String,
+
+    #[structopt(long, default_value = "H")]
+    /// Hydrogen species, e.g. "H D" if deuterium is labelled differently
+    hydrogens: String,
+
+    #[structopt(long, default_value = "1.25")]
+    /// Maximum covalent donor-hydrogen distance, in Angstrom
+    dh: f64,
+
+    #[structopt(long, default_value = "3.5")]
+    /// Maximum donor-acceptor distance, in Angstrom
+    da: f64,
+
+    #[structopt(long, default_value = "30")]
+    /// Maximum angle H-D-A, in degree
+    angle: f64,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of the equilibration frames skipped
+    skip: usize,
+
+    #[structopt(long)]
+    /// Saves the counts and the autocorrelation functions as HTML
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Hbond {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let split = |s: &str| s.split_whitespace().map(|x| x.to_string()).collect::<Vec<_>>();
+        let criteria = HBondCriteria {
+            donors: split(&self.donors),
+            acceptors: split(&self.acceptors),
+            hydrogens: split(&self.hydrogens),
+            dh_cutoff: self.dh,
+            da_cutoff: self.da,
+            angle: self.angle,
+        };
+
+        let xdatcar = global.resolve(&self.xdatcar);
+        info!("Reading {:?} ...", &xdatcar);
+        let skip = self.skip;
+        let mut traj = Trajectory::from_xdatcar(&xdatcar)?;
+        if skip >= traj.0.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Cannot skip {} frames, only {} frames found", skip, traj.0.len())));
+        }
+        traj.0.drain(.. skip);
+        info!("Searching hydrogen bonds in {} frames ...", traj.0.len());
+
+        let analysis = HBondAnalysis::new(&traj, &criteria, self.potim)?;
+        if let Some(path) = self.html.as_ref() {
+            info!("Writing hydrogen bonds to {:?} ...", path);
+            analysis.save_as_html(path)?;
+        }
+        print_formatted(&analysis, global.output_format)
+    }
+}
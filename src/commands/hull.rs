@@ -0,0 +1,55 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::hull::{
+    HullConfig,
+    PhaseDiagram,
+};
+use crate::template::{
+    TemplateContext,
+    save_template,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Constructs the convex hull of formation energies and the chemical potential stability region
+///
+/// Compositions and total energies (or OUTCARs) of the compounds are specified in the TOML config
+/// file. The energies above hull and decomposition products are printed for each compound. If
+/// `target` is given and stable, the vertices of the chemical potential region where it is stable
+/// are printed, with the phases in equilibrium at each vertex.
+pub struct Hull {
+    #[structopt(short, long, default_value = "./hull.toml")]
+    /// Specify the TOML config file
+    config: PathBuf,
+
+    #[structopt(long)]
+    /// Generate a commented config template at the path of `--config` instead, with the atoms
+    /// of POSCAR in the directory of `--input` filled in
+    gen_template: bool,
+}
+
+impl OptProcess for Hull {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let config_path = global.resolve(&self.config);
+        if self.gen_template {
+            let dir = global.input_dir();
+            let ctx = TemplateContext::inspect(&dir.join("PROCAR"), &dir.join("POSCAR"));
+            return save_template::<HullConfig>(&config_path, &ctx);
+        }
+
+        info!("Reading config file {:?} ...", &config_path);
+        let config = HullConfig::from_file(&config_path)?;
+        let diagram = PhaseDiagram::from_config(&config)?;
+        print_formatted(&diagram, global.output_format)
+    }
+}
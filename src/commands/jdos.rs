@@ -0,0 +1,99 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::jdos::Jdos as JointDos;
+use crate::dos::Smearing;
+use crate::wavecar::{
+    Axis,
+    Wavecar,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Calculates the joint density of states from PROCAR for quick optical absorption estimates
+///
+/// Only the vertical transitions from occupied to empty states at the same k-point are counted,
+/// weighted by f_v * (1 - f_c) and the k-point weights. With `--wavecar`, the JDOS weighted by
+/// the squared transition dipole moments is also calculated from the plane-wave coefficients,
+/// which tells apart the bright and dark transitions. The PAW one-center terms are left out,
+/// and WAVECAR should come from the same calculation as PROCAR.
+pub struct Jdos {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(long, default_value = "10.0")]
+    /// Maximum photon energy in eV, the spectra start from zero
+    emax: f64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Number of photon energy grid points
+    nedos: usize,
+
+    #[structopt(long, default_value = "0.05")]
+    /// Smearing width in eV, the standard deviation of Gaussian or the half width of Lorentzian
+    sigma: f64,
+
+    #[structopt(long, default_value = "gaussian", possible_values = &["gaussian", "lorentzian"])]
+    /// Smearing method of the transitions
+    smearing: Smearing,
+
+    #[structopt(long, default_value = "0.0")]
+    /// Scissor shift in eV added to all the transition energies
+    scissor: f64,
+
+    #[structopt(long)]
+    /// Specify the WAVECAR file name to weight the transitions by the transition dipole moments
+    wavecar: Option<PathBuf>,
+
+    #[structopt(long, default_value = "x")]
+    /// Half-grid direction of Gamma-only WAVECAR (x, y or z), which should be the same as the
+    /// one VASP was compiled with, ignored for the other WAVECARs
+    gamma_half: Axis,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the JDOS plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Jdos {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let procar_path = global.resolve(&self.procar);
+        if self.emax <= 0.0 || self.nedos < 2 || self.sigma <= 0.0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "The maximum photon energy and the smearing width should be positive, and NEDOS should be larger than 1"));
+        }
+
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+        let mut jdos = JointDos::from_procar(&procar, self.emax, self.nedos, self.sigma, self.smearing, self.scissor);
+        if let Some(path) = self.wavecar.as_ref() {
+            let path = global.resolve(path);
+            info!("Reading WAVECAR file {:?} ...", &path);
+            let wav = Wavecar::from_file(&path)?.with_gamma_half(self.gamma_half);
+            jdos = jdos.with_tdm(&procar, &wav, self.scissor)?;
+        }
+
+        print_formatted(&jdos, global.output_format)?;
+        jdos.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            jdos.save_as_html(&self.save_in)?;
+        }
+        Ok(())
+    }
+}
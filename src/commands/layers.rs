@@ -0,0 +1,152 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::procar::Procar;
+use crate::chgcar::ChargeDensity;
+use crate::format::Structure;
+use crate::selection::Selection;
+use crate::layers::LayerAnalysis;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Clusters the atoms of 2D heterostructures into layers, with layer-resolved charges and DOS
+///
+/// The atoms are grouped by their heights along the lattice vector `--axis`, a gap larger than
+/// `--tolerance` starts a new layer. With `--chgcar`, the charge of each layer is integrated
+/// over the grid planes closer to it than to the other layers. With `--dos`, the DOS projected
+/// onto the atoms of each layer is calculated from PROCAR and plotted in stacked panels.
+pub struct Layers {
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, the structure of CHGCAR is used instead if given
+    poscar: PathBuf,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector along which the layers are stacked
+    axis: String,
+
+    #[structopt(long, default_value = "0.5")]
+    /// Atoms with heights differing less than this, in Angstrom, are in the same layer
+    tolerance: f64,
+
+    #[structopt(long)]
+    /// Specify the CHGCAR file name to integrate the charge of each layer
+    chgcar: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Calculates the DOS of each layer from PROCAR
+    dos: bool,
+
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(long, default_value = "-5.0")]
+    /// Lower bound of the energy window relative to E-fermi, in eV
+    emin: f64,
+
+    #[structopt(long, default_value = "5.0")]
+    /// Upper bound of the energy window relative to E-fermi, in eV
+    emax: f64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Number of grid points in the energy window
+    nedos: usize,
+
+    #[structopt(long, default_value = "0.05")]
+    /// Gaussian smearing width in eV
+    sigma: f64,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the layer DOS plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Layers {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let axis = match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            _   => 2,
+        };
+
+        let chgcar = match self.chgcar.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Parsing CHGCAR file {:?} ...", &path);
+                Some(ChargeDensity::from_file(&path)?)
+            },
+            None => None,
+        };
+        let structure = match chgcar.as_ref() {
+            Some(chgcar) => chgcar.pos.clone(),
+            None => {
+                let poscar = global.resolve(&self.poscar);
+                info!("Reading POSCAR file {:?} ...", &poscar);
+                Structure::from(Poscar::from_path(&poscar)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?)
+            },
+        };
+
+        let mut layers = LayerAnalysis::new(&structure, axis, self.tolerance);
+        info!("Found {} layers along {}", layers.layers.len(), self.axis);
+        if let Some(chgcar) = chgcar.as_ref() {
+            layers = layers.with_charge(chgcar);
+        }
+
+        if self.dos {
+            let efermi = match self.efermi {
+                Some(e) => e,
+                None => {
+                    let input = global.input_path();
+                    info!("Parsing input file {:?} ...", &input);
+                    Outcar::from_file(&input)?.efermi
+                },
+            };
+            let procar_path = global.resolve(&self.procar);
+            info!("Parsing PROCAR file {:?} ...", &procar_path);
+            let procar = Procar::from_file(&procar_path)?;
+            if procar.nions != structure.car_pos.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Got {} atoms in the structure, but {} in PROCAR", structure.car_pos.len(), procar.nions)));
+            }
+
+            let selections = layers.layers.iter()
+                .enumerate()
+                .map(|(i, l)| Selection {
+                    label: format!("L{}", i + 1),
+                    iatoms: l.iatoms.clone(),
+                    iorbits: (0 .. procar.norbits()).collect(),
+                })
+                .collect::<Vec<_>>();
+            let dos = crate::dos::Dos::from_procar(&procar, efermi, self.emin, self.emax,
+                                                   self.nedos, self.sigma, &selections);
+            layers = layers.with_dos(dos);
+            layers.save_dos_as_txt(&self.save_in)?;
+            if !self.no_save_html {
+                layers.save_dos_as_html(&self.save_in)?;
+            }
+        }
+
+        print_formatted(&layers, global.output_format)
+    }
+}
@@ -0,0 +1,81 @@
+use std::io;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::selection::RawSelection;
+use crate::ldau::{
+    LdauOccupations,
+    LdauStep,
+};
+use super::{
+    GlobalOpts,
+    _index_transform_helper,
+};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Reports the on-site occupation matrices of LDAU runs
+///
+/// The matrices are printed by VASP with LDAUPRINT=2. The traces and the eigenvalues of the
+/// d/f shells at the end of the selected ionic steps are listed for each spin, which helps to
+/// track the oxidation states during relaxations.
+pub struct Ldau {
+    #[structopt(short = "i", long)]
+    /// Selects the ionic steps, starting from '1'. '0' selects all the steps, negative indices
+    /// count reversely. The last step is selected if not given
+    select_steps: Option<Vec<i32>>,
+
+    #[structopt(short, long, default_value = "")]
+    /// Selected atoms, starting from 1, e.g. "1 3..5 -1". All the LDAU atoms are selected if
+    /// empty
+    atoms: String,
+}
+
+impl OptProcess for Ldau {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let input = global.input_path();
+        info!("Parsing occupation matrices in {:?} ...", &input);
+        let occ = LdauOccupations::from_file(&input)?;
+        if occ.0.is_empty() {
+            warn!("No occupation matrices found in {:?}, LDAUPRINT=2 is required", &input);
+            return Ok(());
+        }
+
+        let nions = occ.0.iter()
+            .flat_map(|s| s.matrices.iter().map(|m| m.atom))
+            .max()
+            .unwrap_or(0);
+        let iatoms = RawSelection::parse_iatoms(&self.atoms, nions);
+
+        let select_steps = self.select_steps.clone().unwrap_or_else(|| vec![-1]);
+        let steps = _index_transform_helper(select_steps, occ.0.len())
+            .into_iter()
+            .map(|i| {
+                if i < 1 || i > occ.0.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Step index {} out of bound, {} steps found", i, occ.0.len())));
+                }
+                let s = &occ.0[i - 1];
+                Ok(LdauStep {
+                    step: s.step,
+                    matrices: s.matrices.iter()
+                        .filter(|m| iatoms.contains(&(m.atom - 1)))
+                        .cloned()
+                        .collect(),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        print_formatted(&LdauOccupations(steps), global.output_format)
+    }
+}
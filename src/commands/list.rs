@@ -0,0 +1,27 @@
+use std::io;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::summary::{
+    Summary,
+    BriefInfo,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Lists the brief info of current OUTCAR
+pub struct List {}
+
+impl OptProcess for List {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        let info = BriefInfo::from_outcar(&outcar, &global.input_dir());
+        print_formatted(&info, global.output_format)
+    }
+}
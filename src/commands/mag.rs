@@ -0,0 +1,40 @@
+use std::io;
+use log::warn;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::mag::MagReport;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the magnetic moments of each ion and species at the end of OUTCAR
+pub struct Mag {
+    #[structopt(short = "m", long)]
+    /// Prints only the MAGMOM line for INCAR, which can be used to restart the calculation
+    magmom: bool,
+}
+
+impl OptProcess for Mag {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        let report = match MagReport::from_outcar(&outcar) {
+            Some(report) => report,
+            None => {
+                warn!("Magnetization of ions not found in {:?}, ISPIN=2 or LNONCOLLINEAR=.TRUE. is required", &global.input_path());
+                return Ok(());
+            },
+        };
+        if self.magmom {
+            println!("{}", report.magmom_line());
+            Ok(())
+        } else {
+            print_formatted(&report, global.output_format)
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::mlff::{
+    MlAb,
+    MlLog,
+    MlffSummary,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Summarizes the training set and learning errors of the machine learning force field of VASP 6
+///
+/// The configurations in ML_AB are counted by the system names and elements, together with the
+/// local reference configurations of each element and the root mean square errors of the last
+/// learning step in ML_LOGFILE. The training structures with DFT energies, forces and stress
+/// can be exported as extended XYZ for other machine learning packages.
+pub struct Mlff {
+    #[structopt(long, default_value = "./ML_AB")]
+    /// Specify the ML_AB (or ML_ABN) file name
+    ml_ab: PathBuf,
+
+    #[structopt(long, default_value = "./ML_LOGFILE")]
+    /// Specify the ML_LOGFILE file name
+    logfile: PathBuf,
+
+    #[structopt(long)]
+    /// Exports the training structures to extended XYZ
+    extxyz: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Saves the errors over the learning steps from ML_LOGFILE as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Mlff {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let ab_path = global.resolve(&self.ml_ab);
+        info!("Reading training set {:?} ...", &ab_path);
+        let ab = MlAb::from_file(&ab_path)?;
+
+        let log_path = global.resolve(&self.logfile);
+        let log = if log_path.is_file() {
+            info!("Reading ML_LOGFILE {:?} ...", &log_path);
+            Some(MlLog::from_file(&log_path)?)
+        } else {
+            warn!("{:?} not found, the learning errors are not reported.", &log_path);
+            None
+        };
+
+        if let Some(path) = self.extxyz.as_ref() {
+            ab.save_as_extxyz(path)?;
+        }
+        if let Some(path) = self.html.as_ref() {
+            match log.as_ref() {
+                Some(l) if !l.errors().is_empty() => l.save_errors_as_html(path)?,
+                _ => warn!("No errors found in ML_LOGFILE, {:?} is not saved.", path),
+            }
+        }
+
+        print_formatted(&MlffSummary::new(&ab, log.as_ref()), global.output_format)
+    }
+}
@@ -0,0 +1,279 @@
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::info;
+use structopt::StructOpt;
+use crate::outcar::Outcar;
+use crate::traits::OutputFormat;
+
+pub mod rlx;
+pub mod vib;
+pub mod trj;
+pub mod list;
+pub mod gap;
+pub mod spintex;
+pub mod bandchar;
+pub mod dos;
+pub mod potalign;
+pub mod defect;
+pub mod batch;
+pub mod mag;
+pub mod bader;
+pub mod chgresample;
+pub mod chgsphere;
+pub mod corepot;
+pub mod elastic;
+pub mod dielec;
+pub mod eos;
+pub mod convtest;
+pub mod hull;
+pub mod cohp;
+pub mod band;
+pub mod chgview;
+pub mod uc;
+pub mod potline;
+pub mod jdos;
+pub mod exciton;
+pub mod defpot;
+pub mod bandalign;
+pub mod layers;
+pub mod xas;
+pub mod poscar;
+pub mod neigh;
+pub mod surface;
+pub mod modeproj;
+pub mod ldau;
+pub mod velocity;
+pub mod chgmath;
+pub mod symmetrize;
+pub mod chgvtk;
+pub mod pymatgen;
+pub mod audit;
+pub mod status;
+pub mod bluemoon;
+pub mod pcdat;
+pub mod mlff;
+pub mod siteproj;
+pub mod occmatrix;
+pub mod restart;
+pub mod conductivity;
+pub mod profile;
+pub mod hbond;
+pub mod bondevents;
+pub mod phonondisp;
+pub mod forcesets;
+pub mod wavecar;
+pub mod wav3d;
+pub mod unk;
+pub mod wavdiff;
+
+pub use rlx::Rlx;
+pub use vib::Vib;
+pub use trj::Trj;
+pub use list::List;
+pub use gap::Gap;
+pub use spintex::Spintex;
+pub use bandchar::Bandchar;
+pub use dos::Dos;
+pub use potalign::Potalign;
+pub use defect::Defect;
+pub use batch::Batch;
+pub use mag::Mag;
+pub use bader::Bader;
+pub use chgresample::Chgresample;
+pub use chgsphere::Chgsphere;
+pub use corepot::Corepot;
+pub use elastic::Elastic;
+pub use dielec::Dielec;
+pub use eos::Eos;
+pub use convtest::Convtest;
+pub use hull::Hull;
+pub use cohp::Cohp;
+pub use band::Band;
+pub use chgview::Chgview;
+pub use uc::Uc;
+pub use potline::Potline;
+pub use jdos::Jdos;
+pub use exciton::Exciton;
+pub use defpot::Defpot;
+pub use bandalign::Bandalign;
+pub use layers::Layers;
+pub use xas::Xas;
+pub use poscar::Poscar;
+pub use neigh::Neigh;
+pub use surface::Surface;
+pub use modeproj::Modeproj;
+pub use ldau::Ldau;
+pub use velocity::Velocity;
+pub use chgmath::Chgmath;
+pub use symmetrize::Symmetrize;
+pub use chgvtk::Chgvtk;
+pub use pymatgen::Pymatgen;
+pub use audit::Audit;
+pub use status::Status;
+pub use bluemoon::Bluemoon;
+pub use pcdat::Pcdat;
+pub use mlff::Mlff;
+pub use siteproj::Siteproj;
+pub use occmatrix::Occmatrix;
+pub use restart::Restart;
+pub use conductivity::Conductivity;
+pub use profile::Profile;
+pub use hbond::Hbond;
+pub use bondevents::Bondevents;
+pub use phonondisp::Phonondisp;
+pub use forcesets::Forcesets;
+pub use wavecar::Wavecar;
+pub use wav3d::Wav3d;
+pub use unk::Unk;
+pub use wavdiff::Wavdiff;
+
+
+// Options shared by all the subcommands
+#[derive(Debug, StructOpt)]
+pub struct GlobalOpts {
+    #[structopt(default_value = "./OUTCAR")]
+    /// Specify the input OUTCAR file name, OUTCAR.gz or vasprun.xml is used if the default
+    /// OUTCAR is not found
+    pub input: PathBuf,
+
+    #[structopt(long, global = true)]
+    /// Directory of the calculation, where the input files given by relative paths (including
+    /// the defaults like PROCAR and POSCAR) are looked up. Output files are still saved
+    /// relative to the current directory
+    pub dir: Option<PathBuf>,
+
+    #[structopt(long, global = true, default_value = "table", possible_values = &["table", "json", "csv"])]
+    /// Format of the printed results. Commands which only save files ignore this option
+    pub output_format: OutputFormat,
+
+    #[structopt(long, global = true)]
+    /// Cache the parsed PROCAR and OUTCAR files and the headers of WAVECAR in `.rsgrad-cache`
+    /// next to them, and reuse the caches in later runs if the files are unchanged. Also
+    /// enabled by `RSGRAD_CACHE=1`
+    pub cache: bool,
+
+    #[structopt(long, global = true)]
+    /// Selects one run of the concatenated OUTCAR left by continuation jobs, starting from 1.
+    /// The ionic steps of all the runs are merged if not given
+    pub run: Option<usize>,
+}
+
+impl GlobalOpts {
+    pub fn load_outcar(&self) -> io::Result<Outcar> {
+        let input = self.input_path();
+        info!("Parsing input file {:?} ...", &input);
+        Outcar::from_file_run(&input, self.run)
+    }
+
+    /// Input files given by relative paths are looked up in `--dir`.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match self.dir.as_ref() {
+            Some(dir) if path.is_relative() => dir.join(path.strip_prefix(".").unwrap_or(path)),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Directory arguments of the batch commands are looked up in `--dir`, before the glob
+    /// patterns are expanded.
+    pub fn resolve_patterns(&self, patterns: &[String]) -> Vec<String> {
+        patterns.iter()
+            .map(|p| self.resolve(Path::new(p)).to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// The input OUTCAR, falls back to the gzipped one next to it if not found. The default
+    /// OUTCAR also falls back to vasprun.xml (or vasprun.xml.gz) in the same directory.
+    pub fn input_path(&self) -> PathBuf {
+        let input = self.resolve(&self.input);
+        if input.exists() { return input; }
+
+        let mut gz = input.clone().into_os_string();
+        gz.push(".gz");
+        let mut candidates = vec![PathBuf::from(gz)];
+        if input.file_name().is_some_and(|f| f == "OUTCAR") {
+            candidates.push(input.with_file_name("vasprun.xml"));
+            candidates.push(input.with_file_name("vasprun.xml.gz"));
+        }
+        match candidates.into_iter().find(|p| p.is_file()) {
+            Some(p) => {
+                info!("{:?} not found, {:?} is used instead.", &input, &p);
+                p
+            },
+            None => input,
+        }
+    }
+
+    /// Directory containing the input OUTCAR
+    pub fn input_dir(&self) -> PathBuf {
+        match self.input_path().parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+}
+
+
+/// Transforms the indices from command line to the ones starting from 1. '0' selects all,
+/// negative indices count reversely.
+pub(crate) fn _index_transform_helper(v: Vec<i32>, len: usize) -> Vec<usize> {
+    if v.contains(&0) {
+        (1..=len).collect()
+    } else {
+        v.into_iter()
+         .map(|i| {
+             if i < 0 {
+                 i.rem_euclid(len as i32) as usize + 1
+             } else {
+                 i as usize
+             }
+         })
+         .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_index_transform_helper() {
+        assert_eq!(_index_transform_helper(vec![1, -1, -2], 5), vec![1, 5, 4]);
+        assert_eq!(_index_transform_helper(vec![2, 0], 3), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut global = GlobalOpts::from_iter(["rsgrad"]);
+        assert_eq!(global.resolve(Path::new("./PROCAR")), PathBuf::from("./PROCAR"));
+
+        global.dir = Some(PathBuf::from("calc"));
+        assert_eq!(global.resolve(Path::new("./PROCAR")), PathBuf::from("calc/PROCAR"));
+        assert_eq!(global.resolve(Path::new("../POSCAR")), PathBuf::from("calc/../POSCAR"));
+        assert_eq!(global.resolve(Path::new("/tmp/POSCAR")), PathBuf::from("/tmp/POSCAR"));
+        assert_eq!(global.input_path(), PathBuf::from("calc/OUTCAR"));
+        assert_eq!(global.resolve_patterns(&["vol_*".to_string()]), ["calc/vol_*"]);
+    }
+
+    #[test]
+    fn test_input_path() {
+        let dir = TempDir::new("rsgrad_input_path").unwrap();
+        let mut global = GlobalOpts::from_iter(["rsgrad"]);
+        global.dir = Some(dir.path().to_path_buf());
+        assert_eq!(global.input_path(), dir.path().join("OUTCAR"));
+
+        fs::write(dir.path().join("vasprun.xml"), "").unwrap();
+        assert_eq!(global.input_path(), dir.path().join("vasprun.xml"));
+        fs::write(dir.path().join("OUTCAR.gz"), "").unwrap();
+        assert_eq!(global.input_path(), dir.path().join("OUTCAR.gz"));
+
+        global.input = PathBuf::from("OUTCAR_relax");
+        fs::remove_file(dir.path().join("OUTCAR.gz")).unwrap();
+        assert_eq!(global.input_path(), dir.path().join("OUTCAR_relax"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::format::Trajectory;
+use crate::modeproj::ModeProjection;
+use super::{
+    GlobalOpts,
+    _index_transform_helper,
+};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Projects the displacements of relaxation or MD trajectories onto the vibrational modes
+///
+/// The normal modes and the equilibrium structure are read from the OUTCAR of a phonon
+/// calculation (IBRION=5..8) given by `--phonon`. The trajectory is read from `--xdatcar`, or
+/// the input OUTCAR if not given. The amplitudes of the mass-weighted modes are saved as
+/// mode_amplitudes.txt, and the harmonic energies of the modes as mode_energies.txt, which
+/// include the kinetic energies if the MD time step `--potim` is given.
+pub struct Modeproj {
+    #[structopt(long, required = true)]
+    /// Specify the OUTCAR of the phonon calculation
+    phonon: PathBuf,
+
+    #[structopt(long)]
+    /// Specify the XDATCAR of the trajectory, the input OUTCAR is used if not given
+    xdatcar: Option<PathBuf>,
+
+    #[structopt(short = "i", long)]
+    /// Selects the modes to project, in the order of `rsgrad vib --list` starting from '1'.
+    /// '0' or nothing selects all the modes, negative indices count reversely
+    select_indices: Option<Vec<i32>>,
+
+    #[structopt(long)]
+    /// Time step of MD in fs, the kinetic energies of the modes are estimated if given
+    potim: Option<f64>,
+
+    #[structopt(long)]
+    /// Removes the drift of the center of mass of the trajectory, weighted by the masses
+    remove_drift: bool,
+
+    #[structopt(long)]
+    /// Rigidly aligns the frames onto the phonon structure by translations and rotations,
+    /// weighted by the masses, for molecules and clusters
+    align: bool,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the mode amplitudes plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Modeproj {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let phonon_path = global.resolve(&self.phonon);
+        info!("Parsing phonon OUTCAR {:?} ...", &phonon_path);
+        let phonon = Outcar::from_file(&phonon_path)?;
+        let vibs = match phonon.vib.as_ref() {
+            Some(vibs) => vibs,
+            None => {
+                warn!("No vibration modes found in {:?}, IBRION=5..8 is required", &phonon_path);
+                return Ok(());
+            },
+        };
+        let reference = phonon.get_structure_cloned(1);
+
+        let traj = match self.xdatcar.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Reading XDATCAR file {:?} ...", &path);
+                Trajectory::from_xdatcar(&path)?
+            },
+            None => Trajectory::from(global.load_outcar()?),
+        };
+        if let Some(s) = traj.0.iter().find(|s| s.frac_pos.len() != reference.frac_pos.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Got {} atoms in the phonon calculation, but {} in the trajectory", reference.frac_pos.len(), s.frac_pos.len())));
+        }
+
+        let traj = if self.remove_drift {
+            traj.without_com_drift(Some(&phonon.ion_masses))
+        } else {
+            traj
+        };
+        // The frames follow the phonon structure continuously to keep the molecules whole
+        let traj = if self.align {
+            let mut frames = Trajectory(std::iter::once(reference.clone()).chain(traj.0).collect()).unwrapped().0;
+            frames.remove(0);
+            Trajectory(frames).aligned_to(&reference, Some(&phonon.ion_masses))
+        } else {
+            traj
+        };
+
+        let select_indices = self.select_indices.clone().unwrap_or_else(|| vec![0]);
+        let imodes = _index_transform_helper(select_indices, vibs.len())
+            .into_iter()
+            .map(|i| {
+                if i < 1 || i > vibs.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Mode index {} out of bound, {} modes found", i, vibs.len())));
+                }
+                Ok(i - 1)
+            })
+            .collect::<io::Result<Vec<usize>>>()?;
+
+        let proj = ModeProjection::new(&reference, &phonon.ion_masses, vibs, &imodes, &traj, self.potim);
+        proj.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            proj.save_as_html(&self.save_in)?;
+        }
+        print_formatted(&proj, global.output_format)
+    }
+}
@@ -0,0 +1,68 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Structure;
+use crate::selection::RawSelection;
+use crate::neighbor::{
+    Cutoffs,
+    CoordinationAnalysis,
+    PairCutoff,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Lists the coordination numbers, bond lengths and neighbor species of the selected atoms
+///
+/// Two atoms are bonded if their distance, including periodic images, is less than the cutoff
+/// of the element pair. The cutoffs are the sums of covalent radii scaled by `--scale` unless
+/// given by `--cutoff`. Statistics of the bond lengths of each element pair are also listed.
+pub struct Neigh {
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(short, long, default_value = "")]
+    /// Selected atoms, starting from 1, e.g. "1 3..5 -1". Element symbols and spatial filters
+    /// like "O z>10" or "layer:1" are also supported. All atoms are selected if empty
+    atoms: String,
+
+    #[structopt(short, long)]
+    /// Bond cutoffs of element pairs in Angstrom, e.g. "Ti-O=2.5 O-O=0". The other pairs use
+    /// the covalent radii
+    cutoff: Option<String>,
+
+    #[structopt(long, default_value = "1.2")]
+    /// Scale of the sum of covalent radii used as the cutoff
+    scale: f64,
+}
+
+impl OptProcess for Neigh {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let pairs = self.cutoff.as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|s| s.parse::<PairCutoff>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let cutoffs = Cutoffs { scale: self.scale, pairs };
+
+        let poscar = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &poscar);
+        let structure = Structure::from(Poscar::from_path(&poscar)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+
+        let iatoms = RawSelection::parse_iatoms_in(&self.atoms, &structure);
+        let analysis = CoordinationAnalysis::new(&structure, &iatoms, &cutoffs);
+        print_formatted(&analysis, global.output_format)
+    }
+}
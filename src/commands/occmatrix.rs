@@ -0,0 +1,81 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Structure;
+use crate::audit::Incar;
+use crate::ldau::OccupationControl;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Generates the OCCMATRIX file to seed the orbital ordering of d shells
+///
+/// The target occupations are given by patterns like "1 3:t2g dz2/t2g" or "Mn:t2g x2-y2", the
+/// atoms before the last ':' and the occupied spin up (and spin down after '/') orbitals after
+/// it. The atoms are selected like `rsgrad dos`, and the orbitals are named "dxy", "dyz",
+/// "dz2", "dxz", "x2-y2" or grouped as "d", "t2g" and "eg". The INCAR tags for the occupation
+/// matrix control are printed, with LDAUL checked against the species if INCAR exists.
+pub struct Occmatrix {
+    #[structopt(short, long, required = true, number_of_values = 1)]
+    /// Occupation pattern of the selected atoms, can be repeated
+    pattern: Vec<String>,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(long, default_value = "./INCAR")]
+    /// Specify the INCAR file name, LDAUL, LDAUU and LDAUJ are read if it exists
+    incar: PathBuf,
+
+    #[structopt(short, long, default_value = "OCCMATRIX")]
+    /// Output file name of the occupation matrices
+    output: PathBuf,
+}
+
+impl OptProcess for Occmatrix {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let poscar_path = global.resolve(&self.poscar);
+        let incar_path = global.resolve(&self.incar);
+
+        info!("Reading POSCAR file {:?} ...", &poscar_path);
+        let structure = Structure::from(Poscar::from_path(&poscar_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+        let incar = if incar_path.is_file() {
+            Some(Incar::from_file(&incar_path)?)
+        } else {
+            warn!("{:?} not found, LDAUL is set to 2 for the targeted species and -1 for the others.", &incar_path);
+            None
+        };
+
+        let patterns = self.pattern.iter()
+            .map(|p| {
+                let (atoms, orbits) = p.rsplit_once(':')
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Invalid occupation pattern '{}', expected 'atoms:up/down'", p)))?;
+                let (up, down) = orbits.split_once('/').unwrap_or((orbits, ""));
+                Ok((atoms.to_string(), up.to_string(), down.to_string()))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let ctl = OccupationControl::new(&structure, &patterns, incar.as_ref())?;
+        let output = global.resolve(&self.output);
+        info!("Writing occupation matrices to {:?} ...", &output);
+        fs::write(&output, ctl.occmatrix())?;
+
+        print_formatted(&ctl, global.output_format)
+    }
+}
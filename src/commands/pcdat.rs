@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Trajectory;
+use crate::pcdat::{
+    Pcdat as PcdatFile,
+    PairCorrelation,
+    RdfNorm,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Reads the pair correlation function g(r) in PCDAT written by MD runs
+///
+/// With `--xdatcar`, g(r) is also calculated from the trajectory on the same bins for
+/// consistency checks, e.g. whether PCDAT and XDATCAR come from the same run and how many
+/// frames are needed for converged averages. The first `--skip` frames are dropped for
+/// equilibration.
+pub struct Pcdat {
+    #[structopt(long, default_value = "./PCDAT")]
+    /// Specify the PCDAT file name
+    pcdat: PathBuf,
+
+    #[structopt(long)]
+    /// Calculate g(r) from XDATCAR for comparison
+    xdatcar: Option<PathBuf>,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of frames in XDATCAR skipped for equilibration
+    skip: usize,
+
+    #[structopt(long, default_value = "raw", possible_values = &["raw", "tail", "peak"])]
+    /// Normalization of g(r): "raw" as is, "tail" to 1 at the long range (last 20% of the range),
+    /// or "peak" for the highest peak to 1
+    norm: RdfNorm,
+
+    #[structopt(long)]
+    /// Saves g(r) as HTML plot
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Pcdat {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let path = global.resolve(&self.pcdat);
+        info!("Reading PCDAT file {:?} ...", &path);
+        let pcdat = PcdatFile::from_file(&path)?;
+
+        let traj = match self.xdatcar.as_ref() {
+            Some(p) => {
+                let p = global.resolve(p);
+                info!("Reading XDATCAR file {:?} ...", &p);
+                let mut traj = Trajectory::from_xdatcar(&p)?;
+                if self.skip >= traj.0.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Cannot skip {} frames of the {} ones in XDATCAR", self.skip, traj.0.len())));
+                }
+                traj.0.drain(.. self.skip);
+                info!("Calculating g(r) from {} frames ...", traj.0.len());
+                Some(traj)
+            },
+            None => None,
+        };
+
+        let pc = PairCorrelation::new(&pcdat, traj.as_ref(), self.norm);
+        if let Some(p) = self.html.as_ref() {
+            pc.save_as_html(p)?;
+        }
+        print_formatted(&pc, global.output_format)
+    }
+}
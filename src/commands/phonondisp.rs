@@ -0,0 +1,95 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Structure;
+use crate::fdphonon::DisplacementSet;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Generates the symmetry reduced displaced supercells for the finite-difference phonons
+///
+/// The supercell of `--dim` is built from the pristine POSCAR and saved as SPOSCAR, then only
+/// the symmetry inequivalent atoms are displaced along the directions needed by their site
+/// symmetry, like phonopy does. Each displaced supercell is written as POSCAR in its own run
+/// directory "disp-001", "disp-002", ... together with the copied input files, and the
+/// displacements are recorded in "phonon_disp.json" for `rsgrad forcesets` to collect the
+/// forces after the runs finish.
+pub struct Phonondisp {
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the pristine POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Diagonal supercell of POSCAR, e.g. "2 2 2". POSCAR is used as is if not given
+    dim: Vec<usize>,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Displacement amplitude in Angstrom
+    amplitude: f64,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Tolerance of atomic positions to detect the symmetry, in Angstrom
+    symprec: f64,
+
+    #[structopt(long, default_value = "INCAR KPOINTS POTCAR")]
+    /// Input files copied into each run directory, separated by white spaces
+    files: String,
+
+    #[structopt(long, default_value = "phonon")]
+    /// Define where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Phonondisp {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let poscar = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &poscar);
+        let structure = Structure::from(Poscar::from_path(&poscar)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+        let structure = if self.dim.is_empty() {
+            structure
+        } else {
+            structure.supercell([self.dim[0], self.dim[1], self.dim[2]])
+        };
+
+        let set = DisplacementSet::new(&structure, self.amplitude, self.symprec);
+        if set.nops <= 1 {
+            warn!("No symmetry found except the identity, all the atoms are displaced.");
+        }
+
+        fs::create_dir_all(&self.save_in)?;
+        info!("Writing SPOSCAR and {} displaced supercells into {:?} ...", set.displacements.len(), &self.save_in);
+        structure.clone().save_as_poscar(&self.save_in.join("SPOSCAR"))?;
+        set.save_json(&self.save_in.join("phonon_disp.json"))?;
+
+        let files = self.files.split_whitespace().collect::<Vec<_>>();
+        for (i, d) in set.displacements.iter().enumerate() {
+            let dir = self.save_in.join(&d.dir);
+            fs::create_dir_all(&dir)?;
+            set.displaced(&structure, i).save_as_poscar(&dir.join("POSCAR"))?;
+            for fname in files.iter().filter(|f| **f != "POSCAR") {
+                let src = global.resolve(&PathBuf::from(fname));
+                if src.is_file() {
+                    fs::copy(&src, dir.join(fname))?;
+                } else if i == 0 {
+                    warn!("{:?} not found, skipped.", &src);
+                }
+            }
+        }
+        print_formatted(&set, global.output_format)
+    }
+}
@@ -0,0 +1,412 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::str::FromStr;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::format::{
+    Structure,
+    _car_to_frac,
+};
+use crate::selection::RawSelection;
+use crate::elastic::{
+    Deformation,
+    apply_strain,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Edits POSCAR files
+pub struct Poscar {
+    #[structopt(subcommand)]
+    op: PoscarOp,
+}
+
+#[derive(Debug, StructOpt)]
+enum PoscarOp {
+    Fix(Fix),
+    Transform(Transform),
+    Strain(Strain),
+}
+
+impl OptProcess for Poscar {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        match &self.op {
+            PoscarOp::Fix(cmd)       => cmd.process(global),
+            PoscarOp::Transform(cmd) => cmd.process(global),
+            PoscarOp::Strain(cmd)    => cmd.process(global),
+        }
+    }
+}
+
+
+// Selective dynamics flags along the three lattice vectors, e.g. "F F T" or "FFT"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DynamicsFlags(pub [bool; 3]);
+
+impl FromStr for DynamicsFlags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flags = s.chars()
+            .filter(|c| !c.is_whitespace() && *c != ',')
+            .map(|c| match c {
+                'T' | 't' => Ok(true),
+                'F' | 'f' => Ok(false),
+                _ => Err(()),
+            })
+            .collect::<Result<Vec<bool>, ()>>();
+        match flags.as_deref() {
+            Ok(&[a, b, c]) => Ok(Self([a, b, c])),
+            _ => Err(format!("Invalid flags '{}', should be three of T or F, e.g. \"F F T\"", s)),
+        }
+    }
+}
+
+
+/// Sets the selective dynamics flags of the atoms, the other atoms keep their flags or are
+/// relaxed along all directions if POSCAR has no selective dynamics. Returns the number of
+/// atoms whose flags are changed.
+pub fn set_dynamics(poscar: vasp_poscar::Poscar, iatoms: &[usize], flags: [bool; 3]) -> (vasp_poscar::Poscar, usize) {
+    let mut raw = poscar.into_raw();
+    let nions = raw.group_counts.iter().sum::<usize>();
+    let mut dynamics = raw.dynamics.take().unwrap_or_else(|| vec![[true; 3]; nions]);
+
+    let mut nchanged = 0;
+    for &i in iatoms {
+        if dynamics[i] != flags {
+            dynamics[i] = flags;
+            nchanged += 1;
+        }
+    }
+    raw.dynamics = Some(dynamics);
+    (raw.validate().expect("Selective dynamics should be consistent with the positions"), nchanged)
+}
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Sets the selective dynamics flags of the selected atoms, e.g. to fix the bottom layers of slabs
+///
+/// The atoms follow the syntax of `rsgrad dos --atoms`: indices starting from 1 with ranges
+/// like "1..4", element symbols, and spatial filters like "z<10", "fz>=0.5", "layer:1..2", or
+/// "d<3@5" for the atoms within 3 Angstrom around atom 5. The other atoms keep their flags.
+pub struct Fix {
+    #[structopt(short, long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(short, long, required = true)]
+    /// Selects the atoms, e.g. "1..8", "z<10" or "Pt layer:1..2"
+    atoms: String,
+
+    #[structopt(short, long, default_value = "F F F")]
+    /// Selective dynamics flags set to the atoms, "T" relaxes and "F" fixes along each lattice
+    /// vector
+    flags: DynamicsFlags,
+
+    #[structopt(long, conflicts_with = "flags")]
+    /// Unset the constraints of the selected atoms, same as `--flags "T T T"`
+    free: bool,
+
+    #[structopt(short, long, default_value = "./POSCAR_fixed")]
+    /// Where the modified POSCAR is saved
+    output: PathBuf,
+}
+
+impl OptProcess for Fix {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let path = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &path);
+        let poscar = vasp_poscar::Poscar::from_path(&path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let iatoms = if RawSelection::new("", &self.atoms, "").needs_structure() {
+            RawSelection::parse_iatoms_in(&self.atoms, &Structure::from(poscar.clone()))
+        } else {
+            RawSelection::parse_iatoms(&self.atoms, poscar.num_sites())
+        };
+        let flags = if self.free { [true; 3] } else { self.flags.0 };
+        let (poscar, nchanged) = set_dynamics(poscar, &iatoms, flags);
+
+        let tf = flags.iter().map(|f| if *f { "T" } else { "F" }).collect::<Vec<_>>().join(" ");
+        info!("{} atoms selected, flags of {} atoms set to {}", iatoms.len(), nchanged, tf);
+        info!("Writing POSCAR to {:?} ...", &self.output);
+        fs::write(&self.output, poscar.to_string())
+    }
+}
+
+
+/// Wraps the fractional coordinates into [0, 1).
+pub fn wrap(frac_pos: &mut [[f64; 3]]) {
+    for x in frac_pos.iter_mut().flatten() {
+        *x = x.rem_euclid(1.0);
+        if *x >= 1.0 { *x = 0.0; }  // rem_euclid of tiny negative numbers rounds up to 1
+    }
+}
+
+
+/// Shifts all the atoms by the fractional vector.
+pub fn shift(frac_pos: &mut [[f64; 3]], v: [f64; 3]) {
+    for p in frac_pos.iter_mut() {
+        for i in 0 .. 3 {
+            p[i] += v[i];
+        }
+    }
+}
+
+
+/// Fractional vector moving the geometric center of the slab along `axis` to the middle of the
+/// cell. The slab is made contiguous by cutting at the largest vacuum gap, so slabs across the
+/// cell boundary are handled.
+pub fn slab_center_shift(frac_pos: &[[f64; 3]], axis: usize) -> [f64; 3] {
+    let mut xs = frac_pos.iter().map(|p| p[axis].rem_euclid(1.0)).collect::<Vec<f64>>();
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut ret = [0.0; 3];
+    if xs.is_empty() { return ret; }
+    let n = xs.len();
+    // gap i lies between xs[i] and the next atom above, the last one wraps to the bottom
+    let ibot = (0 .. n)
+        .map(|i| if i + 1 < n { xs[i + 1] - xs[i] } else { xs[0] + 1.0 - xs[n - 1] })
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(i, _)| (i + 1) % n)
+        .unwrap();
+    let bottom = xs[ibot];
+    let top = if ibot == 0 { xs[n - 1] } else { xs[ibot - 1] + 1.0 };
+    ret[axis] = 0.5 - (bottom + top) / 2.0;
+    ret
+}
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Shifts, re-centers and wraps the atoms of POSCAR
+///
+/// The operations are composable and applied in the order of `--shift-frac`, `--shift-cart`,
+/// `--center-atom` or `--center-slab`, then `--wrap`. The lattice, selective dynamics and
+/// velocities are kept, the positions are written in fractional coordinates.
+pub struct Transform {
+    #[structopt(short, long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Shifts all the atoms by a fractional vector, e.g. "--shift-frac 0 0 0.1"
+    shift_frac: Vec<f64>,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Shifts all the atoms by a Cartesian vector in Angstrom, e.g. "--shift-cart 0 0 2.5"
+    shift_cart: Vec<f64>,
+
+    #[structopt(long, conflicts_with = "center-slab")]
+    /// Moves the atom, starting from 1, to the center of the cell
+    center_atom: Option<i32>,
+
+    #[structopt(long)]
+    /// Moves the geometric center of the slab along `--axis` to the middle of the cell
+    center_slab: bool,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the slab
+    axis: String,
+
+    #[structopt(long)]
+    /// Wraps all the atoms into the unit cell
+    wrap: bool,
+
+    #[structopt(short, long, default_value = "./POSCAR_transformed")]
+    /// Where the modified POSCAR is saved
+    output: PathBuf,
+}
+
+impl OptProcess for Transform {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let path = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &path);
+        let poscar = vasp_poscar::Poscar::from_path(&path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let cell = poscar.scaled_lattice_vectors();
+        let mut frac_pos = poscar.frac_positions().into_owned();
+        let nions = frac_pos.len();
+
+        if !self.shift_frac.is_empty() {
+            shift(&mut frac_pos, [self.shift_frac[0], self.shift_frac[1], self.shift_frac[2]]);
+        }
+        if !self.shift_cart.is_empty() {
+            let v = _car_to_frac(&cell, &vec![[self.shift_cart[0], self.shift_cart[1], self.shift_cart[2]]])[0];
+            shift(&mut frac_pos, v);
+        }
+        if let Some(i) = self.center_atom {
+            if i == 0 || i.unsigned_abs() as usize > nions {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Atom index {} out of bound, {} atoms in POSCAR", i, nions)));
+            }
+            let i = if i < 0 { (i + nions as i32) as usize } else { i as usize - 1 };
+            let p = frac_pos[i];
+            shift(&mut frac_pos, [0.5 - p[0], 0.5 - p[1], 0.5 - p[2]]);
+        }
+        if self.center_slab {
+            let axis = match self.axis.as_str() {
+                "a" => 0,
+                "b" => 1,
+                _   => 2,
+            };
+            let v = slab_center_shift(&frac_pos, axis);
+            info!("Slab shifted by {:.6} along {}", v[axis], self.axis);
+            shift(&mut frac_pos, v);
+        }
+        if self.wrap {
+            wrap(&mut frac_pos);
+        }
+
+        let mut raw = poscar.into_raw();
+        raw.positions = vasp_poscar::Coords::Frac(frac_pos);
+        let poscar = raw.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        info!("Writing POSCAR to {:?} ...", &self.output);
+        fs::write(&self.output, poscar.to_string())
+    }
+}
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Applies strains to the cell of POSCAR, or generates the deformations for elastic constants
+///
+/// The lattice vectors are deformed by a' = (I + e) a with the atoms kept at their fractional
+/// coordinates. The strains are given in Voigt notation (xx, yy, zz, yz, zx, xy) with
+/// engineering shear strains. With `--elastic-set`, each of the 6 components is strained by
+/// each of `--magnitudes` in both directions, and the cells are saved as POSCAR_e<i>_<strain>,
+/// e.g. POSCAR_e4_-0.0100, listed in deformations.txt for the stress-strain fitting.
+pub struct Strain {
+    #[structopt(short, long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name
+    poscar: PathBuf,
+
+    #[structopt(long, number_of_values = 6, required_unless = "elastic-set")]
+    /// Strain in Voigt notation, e.g. "--strain 0.01 0.01 0 0 0 0" for a biaxial strain of 1%
+    strain: Vec<f64>,
+
+    #[structopt(long, conflicts_with = "strain")]
+    /// Generates the standard set of deformations for fitting the elastic constants
+    elastic_set: bool,
+
+    #[structopt(long)]
+    /// Strain magnitudes of `--elastic-set`, 0.005 and 0.01 if not given
+    magnitudes: Vec<f64>,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Strain {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let path = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &path);
+        let poscar = vasp_poscar::Poscar::from_path(&path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let deformations = if self.elastic_set {
+            let magnitudes = if self.magnitudes.is_empty() { vec![0.005, 0.01] } else { self.magnitudes.clone() };
+            Deformation::standard_set(&magnitudes)
+        } else {
+            vec![Deformation::new([self.strain[0], self.strain[1], self.strain[2],
+                                   self.strain[3], self.strain[4], self.strain[5]])]
+        };
+
+        let cell = poscar.scaled_lattice_vectors();
+        let frac_pos = poscar.frac_positions().into_owned();
+        fs::create_dir_all(&self.save_in)?;
+        let mut index = fs::File::create(self.save_in.join("deformations.txt"))?;
+        writeln!(index, "# {:>18} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}", "file", "xx", "yy", "zz", "yz", "zx", "xy")?;
+
+        for d in deformations.iter() {
+            let mut raw = poscar.clone().into_raw();
+            raw.comment = format!("{} strained by {}", raw.comment.trim(), d.label);
+            raw.scale = vasp_poscar::ScaleLine::Factor(1.0);
+            raw.lattice_vectors = apply_strain(&cell, &d.strain());
+            raw.positions = vasp_poscar::Coords::Frac(frac_pos.clone());
+            let strained = raw.validate()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+            let fname = format!("POSCAR_{}", d.label);
+            info!("Writing POSCAR to {:?} ...", self.save_in.join(&fname));
+            fs::write(self.save_in.join(&fname), strained.to_string())?;
+            write!(index, "  {:>18}", fname)?;
+            for x in d.voigt.iter() {
+                write!(index, " {:9.5}", x)?;
+            }
+            writeln!(index)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_dynamics() {
+        assert_eq!("F F T".parse::<DynamicsFlags>(), Ok(DynamicsFlags([false, false, true])));
+        assert_eq!("tff".parse::<DynamicsFlags>(), Ok(DynamicsFlags([true, false, false])));
+        assert!("F T".parse::<DynamicsFlags>().is_err());
+
+        let poscar = vasp_poscar::Poscar::from_reader("\
+H2
+1.0
+  5.0 0.0 0.0
+  0.0 5.0 0.0
+  0.0 0.0 5.0
+H
+2
+Cartesian
+  0.0 0.0 0.0
+  0.0 0.0 0.7
+".as_bytes()).unwrap();
+        let (poscar, nchanged) = set_dynamics(poscar, &[1], [false; 3]);
+        assert_eq!(nchanged, 1);
+        assert_eq!(poscar.clone().into_raw().dynamics, Some(vec![[true; 3], [false; 3]]));
+
+        let (poscar, nchanged) = set_dynamics(poscar, &[0, 1], [false; 3]);
+        assert_eq!(nchanged, 1);
+        assert!(poscar.to_string().contains("Selective Dynamics"));
+    }
+
+    #[test]
+    fn test_transform() {
+        let mut pos = vec![[-0.1, 1.0, 0.5], [0.95, -1E-17, 2.25]];
+        wrap(&mut pos);
+        assert!((pos[0][0] - 0.9).abs() < 1E-12);
+        assert_eq!(pos[0][1], 0.0);
+        assert_eq!(pos[1][1], 0.0);
+        assert_eq!(pos[1][2], 0.25);
+
+        // Slab across the boundary along c, from 0.9 to 1.1
+        let pos = vec![[0.0, 0.0, 0.9], [0.0, 0.0, 0.05], [0.0, 0.0, 0.1]];
+        let v = slab_center_shift(&pos, 2);
+        assert_eq!(v[0], 0.0);
+        assert!((v[2] - (0.5 - 1.0)).abs() < 1E-12);
+
+        let mut pos = vec![[0.0, 0.0, 0.2], [0.0, 0.0, 0.4]];
+        let v = slab_center_shift(&pos, 2);
+        shift(&mut pos, v);
+        assert!((pos[0][2] - 0.4).abs() < 1E-12);
+        assert!((pos[1][2] - 0.6).abs() < 1E-12);
+    }
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::chgcar::ChargeDensity;
+use crate::potalign::{
+    PlanarAlignment,
+    SphericalAlignment,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Calculates the potential alignment between the LOCPOTs of defect and host calculations
+///
+/// The alignment dV = V_defect - V_host is sampled far from the defect, from either the planar
+/// averaged potentials or the potentials averaged in spheres centered at the host sites.
+pub struct Potalign {
+    #[structopt(long)]
+    /// Specify the LOCPOT file of host calculation
+    host: PathBuf,
+
+    #[structopt(long)]
+    /// Specify the LOCPOT file of defect calculation
+    defect: PathBuf,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Fractional coordinates of the defect, e.g. "--center 0.5 0.5 0.5"
+    center: Vec<f64>,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the averaging planes
+    axis: String,
+
+    #[structopt(long, default_value = "2.0")]
+    /// Width of the sampling window centered at the plane farthest from the defect, in Angstrom
+    width: f64,
+
+    #[structopt(long)]
+    /// Uses the spherical averaged potentials at the host sites instead of planar averaged ones
+    spherical: bool,
+
+    #[structopt(long, default_value = "5.0")]
+    /// Sites closer than this distance to the defect are excluded in spherical averaging, in Angstrom
+    rmin: f64,
+
+    #[structopt(long, default_value = "1.0")]
+    /// Radius of the spheres in spherical averaging, in Angstrom
+    radius: f64,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the planar averaged potentials plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Potalign {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let host_path = global.resolve(&self.host);
+        let defect_path = global.resolve(&self.defect);
+        if self.center.len() != 3 {
+            warn!("The fractional coordinates of defect are required, e.g. \"--center 0.5 0.5 0.5\"");
+            return Ok(());
+        }
+        let center = [self.center[0], self.center[1], self.center[2]];
+
+        info!("Parsing host LOCPOT file {:?} ...", &host_path);
+        let host = ChargeDensity::from_file(&host_path)?;
+        info!("Parsing defect LOCPOT file {:?} ...", &defect_path);
+        let defect = ChargeDensity::from_file(&defect_path)?;
+
+        if self.spherical {
+            let sa = SphericalAlignment::new(&host, &defect, &center, self.rmin, self.radius);
+            print_formatted(&sa, global.output_format)?;
+        } else {
+            let axis = match self.axis.as_str() {
+                "a" => 0,
+                "b" => 1,
+                _   => 2,
+            };
+            let pa = PlanarAlignment::new(&host, &defect, axis, center[axis], self.width);
+            print_formatted(&pa, global.output_format)?;
+            pa.save_as_txt(&self.save_in)?;
+            if !self.no_save_html {
+                pa.save_as_html(&self.save_in)?;
+            }
+        }
+        Ok(())
+    }
+}
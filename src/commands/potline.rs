@@ -0,0 +1,119 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::chgcar::ChargeDensity;
+use crate::outcar::Outcar;
+use crate::potline::PotentialLine;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Extracts the local potential along a line between two points from LOCPOT
+///
+/// The line needn't be aligned with lattice vectors, e.g. across a tilted interface, and the
+/// potentials are interpolated trilinearly. With `--vacuum-axis` the potentials are relative
+/// to the vacuum level, i.e. the maximum of the planar averaged potential, and the work
+/// function is reported if E-fermi can be read from OUTCAR.
+pub struct Potline {
+    #[structopt(long, default_value = "./LOCPOT")]
+    /// Specify the LOCPOT file name
+    locpot: PathBuf,
+
+    #[structopt(long, number_of_values = 3, required = true)]
+    /// Fractional coordinates of the starting point, e.g. "--start 0 0 0"
+    start: Vec<f64>,
+
+    #[structopt(long, number_of_values = 3, required = true)]
+    /// Fractional coordinates of the end point, e.g. "--end 0.5 0.5 1"
+    end: Vec<f64>,
+
+    #[structopt(long, default_value = "200")]
+    /// Number of points sampled along the line, both ends included
+    npoints: usize,
+
+    #[structopt(long, possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the vacuum layer, the potentials are shifted to the
+    /// vacuum level if given
+    vacuum_axis: Option<String>,
+
+    #[structopt(long)]
+    /// E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the line profile plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Potline {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let locpot_path = global.resolve(&self.locpot);
+        if self.start.len() != 3 || self.end.len() != 3 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "Three fractional coordinates are needed for each end, e.g. \"--start 0 0 0 --end 0.5 0.5 1\""));
+        }
+        if self.npoints < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "At least two points are needed"));
+        }
+
+        info!("Parsing LOCPOT file {:?} ...", &locpot_path);
+        let locpot = ChargeDensity::from_file(&locpot_path)?;
+        let start = [self.start[0], self.start[1], self.start[2]];
+        let end = [self.end[0], self.end[1], self.end[2]];
+        let mut line = PotentialLine::new(&locpot, start, end, self.npoints);
+
+        if let Some(axis) = self.vacuum_axis.as_ref() {
+            let axis = match axis.as_str() {
+                "a" => 0,
+                "b" => 1,
+                _   => 2,
+            };
+            let vac = locpot.planar_average(0, axis)
+                .into_iter()
+                .fold(f64::NEG_INFINITY, f64::max);
+            line = line.with_vacuum(vac);
+
+            let efermi = match self.efermi {
+                Some(e) => Some(e),
+                None => {
+                    let input = global.input_path();
+                    match Outcar::from_file(&input) {
+                        Ok(outcar) => Some(outcar.efermi),
+                        Err(e) => {
+                            warn!("Cannot read E-fermi from {:?}: {}, the work function is not calculated.", &input, e);
+                            None
+                        },
+                    }
+                },
+            };
+            if let Some(efermi) = efermi {
+                line = line.with_efermi(efermi);
+            }
+        } else if let Some(efermi) = self.efermi {
+            line = line.with_efermi(efermi);
+        }
+
+        print_formatted(&line, global.output_format)?;
+        line.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            line.save_as_html(&self.save_in)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::Trajectory;
+use crate::potcar::Potcar;
+use crate::selection::RawSelection;
+use crate::profile::Profile as ProfileReport;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Bins the kinetic temperature and density of MD along a lattice vector
+///
+/// The velocities are the central differences of the unwrapped positions in XDATCAR, thus
+/// XDATCAR should be written every step or a few steps (NBLOCK) for reasonable temperatures.
+/// The velocity of the center of mass is removed per frame, and the masses are read from
+/// POTCAR. The temperature gradient is fitted linearly over the bins in the range of
+/// `--fit-start` and `--fit-end`, e.g. between the heat source and sink of non-equilibrium MD.
+pub struct Profile {
+    #[structopt(long, default_value = "./XDATCAR")]
+    /// Specify the XDATCAR file
+    xdatcar: PathBuf,
+
+    #[structopt(long)]
+    /// Time between the frames of XDATCAR in fs, i.e. POTIM * NBLOCK
+    potim: f64,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector along which the atoms are binned
+    axis: String,
+
+    #[structopt(long, default_value = "20")]
+    /// Number of bins along the axis
+    nbins: usize,
+
+    #[structopt(long, default_value = "0")]
+    /// Number of the equilibration frames skipped
+    skip: usize,
+
+    #[structopt(long)]
+    /// Bins only the selected atoms, e.g. "Ar" or "1..100", all atoms by default
+    atoms: Option<String>,
+
+    #[structopt(long, default_value = "0")]
+    /// Start of the gradient fit, in fractional coordinate along the axis
+    fit_start: f64,
+
+    #[structopt(long, default_value = "1")]
+    /// End of the gradient fit, in fractional coordinate along the axis
+    fit_end: f64,
+
+    #[structopt(long)]
+    /// Saves the profiles as HTML
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Profile {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let axis = match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            _   => 2,
+        };
+
+        let xdatcar = global.resolve(&self.xdatcar);
+        info!("Reading {:?} ...", &xdatcar);
+        let skip = self.skip;
+        let mut traj = Trajectory::from_xdatcar(&xdatcar)?;
+        if skip >= traj.0.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Cannot skip {} frames, only {} frames found", skip, traj.0.len())));
+        }
+        traj.0.drain(.. skip);
+        info!("{} frames loaded", traj.0.len());
+
+        let potcar_path = global.resolve(&PathBuf::from("./POTCAR"));
+        info!("Reading POMASS in {:?} ...", &potcar_path);
+        let masses = Potcar::from_file(&potcar_path)
+            .map_err(|e| io::Error::new(e.kind(),
+                format!("Cannot read {:?} for the masses: {}", &potcar_path, e)))?
+            .masses_of(&traj.0[0])?;
+
+        let iatoms = match self.atoms.as_ref() {
+            Some(a) => RawSelection::parse_iatoms_in(a, &traj.0[0]),
+            None => (0 .. traj.0[0].car_pos.len()).collect(),
+        };
+        info!("Binning {} atoms into {} bins along {}", iatoms.len(), self.nbins, self.axis);
+
+        let report = ProfileReport::new(&traj, &masses, &iatoms, self.potim, axis,
+                                        self.nbins, (self.fit_start, self.fit_end))?;
+        if let Some(path) = self.html.as_ref() {
+            info!("Writing profiles to {:?} ...", path);
+            report.save_as_html(path)?;
+        }
+        print_formatted(&report, global.output_format)
+    }
+}
@@ -0,0 +1,53 @@
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::OptProcess;
+use crate::format::Structure;
+use crate::pymatgen::{
+    structure_to_pymatgen,
+    outcar_to_pymatgen,
+    save_pymatgen_json,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Converts OUTCAR or POSCAR into JSON following the `as_dict()` of pymatgen
+///
+/// The OUTCAR is written as `Outcar` with the ionic steps of `Vasprun`, and POSCAR (or CONTCAR)
+/// given by `--poscar` as `Structure`, e.g. loaded by `Structure.from_dict(json.load(f))` in
+/// Python. The band structures and DOS are saved by `--pymatgen` of `rsgrad band` and `rsgrad dos`.
+pub struct Pymatgen {
+    #[structopt(long)]
+    /// Convert the structure file instead of OUTCAR
+    poscar: Option<PathBuf>,
+
+    #[structopt(short = "o", long)]
+    /// Specify the output file name, "outcar_pymatgen.json" or "structure_pymatgen.json" by default
+    output: Option<PathBuf>,
+}
+
+impl OptProcess for Pymatgen {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let (value, default) = match self.poscar.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Reading POSCAR file {:?} ...", &path);
+                let structure = Structure::from(Poscar::from_path(&path)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+                (structure_to_pymatgen(&structure), "structure_pymatgen.json")
+            },
+            None => (outcar_to_pymatgen(&global.load_outcar()?), "outcar_pymatgen.json"),
+        };
+        let output = self.output.as_deref().unwrap_or_else(|| Path::new(default));
+        save_pymatgen_json(&value, output)
+    }
+}
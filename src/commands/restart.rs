@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::status::JobState;
+use crate::restart::Restart as RestartPlan;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prepares the restart of a relaxation from its last geometry
+///
+/// The last ionic step is read from OUTCAR or XDATCAR, whichever has more steps, thus the runs
+/// crashed before writing CONTCAR can be restarted as well. The selective dynamics of POSCAR
+/// are kept. ISTART and ICHARG are recommended by the WAVECAR and CHGCAR available to the next
+/// run. With `--next`, the inputs are copied into the next numbered directory, e.g. "run01" ->
+/// "run02", with the geometry written as its POSCAR.
+pub struct Restart {
+    #[structopt(long, default_value = "XDATCAR")]
+    /// Specify the XDATCAR file name, read if it has more ionic steps than OUTCAR
+    xdatcar: String,
+
+    #[structopt(short, long, default_value = "POSCAR_restart.vasp")]
+    /// Output file name of the last geometry, ignored if `--next` is set
+    output: PathBuf,
+
+    #[structopt(long)]
+    /// Creates the next numbered run directory next to the current one and copies the inputs
+    next: bool,
+
+    #[structopt(long, default_value = "INCAR KPOINTS POTCAR")]
+    /// Input files copied into the next run directory, separated by white spaces
+    files: String,
+
+    #[structopt(long)]
+    /// Copies WAVECAR and CHGCAR into the next run directory as well
+    wavefunctions: bool,
+}
+
+impl OptProcess for Restart {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let dir = global.input_dir();
+        let outcar = global.input_path();
+        let outcar = outcar.file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("OUTCAR");
+
+        let mut plan = RestartPlan::from_dir(&dir, outcar, &self.xdatcar)?;
+        match plan.state {
+            JobState::Converged => warn!("The job in {:?} is converged already.", &dir),
+            JobState::Running => warn!("OUTCAR in {:?} is not finished, the job crashed or is still running.", &dir),
+            _ => (),
+        }
+        if plan.ibrion == 0 {
+            warn!("Velocities are not kept, use CONTCAR to continue molecular dynamics if it exists.");
+        }
+
+        if self.next {
+            let wavefunctions = self.wavefunctions;
+            plan.recommend(wavefunctions && plan.wavecar, wavefunctions && plan.chgcar);
+            let files = self.files.split_whitespace().map(|s| s.to_string()).collect::<Vec<_>>();
+            let next = plan.prepare_next(&files, wavefunctions)?;
+            info!("Next run prepared in {:?}", &next);
+        } else {
+            plan.save_poscar(&self.output)?;
+        }
+        print_formatted(&plan, global.output_format)
+    }
+}
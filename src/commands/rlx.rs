@@ -0,0 +1,175 @@
+use std::io;
+use std::path::PathBuf;
+use log::warn;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::{
+    IonicIterationsFormat,
+    ScfHistory,
+    EnergyHistory,
+};
+use crate::oszicar::Oszicar;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Tracking info associated with relaxation stuff
+///
+/// The energies are cross-checked with OSZICAR next to OUTCAR if it exists. When OUTCAR is
+/// not available, the energies and SCF steps are read from OSZICAR instead.
+pub struct Rlx {
+    #[structopt(short = "e", long = "toten")]
+    /// Prints TOTEN in eV
+    print_energy: bool,
+
+    #[structopt(short = "a", long = "favg")]
+    /// Prints averaged total force in eV/A
+    print_favg: bool,
+
+    #[structopt(short = "x", long = "fmaxis")]
+    /// Prints the axis where the strongest total force component lies on. [XYZ]
+    print_fmax_axis: bool,
+
+    #[structopt(short = "i" ,long = "fmidx")]
+    /// Prints the index of ion with maximum total force load. Starts from 1
+    print_fmax_index: bool,
+
+    #[structopt(short = "v", long = "volume")]
+    /// Prints lattice volume in A^3
+    print_volume: bool,
+
+    #[structopt(short = "l", long = "lattice")]
+    /// Prints lattice parameters a, b, c in A and alpha, beta, gamma in degrees
+    print_lattice: bool,
+
+    #[structopt(short = "s", long = "stress")]
+    /// Prints the components of stress tensor XX YY ZZ XY YZ ZX in kB
+    print_stress: bool,
+
+    #[structopt(short = "c", long = "charge")]
+    /// Prints the integrated number of electrons and the net charge of the cell in |e|, steps
+    /// deviating from NELECT are highlighted
+    print_charge: bool,
+
+    #[structopt(short = "d", long = "dipole")]
+    /// Prints the dipole moment of the cell in e*A, available with LDIPOL=.TRUE. only
+    print_dipole: bool,
+
+    #[structopt(long)]
+    /// Prints log10(|dE|) of each SCF step for all the ionic steps instead, useful for
+    /// diagnosing the stalling SCF iterations
+    scf_history: bool,
+
+    #[structopt(long)]
+    /// Saves the SCF convergence curves as HTML plot, only valid with '--scf-history'
+    scf_html: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Prints the contributions to the free energy (PSCENC, TEWEN, DENC, ...) of each ionic step
+    /// instead, useful for debugging the suspicious energy drifts
+    energy_terms: bool,
+
+    #[structopt(long)]
+    /// Saves the changes of the energy terms as HTML plot, only valid with '--energy-terms'
+    energy_html: Option<PathBuf>,
+
+    #[structopt(long = "no-fmax")]
+    /// Don't print maximum total force in A^3
+    no_print_fmax: bool,
+
+    #[structopt(long = "no-totenz")]
+    /// Don't print TOTEN without entropy in eV
+    no_print_energyz: bool,
+
+    #[structopt(long = "no-lgde")]
+    /// Don't print Log10(delta(TOTEN without entropy))
+    no_print_lgde: bool,
+
+    #[structopt(long = "no-magmom")]
+    /// Don't print total magnetic moment in muB
+    no_print_magmom: bool,
+
+    #[structopt(long = "no-nscf")]
+    /// Don't print number of SCF iteration for each ionic step
+    no_print_nscf: bool,
+
+    #[structopt(long = "no-time")]
+    /// Don't print time elapsed for each ionic step in minutes
+    no_print_time: bool,
+}
+
+impl OptProcess for Rlx {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let oszicar_path = global.input_dir().join("OSZICAR");
+        if !global.input_path().exists() && oszicar_path.is_file() {
+            warn!("{:?} not found, reading {:?} instead.", global.input_path(), &oszicar_path);
+            let oszicar = Oszicar::from_file(&oszicar_path)?;
+            if self.scf_history {
+                let history = ScfHistory::from(&oszicar);
+                if let Some(path) = self.scf_html.as_ref() {
+                    history.save_as_html(path)?;
+                }
+                return print_formatted(&history, global.output_format);
+            }
+            return print_formatted(&oszicar, global.output_format);
+        }
+
+        let outcar = global.load_outcar()?;
+        if oszicar_path.is_file() {
+            let oszicar = Oszicar::from_file(&oszicar_path)?;
+            if oszicar.ionic_steps.len() > outcar.ion_iters.len() {
+                warn!("OSZICAR has {} ionic steps but OUTCAR has {} only, OUTCAR may be truncated.",
+                      oszicar.ionic_steps.len(), outcar.ion_iters.len());
+            }
+            for (step, e_oszicar, e_outcar) in oszicar.energy_mismatches(&outcar.ion_iters, 1E-3) {
+                warn!("TOTEN of step {} differs between OSZICAR ({:.6}) and OUTCAR ({:.6}), are they from the same run?",
+                      step, e_oszicar, e_outcar);
+            }
+        }
+
+        if self.scf_history {
+            let history = ScfHistory::from(outcar.ion_iters.as_slice());
+            if let Some(path) = self.scf_html.as_ref() {
+                history.save_as_html(path)?;
+            }
+            return print_formatted(&history, global.output_format);
+        }
+        if self.energy_terms {
+            let history = EnergyHistory::from(outcar.ion_iters.as_slice());
+            if let Some(path) = self.energy_html.as_ref() {
+                history.save_as_html(path)?;
+            }
+            return print_formatted(&history, global.output_format);
+        }
+
+        let (neutral, nelect) = (outcar.neutral_nelect(), outcar.nelect);
+        let iif = IonicIterationsFormat::from(outcar.ion_iters)
+            .print_energy     (self.print_energy)
+            .print_energyz    (!self.no_print_energyz)
+            .print_log10de    (!self.no_print_lgde)
+            .print_favg       (self.print_favg)
+            .print_fmax       (!self.no_print_fmax)
+            .print_fmax_axis  (self.print_fmax_axis)
+            .print_fmax_index (self.print_fmax_index)
+            .print_nscf       (!self.no_print_nscf)
+            .print_time_usage (!self.no_print_time)
+            .print_magmom     (!self.no_print_magmom)
+            .print_volume     (self.print_volume)
+            .print_lattice    (self.print_lattice)
+            .print_stress     (self.print_stress)
+            .print_charge     (self.print_charge)
+            .print_dipole     (self.print_dipole)
+            .electrons        (neutral, nelect);
+        if let Some((step, d)) = iif.charge_leakage(1E-3) {
+            warn!("The number of electrons deviates from NELECT={} by {:.4} since step {}, check the charge leakage.",
+                  nelect, d, step);
+        }
+        print_formatted(&iif, global.output_format)
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::format::Structure;
+use crate::siteproj::SiteProjection;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Prints the s, p, d and f electrons and local moments of each ion integrated from PROCAR
+///
+/// The projections are summed over the occupied states weighted by the occupations and k-point
+/// weights, which approximates the l-decomposed charges without DOSCAR. The charges in the
+/// interstitial region are not included, thus the total is less than the valence electrons.
+pub struct Siteproj {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, read for the element symbols of ions
+    poscar: PathBuf,
+}
+
+impl OptProcess for Siteproj {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let procar_path = global.resolve(&self.procar);
+        let poscar_path = global.resolve(&self.poscar);
+
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+
+        let symbols = match Poscar::from_path(&poscar_path) {
+            Ok(poscar) => {
+                let symbols = Structure::from(poscar).symbols();
+                if symbols.len() == procar.nions {
+                    Some(symbols)
+                } else {
+                    warn!("Got {} atoms in {:?}, but {} in PROCAR, the element symbols are ignored.",
+                          symbols.len(), &poscar_path, procar.nions);
+                    None
+                }
+            },
+            Err(_) => {
+                warn!("Cannot read {:?}, the ions are labeled by their indices.", &poscar_path);
+                None
+            },
+        };
+
+        let proj = SiteProjection::from_procar(&procar, symbols.as_deref());
+        print_formatted(&proj, global.output_format)
+    }
+}
@@ -0,0 +1,94 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::OptProcess;
+use crate::procar::Procar;
+use crate::spintex::SpinTexture;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Extracts the spin texture of one band from PROCAR of non-collinear calculation
+pub struct Spintex {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(short = "b", long)]
+    /// Selects the band to operate, starts from 1
+    iband: usize,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, k-points are converted to cartesian
+    /// coordinates if it exists, otherwise fractional coordinates are used
+    poscar: PathBuf,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the spin texture plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Spintex {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let procar_path = global.resolve(&self.procar);
+        let poscar_path = global.resolve(&self.poscar);
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+
+        _check_band(&procar, self.iband)?;
+
+        let cell = if let Ok(poscar) = Poscar::from_path(&poscar_path) {
+            info!("POSCAR was read. K-points are converted to cartesian coordinates.");
+            Some(poscar.scaled_lattice_vectors())
+        } else { None };
+
+        let st = SpinTexture::from_procar(&procar, self.iband, cell.as_ref());
+        st.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            st.save_as_html(&self.save_in)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Rejects PROCARs without spin texture and band indices out of range, which are user inputs
+fn _check_band(procar: &Procar, iband: usize) -> io::Result<()> {
+    if !procar.lncl {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            "Spin texture is only available for PROCAR of non-collinear calculation"));
+    }
+    if iband < 1 || iband > procar.nbands {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Band index {} out of range, expected 1 ..= {}", iband, procar.nbands)));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_band() {
+        let procar = Procar::from_file("tests/PROCAR_ncl").unwrap();
+        assert_eq!(procar.nbands, 2);
+        assert!(_check_band(&procar, 1).is_ok());
+        assert!(_check_band(&procar, 2).is_ok());
+        assert_eq!(_check_band(&procar, 0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(_check_band(&procar, 3).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let collinear = Procar { lncl: false, ..procar };
+        assert_eq!(_check_band(&collinear, 1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
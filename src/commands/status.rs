@@ -0,0 +1,66 @@
+use std::io;
+use rayon::prelude::*;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::status::{
+    JobStatus,
+    StatusTable,
+};
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Reports the status of calculations quickly: converged or not, current steps, time and ETA
+///
+/// Only the header and the last 8 MiB of OUTCAR in each directory are scanned, with the current
+/// steps and energy change taken from OSZICAR if it exists, thus it is cheap enough for
+/// thousands of directories and multi-GB OUTCARs. The times of long runs are extrapolated from
+/// the ionic steps in the tail. Gzipped OUTCAR and vasprun.xml are not supported. Single point calculations are converged if the last
+/// energy change is below EDIFF within NELM steps, relaxations if EDIFFG is reached before
+/// NSW steps. The ETA of running jobs comes from the average time of the finished ionic steps,
+/// which is an upper bound for relaxations. "Idle" is the time since the last write of OUTCAR,
+/// a large value of a running job usually means it was killed. Directories without OUTCAR are
+/// reported as "missing".
+pub struct Status {
+    /// Directories to process, glob patterns like "calc_*" are expanded. Relative paths are
+    /// looked up in `--dir`. Only the input OUTCAR is checked if not given
+    dirs: Vec<String>,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory given by `dirs`
+    outcar: String,
+}
+
+impl OptProcess for Status {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.dirs.is_empty() {
+            let input = global.input_path();
+            let fname = input.file_name().and_then(|f| f.to_str()).unwrap_or("OUTCAR");
+            let job = JobStatus::from_dir(&global.input_dir(), fname)?;
+            return print_formatted(&StatusTable(vec![job]), global.output_format);
+        }
+
+        let dirs = batch::expand_dirs(&global.resolve_patterns(&self.dirs));
+        if dirs.is_empty() {
+            warn!("No directories are selected to operate!");
+            return Ok(());
+        }
+        info!("Checking {} directories ...", dirs.len());
+
+        let jobs = dirs.par_iter()
+            .map(|d| JobStatus::from_dir(d, &self.outcar))
+            .collect::<io::Result<Vec<_>>>()?;
+        print_formatted(&StatusTable(jobs), global.output_format)
+    }
+}
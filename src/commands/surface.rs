@@ -0,0 +1,86 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::summary::Summary;
+use crate::surface::{
+    SlabRecord,
+    SurfaceEnergy,
+};
+use crate::batch;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Calculates the surface energies of symmetric slabs and their convergence with thickness
+///
+/// Each directory is one slab calculation, e.g. "layers_3 layers_5 ..." or "layers_*". The
+/// surface energy is (E_slab - n * E_bulk) / 2A, where n is the number of bulk cells in the
+/// slab, counted from the compositions, and A is the area of the cell surface perpendicular to
+/// `--axis`. Slabs whose compositions are not multiples of the bulk are skipped.
+pub struct Surface {
+    #[structopt(required = true)]
+    /// Directories of the slab calculations, glob patterns like "layers_*" are expanded
+    dirs: Vec<String>,
+
+    #[structopt(long, required = true)]
+    /// Specify the OUTCAR of the bulk calculation
+    bulk: PathBuf,
+
+    #[structopt(long, default_value = "OUTCAR")]
+    /// Name of the OUTCAR file in each directory
+    outcar: String,
+
+    #[structopt(long, default_value = "c", possible_values = &["a", "b", "c"])]
+    /// Lattice vector perpendicular to the surfaces
+    axis: String,
+
+    #[structopt(long, default_value = "0.5")]
+    /// Atoms with heights differing less than this, in Angstrom, are in the same layer
+    tolerance: f64,
+
+    #[structopt(long)]
+    /// Saves the surface energies against the number of layers as an HTML plot, e.g.
+    /// "surface.html"
+    html: Option<PathBuf>,
+}
+
+impl OptProcess for Surface {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let axis = match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            _   => 2,
+        };
+
+        let bulk_path = global.resolve(&self.bulk);
+        info!("Parsing bulk OUTCAR {:?} ...", &bulk_path);
+        let bulk_dir = bulk_path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let bulk = SlabRecord::from_outcar(&Outcar::from_file(&bulk_path)?, &bulk_dir);
+
+        let dirs = batch::expand_dirs(&global.resolve_patterns(&self.dirs));
+        info!("Processing {} directories ...", dirs.len());
+        let records = batch::run_batch::<SlabRecord>(&dirs, &self.outcar);
+        let nfailed = records.iter().filter(|r| r.error.is_some()).count();
+        if nfailed > 0 {
+            warn!("{} of {} directories failed to be processed and are skipped.", nfailed, records.len());
+        }
+
+        let surface = SurfaceEnergy::new(&bulk, &records, axis, self.tolerance);
+        if let Some(path) = self.html.as_ref() {
+            surface.save_as_html(path)?;
+        }
+        print_formatted(&surface, global.output_format)
+    }
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::chgcar::ChargeDensity;
+use crate::symmetry::{
+    space_group,
+    SymmetrizedForces,
+    symmetrize_grid,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Symmetrizes the volumetric data and forces by the space group of the structure
+///
+/// The space group operations are detected within `--symprec`, then the charge density of
+/// `--chgcar` is averaged over the images of each grid point, and the forces of one ionic step
+/// in OUTCAR with `--forces` are averaged over the symmetry equivalent atoms. Useful to clean
+/// up the slightly broken symmetry of a calculation before further analysis.
+///
+/// Only the first block of CHGCAR (the total density) is symmetrized by default. The
+/// magnetization blocks are kept since they may break the crystal symmetry, e.g. of an
+/// antiferromagnetic order, use `--all-blocks` to symmetrize them as scalars too.
+pub struct Symmetrize {
+    #[structopt(long)]
+    /// Volumetric data (CHGCAR, LOCPOT, etc.) to symmetrize, by the structure in itself
+    chgcar: Option<PathBuf>,
+
+    #[structopt(short = "o", long, default_value = "./CHGCAR_sym")]
+    /// Specify the output file name of the symmetrized volumetric data
+    output: PathBuf,
+
+    #[structopt(long)]
+    /// Symmetrize the forces of the OUTCAR, by the structure of the same ionic step
+    forces: bool,
+
+    #[structopt(short = "i", long, default_value = "-1")]
+    /// Ionic step to symmetrize the forces of, starts from '1', negative values count from the last
+    select_step: i32,
+
+    #[structopt(long, default_value = "0.01")]
+    /// Tolerance of atomic positions to detect the symmetry, in Angstrom
+    symprec: f64,
+
+    #[structopt(long)]
+    /// Symmetrize all the blocks of the volumetric data, including the magnetization densities
+    all_blocks: bool,
+}
+
+impl OptProcess for Symmetrize {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.chgcar.is_none() && !self.forces {
+            warn!("Nothing to symmetrize, specify `--chgcar` and/or `--forces`.");
+            return Ok(());
+        }
+
+        if let Some(path) = self.chgcar.as_ref() {
+            let path = global.resolve(path);
+            info!("Parsing volumetric data file {:?} ...", &path);
+            let chg = ChargeDensity::from_file(&path)?;
+            let ops = space_group(&chg.pos, self.symprec);
+            info!("Found {} space group operations, symmetrizing {} grid points ...", ops.len(), chg.npoints());
+            let iblocks = if self.all_blocks {
+                if chg.blocks.len() > 1 {
+                    warn!("Magnetization densities are symmetrized as scalars, magnetic orders breaking the crystal symmetry will be averaged out.");
+                }
+                (0 .. chg.blocks.len()).collect()
+            } else {
+                vec![0]
+            };
+            symmetrize_grid(&chg, &ops, &iblocks).save_as_vasp(&self.output)?;
+        }
+
+        if self.forces {
+            let outcar = global.load_outcar()?;
+            let nsteps = outcar.ion_iters.len() as i32;
+            let istep = if self.select_step < 0 { nsteps + 1 + self.select_step } else { self.select_step };
+            if istep < 1 || istep > nsteps {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Ionic step {} is out of range, {} steps found in OUTCAR", self.select_step, nsteps)));
+            }
+
+            let structure = outcar.get_structure_cloned(istep as usize);
+            let ops = space_group(&structure, self.symprec);
+            info!("Found {} space group operations in ionic step {}, symmetrizing the forces ...", ops.len(), istep);
+            let forces = &outcar.ion_iters[istep as usize - 1].forces;
+            print_formatted(&SymmetrizedForces::new(&structure, forces, &ops, self.symprec), global.output_format)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::format::{
+    Trajectory,
+    _save_as_xsf_helper,
+};
+use crate::outcar::MatX3;
+use crate::selection::RawSelection;
+use crate::potcar::Potcar;
+use super::{
+    GlobalOpts,
+    _index_transform_helper,
+};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Operations about relaxation/MD trajectory
+///
+/// The frames can be subsampled by `--range` and `--every`, and the atoms by `--atoms` before
+/// exporting, thus long MD trajectories can be converted into light-weight ones. With
+/// `--xdatcar`, only the selected frames of XDATCAR are parsed instead of the whole OUTCAR.
+/// The drift of the center of mass and the rigid rotations can be removed for MSD and VACF.
+pub struct Trj {
+    #[structopt(short = "i", long)]
+    /// Selects the indices to operate.
+    ///
+    /// Step indices start from '1', if '0' is given, all the structures will be selected.
+    /// Step indices can be negative, where negative index means counting reversely.
+    /// E.g. "--save-as-poscars -2 -1 1 2 3" means saving the last two and first three
+    /// steps. The frames selected by `--range` and `--every` are operated if not given.
+    select_indices: Option<Vec<i32>>,
+
+    #[structopt(short = "d", long)]
+    /// Saves total trajectory in XDATCAR format, subsampled by `--range` and `--every`
+    save_as_xdatcar: bool,
+
+    #[structopt(short = "p", long)]
+    /// Saves structures of given steps as POSCARs
+    save_as_poscars: bool,
+
+    #[structopt(short = "x", long)]
+    /// Saves structures of given steps as XSFs
+    save_as_xsfs: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+
+    #[structopt(long)]
+    /// Reads the trajectory from XDATCAR instead of OUTCAR, XSFs are saved without forces
+    xdatcar: Option<PathBuf>,
+
+    #[structopt(long)]
+    /// Range of the frames, inclusive and starting from 1, negative indices count reversely,
+    /// e.g. "1000..-1" or "..500". All the frames if not given
+    range: Option<String>,
+
+    #[structopt(long, default_value = "1")]
+    /// Takes one frame of every N frames in the range
+    every: usize,
+
+    #[structopt(long)]
+    /// Unwraps the coordinates across the periodic boundaries before exporting, such that the
+    /// atoms move continuously from the first frame in the range, e.g. for diffusion analyses
+    unwrap: bool,
+
+    #[structopt(long)]
+    /// Removes the drift of the center of mass from the first frame in the range
+    remove_drift: bool,
+
+    #[structopt(long)]
+    /// Rigidly aligns the frames onto the given step by translations and rotations, for
+    /// molecules and clusters. The coordinates are unwrapped
+    align_to: Option<i32>,
+
+    #[structopt(long)]
+    /// Weighs the atoms by POMASS in OUTCAR, or in POTCAR if the trajectory is read from
+    /// XDATCAR, for `--remove-drift` and `--align-to`
+    mass_weighted: bool,
+
+    #[structopt(long)]
+    /// Keeps only the selected atoms, e.g. "1..4 -1 O" or "z>10" resolved on the first frame
+    atoms: Option<String>,
+}
+
+impl OptProcess for Trj {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.every == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--every should be positive"));
+        }
+
+        let (content, outcar) = match self.xdatcar.as_ref() {
+            Some(path) => {
+                let path = global.resolve(path);
+                info!("Reading XDATCAR file {:?} ...", &path);
+                (Some(fs::read_to_string(&path)?), None)
+            },
+            None => (None, Some(global.load_outcar()?)),
+        };
+        let nframes = match (content.as_ref(), outcar.as_ref()) {
+            (Some(c), _) => Trajectory::count_xdatcar_frames(c),
+            (_, Some(o)) => o.ion_iters.len(),
+            _ => unreachable!(),
+        };
+        if nframes == 0 {
+            warn!("No frames found in the trajectory!");
+            return Ok(());
+        }
+
+        let sliced = match self.range.as_deref() {
+            Some(range) => _parse_range(range, nframes)?,
+            None => (1, nframes),
+        };
+        let sliced = (sliced.0 ..= sliced.1).step_by(self.every).collect::<Vec<usize>>();
+        let is_sliced = self.range.is_some() || self.every > 1;
+
+        let mut steps = match self.select_indices.clone() {
+            Some(v) if !v.is_empty() => _index_transform_helper(v, nframes),
+            _ if is_sliced => sliced.clone(),
+            _ => vec![],
+        };
+        if let Some(i) = steps.iter().find(|i| **i < 1 || **i > nframes) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Step index {} out of bound, {} steps found", i, nframes)));
+        }
+        steps.sort_unstable();
+        steps.dedup();
+        if !self.save_as_xdatcar && steps.is_empty() {
+            warn!("No steps are selected to operate !");
+            return Ok(());
+        }
+
+        // Only the frames to be saved are loaded
+        let mut needed = if self.save_as_xdatcar { sliced.clone() } else { vec![] };
+        needed.extend(steps.iter().copied());
+        let align_to = match self.align_to {
+            Some(i) => {
+                let i = _index_transform_helper(vec![i], nframes)[0];
+                if i < 1 || i > nframes {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Step index {} out of bound, {} steps found", i, nframes)));
+                }
+                needed.push(i);
+                Some(i)
+            },
+            None => None,
+        };
+        needed.sort_unstable();
+        needed.dedup();
+        // Unwrapping follows the atoms frame by frame, thus the frames in between are required
+        if self.unwrap || self.remove_drift || align_to.is_some() {
+            needed = (needed[0] ..= needed[needed.len() - 1]).collect();
+        }
+
+        let frames = match (content.as_ref(), outcar.as_ref()) {
+            (Some(c), _) => Trajectory::parse_xdatcar_frames(c, |i| needed.binary_search(&i).is_ok())?.0,
+            (_, Some(o)) => needed.iter().map(|i| o.get_structure_cloned(*i)).collect(),
+            _ => unreachable!(),
+        };
+
+        let masses = if self.mass_weighted {
+            let masses = match outcar.as_ref() {
+                Some(o) => o.ion_masses.clone(),
+                None => {
+                    let potcar_path = global.resolve(&PathBuf::from("./POTCAR"));
+                    info!("Reading POMASS in {:?} ...", &potcar_path);
+                    let potcar = Potcar::from_file(&potcar_path)
+                        .map_err(|e| io::Error::new(e.kind(),
+                            format!("Cannot read {:?} for the masses: {}", &potcar_path, e)))?;
+                    potcar.masses_of(&frames[0])?
+                },
+            };
+            if masses.len() != frames[0].car_pos.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Got {} masses, but {} atoms in the trajectory", masses.len(), frames[0].car_pos.len())));
+            }
+            Some(masses)
+        } else {
+            None
+        };
+
+        let mut traj = Trajectory(frames);
+        if self.unwrap || align_to.is_some() {
+            traj = traj.unwrapped();
+        }
+        if self.remove_drift {
+            traj = traj.without_com_drift(masses.as_deref());
+        }
+        if let Some(i) = align_to {
+            let reference = traj.0[needed.binary_search(&i).unwrap()].clone();
+            traj = traj.aligned_to(&reference, masses.as_deref());
+        }
+        let frames = traj.0;
+
+        let iatoms = self.atoms.as_ref()
+            .map(|a| RawSelection::parse_iatoms_in(a, &frames[0]));
+        let frames = match iatoms.as_ref() {
+            Some(iatoms) => {
+                info!("{} atoms of {} are kept.", iatoms.len(), frames[0].car_pos.len());
+                frames.iter().map(|s| s.select_atoms(iatoms)).collect()
+            },
+            None => frames,
+        };
+        let frame = |step: usize| &frames[needed.binary_search(&step).unwrap()];
+
+        if self.save_as_xdatcar {
+            info!("Saving {} frames of {} ...", sliced.len(), nframes);
+            Trajectory(sliced.iter().map(|i| frame(*i).clone()).collect())
+                .save_as_xdatcar(&self.save_in)?;
+        }
+
+        if self.save_as_poscars || self.save_as_xsfs {
+            fs::create_dir_all(&self.save_in)?;
+        }
+
+        if self.save_as_poscars {
+            steps.par_iter()
+                .map(|i| {
+                    let fname = self.save_in.join(format!("POSCAR_{:05}.vasp", i));
+                    info!("Saving trajectory step #{:5} to {:?} ...", i, &fname);
+                    frame(*i).clone().save_as_poscar(&fname)
+                })
+                .collect::<io::Result<()>>()?;
+        }
+
+        if self.save_as_xsfs {
+            if outcar.is_none() {
+                warn!("Forces are not available in XDATCAR, zeros are written into XSFs.");
+            }
+            steps.par_iter()
+                .map(|i| {
+                    let s = frame(*i);
+                    let forces: MatX3<f64> = match (outcar.as_ref(), iatoms.as_ref()) {
+                        (Some(o), Some(iatoms)) => iatoms.iter().map(|ia| o.ion_iters[i - 1].forces[*ia]).collect(),
+                        (Some(o), None) => o.ion_iters[i - 1].forces.clone(),
+                        (None, _) => vec![[0.0; 3]; s.car_pos.len()],
+                    };
+                    let fname = self.save_in.join(format!("step_{:04}.xsf", i));
+                    info!("Saving ionic step to {:?} ...", &fname);
+                    _save_as_xsf_helper(&fname, s, &forces)
+                })
+                .collect::<io::Result<()>>()?;
+        }
+        Ok(())
+    }
+}
+
+
+// Parses "a..b" into the inclusive range of frames starting from 1, where either bound may be
+// omitted and negative indices count reversely.
+fn _parse_range(input: &str, nframes: usize) -> io::Result<(usize, usize)> {
+    let err = || io::Error::new(io::ErrorKind::InvalidInput,
+        format!("Invalid frame range '{}' for {} frames, expected like \"1..100\"", input, nframes));
+    let (beg, end) = input.split_once("..").ok_or_else(err)?;
+    let to_index = |x: &str, default: usize| -> io::Result<usize> {
+        let x = x.trim();
+        if x.is_empty() {
+            return Ok(default);
+        }
+        let i = x.parse::<i32>().map_err(|_| err())?;
+        let n = nframes as i32;
+        if i == 0 || i.abs() > n {
+            return Err(err());
+        }
+        Ok(if i < 0 { (i + n + 1) as usize } else { i as usize })
+    };
+    let (beg, end) = (to_index(beg, 1)?, to_index(end, nframes)?);
+    if beg > end {
+        return Err(err());
+    }
+    Ok((beg, end))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(_parse_range("2..5", 10).unwrap(), (2, 5));
+        assert_eq!(_parse_range("..-2", 10).unwrap(), (1, 9));
+        assert_eq!(_parse_range("-3..", 10).unwrap(), (8, 10));
+        assert!(_parse_range("5..2", 10).is_err());
+        assert!(_parse_range("0..2", 10).is_err());
+        assert!(_parse_range("3", 10).is_err());
+    }
+}
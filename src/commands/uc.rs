@@ -0,0 +1,499 @@
+use std::io;
+use std::fmt;
+use std::str::FromStr;
+use serde::Serialize;
+use colored::Colorize;
+use regex::Regex;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    Tabular,
+    print_formatted,
+};
+use crate::constants::{
+    PLANCK,
+    HBAR,
+    BOLTZMANN,
+    SPEED_OF_LIGHT,
+    HC,
+    E_CHARGE,
+    ELECTRON_MASS,
+    AMU,
+    AVOGADRO,
+    EPSILON0,
+    BOHR,
+    HARTREE,
+    RYDBERG,
+    BOHR_MAGNETON,
+    FARADAY,
+    EV_KJ_MOL,
+    EV_KCAL_MOL,
+    EV_PER_A3_TO_GPA,
+};
+use super::GlobalOpts;
+
+
+// Physical constants printed by `--constants`, as (name, symbol, value, unit)
+const CONSTANTS: [(&str, &str, f64, &str); 15] = [
+    ("Planck constant",              "h",    PLANCK,             "eV s"),
+    ("Reduced Planck constant",      "hbar", HBAR,               "eV s"),
+    ("Boltzmann constant",           "kB",   BOLTZMANN,          "eV/K"),
+    ("Speed of light",               "c",    SPEED_OF_LIGHT,     "m/s"),
+    ("Planck constant * c",          "hc",   HC,                 "eV nm"),
+    ("Elementary charge",            "e",    E_CHARGE,           "C"),
+    ("Electron mass",                "me",   ELECTRON_MASS,      "kg"),
+    ("Atomic mass unit",             "amu",  AMU,                "kg"),
+    ("Avogadro constant",            "NA",   AVOGADRO,           "1/mol"),
+    ("Vacuum permittivity",          "eps0", EPSILON0,           "F/m"),
+    ("Bohr radius",                  "a0",   BOHR,               "Å"),
+    ("Hartree energy",               "Eh",   HARTREE,            "eV"),
+    ("Rydberg energy",               "Ry",   RYDBERG,            "eV"),
+    ("Bohr magneton",                "muB",  BOHR_MAGNETON,      "eV/T"),
+    ("Faraday constant",             "F",    FARADAY,            "C/mol"),
+];
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    EV,
+    MilliEV,
+    Hartree,
+    Rydberg,
+    KJPerMol,
+    KcalPerMol,
+    Wavenumber,
+    Kelvin,
+    Hertz,
+    GigaHertz,
+    TeraHertz,
+    Nanometer,
+    Angstrom,
+    Micrometer,
+    Femtosecond,
+    Picosecond,
+    EVPerA3,
+    GigaPascal,
+    KiloBar,
+    Bar,
+    Atmosphere,
+    Pascal,
+}
+
+// Quantities are converted only between the units of the same dimension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    Energy,    // including temperatures, frequencies, wavelengths and periods
+    Pressure,
+}
+
+// How a unit relates to the base unit (eV or GPa): y = factor * x, or E = factor / x for
+// wavelengths and periods
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scale {
+    Proportional(f64),
+    Inverse(f64),
+}
+
+impl Unit {
+    pub const ALL: [Unit; 22] = [
+        Unit::EV, Unit::MilliEV, Unit::Hartree, Unit::Rydberg, Unit::KJPerMol, Unit::KcalPerMol,
+        Unit::Wavenumber, Unit::Kelvin, Unit::Hertz, Unit::GigaHertz, Unit::TeraHertz,
+        Unit::Nanometer, Unit::Angstrom, Unit::Micrometer, Unit::Femtosecond, Unit::Picosecond,
+        Unit::EVPerA3, Unit::GigaPascal, Unit::KiloBar, Unit::Bar, Unit::Atmosphere, Unit::Pascal,
+    ];
+
+    pub fn dimension(&self) -> Dimension {
+        match self {
+            Unit::EVPerA3 | Unit::GigaPascal | Unit::KiloBar |
+                Unit::Bar | Unit::Atmosphere | Unit::Pascal => Dimension::Pressure,
+            _ => Dimension::Energy,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Unit::EV          => "eV",
+            Unit::MilliEV     => "meV",
+            Unit::Hartree     => "Ha",
+            Unit::Rydberg     => "Ry",
+            Unit::KJPerMol    => "kJ/mol",
+            Unit::KcalPerMol  => "kcal/mol",
+            Unit::Wavenumber  => "cm-1",
+            Unit::Kelvin      => "K",
+            Unit::Hertz       => "Hz",
+            Unit::GigaHertz   => "GHz",
+            Unit::TeraHertz   => "THz",
+            Unit::Nanometer   => "nm",
+            Unit::Angstrom    => "Å",
+            Unit::Micrometer  => "μm",
+            Unit::Femtosecond => "fs",
+            Unit::Picosecond  => "ps",
+            Unit::EVPerA3     => "eV/Å^3",
+            Unit::GigaPascal  => "GPa",
+            Unit::KiloBar     => "kBar",
+            Unit::Bar         => "bar",
+            Unit::Atmosphere  => "atm",
+            Unit::Pascal      => "Pa",
+        }
+    }
+
+    fn scale(&self) -> Scale {
+        match self {
+            Unit::EV          => Scale::Proportional(1.0),
+            Unit::MilliEV     => Scale::Proportional(1E-3),
+            Unit::Hartree     => Scale::Proportional(HARTREE),
+            Unit::Rydberg     => Scale::Proportional(RYDBERG),
+            Unit::KJPerMol    => Scale::Proportional(1.0 / EV_KJ_MOL),
+            Unit::KcalPerMol  => Scale::Proportional(1.0 / EV_KCAL_MOL),
+            Unit::Wavenumber  => Scale::Proportional(HC * 1E-7),
+            Unit::Kelvin      => Scale::Proportional(BOLTZMANN),
+            Unit::Hertz       => Scale::Proportional(PLANCK),
+            Unit::GigaHertz   => Scale::Proportional(PLANCK * 1E9),
+            Unit::TeraHertz   => Scale::Proportional(PLANCK * 1E12),
+            Unit::Nanometer   => Scale::Inverse(HC),
+            Unit::Angstrom    => Scale::Inverse(HC * 10.0),
+            Unit::Micrometer  => Scale::Inverse(HC * 1E-3),
+            Unit::Femtosecond => Scale::Inverse(PLANCK * 1E15),
+            Unit::Picosecond  => Scale::Inverse(PLANCK * 1E12),
+            Unit::EVPerA3     => Scale::Proportional(EV_PER_A3_TO_GPA),
+            Unit::GigaPascal  => Scale::Proportional(1.0),
+            Unit::KiloBar     => Scale::Proportional(0.1),
+            Unit::Bar         => Scale::Proportional(1E-4),
+            Unit::Atmosphere  => Scale::Proportional(1.01325E-4),
+            Unit::Pascal      => Scale::Proportional(1E-9),
+        }
+    }
+
+    /// Value in eV for energies, or in GPa for pressures.
+    pub fn to_base(&self, x: f64) -> f64 {
+        match self.scale() {
+            Scale::Proportional(a) => a * x,
+            Scale::Inverse(a)      => a / x,
+        }
+    }
+
+    pub fn from_base(&self, e: f64) -> f64 {
+        match self.scale() {
+            Scale::Proportional(a) => e / a,
+            Scale::Inverse(a)      => a / e,
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ev" | "ev/atom"                 => Ok(Unit::EV),
+            "mev" | "mev/atom"               => Ok(Unit::MilliEV),
+            "ha" | "hartree"                 => Ok(Unit::Hartree),
+            "ry" | "rydberg"                 => Ok(Unit::Rydberg),
+            "kj/mol"                         => Ok(Unit::KJPerMol),
+            "kcal/mol"                       => Ok(Unit::KcalPerMol),
+            "cm-1" | "cm^-1" | "1/cm"        => Ok(Unit::Wavenumber),
+            "k" | "kelvin"                   => Ok(Unit::Kelvin),
+            "hz"                             => Ok(Unit::Hertz),
+            "ghz"                            => Ok(Unit::GigaHertz),
+            "thz"                            => Ok(Unit::TeraHertz),
+            "nm"                             => Ok(Unit::Nanometer),
+            "a" | "å" | "angstrom"           => Ok(Unit::Angstrom),
+            "um" | "μm" | "µm" | "micron"    => Ok(Unit::Micrometer),
+            "fs"                             => Ok(Unit::Femtosecond),
+            "ps"                             => Ok(Unit::Picosecond),
+            "ev/å^3" | "ev/å3" | "ev/a^3" | "ev/a3" => Ok(Unit::EVPerA3),
+            "gpa"                            => Ok(Unit::GigaPascal),
+            "kbar" | "kb"                    => Ok(Unit::KiloBar),
+            "bar"                            => Ok(Unit::Bar),
+            "atm"                            => Ok(Unit::Atmosphere),
+            "pa"                             => Ok(Unit::Pascal),
+            _ => Err(format!("Invalid unit '{}', should be one of {}", s,
+                             Unit::ALL.iter().map(|u| u.label()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+}
+
+
+/// A value with unit, e.g. "300 K", "1550nm" or "0.2 eV".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantity {
+    pub value : f64,
+    pub unit  : Unit,
+}
+
+impl Quantity {
+    /// The same quantity in `unit`, None if the dimensions differ.
+    pub fn convert(&self, unit: Unit) -> Option<f64> {
+        if unit.dimension() == self.unit.dimension() {
+            Some(unit.from_base(self.unit.to_base(self.value)))
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(r"^\s*([-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?)\s*(\S+)\s*$").unwrap();
+        let caps = re.captures(s)
+            .ok_or_else(|| format!("Invalid quantity '{}', should be a number followed by a unit, e.g. \"300 K\"", s))?;
+        let value = caps[1].parse::<f64>().map_err(|e| e.to_string())?;
+        let unit = caps[2].parse::<Unit>()?;
+
+        if matches!(unit.scale(), Scale::Inverse(_)) && value <= 0.0 {
+            return Err(format!("Invalid quantity '{}', wavelengths and periods should be positive", s));
+        }
+        Ok(Self { value, unit })
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit.label())
+    }
+}
+
+
+/// The quantities converted into all the supported units of their dimensions, the values are
+/// None for the units of other dimensions.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConversionTable {
+    pub inputs : Vec<String>,
+    pub units  : Vec<String>,
+    pub values : Vec<Vec<Option<f64>>>,  // [nunits][ninputs]
+    #[serde(skip)]
+    quantities : Vec<Quantity>,
+    #[serde(skip)]
+    unit_list  : Vec<Unit>,
+}
+
+impl ConversionTable {
+    pub fn new(quantities: &[Quantity]) -> Self {
+        let unit_list = Unit::ALL.iter()
+            .filter(|u| quantities.iter().any(|q| q.unit.dimension() == u.dimension()))
+            .copied()
+            .collect::<Vec<_>>();
+        Self {
+            inputs: quantities.iter().map(|q| q.to_string()).collect(),
+            units: unit_list.iter().map(|u| u.label().to_string()).collect(),
+            values: unit_list.iter()
+                .map(|u| quantities.iter().map(|q| q.convert(*u)).collect())
+                .collect(),
+            quantities: quantities.to_vec(),
+            unit_list,
+        }
+    }
+}
+
+impl Tabular for ConversionTable {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["unit".to_string()];
+        ret.extend(self.inputs.iter().cloned());
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.units.iter()
+            .zip(self.values.iter())
+            .map(|(u, v)| {
+                let mut row = vec![u.clone()];
+                row.extend(v.iter().map(|x| x.map(|x| format!("{:e}", x)).unwrap_or_default()));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ConversionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = self.inputs.iter()
+            .map(|s| format!("{:>16}", s))
+            .collect::<String>();
+        writeln!(f, "{}", format!("{:>10}{}", "Unit", header).bright_green())?;
+
+        for (iu, unit) in self.unit_list.iter().enumerate() {
+            write!(f, "{:>10}", unit.label())?;
+            for (iq, q) in self.quantities.iter().enumerate() {
+                let s = format!("{:>16}", self.values[iu][iq].map(_format_value).unwrap_or_default());
+                if q.unit == *unit {
+                    write!(f, "{}", s.bright_yellow())?;
+                } else {
+                    write!(f, "{}", s)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Physical constants in the units used by VASP.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConstantsTable {
+    pub constants: Vec<Constant>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Constant {
+    pub name   : String,
+    pub symbol : String,
+    pub value  : f64,
+    pub unit   : String,
+}
+
+impl ConstantsTable {
+    pub fn new() -> Self {
+        Self {
+            constants: CONSTANTS.iter()
+                .map(|(name, symbol, value, unit)| Constant {
+                    name: name.to_string(),
+                    symbol: symbol.to_string(),
+                    value: *value,
+                    unit: unit.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for ConstantsTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tabular for ConstantsTable {
+    fn headers(&self) -> Vec<String> {
+        ["name", "symbol", "value", "unit"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.constants.iter()
+            .map(|c| vec![c.name.clone(), c.symbol.clone(), format!("{:e}", c.value), c.unit.clone()])
+            .collect()
+    }
+}
+
+impl fmt::Display for ConstantsTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("{:>26} {:>6} {:>18}  {}", "Constant", "Symbol", "Value", "Unit").bright_green())?;
+        for c in self.constants.iter() {
+            writeln!(f, "{:>26} {:>6} {:>18}  {}", c.name, c.symbol.bright_yellow(), format!("{:e}", c.value), c.unit)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Seven significant digits, in scientific notation for very large or small values
+fn _format_value(x: f64) -> String {
+    if x == 0.0 {
+        return "0".to_string();
+    }
+    let exp = x.abs().log10().floor() as i32;
+    if (-3 ..= 6).contains(&exp) {
+        format!("{:.*}", (6 - exp) as usize, x)
+    } else {
+        format!("{:.6e}", x)
+    }
+}
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Converts energies between the units of energy, temperature, frequency, wavelength and time
+///
+/// Quantities are given as numbers followed by units, e.g. "300 K", "1550nm" or "0.2 eV".
+/// Wavelengths and periods are converted by E = hc/λ and E = h/t, temperatures by E = kT.
+/// Pressures and stresses are converted separately, e.g. "0.01 eV/Å^3" or "-5.2 kBar".
+///
+/// Supported energy units: eV (or eV/atom), meV, Ha, Ry, kJ/mol, kcal/mol, cm-1, K, Hz, GHz,
+/// THz, nm, Å (or A), μm (or um), fs and ps. Supported pressure units: eV/Å^3 (or eV/A3), GPa,
+/// kBar, bar, atm and Pa.
+pub struct Uc {
+    #[structopt(required_unless = "constants")]
+    /// Quantities to convert, each one as a column of the printed table
+    quantities: Vec<Quantity>,
+
+    #[structopt(long)]
+    /// Print the table of physical constants, e.g. hbar, kB, Bohr radius and Hartree energy
+    constants: bool,
+}
+
+impl OptProcess for Uc {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.constants {
+            print_formatted(&ConstantsTable::new(), global.output_format)?;
+        }
+        if !self.quantities.is_empty() {
+            let table = ConversionTable::new(&self.quantities);
+            print_formatted(&table, global.output_format)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!("300 K".parse::<Quantity>(), Ok(Quantity { value: 300.0, unit: Unit::Kelvin }));
+        assert_eq!("1550nm".parse::<Quantity>(), Ok(Quantity { value: 1550.0, unit: Unit::Nanometer }));
+        assert_eq!(" -2.5e-1 eV ".parse::<Quantity>(), Ok(Quantity { value: -0.25, unit: Unit::EV }));
+        assert_eq!("5 meV".parse::<Quantity>().unwrap().unit, Unit::MilliEV);
+        assert_eq!("1 cm^-1".parse::<Quantity>().unwrap().unit, Unit::Wavenumber);
+        assert!("eV".parse::<Quantity>().is_err());
+        assert!("1 furlong".parse::<Quantity>().is_err());
+        assert!("0 nm".parse::<Quantity>().is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let close = |a: Option<f64>, b: f64| (a.unwrap() - b).abs() < 1E-6 * b.abs();
+        assert!(close("1 eV".parse::<Quantity>().unwrap().convert(Unit::Nanometer), 1239.84198));
+        assert!(close("300 K".parse::<Quantity>().unwrap().convert(Unit::MilliEV), 25.8519998));
+        assert!(close("1 Ha".parse::<Quantity>().unwrap().convert(Unit::KcalPerMol), 627.509474));
+        assert!(close("1 THz".parse::<Quantity>().unwrap().convert(Unit::Wavenumber), 33.3564095));
+        assert!(close("100 fs".parse::<Quantity>().unwrap().convert(Unit::TeraHertz), 10.0));
+
+        // Round trip through every unit
+        let q = "1550 nm".parse::<Quantity>().unwrap();
+        for unit in Unit::ALL.iter() {
+            if let Some(value) = q.convert(*unit) {
+                assert!(close(Quantity { value, unit: *unit }.convert(Unit::Nanometer), 1550.0));
+            }
+        }
+
+        // Stress and pressure
+        assert!(close("1 eV/A^3".parse::<Quantity>().unwrap().convert(Unit::GigaPascal), 160.21766208));
+        assert!(close("-52 kBar".parse::<Quantity>().unwrap().convert(Unit::GigaPascal), -5.2));
+        assert!(close("1 eV/atom".parse::<Quantity>().unwrap().convert(Unit::KJPerMol), 96.48533212));
+        assert_eq!("1 GPa".parse::<Quantity>().unwrap().convert(Unit::EV), None);
+    }
+
+    #[test]
+    fn test_conversion_table() {
+        let table = ConversionTable::new(&["0.2 eV".parse().unwrap(), "300 K".parse().unwrap()]);
+        assert_eq!(table.headers(), vec!["unit", "0.2 eV", "300 K"]);
+        assert_eq!(table.rows()[0], vec!["eV", "2e-1", &format!("{:e}", BOLTZMANN * 300.0)]);
+        assert_eq!(table.units.len(), 16);
+
+        let table = ConversionTable::new(&["0.2 eV".parse().unwrap(), "1 GPa".parse().unwrap()]);
+        assert_eq!(table.units.len(), 22);
+        assert_eq!(table.rows()[16], vec!["eV/Å^3", "", &format!("{:e}", 1.0 / EV_PER_A3_TO_GPA)]);
+
+        let constants = ConstantsTable::new();
+        assert_eq!(constants.rows()[2], vec!["Boltzmann constant", "kB", "8.617333262e-5", "eV/K"]);
+        assert_eq!(constants.rows()[14], vec!["Faraday constant", "F", "9.648533212e4", "C/mol"]);
+        assert_eq!(_format_value(1239.84198), "1239.842");
+        assert_eq!(_format_value(4.1356677E-15), "4.135668e-15");
+    }
+}
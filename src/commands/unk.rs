@@ -0,0 +1,121 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use itertools::iproduct;
+use crate::traits::OptProcess;
+use crate::selection::RawSelection;
+use crate::wavecar::{
+    self,
+    Axis,
+    WavecarType,
+};
+use crate::wannier::UnkFile;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Writes the periodic parts of Bloch functions in WAVECAR as UNK files of Wannier90
+///
+/// One file is written for each k-point and spin channel, i.e. "UNK00001.1" and "UNK00001.2" for
+/// ISPIN=2, or "UNK00001.NC" with both spinor components for non-collinear calculations. The
+/// files are Fortran unformatted, as read by Wannier90 with `wvfn_formatted = false`. The
+/// Gamma-only WAVECAR is unfolded to the full set of plane waves before the transformation.
+///
+/// Wannier90 requires the k-points of the full mesh, thus the WAVECAR should come from a
+/// calculation without symmetry reduction (ISYM = -1 or 0). The bands are selected to match
+/// `exclude_bands` of wannier90.win, and the initial projections (AMN) are not written.
+pub struct Unk {
+    #[structopt(short, long, default_value = "./WAVECAR")]
+    /// Specify the WAVECAR file name
+    wavecar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Selects the bands written into the UNK files, e.g. "-b 5..20", all of them if not given
+    bands: Option<String>,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Grid size NGX NGY NGZ, e.g. "--ngrid 40 40 60", the FFT grid of ENCUT if not given
+    ngrid: Vec<usize>,
+
+    #[structopt(long, default_value = "x")]
+    /// Half-grid direction of Gamma-only WAVECAR (x, y or z), which should be the same as the
+    /// one VASP was compiled with, ignored for the other WAVECARs
+    gamma_half: Axis,
+
+    #[structopt(short, long)]
+    /// Number of workers transforming the wavefunctions, the number of CPU cores if not given
+    jobs: Option<usize>,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Unk {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let wavecar_path = global.resolve(&self.wavecar);
+        if !self.ngrid.is_empty() && self.ngrid.len() != 3 {
+            warn!("Three numbers are required for the grid size, e.g. \"--ngrid 40 40 60\"");
+            return Ok(());
+        }
+
+        info!("Reading WAVECAR file {:?} ...", &wavecar_path);
+        let wav = wavecar::Wavecar::from_file(&wavecar_path)?.with_gamma_half(self.gamma_half);
+        info!("ISPIN = {}, NKPTS = {}, NBANDS = {}, ENCUT = {} eV, {:?}",
+              wav.nspin, wav.nkpts, wav.nbands, wav.encut, wav.wavetype);
+
+        let ibands = match self.bands.as_ref() {
+            Some(s) => RawSelection::parse_iatoms(s, wav.nbands),
+            None    => (0 .. wav.nbands).collect(),
+        };
+        if ibands.is_empty() {
+            warn!("No bands selected.");
+            return Ok(());
+        }
+        let ngrid = if self.ngrid.is_empty() {
+            wav.ngrid
+        } else {
+            [self.ngrid[0], self.ngrid[1], self.ngrid[2]]
+        };
+
+        let ncl = wav.wavetype == WavecarType::NonCollinear;
+        let nspinor = if ncl { 2 } else { 1 };
+        let states = iproduct!(0 .. wav.nspin, 0 .. wav.nkpts, ibands.iter().copied()).collect::<Vec<_>>();
+        let nworkers = self.jobs.unwrap_or_else(rayon::current_num_threads);
+        info!("Transforming {} states onto grid {:?} by {} workers ...", states.len(), ngrid, nworkers);
+
+        let save_in = global.resolve(&self.save_in);
+        fs::create_dir_all(&save_in)?;
+
+        // The bands are passed in the order of completion, a file is closed once all its bands
+        // are written, thus only a few of them are open at the same time.
+        let mut files: HashMap<(usize, usize), (UnkFile, usize)> = HashMap::new();
+        wav.for_each_realspace(&states, ngrid, nworkers, |(is, ik, ib), grids| {
+            let (unk, nwritten) = match files.entry((is, ik)) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let fname = save_in.join(UnkFile::fname(ik, is, ncl));
+                    info!("Writing UNK file {:?} ...", &fname);
+                    e.insert((UnkFile::create(&fname, ngrid, ik, ibands.len(), nspinor)?, 0))
+                },
+            };
+            let iband = ibands.iter().position(|&b| b == ib).unwrap();
+            unk.write_band(iband, grids)?;
+            *nwritten += 1;
+            if *nwritten == ibands.len() {
+                files.remove(&(is, ik));
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::procar::Procar;
+use crate::format::Structure;
+use crate::velocity::{
+    KGrid,
+    GroupVelocities,
+    VelocityReport,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Estimates the group velocities and mobilities from the band energies of PROCAR
+///
+/// The velocities v = dE/dk / hbar are calculated by central finite differences on the uniform
+/// k-point grid, thus the full grid is required, e.g. run with ISYM=0 or -1 and a dense
+/// Gamma-centered or Monkhorst-Pack mesh. K-points with zero weights are dropped first.
+///
+/// The Fermi velocities are averaged over the states weighted by the derivative of the Fermi-
+/// Dirac distribution. Given the relaxation time by `--tau`, the mobility tensors of the
+/// electrons above and holes below E-fermi are estimated in the constant relaxation time
+/// approximation. Bands are matched by their indices, thus the velocities near band crossings
+/// are less reliable.
+pub struct Velocity {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name for the lattice vectors
+    poscar: PathBuf,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(long, default_value = "0.1")]
+    /// States within this window around E-fermi are saved in velocities.txt, in eV
+    window: f64,
+
+    #[structopt(short = "T", long, default_value = "300")]
+    /// Temperature for the Fermi-Dirac distribution, in K
+    temperature: f64,
+
+    #[structopt(long)]
+    /// Constant relaxation time in fs, the mobilities are estimated if given
+    tau: Option<f64>,
+
+    #[structopt(long, default_value = ".")]
+    /// Define the directory to save velocities.txt
+    save_in: PathBuf,
+}
+
+impl OptProcess for Velocity {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.temperature <= 0.0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Temperature should be positive."));
+        }
+
+        let efermi = match self.efermi {
+            Some(e) => e,
+            None => global.load_outcar()?.efermi,
+        };
+
+        let procar_path = global.resolve(&self.procar);
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let mut procar = Procar::from_file(&procar_path)?;
+        let izero = procar.zero_weight_kpoints();
+        if !izero.is_empty() {
+            info!("{} k-points with zero weights dropped.", izero.len());
+            let ikpoints = (0 .. procar.nkpts)
+                .filter(|ik| !izero.contains(ik))
+                .collect::<Vec<_>>();
+            procar = procar.select_kpoints(&ikpoints);
+        }
+
+        let grid = KGrid::from_kpoints(&procar.kpoints).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            "K-points of PROCAR do not form a complete uniform grid, run with ISYM=0 or -1."))?;
+        info!("Uniform k-point grid found: {} x {} x {}", grid.dims[0], grid.dims[1], grid.dims[2]);
+        if grid.dims.contains(&2) {
+            warn!("Velocities along the axes with 2 k-points are set to zero, use denser grids.");
+        }
+
+        let poscar_path = global.resolve(&self.poscar);
+        info!("Reading POSCAR file {:?} ...", &poscar_path);
+        let cell = Structure::from(Poscar::from_path(&poscar_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?).cell;
+
+        let vel = GroupVelocities::from_procar(&procar, &grid, &cell);
+        let report = VelocityReport::new(&procar, &vel, &cell, efermi, self.temperature, self.window, self.tau);
+        if report.states.is_empty() {
+            warn!("No states found within {} eV around E-fermi, the system may be gapped.", self.window);
+        }
+        report.save_as_txt(&global.resolve(&self.save_in))?;
+
+        print_formatted(&report, global.output_format)
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::path::PathBuf;
+use log::warn;
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::format::{
+    Vibrations,
+    PrintAllVibFreqs,
+};
+use super::{
+    GlobalOpts,
+    _index_transform_helper,
+};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Tracking info associated with vibration stuff
+pub struct Vib {
+    #[structopt(short, long)]
+    /// Shows vibration modes in brief
+    list: bool,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Diagonal supercell of the primitive cell used in the calculation, e.g. "2 2 2". Each
+    /// mode in the list is assigned the primitive q-point with the largest Fourier weight of
+    /// its eigenvector
+    supercell: Vec<usize>,
+
+    #[structopt(short = "x", long)]
+    /// Saves each selected modes to XSF file
+    save_as_xsfs: bool,
+
+    #[structopt(short = "i", long)]
+    /// Selects the indices to operate.
+    ///
+    /// Step indices start from '1', if '0' is given, all the structures will be selected.
+    /// Step indices can be negative, where negative index means counting reversely.
+    /// E.g. "--save-as-poscars -2 -1 1 2 3" means saving the last two and first three
+    /// steps.
+    select_indices: Option<Vec<i32>>,
+
+    #[structopt(long, default_value = ".")]
+    /// Define where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Vib {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let outcar = global.load_outcar()?;
+        if self.list {
+            let vibs = Vibrations::from(outcar);
+            if self.supercell.is_empty() {
+                let paf: PrintAllVibFreqs = vibs.into();
+                return print_formatted(&paf, global.output_format);
+            }
+            let supercell = [self.supercell[0], self.supercell[1], self.supercell[2]];
+            let qpoints = vibs.fold_qpoints(supercell, 0.05);
+            let paf = PrintAllVibFreqs::from(vibs).with_qpoints(qpoints);
+            return print_formatted(&paf, global.output_format);
+        }
+
+        if self.save_as_xsfs {
+            let select_indices = self.select_indices.clone().unwrap_or_default();
+            if select_indices.is_empty() {
+                warn!("No modes are selected to operate!");
+                return Ok(());
+            }
+
+            let vibs = Vibrations::from(outcar);
+            let len = vibs.modes.len();
+
+            let inds: Vec<usize> = _index_transform_helper(select_indices, len);
+
+            inds.par_iter()
+                .map(|i| {
+                    vibs.save_as_xsf(*i, &self.save_in)?;
+                    Ok(())
+                })
+                .collect::<io::Result<()>>()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::io;
+use std::path::PathBuf;
+use log::{
+    info,
+    warn,
+};
+use structopt::StructOpt;
+use itertools::{
+    iproduct,
+    Itertools,
+};
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::OptProcess;
+use crate::selection::RawSelection;
+use crate::wavecar::{
+    self,
+    Axis,
+    WavMode,
+    WavecarType,
+};
+use crate::chgcar::ChargeDensity;
+use crate::format::Structure;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Writes the real-space wavefunctions of the selected states in WAVECAR as CHGCAR or cube files
+///
+/// The states are transformed by a fixed number of workers with their own FFT plans and buffers,
+/// and each state is written out before the next ones are read, thus the memory stays bounded
+/// for any number of bands. Several quantities can be written in one run by `--modes`:
+///
+/// - abs2: |psi|^2 * V integrating to one electron as in CHGCAR, summed over the spinors
+///
+/// - re, im: real and imaginary parts of psi * sqrt(V), one file for each spinor component of
+///   non-collinear states
+///
+/// - arg: phase of psi in radians, one file for each spinor component of non-collinear states
+///
+/// - up, dn: |psi|^2 * V of the spin up and down components of non-collinear states
+///
+/// The periodic parts of Bloch functions are written, i.e. without exp(ik.r). The files are
+/// named as "<prefix>_<spin>_<kpoint>_<band>_<mode>.vasp", or ".cube" with `--cube`, where the
+/// modes of spinor components are suffixed by "_up" and "_dn", e.g. "wav_1_1_12_re_dn.vasp".
+pub struct Wav3d {
+    #[structopt(short, long, default_value = "./WAVECAR")]
+    /// Specify the WAVECAR file name
+    wavecar: PathBuf,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, the structure is written into the output files
+    poscar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Selects the spin channels, all of them if not given
+    spins: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the k-points, all of them if not given
+    kpoints: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the bands, e.g. "-b -2..-1 5", all of them if not given
+    bands: Option<String>,
+
+    #[structopt(short, long, default_value = "abs2")]
+    /// Quantities to be written, any of abs2, re, im, arg, up and dn, e.g. "-m abs2 re im"
+    modes: Vec<WavMode>,
+
+    #[structopt(long)]
+    /// Writes Gaussian cube files instead of CHGCAR
+    cube: bool,
+
+    #[structopt(long, number_of_values = 3)]
+    /// Grid size NGX NGY NGZ, e.g. "--ngrid 120 120 240", twice the FFT grid of ENCUT if not given
+    ngrid: Vec<usize>,
+
+    #[structopt(long, default_value = "x")]
+    /// Half-grid direction of Gamma-only WAVECAR (x, y or z), which should be the same as the
+    /// one VASP was compiled with, ignored for the other WAVECARs
+    gamma_half: Axis,
+
+    #[structopt(short, long)]
+    /// Number of workers transforming the wavefunctions, the number of CPU cores if not given
+    jobs: Option<usize>,
+
+    #[structopt(long, default_value = "wav")]
+    /// Prefix of the output file names
+    prefix: String,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Wav3d {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let wavecar_path = global.resolve(&self.wavecar);
+        let poscar_path = global.resolve(&self.poscar);
+        if !self.ngrid.is_empty() && self.ngrid.len() != 3 {
+            warn!("Three numbers are required for the grid size, e.g. \"--ngrid 120 120 240\"");
+            return Ok(());
+        }
+
+        info!("Reading WAVECAR file {:?} ...", &wavecar_path);
+        let wav = wavecar::Wavecar::from_file(&wavecar_path)?.with_gamma_half(self.gamma_half);
+        info!("ISPIN = {}, NKPTS = {}, NBANDS = {}, ENCUT = {} eV, {:?}",
+              wav.nspin, wav.nkpts, wav.nbands, wav.encut, wav.wavetype);
+
+        if wav.wavetype != WavecarType::NonCollinear && self.modes.iter().any(|m| matches!(m, WavMode::Up | WavMode::Down)) {
+            warn!("The spinor-resolved modes are skipped for the collinear WAVECAR.");
+        }
+
+        info!("Reading POSCAR file {:?} ...", &poscar_path);
+        let pos = Structure::from(Poscar::from_path(&poscar_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+        if pos.cell.iter().flatten().zip(wav.cell.iter().flatten()).any(|(a, b)| (a - b).abs() > 1E-4) {
+            warn!("The lattice of POSCAR differs from the one of WAVECAR, the latter is used for the plane waves.");
+        }
+
+        let select = |sel: Option<&String>, n: usize| match sel {
+            Some(s) => RawSelection::parse_iatoms(s, n),
+            None    => (0 .. n).collect(),
+        };
+        let ispins = select(self.spins.as_ref(), wav.nspin);
+        let ikpts = select(self.kpoints.as_ref(), wav.nkpts);
+        let ibands = select(self.bands.as_ref(), wav.nbands);
+        let states = iproduct!(ispins, ikpts, ibands).collect::<Vec<_>>();
+        if states.is_empty() {
+            warn!("No states selected.");
+            return Ok(());
+        }
+
+        let ngrid = if self.ngrid.is_empty() {
+            wav.ngrid.map(|n| n * 2)
+        } else {
+            [self.ngrid[0], self.ngrid[1], self.ngrid[2]]
+        };
+        let nworkers = self.jobs.unwrap_or_else(rayon::current_num_threads);
+        info!("Transforming {} states onto grid {:?} by {} workers ...", states.len(), ngrid, nworkers);
+
+        let save_in = global.resolve(&self.save_in);
+        std::fs::create_dir_all(&save_in)?;
+        let mut chg = ChargeDensity {
+            pos,
+            ngrid,
+            blocks: vec![vec![]],
+        };
+        let ext = if self.cube { "cube" } else { "vasp" };
+        wav.for_each_realspace(&states, ngrid, nworkers, |(is, ik, ib), grids| {
+            for (suffix, values) in self.modes.iter().unique().flat_map(|m| m.values(grids)) {
+                chg.blocks[0] = values;
+                let fname = save_in.join(format!("{}_{}_{}_{}_{}.{}", self.prefix, is + 1, ik + 1, ib + 1, suffix, ext));
+                if self.cube {
+                    chg.save_as_cube(0, &fname)?;
+                } else {
+                    chg.save_as_vasp(&fname)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use rayon::prelude::*;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::selection::RawSelection;
+use crate::wavecar::{
+    self,
+    Axis,
+};
+use crate::wavdiff::{
+    WavecarDiff,
+    WavdiffEntry,
+    WavdiffTable,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Compares the wavefunctions of WAVECARs with a reference one band by band
+///
+/// Useful for convergence tests and validating restarts, e.g.
+/// `rsgrad wavdiff WAVECAR_400 WAVECAR_500 WAVECAR_600 -b 1..40`. The k-points are matched by
+/// their coordinates, thus WAVECARs of different k-meshes are compared on the common k-points.
+/// The plane waves are matched by their Miller indices, thus WAVECARs of different ENCUT can be
+/// compared, and the WAVECARs are compared in parallel.
+///
+/// For each state of the reference, the energy difference, the overlap |<a_n|b_n>|^2 with the
+/// same band, the band overlapping most and the overlap summed over the degenerate bands are
+/// listed, the states changed most first. The last one is close to 1 for converged states even
+/// if the degenerate states are mixed.
+pub struct Wavdiff {
+    /// The reference WAVECAR
+    reference: PathBuf,
+
+    #[structopt(required = true)]
+    /// WAVECARs compared with the reference
+    wavecars: Vec<PathBuf>,
+
+    #[structopt(short, long)]
+    /// Selects the spin channels, all of them if not given
+    spins: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the bands compared, all of them if not given
+    bands: Option<String>,
+
+    #[structopt(long, default_value = "1E-3")]
+    /// Energy tolerance of degenerate states, in eV
+    degen_tol: f64,
+
+    #[structopt(long, default_value = "20")]
+    /// Number of states changed most listed for each WAVECAR, all of them if 0 is given
+    top: usize,
+
+    #[structopt(long, default_value = "x")]
+    /// Half-grid direction of Gamma-only WAVECARs (x, y or z), which should be the same as the
+    /// one VASP was compiled with, ignored for the other WAVECARs
+    gamma_half: Axis,
+}
+
+impl OptProcess for Wavdiff {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let read = |path: &PathBuf| -> io::Result<wavecar::Wavecar> {
+            let path = global.resolve(path);
+            info!("Reading WAVECAR file {:?} ...", &path);
+            Ok(wavecar::Wavecar::from_file(&path)?.with_gamma_half(self.gamma_half))
+        };
+        let reference = read(&self.reference)?;
+
+        let select = |sel: Option<&String>, n: usize| match sel {
+            Some(s) => RawSelection::parse_iatoms(s, n),
+            None    => (0 .. n).collect(),
+        };
+        let ispins = select(self.spins.as_ref(), reference.nspin);
+        let ibands = select(self.bands.as_ref(), reference.nbands);
+
+        let entries = self.wavecars.par_iter()
+            .map(|path| {
+                let mut diff = WavecarDiff::compare(&reference, &read(path)?, &ispins, &ibands, self.degen_tol)?;
+                if self.top > 0 {
+                    diff.states.truncate(self.top);
+                }
+                Ok(WavdiffEntry { wavecar: path.clone(), diff })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        print_formatted(&WavdiffTable(entries), global.output_format)
+    }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::traits::OptProcess;
+use crate::selection::RawSelection;
+use crate::wavecar;
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto)]
+/// Lists the band energies and occupations in WAVECAR
+///
+/// The table is printed line by line without being built in memory, thus it stays fast for the
+/// WAVECARs of thousands of k-points and bands. Spins, k-points and bands are selected by
+/// indices starting from 1, e.g. "1..10 -1", negative indices count reversely.
+pub struct Wavecar {
+    #[structopt(short, long, default_value = "./WAVECAR")]
+    /// Specify the WAVECAR file name
+    wavecar: PathBuf,
+
+    #[structopt(short, long)]
+    /// Selects the spin channels, all of them if not given
+    spins: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the k-points, all of them if not given
+    kpoints: Option<String>,
+
+    #[structopt(short, long)]
+    /// Selects the bands, all of them if not given
+    bands: Option<String>,
+}
+
+impl OptProcess for Wavecar {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        let path = global.resolve(&self.wavecar);
+        info!("Reading WAVECAR file {:?} ...", &path);
+        let wav = wavecar::Wavecar::from_file(&path)?;
+        info!("ISPIN = {}, NKPTS = {}, NBANDS = {}, ENCUT = {} eV, E-fermi = {:.4} eV, precision tag {}",
+              wav.nspin, wav.nkpts, wav.nbands, wav.encut, wav.efermi, wav.tag);
+
+        let select = |sel: Option<&String>, n: usize| match sel {
+            Some(s) => RawSelection::parse_iatoms(s, n),
+            None    => (0 .. n).collect(),
+        };
+        let ispins = select(self.spins.as_ref(), wav.nspin);
+        let ikpts = select(self.kpoints.as_ref(), wav.nkpts);
+        let ibands = select(self.bands.as_ref(), wav.nbands);
+
+        let stdout = io::stdout();
+        let mut w = io::BufWriter::new(stdout.lock());
+        wav.write_eigs(&mut w, &ispins, &ikpts, &ibands, global.output_format)?;
+        w.flush()
+    }
+}
@@ -0,0 +1,149 @@
+use std::io;
+use std::path::PathBuf;
+use log::info;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use vasp_poscar::Poscar;
+use crate::traits::{
+    OptProcess,
+    print_formatted,
+};
+use crate::outcar::Outcar;
+use crate::procar::Procar;
+use crate::format::Structure;
+use crate::selection::RawSelection;
+use crate::xas::{
+    Broadening,
+    Edge,
+    XasSpectrum,
+};
+use super::GlobalOpts;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::ColoredHelp,
+            setting = AppSettings::ColorAuto,
+            setting = AppSettings::AllowNegativeNumbers)]
+/// Approximates XAS or ELNES near-edge shapes by the broadened unoccupied PDOS of absorber atoms
+///
+/// The DOS of empty states projected onto the absorber atoms and the final-state orbitals is
+/// broadened by Lorentzians, whose FWHM grows linearly with the energy above E-fermi to mimic
+/// the shorter lifetimes of higher excited states. The orbitals are chosen by `--orbits`, or by
+/// the dipole selection rule of `--edge`. Core-hole effects are included only if PROCAR comes
+/// from a calculation with the core hole, e.g. ICORELEVEL=2 or a core-excited POTCAR.
+pub struct Xas {
+    #[structopt(long, default_value = "./PROCAR")]
+    /// Specify the PROCAR file name
+    procar: PathBuf,
+
+    #[structopt(short, long, required = true)]
+    /// Absorber atoms, starting from 1, following the syntax of `rsgrad dos --atoms`, e.g. "5"
+    /// or "O z>10"
+    atoms: String,
+
+    #[structopt(short, long, conflicts_with = "edge")]
+    /// Orbitals of the final states, e.g. "p" or "s d", all the orbitals if neither this nor
+    /// `--edge` is given
+    orbits: Option<String>,
+
+    #[structopt(long, possible_values = &["k", "l1", "l23"])]
+    /// Absorption edge, selects p orbitals for K and L1, s and d orbitals for L2,3
+    edge: Option<Edge>,
+
+    #[structopt(long, default_value = "./POSCAR")]
+    /// Specify the POSCAR file name, read if the atoms are selected by element symbols or
+    /// spatial filters
+    poscar: PathBuf,
+
+    #[structopt(long)]
+    /// Specify E-fermi in eV, read from OUTCAR if not given
+    efermi: Option<f64>,
+
+    #[structopt(long, default_value = "-2.0")]
+    /// Lower bound of the energy window relative to E-fermi, in eV
+    emin: f64,
+
+    #[structopt(long, default_value = "30.0")]
+    /// Upper bound of the energy window relative to E-fermi, in eV
+    emax: f64,
+
+    #[structopt(long, default_value = "1000")]
+    /// Number of grid points in the energy window
+    nedos: usize,
+
+    #[structopt(long, default_value = "0.2")]
+    /// Lorentzian FWHM at E-fermi in eV, e.g. the core-hole lifetime broadening
+    gamma0: f64,
+
+    #[structopt(long, default_value = "0.1")]
+    /// Increase of the Lorentzian FWHM per eV above E-fermi
+    gamma_slope: f64,
+
+    #[structopt(long, default_value = "3.0")]
+    /// Maximum Lorentzian FWHM in eV
+    gamma_max: f64,
+
+    #[structopt(long = "no-html")]
+    /// Don't save the spectrum plot in HTML format
+    no_save_html: bool,
+
+    #[structopt(long, default_value = ".")]
+    /// Defines where the files would be saved
+    save_in: PathBuf,
+}
+
+impl OptProcess for Xas {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()> {
+        if self.gamma0 <= 0.0 || self.gamma_slope < 0.0 || self.gamma_max < self.gamma0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "The Lorentzian widths should be positive, and --gamma-max should be no less than --gamma0"));
+        }
+
+        let efermi = match self.efermi {
+            Some(e) => e,
+            None => {
+                let input = global.input_path();
+                info!("Parsing input file {:?} ...", &input);
+                Outcar::from_file(&input)?.efermi
+            },
+        };
+
+        let procar_path = global.resolve(&self.procar);
+        info!("Parsing PROCAR file {:?} ...", &procar_path);
+        let procar = Procar::from_file(&procar_path)?;
+
+        let orbits = match (self.edge, self.orbits.as_ref()) {
+            (Some(edge), _) => edge.orbits().to_string(),
+            (None, Some(orbits)) => orbits.clone(),
+            (None, None) => String::new(),
+        };
+        let label = [self.atoms.trim(), orbits.trim()].iter()
+            .filter(|x| !x.is_empty())
+            .map(|x| x.replace(' ', "_"))
+            .collect::<Vec<_>>()
+            .join("-");
+        let raw = RawSelection::new(&label, &self.atoms, &orbits);
+        let sel = if raw.needs_structure() {
+            let poscar = global.resolve(&self.poscar);
+            info!("Reading POSCAR file {:?} to resolve the atom selections ...", &poscar);
+            let structure = Structure::from(Poscar::from_path(&poscar)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+            raw.parse_in(&structure, &procar.orbitals)
+        } else {
+            raw.parse(procar.nions, &procar.orbitals)
+        };
+
+        let broadening = Broadening {
+            gamma0: self.gamma0,
+            slope: self.gamma_slope,
+            max: self.gamma_max,
+        };
+        let xas = XasSpectrum::from_procar(&procar, efermi, self.emin, self.emax, self.nedos, broadening, &sel);
+        print_formatted(&xas, global.output_format)?;
+        xas.save_as_txt(&self.save_in)?;
+        if !self.no_save_html {
+            xas.save_as_html(&self.save_in)?;
+        }
+        Ok(())
+    }
+}
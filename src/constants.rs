@@ -0,0 +1,28 @@
+// Physical constants and unit conversions shared by the analysis modules, CODATA 2018
+
+
+pub const PLANCK          : f64 = 4.135667696E-15;   // eV s
+pub const HBAR            : f64 = 6.582119569E-16;   // eV s
+pub const BOLTZMANN       : f64 = 8.617333262E-5;    // eV/K
+pub const BOLTZMANN_SI    : f64 = 1.380649E-23;      // J/K
+pub const SPEED_OF_LIGHT  : f64 = 299792458.0;       // m/s
+pub const HC              : f64 = 1239.84198;        // eV nm
+pub const E_CHARGE        : f64 = 1.602176634E-19;   // C
+pub const ELECTRON_MASS   : f64 = 9.1093837015E-31;  // kg
+pub const AMU             : f64 = 1.66053906660E-27; // kg
+pub const AVOGADRO        : f64 = 6.02214076E23;     // 1/mol
+pub const EPSILON0        : f64 = 8.8541878128E-12;  // F/m
+pub const FINE_STRUCTURE  : f64 = 7.2973525693E-3;
+pub const BOHR            : f64 = 0.529177210903;    // Angstrom
+pub const HARTREE         : f64 = 27.211386245988;   // eV
+pub const RYDBERG         : f64 = HARTREE / 2.0;     // eV
+pub const BOHR_MAGNETON   : f64 = 5.7883818060E-5;   // eV/T
+pub const FARADAY         : f64 = 96485.33212;       // C/mol
+
+pub const COULOMB         : f64 = 14.399645;         // e^2 / (4 pi epsilon_0) in eV Angstrom
+pub const HBAR2_ME        : f64 = 7.619964;          // hbar^2 / m_e in eV Angstrom^2
+pub const DEBYE_PER_E_A   : f64 = 4.803204712;       // Debye per e Angstrom
+pub const EV_KJ_MOL       : f64 = 96.48533212;       // kJ/mol per eV
+pub const EV_KCAL_MOL     : f64 = 23.060547830;      // kcal/mol per eV
+pub const EV_PER_A3_TO_GPA: f64 = 160.21766208;      // GPa per eV/Angstrom^3
+pub const AMU_A2_PER_FS2_TO_EV: f64 = 103.642_696_56;  // amu Angstrom^2 / fs^2 in eV
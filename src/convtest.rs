@@ -0,0 +1,295 @@
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::str::FromStr;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    MatX3,
+};
+use crate::batch::BatchRecord;
+use crate::summary::{
+    Summary,
+    _fmax,
+    _read_dynamics,
+};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ConvParam {
+    Encut,
+    Kpoints,
+}
+
+impl FromStr for ConvParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "encut"   => Ok(Self::Encut),
+            "kpoints" => Ok(Self::Kpoints),
+            _ => Err(format!("Invalid convergence parameter '{}', should be encut or kpoints", s)),
+        }
+    }
+}
+
+
+/// Reads the k-point mesh from KPOINTS of Gamma-centered or Monkhorst-Pack automatic mode.
+/// Returns None for the other modes.
+pub fn read_kmesh(path: &(impl AsRef<Path> + ?Sized)) -> Option<[usize; 3]> {
+    let context = fs::read_to_string(path).ok()?;
+    let lines = context.lines().collect::<Vec<_>>();
+    if lines.len() < 4 || lines[1].trim() != "0" { return None; }
+    if !lines[2].trim_start().starts_with(['G', 'g', 'M', 'm']) { return None; }
+
+    let v = lines[3].split_whitespace()
+        .take(3)
+        .map(|x| x.parse::<usize>().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    if v.len() == 3 { Some([v[0], v[1], v[2]]) } else { None }
+}
+
+
+// Final energy and forces of one calculation in the convergence test
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConvRecord {
+    pub encut   : f64,
+    pub kmesh   : Option<[usize; 3]>,  // from KPOINTS, None if not automatic mesh
+    pub nkpts   : i32,                 // irreducible k-points
+    pub nions   : i32,
+    pub toten_z : f64,
+    pub fmax    : f64,
+    #[serde(skip)]
+    pub forces  : MatX3<f64>,
+}
+
+impl Summary for ConvRecord {
+    fn from_outcar(outcar: &Outcar, dir: &Path) -> Self {
+        let it = outcar.ion_iters.last().expect("No ionic steps found in OUTCAR");
+        let dynamics = _read_dynamics(dir, it.forces.len());
+        Self {
+            encut: outcar.encut,
+            kmesh: read_kmesh(&dir.join("KPOINTS")),
+            nkpts: outcar.nkpts,
+            nions: outcar.nions,
+            toten_z: it.toten_z,
+            fmax: _fmax(&it.forces, &dynamics),
+            forces: it.forces.clone(),
+        }
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["encut", "kmesh", "nkpts", "nions", "toten_z", "fmax"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            format!("{:.1}", self.encut),
+            self.kmesh.map(|k| format!("{}x{}x{}", k[0], k[1], k[2])).unwrap_or_default(),
+            self.nkpts.to_string(),
+            self.nions.to_string(),
+            format!("{:.6}", self.toten_z),
+            format!("{:.4}", self.fmax),
+        ]
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConvPoint {
+    pub dir     : PathBuf,
+    pub label   : String,  // e.g. "400.0" or "4x4x4"
+    pub value   : f64,     // ENCUT, or the k-points per reciprocal atom (KPPRA)
+    pub energy  : f64,     // energy(sigma->0) per atom, in eV
+    pub de      : f64,     // difference of energy per atom to the most converged one, in meV
+    pub df      : f64,     // max difference of force components to the most converged one, in eV/A
+}
+
+
+/// Convergence of the energy per atom and forces with respect to ENCUT or k-point density,
+/// where the calculation with the largest parameter is taken as the reference.
+///
+/// The recommended one is the smallest parameter, above which all the calculations are within
+/// the thresholds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConvTest {
+    pub param       : ConvParam,
+    pub ethr        : f64,  // in meV/atom
+    pub fthr        : f64,  // in eV/A
+    pub points      : Vec<ConvPoint>,
+    pub recommended : Option<usize>,  // index in points
+}
+
+impl ConvTest {
+    /// Failed records are skipped.
+    pub fn new(records: &[BatchRecord<ConvRecord>], param: ConvParam, ethr: f64, fthr: f64) -> Self {
+        let mut data = records.iter()
+            .filter_map(|r| r.data.as_ref().map(|d| (r.dir.clone(), d)))
+            .map(|(dir, d)| {
+                let (label, value) = match (param, d.kmesh) {
+                    (ConvParam::Encut, _) => (format!("{:.1}", d.encut), d.encut),
+                    (ConvParam::Kpoints, Some(k)) => (format!("{}x{}x{}", k[0], k[1], k[2]),
+                                                      (k[0] * k[1] * k[2]) as f64 * d.nions as f64),
+                    (ConvParam::Kpoints, None) => (format!("NKPTS={}", d.nkpts), d.nkpts as f64 * d.nions as f64),
+                };
+                (dir, label, value, d)
+            })
+            .collect::<Vec<_>>();
+        data.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+        let (_, _, _, reference) = match data.last() {
+            Some(x) => x,
+            None => return Self { param, ethr, fthr, points: vec![], recommended: None },
+        };
+        let eref = reference.toten_z / reference.nions as f64;
+
+        let points = data.iter()
+            .map(|(dir, label, value, d)| {
+                let energy = d.toten_z / d.nions as f64;
+                let df = if d.forces.len() == reference.forces.len() {
+                    d.forces.iter().zip(reference.forces.iter())
+                        .flat_map(|(f, r)| (0 .. 3).map(move |i| (f[i] - r[i]).abs()))
+                        .fold(0.0f64, f64::max)
+                } else {
+                    f64::NAN
+                };
+                ConvPoint {
+                    dir: dir.clone(),
+                    label: label.clone(),
+                    value: *value,
+                    energy,
+                    de: (energy - eref) * 1000.0,
+                    df,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Scan from the most converged one downwards
+        let is_ok = |p: &ConvPoint| p.de.abs() <= ethr && p.df <= fthr;
+        let recommended = (0 .. points.len())
+            .rev()
+            .take_while(|&i| is_ok(&points[i]))
+            .last();
+
+        Self {
+            param,
+            ethr,
+            fthr,
+            points,
+            recommended,
+        }
+    }
+
+    fn _param_name(&self) -> &'static str {
+        match self.param {
+            ConvParam::Encut   => "ENCUT",
+            ConvParam::Kpoints => "KPPRA",
+        }
+    }
+}
+
+impl Tabular for ConvTest {
+    fn headers(&self) -> Vec<String> {
+        ["dir", "label", "value", "energy", "de", "df", "converged"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.points.iter()
+            .enumerate()
+            .map(|(i, p)| vec![
+                p.dir.display().to_string(),
+                p.label.clone(),
+                format!("{:.1}", p.value),
+                format!("{:.6}", p.energy),
+                format!("{:.3}", p.de),
+                format!("{:.4}", p.df),
+                self.recommended.map(|r| i >= r).unwrap_or(false).to_string(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for ConvTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Thresholds: dE < {} meV/atom, dF < {} eV/A", self.ethr, self.fthr).bright_green())?;
+        writeln!(f, "{}", format!("  {:>12} {:>10}  E/atom(eV)  dE(meV/atom)   dF(eV/A)  Directory", "Label", self._param_name()).bright_green())?;
+        for (i, p) in self.points.iter().enumerate() {
+            let line = format!("  {:>12} {:10.1} {:11.6} {:13.3} {:10.4}  {}",
+                               p.label, p.value, p.energy, p.de, p.df, p.dir.display());
+            if self.recommended == Some(i) {
+                writeln!(f, "{}", line.bright_yellow())?;
+            } else {
+                writeln!(f, "{}", line)?;
+            }
+        }
+
+        match self.recommended.map(|i| &self.points[i]) {
+            Some(p) if self.points.len() > 1 && p.dir != self.points.last().unwrap().dir => {
+                writeln!(f, "{}", format!("# Recommended: {} ({})", p.label, p.dir.display()).bright_yellow())
+            },
+            _ => writeln!(f, "{}", "# Not converged yet, try larger parameters".bright_yellow()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _record(dir: &str, encut: f64, toten_z: f64, fx: f64) -> BatchRecord<ConvRecord> {
+        BatchRecord {
+            dir: PathBuf::from(dir),
+            error: None,
+            data: Some(ConvRecord {
+                encut,
+                kmesh: None,
+                nkpts: 10,
+                nions: 2,
+                toten_z,
+                fmax: fx,
+                forces: vec![[fx, 0.0, 0.0], [-fx, 0.0, 0.0]],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_convtest() {
+        let records = vec![
+            _record("encut_500", 500.0, -10.0000, 0.100),
+            _record("encut_300", 300.0, -9.9000, 0.150),
+            _record("encut_400", 400.0, -9.9990, 0.102),
+            _record("encut_450", 450.0, -9.9995, 0.101),
+            BatchRecord { dir: PathBuf::from("encut_600"), error: Some("failed".to_string()), data: None },
+        ];
+        let conv = ConvTest::new(&records, ConvParam::Encut, 1.0, 0.01);
+        assert_eq!(conv.points.len(), 4);
+        assert_eq!(conv.points[0].label, "300.0");
+        assert!((conv.points[0].de - 50.0).abs() < 1E-8);
+        assert!((conv.points[0].df - 0.05).abs() < 1E-10);
+        assert_eq!(conv.recommended, Some(1));
+        assert_eq!(conv.rows()[1][6], "true");
+        assert_eq!(conv.rows()[0][6], "false");
+
+        // Only the reference itself is within the thresholds
+        let conv = ConvTest::new(&records, ConvParam::Encut, 0.1, 0.01);
+        assert_eq!(conv.recommended, Some(3));
+    }
+
+    #[test]
+    fn test_read_kmesh() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("KPOINTS");
+        fs::write(&path, "Automatic mesh\n0\nGamma\n  4  4  2\n  0  0  0\n").unwrap();
+        assert_eq!(read_kmesh(&path), Some([4, 4, 2]));
+        fs::write(&path, "Fully automatic\n0\nAuto\n  20\n").unwrap();
+        assert_eq!(read_kmesh(&path), None);
+        assert_eq!(read_kmesh(&dir.path().join("NOT_EXIST")), None);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SitePotential {
+    pub index     : usize,        // starts from 1
+    pub symbol    : String,
+    pub potential : f64,          // in eV
+    pub shift     : Option<f64>,  // aligned difference to the reference calculation
+}
+
+
+/// Averaged electrostatic potentials at the ion cores, parsed from the "average (electrostatic)
+/// potential at core" section of OUTCAR.
+///
+/// When compared with a reference calculation, the shift of each site is
+/// `(V - V_ref) - alignment`, which is an initial-state estimation of the core-level shift.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CorePotentials {
+    pub ions      : Vec<SitePotential>,
+    pub alignment : Option<f64>,
+}
+
+impl CorePotentials {
+    /// `symbols` are the element symbols of each ion.
+    pub fn new(symbols: &[String], potentials: &[f64]) -> Self {
+        assert_eq!(symbols.len(), potentials.len(), "Inconsistent ion numbers");
+        let ions = symbols.iter()
+            .zip(potentials.iter())
+            .enumerate()
+            .map(|(i, (symbol, potential))| SitePotential {
+                index: i + 1,
+                symbol: symbol.clone(),
+                potential: *potential,
+                shift: None,
+            })
+            .collect();
+
+        Self {
+            ions,
+            alignment: None,
+        }
+    }
+
+    /// `reference` are the core potentials of the reference calculation, in the same ion order.
+    pub fn with_reference(mut self, reference: &[f64], alignment: f64) -> Self {
+        assert_eq!(self.ions.len(), reference.len(), "Inconsistent ion numbers of the reference calculation");
+        self.ions.iter_mut()
+            .zip(reference.iter())
+            .for_each(|(ion, r)| ion.shift = Some(ion.potential - r - alignment));
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /// Averaged potential difference at the selected bulk-like sites (indices start from 0),
+    /// which are supposed to be unaffected by the defect or surface.
+    pub fn bulk_alignment(potentials: &[f64], reference: &[f64], iatoms: &[usize]) -> f64 {
+        assert!(!iatoms.is_empty(), "No site selected for the alignment");
+        iatoms.iter()
+            .map(|&i| potentials[i] - reference[i])
+            .sum::<f64>() / iatoms.len() as f64
+    }
+}
+
+impl Tabular for CorePotentials {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["index", "symbol", "potential"];
+        if self.alignment.is_some() {
+            ret.push("shift");
+        }
+        ret.into_iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.ions.iter()
+            .map(|ion| {
+                let mut row = vec![
+                    ion.index.to_string(),
+                    ion.symbol.clone(),
+                    format!("{:.4}", ion.potential),
+                ];
+                row.extend(ion.shift.map(|s| format!("{:.4}", s)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for CorePotentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(alignment) = self.alignment {
+            writeln!(f, "{}", format!("# Potential alignment to the reference: {:.4} eV", alignment).bright_green())?;
+            writeln!(f, "{}", "  #Ion Elem  Potential/eV    Shift/eV".bright_green())?;
+        } else {
+            writeln!(f, "{}", "  #Ion Elem  Potential/eV".bright_green())?;
+        }
+
+        for ion in self.ions.iter() {
+            let shift = ion.shift
+                .map(|s| format!("{:12.4}", s).bright_yellow().to_string())
+                .unwrap_or_default();
+            writeln!(f, "  {:4} {:>4} {:13.4}{}", ion.index, ion.symbol, ion.potential, shift)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_potentials() {
+        let symbols = vec!["Se".to_string(), "Se".to_string(), "V".to_string()];
+        let pots = CorePotentials::new(&symbols, &[-60.7404, -60.7404, -48.8189]);
+        assert_eq!(pots.headers(), vec!["index", "symbol", "potential"]);
+        assert_eq!(pots.rows()[2], vec!["3", "V", "-48.8189"]);
+
+        let reference = [-60.7254, -60.7254, -48.8031];
+        let alignment = CorePotentials::bulk_alignment(&[-60.7404, -60.7404, -48.8189], &reference, &[0, 1]);
+        assert!((alignment - (-0.015)).abs() < 1E-10);
+
+        let pots = pots.with_reference(&reference, alignment);
+        assert_eq!(pots.headers().last().unwrap(), "shift");
+        assert_eq!(pots.rows()[0][3], "0.0000");
+        assert_eq!(pots.rows()[2][3], "-0.0008");
+    }
+}
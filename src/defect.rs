@@ -0,0 +1,535 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+use colored::Colorize;
+use log::info;
+use crate::outcar::Outcar;
+use crate::chgcar::ChargeDensity;
+use crate::potalign::PlanarAlignment;
+use crate::fscorr::{
+    makov_payne,
+    FnvCorrection,
+};
+use crate::plot::Plot;
+use crate::traits::Tabular;
+use crate::template::{
+    ConfigTemplate,
+    TemplateContext,
+};
+
+
+// Configuration of `rsgrad defect`, e.g.
+//
+// host   = "host/OUTCAR"
+// locpot = "host/LOCPOT"       # optional, potential alignment is skipped if not given
+// vbm    = 2.345               # valence band maximum of host in eV
+// gap    = 3.2                 # band gap of host in eV
+//
+// [chempots]
+// O = -4.93
+//
+// [[defects]]
+// name    = "V_O"
+// species = { O = -1 }         # numbers of atoms added (positive) or removed (negative)
+// center  = [0.5, 0.5, 0.5]    # fractional coordinates of the defect
+//
+// [[defects.states]]
+// charge = 2
+// outcar = "VO_2/OUTCAR"
+// locpot = "VO_2/LOCPOT"
+//
+// [correction]                 # optional finite-size correction
+// scheme  = "fnv"              # "mp" for Makov-Payne or "fnv" for Freysoldt-Neugebauer-Van de Walle
+// epsilon = 10.0               # dielectric constant
+// beta    = 1.0                # width of Gaussian model charge in Angstrom, for FNV only
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct DefectConfig {
+    pub host     : PathBuf,
+    #[serde(default)]
+    pub locpot   : Option<PathBuf>,
+    pub vbm      : f64,
+    pub gap      : f64,
+    #[serde(default)]
+    pub chempots : BTreeMap<String, f64>,
+    #[serde(default = "DefectConfig::default_axis")]
+    pub axis     : String,  // axis of planar averaging, "a", "b" or "c"
+    #[serde(default = "DefectConfig::default_width")]
+    pub width      : f64,     // width of sampling window in planar averaging, in Angstrom
+    #[serde(default)]
+    pub correction : Option<CorrectionConfig>,
+    pub defects    : Vec<DefectEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct CorrectionConfig {
+    pub scheme  : String,
+    pub epsilon : f64,
+    #[serde(default = "CorrectionConfig::default_beta")]
+    pub beta    : f64,
+}
+
+impl CorrectionConfig {
+    fn default_beta() -> f64 { 1.0 }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct DefectEntry {
+    pub name    : String,
+    #[serde(default)]
+    pub species : BTreeMap<String, i32>,
+    #[serde(default)]
+    pub center  : Option<[f64; 3]>,
+    pub states  : Vec<ChargeStateEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ChargeStateEntry {
+    pub charge     : i32,
+    pub outcar     : PathBuf,
+    #[serde(default)]
+    pub locpot     : Option<PathBuf>,
+    #[serde(default)]
+    pub correction : Option<f64>,  // finite-size correction in eV, overrides the one from [correction]
+}
+
+impl DefectConfig {
+    fn default_axis() -> String { "c".to_string() }
+    fn default_width() -> f64 { 2.0 }
+
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        toml::from_str(&context)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn iaxis(&self) -> usize {
+        match self.axis.as_str() {
+            "a" => 0,
+            "b" => 1,
+            "c" => 2,
+            _   => panic!("Invalid axis '{}', should be 'a', 'b' or 'c'", self.axis),
+        }
+    }
+}
+
+impl ConfigTemplate for DefectConfig {
+    fn template(ctx: &TemplateContext) -> String {
+        let elements = ctx.elements();
+        let element = elements.first().cloned().unwrap_or_else(|| "O".to_string());
+        let chempots = if elements.is_empty() {
+            "O = 0.0\n".to_string()
+        } else {
+            elements.iter().map(|e| format!("{} = 0.0\n", e)).collect::<String>()
+        };
+        format!(r#"# Configuration of `rsgrad defect`
+host   = "host/OUTCAR"
+# locpot = "host/LOCPOT"      # optional, potential alignment is skipped if not given
+vbm    = 0.0                # valence band maximum of host in eV
+gap    = 1.0                # band gap of host in eV
+axis   = "c"                # axis of planar averaging in potential alignment, "a", "b" or "c"
+width  = 2.0                # width of sampling window in planar averaging, in Angstrom
+
+[chempots]                  # chemical potentials in eV of the species added or removed
+{chempots}
+[[defects]]
+name    = "V_{e}"
+species = {{ {e} = -1 }}      # numbers of atoms added (positive) or removed (negative)
+# center  = [0.5, 0.5, 0.5]  # fractional coordinates of the defect, required by FNV correction
+
+[[defects.states]]
+charge = 0
+outcar = "V_{e}_0/OUTCAR"
+# locpot     = "V_{e}_0/LOCPOT"
+# correction = 0.0          # finite-size correction in eV, overrides the one from [correction]
+
+# [correction]              # optional finite-size correction
+# scheme  = "fnv"           # "mp" for Makov-Payne or "fnv" for Freysoldt-Neugebauer-Van de Walle
+# epsilon = 10.0            # dielectric constant
+# beta    = 1.0             # width of Gaussian model charge in Angstrom, for FNV only
+"#, chempots = chempots, e = element)
+    }
+}
+
+
+// Final total energy (sigma -> 0) of the last ionic step
+fn _final_energy(outcar: &Outcar) -> f64 {
+    outcar.ion_iters.last()
+        .expect("No ionic steps found in OUTCAR")
+        .toten_z
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChargeState {
+    pub charge     : i32,
+    pub energy     : f64,  // total energy of defect calculation
+    pub dv         : f64,  // potential alignment, V_defect - V_host
+    pub correction : f64,
+    pub eform      : f64,  // formation energy with E-fermi at VBM
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DefectFormation {
+    pub name   : String,
+    pub states : Vec<ChargeState>,
+}
+
+impl DefectFormation {
+    /// E_f(q) = E_defect - E_host - sum_i n_i mu_i + q (E_vbm + E_F + dV) + E_corr
+    pub fn new(name: &str, host_energy: f64, vbm: f64, species: &BTreeMap<String, i32>,
+               chempots: &BTreeMap<String, f64>, states: &[(i32, f64, f64, f64)]) -> Self {
+        let dmu = species.iter()
+            .map(|(s, n)| {
+                let mu = chempots.get(s)
+                    .unwrap_or_else(|| panic!("Chemical potential of '{}' is required", s));
+                *n as f64 * mu
+            })
+            .sum::<f64>();
+
+        let states = states.iter()
+            .map(|&(charge, energy, dv, correction)| {
+                let q = charge as f64;
+                ChargeState {
+                    charge,
+                    energy,
+                    dv,
+                    correction,
+                    eform: energy - host_energy - dmu + q * (vbm + dv) + correction,
+                }
+            })
+            .collect();
+
+        Self {
+            name: name.to_owned(),
+            states,
+        }
+    }
+
+    /// Formation energy and charge of the most stable state at `efermi` relative to VBM
+    pub fn stable_state(&self, efermi: f64) -> (i32, f64) {
+        self.states.iter()
+            .map(|s| (s.charge, s.eform + s.charge as f64 * efermi))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .expect("No charge states found")
+    }
+
+    /// Thermodynamic transition levels ε(q/q') in [emin, emax] relative to VBM
+    pub fn transition_levels(&self, emin: f64, emax: f64) -> Vec<(i32, i32, f64)> {
+        let mut ret = vec![];
+        let (mut q, _) = self.stable_state(emin);
+        let mut ef = emin;
+
+        loop {
+            let cur = self.states.iter()
+                .filter(|s| s.charge == q)
+                .map(|s| s.eform)
+                .fold(f64::MAX, f64::min);
+
+            // The states with lower charges become favorable as E-fermi increases
+            let next = self.states.iter()
+                .filter(|s| s.charge < q)
+                .map(|s| (s.charge, (s.eform - cur) / (q - s.charge) as f64))
+                .filter(|(_, x)| *x > ef)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+            match next {
+                Some((qn, x)) if x < emax => {
+                    ret.push((q, qn, x));
+                    q = qn;
+                    ef = x;
+                },
+                _ => break,
+            }
+        }
+        ret
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DefectDiagram {
+    pub vbm     : f64,
+    pub gap     : f64,
+    pub defects : Vec<DefectFormation>,
+}
+
+impl DefectDiagram {
+    pub fn from_config(config: &DefectConfig) -> io::Result<Self> {
+        info!("Parsing host OUTCAR file {:?} ...", &config.host);
+        let host_outcar = Outcar::from_file(&config.host)?;
+        let host_energy = _final_energy(&host_outcar);
+        let host_cell = host_outcar.ion_iters.last()
+            .expect("No ionic steps found in OUTCAR")
+            .cell;
+        let host_locpot = match config.locpot.as_ref() {
+            Some(path) => {
+                info!("Parsing host LOCPOT file {:?} ...", path);
+                Some(ChargeDensity::from_file(path)?)
+            },
+            None => None,
+        };
+        let iaxis = config.iaxis();
+
+        let mut defects = vec![];
+        for defect in config.defects.iter() {
+            let mut states = vec![];
+            for state in defect.states.iter() {
+                info!("Parsing OUTCAR file {:?} ...", &state.outcar);
+                let energy = _final_energy(&Outcar::from_file(&state.outcar)?);
+
+                let pa = match (host_locpot.as_ref(), state.locpot.as_ref()) {
+                    (Some(host), Some(path)) => {
+                        let center = defect.center
+                            .unwrap_or_else(|| panic!("Defect center of '{}' is required for potential alignment", defect.name));
+                        info!("Parsing LOCPOT file {:?} ...", path);
+                        let locpot = ChargeDensity::from_file(path)?;
+                        Some(PlanarAlignment::new(host, &locpot, iaxis, center[iaxis], config.width))
+                    },
+                    _ => None,
+                };
+                let mut dv = pa.as_ref().map(|pa| pa.dv).unwrap_or(0.0);
+
+                let q = state.charge as f64;
+                let correction = match (state.correction, config.correction.as_ref()) {
+                    (Some(c), _) => c,
+                    (None, None) => 0.0,
+                    (None, Some(c)) => match c.scheme.as_str() {
+                        "mp" => makov_payne(&host_cell, q, c.epsilon),
+                        "fnv" => {
+                            let pa = pa.as_ref()
+                                .unwrap_or_else(|| panic!("LOCPOTs of host and '{}' are required for FNV correction", defect.name));
+                            let fnv = FnvCorrection::new(pa, &host_cell, q, c.epsilon, c.beta);
+                            // The short range potential replaces the plain potential alignment
+                            dv = fnv.dv;
+                            fnv.elat
+                        },
+                        _ => panic!("Invalid correction scheme '{}', should be 'mp' or 'fnv'", c.scheme),
+                    },
+                };
+                states.push((state.charge, energy, dv, correction));
+            }
+            defects.push(DefectFormation::new(&defect.name, host_energy, config.vbm,
+                                              &defect.species, &config.chempots, &states));
+        }
+
+        Ok(Self {
+            vbm: config.vbm,
+            gap: config.gap,
+            defects,
+        })
+    }
+
+    fn _efermi_grid(&self) -> Vec<f64> {
+        const NPOINTS: usize = 201;
+        (0 .. NPOINTS).map(|i| self.gap * i as f64 / (NPOINTS - 1) as f64).collect()
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "defect.txt")?;
+        info!("Saving formation energies to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        write!(f, "# {:>10}", "E_F/eV")?;
+        for d in self.defects.iter() {
+            write!(f, " {:>14}", d.name)?;
+        }
+        writeln!(f)?;
+        for ef in self._efermi_grid() {
+            write!(f, "  {:10.5}", ef)?;
+            for d in self.defects.iter() {
+                write!(f, " {:14.6}", d.stable_state(ef).1)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "defect.html")?;
+        let efs = self._efermi_grid();
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Defect formation energies",
+                "xaxis": {"title": "E_F - E_VBM (eV)", "range": [0.0, self.gap]},
+                "yaxis": {"title": "Formation energy (eV)"},
+            }));
+        for d in self.defects.iter() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": d.name,
+                "x": efs,
+                "y": efs.iter().map(|ef| d.stable_state(*ef).1).collect::<Vec<f64>>(),
+            }));
+
+            let levels = d.transition_levels(0.0, self.gap);
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "markers+text",
+                "showlegend": false,
+                "x": levels.iter().map(|l| l.2).collect::<Vec<f64>>(),
+                "y": levels.iter().map(|l| d.stable_state(l.2).1).collect::<Vec<f64>>(),
+                "text": levels.iter().map(|l| format!("({:+}/{:+})", l.0, l.1)).collect::<Vec<String>>(),
+                "textposition": "top center",
+            }));
+        }
+        plot.save_html(&fname)
+    }
+}
+
+impl Tabular for DefectDiagram {
+    fn headers(&self) -> Vec<String> {
+        ["defect", "charge", "energy", "dv", "correction", "eform"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.defects.iter()
+            .flat_map(|d| d.states.iter().map(move |s| vec![
+                d.name.clone(),
+                s.charge.to_string(),
+                format!("{:.6}", s.energy),
+                format!("{:.4}", s.dv),
+                format!("{:.4}", s.correction),
+                format!("{:.4}", s.eform),
+            ]))
+            .collect()
+    }
+}
+
+impl fmt::Display for DefectDiagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>12} {:>6} {:>14} {:>10} {:>10} {:>12}",
+                                  "Defect", "Charge", "E_tot/eV", "dV/eV", "E_corr/eV", "E_f(VBM)/eV").bright_green())?;
+        for d in self.defects.iter() {
+            for s in d.states.iter() {
+                writeln!(f, "  {:>12} {:>+6} {:14.6} {:10.4} {:10.4} {}",
+                         d.name, s.charge, s.energy, s.dv, s.correction,
+                         format!("{:12.4}", s.eform).bright_yellow())?;
+            }
+        }
+
+        writeln!(f, "{}", "# Transition levels relative to VBM".bright_green())?;
+        for d in self.defects.iter() {
+            for (q1, q2, x) in d.transition_levels(0.0, self.gap) {
+                writeln!(f, "  {:>12} ({:+}/{:+}) {:10.4} eV", d.name, q1, q2, x)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _generate_defect() -> DefectFormation {
+        let species = [("O".to_string(), -1)].iter().cloned().collect::<BTreeMap<_, _>>();
+        let chempots = [("O".to_string(), -5.0)].iter().cloned().collect::<BTreeMap<_, _>>();
+        // E_f(VBM) = E - (-100) - 5 + q * 1.0, i.e. 0.0, 1.0 and 5.0 for q = +2, +1 and 0
+        let states = [(2, -97.0, 0.0, 0.0), (1, -95.5, 0.0, 0.5), (0, -90.0, 0.0, 0.0)];
+        DefectFormation::new("V_O", -100.0, 1.0, &species, &chempots, &states)
+    }
+
+    #[test]
+    fn test_formation_energy() {
+        let d = _generate_defect();
+        let eforms = d.states.iter().map(|s| s.eform).collect::<Vec<_>>();
+        assert_eq!(eforms, vec![0.0, 1.0, 5.0]);
+        assert_eq!(d.stable_state(0.0), (2, 0.0));
+        assert_eq!(d.stable_state(3.0), (1, 4.0));
+    }
+
+    #[test]
+    fn test_transition_levels() {
+        let d = _generate_defect();
+        assert_eq!(d.transition_levels(0.0, 5.0), vec![(2, 1, 1.0), (1, 0, 4.0)]);
+        assert_eq!(d.transition_levels(0.0, 3.0), vec![(2, 1, 1.0)]);
+    }
+
+    #[test]
+    fn test_defect_config() {
+        let input = r#"
+host = "host/OUTCAR"
+vbm  = 2.0
+gap  = 3.0
+
+[chempots]
+O = -4.93
+
+[[defects]]
+name    = "V_O"
+species = { O = -1 }
+
+[[defects.states]]
+charge = 2
+outcar = "VO_2/OUTCAR"
+correction = 0.3
+
+[[defects.states]]
+charge = 1
+outcar = "VO_1/OUTCAR"
+
+[correction]
+scheme  = "mp"
+epsilon = 9.5
+"#;
+        let config: DefectConfig = toml::from_str(input).unwrap();
+        assert_eq!(config.axis, "c");
+        assert_eq!(config.locpot, None);
+        assert_eq!(config.defects[0].species["O"], -1);
+        assert_eq!(config.defects[0].states[0].charge, 2);
+        assert_eq!(config.defects[0].states[0].correction, Some(0.3));
+        assert_eq!(config.defects[0].states[1].correction, None);
+        assert_eq!(config.correction, Some(CorrectionConfig { scheme: "mp".to_string(), epsilon: 9.5, beta: 1.0 }));
+    }
+
+    #[test]
+    fn test_defect_template() {
+        let ctx = TemplateContext {
+            orbitals: None,
+            symbols: Some(["Zn", "O"].iter().map(|x| x.to_string()).collect()),
+        };
+        let config: DefectConfig = toml::from_str(&DefectConfig::template(&ctx)).unwrap();
+        assert_eq!(config.chempots.len(), 2);
+        assert_eq!(config.defects[0].name, "V_Zn");
+        assert_eq!(config.defects[0].species["Zn"], -1);
+        assert!(toml::from_str::<DefectConfig>(&DefectConfig::template(&TemplateContext::default())).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Chemical potential of 'Zn' is required")]
+    fn test_formation_energy_fail() {
+        let species = [("Zn".to_string(), 1)].iter().cloned().collect::<BTreeMap<_, _>>();
+        DefectFormation::new("Zn_i", -100.0, 1.0, &species, &BTreeMap::new(), &[(0, -98.0, 0.0, 0.0)]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::outcar::Mat33;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::summary::_volume;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum StrainMode {
+    Hydrostatic,
+    Uniaxial(usize),  // index of the strained lattice vector
+}
+
+impl FromStr for StrainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hydrostatic" => Ok(Self::Hydrostatic),
+            "a"           => Ok(Self::Uniaxial(0)),
+            "b"           => Ok(Self::Uniaxial(1)),
+            "c"           => Ok(Self::Uniaxial(2)),
+            _ => Err(format!("Invalid strain mode '{}', should be hydrostatic, a, b or c", s)),
+        }
+    }
+}
+
+impl fmt::Display for StrainMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hydrostatic => write!(f, "hydrostatic"),
+            Self::Uniaxial(i) => write!(f, "uniaxial along {}", ["a", "b", "c"][*i]),
+        }
+    }
+}
+
+impl StrainMode {
+    /// Volumetric strain dV/V0 for hydrostatic mode, or the relative change of the length of
+    /// the lattice vector for uniaxial modes.
+    pub fn strain(&self, cell: &Mat33<f64>, cell0: &Mat33<f64>) -> f64 {
+        let norm = |v: &[f64; 3]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        match self {
+            Self::Hydrostatic => _volume(cell) / _volume(cell0) - 1.0,
+            Self::Uniaxial(i) => norm(&cell[*i]) / norm(&cell0[*i]) - 1.0,
+        }
+    }
+}
+
+
+/// Least squares fit of y = slope * x + intercept.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct LinearFit {
+    pub slope     : f64,
+    pub intercept : f64,
+    pub slope_err : f64,  // standard error of the slope, NaN for two points
+    pub r2        : f64,  // coefficient of determination
+}
+
+impl LinearFit {
+    pub fn fit(xs: &[f64], ys: &[f64]) -> Self {
+        assert_eq!(xs.len(), ys.len(), "Inconsistent number of data points");
+        assert!(xs.len() >= 2, "At least two points are needed for a linear fit");
+        let n = xs.len() as f64;
+        let xm = xs.iter().sum::<f64>() / n;
+        let ym = ys.iter().sum::<f64>() / n;
+        let sxx = xs.iter().map(|x| (x - xm).powi(2)).sum::<f64>();
+        let sxy = xs.iter().zip(ys.iter()).map(|(x, y)| (x - xm) * (y - ym)).sum::<f64>();
+        let syy = ys.iter().map(|y| (y - ym).powi(2)).sum::<f64>();
+        assert!(sxx > 0.0, "The strains should not be all the same");
+
+        let slope = sxy / sxx;
+        let intercept = ym - slope * xm;
+        let ssr = xs.iter().zip(ys.iter())
+            .map(|(x, y)| (y - slope * x - intercept).powi(2))
+            .sum::<f64>();
+        let slope_err = if xs.len() > 2 { (ssr / (n - 2.0) / sxx).sqrt() } else { f64::NAN };
+        let r2 = if syy > 0.0 { 1.0 - ssr / syy } else { 1.0 };
+
+        Self {
+            slope,
+            intercept,
+            slope_err,
+            r2,
+        }
+    }
+}
+
+
+/// Band edges of a series of strained calculations, and the deformation potentials as the
+/// slopes of linear fits of band edges to strains, in eV per unit strain.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeformationPotential {
+    pub mode    : StrainMode,
+    pub strains : Vec<f64>,  // sorted
+    pub vbm     : Vec<f64>,  // in eV, aligned to the reference if any
+    pub cbm     : Vec<f64>,
+    pub fits    : [LinearFit; 3],  // VBM, CBM and gap
+}
+
+impl DeformationPotential {
+    pub fn new(mode: StrainMode, strains: &[f64], vbm: &[f64], cbm: &[f64]) -> Self {
+        let mut data = (0 .. strains.len())
+            .map(|i| (strains[i], vbm[i], cbm[i]))
+            .collect::<Vec<_>>();
+        data.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let strains = data.iter().map(|d| d.0).collect::<Vec<f64>>();
+        let vbm = data.iter().map(|d| d.1).collect::<Vec<f64>>();
+        let cbm = data.iter().map(|d| d.2).collect::<Vec<f64>>();
+        let gap = data.iter().map(|d| d.2 - d.1).collect::<Vec<f64>>();
+
+        let fits = [
+            LinearFit::fit(&strains, &vbm),
+            LinearFit::fit(&strains, &cbm),
+            LinearFit::fit(&strains, &gap),
+        ];
+
+        Self {
+            mode,
+            strains,
+            vbm,
+            cbm,
+            fits,
+        }
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Deformation potentials, {} strain", self.mode),
+                "xaxis": {"title": "Strain"},
+                "yaxis": {"title": "Energy (eV)"},
+            }));
+
+        let (smin, smax) = (self.strains[0], *self.strains.last().unwrap());
+        let gap = self.vbm.iter().zip(self.cbm.iter()).map(|(v, c)| c - v).collect::<Vec<f64>>();
+        for ((name, ys), fit) in EDGES.iter().zip([&self.vbm, &self.cbm, &gap]).zip(self.fits.iter()) {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "markers",
+                "name": name,
+                "x": self.strains,
+                "y": ys,
+            }));
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("{} fit, D = {:.3} eV", name, fit.slope),
+                "x": [smin, smax],
+                "y": [fit.slope * smin + fit.intercept, fit.slope * smax + fit.intercept],
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+const EDGES: [&str; 3] = ["VBM", "CBM", "Gap"];
+
+impl Tabular for DeformationPotential {
+    fn headers(&self) -> Vec<String> {
+        ["edge", "deformation_potential", "error", "intercept", "r2"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        EDGES.iter()
+            .zip(self.fits.iter())
+            .map(|(name, f)| vec![
+                name.to_string(),
+                format!("{:.4}", f.slope),
+                format!("{:.4}", f.slope_err),
+                format!("{:.6}", f.intercept),
+                format!("{:.5}", f.r2),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for DeformationPotential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("#  Strain({})     VBM/eV     CBM/eV     Gap/eV", self.mode).bright_green())?;
+        for ((s, v), c) in self.strains.iter().zip(self.vbm.iter()).zip(self.cbm.iter()) {
+            writeln!(f, "  {:10.5} {:10.4} {:10.4} {:10.4}", s, v, c, c - v)?;
+        }
+
+        writeln!(f, "{}", "# Edge        D/eV     Error/eV   Intercept/eV        R^2".bright_green())?;
+        for (name, fit) in EDGES.iter().zip(self.fits.iter()) {
+            writeln!(f, "  {:<6} {} {:12.4} {:14.6} {:10.5}",
+                     name, format!("{:12.4}", fit.slope).bright_yellow(), fit.slope_err, fit.intercept, fit.r2)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deformation_potential() {
+        let cell0 = [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]];
+        let cell = [[4.04, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]];
+        assert!((StrainMode::Uniaxial(0).strain(&cell, &cell0) - 0.01).abs() < 1E-12);
+        assert!((StrainMode::Hydrostatic.strain(&cell, &cell0) - 0.01).abs() < 1E-12);
+        assert_eq!(StrainMode::Uniaxial(0).strain(&cell, &cell).abs(), 0.0);
+        assert_eq!("c".parse::<StrainMode>(), Ok(StrainMode::Uniaxial(2)));
+
+        // VBM shifts by -2 eV and CBM by -8 eV per unit strain
+        let strains = [0.01, -0.01, 0.0, 0.02];
+        let vbm = strains.iter().map(|s| 1.0 - 2.0 * s).collect::<Vec<f64>>();
+        let cbm = strains.iter().map(|s| 2.0 - 8.0 * s).collect::<Vec<f64>>();
+        let dp = DeformationPotential::new(StrainMode::Hydrostatic, &strains, &vbm, &cbm);
+        assert_eq!(dp.strains, vec![-0.01, 0.0, 0.01, 0.02]);
+        assert!((dp.fits[0].slope + 2.0).abs() < 1E-10);
+        assert!((dp.fits[2].slope + 6.0).abs() < 1E-10);
+        assert!((dp.fits[2].intercept - 1.0).abs() < 1E-10);
+        assert!(dp.fits[1].slope_err < 1E-10);
+        assert!((dp.fits[1].r2 - 1.0).abs() < 1E-10);
+
+        let fit = LinearFit::fit(&[0.0, 1.0, 2.0], &[0.0, 2.0, 1.0]);
+        assert!((fit.slope - 0.5).abs() < 1E-10);
+        assert!((fit.slope_err - (1.5f64 / 2.0).sqrt()).abs() < 1E-10);
+    }
+}
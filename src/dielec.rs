@@ -0,0 +1,206 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    Dielectric,
+    Mat33,
+};
+
+
+pub type Mat36<T> = [[T; 6]; 3];
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+const VOIGT_AXES: [&str; 6] = ["xx", "yy", "zz", "xy", "yz", "zx"];
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BornCharge {
+    pub index   : usize,  // starts from 1
+    pub symbol  : String,
+    pub tensor  : Mat33<f64>,
+    pub average : f64,    // trace / 3
+}
+
+
+/// Static dielectric tensors, Born effective charges and piezoelectric tensors, where the
+/// totals are the sums of electronic and ionic parts. Quantities not found in OUTCAR are None.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DielecReport {
+    pub epsilon       : Option<Mat33<f64>>,
+    pub epsilon_ion   : Option<Mat33<f64>>,
+    pub epsilon_total : Option<Mat33<f64>>,
+    pub piezo         : Option<Mat36<f64>>,  // in C/m^2
+    pub piezo_ion     : Option<Mat36<f64>>,
+    pub piezo_total   : Option<Mat36<f64>>,
+    pub born_charges  : Vec<BornCharge>,
+    pub born_sum      : Option<Mat33<f64>>,  // should vanish by the charge neutrality
+}
+
+impl DielecReport {
+    /// `symbols` are the element symbols of each ion.
+    pub fn new(d: &Dielectric, symbols: &[String]) -> Self {
+        let epsilon_total = match (d.epsilon, d.epsilon_ion) {
+            (Some(e), Some(i)) => Some(_add(&e, &i)),
+            _ => None,
+        };
+        let piezo_total = match (d.piezo, d.piezo_ion) {
+            (Some(e), Some(i)) => Some(_add(&e, &i)),
+            _ => None,
+        };
+
+        let born_charges = d.born_charges.as_ref()
+            .map(|zs| {
+                assert_eq!(zs.len(), symbols.len(), "Inconsistent ion numbers");
+                zs.iter()
+                  .zip(symbols.iter())
+                  .enumerate()
+                  .map(|(i, (z, symbol))| BornCharge {
+                      index: i + 1,
+                      symbol: symbol.clone(),
+                      tensor: *z,
+                      average: (z[0][0] + z[1][1] + z[2][2]) / 3.0,
+                  })
+                  .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let born_sum = d.born_charges.as_ref()
+            .map(|zs| zs.iter().fold([[0.0f64; 3]; 3], |acc, z| _add(&acc, z)));
+
+        Self {
+            epsilon: d.epsilon,
+            epsilon_ion: d.epsilon_ion,
+            epsilon_total,
+            piezo: d.piezo,
+            piezo_ion: d.piezo_ion,
+            piezo_total,
+            born_charges,
+            born_sum,
+        }
+    }
+
+    pub fn from_outcar(outcar: &Outcar) -> Self {
+        let d = outcar.dielectric.as_ref()
+            .expect("Dielectric properties not found in OUTCAR, is it a LEPSILON or LCALCEPS calculation?");
+        let symbols = outcar.ion_types.iter()
+            .zip(outcar.ions_per_type.iter())
+            .flat_map(|(s, n)| vec![s.clone(); *n as usize])
+            .collect::<Vec<_>>();
+        Self::new(d, &symbols)
+    }
+
+    // (name, rows, column names) of the available tensors
+    #[allow(clippy::type_complexity)]
+    fn _tensors(&self) -> Vec<(&'static str, Vec<Vec<f64>>, &'static [&'static str])> {
+        let to_vec = |m: &[[f64; 3]]| m.iter().map(|r| r.to_vec()).collect::<Vec<_>>();
+        let to_vec6 = |m: &[[f64; 6]]| m.iter().map(|r| r.to_vec()).collect::<Vec<_>>();
+
+        let mut ret = vec![];
+        let eps = [("epsilon", &self.epsilon), ("epsilon_ion", &self.epsilon_ion), ("epsilon_total", &self.epsilon_total)];
+        for (name, m) in eps.iter() {
+            if let Some(m) = m { ret.push((*name, to_vec(m), &AXES[..])); }
+        }
+        let piezo = [("piezo", &self.piezo), ("piezo_ion", &self.piezo_ion), ("piezo_total", &self.piezo_total)];
+        for (name, m) in piezo.iter() {
+            if let Some(m) = m { ret.push((*name, to_vec6(m), &VOIGT_AXES[..])); }
+        }
+        ret
+    }
+}
+
+fn _add<const N: usize>(a: &[[f64; N]; 3], b: &[[f64; N]; 3]) -> [[f64; N]; 3] {
+    let mut ret = *a;
+    ret.iter_mut().flat_map(|r| r.iter_mut())
+        .zip(b.iter().flat_map(|r| r.iter()))
+        .for_each(|(x, y)| *x += y);
+    ret
+}
+
+impl Tabular for DielecReport {
+    fn headers(&self) -> Vec<String> {
+        ["quantity", "ion", "i", "j", "value"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    // One component per row
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut ret = vec![];
+        for (name, m, cols) in self._tensors() {
+            for (i, row) in m.iter().enumerate() {
+                for (j, x) in row.iter().enumerate() {
+                    ret.push(vec![name.to_string(), String::new(), AXES[i].to_string(), cols[j].to_string(), format!("{:.5}", x)]);
+                }
+            }
+        }
+        for z in self.born_charges.iter() {
+            for (i, row) in z.tensor.iter().enumerate() {
+                for (j, x) in row.iter().enumerate() {
+                    ret.push(vec!["born".to_string(), z.index.to_string(), AXES[i].to_string(), AXES[j].to_string(), format!("{:.5}", x)]);
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl fmt::Display for DielecReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, m, cols) in self._tensors() {
+            let unit = if name.starts_with("piezo") { " (C/m^2)" } else { "" };
+            writeln!(f, "{}", format!("# {}{}", name, unit).bright_green())?;
+            writeln!(f, "{}", format!("   {}", cols.iter().map(|c| format!("{:>11}", c)).collect::<String>()).bright_green())?;
+            for (i, row) in m.iter().enumerate() {
+                writeln!(f, "  {}{}", AXES[i], row.iter().map(|x| format!("{:11.5}", x)).collect::<String>())?;
+            }
+        }
+
+        if !self.born_charges.is_empty() {
+            writeln!(f, "{}", "# Born effective charges (|e|)".bright_green())?;
+            writeln!(f, "{}", "  #Ion Elem       xx       yy       zz       xy       yz       zx       xz       zy       yx      avg".bright_green())?;
+            for z in self.born_charges.iter() {
+                let t = &z.tensor;
+                let values = [t[0][0], t[1][1], t[2][2], t[0][1], t[1][2], t[2][0], t[0][2], t[2][1], t[1][0]];
+                writeln!(f, "  {:4} {:>4}{}{}", z.index, z.symbol,
+                         values.iter().map(|x| format!("{:9.4}", x)).collect::<String>(),
+                         format!("{:9.4}", z.average).bright_yellow())?;
+            }
+            if let Some(s) = self.born_sum.as_ref() {
+                let values = [s[0][0], s[1][1], s[2][2], s[0][1], s[1][2], s[2][0], s[0][2], s[2][1], s[1][0]];
+                writeln!(f, "  {:>9}{}", "Sum", values.iter().map(|x| format!("{:9.4}", x)).collect::<String>())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dielec_report() {
+        let d = Dielectric {
+            epsilon: Some([[7.0, 0.0, 0.0], [0.0, 7.0, 0.0], [0.0, 0.0, 8.0]]),
+            epsilon_ion: Some([[1.5, 0.0, 0.0], [0.0, 1.5, 0.0], [0.0, 0.0, 2.0]]),
+            born_charges: Some(vec![[[2.5, 0.0, 0.0], [0.0, 2.5, 0.0], [0.0, 0.0, 2.8]],
+                                    [[-2.5, 0.0, 0.0], [0.0, -2.5, 0.0], [0.0, 0.0, -2.7]]]),
+            piezo: Some([[0.0; 6], [0.0; 6], [-0.1, -0.1, 0.5, 0.0, 0.0, 0.0]]),
+            piezo_ion: None,
+        };
+        let symbols = vec!["Zn".to_string(), "O".to_string()];
+        let report = DielecReport::new(&d, &symbols);
+
+        assert_eq!(report.epsilon_total.unwrap()[2][2], 10.0);
+        assert_eq!(report.piezo_total, None);
+        assert!((report.born_charges[0].average - 2.6).abs() < 1E-10);
+        assert!((report.born_sum.unwrap()[2][2] - 0.1).abs() < 1E-10);
+
+        // 3 dielectric tensors, 1 piezoelectric tensor and 2 Born charges
+        let rows = report.rows();
+        assert_eq!(rows.len(), 3 * 9 + 18 + 2 * 9);
+        assert_eq!(rows[3 * 9 + 14], vec!["piezo", "", "z", "zz", "0.50000"]);
+        assert_eq!(rows.last().unwrap(), &vec!["born", "2", "z", "z", "-2.70000"]);
+    }
+}
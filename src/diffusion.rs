@@ -0,0 +1,378 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::format::Trajectory;
+use crate::summary::_volume;
+use crate::constants::{
+    E_CHARGE,
+    BOLTZMANN_SI,
+};
+
+
+// A^2/fs in m^2/s
+const A2_PER_FS_TO_M2_PER_S: f64 = 1E-5;
+
+
+/// Mean squared displacements of a time series of positions for all the lags, averaged over all
+/// the time origins, by the FFT algorithm of Calandrini et al. (O(n log n) instead of O(n^2)).
+pub fn msd(x: &[[f64; 3]]) -> Vec<f64> {
+    let n = x.len();
+    if n == 0 {
+        return vec![];
+    }
+
+    // S1(m) = sum_k (|x_{k+m}|^2 + |x_k|^2) / (n - m), by recursion
+    let d = x.iter().map(|p| p.iter().map(|v| v * v).sum::<f64>()).collect::<Vec<f64>>();
+    let mut q = 2.0 * d.iter().sum::<f64>();
+    let s1 = (0 .. n)
+        .map(|m| {
+            if m > 0 {
+                q -= d[m - 1] + d[n - m];
+            }
+            q / (n - m) as f64
+        })
+        .collect::<Vec<f64>>();
+
+    // S2(m) = sum_k x_k . x_{k+m} / (n - m)
+    let mut s2 = vec![0.0; n];
+    for k in 0 .. 3 {
+        let c = _autocorrelation(&x.iter().map(|p| p[k]).collect::<Vec<f64>>());
+        s2.iter_mut().zip(c).for_each(|(s, c)| *s += c);
+    }
+
+    s1.into_iter()
+        .zip(s2)
+        .enumerate()
+        .map(|(m, (s1, s2))| s1 - 2.0 * s2 / (n - m) as f64)
+        .collect()
+}
+
+
+/// Unnormalized autocorrelation sum_k x_k x_{k+m} for all the lags m, by FFT with zero padding.
+pub(crate) fn _autocorrelation(x: &[f64]) -> Vec<f64> {
+    let n = x.len();
+    let mut planner = FftPlanner::<f64>::new();
+    let fft = planner.plan_fft_forward(2 * n);
+    let ifft = planner.plan_fft_inverse(2 * n);
+    let mut buf = x.iter()
+        .map(|v| Complex::new(*v, 0.0))
+        .chain(std::iter::repeat_n(Complex::new(0.0, 0.0), n))
+        .collect::<Vec<_>>();
+    fft.process(&mut buf);
+    buf.iter_mut().for_each(|c| *c = Complex::new(c.norm_sqr(), 0.0));
+    ifft.process(&mut buf);
+    buf.iter().take(n).map(|c| c.re / (2 * n) as f64).collect()
+}
+
+
+/// Parses the ionic charges like "Li:1 La:3 O:-2".
+pub fn parse_charges(input: &str) -> io::Result<Vec<(String, f64)>> {
+    input.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|x| !x.is_empty())
+        .map(|token| {
+            token.split_once(':')
+                .and_then(|(s, q)| Some((s.to_string(), q.parse::<f64>().ok()?)))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Invalid ionic charge '{}', expected like \"Li:1\"", token)))
+        })
+        .collect()
+}
+
+
+// Slope of the least squares line
+fn _slope(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let sxy = x.iter().zip(y.iter()).map(|(a, b)| (a - mx) * (b - my)).sum::<f64>();
+    let sxx = x.iter().map(|a| (a - mx) * (a - mx)).sum::<f64>();
+    sxy / sxx
+}
+
+// Mean and its standard error of the block estimates, the error is zero for one block
+fn _mean_error(v: &[f64]) -> (f64, f64) {
+    let n = v.len() as f64;
+    let mean = v.iter().sum::<f64>() / n;
+    if v.len() < 2 {
+        return (mean, 0.0);
+    }
+    let var = v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, (var / n).sqrt())
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpeciesDiffusion {
+    pub symbol           : String,
+    pub nions            : usize,
+    pub charge           : f64,
+    pub diffusivity      : f64,   // tracer diffusion coefficient, in cm^2/s
+    pub diffusivity_err  : f64,
+    pub conductivity     : f64,   // Nernst-Einstein contribution, in S/cm
+    pub conductivity_err : f64,
+}
+
+
+/// Ionic conductivity from the unwrapped trajectory of MD. The tracer diffusion coefficients
+/// D = MSD / 6t of the charged species give the Nernst-Einstein conductivity
+/// sigma_NE = e^2 / (V kB T) sum_s N_s q_s^2 D_s, and the MSD of the total ionic displacement
+/// sum_i q_i r_i gives the conductivity with the ion-ion correlations included. The Haven
+/// ratio is sigma_NE / sigma. The trajectory is split into `nblocks` consecutive blocks, each
+/// estimate is averaged over the blocks with its standard error.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Conductivity {
+    pub temperature  : f64,          // in K
+    pub volume       : f64,          // average volume in A^3
+    pub nblocks      : usize,
+    pub species      : Vec<SpeciesDiffusion>,
+    pub sigma_ne     : f64,          // in S/cm
+    pub sigma_ne_err : f64,
+    pub sigma        : f64,          // in S/cm
+    pub sigma_err    : f64,
+    pub haven        : f64,
+    pub haven_err    : f64,
+    pub times        : Vec<f64>,     // lag times in fs
+    pub msd          : Vec<Vec<f64>>,  // [nspecies][ntimes] in A^2, averaged over the blocks
+    pub charge_msd   : Vec<f64>,     // MSD of sum_i q_i r_i in e^2 A^2, averaged over the blocks
+}
+
+impl Conductivity {
+    /// `traj` should be unwrapped, `dt` is the time between frames in fs, the MSD is fitted in
+    /// the range `fit` of lags, in fractions of the half block length.
+    pub fn new(traj: &Trajectory, charges: &[(String, f64)], dt: f64, temperature: f64,
+               nblocks: usize, fit: (f64, f64)) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let first = traj.0.first().ok_or_else(|| err("Empty trajectory".to_string()))?;
+        let symbols = first.symbols();
+        if let Some((s, _)) = charges.iter().find(|(s, _)| !symbols.contains(s)) {
+            return Err(err(format!("Element {} not found in the trajectory, available elements: {:?}", s, first.ion_types)));
+        }
+        if !(0.0 <= fit.0 && fit.0 < fit.1 && fit.1 <= 1.0) {
+            return Err(err(format!("Invalid fitting range {:?}, expected 0 <= start < end <= 1", fit)));
+        }
+
+        let nblocks = nblocks.max(1);
+        let len = traj.0.len() / nblocks;
+        let nlags = len / 2;
+        let (ifit0, ifit1) = ((fit.0 * nlags as f64) as usize, (fit.1 * nlags as f64) as usize);
+        if ifit1 < ifit0 + 2 {
+            return Err(err(format!("Too few frames ({}) for {} blocks to fit the MSD", traj.0.len(), nblocks)));
+        }
+        let times = (0 ..= nlags).map(|m| m as f64 * dt).collect::<Vec<f64>>();
+
+        let volume = traj.0.iter().map(|s| _volume(&s.cell).abs()).sum::<f64>() / traj.0.len() as f64;
+        // sigma in S/cm from the MSD slope in e^2 A^2/fs
+        let prefactor = E_CHARGE * E_CHARGE / (6.0 * volume * 1E-30 * BOLTZMANN_SI * temperature) * A2_PER_FS_TO_M2_PER_S / 100.0;
+
+        let iatoms = charges.iter()
+            .map(|(s, _)| (0 .. symbols.len()).filter(|i| &symbols[*i] == s).collect::<Vec<usize>>())
+            .collect::<Vec<_>>();
+
+        let mut msd_blocks = vec![vec![]; charges.len()];   // [nspecies][nblocks][nlags+1]
+        let mut charge_blocks = vec![];                     // [nblocks][nlags+1]
+        for frames in traj.0.chunks(len).take(nblocks) {
+            for (is, ia) in iatoms.iter().enumerate() {
+                let mut total = vec![0.0; nlags + 1];
+                for i in ia.iter() {
+                    let x = frames.iter().map(|s| s.car_pos[*i]).collect::<Vec<_>>();
+                    total.iter_mut().zip(msd(&x)).for_each(|(t, m)| *t += m);
+                }
+                msd_blocks[is].push(total.into_iter().map(|t| t / ia.len() as f64).collect::<Vec<f64>>());
+            }
+            let dipole = frames.iter()
+                .map(|s| {
+                    let mut d = [0.0; 3];
+                    for ((_, q), ia) in charges.iter().zip(iatoms.iter()) {
+                        for i in ia.iter() {
+                            (0 .. 3).for_each(|k| d[k] += q * s.car_pos[*i][k]);
+                        }
+                    }
+                    d
+                })
+                .collect::<Vec<_>>();
+            charge_blocks.push(msd(&dipole)[..= nlags].to_vec());
+        }
+
+        let slope = |y: &[f64]| _slope(&times[ifit0 ..= ifit1], &y[ifit0 ..= ifit1]);
+        let mut sigma_ne_blocks = vec![0.0; nblocks];
+        let species = charges.iter()
+            .zip(iatoms.iter())
+            .zip(msd_blocks.iter())
+            .map(|(((symbol, q), ia), blocks)| {
+                let d = blocks.iter().map(|m| slope(m) / 6.0 * A2_PER_FS_TO_M2_PER_S * 1E4).collect::<Vec<f64>>();
+                let s = blocks.iter().map(|m| prefactor * ia.len() as f64 * q * q * slope(m)).collect::<Vec<f64>>();
+                sigma_ne_blocks.iter_mut().zip(s.iter()).for_each(|(t, x)| *t += x);
+                let (diffusivity, diffusivity_err) = _mean_error(&d);
+                let (conductivity, conductivity_err) = _mean_error(&s);
+                SpeciesDiffusion {
+                    symbol: symbol.clone(),
+                    nions: ia.len(),
+                    charge: *q,
+                    diffusivity,
+                    diffusivity_err,
+                    conductivity,
+                    conductivity_err,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let sigma_blocks = charge_blocks.iter().map(|m| prefactor * slope(m)).collect::<Vec<f64>>();
+        let haven_blocks = sigma_ne_blocks.iter().zip(sigma_blocks.iter()).map(|(a, b)| a / b).collect::<Vec<f64>>();
+        let (sigma_ne, sigma_ne_err) = _mean_error(&sigma_ne_blocks);
+        let (sigma, sigma_err) = _mean_error(&sigma_blocks);
+        let (haven, haven_err) = _mean_error(&haven_blocks);
+
+        let average = |blocks: &[Vec<f64>]| (0 ..= nlags)
+            .map(|m| blocks.iter().map(|b| b[m]).sum::<f64>() / blocks.len() as f64)
+            .collect::<Vec<f64>>();
+
+        Ok(Self {
+            temperature,
+            volume,
+            nblocks,
+            species,
+            sigma_ne,
+            sigma_ne_err,
+            sigma,
+            sigma_err,
+            haven,
+            haven_err,
+            msd: msd_blocks.iter().map(|b| average(b)).collect(),
+            charge_msd: average(&charge_blocks),
+            times,
+        })
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("MSD at {} K", self.temperature),
+                "xaxis": {"title": "Time (ps)"},
+                "yaxis": {"title": "MSD (A^2)"},
+            }));
+        let x = self.times.iter().map(|t| t / 1000.0).collect::<Vec<f64>>();
+        for (sp, m) in self.species.iter().zip(self.msd.iter()) {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": sp.symbol,
+                "x": x,
+                "y": m,
+            }));
+        }
+        // scaled to be comparable with the tracer MSD, the ratio is the inverse Haven ratio
+        let norm = self.species.iter().map(|s| s.nions as f64 * s.charge * s.charge).sum::<f64>();
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": "charge MSD / sum(q^2)",
+            "x": x,
+            "y": self.charge_msd.iter().map(|m| m / norm).collect::<Vec<f64>>(),
+        }));
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for Conductivity {
+    fn headers(&self) -> Vec<String> {
+        ["symbol", "nions", "charge", "diffusivity", "diffusivity_err", "conductivity", "conductivity_err"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.species.iter()
+            .map(|s| vec![
+                s.symbol.clone(),
+                s.nions.to_string(),
+                s.charge.to_string(),
+                format!("{:.6E}", s.diffusivity),
+                format!("{:.6E}", s.diffusivity_err),
+                format!("{:.6E}", s.conductivity),
+                format!("{:.6E}", s.conductivity_err),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for Conductivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# T = {} K, V = {:.3} A^3, errors from {} blocks",
+                                  self.temperature, self.volume, self.nblocks).bright_green())?;
+        writeln!(f, "{}", format!("  {:>4} {:>5} {:>6} {:>24} {:>24}",
+                                  "Elem", "N", "q", "D/(cm2/s)", "sigma_NE/(S/cm)").bright_green())?;
+        for s in self.species.iter() {
+            writeln!(f, "  {:>4} {:5} {:6.2} {} {:>24}",
+                     s.symbol, s.nions, s.charge,
+                     format!("{:>24}", format!("{:.3E} +/- {:.1E}", s.diffusivity, s.diffusivity_err)).bright_yellow(),
+                     format!("{:.3E} +/- {:.1E}", s.conductivity, s.conductivity_err))?;
+        }
+        writeln!(f, "{}{}", "# Nernst-Einstein sigma/(S/cm): ".bright_green(),
+                 format!("{:.3E} +/- {:.1E}", self.sigma_ne, self.sigma_ne_err).bright_yellow())?;
+        writeln!(f, "{}{}", "# Collective sigma/(S/cm):      ".bright_green(),
+                 format!("{:.3E} +/- {:.1E}", self.sigma, self.sigma_err).bright_yellow())?;
+        writeln!(f, "{}{}", "# Haven ratio:                  ".bright_green(),
+                 format!("{:.3} +/- {:.3}", self.haven, self.haven_err).bright_yellow())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Structure;
+
+    #[test]
+    fn test_msd() {
+        // ballistic motion, MSD = (v t)^2 for all the origins
+        let x = (0 .. 10).map(|i| [0.5 * i as f64, 0.0, 0.0]).collect::<Vec<_>>();
+        let m = msd(&x);
+        for (i, v) in m.iter().enumerate() {
+            assert!((v - 0.25 * (i * i) as f64).abs() < 1E-10);
+        }
+
+        // against the direct average over the time origins
+        let x = (0 .. 7).map(|i| [(i as f64).sin(), (i * i) as f64 * 0.1, 1.0 / (i + 1) as f64]).collect::<Vec<_>>();
+        let m = msd(&x);
+        for lag in 0 .. 7 {
+            let direct = (0 .. 7 - lag)
+                .map(|t| (0 .. 3).map(|k| (x[t + lag][k] - x[t][k]).powi(2)).sum::<f64>())
+                .sum::<f64>() / (7 - lag) as f64;
+            assert!((m[lag] - direct).abs() < 1E-10);
+        }
+    }
+
+    #[test]
+    fn test_conductivity() {
+        // Two cations moving together, the MSD of their total displacement is twice the sum of
+        // the tracer ones, thus the Haven ratio is 0.5
+        let frames = (0 .. 40)
+            .map(|t| {
+                let x = 0.1 * t as f64;
+                Structure {
+                    cell: [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]],
+                    ion_types: vec!["Li".to_string(), "O".to_string()],
+                    ions_per_type: vec![2, 1],
+                    car_pos: vec![[x, 0.0, 0.0], [x, 5.0, 0.0], [0.0, 0.0, 5.0]],
+                    frac_pos: vec![[x / 10.0, 0.0, 0.0], [x / 10.0, 0.5, 0.0], [0.0, 0.0, 0.5]],
+                }
+            })
+            .collect();
+        let traj = Trajectory(frames);
+        let cond = Conductivity::new(&traj, &[("Li".to_string(), 1.0)], 1.0, 300.0, 2, (0.0, 1.0)).unwrap();
+        assert_eq!(cond.species[0].nions, 2);
+        assert_eq!(cond.msd[0].len(), 11);
+        assert!((cond.haven - 0.5).abs() < 1E-8);
+        assert!(cond.haven_err < 1E-8);
+        assert!((cond.volume - 1000.0).abs() < 1E-8);
+
+        assert!(Conductivity::new(&traj, &[("Na".to_string(), 1.0)], 1.0, 300.0, 2, (0.0, 1.0)).is_err());
+        assert_eq!(parse_charges("Li:1 O:-2").unwrap(), vec![("Li".to_string(), 1.0), ("O".to_string(), -2.0)]);
+        assert!(parse_charges("Li").is_err());
+    }
+}
@@ -0,0 +1,1121 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::str::FromStr;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+use colored::Colorize;
+use itertools::Itertools;
+use log::info;
+use crate::procar::Procar;
+use crate::outcar::Mat33;
+use crate::selection::{
+    RawSelection,
+    Selection,
+};
+use crate::pcoop::RawPair;
+use crate::photoion::Atom;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+use crate::template::{
+    ConfigTemplate,
+    TemplateContext,
+};
+use crate::pymatgen::{
+    dos_to_pymatgen,
+    save_pymatgen_json,
+};
+
+
+// Configuration of `rsgrad dos`, all the energies are in eV and relative to E-fermi
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct DosConfig {
+    #[serde(default = "DosConfig::default_procar")]
+    pub procar : PathBuf,
+    #[serde(default = "DosConfig::default_outcar")]
+    pub outcar : PathBuf,
+    #[serde(default)]
+    pub efermi : Option<f64>,  // read from OUTCAR if not given
+    #[serde(default = "DosConfig::default_emin")]
+    pub emin   : f64,
+    #[serde(default = "DosConfig::default_emax")]
+    pub emax   : f64,
+    #[serde(default = "DosConfig::default_nedos")]
+    pub nedos  : usize,
+    #[serde(default = "DosConfig::default_sigma")]
+    pub sigma  : f64,
+    #[serde(default = "DosConfig::default_poscar")]
+    pub poscar : PathBuf,    // read only if the atoms are selected by symbols or spatial filters
+    #[serde(default)]
+    pub pdos   : Vec<RawSelection>,
+    #[serde(default)]
+    pub select : Vec<String>,  // shorthand selections like "Fe:d", appended to pdos
+    #[serde(default)]
+    pub pairs  : Vec<RawPair>,   // atom pairs for the approximate COOP analysis
+    #[serde(default)]
+    pub scissor : f64,           // shift of the states above E-fermi
+    #[serde(default)]
+    pub xps    : Vec<String>,  // photoionization cross sections like "Ti:d=0.0071" for the valence-band XPS
+    #[serde(default)]
+    pub photon_energy : Option<f64>,  // computes the cross sections of "Ti:d" in `xps`
+}
+
+impl DosConfig {
+    fn default_procar() -> PathBuf { PathBuf::from("./PROCAR") }
+    fn default_outcar() -> PathBuf { PathBuf::from("./OUTCAR") }
+    fn default_poscar() -> PathBuf { PathBuf::from("./POSCAR") }
+    fn default_emin() -> f64 { -5.0 }
+    fn default_emax() -> f64 { 5.0 }
+    fn default_nedos() -> usize { 1000 }
+    fn default_sigma() -> f64 { 0.05 }
+
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        toml::from_str(&context)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Selections of `pdos` followed by the shorthand ones of `select`.
+    pub fn selections(&self) -> Vec<RawSelection> {
+        self.pdos.iter()
+            .cloned()
+            .chain(self.select.iter().map(|s| RawSelection::from_shorthand(s)))
+            .collect()
+    }
+
+    pub fn cross_sections(&self) -> Result<Vec<CrossSection>, String> {
+        self.xps.iter().map(|s| s.parse()).collect()
+    }
+}
+
+
+// Photoionization cross section of the shorthand selection, written like "Ti:d=0.0071" in Mb
+// per electron, only the ratios between them matter. Without the value, like "Ti:d", it's
+// calculated for the valence subshell of the element at the photon energy, see
+// `photoion::Atom::cross_section`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossSection {
+    pub selection : RawSelection,
+    pub value     : Option<f64>,
+}
+
+impl CrossSection {
+    /// The given value, or the one calculated at `photon_energy` in eV for the element and the
+    /// angular momentum shared by all the selected atoms and orbitals. `symbols` are the
+    /// element symbols of all the atoms, and `atoms` caches the solved ones.
+    pub fn resolve(&self, selection: &Selection, symbols: &[String], orbitals: &[String],
+                   photon_energy: Option<f64>, atoms: &mut HashMap<String, Atom>) -> Result<f64, String> {
+        if let Some(value) = self.value {
+            return Ok(value);
+        }
+        let label = &self.selection.label;
+        let photon_energy = photon_energy
+            .ok_or_else(|| format!("Cross section of '{}' not given, it's calculated only with the photon energy", label))?;
+
+        let symbol = match selection.iatoms.iter().map(|&i| &symbols[i]).unique().collect::<Vec<_>>()[..] {
+            [symbol] => symbol.clone(),
+            _ => return Err(format!("Atoms of '{}' should be of one element to calculate the cross section", label)),
+        };
+        let l = match selection.iorbits.iter().map(|&i| _angular_momentum(&orbitals[i])).unique().collect::<Vec<_>>()[..] {
+            [Some(l)] => l,
+            _ => return Err(format!("Orbitals of '{}' should be of one angular momentum to calculate the cross section", label)),
+        };
+
+        let atom = match atoms.entry(symbol.clone()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                info!("Solving the atom {} for the photoionization cross sections ...", &symbol);
+                e.insert(Atom::from_symbol(&symbol)?)
+            },
+        };
+        let n = atom.valence_shell(l);
+        let value = atom.cross_section(n, l, photon_energy)
+            .ok_or_else(|| format!("Subshell {}{} of {} is not bound", n, ORBITAL_LABELS[l], &symbol))?;
+        info!("Photoionization cross section of {} {}{} at {} eV: {:.4E} Mb per electron",
+              &symbol, n, ORBITAL_LABELS[l], photon_energy, value);
+        Ok(value)
+    }
+}
+
+const ORBITAL_LABELS: [char; 4] = ['s', 'p', 'd', 'f'];
+
+// Angular momentum of the orbital names of PROCAR, like "px" and "x2-y2"
+fn _angular_momentum(orbital: &str) -> Option<usize> {
+    match orbital {
+        "x2-y2" => Some(2),
+        _ => ORBITAL_LABELS.iter().position(|&c| orbital.starts_with(c)),
+    }
+}
+
+impl FromStr for CrossSection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sel, value) = match s.rsplit_once('=') {
+            Some(x) => x,
+            None => return Ok(Self {
+                selection: RawSelection::from_shorthand(s.trim()),
+                value: None,
+            }),
+        };
+        let value = value.trim().parse::<f64>()
+            .map_err(|_| format!("Invalid cross section '{}', should be like \"Ti:d=0.0071\"", s))?;
+        if value < 0.0 {
+            return Err(format!("Invalid cross section '{}', should be non-negative", s));
+        }
+        Ok(Self {
+            selection: RawSelection::from_shorthand(sel.trim()),
+            value: Some(value),
+        })
+    }
+}
+
+
+impl ConfigTemplate for DosConfig {
+    fn template(ctx: &TemplateContext) -> String {
+        let (atoms, orbits) = ctx.example_selection();
+        format!(r#"# Configuration of `rsgrad dos`, all the energies are in eV and relative to E-fermi
+procar  = "./PROCAR"
+outcar  = "./OUTCAR"    # E-fermi is read from it if `efermi` is not given
+poscar  = "./POSCAR"    # read if the atoms are selected by element symbols or spatial filters
+# efermi = 0.0
+emin    = -5.0
+emax    = 5.0
+nedos   = 1000          # number of grid points in the energy window
+sigma   = 0.05          # Gaussian smearing width
+scissor = 0.0           # shifts the states above E-fermi to correct the band gap
+select  = []            # shorthand selections like "Fe:d" or "O z>10:p", appended to [[pdos]]
+xps     = []            # cross sections like "Ti:d=0.0071" to simulate the valence-band XPS
+# photon_energy = 1486.6  # calculates the cross sections of `xps` without values, like "Ti:d"
+
+# Atoms are selected by indices starting from 1, ranges like "1..4", negative indices,
+# element symbols, or spatial filters like "z>10", "fz<0.5" and "layer:1..2".
+{}
+[[pdos]]
+label  = "{}"
+atoms  = "{}"
+orbits = "{}"
+
+# Atom pairs for the approximate COOP analysis
+# [[pairs]]
+# label   = "A-B"
+# atoms1  = "1"
+# atoms2  = "2"
+# orbits1 = ""
+# orbits2 = ""
+"#, ctx.comments(), [atoms.as_str(), orbits.as_str()].iter().filter(|x| !x.is_empty()).join("-"), atoms, orbits)
+    }
+}
+
+// How the spin channels are plotted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DosPlotMode {
+    Mirror,      // spin down with negative values in the same panel
+    Split,       // spin up and down in separate panels
+    Integrated,  // integrated DOS, i.e. the number of electrons below each energy
+}
+
+impl FromStr for DosPlotMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mirror"     => Ok(Self::Mirror),
+            "split"      => Ok(Self::Split),
+            "integrated" => Ok(Self::Integrated),
+            _ => Err(format!("Invalid DOS plot mode '{}', should be mirror, split or integrated", s)),
+        }
+    }
+}
+
+
+// Total and projected density of states with gaussian smearing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dos {
+    pub efermi   : f64,
+    pub energies : Vec<f64>,                    // relative to E-fermi
+    pub total    : Vec<Vec<f64>>,               // [nspin][nedos]
+    pub pdos     : Vec<(String, Vec<Vec<f64>>)>,  // (label, [nspin][nedos])
+    pub itotal   : Vec<Vec<f64>>,               // integrated from -inf, [nspin][nedos]
+    pub ipdos    : Vec<Vec<Vec<f64>>>,          // integrated from -inf, [nselection][nspin][nedos]
+}
+
+
+// Sum of the projections of one state onto selected atoms and orbitals
+pub(crate) fn _selected_weight(procar: &Procar, icomp: usize, ikpoint: usize, iband: usize, sel: &Selection) -> f64 {
+    let proj = procar.band_projections(icomp, ikpoint, iband);
+    let norbits = procar.norbits();
+    sel.iatoms.iter()
+        .map(|ia| {
+            sel.iorbits.iter()
+               .map(|io| proj[ia * norbits + io])
+               .sum::<f64>()
+        })
+        .sum()
+}
+
+
+// Normalized k-point weights, and the number of electrons each state holds
+pub(crate) fn _state_weights(procar: &Procar) -> (Vec<f64>, f64) {
+    let wsum = procar.weights.iter().sum::<f64>();
+    let weights = procar.weights.iter().map(|w| w / wsum).collect();
+    let factor = if procar.nspin == 1 && !procar.lncl { 2.0 } else { 1.0 };
+    (weights, factor)
+}
+
+
+/// Adaptive smearing widths of each state, in the layout of `Procar::eigvals`. The width is
+/// `a * |dE/dk| * dk` clamped into [`sigma_min`, `sigma_max`], where the band gradient and the
+/// k-point spacing dk are estimated from the nearest k-points including the periodic images,
+/// thus flat bands are sharp and dispersive bands stay smooth. `recip` is the reciprocal lattice
+/// vectors (rows), fractional coordinates are used if None. Bands are matched by their indices.
+pub fn adaptive_sigmas(procar: &Procar, recip: Option<&Mat33<f64>>, a: f64, sigma_min: f64, sigma_max: f64) -> Vec<f64> {
+    assert!(0.0 < sigma_min && sigma_min <= sigma_max, "Invalid bounds of smearing width");
+    let to_cart = |k: [f64; 3]| -> [f64; 3] {
+        match recip {
+            Some(b) => [
+                k[0] * b[0][0] + k[1] * b[1][0] + k[2] * b[2][0],
+                k[0] * b[0][1] + k[1] * b[1][1] + k[2] * b[2][1],
+                k[0] * b[0][2] + k[1] * b[1][2] + k[2] * b[2][2],
+            ],
+            None => k,
+        }
+    };
+
+    // Distances to the other k-points with the minimum image convention
+    let nkpts = procar.nkpts;
+    let dists = (0 .. nkpts)
+        .map(|i| {
+            (0 .. nkpts)
+                .map(|j| {
+                    let d = to_cart([0, 1, 2].map(|x| {
+                        let d = procar.kpoints[j][x] - procar.kpoints[i][x];
+                        d - d.round()
+                    }));
+                    (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+                })
+                .collect::<Vec<f64>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Neighbours are the k-points within 1.5 times the nearest distance
+    let neighbours = dists.iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let dmin = d.iter().enumerate()
+                .filter(|(j, x)| *j != i && **x > 1E-8)
+                .map(|(_, x)| *x)
+                .fold(f64::INFINITY, f64::min);
+            d.iter().enumerate()
+                .filter(|(j, x)| *j != i && **x > 1E-8 && **x < 1.5 * dmin)
+                .map(|(j, x)| (j, *x))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut sigmas = vec![sigma_max; procar.eigvals.len()];
+    for ispin in 0 .. procar.nspin {
+        for ik in 0 .. nkpts {
+            let nb = &neighbours[ik];
+            if nb.is_empty() { continue; }
+            let dk = nb.iter().map(|(_, d)| d).sum::<f64>() / nb.len() as f64;
+            for ib in 0 .. procar.nbands {
+                let e = procar.eigval(ispin, ik, ib);
+                let grad = nb.iter()
+                    .map(|(j, d)| (procar.eigval(ispin, *j, ib) - e).abs() / d)
+                    .fold(0.0, f64::max);
+                sigmas[(ispin * nkpts + ik) * procar.nbands + ib] = (a * grad * dk).clamp(sigma_min, sigma_max);
+            }
+        }
+    }
+    sigmas
+}
+
+
+/// Broadening of the delta functions of states, shared by the DOS, pair overlaps, JDOS and XAS.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Smearing {
+    Gaussian,
+    Lorentzian,
+}
+
+impl FromStr for Smearing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gaussian"   => Ok(Self::Gaussian),
+            "lorentzian" => Ok(Self::Lorentzian),
+            _            => Err(format!("Invalid smearing '{}', should be gaussian or lorentzian", s)),
+        }
+    }
+}
+
+impl Smearing {
+    /// Broadened delta function, with `width` the standard deviation or the half width at half maximum
+    pub fn delta(&self, x: f64, width: f64) -> f64 {
+        match self {
+            Self::Gaussian   => (-0.5 * (x / width).powi(2)).exp() / (width * (2.0 * std::f64::consts::PI).sqrt()),
+            Self::Lorentzian => width / std::f64::consts::PI / (x * x + width * width),
+        }
+    }
+
+    /// States farther than this from a grid point are neglected
+    pub fn cutoff(&self, width: f64) -> f64 {
+        match self {
+            Self::Gaussian   => 5.0 * width,
+            Self::Lorentzian => 50.0 * width,
+        }
+    }
+}
+
+
+// Cumulative distribution function of the standard normal distribution, with the erf
+// approximation of Abramowitz and Stegun 7.1.26, error below 1.5E-7
+fn _gaussian_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-z * z).exp();
+    0.5 * (1.0 + erf.copysign(x))
+}
+
+
+impl Dos {
+    pub fn from_procar(procar: &Procar, efermi: f64, emin: f64, emax: f64,
+                       nedos: usize, sigma: f64, selections: &[Selection]) -> Self {
+        let sigmas = vec![sigma; procar.eigvals.len()];
+        Self::from_procar_with_sigmas(procar, efermi, emin, emax, nedos, &sigmas, selections)
+    }
+
+    /// Same as `from_procar`, with the smearing width of each state given in the layout of
+    /// `Procar::eigvals`, e.g. from `adaptive_sigmas`.
+    pub fn from_procar_with_sigmas(procar: &Procar, efermi: f64, emin: f64, emax: f64,
+                                   nedos: usize, sigmas: &[f64], selections: &[Selection]) -> Self {
+        assert!(emin < emax, "Invalid energy range, emin should be less than emax");
+        assert!(nedos > 1, "NEDOS should be larger than 1");
+        assert_eq!(sigmas.len(), procar.eigvals.len(), "Inconsistent number of smearing widths and states");
+        assert!(sigmas.iter().all(|s| *s > 0.0), "Smearing width should be positive");
+
+        let de = (emax - emin) / (nedos - 1) as f64;
+        let energies = (0 .. nedos).map(|i| emin + de * i as f64).collect::<Vec<f64>>();
+        let (weights, factor) = _state_weights(procar);
+
+        let nspin = procar.nspin;
+        let nsel = selections.len();
+        let mut total = vec![vec![0.0f64; nedos]; nspin];
+        let mut pdos = vec![vec![vec![0.0f64; nedos]; nspin]; nsel];
+        let mut itotal = vec![vec![0.0f64; nedos]; nspin];
+        let mut ipdos = vec![vec![vec![0.0f64; nedos]; nspin]; nsel];
+
+        // States lying 5 sigma below a grid point are fully counted in the integrated DOS, they
+        // are recorded as steps at the first such grid point and accumulated at last.
+        let mut steps = vec![vec![0.0f64; nedos + 1]; nspin];
+        let mut psteps = vec![vec![vec![0.0f64; nedos + 1]; nspin]; nsel];
+
+        for ispin in 0 .. nspin {
+            for (ik, wk) in weights.iter().enumerate() {
+                for ib in 0 .. procar.nbands {
+                    let e = procar.eigval(ispin, ik, ib) - efermi;
+                    let sigma = sigmas[(ispin * procar.nkpts + ik) * procar.nbands + ib];
+                    let cutoff = Smearing::Gaussian.cutoff(sigma);
+                    if e > emax + cutoff { continue; }
+
+                    let w = wk * factor;
+                    let projs = selections.iter()
+                        .map(|sel| _selected_weight(procar, ispin, ik, ib, sel))
+                        .collect::<Vec<f64>>();
+
+                    let ifull = if e < emin - cutoff {
+                        0
+                    } else {
+                        let ibeg = (((e - cutoff - emin) / de).floor().max(0.0)) as usize;
+                        let iend = ((((e + cutoff - emin) / de).ceil()) as usize).min(nedos - 1);
+                        for i in ibeg ..= iend {
+                            let g = w * Smearing::Gaussian.delta(energies[i] - e, sigma);
+                            let c = w * _gaussian_cdf((energies[i] - e) / sigma);
+                            total[ispin][i] += g;
+                            itotal[ispin][i] += c;
+                            for (isel, p) in projs.iter().enumerate() {
+                                pdos[isel][ispin][i] += g * p;
+                                ipdos[isel][ispin][i] += c * p;
+                            }
+                        }
+                        iend + 1
+                    };
+
+                    steps[ispin][ifull] += w;
+                    for (isel, p) in projs.iter().enumerate() {
+                        psteps[isel][ispin][ifull] += w * p;
+                    }
+                }
+            }
+        }
+
+        let accumulate = |curve: &mut Vec<f64>, steps: &[f64]| {
+            let mut acc = 0.0;
+            for (c, s) in curve.iter_mut().zip(steps.iter()) {
+                acc += s;
+                *c += acc;
+            }
+        };
+        for (curve, s) in itotal.iter_mut().zip(steps.iter()) {
+            accumulate(curve, s);
+        }
+        for (curves, ps) in ipdos.iter_mut().zip(psteps.iter()) {
+            for (curve, s) in curves.iter_mut().zip(ps.iter()) {
+                accumulate(curve, s);
+            }
+        }
+
+        Self {
+            efermi,
+            energies,
+            total,
+            pdos: selections.iter()
+                .map(|sel| sel.label.clone())
+                .zip(pdos)
+                .collect(),
+            itotal,
+            ipdos,
+        }
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        self.save_as_named_txt(path, "dos.txt")
+    }
+
+    /// Saves the total DOS as `Dos` of pymatgen in JSON, see `dos_to_pymatgen`.
+    pub fn save_as_pymatgen(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "dos_pymatgen.json")?;
+        save_pymatgen_json(&dos_to_pymatgen(self), &fname)
+    }
+
+    pub fn save_as_named_txt(&self, path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<()> {
+        let fname = _prepare_fname(path, name)?;
+        info!("Saving DOS to {:?} ...", &fname);
+        self._write_columns(&fname, &self.total, self.pdos.iter().map(|(_, p)| p))
+    }
+
+    /// Difference between the total DOS integrated on the grid by the trapezoidal rule and the
+    /// exact integrated DOS, from the lower bound up to E-fermi and summed over the spins. Large
+    /// differences mean the grid is too coarse for the smearing width. None if E-fermi is out of
+    /// the energy window.
+    pub fn grid_integration_error(&self) -> Option<f64> {
+        let i0 = self.energies.iter().rposition(|e| *e <= 0.0)?;
+        if i0 == 0 { return None; }
+        let error = self.total.iter()
+            .zip(self.itotal.iter())
+            .map(|(dos, idos)| {
+                let trapz = (0 .. i0)
+                    .map(|i| 0.5 * (dos[i] + dos[i + 1]) * (self.energies[i + 1] - self.energies[i]))
+                    .sum::<f64>();
+                trapz - (idos[i0] - idos[0])
+            })
+            .sum::<f64>();
+        Some(error.abs())
+    }
+
+    /// Saves the integrated DOS, i.e. the number of electrons below each energy, as idos.txt.
+    pub fn save_integrated_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "idos.txt")?;
+        info!("Saving integrated DOS to {:?} ...", &fname);
+        self._write_columns(&fname, &self.itotal, self.ipdos.iter())
+    }
+
+    fn _write_columns<'a>(&self, fname: &Path, total: &[Vec<f64>],
+                          pdos: impl Iterator<Item = &'a Vec<Vec<f64>>> + Clone) -> io::Result<()> {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(fname)?;
+
+        let nspin = self.total.len();
+        let spin_suffix = |ispin: usize| -> &str {
+            match (nspin, ispin) {
+                (1, _) => "",
+                (_, 0) => "_up",
+                _      => "_dn",
+            }
+        };
+
+        write!(f, "# {:>10}", "E-Ef/eV")?;
+        for ispin in 0 .. nspin {
+            write!(f, " {:>14}", format!("tot{}", spin_suffix(ispin)))?;
+        }
+        for (label, _) in self.pdos.iter() {
+            for ispin in 0 .. nspin {
+                write!(f, " {:>14}", format!("{}{}", label, spin_suffix(ispin)))?;
+            }
+        }
+        writeln!(f)?;
+
+        for (i, e) in self.energies.iter().enumerate() {
+            write!(f, "  {:10.5}", e)?;
+            for t in total.iter() {
+                write!(f, " {:14.6}", t[i])?;
+            }
+            for p in pdos.clone() {
+                for ps in p.iter() {
+                    write!(f, " {:14.6}", ps[i])?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    // Spin down components are plotted with negative values in the mirror mode, and in the
+    // lower panel in the split mode.
+    fn _plot(&self, mode: DosPlotMode) -> Plot {
+        let nspin = self.total.len();
+        let split = mode == DosPlotMode::Split && nspin == 2;
+        let integrated = mode == DosPlotMode::Integrated;
+
+        let ytitle = if integrated { "Integrated DOS (electrons)" } else { "DOS (states/eV)" };
+        let mut layout = json!({
+            "title": if integrated { "Integrated density of states" } else { "Density of states" },
+            "xaxis": {"title": "E-Ef (eV)"},
+            "yaxis": {"title": ytitle},
+            "shapes": [{
+                "type": "line", "xref": "x", "yref": "paper",
+                "x0": 0.0, "x1": 0.0, "y0": 0.0, "y1": 1.0,
+                "line": {"dash": "dash", "color": "gray", "width": 1},
+            }],
+        });
+        if split {
+            layout["yaxis"] = json!({"title": format!("{} down", ytitle), "domain": [0.0, 0.48], "autorange": "reversed"});
+            layout["yaxis2"] = json!({"title": format!("{} up", ytitle), "domain": [0.52, 1.0], "anchor": "x"});
+        }
+        let mut plot = Plot::new().layout(layout);
+
+        let mut add = |name: &str, ispin: usize, y: &[f64], color: Option<&str>| {
+            let sign = if ispin == 1 && mode == DosPlotMode::Mirror { -1.0 } else { 1.0 };
+            let suffix = match (nspin, ispin) {
+                (1, _) => "",
+                (_, 0) => " up",
+                _      => " down",
+            };
+            let mut trace = json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("{}{}", name, suffix),
+                "x": self.energies,
+                "y": y.iter().map(|v| v * sign).collect::<Vec<f64>>(),
+            });
+            if integrated {
+                trace["line"] = json!({"dash": if ispin == 1 { "dash" } else { "solid" }});
+            } else {
+                trace["fill"] = json!("tozeroy");
+            }
+            if let Some(c) = color {
+                trace["line"]["color"] = json!(c);
+            }
+            if split && ispin == 0 {
+                trace["yaxis"] = json!("y2");
+            }
+            plot.add_trace(trace);
+        };
+
+        let (total, pdos) = if integrated {
+            (&self.itotal, self.ipdos.iter().collect::<Vec<_>>())
+        } else {
+            (&self.total, self.pdos.iter().map(|(_, p)| p).collect::<Vec<_>>())
+        };
+        for (ispin, t) in total.iter().enumerate() {
+            add("tot", ispin, t, Some("gray"));
+        }
+        for ((label, _), p) in self.pdos.iter().zip(pdos) {
+            for (ispin, ps) in p.iter().enumerate() {
+                add(label, ispin, ps, None);
+            }
+        }
+
+        plot
+    }
+
+    /// Spin down components are plotted with negative values in the mirror mode, and in the
+    /// lower panel in the split mode.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized), mode: DosPlotMode) -> io::Result<()> {
+        let fname = _prepare_fname(path, "dos.html")?;
+        self._plot(mode).save_html(&fname)
+    }
+
+    /// Saves the plot as a static image, see `Plot::save_image`.
+    pub fn save_as_image(&self, path: &(impl AsRef<Path> + ?Sized), dpi: f64, mode: DosPlotMode) -> io::Result<()> {
+        self._plot(mode).save_image(path, dpi)
+    }
+}
+
+
+/// Valence-band XPS spectrum simulated by the projected DOS weighted by the photoionization
+/// cross sections, summed over spins, with the empty states cut off at E-fermi smoothly by the
+/// smearing width.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XpsSpectrum {
+    pub energies  : Vec<f64>,  // relative to E-fermi, i.e. the negative binding energies
+    pub intensity : Vec<f64>,
+}
+
+impl XpsSpectrum {
+    /// `dos` holds one projection for each of `cross_sections`.
+    pub fn from_dos(dos: &Dos, cross_sections: &[f64], sigma: f64) -> Self {
+        assert_eq!(dos.pdos.len(), cross_sections.len(), "Inconsistent number of projections and cross sections");
+        let intensity = dos.energies.iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let occ = _gaussian_cdf(-e / sigma);
+                dos.pdos.iter()
+                    .zip(cross_sections.iter())
+                    .map(|((_, p), cs)| cs * p.iter().map(|spin| spin[i]).sum::<f64>())
+                    .sum::<f64>() * occ
+            })
+            .collect();
+
+        Self {
+            energies: dos.energies.clone(),
+            intensity,
+        }
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "xps.txt")?;
+        info!("Saving simulated valence-band XPS to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# {:>10} {:>14}", "E_B/eV", "intensity")?;
+        for (e, v) in self.energies.iter().zip(self.intensity.iter()) {
+            writeln!(f, "  {:10.5} {:14.6E}", -e, v)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "xps.html")?;
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Simulated valence-band XPS",
+                "xaxis": {"title": "Binding energy (eV)", "autorange": "reversed"},
+                "yaxis": {"title": "Intensity (arb. units)"},
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": "XPS",
+            "x": self.energies.iter().map(|e| -e).collect::<Vec<f64>>(),
+            "y": self.intensity,
+            "fill": "tozeroy",
+        }));
+        plot.save_html(&fname)
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+// Moments of the projected DOS, evaluated with the eigenvalues directly without smearing
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BandMoments {
+    pub label    : String,
+    pub ispin    : usize,  // starts from 1
+    pub center   : f64,    // first moment, relative to E-fermi
+    pub width    : f64,    // square root of the second central moment
+    pub skewness : f64,
+    pub kurtosis : f64,
+    pub filling  : f64,    // fraction of states below E-fermi
+    pub nstates  : f64,    // number of electrons the selected states can hold
+}
+
+impl BandMoments {
+    /// Only the states lying in [emin, emax] relative to E-fermi are considered.
+    pub fn from_procar(procar: &Procar, efermi: f64, emin: f64, emax: f64, sel: &Selection) -> Vec<Self> {
+        let (weights, factor) = _state_weights(procar);
+
+        (0 .. procar.nspin)
+            .map(|ispin| {
+                let states = (0 .. procar.nkpts)
+                    .flat_map(|ik| (0 .. procar.nbands).map(move |ib| (ik, ib)))
+                    .map(|(ik, ib)| {
+                        let e = procar.eigval(ispin, ik, ib) - efermi;
+                        let w = weights[ik] * factor * _selected_weight(procar, ispin, ik, ib, sel);
+                        (e, w)
+                    })
+                    .filter(|(e, _)| *e >= emin && *e <= emax)
+                    .collect::<Vec<(f64, f64)>>();
+
+                let wsum = states.iter().map(|(_, w)| w).sum::<f64>();
+                let moment = |center: f64, n: i32| -> f64 {
+                    if wsum <= 0.0 { return f64::NAN; }
+                    states.iter().map(|(e, w)| w * (e - center).powi(n)).sum::<f64>() / wsum
+                };
+
+                let center = moment(0.0, 1);
+                let mu2 = moment(center, 2);
+                let mu3 = moment(center, 3);
+                let mu4 = moment(center, 4);
+                let filling = if wsum > 0.0 {
+                    states.iter().filter(|(e, _)| *e <= 0.0).map(|(_, w)| w).sum::<f64>() / wsum
+                } else { f64::NAN };
+
+                Self {
+                    label: sel.label.clone(),
+                    ispin: ispin + 1,
+                    center,
+                    width: mu2.sqrt(),
+                    skewness: mu3 / mu2.powf(1.5),
+                    kurtosis: mu4 / (mu2 * mu2),
+                    filling,
+                    nstates: wsum,
+                }
+            })
+            .collect()
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct BandMomentsTable(pub Vec<BandMoments>);
+
+impl Tabular for BandMomentsTable {
+    fn headers(&self) -> Vec<String> {
+        ["label", "ispin", "center", "width", "skewness", "kurtosis", "filling", "nstates"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|m| vec![
+                m.label.clone(),
+                m.ispin.to_string(),
+                format!("{:.6}", m.center),
+                format!("{:.6}", m.width),
+                format!("{:.6}", m.skewness),
+                format!("{:.6}", m.kurtosis),
+                format!("{:.6}", m.filling),
+                format!("{:.6}", m.nstates),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for BandMomentsTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>12} {:>4} {:>10} {:>9} {:>9} {:>9} {:>8} {:>9}",
+                                  "Label", "Spin", "Center/eV", "Width/eV", "Skewness",
+                                  "Kurtosis", "Filling", "#States").bright_green())?;
+        for m in self.0.iter() {
+            writeln!(f, "  {:>12} {:4} {} {:9.4} {:9.4} {:9.4} {:8.4} {:9.4}",
+                     m.label, m.ispin, format!("{:10.4}", m.center).bright_yellow(),
+                     m.width, m.skewness, m.kurtosis, m.filling, m.nstates)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Number of electrons below E-fermi with the same gaussian smearing as the DOS, i.e. the
+// integrated DOS at E-fermi
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ElectronCount {
+    pub label     : String,
+    pub ispin     : usize,  // starts from 1
+    pub electrons : f64,
+}
+
+impl ElectronCount {
+    /// Counts for the total DOS labelled "tot" and each selection, in every spin channel.
+    pub fn from_procar(procar: &Procar, efermi: f64, sigma: f64, selections: &[Selection]) -> Vec<Self> {
+        let (weights, factor) = _state_weights(procar);
+        let mut ret = vec![];
+
+        for ispin in 0 .. procar.nspin {
+            let mut total = 0.0;
+            let mut projected = vec![0.0; selections.len()];
+            for (ik, wk) in weights.iter().enumerate() {
+                for ib in 0 .. procar.nbands {
+                    let occ = wk * factor * _gaussian_cdf((efermi - procar.eigval(ispin, ik, ib)) / sigma);
+                    total += occ;
+                    for (n, sel) in projected.iter_mut().zip(selections.iter()) {
+                        *n += occ * _selected_weight(procar, ispin, ik, ib, sel);
+                    }
+                }
+            }
+            ret.push(Self { label: "tot".to_string(), ispin: ispin + 1, electrons: total });
+            ret.extend(selections.iter().zip(projected).map(|(sel, n)| {
+                Self { label: sel.label.clone(), ispin: ispin + 1, electrons: n }
+            }));
+        }
+        ret
+    }
+}
+
+
+/// Number of electrons below `efermi` in all the spin channels, with the gaussian smearing.
+pub fn count_electrons(procar: &Procar, efermi: f64, sigma: f64) -> f64 {
+    ElectronCount::from_procar(procar, efermi, sigma, &[])
+        .iter()
+        .map(|c| c.electrons)
+        .sum()
+}
+
+
+/// E-fermi where the electrons below it equal `nelect`, found by bisection. None if there are
+/// not enough states in PROCAR.
+pub fn refine_efermi(procar: &Procar, nelect: f64, sigma: f64) -> Option<f64> {
+    let emin = procar.eigvals.iter().cloned().fold(f64::INFINITY, f64::min) - 10.0 * sigma;
+    let emax = procar.eigvals.iter().cloned().fold(f64::NEG_INFINITY, f64::max) + 10.0 * sigma;
+    if count_electrons(procar, emax, sigma) < nelect - 1E-6 { return None; }
+
+    let (mut lo, mut hi) = (emin, emax);
+    for _ in 0 .. 100 {
+        let mid = 0.5 * (lo + hi);
+        let n = count_electrons(procar, mid, sigma);
+        if (n - nelect).abs() < 1E-6 { return Some(mid); }
+        if n < nelect { lo = mid; } else { hi = mid; }
+    }
+    Some(0.5 * (lo + hi))
+}
+
+
+#[derive(Serialize)]
+pub struct ElectronCountTable(pub Vec<ElectronCount>);
+
+impl Tabular for ElectronCountTable {
+    fn headers(&self) -> Vec<String> {
+        ["label", "ispin", "electrons"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|c| vec![
+                c.label.clone(),
+                c.ispin.to_string(),
+                format!("{:.6}", c.electrons),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for ElectronCountTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>12} {:>4} {:>12}", "Label", "Spin", "#Electrons").bright_green())?;
+        for c in self.0.iter() {
+            writeln!(f, "  {:>12} {:4} {}", c.label, c.ispin, format!("{:12.4}", c.electrons).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_sigmas() {
+        let procar = _generate_procar();
+        // |dE/dk| = 2 and dk = 0.5 in fractional coordinates for both bands
+        let sigmas = adaptive_sigmas(&procar, None, 0.1, 0.01, 0.5);
+        assert!(sigmas.iter().all(|s| (s - 0.1).abs() < 1E-10));
+        let sigmas = adaptive_sigmas(&procar, None, 0.001, 0.01, 0.5);
+        assert!(sigmas.iter().all(|s| (s - 0.01).abs() < 1E-10));
+
+        let sigmas = vec![0.05; procar.eigvals.len()];
+        assert_eq!(Dos::from_procar_with_sigmas(&procar, 0.0, -3.0, 3.0, 601, &sigmas, &[]),
+                   Dos::from_procar(&procar, 0.0, -3.0, 3.0, 601, 0.05, &[]));
+    }
+
+    #[test]
+    fn test_electron_counting() {
+        let procar = _generate_procar();
+        assert!((count_electrons(&procar, 0.0, 0.05) - 2.0).abs() < 1E-6);
+        assert!((count_electrons(&procar, 1.5, 0.05) - 3.0).abs() < 1E-6);
+
+        let efermi = refine_efermi(&procar, 2.5, 0.05).unwrap();
+        assert!((efermi - 1.0).abs() < 1E-4);
+        let efermi = refine_efermi(&procar, 3.0, 0.05).unwrap();
+        assert!(efermi > 1.0 && efermi < 2.0);
+        assert_eq!(refine_efermi(&procar, 5.0, 0.05), None);
+
+        let fine = Dos::from_procar(&procar, 0.0, -3.0, 3.0, 1201, 0.05, &[]);
+        let coarse = Dos::from_procar(&procar, 0.0, -3.0, 3.0, 13, 0.05, &[]);
+        assert!(fine.grid_integration_error().unwrap() < 1E-3);
+        assert!(coarse.grid_integration_error().unwrap() > 0.1);
+    }
+
+    fn _generate_procar() -> Procar {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    2         # of bands:   2         # of ions:   1
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 0.50000000
+
+band     1 # energy   -2.00000000 # occ.  1.00000000
+
+ion      s      d    tot
+    1  0.200  0.800  1.000
+tot    0.200  0.800  1.000
+
+band     2 # energy    1.00000000 # occ.  0.00000000
+
+ion      s      d    tot
+    1  0.500  0.500  1.000
+tot    0.500  0.500  1.000
+
+ k-point     2 :    0.50000000 0.00000000 0.00000000     weight = 0.50000000
+
+band     1 # energy   -1.00000000 # occ.  1.00000000
+
+ion      s      d    tot
+    1  0.200  0.800  1.000
+tot    0.200  0.800  1.000
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s      d    tot
+    1  0.500  0.500  1.000
+tot    0.500  0.500  1.000
+
+"#;
+        Procar::parse(input)
+    }
+
+    #[test]
+    fn test_total_dos_integration() {
+        let procar = _generate_procar();
+        let sels = vec![RawSelection::new("d", "1", "d").parse(procar.nions, &procar.orbitals)];
+        let dos = Dos::from_procar(&procar, 0.0, -6.0, 6.0, 2401, 0.1, &sels);
+        let de = dos.energies[1] - dos.energies[0];
+
+        // Two bands with two electrons for each in non-spin-polarized calculation
+        let ntot = dos.total[0].iter().sum::<f64>() * de;
+        assert!((ntot - 4.0).abs() < 1E-4);
+        let nd = dos.pdos[0].1[0].iter().sum::<f64>() * de;
+        assert!((nd - 2.6).abs() < 1E-4);
+
+        // The integrated DOS counts the electrons, including the states below the window
+        let dos = Dos::from_procar(&procar, 0.0, -1.5, 6.0, 1501, 0.1, &sels);
+        assert!((dos.itotal[0][0] - 1.0).abs() < 1E-6);
+        assert!((dos.itotal[0][300] - 2.0).abs() < 1E-6);
+        assert!((dos.itotal[0][1500] - 4.0).abs() < 1E-6);
+        assert!((dos.ipdos[0][0][1500] - 2.6).abs() < 1E-6);
+
+        let counts = ElectronCount::from_procar(&procar, 0.0, 0.1, &sels);
+        assert_eq!(counts.len(), 2);
+        assert!((counts[0].electrons - 2.0).abs() < 1E-6);
+        assert!((counts[1].electrons - 1.6).abs() < 1E-6);
+    }
+
+    #[test]
+    fn test_xps_spectrum() {
+        let procar = _generate_procar();
+        let cs = ["1:s=2.0".parse::<CrossSection>().unwrap(), "1:d=0.5".parse::<CrossSection>().unwrap()];
+        assert_eq!(cs[1].selection, RawSelection::new("1:d", "1", "d"));
+        assert!("1:d=-1".parse::<CrossSection>().is_err());
+
+        let sels = cs.iter().map(|c| c.selection.parse(procar.nions, &procar.orbitals)).collect::<Vec<_>>();
+
+        // Without the value it's calculated at the photon energy, for one element and one orbital
+        let bare = "1:d".parse::<CrossSection>().unwrap();
+        assert_eq!(bare.value, None);
+        let symbols = vec!["H".to_string()];
+        let mut atoms = HashMap::new();
+        assert_eq!(cs[0].resolve(&sels[0], &symbols, &procar.orbitals, None, &mut atoms), Ok(2.0));
+        assert!(bare.resolve(&sels[1], &symbols, &procar.orbitals, None, &mut atoms).is_err());
+        let mixed = RawSelection::new("1", "1", "").parse(procar.nions, &procar.orbitals);
+        assert!(bare.resolve(&mixed, &symbols, &procar.orbitals, Some(100.0), &mut atoms).is_err());
+        let value = bare.resolve(&sels[1], &symbols, &procar.orbitals, Some(100.0), &mut atoms).unwrap();
+        assert_eq!(Some(value), Atom::new(1).unwrap().cross_section(3, 2, 100.0));
+        assert_eq!(atoms.len(), 1);
+        assert_eq!(_angular_momentum("x2-y2"), Some(2));
+        assert_eq!(_angular_momentum("fxyz"), Some(3));
+        let dos = Dos::from_procar(&procar, 0.0, -6.0, 6.0, 2401, 0.1, &sels);
+        let xps = XpsSpectrum::from_dos(&dos, &[2.0, 0.5], 0.1);
+        let de = dos.energies[1] - dos.energies[0];
+
+        // Only the occupied bands count, 2 electrons each with s 0.2 and d 0.8
+        let total = xps.intensity.iter().sum::<f64>() * de;
+        assert!((total - 2.0 * (2.0 * 0.2 + 0.5 * 0.8)).abs() < 1E-4);
+    }
+
+    #[test]
+    fn test_band_moments() {
+        let procar = _generate_procar();
+        let sel = RawSelection::new("d", "", "d").parse(procar.nions, &procar.orbitals);
+        let m = BandMoments::from_procar(&procar, 0.0, -10.0, 0.0, &sel);
+        assert_eq!(m.len(), 1);
+        assert!((m[0].center - (-1.5)).abs() < 1E-8);
+        assert!((m[0].width - 0.5).abs() < 1E-8);
+        assert!(m[0].skewness.abs() < 1E-8);
+        assert!((m[0].kurtosis - 1.0).abs() < 1E-8);
+        assert!((m[0].filling - 1.0).abs() < 1E-8);
+        assert!((m[0].nstates - 1.6).abs() < 1E-8);
+    }
+
+    #[test]
+    fn test_dos_config() {
+        let input = r#"
+sigma = 0.1
+select = ["O:p", "Ti"]
+
+[[pdos]]
+label  = "Ti-d"
+atoms  = "1..2"
+orbits = "dxy dyz"
+
+[[pairs]]
+label  = "Ti-O"
+atoms1 = "1"
+atoms2 = "3..4"
+"#;
+        let config: DosConfig = toml::from_str(input).unwrap();
+        assert_eq!(config.sigma, 0.1);
+        assert_eq!(config.nedos, 1000);
+        assert_eq!(config.efermi, None);
+        assert_eq!(config.scissor, 0.0);
+        assert!(config.xps.is_empty());
+        assert_eq!(config.pdos, vec![RawSelection::new("Ti-d", "1..2", "dxy dyz")]);
+        assert_eq!(config.selections()[1..], [RawSelection::new("O:p", "O", "p"), RawSelection::new("Ti", "Ti", "")]);
+        assert_eq!(config.pairs.len(), 1);
+        assert_eq!(config.pairs[0].atoms2, "3..4");
+        assert_eq!(config.pairs[0].orbits1, "");
+    }
+
+    #[test]
+    fn test_dos_template() {
+        let ctx = TemplateContext {
+            orbitals: Some(vec!["s".to_string(), "p".to_string(), "d".to_string()]),
+            symbols: Some(vec!["Ti".to_string(), "O".to_string(), "O".to_string()]),
+        };
+        let config: DosConfig = toml::from_str(&DosConfig::template(&ctx)).unwrap();
+        assert_eq!(config.pdos, vec![RawSelection::new("Ti-d", "Ti", "d")]);
+        assert_eq!(config.nedos, 1000);
+
+        let config: DosConfig = toml::from_str(&DosConfig::template(&TemplateContext::default())).unwrap();
+        assert_eq!(config.pdos, vec![RawSelection::new("1", "1", "")]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::Mat33;
+use crate::linalg::Lu;
+
+
+pub type Mat66<T> = [[T; 6]; 6];
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Moduli {
+    pub bulk    : f64,  // in GPa
+    pub shear   : f64,  // in GPa
+    pub young   : f64,  // in GPa
+    pub poisson : f64,
+}
+
+impl Moduli {
+    fn from_bulk_shear(bulk: f64, shear: f64) -> Self {
+        Self {
+            bulk,
+            shear,
+            young: 9.0 * bulk * shear / (3.0 * bulk + shear),
+            poisson: (3.0 * bulk - 2.0 * shear) / (2.0 * (3.0 * bulk + shear)),
+        }
+    }
+}
+
+
+/// Elastic constants in Voigt notation (xx, yy, zz, yz, zx, xy) and GPa, with the
+/// polycrystalline averages of Voigt (uniform strain), Reuss (uniform stress) and Hill
+/// (arithmetic mean of the former two).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ElasticReport {
+    pub stiffness  : Mat66<f64>,
+    pub compliance : Mat66<f64>,  // in 1/GPa
+    pub voigt      : Moduli,
+    pub reuss      : Moduli,
+    pub hill       : Moduli,
+}
+
+impl ElasticReport {
+    /// `moduli` is the "TOTAL ELASTIC MODULI" of OUTCAR, in kBar and the order of
+    /// XX YY ZZ XY YZ ZX.
+    pub fn from_outcar_moduli(moduli: &Mat66<f64>) -> Self {
+        const VOIGT_ORDER: [usize; 6] = [0, 1, 2, 4, 5, 3];
+        let mut c = [[0.0f64; 6]; 6];
+        for i in 0 .. 6 {
+            for j in 0 .. 6 {
+                c[i][j] = moduli[VOIGT_ORDER[i]][VOIGT_ORDER[j]] / 10.0;  // kBar to GPa
+            }
+        }
+        Self::new(c)
+    }
+
+    pub fn new(stiffness: Mat66<f64>) -> Self {
+        let c = &stiffness;
+        let inv = Lu::new(c.iter().map(|r| r.to_vec()).collect())
+            .expect("Singular elastic constants matrix")
+            .inverse();
+        let s: Mat66<f64> = std::array::from_fn(|i| std::array::from_fn(|j| inv[i][j]));
+
+        let kv = ((c[0][0] + c[1][1] + c[2][2]) + 2.0 * (c[0][1] + c[1][2] + c[2][0])) / 9.0;
+        let gv = ((c[0][0] + c[1][1] + c[2][2]) - (c[0][1] + c[1][2] + c[2][0])
+                  + 3.0 * (c[3][3] + c[4][4] + c[5][5])) / 15.0;
+        let kr = 1.0 / ((s[0][0] + s[1][1] + s[2][2]) + 2.0 * (s[0][1] + s[1][2] + s[2][0]));
+        let gr = 15.0 / (4.0 * (s[0][0] + s[1][1] + s[2][2]) - 4.0 * (s[0][1] + s[1][2] + s[2][0])
+                         + 3.0 * (s[3][3] + s[4][4] + s[5][5]));
+
+        Self {
+            stiffness,
+            compliance: s,
+            voigt: Moduli::from_bulk_shear(kv, gv),
+            reuss: Moduli::from_bulk_shear(kr, gr),
+            hill: Moduli::from_bulk_shear((kv + kr) / 2.0, (gv + gr) / 2.0),
+        }
+    }
+}
+
+impl Tabular for ElasticReport {
+    fn headers(&self) -> Vec<String> {
+        ["modulus", "voigt", "reuss", "hill"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let all = [&self.voigt, &self.reuss, &self.hill];
+        let row = |name: &str, f: &dyn Fn(&Moduli) -> f64| -> Vec<String> {
+            let mut ret = vec![name.to_string()];
+            ret.extend(all.iter().map(|m| format!("{:.4}", f(m))));
+            ret
+        };
+        vec![
+            row("bulk", &|m| m.bulk),
+            row("shear", &|m| m.shear),
+            row("young", &|m| m.young),
+            row("poisson", &|m| m.poisson),
+        ]
+    }
+}
+
+impl fmt::Display for ElasticReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Elastic constants Cij/GPa".bright_green())?;
+        for row in self.stiffness.iter() {
+            writeln!(f, "{}", row.iter().map(|x| format!("{:11.3}", x)).collect::<String>())?;
+        }
+
+        writeln!(f, "{}", "# Modulus         Voigt      Reuss       Hill".bright_green())?;
+        for row in self.rows() {
+            writeln!(f, "  {:<8} {:>10} {:>10} {}", row[0], row[1], row[2], format!("{:>10}", row[3]).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Symmetric strain tensor from the Voigt vector (xx, yy, zz, yz, zx, xy), the shear components
+/// are engineering strains, i.e. twice the tensor components.
+pub fn strain_from_voigt(v: &[f64; 6]) -> Mat33<f64> {
+    [[v[0],       v[5] / 2.0, v[4] / 2.0],
+     [v[5] / 2.0, v[1],       v[3] / 2.0],
+     [v[4] / 2.0, v[3] / 2.0, v[2]      ]]
+}
+
+
+/// Deforms the lattice vectors (rows of `cell`) by the strain tensor, a' = (I + e) a.
+pub fn apply_strain(cell: &Mat33<f64>, strain: &Mat33<f64>) -> Mat33<f64> {
+    let mut ret = *cell;
+    for (r, a) in ret.iter_mut().zip(cell.iter()) {
+        for (i, x) in r.iter_mut().enumerate() {
+            *x += (0 .. 3).map(|j| strain[i][j] * a[j]).sum::<f64>();
+        }
+    }
+    ret
+}
+
+
+// One strained cell for the finite-difference fitting of elastic constants
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Deformation {
+    pub label : String,    // e.g. "e4_-0.0100", used in the file names
+    pub voigt : [f64; 6],
+}
+
+impl Deformation {
+    pub fn new(voigt: [f64; 6]) -> Self {
+        let label = match voigt.iter().filter(|x| **x != 0.0).count() {
+            0 => "e0".to_string(),
+            1 => {
+                let i = voigt.iter().position(|x| *x != 0.0).unwrap();
+                format!("e{}_{:+.4}", i + 1, voigt[i])
+            },
+            _ => format!("e_{}", voigt.iter().map(|x| format!("{:+.4}", x)).collect::<Vec<_>>().join("_")),
+        };
+        Self { label, voigt }
+    }
+
+    /// The standard set of deformations, each Voigt component strained by each of the
+    /// magnitudes in both directions separately. The stress-strain relations of the 6
+    /// components give all the 21 independent elastic constants.
+    pub fn standard_set(magnitudes: &[f64]) -> Vec<Self> {
+        (0 .. 6)
+            .flat_map(|i| {
+                magnitudes.iter()
+                    .flat_map(|m| [-m.abs(), m.abs()])
+                    .map(move |x| {
+                        let mut voigt = [0.0; 6];
+                        voigt[i] = x;
+                        Self::new(voigt)
+                    })
+            })
+            .collect()
+    }
+
+    pub fn strain(&self) -> Mat33<f64> {
+        strain_from_voigt(&self.voigt)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TOTAL ELASTIC MODULI of a cubic crystal, in kBar
+    fn _generate_moduli() -> Mat66<f64> {
+        let (c11, c12, c44) = (2645.5461, 1081.0808, 759.3497);
+        [[c11, c12, c12, 0.0, 0.0, 0.0],
+         [c12, c11, c12, 0.0, 0.0, 0.0],
+         [c12, c12, c11, 0.0, 0.0, 0.0],
+         [0.0, 0.0, 0.0, c44, 0.0, 0.0],
+         [0.0, 0.0, 0.0, 0.0, c44, 0.0],
+         [0.0, 0.0, 0.0, 0.0, 0.0, c44]]
+    }
+
+    #[test]
+    fn test_compliance() {
+        let m = _generate_moduli();
+        let inv = ElasticReport::new(m).compliance;
+        for (i, row) in m.iter().enumerate() {
+            for j in 0 .. 6 {
+                let x = row.iter().zip(inv.iter()).map(|(a, b)| a * b[j]).sum::<f64>();
+                assert!((x - if i == j { 1.0 } else { 0.0 }).abs() < 1E-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_elastic_report() {
+        let report = ElasticReport::from_outcar_moduli(&_generate_moduli());
+        let (c11, c12, c44) = (264.55461, 108.10808, 75.93497);
+        assert!((report.stiffness[3][3] - c44).abs() < 1E-10);
+
+        // Voigt and Reuss bulk moduli coincide for cubic crystals
+        let k = (c11 + 2.0 * c12) / 3.0;
+        assert!((report.voigt.bulk - k).abs() < 1E-8);
+        assert!((report.reuss.bulk - k).abs() < 1E-8);
+
+        let gv = (c11 - c12 + 3.0 * c44) / 5.0;
+        let gr = 5.0 * (c11 - c12) * c44 / (4.0 * c44 + 3.0 * (c11 - c12));
+        assert!((report.voigt.shear - gv).abs() < 1E-8);
+        assert!((report.reuss.shear - gr).abs() < 1E-8);
+        assert!((report.hill.shear - (gv + gr) / 2.0).abs() < 1E-8);
+
+        let m = &report.hill;
+        assert!((m.young / (2.0 * m.shear) - 1.0 - m.poisson).abs() < 1E-10);
+        assert_eq!(report.rows().len(), 4);
+    }
+
+    #[test]
+    fn test_deformations() {
+        let cell = [[3.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 5.0]];
+        let strained = apply_strain(&cell, &strain_from_voigt(&[0.01, 0.0, 0.0, 0.02, 0.0, 0.0]));
+        assert_eq!(strained[0], [3.03, 0.0, 0.0]);
+        assert_eq!(strained[1], [0.0, 4.0, 0.04]);
+        assert_eq!(strained[2], [0.0, 0.05, 5.0]);
+
+        let set = Deformation::standard_set(&[0.005, 0.01]);
+        assert_eq!(set.len(), 24);
+        assert_eq!(set[0].label, "e1_-0.0050");
+        assert_eq!(set[23].label, "e6_+0.0100");
+        assert_eq!(set[23].strain()[0][1], 0.005);
+        assert_eq!(Deformation::new([0.01, 0.01, 0.0, 0.0, 0.0, 0.0]).label,
+                   "e_+0.0100_+0.0100_+0.0000_+0.0000_+0.0000_+0.0000");
+    }
+}
@@ -0,0 +1,354 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::constants::EV_PER_A3_TO_GPA;
+use crate::linalg::solve;
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum EosKind {
+    BirchMurnaghan,
+    Murnaghan,
+    Vinet,
+}
+
+impl FromStr for EosKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bm" | "birch-murnaghan" => Ok(Self::BirchMurnaghan),
+            "murnaghan"              => Ok(Self::Murnaghan),
+            "vinet"                  => Ok(Self::Vinet),
+            _ => Err(format!("Invalid equation of state '{}', should be bm, murnaghan or vinet", s)),
+        }
+    }
+}
+
+impl fmt::Display for EosKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::BirchMurnaghan => "Birch-Murnaghan",
+            Self::Murnaghan      => "Murnaghan",
+            Self::Vinet          => "Vinet",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl EosKind {
+    pub const ALL: [EosKind; 3] = [Self::BirchMurnaghan, Self::Murnaghan, Self::Vinet];
+
+    /// `params` are [E0 (eV), V0 (A^3), B0 (eV/A^3), B0'].
+    pub fn energy(&self, params: &[f64; 4], v: f64) -> f64 {
+        let [e0, v0, b0, b0p] = *params;
+        match self {
+            Self::BirchMurnaghan => {
+                // Third order Birch-Murnaghan
+                let eta2 = (v0 / v).powf(2.0 / 3.0);
+                e0 + 9.0 * v0 * b0 / 16.0 * ((eta2 - 1.0).powi(3) * b0p + (eta2 - 1.0).powi(2) * (6.0 - 4.0 * eta2))
+            },
+            Self::Murnaghan => {
+                e0 + b0 * v / b0p * ((v0 / v).powf(b0p) / (b0p - 1.0) + 1.0) - v0 * b0 / (b0p - 1.0)
+            },
+            Self::Vinet => {
+                let x = (v / v0).cbrt();
+                e0 + 2.0 * b0 * v0 / (b0p - 1.0).powi(2)
+                    * (2.0 - (5.0 + 3.0 * b0p * (x - 1.0) - 3.0 * x) * (-1.5 * (b0p - 1.0) * (x - 1.0)).exp())
+            },
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EosFit {
+    pub kind : EosKind,
+    pub e0   : f64,  // in eV
+    pub v0   : f64,  // in A^3
+    pub b0   : f64,  // in GPa
+    pub b0p  : f64,  // dB/dP at V0
+    pub rms  : f64,  // root mean square of the residuals, in eV
+}
+
+impl EosFit {
+    /// Least squares fitting by Levenberg-Marquardt algorithm, the initial guess comes from
+    /// a parabolic fitting. At least 4 points are required.
+    pub fn fit(kind: EosKind, volumes: &[f64], energies: &[f64]) -> Self {
+        assert_eq!(volumes.len(), energies.len(), "Inconsistent numbers of volumes and energies");
+        assert!(volumes.len() >= 4, "At least 4 points are required to fit the equation of state");
+
+        let cost = |p: &[f64; 4]| -> f64 {
+            volumes.iter().zip(energies.iter())
+                .map(|(v, e)| (kind.energy(p, *v) - e).powi(2))
+                .sum()
+        };
+
+        let mut p = _initial_guess(volumes, energies);
+        let mut c = cost(&p);
+        let mut lambda = 1E-3;
+
+        for _ in 0 .. 1000 {
+            // Jacobian by central differences
+            let jac = volumes.iter()
+                .map(|v| {
+                    let mut row = [0.0f64; 4];
+                    for (k, d) in row.iter_mut().enumerate() {
+                        let h = 1E-6 * p[k].abs().max(1E-3);
+                        let (mut pp, mut pm) = (p, p);
+                        pp[k] += h;
+                        pm[k] -= h;
+                        *d = (kind.energy(&pp, *v) - kind.energy(&pm, *v)) / (2.0 * h);
+                    }
+                    row
+                })
+                .collect::<Vec<_>>();
+            let res = volumes.iter().zip(energies.iter())
+                .map(|(v, e)| kind.energy(&p, *v) - e)
+                .collect::<Vec<f64>>();
+
+            let mut jtj = [[0.0f64; 4]; 4];
+            let mut jtr = [0.0f64; 4];
+            for (row, r) in jac.iter().zip(res.iter()) {
+                for i in 0 .. 4 {
+                    jtr[i] -= row[i] * r;
+                    for j in 0 .. 4 {
+                        jtj[i][j] += row[i] * row[j];
+                    }
+                }
+            }
+
+            let converged;
+            loop {
+                let mut a = jtj;
+                (0 .. 4).for_each(|i| a[i][i] *= 1.0 + lambda);
+                let trial = solve(a.iter().map(|r| r.to_vec()).collect(), &jtr)
+                    .filter(|d| d.iter().all(|x| x.is_finite()))
+                    .map(|d| [p[0] + d[0], p[1] + d[1], p[2] + d[2], p[3] + d[3]]);
+                let ctrial = trial.map(|t| cost(&t)).filter(|x| x.is_finite());
+
+                match (trial, ctrial) {
+                    (Some(t), Some(ct)) if ct <= c => {
+                        converged = c - ct <= 1E-14 * c.max(1E-30);
+                        p = t;
+                        c = ct;
+                        lambda = (lambda / 10.0).max(1E-12);
+                        break;
+                    },
+                    _ => {
+                        lambda *= 10.0;
+                        if lambda > 1E12 { converged = true; break; }
+                    },
+                }
+            }
+            if converged { break; }
+        }
+
+        Self {
+            kind,
+            e0: p[0],
+            v0: p[1],
+            b0: p[2] * EV_PER_A3_TO_GPA,
+            b0p: p[3],
+            rms: (c / volumes.len() as f64).sqrt(),
+        }
+    }
+
+    pub fn params(&self) -> [f64; 4] {
+        [self.e0, self.v0, self.b0 / EV_PER_A3_TO_GPA, self.b0p]
+    }
+
+    pub fn energy(&self, v: f64) -> f64 {
+        self.kind.energy(&self.params(), v)
+    }
+}
+
+
+// E = a*V^2 + b*V + c, then V0 = -b/2a and B0 = V0 * d2E/dV2 = 2a*V0
+fn _initial_guess(volumes: &[f64], energies: &[f64]) -> [f64; 4] {
+    let n = volumes.len() as f64;
+    let sum = |f: &dyn Fn(f64, f64) -> f64| volumes.iter().zip(energies.iter()).map(|(v, e)| f(*v, *e)).sum::<f64>();
+    let vmean = sum(&|v, _| v) / n;
+
+    // Centered volumes for better conditioning
+    let s = |k: i32| sum(&|v, _| (v - vmean).powi(k));
+    let se = |k: i32| sum(&|v, e| (v - vmean).powi(k) * e);
+    let a = [[s(4), s(3), s(2)],
+             [s(3), s(2), s(1)],
+             [s(2), s(1), n]];
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let rhs = [se(2), se(1), se(0)];
+    let d = det(&a);
+    let coef = (0 .. 3)
+        .map(|k| {
+            let mut m = a;
+            (0 .. 3).for_each(|i| m[i][k] = rhs[i]);
+            det(&m) / d
+        })
+        .collect::<Vec<f64>>();
+
+    let (qa, qb, qc) = (coef[0], coef[1], coef[2]);
+    let (vmin, vmax) = volumes.iter().fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+    let (v0, b0) = if qa > 0.0 {
+        let x0 = -qb / (2.0 * qa);
+        ((x0 + vmean).clamp(vmin, vmax), 2.0 * qa * (x0 + vmean).clamp(vmin, vmax))
+    } else {
+        (vmean, 0.5)
+    };
+    let x0 = v0 - vmean;
+    [qa * x0 * x0 + qb * x0 + qc, v0, b0, 4.0]
+}
+
+
+/// Energy-volume data and the fitted equations of state.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EosReport {
+    pub volumes  : Vec<f64>,  // in A^3, sorted
+    pub energies : Vec<f64>,  // in eV
+    pub fits     : Vec<EosFit>,
+}
+
+impl EosReport {
+    pub fn new(volumes: &[f64], energies: &[f64], kinds: &[EosKind]) -> Self {
+        let mut data = volumes.iter().cloned().zip(energies.iter().cloned()).collect::<Vec<_>>();
+        data.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let (volumes, energies): (Vec<f64>, Vec<f64>) = data.into_iter().unzip();
+
+        let fits = kinds.iter()
+            .map(|k| EosFit::fit(*k, &volumes, &energies))
+            .collect();
+
+        Self {
+            volumes,
+            energies,
+            fits,
+        }
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Equation of state",
+                "xaxis": {"title": "Volume (A^3)"},
+                "yaxis": {"title": "Energy (eV)"},
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "markers",
+            "name": "DFT",
+            "x": self.volumes,
+            "y": self.energies,
+        }));
+
+        let (vmin, vmax) = (self.volumes[0], *self.volumes.last().unwrap());
+        let xs = (0 ..= 200)
+            .map(|i| vmin + (vmax - vmin) * i as f64 / 200.0)
+            .collect::<Vec<f64>>();
+        for fit in self.fits.iter() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": fit.kind.to_string(),
+                "x": xs,
+                "y": xs.iter().map(|v| fit.energy(*v)).collect::<Vec<f64>>(),
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for EosReport {
+    fn headers(&self) -> Vec<String> {
+        ["eos", "e0", "v0", "b0", "b0p", "rms"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.fits.iter()
+            .map(|f| vec![
+                f.kind.to_string(),
+                format!("{:.6}", f.e0),
+                format!("{:.4}", f.v0),
+                format!("{:.3}", f.b0),
+                format!("{:.3}", f.b0p),
+                format!("{:.2E}", f.rms),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for EosReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "#   Volume/A3      Energy/eV".bright_green())?;
+        for (v, e) in self.volumes.iter().zip(self.energies.iter()) {
+            writeln!(f, "  {:11.4} {:14.6}", v, e)?;
+        }
+
+        writeln!(f, "{}", "# EOS                    E0/eV      V0/A3     B0/GPa      B0'    RMS/eV".bright_green())?;
+        for fit in self.fits.iter() {
+            writeln!(f, "  {:<16} {:12.6} {:10.4} {} {:8.3} {:9.2E}",
+                     fit.kind.to_string(), fit.e0, fit.v0,
+                     format!("{:10.3}", fit.b0).bright_yellow(), fit.b0p, fit.rms)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eos_fit() {
+        let params = [-10.5, 40.0, 0.6, 4.5];
+        let volumes = (0 .. 9).map(|i| 34.0 + 1.5 * i as f64).collect::<Vec<f64>>();
+
+        for kind in EosKind::ALL.iter() {
+            let energies = volumes.iter().map(|v| kind.energy(&params, *v)).collect::<Vec<f64>>();
+            let fit = EosFit::fit(*kind, &volumes, &energies);
+            assert!((fit.e0 - params[0]).abs() < 1E-6, "{:?}", fit);
+            assert!((fit.v0 - params[1]).abs() < 1E-5, "{:?}", fit);
+            assert!((fit.b0 - params[2] * EV_PER_A3_TO_GPA).abs() < 1E-3, "{:?}", fit);
+            assert!((fit.b0p - params[3]).abs() < 1E-4, "{:?}", fit);
+            assert!(fit.rms < 1E-8);
+        }
+    }
+
+    #[test]
+    fn test_eos_kind() {
+        assert_eq!("BM".parse::<EosKind>(), Ok(EosKind::BirchMurnaghan));
+        assert_eq!("vinet".parse::<EosKind>(), Ok(EosKind::Vinet));
+        assert!("foo".parse::<EosKind>().is_err());
+
+        // Minimum at V0 for all the forms
+        let params = [-10.5, 40.0, 0.6, 4.5];
+        for kind in EosKind::ALL.iter() {
+            let e = |v: f64| kind.energy(&params, v);
+            assert!((e(40.0) - params[0]).abs() < 1E-12);
+            assert!(e(39.9) > e(40.0) && e(40.1) > e(40.0));
+        }
+    }
+
+    #[test]
+    fn test_eos_report() {
+        let params = [-10.5, 40.0, 0.6, 4.5];
+        let volumes = [43.0, 37.0, 40.0, 38.5, 41.5, 35.5];
+        let energies = volumes.iter().map(|v| EosKind::Vinet.energy(&params, *v)).collect::<Vec<f64>>();
+        let report = EosReport::new(&volumes, &energies, &EosKind::ALL);
+        assert_eq!(report.volumes, vec![35.5, 37.0, 38.5, 40.0, 41.5, 43.0]);
+        assert_eq!(report.rows().len(), 3);
+        assert_eq!(report.rows()[2][2], "40.0000");
+    }
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+use std::io;
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use colored::Colorize;
+use crate::procar::Procar;
+use crate::traits::Tabular;
+
+
+// One electron-hole pair of an exciton, from the valence state E_v to the conduction state E_c
+// at k-point `kpoint` in fractional coordinates, with the amplitude |X_vck| of the eigenvector.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ExcitonTransition {
+    pub kpoint    : [f64; 3],
+    pub ev        : f64,
+    pub ec        : f64,
+    pub amplitude : f64,
+}
+
+// Weights of each state as [nspin][nkpts][nbands]
+pub type StateWeights = Vec<Vec<Vec<f64>>>;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Exciton {
+    pub index       : usize,   // starting from 1
+    pub energy      : f64,     // in eV
+    pub transitions : Vec<ExcitonTransition>,
+}
+
+impl Exciton {
+    /// Sum of the squared amplitudes, which is one for normalized eigenvectors.
+    pub fn norm(&self) -> f64 {
+        self.transitions.iter().map(|t| t.amplitude.powi(2)).sum()
+    }
+
+    /// The transition with the largest amplitude.
+    pub fn dominant(&self) -> Option<&ExcitonTransition> {
+        self.transitions.iter().max_by(|a, b| a.amplitude.partial_cmp(&b.amplitude).unwrap())
+    }
+
+    /// Hole and electron weights of each state of PROCAR as [nspin][nkpts][nbands], i.e. the
+    /// squared amplitudes summed onto the valence and conduction states respectively and
+    /// normalized to one. The k-points are matched by fractional coordinates up to reciprocal
+    /// lattice vectors, and the bands by the closest eigenvalues in either spin channel. Also
+    /// returns the number of transitions whose k-point is not found in PROCAR.
+    pub fn band_weights(&self, procar: &Procar) -> (StateWeights, StateWeights, usize) {
+        let mut hole = vec![vec![vec![0.0f64; procar.nbands]; procar.nkpts]; procar.nspin];
+        let mut electron = hole.clone();
+        let mut nunmatched = 0;
+
+        for t in self.transitions.iter() {
+            let ik = match _match_kpoint(&procar.kpoints, &t.kpoint) {
+                Some(ik) => ik,
+                None => { nunmatched += 1; continue; },
+            };
+            let w = t.amplitude.powi(2);
+            let (isv, ibv) = _match_state(procar, ik, t.ev);
+            let (isc, ibc) = _match_state(procar, ik, t.ec);
+            hole[isv][ik][ibv] += w;
+            electron[isc][ik][ibc] += w;
+        }
+
+        let norm = |ws: &mut StateWeights| {
+            let total = ws.iter().flatten().flatten().sum::<f64>();
+            if total > 0.0 {
+                ws.iter_mut().flatten().flatten().for_each(|w| *w /= total);
+            }
+        };
+        norm(&mut hole);
+        norm(&mut electron);
+        (hole, electron, nunmatched)
+    }
+}
+
+
+// Index of the k-point equivalent to `k` up to reciprocal lattice vectors
+fn _match_kpoint(kpoints: &[[f64; 3]], k: &[f64; 3]) -> Option<usize> {
+    kpoints.iter().position(|kp| {
+        (0 .. 3).all(|i| {
+            let d = kp[i] - k[i];
+            (d - d.round()).abs() < KPOINT_TOLERANCE
+        })
+    })
+}
+
+// Spin channel and band of the eigenvalue closest to `e` at k-point `ik`
+fn _match_state(procar: &Procar, ik: usize, e: f64) -> (usize, usize) {
+    (0 .. procar.nspin)
+        .flat_map(|ispin| (0 .. procar.nbands).map(move |ib| (ispin, ib)))
+        .min_by(|&(s1, b1), &(s2, b2)| {
+            let d1 = (procar.eigval(s1, ik, b1) - e).abs();
+            let d2 = (procar.eigval(s2, ik, b2) - e).abs();
+            d1.partial_cmp(&d2).unwrap()
+        })
+        .expect("No bands in PROCAR")
+}
+
+const KPOINT_TOLERANCE: f64 = 1E-4;
+
+
+// Eigenvectors of the Bethe-Salpeter equation written to BSEFATBAND by VASP. Each exciton
+// starts with a header line holding its energy as the last number, followed by one line per
+// transition: kx ky kz E_v E_c |X| and optionally the real and imaginary parts of X.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BseFatband {
+    pub excitons : Vec<Exciton>,
+}
+
+impl BseFatband {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Self::parse(&context)
+    }
+
+    pub fn parse(context: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut excitons: Vec<Exciton> = vec![];
+
+        for (iline, line) in context.lines().enumerate() {
+            let nums = line.split_whitespace()
+                .filter_map(|x| x.parse::<f64>().ok())
+                .collect::<Vec<f64>>();
+            let ntokens = line.split_whitespace().count();
+
+            if ntokens >= 6 && nums.len() == ntokens {
+                let exciton = excitons.last_mut()
+                    .ok_or_else(|| invalid(format!("Transition before any exciton header at line {} of BSEFATBAND", iline + 1)))?;
+                exciton.transitions.push(ExcitonTransition {
+                    kpoint: [nums[0], nums[1], nums[2]],
+                    ev: nums[3],
+                    ec: nums[4],
+                    amplitude: nums[5],
+                });
+            } else if let Some(energy) = nums.last() {
+                excitons.push(Exciton {
+                    index: excitons.len() + 1,
+                    energy: *energy,
+                    transitions: vec![],
+                });
+            }
+        }
+
+        if excitons.is_empty() {
+            return Err(invalid("No excitons found in BSEFATBAND".to_string()));
+        }
+        Ok(Self { excitons })
+    }
+}
+
+impl Tabular for BseFatband {
+    fn headers(&self) -> Vec<String> {
+        ["index", "energy", "ntransitions", "kx", "ky", "kz", "ev", "ec", "amplitude"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.excitons.iter()
+            .map(|x| {
+                let mut row = vec![
+                    x.index.to_string(),
+                    format!("{:.4}", x.energy),
+                    x.transitions.len().to_string(),
+                ];
+                match x.dominant() {
+                    Some(t) => row.extend([
+                        format!("{:.4}", t.kpoint[0]),
+                        format!("{:.4}", t.kpoint[1]),
+                        format!("{:.4}", t.kpoint[2]),
+                        format!("{:.4}", t.ev),
+                        format!("{:.4}", t.ec),
+                        format!("{:.4}", t.amplitude),
+                    ]),
+                    None => row.extend(std::iter::repeat_n(String::new(), 6)),
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for BseFatband {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# {:>5} {:>10} {:>6}   {:<26} {:>10} {:>10} {:>8}",
+                                  "Index", "E/eV", "Ntrans", "Dominant k-point", "E_v/eV", "E_c/eV", "|X|").bright_green())?;
+        for x in self.excitons.iter() {
+            write!(f, "  {:5} {} {:6}", x.index, format!("{:10.4}", x.energy).bright_yellow(), x.transitions.len())?;
+            if let Some(t) = x.dominant() {
+                write!(f, "   ({:7.4}, {:7.4}, {:7.4}) {:10.4} {:10.4} {:8.4}",
+                       t.kpoint[0], t.kpoint[1], t.kpoint[2], t.ev, t.ec, t.amplitude)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exciton_band_weights() {
+        let bse = BseFatband::parse(r#"   BSE eigenvalue     1   2.10000
+  0.000000  0.000000  0.000000   -1.0000    1.2000   0.800000   0.800000   0.000000
+  0.500000  0.000000  0.000000   -0.5000    1.6000   0.600000   0.000000   0.600000
+   BSE eigenvalue     2   2.50000
+ -0.500000  0.000000  0.000000   -0.5000    1.6000   1.000000   1.000000   0.000000
+"#).unwrap();
+        assert_eq!(bse.excitons.len(), 2);
+        assert_eq!(bse.excitons[0].energy, 2.1);
+        assert_eq!(bse.excitons[0].transitions.len(), 2);
+        assert!((bse.excitons[0].norm() - 1.0).abs() < 1E-10);
+        assert_eq!(bse.excitons[1].dominant().unwrap().ec, 1.6);
+        assert_eq!(bse.rows()[1][2], "1");
+
+        let procar = Procar {
+            nkpts: 2,
+            nbands: 2,
+            nions: 1,
+            nspin: 1,
+            lncl: false,
+            orbitals: vec!["s".to_string()],
+            kpoints: vec![[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]],
+            weights: vec![0.5, 0.5],
+            eigvals: vec![-1.01, 1.21, -0.49, 1.59],
+            occupations: vec![2.0, 0.0, 2.0, 0.0],
+            projections: vec![1.0; 4],
+            phases: None,
+        };
+        let (hole, electron, nunmatched) = bse.excitons[0].band_weights(&procar);
+        assert_eq!(nunmatched, 0);
+        assert!((hole[0][0][0] - 0.64).abs() < 1E-10);
+        assert!((electron[0][1][1] - 0.36).abs() < 1E-10);
+        assert_eq!(hole[0][0][1], 0.0);
+
+        // k-point -0.5 is equivalent to 0.5
+        let (hole, _, _) = bse.excitons[1].band_weights(&procar);
+        assert_eq!(hole[0][1][0], 1.0);
+        assert!(BseFatband::parse("").is_err());
+    }
+}
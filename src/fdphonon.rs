@@ -0,0 +1,373 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::Path;
+use colored::Colorize;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use crate::traits::Tabular;
+use crate::outcar::MatX3;
+use crate::format::{
+    Structure,
+    _car_to_frac,
+};
+use crate::symmetry::{
+    space_group,
+    atom_mapping,
+};
+
+
+// Trial directions in the basis of lattice vectors, the axes first as phonopy does
+const DIRECTIONS: [[f64; 3]; 7] = [
+    [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0],
+];
+
+
+// Adds `v` into the orthonormal `basis` if it is linearly independent, returns whether added
+fn _extend_basis(basis: &mut Vec<[f64; 3]>, v: &[f64; 3]) -> bool {
+    let mut u = *v;
+    for b in basis.iter() {
+        let p = (0 .. 3).map(|k| u[k] * b[k]).sum::<f64>();
+        u = [0, 1, 2].map(|k| u[k] - p * b[k]);
+    }
+    let norm = u.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm < 1E-6 {
+        return false;
+    }
+    basis.push(u.map(|x| x / norm));
+    true
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Displacement {
+    pub index        : usize,      // starts from 1
+    pub atom         : usize,      // starts from 1
+    pub symbol       : String,
+    pub displacement : [f64; 3],   // Cartesian, in Angstrom
+    pub dir          : String,     // run directory, e.g. "disp-001"
+}
+
+
+/// Displacements of the symmetry inequivalent atoms for the finite-difference phonons. For each
+/// inequivalent atom, the displacement directions are picked from the lattice vectors and their
+/// sums until their images under the site symmetry span the 3D space, and the opposite
+/// displacement is added if it is not equivalent by the site symmetry, like the default of
+/// phonopy.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplacementSet {
+    pub amplitude     : f64,       // in Angstrom
+    pub symprec       : f64,
+    pub nops          : usize,
+    pub natoms        : usize,
+    pub displacements : Vec<Displacement>,
+}
+
+impl DisplacementSet {
+    pub fn new(structure: &Structure, amplitude: f64, symprec: f64) -> Self {
+        let symbols = structure.symbols();
+        let natoms = symbols.len();
+        let ops = space_group(structure, symprec)
+            .into_iter()
+            .filter_map(|op| atom_mapping(&op, structure, symprec).map(|perm| (op.cart_rotation(&structure.cell), perm)))
+            .collect::<Vec<_>>();
+
+        let rotate = |r: &[[f64; 3]; 3], v: &[f64; 3]| [0, 1, 2].map(|a| (0 .. 3).map(|b| r[a][b] * v[b]).sum::<f64>());
+        let same = |a: &[f64; 3], b: &[f64; 3]| (0 .. 3).all(|k| (a[k] - b[k]).abs() < 1E-6);
+        let candidates = DIRECTIONS.iter()
+            .map(|n| {
+                let v = [0, 1, 2].map(|j| (0 .. 3).map(|k| n[k] * structure.cell[k][j]).sum::<f64>());
+                let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                v.map(|x| x / norm)
+            })
+            .collect::<Vec<_>>();
+
+        let mut covered = vec![false; natoms];
+        let mut displacements = vec![];
+        for ia in 0 .. natoms {
+            if covered[ia] {
+                continue;
+            }
+            ops.iter().for_each(|(_, perm)| covered[perm[ia]] = true);
+            let site = ops.iter()
+                .filter(|(_, perm)| perm[ia] == ia)
+                .map(|(r, _)| *r)
+                .collect::<Vec<_>>();
+
+            let mut basis = vec![];
+            for d in candidates.iter() {
+                if basis.len() == 3 {
+                    break;
+                }
+                if !_extend_basis(&mut basis, d) {
+                    continue;
+                }
+                site.iter().for_each(|r| { _extend_basis(&mut basis, &rotate(r, d)); });
+
+                let minus = d.map(|x| -x);
+                let mut directions = vec![*d];
+                if !site.iter().any(|r| same(&rotate(r, d), &minus)) {
+                    directions.push(minus);
+                }
+                for v in directions {
+                    let index = displacements.len() + 1;
+                    displacements.push(Displacement {
+                        index,
+                        atom: ia + 1,
+                        symbol: symbols[ia].clone(),
+                        displacement: v.map(|x| x * amplitude),
+                        dir: format!("disp-{:03}", index),
+                    });
+                }
+            }
+        }
+
+        Self {
+            amplitude,
+            symprec,
+            nops: ops.len(),
+            natoms,
+            displacements,
+        }
+    }
+
+    /// The structure with the `i`-th displacement applied, `i` starts from 0.
+    pub fn displaced(&self, structure: &Structure, i: usize) -> Structure {
+        let d = &self.displacements[i];
+        let mut ret = structure.clone();
+        let p = &mut ret.car_pos[d.atom - 1];
+        (0 .. 3).for_each(|k| p[k] += d.displacement[k]);
+        ret.frac_pos = _car_to_frac(&ret.cell, &ret.car_pos);
+        ret
+    }
+
+    pub fn save_json(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+impl Tabular for DisplacementSet {
+    fn headers(&self) -> Vec<String> {
+        ["index", "dir", "atom", "symbol", "dx", "dy", "dz"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.displacements.iter()
+            .map(|d| {
+                let mut row = vec![d.index.to_string(), d.dir.clone(), d.atom.to_string(), d.symbol.clone()];
+                row.extend(d.displacement.iter().map(|x| format!("{:.6}", x)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for DisplacementSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let natoms = {
+            let mut atoms = self.displacements.iter().map(|d| d.atom).collect::<Vec<_>>();
+            atoms.dedup();
+            atoms.len()
+        };
+        writeln!(f, "{}", format!("# {} symmetry operations, {} inequivalent atoms of {}, {} displacements of {} A",
+                                  self.nops, natoms, self.natoms, self.displacements.len(), self.amplitude).bright_green())?;
+        writeln!(f, "{}", format!("  {:>8} {:>5} {:>4} {:>10} {:>10} {:>10}", "Dir", "#Ion", "Elem", "dx/A", "dy/A", "dz/A").bright_green())?;
+        for d in self.displacements.iter() {
+            writeln!(f, "  {} {:5} {:>4} {:10.6} {:10.6} {:10.6}",
+                     d.dir.bright_yellow(), d.atom, d.symbol, d.displacement[0], d.displacement[1], d.displacement[2])?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ForceEntry {
+    pub index        : usize,
+    pub dir          : String,
+    pub atom         : usize,
+    pub displacement : [f64; 3],
+    #[serde(skip)]
+    pub forces       : MatX3<f64>,   // in eV/A
+    pub max_force    : f64,          // largest force on the atoms, in eV/A
+    pub drift        : f64,          // norm of the total force, should be close to zero
+}
+
+
+/// Forces of the displaced supercells written as FORCE_SETS of phonopy, the residual forces
+/// of the pristine supercell are subtracted if given.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ForceSets {
+    pub natoms   : usize,
+    pub residual : bool,
+    pub entries  : Vec<ForceEntry>,
+}
+
+impl ForceSets {
+    /// `forces` are of each displacement in `set`, in order.
+    pub fn new(set: &DisplacementSet, forces: Vec<MatX3<f64>>, residual: Option<&[[f64; 3]]>) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if forces.len() != set.displacements.len() {
+            return Err(err(format!("Got {} sets of forces for {} displacements", forces.len(), set.displacements.len())));
+        }
+        if let Some(r) = residual.filter(|r| r.len() != set.natoms) {
+            return Err(err(format!("Got {} residual forces, but {} atoms in the supercell", r.len(), set.natoms)));
+        }
+
+        let entries = set.displacements.iter()
+            .zip(forces)
+            .map(|(d, mut forces)| {
+                if forces.len() != set.natoms {
+                    return Err(err(format!("Got {} forces in {}, but {} atoms in the supercell", forces.len(), d.dir, set.natoms)));
+                }
+                if let Some(r) = residual {
+                    forces.iter_mut().zip(r.iter()).for_each(|(f, r)| (0 .. 3).for_each(|k| f[k] -= r[k]));
+                }
+                let norm = |v: &[f64; 3]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let total = [0, 1, 2].map(|k| forces.iter().map(|f| f[k]).sum::<f64>());
+                Ok(ForceEntry {
+                    index: d.index,
+                    dir: d.dir.clone(),
+                    atom: d.atom,
+                    displacement: d.displacement,
+                    max_force: forces.iter().map(norm).fold(0.0, f64::max),
+                    drift: norm(&total),
+                    forces,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            natoms: set.natoms,
+            residual: residual.is_some(),
+            entries,
+        })
+    }
+
+    /// FORCE_SETS in the format of phonopy, with the displacements and forces in Cartesian.
+    pub fn to_force_sets(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "{:<5}", self.natoms).unwrap();
+        writeln!(s, "{:<5}", self.entries.len()).unwrap();
+        for e in self.entries.iter() {
+            writeln!(s).unwrap();
+            writeln!(s, "{:<5}", e.atom).unwrap();
+            writeln!(s, "{:20.16} {:20.16} {:20.16}", e.displacement[0], e.displacement[1], e.displacement[2]).unwrap();
+            for f in e.forces.iter() {
+                writeln!(s, "{:15.10} {:15.10} {:15.10}", f[0], f[1], f[2]).unwrap();
+            }
+        }
+        s
+    }
+
+    pub fn save(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        fs::write(path, self.to_force_sets())
+    }
+}
+
+impl Tabular for ForceSets {
+    fn headers(&self) -> Vec<String> {
+        ["index", "dir", "atom", "max_force", "drift"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.entries.iter()
+            .map(|e| vec![
+                e.index.to_string(),
+                e.dir.clone(),
+                e.atom.to_string(),
+                format!("{:.6}", e.max_force),
+                format!("{:.6}", e.drift),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for ForceSets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Forces of {} displacements in the supercell of {} atoms{}",
+                                  self.entries.len(), self.natoms,
+                                  if self.residual { ", residual forces subtracted" } else { "" }).bright_green())?;
+        writeln!(f, "{}", format!("  {:>8} {:>5} {:>12} {:>12}", "Dir", "#Ion", "MaxF/(eV/A)", "Drift/(eV/A)").bright_green())?;
+        for e in self.entries.iter() {
+            writeln!(f, "  {:>8} {:5} {:12.6} {}", e.dir, e.atom, e.max_force, format!("{:12.6}", e.drift).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _structure(cell: [[f64; 3]; 3], ion_types: &[&str], ions_per_type: Vec<i32>, frac_pos: Vec<[f64; 3]>) -> Structure {
+        let car_pos = frac_pos.iter()
+            .map(|p| [0, 1, 2].map(|j| (0 .. 3).map(|k| p[k] * cell[k][j]).sum::<f64>()))
+            .collect();
+        Structure {
+            cell,
+            ion_types: ion_types.iter().map(|s| s.to_string()).collect(),
+            ions_per_type,
+            car_pos,
+            frac_pos,
+        }
+    }
+
+    #[test]
+    fn test_displacements() {
+        // Rock salt NaCl, the sites are of Oh symmetry, one displacement for each species
+        let a = 5.64;
+        let nacl = _structure([[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]], &["Na", "Cl"], vec![4, 4], vec![
+            [0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0],
+            [0.5, 0.5, 0.5], [0.5, 0.0, 0.0], [0.0, 0.5, 0.0], [0.0, 0.0, 0.5],
+        ]);
+        let set = DisplacementSet::new(&nacl, 0.01, 1E-3);
+        assert_eq!(set.nops, 48 * 4);
+        assert_eq!(set.displacements.iter().map(|d| d.atom).collect::<Vec<_>>(), vec![1, 5]);
+        assert!((set.displacements[0].displacement[0] - 0.01).abs() < 1E-12);
+        assert_eq!(set.displacements[1].dir, "disp-002");
+
+        // Two equivalent atoms along c of 4mm sites, thus +c and -c are both needed
+        let chain = _structure([[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 5.0]], &["C"], vec![2], vec![
+            [0.0, 0.0, 0.0], [0.0, 0.0, 0.3],
+        ]);
+        let set = DisplacementSet::new(&chain, 0.02, 1E-3);
+        let disps = set.displacements.iter().map(|d| (d.atom, d.displacement.map(|x| (x / 0.02).round() as i32))).collect::<Vec<_>>();
+        assert_eq!(disps, vec![(1, [1, 0, 0]), (1, [0, 0, 1]), (1, [0, 0, -1])]);
+
+        let displaced = set.displaced(&chain, 2);
+        assert!((displaced.car_pos[0][2] + 0.02).abs() < 1E-12);
+        assert!((displaced.frac_pos[0][2] + 0.004).abs() < 1E-12);
+
+        // FORCE_SETS
+        let forces = (0 .. 3).map(|i| vec![[0.1 * i as f64, 0.0, -0.5], [0.0, 0.0, 0.5]]).collect();
+        let sets = ForceSets::new(&set, forces, Some(&[[0.0, 0.0, 0.1], [0.0, 0.0, -0.1]])).unwrap();
+        assert!((sets.entries[0].max_force - 0.6).abs() < 1E-12);
+        assert!((sets.entries[2].drift - 0.2).abs() < 1E-12);
+        let text = sets.to_force_sets();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2 + 3 * (1 + 1 + 1 + 2));
+        assert_eq!(lines[0].trim(), "2");
+        assert_eq!(lines[1].trim(), "3");
+        assert_eq!(lines[3].trim(), "1");
+        assert!(ForceSets::new(&set, vec![], None).is_err());
+
+        // supercell
+        let sc = chain.supercell([2, 2, 1]);
+        assert_eq!(sc.ions_per_type, vec![8]);
+        assert!((sc.car_pos[2][0] - 3.0).abs() < 1E-12);
+        assert_eq!(DisplacementSet::new(&sc, 0.02, 1E-3).displacements.len(), 3);
+        // 4mm is lowered to mm2 in the 2x1x1 supercell, both a and b are needed
+        assert_eq!(DisplacementSet::new(&chain.supercell([2, 1, 1]), 0.02, 1E-3).displacements.len(), 4);
+    }
+}
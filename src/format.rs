@@ -11,21 +11,29 @@ use itertools::multizip;
 use colored::Colorize;
 use vasp_poscar::{self, Poscar};
 use log::info;
+use serde::{
+    Serialize,
+    Serializer,
+};
+use serde_json::json;
+use crate::traits::Tabular;
+use crate::plot::Plot;
 use crate::outcar::{
     Outcar,
     IonicIteration,
+    EnergyTerms,
     Vibration,
     Mat33,
     MatX3,
 };
 
 
-fn _save_as_xsf_helper(fname: &Path, structure: &Structure, forces: &MatX3<f64>) -> io::Result<()> {
+pub(crate) fn _save_as_xsf_helper(fname: &Path, structure: &Structure, forces: &MatX3<f64>) -> io::Result<()> {
     let mut f = fs::OpenOptions::new()
         .create(true)
         .truncate(true)
         .write(true)
-        .open(&fname)?;
+        .open(fname)?;
 
     writeln!(f, "CRYSTAL")?;
     writeln!(f, "PRIMVEC")?;
@@ -35,17 +43,7 @@ fn _save_as_xsf_helper(fname: &Path, structure: &Structure, forces: &MatX3<f64>)
     writeln!(f, "PRIMCOORD")?;
     writeln!(f, "{:3} {:3}", structure.ions_per_type.iter().sum::<i32>(), 1)?;
 
-    // generate the chemical symbol array for each atom
-    let syms = {
-        let symbs = &structure.ion_types;
-        let nsymb = &structure.ions_per_type;
-        symbs.iter()
-             .zip(nsymb.iter())
-             .fold(vec![], |mut acc, (s, n)| {
-                 acc.extend(vec![s; (*n) as usize]);
-                 acc
-             })
-    };
+    let syms = structure.symbols();
 
     for (s, p, m) in multizip((syms, &structure.car_pos, forces)) {
         writeln!(f, "{:4} {:15.10} {:15.10} {:15.10}   {:15.10} {:15.10} {:15.10}",
@@ -71,6 +69,12 @@ pub struct IonicIterationsFormat {
     print_time_usage : bool,
     print_magmom     : bool,
     print_volume     : bool,
+    print_lattice    : bool,
+    print_stress     : bool,
+    print_charge     : bool,
+    print_dipole     : bool,
+
+    electrons        : Option<(f64, f64)>,  // electrons of the neutral cell and NELECT
 }
 
 impl From<Vec<IonicIteration>> for IonicIterationsFormat {
@@ -88,6 +92,11 @@ impl From<Vec<IonicIteration>> for IonicIterationsFormat {
             print_time_usage : true,
             print_magmom     : true,
             print_volume     : false,
+            print_lattice    : false,
+            print_stress     : false,
+            print_charge     : false,
+            print_dipole     : false,
+            electrons        : None,
         }
     }
 }
@@ -114,10 +123,61 @@ impl IonicIterationsFormat {
     impl_builder_item!(print_time_usage);
     impl_builder_item!(print_magmom);
     impl_builder_item!(print_volume);
+    impl_builder_item!(print_lattice);
+    impl_builder_item!(print_stress);
+    impl_builder_item!(print_charge);
+    impl_builder_item!(print_dipole);
+
+    /// Net charges of the cell are available once the electrons of the neutral cell are known,
+    /// steps deviating from NELECT are highlighted.
+    pub fn electrons(mut self, neutral: f64, nelect: f64) -> Self {
+        self.electrons = Some((neutral, nelect));
+        self
+    }
+
+    /// The first step whose integrated number of electrons deviates from NELECT by more than
+    /// `tolerance`, and the deviation.
+    pub fn charge_leakage(&self, tolerance: f64) -> Option<(usize, f64)> {
+        let (_, nelect) = self.electrons?;
+        self._data.iter()
+            .enumerate()
+            .map(|(i, it)| (i + 1, it.nelect - nelect))
+            .find(|(_, d)| d.abs() > tolerance)
+    }
 }
 
-impl fmt::Display for IonicIterationsFormat {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+// Quantities of one ionic step, as listed by `rsgrad rlx`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IonicStepInfo {
+    pub step       : usize,  // starts from 1
+    pub toten      : f64,
+    pub toten_z    : f64,
+    pub log10de    : f64,
+    pub favg       : f64,
+    pub fmax       : f64,
+    pub fmax_index : usize,  // starts from 1
+    pub fmax_axis  : char,
+    pub nscf       : i32,
+    pub time       : f64,    // in minutes
+    pub volume     : f64,
+    pub a          : f64,
+    pub b          : f64,
+    pub c          : f64,
+    pub alpha      : f64,
+    pub beta       : f64,
+    pub gamma      : f64,
+    pub pressure   : f64,       // external pressure in kB
+    pub stress     : [f64; 6],  // XX YY ZZ XY YZ ZX in kB
+    pub magmom     : Option<Vec<f64>>,
+    pub nelect     : f64,               // integrated number of electrons
+    pub charge     : Option<f64>,       // net charge of the cell in |e|, positive if electrons removed
+    pub dipole     : Option<[f64; 3]>,  // dipole moment in e*A, LDIPOL=.TRUE. only
+}
+
+impl IonicIterationsFormat {
+    /// Fixed directions are excluded in force statistics if POSCAR with selective dynamics
+    /// exists in current directory.
+    pub fn steps(&self) -> Vec<IonicStepInfo> {
         let nions = self._data[0].forces.len();
         let dynamics =
             if let Ok(poscar) = Poscar::from_path("POSCAR") {
@@ -134,39 +194,17 @@ impl fmt::Display for IonicIterationsFormat {
         .collect::<Vec<_>>();
 
         let mut ce: f64 = 0.0;
-
-        // Prepare Header
-        let mut header = "  #Step".to_owned();
-        header += if self.print_energy     { "    TOTEN/eV" } else { "" };
-        header += if self.print_energyz    { "  TOTEN_z/eV" } else { "" };
-        header += if self.print_log10de    { " LgdE" }        else { "" };
-        header += if self.print_favg       { "   Favg" }      else { "" };
-        header += if self.print_fmax       { "   Fmax" }      else { "" };
-        header += if self.print_fmax_index { " idx" }         else { "" };
-        header += if self.print_fmax_axis  { "  " }           else { "" };
-        header += if self.print_nscf       { " #SCF" }        else { "" };
-        header += if self.print_time_usage { " Time/m" }      else { "" };
-        header += if self.print_volume     { "   Vol/A3" }    else { "" };
-        header += if self.print_magmom     { " Mag/muB" }     else { "" };
-        writeln!(f, "{}", header.bright_green())?;
+        let mut ret = vec![];
 
         for (i, it) in self._data.iter().enumerate() {
-            let mut line = format!("{:7}", i+1);
-
             let de = self._data[i].toten_z - ce;
             ce = self._data[i].toten_z;
-            if self.print_energy  { line += &format!(" {:11.5}", it.toten); }
-            if self.print_energyz { line += &format!(" {:11.5}", it.toten_z).bright_green().to_string(); }
-            if self.print_log10de { line += &format!(" {:4.1}", de.abs().log10()); }
 
             let fsize = it.forces.iter()
                                  .zip(dynamics.iter())
                                  .map(|(f, d)| (f[0]*f[0]*d[0] + f[1]*f[1]*d[1] + f[2]*f[2]*d[2]).sqrt())
                                  .collect::<Vec<_>>();
-
-            if self.print_favg {
-                line += &format!(" {:6.3}", fsize.iter().sum::<f64>() / it.forces.len() as f64);
-            }
+            let favg = fsize.iter().sum::<f64>() / it.forces.len() as f64;
 
             let (fmax_ind, fmax) = fsize.into_iter()
                                         .enumerate()
@@ -187,31 +225,109 @@ impl fmt::Display for IonicIterationsFormat {
                     }
                     acc
                 }) {
-                    (0, _) => "X",
-                    (1, _) => "Y",
-                    (2, _) => "Z",
+                    (0, _) => 'X',
+                    (1, _) => 'Y',
+                    (2, _) => 'Z',
                     _ => unreachable!("Invalid Fmax Axis here")
                 };
 
-            if self.print_fmax       { line += &format!(" {:6.3}", fmax).bright_green().to_string(); }
-            if self.print_fmax_index { line += &format!(" {:3}", fmax_ind+1); }
-            if self.print_fmax_axis  { line += &format!(" {:1}", fmaxis); }
-            if self.print_nscf       { line += &format!(" {:4}", it.nscf).bright_yellow().to_string(); }
-            if self.print_time_usage { line += &format!(" {:6.2}", it.cputime/60.0); }
+            let volume = {
+                let c = it.cell;
 
-            if self.print_volume {
-                let volume = {
-                    let c = it.cell;
+                // |00 01 02|
+                // |10 11 12|
+                // |20 21 22|
 
-                    // |00 01 02|
-                    // |10 11 12|
-                    // |20 21 22|
+                c[0][0] * (c[1][1] * c[2][2] - c[2][1] * c[1][2])
+                    - c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0])
+                    + c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0])
+            };
 
-                    c[0][0] * (c[1][1] * c[2][2] - c[2][1] * c[1][2])
-                        - c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0])
-                        + c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0])
+            let [a, b, c, alpha, beta, gamma] = _lattice_parameters(&it.cell);
+
+            ret.push(IonicStepInfo {
+                step: i + 1,
+                toten: it.toten,
+                toten_z: it.toten_z,
+                log10de: de.abs().log10(),
+                favg,
+                fmax,
+                fmax_index: fmax_ind + 1,
+                fmax_axis: fmaxis,
+                nscf: it.nscf,
+                time: it.cputime / 60.0,
+                volume,
+                a, b, c,
+                alpha, beta, gamma,
+                pressure: it.stress,
+                stress: it.stress_tensor,
+                magmom: it.magmom.clone(),
+                nelect: it.nelect,
+                charge: self.electrons.map(|(neutral, _)| neutral - it.nelect),
+                dipole: it.energy_terms.as_ref().and_then(|t| t.dipole_moment),
+            });
+        }
+        ret
+    }
+}
+
+impl fmt::Display for IonicIterationsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Prepare Header
+        let mut header = "  #Step".to_owned();
+        header += if self.print_energy     { "    TOTEN/eV" } else { "" };
+        header += if self.print_energyz    { "  TOTEN_z/eV" } else { "" };
+        header += if self.print_log10de    { " LgdE" }        else { "" };
+        header += if self.print_favg       { "   Favg" }      else { "" };
+        header += if self.print_fmax       { "   Fmax" }      else { "" };
+        header += if self.print_fmax_index { " idx" }         else { "" };
+        header += if self.print_fmax_axis  { "  " }           else { "" };
+        header += if self.print_nscf       { " #SCF" }        else { "" };
+        header += if self.print_time_usage { " Time/m" }      else { "" };
+        header += if self.print_volume     { "   Vol/A3" }    else { "" };
+        header += if self.print_lattice    { "    a/A    b/A    c/A  alpha   beta  gamma" } else { "" };
+        header += if self.print_stress     { "  Sxx/kB  Syy/kB  Szz/kB  Sxy/kB  Syz/kB  Szx/kB" } else { "" };
+        header += if self.print_charge     { "   NElect  Charge" } else { "" };
+        header += if self.print_dipole     { "  Px/eA  Py/eA  Pz/eA" } else { "" };
+        header += if self.print_magmom     { " Mag/muB" }     else { "" };
+        writeln!(f, "{}", header.bright_green())?;
+
+        for it in self.steps() {
+            let mut line = format!("{:7}", it.step);
+
+            if self.print_energy     { line += &format!(" {:11.5}", it.toten); }
+            if self.print_energyz    { line += &format!(" {:11.5}", it.toten_z).bright_green().to_string(); }
+            if self.print_log10de    { line += &format!(" {:4.1}", it.log10de); }
+            if self.print_favg       { line += &format!(" {:6.3}", it.favg); }
+            if self.print_fmax       { line += &format!(" {:6.3}", it.fmax).bright_green().to_string(); }
+            if self.print_fmax_index { line += &format!(" {:3}", it.fmax_index); }
+            if self.print_fmax_axis  { line += &format!(" {:1}", it.fmax_axis); }
+            if self.print_nscf       { line += &format!(" {:4}", it.nscf).bright_yellow().to_string(); }
+            if self.print_time_usage { line += &format!(" {:6.2}", it.time); }
+            if self.print_volume     { line += &format!(" {:8.1}", it.volume); }
+            if self.print_lattice {
+                line += &format!(" {:6.3} {:6.3} {:6.3} {:6.2} {:6.2} {:6.2}",
+                                 it.a, it.b, it.c, it.alpha, it.beta, it.gamma);
+            }
+            if self.print_stress {
+                line += &it.stress.iter()
+                               .map(|x| format!(" {:7.2}", x))
+                               .collect::<Vec<_>>()
+                               .join("");
+            }
+            if self.print_charge {
+                let leaked = self.electrons.is_some_and(|(_, n)| (it.nelect - n).abs() > 1E-3);
+                let charge = match it.charge {
+                    Some(c) => format!(" {:8.3} {:7.3}", it.nelect, c),
+                    None    => format!(" {:8.3} {:>7}", it.nelect, "-"),
+                };
+                line += &if leaked { charge.bright_red().to_string() } else { charge };
+            }
+            if self.print_dipole {
+                line += &match it.dipole {
+                    Some(d) => format!(" {:6.3} {:6.3} {:6.3}", d[0], d[1], d[2]),
+                    None    => format!(" {:>20}", "NoDipole"),
                 };
-                line += &format!(" {:8.1}", volume);
             }
 
             if self.print_magmom {
@@ -227,11 +343,63 @@ impl fmt::Display for IonicIterationsFormat {
         }
         Ok(())
     }
+}
 
+// All the quantities are included in JSON and CSV, regardless of the printing flags
+impl Serialize for IonicIterationsFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.steps().serialize(serializer)
+    }
 }
 
+impl Tabular for IonicIterationsFormat {
+    fn headers(&self) -> Vec<String> {
+        ["step", "toten", "toten_z", "log10de", "favg", "fmax", "fmax_index",
+         "fmax_axis", "nscf", "time", "volume", "a", "b", "c", "alpha", "beta", "gamma",
+         "pressure", "stress_xx", "stress_yy", "stress_zz", "stress_xy", "stress_yz", "stress_zx",
+         "magmom", "nelect", "charge", "dipole"]
+            .iter().map(|s| s.to_string()).collect()
+    }
 
-#[derive(Clone)]
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.steps().into_iter()
+            .map(|it| {
+                let mut row = vec![
+                    it.step.to_string(),
+                    format!("{:.6}", it.toten),
+                    format!("{:.6}", it.toten_z),
+                    format!("{:.2}", it.log10de),
+                    format!("{:.4}", it.favg),
+                    format!("{:.4}", it.fmax),
+                    it.fmax_index.to_string(),
+                    it.fmax_axis.to_string(),
+                    it.nscf.to_string(),
+                    format!("{:.2}", it.time),
+                    format!("{:.4}", it.volume),
+                    format!("{:.6}", it.a),
+                    format!("{:.6}", it.b),
+                    format!("{:.6}", it.c),
+                    format!("{:.4}", it.alpha),
+                    format!("{:.4}", it.beta),
+                    format!("{:.4}", it.gamma),
+                    format!("{:.2}", it.pressure),
+                ];
+                row.extend(it.stress.iter().map(|x| format!("{:.5}", x)));
+                row.push(it.magmom.unwrap_or_default().iter()
+                    .map(|m| format!("{:.4}", m))
+                    .collect::<Vec<_>>()
+                    .join(" "));
+                row.push(format!("{:.6}", it.nelect));
+                row.push(it.charge.map(|c| format!("{:.6}", c)).unwrap_or_default());
+                row.push(it.dipole.map(|d| format!("{:.6} {:.6} {:.6}", d[0], d[1], d[2])).unwrap_or_default());
+                row
+            })
+            .collect()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Structure {
     pub cell          : Mat33<f64>,
     pub ion_types     : Vec<String>,
@@ -248,7 +416,7 @@ impl Outcar {
         assert!(1 <= index && index <= len, "Index out of bound.");
         let index = index - 1;
 
-        let cell     = self.ion_iters[index].cell.clone();
+        let cell     = self.ion_iters[index].cell;
         let car_pos  = self.ion_iters[index].positions.clone();
         let frac_pos = _car_to_frac(&cell, &car_pos);
 
@@ -274,13 +442,13 @@ impl Outcar {
         if !fname.is_dir() {
             fs::create_dir_all(&fname)?;
         }
-        fname.push(&format!("step_{:04}.xsf", index));
+        fname.push(format!("step_{:04}.xsf", index));
         info!("Saving ionic step to {:?} ...", fname);
         _save_as_xsf_helper(&fname, s, f)
     }
 }
 
-fn _car_to_frac(cell: &Mat33<f64>, carpos: &MatX3<f64>) -> MatX3<f64> {
+pub(crate) fn _car_to_frac(cell: &Mat33<f64>, carpos: &MatX3<f64>) -> MatX3<f64> {
     let convmat = _calc_inv_3x3(cell);
     carpos.iter()
           .map(|v| {
@@ -295,7 +463,30 @@ fn _car_to_frac(cell: &Mat33<f64>, carpos: &MatX3<f64>) -> MatX3<f64> {
           }).collect()
 }
 
-fn _calc_inv_3x3(cell: &Mat33<f64>) -> Mat33<f64> {
+// Element symbols indexed by atomic numbers, "X" for the dummy atom of 0
+pub(crate) const ELEMENTS: [&str; 119] = [
+    "X",
+    "H",  "He", "Li", "Be", "B",  "C",  "N",  "O",  "F",  "Ne", "Na", "Mg", "Al", "Si", "P",  "S",
+    "Cl", "Ar", "K",  "Ca", "Sc", "Ti", "V",  "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge",
+    "As", "Se", "Br", "Kr", "Rb", "Sr", "Y",  "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd",
+    "In", "Sn", "Sb", "Te", "I",  "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd", "Pm", "Sm", "Eu", "Gd",
+    "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W",  "Re", "Os", "Ir", "Pt", "Au", "Hg",
+    "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U",  "Np", "Pu", "Am", "Cm",
+    "Bk", "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn",
+    "Nh", "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+// Lengths of lattice vectors in A and the angles between them in degrees: a, b, c, alpha, beta, gamma
+pub(crate) fn _lattice_parameters(cell: &Mat33<f64>) -> [f64; 6] {
+    let norm = |v: &[f64; 3]| (v[0]*v[0] + v[1]*v[1] + v[2]*v[2]).sqrt();
+    let angle = |u: &[f64; 3], v: &[f64; 3]| {
+        ((u[0]*v[0] + u[1]*v[1] + u[2]*v[2]) / (norm(u) * norm(v))).acos().to_degrees()
+    };
+    [norm(&cell[0]), norm(&cell[1]), norm(&cell[2]),
+     angle(&cell[1], &cell[2]), angle(&cell[0], &cell[2]), angle(&cell[0], &cell[1])]
+}
+
+pub(crate) fn _calc_inv_3x3(cell: &Mat33<f64>) -> Mat33<f64> {
     let a = cell[0][0];
     let b = cell[0][1];
     let c = cell[0][2];
@@ -323,6 +514,119 @@ fn _calc_inv_3x3(cell: &Mat33<f64>) -> Mat33<f64> {
 pub struct Trajectory(pub Vec<Structure>);
 
 impl Trajectory {
+    /// Reads XDATCAR of VASP5 format, the header is repeated before each frame if the cell is
+    /// variable (ISIF>=3).
+    pub fn from_xdatcar(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse_xdatcar(&content)
+    }
+
+    pub fn parse_xdatcar(content: &str) -> io::Result<Self> {
+        Self::parse_xdatcar_frames(content, |_| true)
+    }
+
+    /// Number of frames in XDATCAR, counted without parsing the coordinates.
+    pub fn count_xdatcar_frames(content: &str) -> usize {
+        content.lines()
+            .filter(|l| l.to_lowercase().contains("configuration"))
+            .count()
+    }
+
+    /// Same as `parse_xdatcar`, only the frames with `keep(index)` (index starts from 1) are
+    /// parsed and kept, thus long MD trajectories can be subsampled with little memory.
+    pub fn parse_xdatcar_frames(content: &str, keep: impl Fn(usize) -> bool) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let parse_floats = |line: &str| -> io::Result<Vec<f64>> {
+            line.split_whitespace()
+                .map(|x| x.parse::<f64>().map_err(|_| err(format!("Cannot parse '{}' in XDATCAR as float values", line.trim()))))
+                .collect()
+        };
+
+        let lines = content.lines().collect::<Vec<&str>>();
+        let mut header: Option<(Mat33<f64>, Vec<String>, Vec<i32>)> = None;
+        let mut ret = vec![];
+        let mut nframes = 0;
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i].trim();
+            if line.is_empty() {
+                i += 1;
+                continue;
+            }
+
+            if !line.to_lowercase().contains("configuration") {
+                // comment, scale, 3 lattice vectors, symbols and counts
+                if i + 7 > lines.len() {
+                    return Err(err("Incomplete header in XDATCAR".to_string()));
+                }
+                let scale = *parse_floats(lines[i + 1])?.first()
+                    .ok_or_else(|| err("Missing scale factor in XDATCAR".to_string()))?;
+                let mut cell = [[0.0f64; 3]; 3];
+                for (j, row) in cell.iter_mut().enumerate() {
+                    let v = parse_floats(lines[i + 2 + j])?;
+                    if v.len() < 3 {
+                        return Err(err(format!("Invalid lattice vector '{}' in XDATCAR", lines[i + 2 + j].trim())));
+                    }
+                    *row = [v[0], v[1], v[2]];
+                }
+                // negative scale is the volume of the cell
+                let scale = if scale < 0.0 {
+                    let c = &cell;
+                    let volume = c[0][0] * (c[1][1] * c[2][2] - c[2][1] * c[1][2])
+                        - c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0])
+                        + c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0]);
+                    (-scale / volume.abs()).cbrt()
+                } else { scale };
+                cell.iter_mut().flatten().for_each(|x| *x *= scale);
+
+                let ion_types = lines[i + 5].split_whitespace().map(|x| x.to_string()).collect::<Vec<String>>();
+                let ions_per_type = lines[i + 6].split_whitespace()
+                    .map(|x| x.parse::<i32>().map_err(|_| err("Element symbols and counts are required in XDATCAR".to_string())))
+                    .collect::<io::Result<Vec<i32>>>()?;
+                header = Some((cell, ion_types, ions_per_type));
+                i += 7;
+                continue;
+            }
+
+            let (cell, ion_types, ions_per_type) = header.clone()
+                .ok_or_else(|| err("No header found before the first configuration in XDATCAR".to_string()))?;
+            let nions = ions_per_type.iter().sum::<i32>() as usize;
+            if i + 1 + nions > lines.len() {
+                return Err(err(format!("Incomplete configuration '{}' in XDATCAR", line)));
+            }
+            nframes += 1;
+            if !keep(nframes) {
+                i += 1 + nions;
+                continue;
+            }
+            let frac_pos = lines[i + 1 .. i + 1 + nions].iter()
+                .map(|l| {
+                    let v = parse_floats(&l.split_whitespace().take(3).collect::<Vec<_>>().join(" "))?;
+                    if v.len() < 3 {
+                        return Err(err(format!("Invalid position '{}' in XDATCAR", l.trim())));
+                    }
+                    Ok([v[0], v[1], v[2]])
+                })
+                .collect::<io::Result<MatX3<f64>>>()?;
+            let car_pos = frac_pos.iter()
+                .map(|p| [0, 1, 2].map(|j| (0 .. 3).map(|k| p[k] * cell[k][j]).sum::<f64>()))
+                .collect::<MatX3<f64>>();
+            ret.push(Structure {
+                cell,
+                ion_types,
+                ions_per_type,
+                car_pos,
+                frac_pos,
+            });
+            i += 1 + nions;
+        }
+
+        if nframes == 0 {
+            return Err(err("No configurations found in XDATCAR".to_string()));
+        }
+        Ok(Self(ret))
+    }
+
     pub fn save_as_xdatcar(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
         let mut fname = PathBuf::new();
         fname.push(path);
@@ -372,11 +676,11 @@ impl Trajectory {
             for elem in v.ion_types.iter() {
                 write!(f, "{:>4}", elem)?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
             for nelm in v.ions_per_type.iter() {
                 write!(f, "{:>4}", nelm)?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
 
             writeln!(f, "Direct configuration={:6}", i+1)?;
             for row in v.frac_pos.iter() {
@@ -397,7 +701,7 @@ impl Trajectory {
         if !fname.is_dir() {
             fs::create_dir_all(&fname)?;
         }
-        fname.push(&format!("POSCAR_{:05}.vasp", index+1));
+        fname.push(format!("POSCAR_{:05}.vasp", index+1));
         info!("Saving trajectory step #{:5} to {:?} ...", index+1, &fname);
 
         let mut f = fs::OpenOptions::new()
@@ -413,6 +717,155 @@ impl Trajectory {
     pub fn _save_into_seperated_dirs(self, _path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
         todo!();
     }
+
+    /// Coordinates unwrapped across the periodic boundaries, such that the atoms move
+    /// continuously from the first frame. The displacements between consecutive frames are
+    /// taken by the minimum image convention in fractional coordinates, thus the frames should
+    /// be dense enough that no atom moves more than half of the cell between two of them.
+    /// Variable cells are supported, the Cartesian coordinates follow the cell of each frame.
+    pub fn unwrapped(&self) -> Self {
+        let mut ret = self.0.clone();
+        for i in 1 .. ret.len() {
+            assert_eq!(ret[i].frac_pos.len(), ret[i - 1].frac_pos.len(), "Inconsistent numbers of atoms in the trajectory");
+            let frac_pos = self.0[i].frac_pos.iter()
+                .zip(self.0[i - 1].frac_pos.iter())
+                .zip(ret[i - 1].frac_pos.iter())
+                .map(|((cur, prev), unwrapped)| [0, 1, 2].map(|k| {
+                    let d = cur[k] - prev[k];
+                    unwrapped[k] + d - d.round()
+                }))
+                .collect::<MatX3<f64>>();
+            let cell = ret[i].cell;
+            ret[i].car_pos = frac_pos.iter()
+                .map(|p| [0, 1, 2].map(|j| (0 .. 3).map(|k| p[k] * cell[k][j]).sum::<f64>()))
+                .collect();
+            ret[i].frac_pos = frac_pos;
+        }
+        Self(ret)
+    }
+
+    /// Removes the drift of the center of mass from the first frame, the drift is measured on
+    /// the unwrapped coordinates. All the atoms weigh the same if `masses` is None.
+    pub fn without_com_drift(&self, masses: Option<&[f64]>) -> Self {
+        let unwrapped = self.unwrapped();
+        let origin = match unwrapped.0.first() {
+            Some(s) => _center_of_mass(&s.car_pos, masses),
+            None => return self.clone(),
+        };
+        let frames = self.0.iter()
+            .zip(unwrapped.0.iter())
+            .map(|(s, u)| {
+                let com = _center_of_mass(&u.car_pos, masses);
+                let drift = [0, 1, 2].map(|k| com[k] - origin[k]);
+                let car_pos = s.car_pos.iter()
+                    .map(|p| [0, 1, 2].map(|k| p[k] - drift[k]))
+                    .collect::<MatX3<f64>>();
+                Structure {
+                    frac_pos: _car_to_frac(&s.cell, &car_pos),
+                    car_pos,
+                    ..s.clone()
+                }
+            })
+            .collect();
+        Self(frames)
+    }
+
+    /// Rigidly aligns each frame onto `reference` by the translation and rotation minimizing the
+    /// (mass-weighted) RMSD, with the quaternion method of Horn. The coordinates are taken as
+    /// they are, thus the trajectory should be unwrapped first, and the cells are kept. It is
+    /// meant for molecules and clusters, whose rotations are free.
+    pub fn aligned_to(&self, reference: &Structure, masses: Option<&[f64]>) -> Self {
+        let ref_com = _center_of_mass(&reference.car_pos, masses);
+        let frames = self.0.iter()
+            .map(|s| {
+                assert_eq!(s.car_pos.len(), reference.car_pos.len(), "Inconsistent numbers of atoms in the trajectory and reference");
+                let com = _center_of_mass(&s.car_pos, masses);
+                let a = s.car_pos.iter().map(|p| [0, 1, 2].map(|k| p[k] - com[k])).collect::<MatX3<f64>>();
+                let b = reference.car_pos.iter().map(|p| [0, 1, 2].map(|k| p[k] - ref_com[k])).collect::<MatX3<f64>>();
+                let rot = _optimal_rotation(&a, &b, masses);
+                let car_pos = a.iter()
+                    .map(|p| [0, 1, 2].map(|i| (0 .. 3).map(|j| rot[i][j] * p[j]).sum::<f64>() + ref_com[i]))
+                    .collect::<MatX3<f64>>();
+                Structure {
+                    frac_pos: _car_to_frac(&s.cell, &car_pos),
+                    car_pos,
+                    ..s.clone()
+                }
+            })
+            .collect();
+        Self(frames)
+    }
+}
+
+
+fn _center_of_mass(car_pos: &MatX3<f64>, masses: Option<&[f64]>) -> [f64; 3] {
+    let weight = |i: usize| masses.map(|m| m[i]).unwrap_or(1.0);
+    let total = (0 .. car_pos.len()).map(weight).sum::<f64>();
+    [0, 1, 2].map(|k| car_pos.iter().enumerate().map(|(i, p)| weight(i) * p[k]).sum::<f64>() / total)
+}
+
+
+// Rotation matrix R minimizing sum_i m_i |R a_i - b_i|^2 of the centered coordinates, from the
+// eigenvector of the largest eigenvalue of Horn's 4x4 quaternion matrix.
+fn _optimal_rotation(a: &MatX3<f64>, b: &MatX3<f64>, masses: Option<&[f64]>) -> Mat33<f64> {
+    let mut c = [[0.0f64; 3]; 3];
+    for (i, (p, q)) in a.iter().zip(b.iter()).enumerate() {
+        let w = masses.map(|m| m[i]).unwrap_or(1.0);
+        for j in 0 .. 3 {
+            for k in 0 .. 3 {
+                c[j][k] += w * p[j] * q[k];
+            }
+        }
+    }
+    let [[sxx, sxy, sxz], [syx, syy, syz], [szx, szy, szz]] = c;
+    let n = [
+        [sxx + syy + szz, syz - szy,        szx - sxz,        sxy - syx       ],
+        [syz - szy,       sxx - syy - szz,  sxy + syx,        szx + sxz       ],
+        [szx - sxz,       sxy + syx,       -sxx + syy - szz,  syz + szy       ],
+        [sxy - syx,       szx + sxz,        syz + szy,       -sxx - syy + szz ],
+    ];
+    let [w, x, y, z] = _largest_eigenvector(n);
+    [[w*w + x*x - y*y - z*z, 2.0 * (x*y - w*z),     2.0 * (x*z + w*y)    ],
+     [2.0 * (x*y + w*z),     w*w - x*x + y*y - z*z, 2.0 * (y*z - w*x)    ],
+     [2.0 * (x*z - w*y),     2.0 * (y*z + w*x),     w*w - x*x - y*y + z*z]]
+}
+
+
+// Cyclic Jacobi rotations with the eigenvectors accumulated, returns the normalized eigenvector
+// of the largest eigenvalue of the symmetric matrix.
+fn _largest_eigenvector(mut a: [[f64; 4]; 4]) -> [f64; 4] {
+    let mut v = [[0.0f64; 4]; 4];
+    (0 .. 4).for_each(|i| v[i][i] = 1.0);
+
+    for _ in 0 .. 100 {
+        let off = (0 .. 4).flat_map(|i| (0 .. 4).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum::<f64>();
+        if off < 1E-24 { break; }
+
+        for p in 0 .. 4 {
+            for q in p+1 .. 4 {
+                if a[p][q].abs() < 1E-300 { continue; }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (ap, aq) = (a[p], a[q]);
+                a[p] = [0, 1, 2, 3].map(|k| c * ap[k] - s * aq[k]);
+                a[q] = [0, 1, 2, 3].map(|k| s * ap[k] + c * aq[k]);
+            }
+        }
+    }
+
+    let imax = (0 .. 4).max_by(|i, j| a[*i][*i].partial_cmp(&a[*j][*j]).unwrap()).unwrap();
+    let ret = [0, 1, 2, 3].map(|k| v[k][imax]);
+    let norm = ret.iter().map(|x| x * x).sum::<f64>().sqrt();
+    ret.map(|x| x / norm)
 }
 
 
@@ -459,7 +912,89 @@ impl From<Structure> for Poscar {
 }
 
 
+impl From<Poscar> for Structure {
+    fn from(poscar: Poscar) -> Self {
+        let cell = poscar.scaled_lattice_vectors();
+        let ion_types = poscar.group_symbols()
+            .expect("Element symbols are required in POSCAR")
+            .map(|s| s.to_owned())
+            .collect::<Vec<String>>();
+        let ions_per_type = poscar.group_counts()
+            .map(|n| n as i32)
+            .collect::<Vec<i32>>();
+        let car_pos = poscar.scaled_cart_positions().into_owned();
+        let frac_pos = poscar.frac_positions().into_owned();
+
+        Self {
+            cell,
+            ion_types,
+            ions_per_type,
+            car_pos,
+            frac_pos,
+        }
+    }
+}
+
+
 impl Structure {
+    // generate the chemical symbol array for each atom
+    pub fn symbols(&self) -> Vec<String> {
+        self.ion_types.iter()
+            .zip(self.ions_per_type.iter())
+            .fold(vec![], |mut acc, (s, n)| {
+                acc.extend(vec![s.clone(); (*n) as usize]);
+                acc
+            })
+    }
+
+    /// Structure of the selected atoms only, `iatoms` start from 0 and should be sorted. The
+    /// species without selected atoms are dropped.
+    pub fn select_atoms(&self, iatoms: &[usize]) -> Self {
+        let symbols = self.symbols();
+        let mut ion_types: Vec<String> = vec![];
+        let mut ions_per_type: Vec<i32> = vec![];
+        for i in iatoms.iter() {
+            if ion_types.last() == Some(&symbols[*i]) {
+                *ions_per_type.last_mut().unwrap() += 1;
+            } else {
+                ion_types.push(symbols[*i].clone());
+                ions_per_type.push(1);
+            }
+        }
+        Self {
+            cell: self.cell,
+            ion_types,
+            ions_per_type,
+            car_pos: iatoms.iter().map(|i| self.car_pos[*i]).collect(),
+            frac_pos: iatoms.iter().map(|i| self.frac_pos[*i]).collect(),
+        }
+    }
+
+    /// Diagonal supercell of `dim` times along each lattice vector. The images of each atom are
+    /// adjacent, thus the atoms of one species stay together.
+    pub fn supercell(&self, dim: [usize; 3]) -> Self {
+        let cell = [0, 1, 2].map(|i| self.cell[i].map(|x| x * dim[i] as f64));
+        let frac_pos = self.frac_pos.iter()
+            .flat_map(|p| (0 .. dim[0])
+                      .flat_map(move |a| (0 .. dim[1])
+                                .flat_map(move |b| (0 .. dim[2])
+                                          .map(move |c| [(p[0] + a as f64) / dim[0] as f64,
+                                                         (p[1] + b as f64) / dim[1] as f64,
+                                                         (p[2] + c as f64) / dim[2] as f64]))))
+            .collect::<MatX3<f64>>();
+        let car_pos = frac_pos.iter()
+            .map(|p| [0, 1, 2].map(|j| (0 .. 3).map(|k| p[k] * cell[k][j]).sum::<f64>()))
+            .collect();
+        let n = (dim[0] * dim[1] * dim[2]) as i32;
+        Self {
+            cell,
+            ion_types: self.ion_types.clone(),
+            ions_per_type: self.ions_per_type.iter().map(|x| x * n).collect(),
+            car_pos,
+            frac_pos,
+        }
+    }
+
     pub fn save_as_poscar(self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
         let mut f = fs::OpenOptions::new()
             .create(true)
@@ -512,9 +1047,90 @@ impl Vibrations {
         info!("Saving mode #{:4} as {:?} ...", index+1, &fname);
         _save_as_xsf_helper(&fname, &self.structure, &self.modes[index].dxdydz)
     }
+
+    /// Assigns each mode of a diagonal `supercell` of the primitive cell to the primitive q-point
+    /// with the largest Fourier weight of its eigenvector. Atoms are mapped to the primitive cell
+    /// by their fractional coordinates scaled by the supercell, atoms of the same species at the
+    /// same position within `tolerance` form one sublattice. The weights of all the commensurate
+    /// q-points sum to 1, a small largest weight indicates the mode is a mixture.
+    pub fn fold_qpoints(&self, supercell: [usize; 3], tolerance: f64) -> Vec<QFolding> {
+        let symbols = self.structure.symbols();
+        let n = supercell.map(|x| x.max(1) as i64);
+
+        // Primitive cell vector and sublattice of each atom
+        let mut sites: Vec<(String, [f64; 3])> = vec![];
+        let mapping = self.structure.frac_pos.iter()
+            .zip(symbols.iter())
+            .map(|(p, s)| {
+                let scaled = [0, 1, 2].map(|i| p[i].rem_euclid(1.0) * n[i] as f64);
+                let cell = [0, 1, 2].map(|i| (scaled[i] + tolerance).floor() as i64);
+                let reduced = [0, 1, 2].map(|i| scaled[i] - cell[i] as f64);
+                let isite = sites.iter()
+                    .position(|(t, r)| t == s && (0 .. 3).all(|i| {
+                        let d = r[i] - reduced[i];
+                        (d - d.round()).abs() < tolerance
+                    }))
+                    .unwrap_or_else(|| {
+                        sites.push((s.clone(), reduced));
+                        sites.len() - 1
+                    });
+                (isite, cell)
+            })
+            .collect::<Vec<_>>();
+
+        let qpoints = (0 .. n[0]).flat_map(|a| (0 .. n[1]).flat_map(move |b| (0 .. n[2]).map(move |c| [a, b, c])))
+            .map(|q| [0, 1, 2].map(|i| {
+                // folded into (-0.5, 0.5]
+                let x = q[i] as f64 / n[i] as f64;
+                if x > 0.5 + 1E-8 { x - 1.0 } else { x }
+            }))
+            .collect::<Vec<[f64; 3]>>();
+
+        self.modes.iter()
+            .map(|v| {
+                let total = v.dxdydz.iter().flatten().map(|x| x * x).sum::<f64>();
+                let weights = qpoints.iter()
+                    .map(|q| {
+                        let mut amp = vec![[(0.0f64, 0.0f64); 3]; sites.len()];
+                        for (d, (isite, cell)) in v.dxdydz.iter().zip(mapping.iter()) {
+                            let phase = 2.0 * std::f64::consts::PI * (0 .. 3).map(|i| q[i] * cell[i] as f64).sum::<f64>();
+                            for (a, x) in amp[*isite].iter_mut().zip(d.iter()) {
+                                a.0 += x * phase.cos();
+                                a.1 += x * phase.sin();
+                            }
+                        }
+                        let ncells = mapping.len() as f64 / sites.len() as f64;
+                        amp.iter().flatten().map(|(re, im)| re * re + im * im).sum::<f64>() / ncells / total
+                    })
+                    .collect::<Vec<f64>>();
+                let (iq, weight) = weights.iter()
+                    .enumerate()
+                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+                    .map(|(i, w)| (i, *w))
+                    .unwrap();
+                QFolding { q: qpoints[iq], weight }
+            })
+            .collect()
+    }
+}
+
+
+// Primitive q-point of a supercell mode in the reciprocal basis of the primitive cell
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct QFolding {
+    pub q      : [f64; 3],
+    pub weight : f64,  // Fourier weight of the eigenvector at q, in [0, 1]
 }
 
-pub struct PrintAllVibFreqs(Vec<Vibration>);
+pub struct PrintAllVibFreqs(Vec<Vibration>, Option<Vec<QFolding>>);
+
+impl PrintAllVibFreqs {
+    pub fn with_qpoints(mut self, qpoints: Vec<QFolding>) -> Self {
+        assert_eq!(qpoints.len(), self.0.len(), "Inconsistent numbers of modes and q-points");
+        self.1 = Some(qpoints);
+        self
+    }
+}
 
 impl fmt::Display for PrintAllVibFreqs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -527,25 +1143,333 @@ impl fmt::Display for PrintAllVibFreqs {
             } else {
                 "False".bright_green()
             };
-            writeln!(f, "  ModeIndex: {}  Frequency/cm-1:  {}  IsImagine: {}",
+            write!(f, "  ModeIndex: {}  Frequency/cm-1:  {}  IsImagine: {}",
                      idxstr, freqstr, imagstr)?;
+            if let Some(q) = self.1.as_ref().map(|q| q[i]) {
+                write!(f, "  q: ({:6.3} {:6.3} {:6.3})  Weight: {}",
+                       q.q[0], q.q[1], q.q[2], format!("{:5.3}", q.weight).bright_yellow())?;
+            }
+            writeln!(f)?;
         }
         Ok(())
     }
     
 }
 
+#[derive(Serialize)]
+struct _VibFreq {
+    index      : usize,
+    freq       : f64,
+    is_imagine : bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qpoint     : Option<QFolding>,
+}
+
+impl Serialize for PrintAllVibFreqs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.iter()
+            .enumerate()
+            .map(|(i, v)| _VibFreq {
+                index: i + 1,
+                freq: v.freq,
+                is_imagine: v.is_imagine,
+                qpoint: self.1.as_ref().map(|q| q[i]),
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl Tabular for PrintAllVibFreqs {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["index", "freq", "is_imagine"];
+        if self.1.is_some() {
+            ret.extend(["qa", "qb", "qc", "weight"]);
+        }
+        ret.into_iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let mut row = vec![(i + 1).to_string(), format!("{:.5}", v.freq), v.is_imagine.to_string()];
+                if let Some(q) = self.1.as_ref().map(|q| q[i]) {
+                    row.extend(q.q.iter().map(|x| format!("{:.4}", x)));
+                    row.push(format!("{:.4}", q.weight));
+                }
+                row
+            })
+            .collect()
+    }
+}
+
 impl From<Vibrations> for PrintAllVibFreqs {
     fn from(vibs: Vibrations) -> Self {
-        Self(vibs.modes)
+        Self(vibs.modes, None)
+    }
+}
+
+
+// Energy change of each SCF step in eV, one row per ionic step
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScfHistory(pub Vec<Vec<f64>>);
+
+impl From<&[IonicIteration]> for ScfHistory {
+    fn from(iters: &[IonicIteration]) -> Self {
+        Self(iters.iter().map(|it| it.scf_de.clone()).collect())
+    }
+}
+
+impl ScfHistory {
+    /// Plots log10(|dE|) against the SCF steps, one curve for each ionic step.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "SCF convergence",
+                "xaxis": {"title": "SCF step"},
+                "yaxis": {"title": "log10(|dE|/eV)"},
+            }));
+        for (i, de) in self.0.iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines+markers",
+                "name": format!("step {}", i + 1),
+                "x": (1 ..= de.len()).collect::<Vec<_>>(),
+                "y": de.iter().map(|x| x.abs().log10()).collect::<Vec<_>>(),
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+impl fmt::Display for ScfHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "  #Step #SCF  Log10(|dE|/eV) of each SCF step".bright_green())?;
+        for (i, de) in self.0.iter().enumerate() {
+            let lgde = de.iter()
+                .map(|x| format!(" {:5.1}", x.abs().log10()))
+                .collect::<String>();
+            writeln!(f, "{:7} {}{}", i + 1, format!("{:4}", de.len()).bright_yellow(), lgde)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tabular for ScfHistory {
+    fn headers(&self) -> Vec<String> {
+        vec!["step".to_string(), "iscf".to_string(), "de".to_string()]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .enumerate()
+            .flat_map(|(i, de)| {
+                de.iter()
+                  .enumerate()
+                  .map(move |(j, x)| vec![(i + 1).to_string(), (j + 1).to_string(), format!("{:.7E}", x)])
+            })
+            .collect()
+    }
+}
+
+
+// Contributions to the free energy at the end of each ionic step, steps without the energy
+// block printed are skipped
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EnergyHistory(pub Vec<(usize, EnergyTerms)>);
+
+impl From<&[IonicIteration]> for EnergyHistory {
+    fn from(iters: &[IonicIteration]) -> Self {
+        Self(iters.iter()
+             .enumerate()
+             .filter_map(|(i, it)| it.energy_terms.clone().map(|t| (i + 1, t)))
+             .collect())
+    }
+}
+
+impl EnergyHistory {
+    const NAMES: [&'static str; 11] = ["PSCENC", "TEWEN", "DENC", "EXHF", "XCENC", "PAW_DC",
+                                       "EENTRO", "EBANDS", "EATOM", "Ediel_sol", "TOTEN"];
+
+    fn _values(t: &EnergyTerms) -> [f64; 11] {
+        [t.pscenc, t.tewen, t.denc, t.exhf, t.xcenc, t.paw_dc[0] + t.paw_dc[1],
+         t.eentro, t.ebands, t.eatom, t.ediel_sol, t.total()]
+    }
+
+    /// Plots the changes of the terms relative to the first step, drifting terms stand out.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Energy terms",
+                "xaxis": {"title": "Ionic step"},
+                "yaxis": {"title": "E - E(first step) (eV)"},
+            }));
+        let first = match self.0.first() {
+            Some((_, t)) => Self::_values(t),
+            None => return plot.save_html(path),
+        };
+        for (i, name) in Self::NAMES.iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines+markers",
+                "name": name,
+                "x": self.0.iter().map(|(s, _)| *s).collect::<Vec<_>>(),
+                "y": self.0.iter().map(|(_, t)| Self::_values(t)[i] - first[i]).collect::<Vec<_>>(),
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+impl fmt::Display for EnergyHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = Self::NAMES.iter()
+            .map(|n| format!(" {:>13}", n))
+            .collect::<String>();
+        writeln!(f, "{}", format!("  #Step{}  DipoleCorr", header).bright_green())?;
+        for (step, t) in self.0.iter() {
+            let values = Self::_values(t);
+            let terms = values[.. 10].iter()
+                .map(|x| format!(" {:13.6}", x))
+                .collect::<String>();
+            let dipole = t.dipole_correction.map(|x| format!("{:11.6}", x)).unwrap_or_else(|| format!("{:>11}", "-"));
+            writeln!(f, "{:7}{} {} {}", step, terms, format!("{:13.6}", values[10]).bright_yellow(), dipole)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tabular for EnergyHistory {
+    fn headers(&self) -> Vec<String> {
+        ["step", "pscenc", "tewen", "denc", "exhf", "xcenc", "paw_dc1", "paw_dc2", "eentro",
+         "ebands", "eatom", "ediel_sol", "toten", "dipole_correction", "dipole_moment"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .map(|(step, t)| vec![
+                step.to_string(),
+                format!("{:.8}", t.pscenc),
+                format!("{:.8}", t.tewen),
+                format!("{:.8}", t.denc),
+                format!("{:.8}", t.exhf),
+                format!("{:.8}", t.xcenc),
+                format!("{:.8}", t.paw_dc[0]),
+                format!("{:.8}", t.paw_dc[1]),
+                format!("{:.8}", t.eentro),
+                format!("{:.8}", t.ebands),
+                format!("{:.8}", t.eatom),
+                format!("{:.8}", t.ediel_sol),
+                format!("{:.8}", t.total()),
+                t.dipole_correction.map(|x| format!("{:.6}", x)).unwrap_or_default(),
+                t.dipole_moment.map(|d| format!("{:.6} {:.6} {:.6}", d[0], d[1], d[2])).unwrap_or_default(),
+            ])
+            .collect()
     }
 }
 
 
 #[cfg(test)]
+#[allow(clippy::excessive_precision, clippy::useless_conversion)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_xdatcar() {
+        let input = "\
+H2
+           1
+     2.000000    0.000000    0.000000
+     0.000000    2.000000    0.000000
+     0.000000    0.000000    2.000000
+    H
+    2
+Direct configuration=     1
+  0.00000000  0.00000000  0.00000000
+  0.50000000  0.50000000  0.50000000
+Direct configuration=     2
+  0.10000000  0.00000000  0.00000000
+  0.50000000  0.50000000  0.50000000
+H2
+          -1
+     1.000000    0.000000    0.000000
+     0.000000    1.000000    0.000000
+     0.000000    0.000000    1.000000
+    H
+    2
+Direct configuration=     3
+  0.00000000  0.00000000  0.00000000
+  0.50000000  0.50000000  0.25000000
+";
+        let traj = Trajectory::parse_xdatcar(input).unwrap();
+        assert_eq!(traj.0.len(), 3);
+        assert_eq!(traj.0[1].car_pos[0], [0.2, 0.0, 0.0]);
+        assert_eq!(traj.0[2].cell[2], [0.0, 0.0, 1.0]);
+        assert_eq!(traj.0[2].car_pos[1], [0.5, 0.5, 0.25]);
+        assert!(Trajectory::parse_xdatcar("Direct configuration=     1\n").is_err());
+
+        assert_eq!(Trajectory::count_xdatcar_frames(input), 3);
+        let traj = Trajectory::parse_xdatcar_frames(input, |i| i % 2 == 1).unwrap();
+        assert_eq!(traj.0.len(), 2);
+        assert_eq!(traj.0[1].cell[2], [0.0, 0.0, 1.0]);
+
+        let s = traj.0[1].select_atoms(&[1]);
+        assert_eq!((s.ion_types, s.ions_per_type), (vec!["H".to_string()], vec![1]));
+        assert_eq!(s.frac_pos, vec![[0.5, 0.5, 0.25]]);
+    }
+
+    #[test]
+    fn test_unwrap_trajectory() {
+        let frame = |x: f64| Structure {
+            cell: [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]],
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![1],
+            car_pos: vec![[x * 2.0, 1.0, 1.0]],
+            frac_pos: vec![[x, 0.5, 0.5]],
+        };
+        // crossing the boundary forwards, then backwards
+        let traj = Trajectory(vec![frame(0.8), frame(0.95), frame(0.1), frame(0.3), frame(0.9)]);
+        let unwrapped = traj.unwrapped();
+        let xs = unwrapped.0.iter().map(|s| s.frac_pos[0][0]).collect::<Vec<_>>();
+        for (x, r) in xs.iter().zip([0.8, 0.95, 1.1, 1.3, 0.9].iter()) {
+            assert!((x - r).abs() < 1E-10);
+        }
+        assert!((unwrapped.0[2].car_pos[0][0] - 2.2).abs() < 1E-10);
+        assert_eq!(unwrapped.0[2].car_pos[0][1], 1.0);
+    }
+
+    #[test]
+    fn test_remove_drift_and_align() {
+        let frame = |pos: MatX3<f64>| {
+            let cell = [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]];
+            Structure {
+                frac_pos: _car_to_frac(&cell, &pos),
+                cell,
+                ion_types: vec!["O".to_string(), "H".to_string()],
+                ions_per_type: vec![1, 2],
+                car_pos: pos,
+            }
+        };
+        let water = vec![[5.0, 5.0, 5.0], [5.8, 5.6, 5.0], [4.2, 5.6, 5.0]];
+        let masses = [16.0, 1.0, 1.0];
+
+        // translated across the boundary along x, and rotated by 90 degrees around z
+        let moved = water.iter().map(|p| [(p[0] + 4.5) % 10.0, p[1] + 1.0, p[2]]).collect::<MatX3<f64>>();
+        let rotated = water.iter().map(|p| [6.0 - (p[1] - 5.0), 5.0 + (p[0] - 5.0), p[2]]).collect::<MatX3<f64>>();
+        let traj = Trajectory(vec![frame(water.clone()), frame(moved)]);
+
+        let fixed = traj.unwrapped().without_com_drift(Some(&masses));
+        assert!(fixed.0[1].car_pos.iter().flatten().zip(water.iter().flatten()).all(|(a, b)| (a - b).abs() < 1E-8));
+
+        let traj = Trajectory(vec![frame(water.clone()), frame(rotated)]);
+        let aligned = traj.aligned_to(&traj.0[0], Some(&masses));
+        for s in aligned.0.iter() {
+            assert!(s.car_pos.iter().flatten().zip(water.iter().flatten()).all(|(a, b)| (a - b).abs() < 1E-8));
+        }
+    }
+
     #[test]
     fn test_structure_to_poscar() {
         let s = Structure {
@@ -578,6 +1502,16 @@ Cartesian
                                           [ -5.0,   4.0,  1.0]]);
     }
 
+    #[test]
+    fn test_lattice_parameters() {
+        let cell = [[2.0, 0.0, 0.0],
+                    [1.0, 3.0f64.sqrt(), 0.0],
+                    [0.0, 0.0, 5.0]];
+        let v = _lattice_parameters(&cell);
+        let expected = [2.0, 2.0, 5.0, 90.0, 90.0, 60.0];
+        assert!(v.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1E-8), "{:?}", v);
+    }
+
     #[test]
     #[should_panic]
     fn test_inv_3x3_singular() {
@@ -665,6 +1599,39 @@ Cartesian
         }
     }
 
+    #[test]
+    fn test_fold_qpoints() {
+        // Chain of 4 atoms along a in a 4x1x1 supercell, with modes at q = 0, 1/4 and 1/2
+        let structure = Structure {
+            cell: [[8.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]],
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![4],
+            car_pos: vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [4.0, 0.0, 0.0], [6.0, 0.0, 0.0]],
+            frac_pos: vec![[0.0, 0.0, 0.0], [0.25, 0.0, 0.0], [0.5, 0.0, 0.0], [0.7499, 0.0, 0.0]],
+        };
+        let mode = |d: [f64; 4]| Vibration::new(100.0, d.iter().map(|x| [*x, 0.0, 0.0]).collect(), false);
+        let vibs = Vibrations {
+            modes: vec![
+                mode([0.5, 0.5, 0.5, 0.5]),
+                mode([0.5, -0.5, 0.5, -0.5]),
+                mode([0.7, 0.0, -0.7, 0.0]),
+            ],
+            structure,
+        };
+
+        let folding = vibs.fold_qpoints([4, 1, 1], 0.05);
+        assert_eq!(folding[0].q, [0.0, 0.0, 0.0]);
+        assert_eq!(folding[1].q, [0.5, 0.0, 0.0]);
+        assert!((folding[1].weight - 1.0).abs() < 1E-10);
+        // A standing wave of cos(2pi x/4), half at q = 1/4 and half at -1/4
+        assert!((folding[2].q[0].abs() - 0.25).abs() < 1E-10);
+        assert!((folding[2].weight - 0.5).abs() < 1E-10);
+
+        let paf = PrintAllVibFreqs::from(vibs).with_qpoints(folding);
+        assert_eq!(paf.headers().len(), 7);
+        assert_eq!(paf.rows()[1][3 ..], ["0.5000", "0.0000", "0.0000", "1.0000"]);
+    }
+
     #[test]
     #[ignore = "May fail on CI"]
     fn test_print_all_modes() {
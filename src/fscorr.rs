@@ -0,0 +1,234 @@
+use std::f64::consts::PI;
+use std::fmt;
+use colored::Colorize;
+use crate::outcar::Mat33;
+use crate::format::_calc_inv_3x3;
+use crate::summary::_volume;
+use crate::potalign::PlanarAlignment;
+use crate::constants::COULOMB;
+
+
+
+// Complementary error function, with fractional error less than 1.2E-7 (Numerical Recipes)
+pub(crate) fn _erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let ans = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 +
+              t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 +
+              t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { ans } else { 2.0 - ans }
+}
+
+
+fn _norm(v: &[f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+
+// Reciprocal lattice vectors including the 2pi factor, stored as rows
+pub(crate) fn _reciprocal(cell: &Mat33<f64>) -> Mat33<f64> {
+    let inv = _calc_inv_3x3(cell);
+    let mut ret = [[0.0f64; 3]; 3];
+    for i in 0 .. 3 {
+        for j in 0 .. 3 {
+            ret[i][j] = 2.0 * PI * inv[j][i];
+        }
+    }
+    ret
+}
+
+
+// All the non-zero lattice vectors of `basis` shorter than `cutoff`
+fn _lattice_vectors(basis: &Mat33<f64>, cutoff: f64) -> Vec<[f64; 3]> {
+    // |n_i| <= cutoff * |b_i| / 2pi, where b_i is the dual vector of basis vector a_i
+    let dual = _reciprocal(basis);
+    let nmax = dual.iter()
+        .map(|b| (cutoff * _norm(b) / (2.0 * PI)).ceil() as i64)
+        .collect::<Vec<i64>>();
+
+    let mut ret = vec![];
+    for n0 in -nmax[0] ..= nmax[0] {
+        for n1 in -nmax[1] ..= nmax[1] {
+            for n2 in -nmax[2] ..= nmax[2] {
+                if n0 == 0 && n1 == 0 && n2 == 0 { continue; }
+                let (n0, n1, n2) = (n0 as f64, n1 as f64, n2 as f64);
+                let v = [
+                    n0 * basis[0][0] + n1 * basis[1][0] + n2 * basis[2][0],
+                    n0 * basis[0][1] + n1 * basis[1][1] + n2 * basis[2][1],
+                    n0 * basis[0][2] + n1 * basis[1][2] + n2 * basis[2][2],
+                ];
+                if _norm(&v) <= cutoff {
+                    ret.push(v);
+                }
+            }
+        }
+    }
+    ret
+}
+
+
+/// Electrostatic energy of a unit point charge in a periodic cell with compensating background,
+/// evaluated by Ewald summation, in eV. It equals to -alpha_M / (2L) for cubic cells.
+pub fn madelung_energy(cell: &Mat33<f64>) -> f64 {
+    let volume = _volume(cell).abs();
+    let eta = PI.sqrt() / volume.powf(1.0 / 3.0);
+    let rcut = 6.0 / eta;
+    let gcut = 12.0 * eta;
+
+    let ereal = _lattice_vectors(cell, rcut).iter()
+        .map(|r| {
+            let d = _norm(r);
+            _erfc(eta * d) / d
+        })
+        .sum::<f64>() * 0.5;
+
+    let erecip = _lattice_vectors(&_reciprocal(cell), gcut).iter()
+        .map(|g| {
+            let g2 = g[0] * g[0] + g[1] * g[1] + g[2] * g[2];
+            (-g2 / (4.0 * eta * eta)).exp() / g2
+        })
+        .sum::<f64>() * 2.0 * PI / volume;
+
+    let eself = -eta / PI.sqrt();
+    let ebackground = -PI / (2.0 * volume * eta * eta);
+
+    (ereal + erecip + eself + ebackground) * COULOMB
+}
+
+
+/// First order Makov-Payne correction q^2 alpha_M / (2 epsilon L), in eV
+pub fn makov_payne(cell: &Mat33<f64>, charge: f64, epsilon: f64) -> f64 {
+    -charge * charge * madelung_energy(cell) / epsilon
+}
+
+
+/// Lattice energy correction E_iso - E_periodic of Gaussian model charge
+/// rho(r) = q / (pi^1.5 beta^3) exp(-r^2 / beta^2), in eV.
+pub fn gaussian_lattice_energy(cell: &Mat33<f64>, charge: f64, epsilon: f64, beta: f64) -> f64 {
+    assert!(beta > 0.0, "Width of Gaussian model charge should be positive");
+    let volume = _volume(cell).abs();
+    let gcut = 60.0f64.sqrt() / beta;
+
+    let eiso = charge * charge / (epsilon * beta * (2.0 * PI).sqrt());
+    let eper = _lattice_vectors(&_reciprocal(cell), gcut).iter()
+        .map(|g| {
+            let g2 = g[0] * g[0] + g[1] * g[1] + g[2] * g[2];
+            (-g2 * beta * beta / 2.0).exp() / g2
+        })
+        .sum::<f64>() * 2.0 * PI * charge * charge / (epsilon * volume);
+
+    (eiso - eper) * COULOMB
+}
+
+
+/// Planar averaged potential energy of electrons generated by the Gaussian model charge,
+/// in the same convention as LOCPOT. `center` is the fractional coordinate of defect along
+/// `axis`, and the potential is evaluated at `npoints` evenly spaced planes.
+pub fn gaussian_planar_potential(cell: &Mat33<f64>, axis: usize, center: f64, npoints: usize,
+                                 charge: f64, epsilon: f64, beta: f64) -> Vec<f64> {
+    let volume = _volume(cell).abs();
+    let b = _norm(&_reciprocal(cell)[axis]);
+    let mmax = (60.0f64.sqrt() / beta / b).ceil() as usize;
+
+    (0 .. npoints)
+        .map(|i| {
+            let s = i as f64 / npoints as f64 - center;
+            let phi = (1 ..= mmax)
+                .map(|m| {
+                    let g = m as f64 * b;
+                    let g2 = g * g;
+                    2.0 * (-g2 * beta * beta / 4.0).exp() / g2 * (2.0 * PI * m as f64 * s).cos()
+                })
+                .sum::<f64>() * 4.0 * PI * charge / (epsilon * volume);
+            -phi * COULOMB
+        })
+        .collect()
+}
+
+
+// Freysoldt-Neugebauer-Van de Walle correction with Gaussian model charge
+#[derive(Clone, Debug, PartialEq)]
+pub struct FnvCorrection {
+    pub charge  : f64,
+    pub elat    : f64,       // E_iso - E_periodic of the model charge
+    pub dv      : f64,       // averaged short range potential in the sampling window
+    pub vmodel  : Vec<f64>,  // planar averaged model potential
+}
+
+impl FnvCorrection {
+    /// The total correction is `elat + charge * dv`, where `dv` replaces the plain potential
+    /// alignment of `pa`.
+    pub fn new(pa: &PlanarAlignment, cell: &Mat33<f64>, charge: f64, epsilon: f64, beta: f64) -> Self {
+        let elat = gaussian_lattice_energy(cell, charge, epsilon, beta);
+        let vmodel = gaussian_planar_potential(cell, pa.axis, pa.center, pa.coords.len(), charge, epsilon, beta);
+
+        let window = pa.window();
+        let dv = window.iter()
+            .map(|&i| pa.defect[i] - pa.host[i] - vmodel[i])
+            .sum::<f64>() / window.len() as f64;
+
+        Self {
+            charge,
+            elat,
+            dv,
+            vmodel,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.elat + self.charge * self.dv
+    }
+}
+
+impl fmt::Display for FnvCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Freysoldt-Neugebauer-Van de Walle correction".bright_green())?;
+        writeln!(f, "  E_lat = {:.6} eV  dV_sr = {:.6} eV", self.elat, self.dv)?;
+        writeln!(f, "  E_corr = E_lat + q * dV_sr = {} eV", format!("{:.6}", self.total()).bright_yellow())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erfc() {
+        assert!((_erfc(0.0) - 1.0).abs() < 1E-7);
+        assert!((_erfc(1.0) - 0.157299207).abs() < 1E-7);
+        assert!((_erfc(-0.5) - 1.520499878).abs() < 1E-7);
+    }
+
+    #[test]
+    fn test_madelung_energy() {
+        // Madelung constant of simple cubic lattice with compensating background
+        let cell = [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]];
+        let alpha = 2.8372974794;
+        assert!((madelung_energy(&cell) + alpha / 20.0 * COULOMB).abs() < 1E-5);
+        assert!((makov_payne(&cell, 2.0, 10.0) - 4.0 * alpha / 200.0 * COULOMB).abs() < 1E-5);
+
+        // Non-orthogonal cell of the same lattice
+        let cell = [[10.0, 0.0, 0.0], [10.0, 10.0, 0.0], [0.0, 10.0, 10.0]];
+        assert!((madelung_energy(&cell) + alpha / 20.0 * COULOMB).abs() < 1E-5);
+    }
+
+    #[test]
+    fn test_gaussian_lattice_energy() {
+        // Approaches the point charge limit when the Gaussian is much narrower than the cell
+        let cell = [[20.0, 0.0, 0.0], [0.0, 20.0, 0.0], [0.0, 0.0, 20.0]];
+        let emp = makov_payne(&cell, 1.0, 1.0);
+        let egauss = gaussian_lattice_energy(&cell, 1.0, 1.0, 1.0);
+        assert!((emp - egauss).abs() / emp < 1E-2);
+    }
+
+    #[test]
+    fn test_gaussian_planar_potential() {
+        let cell = [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]];
+        let v = gaussian_planar_potential(&cell, 2, 0.0, 100, 1.0, 1.0, 1.0);
+        // Zero average, attractive to electrons near the positive charge, symmetric about it
+        assert!(v.iter().sum::<f64>().abs() < 1E-8);
+        assert!(v[0] < 0.0 && v[50] > 0.0);
+        assert!((v[1] - v[99]).abs() < 1E-8);
+    }
+}
@@ -0,0 +1,363 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use std::collections::BTreeMap;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use rayon::prelude::*;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::format::{
+    Structure,
+    Trajectory,
+};
+use crate::neighbor::{
+    Neighbor,
+    NeighborList,
+};
+use crate::diffusion::_autocorrelation;
+
+
+/// Geometric criteria of hydrogen bonds D-H...A: the hydrogen is covalently bonded to its
+/// nearest donor within `dh_cutoff`, the donor-acceptor distance is shorter than `da_cutoff`,
+/// and the angle H-D-A is smaller than `angle`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HBondCriteria {
+    pub donors    : Vec<String>,
+    pub acceptors : Vec<String>,
+    pub hydrogens : Vec<String>,
+    pub dh_cutoff : f64,   // in Angstrom
+    pub da_cutoff : f64,   // in Angstrom
+    pub angle     : f64,   // in degree
+}
+
+impl Default for HBondCriteria {
+    fn default() -> Self {
+        Self {
+            donors: vec!["O".to_string()],
+            acceptors: vec!["O".to_string()],
+            hydrogens: vec!["H".to_string()],
+            dh_cutoff: 1.25,
+            da_cutoff: 3.5,
+            angle: 30.0,
+        }
+    }
+}
+
+
+/// Hydrogen bond D-H...A by the atom indices, starting from 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct HBond {
+    pub donor    : usize,
+    pub hydrogen : usize,
+    pub acceptor : usize,
+}
+
+
+// Cartesian vector from atom `i` to its neighbor
+fn _bond_vector(structure: &Structure, i: usize, nb: &Neighbor) -> [f64; 3] {
+    let d = [0, 1, 2].map(|k| structure.frac_pos[nb.index][k] + nb.image[k] as f64 - structure.frac_pos[i][k]);
+    [0, 1, 2].map(|j| (0 .. 3).map(|k| d[k] * structure.cell[k][j]).sum::<f64>())
+}
+
+
+/// Hydrogen bonds of the structure, the periodic images are included.
+pub fn find_hbonds(structure: &Structure, criteria: &HBondCriteria) -> Vec<HBond> {
+    let symbols = structure.symbols();
+    let neighbors = NeighborList::new(structure, criteria.dh_cutoff.max(criteria.da_cutoff)).neighbors;
+    let cos_max = criteria.angle.to_radians().cos();
+    let norm = |v: &[f64; 3]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+    let mut ret = vec![];
+    for (ih, nbs) in neighbors.iter().enumerate() {
+        if !criteria.hydrogens.contains(&symbols[ih]) {
+            continue;
+        }
+        // neighbors are sorted by distance, thus the first donor is the nearest one
+        let donor = nbs.iter()
+            .take_while(|nb| nb.distance < criteria.dh_cutoff)
+            .find(|nb| criteria.donors.contains(&symbols[nb.index]));
+        let id = match donor {
+            Some(nb) => nb.index,
+            None => continue,
+        };
+        let dh = neighbors[id].iter()
+            .find(|nb| nb.index == ih && nb.distance < criteria.dh_cutoff)
+            .map(|nb| _bond_vector(structure, id, nb))
+            .unwrap();
+
+        for nb in neighbors[id].iter().take_while(|nb| nb.distance < criteria.da_cutoff) {
+            if nb.index == id || !criteria.acceptors.contains(&symbols[nb.index]) {
+                continue;
+            }
+            let da = _bond_vector(structure, id, nb);
+            let cos = dh.iter().zip(da.iter()).map(|(a, b)| a * b).sum::<f64>() / (norm(&dh) * norm(&da));
+            if cos >= cos_max {
+                ret.push(HBond { donor: id, hydrogen: ih, acceptor: nb.index });
+            }
+        }
+    }
+    ret.sort_unstable();
+    ret.dedup();
+    ret
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HBondKind {
+    pub label   : String,         // e.g. "O-H...N"
+    pub counts  : Vec<usize>,     // per frame
+    pub average : f64,
+}
+
+
+/// Hydrogen bond statistics over a trajectory. The lifetimes are the integrals of the
+/// intermittent and continuous autocorrelation functions C(t) = <h(0) h(t)> / <h>, where h(t) of
+/// one D-H...A bond is 1 if it exists at t. The continuous one requires the bond to exist all the
+/// time from 0 to t. The correlations are calculated up to half of the trajectory, thus the
+/// lifetimes are underestimated if C(t) doesn't decay to zero by then.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HBondAnalysis {
+    pub criteria         : HBondCriteria,
+    pub times            : Vec<f64>,     // in fs
+    pub counts           : Vec<usize>,   // per frame
+    pub average          : f64,
+    pub std              : f64,
+    pub per_donor        : f64,          // average hydrogen bonds donated per donor atom
+    pub kinds            : Vec<HBondKind>,
+    pub nbonds           : usize,        // distinct D-H...A bonds ever found
+    pub acf_intermittent : Vec<f64>,     // for the lags in `times`
+    pub acf_continuous   : Vec<f64>,
+    pub tau_intermittent : f64,          // in fs
+    pub tau_continuous   : f64,          // in fs
+}
+
+impl HBondAnalysis {
+    /// `dt` is the time between frames in fs.
+    pub fn new(traj: &Trajectory, criteria: &HBondCriteria, dt: f64) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let first = traj.0.first().ok_or_else(|| err("Empty trajectory".to_string()))?;
+        let symbols = first.symbols();
+        for (name, species) in [("donor", &criteria.donors), ("acceptor", &criteria.acceptors), ("hydrogen", &criteria.hydrogens)] {
+            if !species.iter().any(|s| symbols.contains(s)) {
+                return Err(err(format!("No {} species {:?} found in the trajectory, available elements: {:?}", name, species, first.ion_types)));
+            }
+        }
+
+        let nframes = traj.0.len();
+        let frames = traj.0.par_iter().map(|s| find_hbonds(s, criteria)).collect::<Vec<_>>();
+        let counts = frames.iter().map(|b| b.len()).collect::<Vec<usize>>();
+        let average = counts.iter().sum::<usize>() as f64 / nframes as f64;
+        let std = (counts.iter().map(|c| (*c as f64 - average).powi(2)).sum::<f64>() / nframes as f64).sqrt();
+        let ndonors = symbols.iter().filter(|s| criteria.donors.contains(s)).count();
+
+        // Counts of each kind of bonds, ordered by their first appearance
+        let mut kinds: Vec<HBondKind> = vec![];
+        for (it, bonds) in frames.iter().enumerate() {
+            for b in bonds.iter() {
+                let label = format!("{}-{}...{}", symbols[b.donor], symbols[b.hydrogen], symbols[b.acceptor]);
+                let kind = match kinds.iter_mut().find(|k| k.label == label) {
+                    Some(k) => k,
+                    None => {
+                        kinds.push(HBondKind { label, counts: vec![0; nframes], average: 0.0 });
+                        kinds.last_mut().unwrap()
+                    },
+                };
+                kind.counts[it] += 1;
+            }
+        }
+        kinds.iter_mut().for_each(|k| k.average = k.counts.iter().sum::<usize>() as f64 / nframes as f64);
+
+        // Frames where each bond exists
+        let mut history: BTreeMap<HBond, Vec<usize>> = BTreeMap::new();
+        for (it, bonds) in frames.iter().enumerate() {
+            for b in bonds.iter() {
+                history.entry(*b).or_default().push(it);
+            }
+        }
+
+        let nlags = nframes.div_ceil(2);
+        let mut intermittent = vec![0.0; nlags];
+        let mut continuous = vec![0.0; nlags];
+        for present in history.values() {
+            let mut h = vec![0.0; nframes];
+            present.iter().for_each(|it| h[*it] = 1.0);
+            intermittent.iter_mut().zip(_autocorrelation(&h)).for_each(|(c, x)| *c += x);
+
+            // runs of consecutive frames
+            let mut beg = 0;
+            for i in 1 ..= present.len() {
+                if i == present.len() || present[i] != present[i - 1] + 1 {
+                    let len = i - beg;
+                    continuous.iter_mut().take(len).enumerate().for_each(|(m, c)| *c += (len - m) as f64);
+                    beg = i;
+                }
+            }
+        }
+        // normalized by the number of time origins and <h>
+        let norm = intermittent.first().copied().unwrap_or(0.0) / nframes as f64;
+        let normalize = |v: Vec<f64>| -> Vec<f64> {
+            v.into_iter()
+                .enumerate()
+                .map(|(m, c)| if norm > 0.0 { c / (nframes - m) as f64 / norm } else { 0.0 })
+                .collect()
+        };
+        let acf_intermittent = normalize(intermittent);
+        let acf_continuous = normalize(continuous);
+        let integrate = |v: &[f64]| v.windows(2).map(|w| (w[0] + w[1]) / 2.0 * dt).sum::<f64>();
+
+        Ok(Self {
+            criteria: criteria.clone(),
+            times: (0 .. nframes).map(|i| i as f64 * dt).collect(),
+            counts,
+            average,
+            std,
+            per_donor: average / ndonors.max(1) as f64,
+            kinds,
+            nbonds: history.len(),
+            tau_intermittent: integrate(&acf_intermittent),
+            tau_continuous: integrate(&acf_continuous),
+            acf_intermittent,
+            acf_continuous,
+        })
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Hydrogen bonds",
+                "xaxis": {"title": "Time (fs)", "domain": [0.0, 0.48]},
+                "xaxis2": {"title": "Lag time (fs)", "domain": [0.52, 1.0]},
+                "yaxis": {"title": "Number of H-bonds"},
+                "yaxis2": {"title": "C(t)", "anchor": "x2"},
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": "total",
+            "x": self.times,
+            "y": self.counts,
+        }));
+        if self.kinds.len() > 1 {
+            for k in self.kinds.iter() {
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": k.label,
+                    "x": self.times,
+                    "y": k.counts,
+                }));
+            }
+        }
+        for (name, acf) in [("intermittent", &self.acf_intermittent), ("continuous", &self.acf_continuous)] {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": name,
+                "x": &self.times[.. acf.len()],
+                "y": acf,
+                "xaxis": "x2",
+                "yaxis": "y2",
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for HBondAnalysis {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["frame".to_string(), "time".to_string(), "total".to_string()];
+        ret.extend(self.kinds.iter().map(|k| k.label.clone()));
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.counts.iter()
+            .enumerate()
+            .map(|(it, c)| {
+                let mut row = vec![(it + 1).to_string(), format!("{:.2}", self.times[it]), c.to_string()];
+                row.extend(self.kinds.iter().map(|k| k.counts[it].to_string()));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for HBondAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.criteria;
+        writeln!(f, "{}", format!("# D-H < {} A, D...A < {} A, angle H-D-A < {} deg, {} frames",
+                                  c.dh_cutoff, c.da_cutoff, c.angle, self.counts.len()).bright_green())?;
+        writeln!(f, "{}", format!("  {:>12} {:>10}", "Kind", "<N>").bright_green())?;
+        for k in self.kinds.iter() {
+            writeln!(f, "  {:>12} {:10.3}", k.label, k.average)?;
+        }
+        writeln!(f, "{}{}", "# Average H-bonds:        ".bright_green(),
+                 format!("{:.3} +/- {:.3}", self.average, self.std).bright_yellow())?;
+        writeln!(f, "{}{}", "# H-bonds per donor:      ".bright_green(),
+                 format!("{:.3}", self.per_donor).bright_yellow())?;
+        writeln!(f, "{}{}", "# Distinct H-bonds:       ".bright_green(), self.nbonds)?;
+        writeln!(f, "{}{}", "# Intermittent lifetime:  ".bright_green(),
+                 format!("{:.2} fs", self.tau_intermittent).bright_yellow())?;
+        writeln!(f, "{}{}", "# Continuous lifetime:    ".bright_green(),
+                 format!("{:.2} fs", self.tau_continuous).bright_yellow())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Water dimer of O O H H H H, the donor O1-H3 points to O2 if `angle` is small
+    fn _dimer(angle: f64) -> Structure {
+        let car_pos = vec![
+            [0.0, 0.0, 0.0], [2.9, 0.0, 0.0],
+            [0.96 * angle.to_radians().cos(), 0.96 * angle.to_radians().sin(), 0.0], [-0.24, 0.93, 0.0],
+            [3.2, 0.9, 0.0], [3.2, -0.45, 0.78],
+        ];
+        Structure {
+            cell: [[12.0, 0.0, 0.0], [0.0, 12.0, 0.0], [0.0, 0.0, 12.0]],
+            ion_types: vec!["O".to_string(), "H".to_string()],
+            ions_per_type: vec![2, 4],
+            frac_pos: car_pos.iter().map(|p| [p[0] / 12.0, p[1] / 12.0, p[2] / 12.0]).collect(),
+            car_pos,
+        }
+    }
+
+    #[test]
+    fn test_find_hbonds() {
+        let criteria = HBondCriteria::default();
+        assert_eq!(find_hbonds(&_dimer(5.0), &criteria), vec![HBond { donor: 0, hydrogen: 2, acceptor: 1 }]);
+        assert!(find_hbonds(&_dimer(60.0), &criteria).is_empty());
+
+        // across the periodic boundary
+        let mut s = _dimer(5.0);
+        s.car_pos.iter_mut().for_each(|p| p[0] -= 1.5);
+        s.frac_pos = s.car_pos.iter().map(|p| [(p[0] / 12.0).rem_euclid(1.0), p[1] / 12.0, p[2] / 12.0]).collect();
+        assert_eq!(find_hbonds(&s, &criteria).len(), 1);
+    }
+
+    #[test]
+    fn test_hbond_lifetimes() {
+        // h(t) = 1 1 0 1, <h> = 3/4
+        let traj = Trajectory(vec![_dimer(5.0), _dimer(5.0), _dimer(60.0), _dimer(5.0)]);
+        let analysis = HBondAnalysis::new(&traj, &HBondCriteria::default(), 2.0).unwrap();
+        assert_eq!(analysis.counts, vec![1, 1, 0, 1]);
+        assert_eq!(analysis.nbonds, 1);
+        assert_eq!(analysis.kinds[0].label, "O-H...O");
+        assert!((analysis.average - 0.75).abs() < 1E-10);
+        assert!((analysis.per_donor - 0.375).abs() < 1E-10);
+
+        // lag 1: <h(0) h(1)> = 1/3, continuous runs of 2 and 1 give 1/3 as well
+        assert_eq!(analysis.acf_intermittent.len(), 2);
+        assert!((analysis.acf_intermittent[0] - 1.0).abs() < 1E-10);
+        assert!((analysis.acf_intermittent[1] - 4.0 / 9.0).abs() < 1E-10);
+        assert!((analysis.acf_continuous[1] - 4.0 / 9.0).abs() < 1E-10);
+        assert!((analysis.tau_intermittent - (1.0 + 4.0 / 9.0)).abs() < 1E-10);
+
+        let criteria = HBondCriteria { donors: vec!["N".to_string()], ..HBondCriteria::default() };
+        assert!(HBondAnalysis::new(&traj, &criteria, 2.0).is_err());
+    }
+}
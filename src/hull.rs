@@ -0,0 +1,473 @@
+use std::fmt;
+use std::io;
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use colored::Colorize;
+use itertools::Itertools;
+use log::info;
+use crate::outcar::Outcar;
+use crate::traits::Tabular;
+use crate::linalg::solve;
+use crate::template::{
+    ConfigTemplate,
+    TemplateContext,
+};
+
+
+const TOLERANCE: f64 = 1E-8;
+
+
+// Configuration of `rsgrad hull`, e.g.
+//
+// target = "Li2O"              # optional, the stability region of this phase is calculated
+//
+// [[compounds]]
+// name        = "Li"
+// composition = { Li = 2 }     # numbers of atoms in the cell
+// energy      = -3.80          # total energy of the cell in eV
+//
+// [[compounds]]
+// name        = "Li2O"
+// composition = { Li = 8, O = 4 }
+// outcar      = "Li2O/OUTCAR"  # energy(sigma->0) of the last ionic step is used if energy is absent
+//
+// Each element should have at least one elemental compound as the reference.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct HullConfig {
+    #[serde(default)]
+    pub target    : Option<String>,
+    pub compounds : Vec<CompoundEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct CompoundEntry {
+    pub name        : String,
+    pub composition : BTreeMap<String, f64>,
+    #[serde(default)]
+    pub energy      : Option<f64>,
+    #[serde(default)]
+    pub outcar      : Option<PathBuf>,
+}
+
+impl HullConfig {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        toml::from_str(&context)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+impl ConfigTemplate for HullConfig {
+    fn template(ctx: &TemplateContext) -> String {
+        let elements = ctx.elements();
+        let elements = if elements.is_empty() { vec!["A".to_string(), "B".to_string()] } else { elements };
+        let counts = ctx.symbols.as_ref()
+            .map(|s| s.iter().cloned().counts())
+            .unwrap_or_default();
+
+        let mut ret = String::from(r#"# Configuration of `rsgrad hull`, each element needs at least one elemental compound as the reference
+# target = "AB"         # optional, the stability region of this phase is calculated
+"#);
+        for e in elements.iter() {
+            ret += &format!(r#"
+[[compounds]]
+name        = "{e}"
+composition = {{ {e} = 1 }}   # numbers of atoms in the cell
+energy      = 0.0           # total energy of the cell in eV, replace it or give `outcar`
+"#, e = e);
+        }
+        if counts.is_empty() {
+            ret += r#"
+[[compounds]]
+name        = "AB"
+composition = { A = 1, B = 1 }
+outcar      = "AB/OUTCAR"   # energy(sigma->0) of the last ionic step is used if energy is absent
+"#;
+        } else {
+            let name = elements.iter()
+                .map(|e| match counts[e] { 1 => e.clone(), n => format!("{}{}", e, n) })
+                .collect::<String>();
+            let composition = elements.iter()
+                .map(|e| format!("{} = {}", e, counts[e]))
+                .join(", ");
+            ret += &format!(r#"
+[[compounds]]
+name        = "{}"          # composition of POSCAR
+composition = {{ {} }}
+outcar      = "./OUTCAR"    # energy(sigma->0) of the last ionic step is used if energy is absent
+"#, name, composition);
+        }
+        ret
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HullEntry {
+    pub name          : String,
+    pub natoms        : f64,
+    pub fractions     : Vec<f64>,  // atomic fractions of each element
+    pub formation     : f64,       // formation energy in eV/atom
+    pub e_above_hull  : f64,       // in eV/atom, zero for stable phases
+    pub decomposition : Vec<(String, f64)>,  // competing phases and their atomic fractions
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChempotVertex {
+    pub dmu      : Vec<f64>,     // chemical potentials relative to the elemental references, in eV
+    pub limiting : Vec<String>,  // phases in equilibrium with the target at this vertex
+}
+
+
+/// Convex hull of formation energies, and the chemical potential region where the target
+/// phase is stable against decomposition into the elements and other compounds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PhaseDiagram {
+    pub elements : Vec<String>,
+    pub entries  : Vec<HullEntry>,
+    pub target   : Option<String>,
+    pub region   : Vec<ChempotVertex>,  // empty if target is not given or unstable
+}
+
+impl PhaseDiagram {
+    pub fn from_config(config: &HullConfig) -> io::Result<Self> {
+        let mut energies = vec![];
+        for c in config.compounds.iter() {
+            let e = match (c.energy, c.outcar.as_ref()) {
+                (Some(e), _) => e,
+                (None, Some(path)) => {
+                    info!("Parsing OUTCAR file {:?} ...", path);
+                    Outcar::from_file(path)?
+                        .ion_iters.last()
+                        .expect("No ionic steps found in OUTCAR")
+                        .toten_z
+                },
+                (None, None) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Neither energy nor outcar is given for compound '{}'", c.name))),
+            };
+            energies.push(e);
+        }
+
+        let compositions = config.compounds.iter().map(|c| c.composition.clone()).collect::<Vec<_>>();
+        let names = config.compounds.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        Self::new(&names, &compositions, &energies, config.target.as_deref())
+    }
+
+    /// `energies` are the total energies of each compound with the given compositions.
+    pub fn new(names: &[String], compositions: &[BTreeMap<String, f64>], energies: &[f64], target: Option<&str>) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let elements = compositions.iter()
+            .flat_map(|c| c.keys().cloned())
+            .sorted()
+            .dedup()
+            .collect::<Vec<String>>();
+        let nelm = elements.len();
+
+        let natoms = compositions.iter().map(|c| c.values().sum::<f64>()).collect::<Vec<f64>>();
+        let fractions = compositions.iter()
+            .zip(natoms.iter())
+            .map(|(c, n)| elements.iter().map(|e| c.get(e).cloned().unwrap_or(0.0) / n).collect::<Vec<f64>>())
+            .collect::<Vec<_>>();
+        let epa = energies.iter().zip(natoms.iter()).map(|(e, n)| e / n).collect::<Vec<f64>>();
+
+        // Lowest energy per atom of the elemental phases
+        let refs = (0 .. nelm)
+            .map(|ie| {
+                fractions.iter().zip(epa.iter())
+                    .filter(|(x, _)| (x[ie] - 1.0).abs() < TOLERANCE)
+                    .map(|(_, e)| *e)
+                    .fold(None, |acc: Option<f64>, e| Some(acc.map_or(e, |a| a.min(e))))
+                    .ok_or_else(|| invalid(format!("No elemental reference found for {}", elements[ie])))
+            })
+            .collect::<io::Result<Vec<f64>>>()?;
+
+        let formation = fractions.iter().zip(epa.iter())
+            .map(|(x, e)| e - x.iter().zip(refs.iter()).map(|(a, b)| a * b).sum::<f64>())
+            .collect::<Vec<f64>>();
+
+        let entries = (0 .. names.len())
+            .map(|i| {
+                let others = (0 .. names.len()).filter(|&j| j != i).collect::<Vec<_>>();
+                let (ehull, decomp) = _hull_energy(&fractions[i], &fractions, &formation, &others)
+                    .unwrap_or((f64::INFINITY, vec![]));
+                HullEntry {
+                    name: names[i].clone(),
+                    natoms: natoms[i],
+                    fractions: fractions[i].clone(),
+                    formation: formation[i],
+                    e_above_hull: (formation[i] - ehull).max(0.0),
+                    decomposition: decomp.into_iter().map(|(j, w)| (names[j].clone(), w)).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let region = match target {
+            Some(t) => {
+                let it = names.iter().position(|n| n == t)
+                    .ok_or_else(|| invalid(format!("Target phase '{}' not found in compounds", t)))?;
+                if entries[it].e_above_hull > TOLERANCE {
+                    vec![]
+                } else {
+                    _stability_region(it, &elements, names, &fractions, &formation)
+                }
+            },
+            None => vec![],
+        };
+
+        Ok(Self {
+            elements,
+            entries,
+            target: target.map(|s| s.to_string()),
+            region,
+        })
+    }
+
+    pub fn is_stable(&self, i: usize) -> bool {
+        self.entries[i].e_above_hull <= TOLERANCE
+    }
+}
+
+
+// Lowest formation energy at composition `x` from the mixtures of `candidates`. Mixtures of
+// at most `nelm` phases are enumerated, which is enough for the facets of the hull.
+fn _hull_energy(x: &[f64], fractions: &[Vec<f64>], formation: &[f64], candidates: &[usize]) -> Option<(f64, Vec<(usize, f64)>)> {
+    let nelm = x.len();
+    let mut best: Option<(f64, Vec<(usize, f64)>)> = None;
+
+    for k in 1 ..= nelm.min(candidates.len()) {
+        for combo in candidates.iter().cloned().combinations(k) {
+            // Least squares solution of sum_j w_j * x_j = x
+            let ata = combo.iter()
+                .map(|&a| combo.iter().map(|&b| _dot(&fractions[a], &fractions[b])).collect::<Vec<f64>>())
+                .collect::<Vec<_>>();
+            let atb = combo.iter().map(|&a| _dot(&fractions[a], x)).collect::<Vec<f64>>();
+            let w = match solve(ata, &atb) {
+                Some(w) => w,
+                None => continue,
+            };
+            if w.iter().any(|v| *v < -TOLERANCE) { continue; }
+
+            let residual = (0 .. nelm)
+                .map(|ie| combo.iter().zip(w.iter()).map(|(&j, wj)| wj * fractions[j][ie]).sum::<f64>() - x[ie])
+                .fold(0.0f64, |acc, d| acc.max(d.abs()));
+            if residual > 1E-6 { continue; }
+
+            let e = combo.iter().zip(w.iter()).map(|(&j, wj)| wj * formation[j]).sum::<f64>();
+            if best.as_ref().is_none_or(|b| e < b.0 - TOLERANCE) {
+                let decomp = combo.iter().cloned()
+                    .zip(w.iter().cloned())
+                    .filter(|(_, wj)| *wj > TOLERANCE)
+                    .collect();
+                best = Some((e, decomp));
+            }
+        }
+    }
+    best
+}
+
+
+// Vertices of the polytope
+//   sum_i n_i * dmu_i  = dHf(target)     for the target phase, per atom
+//   sum_i x_i * dmu_i <= dHf(j)          for the other phases j, per atom
+//   dmu_i <= 0
+// found by activating nelm-1 of the inequalities.
+fn _stability_region(it: usize, elements: &[String], names: &[String], fractions: &[Vec<f64>], formation: &[f64]) -> Vec<ChempotVertex> {
+    let nelm = fractions[it].len();
+
+    // (coefficients, bound, name of the limiting phase), elements are limited by dmu_i <= 0
+    let mut constraints = (0 .. nelm)
+        .map(|ie| {
+            let mut a = vec![0.0; nelm];
+            a[ie] = 1.0;
+            (a, 0.0, elements[ie].clone())
+        })
+        .collect::<Vec<(Vec<f64>, f64, String)>>();
+    constraints.extend((0 .. names.len())
+        .filter(|&j| j != it && fractions[j].iter().filter(|x| **x > TOLERANCE).count() > 1)
+        .map(|j| (fractions[j].clone(), formation[j], names[j].clone())));
+
+    let mut vertices: Vec<ChempotVertex> = vec![];
+    for combo in (0 .. constraints.len()).combinations(nelm - 1) {
+        let mut a = vec![fractions[it].clone()];
+        let mut b = vec![formation[it]];
+        for &ic in combo.iter() {
+            a.push(constraints[ic].0.clone());
+            b.push(constraints[ic].1);
+        }
+        let dmu = match solve(a, &b) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let feasible = constraints.iter().all(|(a, b, _)| _dot(a, &dmu) <= b + 1E-6);
+        if !feasible { continue; }
+        if vertices.iter().any(|v| v.dmu.iter().zip(dmu.iter()).all(|(x, y)| (x - y).abs() < 1E-6)) {
+            continue;
+        }
+
+        let limiting = constraints.iter()
+            .filter(|(a, b, _)| (_dot(a, &dmu) - b).abs() < 1E-6)
+            .map(|(_, _, name)| name.clone())
+            .collect();
+        vertices.push(ChempotVertex { dmu, limiting });
+    }
+    vertices
+}
+
+
+fn _dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+
+fn _format_decomposition(decomp: &[(String, f64)]) -> String {
+    decomp.iter()
+        .map(|(name, w)| format!("{:.3} {}", w, name))
+        .join(" + ")
+}
+
+
+impl Tabular for PhaseDiagram {
+    fn headers(&self) -> Vec<String> {
+        ["name", "formation", "e_above_hull", "stable", "decomposition"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.entries.iter()
+            .enumerate()
+            .map(|(i, e)| vec![
+                e.name.clone(),
+                format!("{:.4}", e.formation),
+                format!("{:.4}", e.e_above_hull),
+                self.is_stable(i).to_string(),
+                _format_decomposition(&e.decomposition),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for PhaseDiagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Name            dHf(eV/atom)  Ehull(eV/atom)  Decomposition".bright_green())?;
+        for (i, e) in self.entries.iter().enumerate() {
+            let ehull = format!("{:14.4}", e.e_above_hull);
+            let ehull = if self.is_stable(i) { ehull.bright_yellow().to_string() } else { ehull };
+            writeln!(f, "  {:<16} {:12.4} {}  {}", e.name, e.formation, ehull, _format_decomposition(&e.decomposition))?;
+        }
+
+        if let Some(target) = self.target.as_ref() {
+            if self.region.is_empty() {
+                writeln!(f, "{}", format!("# {} is not stable, no chemical potential region found", target).bright_yellow())?;
+            } else {
+                writeln!(f, "{}", format!("# Vertices of the stability region of {}, chemical potentials relative to elements (eV)", target).bright_green())?;
+                let header = self.elements.iter().map(|e| format!("{:>10}", format!("dmu_{}", e))).collect::<String>();
+                writeln!(f, "{}", format!("  {}  Limiting phases", header).bright_green())?;
+                for v in self.region.iter() {
+                    let dmu = v.dmu.iter().map(|x| format!("{:10.4}", x)).collect::<String>();
+                    writeln!(f, "  {}  {}", dmu, v.limiting.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _generate_diagram(target: Option<&str>) -> PhaseDiagram {
+        let input = r#"
+target = "Li2O"
+
+[[compounds]]
+name        = "Li"
+composition = { Li = 2 }
+energy      = -4.0
+
+[[compounds]]
+name        = "O2"
+composition = { O = 2 }
+energy      = -10.0
+
+[[compounds]]
+name        = "Li2O"
+composition = { Li = 2, O = 1 }
+energy      = -20.0
+
+[[compounds]]
+name        = "Li2O2"
+composition = { Li = 2, O = 2 }
+energy      = -26.0
+
+[[compounds]]
+name        = "LiO2"
+composition = { Li = 1, O = 2 }
+energy      = -15.0
+"#;
+        let config: HullConfig = toml::from_str(input).unwrap();
+        let names = config.compounds.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        let comps = config.compounds.iter().map(|c| c.composition.clone()).collect::<Vec<_>>();
+        let energies = config.compounds.iter().map(|c| c.energy.unwrap()).collect::<Vec<_>>();
+        PhaseDiagram::new(&names, &comps, &energies, target).unwrap()
+    }
+
+    #[test]
+    fn test_hull() {
+        let pd = _generate_diagram(None);
+        assert_eq!(pd.elements, vec!["Li", "O"]);
+
+        // Li2O: (-20 - 2*(-2) - (-5)) / 3 = -11/3
+        assert!((pd.entries[2].formation - (-11.0 / 3.0)).abs() < 1E-10);
+        assert!(pd.is_stable(2));
+        assert!(pd.is_stable(3));
+
+        // LiO2: dHf = (-15 + 2 + 10) / 3 = -1, hull at x_O = 2/3 is the mixture of Li2O2 and O2
+        let e = &pd.entries[4];
+        assert!((e.formation - (-1.0)).abs() < 1E-10);
+        let ehull = 2.0 / 3.0 * pd.entries[3].formation;
+        assert!((e.e_above_hull - (e.formation - ehull)).abs() < 1E-10);
+        let names = e.decomposition.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["O2", "Li2O2"]);
+        assert!(pd.region.is_empty());
+    }
+
+    #[test]
+    fn test_hull_template() {
+        let ctx = TemplateContext {
+            orbitals: None,
+            symbols: Some(["Li", "Li", "O"].iter().map(|x| x.to_string()).collect()),
+        };
+        let config: HullConfig = toml::from_str(&HullConfig::template(&ctx)).unwrap();
+        assert_eq!(config.compounds.len(), 3);
+        assert_eq!(config.compounds[2].name, "Li2O");
+        assert_eq!(config.compounds[2].composition["Li"], 2.0);
+        assert!(toml::from_str::<HullConfig>(&HullConfig::template(&TemplateContext::default())).is_ok());
+    }
+
+    #[test]
+    fn test_stability_region() {
+        let pd = _generate_diagram(Some("Li2O"));
+        assert_eq!(pd.region.len(), 2);
+
+        // dHf(Li2O) = 2*dmu_Li + dmu_O = -11 eV per formula, bounded by Li metal and Li2O2
+        let li_rich = pd.region.iter().find(|v| v.dmu[0].abs() < 1E-8).unwrap();
+        assert!((li_rich.dmu[1] - (-11.0)).abs() < 1E-8);
+        assert_eq!(li_rich.limiting, vec!["Li"]);
+        let o_rich = pd.region.iter().find(|v| v.limiting.contains(&"Li2O2".to_string())).unwrap();
+        // 2*dmu_Li + dmu_O = -11 and 2*dmu_Li + 2*dmu_O = 4 * dHf(Li2O2) = -12
+        assert!((o_rich.dmu[1] - (-1.0)).abs() < 1E-8);
+        assert!((o_rich.dmu[0] - (-5.0)).abs() < 1E-8);
+    }
+}
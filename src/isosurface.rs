@@ -0,0 +1,238 @@
+use std::io;
+use std::collections::HashMap;
+use std::path::Path;
+use serde_json::json;
+use crate::chgcar::ChargeDensity;
+use crate::outcar::Mat33;
+use crate::format::Structure;
+use crate::plot::Plot;
+use crate::settings::Settings;
+
+
+// Corners of a grid cube, and the six tetrahedra sharing the diagonal 0-6 which fill the cube.
+// Splitting cubes into tetrahedra avoids the ambiguous cases of the classic marching cubes
+// tables, at the cost of about twice as many triangles.
+const CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0],
+    [0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1],
+];
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 5, 1, 6], [0, 1, 2, 6], [0, 2, 3, 6],
+    [0, 3, 7, 6], [0, 7, 4, 6], [0, 4, 5, 6],
+];
+
+const COLORS: [&str; 8] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+
+/// Triangulated isosurface in Cartesian coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Isosurface {
+    pub level     : f64,
+    pub vertices  : Vec<[f64; 3]>,
+    pub triangles : Vec<[usize; 3]>,
+}
+
+impl Isosurface {
+    /// Extracts the isosurface of periodic grid data by marching cubes, where each cube is split
+    /// into tetrahedra. `data` is in [ngz][ngy][ngx] order with x running fastest, and every
+    /// `stride`th grid point along each axis is used.
+    pub fn from_grid(data: &[f64], ngrid: [usize; 3], cell: &Mat33<f64>, level: f64, stride: usize) -> Self {
+        assert!(stride > 0, "Stride should be positive");
+        assert_eq!(data.len(), ngrid.iter().product::<usize>(), "Inconsistent grid size");
+
+        // Grid points used after striding, the last cube wraps to the first point periodically
+        let ns = [0, 1, 2].map(|i| ngrid[i].div_ceil(stride));
+        let gidx = |i: [usize; 3]| -> usize {
+            let [x, y, z] = [0, 1, 2].map(|a| (i[a] % ns[a]) * stride);
+            x + ngrid[0] * (y + ngrid[1] * z)
+        };
+        // Unwrapped fractional coordinates, the points wrapped periodically sit on the far faces
+        let to_cart = |i: [usize; 3]| -> [f64; 3] {
+            let f = [0, 1, 2].map(|a| if i[a] == ns[a] { 1.0 } else { (i[a] * stride) as f64 / ngrid[a] as f64 });
+            [0, 1, 2].map(|j| f[0] * cell[0][j] + f[1] * cell[1][j] + f[2] * cell[2][j])
+        };
+        // Unique key of an unwrapped grid point, including the far faces
+        let key = |i: [usize; 3]| -> usize {
+            i[0] + (ns[0] + 1) * (i[1] + (ns[1] + 1) * i[2])
+        };
+
+        let mut vertices = vec![];
+        let mut triangles = vec![];
+        let mut edge_vertex: HashMap<(usize, usize), usize> = HashMap::new();
+
+        for iz in 0 .. ns[2] {
+            for iy in 0 .. ns[1] {
+                for ix in 0 .. ns[0] {
+                    let corners = CORNERS.map(|c| [ix + c[0], iy + c[1], iz + c[2]]);
+                    let values = corners.map(|c| data[gidx(c)]);
+                    if values.iter().all(|v| *v > level) || values.iter().all(|v| *v <= level) {
+                        continue;
+                    }
+
+                    let mut vertex = |a: usize, b: usize| -> usize {
+                        let (ka, kb) = (key(corners[a]), key(corners[b]));
+                        let edge = if ka < kb { (ka, kb) } else { (kb, ka) };
+                        *edge_vertex.entry(edge).or_insert_with(|| {
+                            let (pa, pb) = (to_cart(corners[a]), to_cart(corners[b]));
+                            let t = (level - values[a]) / (values[b] - values[a]);
+                            vertices.push([0, 1, 2].map(|j| pa[j] + t * (pb[j] - pa[j])));
+                            vertices.len() - 1
+                        })
+                    };
+
+                    for tet in TETRAHEDRA.iter() {
+                        let (inside, outside): (Vec<usize>, Vec<usize>) = tet.iter().partition(|&&c| values[c] > level);
+                        match inside.len() {
+                            1 | 3 => {
+                                let (apex, base) = if inside.len() == 1 { (inside[0], &outside) } else { (outside[0], &inside) };
+                                triangles.push([vertex(apex, base[0]), vertex(apex, base[1]), vertex(apex, base[2])]);
+                            },
+                            2 => {
+                                let (i0, i1, o0, o1) = (inside[0], inside[1], outside[0], outside[1]);
+                                let quad = [vertex(i0, o0), vertex(i0, o1), vertex(i1, o1), vertex(i1, o0)];
+                                triangles.push([quad[0], quad[1], quad[2]]);
+                                triangles.push([quad[0], quad[2], quad[3]]);
+                            },
+                            _ => {},
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            level,
+            vertices,
+            triangles,
+        }
+    }
+}
+
+
+/// Saves the isosurfaces of volumetric data, together with the unit cell and atoms, as an
+/// interactive 3D plot.
+pub fn save_isosurface_html(chg: &ChargeDensity, surfaces: &[Isosurface], path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+    let mut plot = Plot::new()
+        .layout(json!({
+            "title": "Isosurface",
+            "showlegend": true,
+            "scene": {
+                "aspectmode": "data",
+                "xaxis": {"visible": false},
+                "yaxis": {"visible": false},
+                "zaxis": {"visible": false},
+            },
+        }));
+
+    for (i, s) in surfaces.iter().enumerate() {
+        plot.add_trace(json!({
+            "type": "mesh3d",
+            "name": format!("isolevel {}", s.level),
+            "showlegend": true,
+            "x": s.vertices.iter().map(|v| v[0]).collect::<Vec<f64>>(),
+            "y": s.vertices.iter().map(|v| v[1]).collect::<Vec<f64>>(),
+            "z": s.vertices.iter().map(|v| v[2]).collect::<Vec<f64>>(),
+            "i": s.triangles.iter().map(|t| t[0]).collect::<Vec<usize>>(),
+            "j": s.triangles.iter().map(|t| t[1]).collect::<Vec<usize>>(),
+            "k": s.triangles.iter().map(|t| t[2]).collect::<Vec<usize>>(),
+            "color": if s.level < 0.0 { "#17becf" } else { ["#ffd700", "#ff7f0e"][i % 2] },
+            "opacity": 0.6,
+        }));
+    }
+
+    _add_structure_traces(&mut plot, &chg.pos);
+    plot.save_html(path)
+}
+
+
+/// Adds the edges of the unit cell and the atoms colored by elements to a 3D plot.
+pub(crate) fn _add_structure_traces(plot: &mut Plot, pos: &Structure) {
+    let cell = &pos.cell;
+
+    // The 12 edges of the unit cell, separated by nulls
+    let corner = |f: [f64; 3]| [0, 1, 2].map(|j| f[0] * cell[0][j] + f[1] * cell[1][j] + f[2] * cell[2][j]);
+    let edges = [
+        ([0., 0., 0.], [1., 0., 0.]), ([0., 1., 0.], [1., 1., 0.]), ([0., 0., 1.], [1., 0., 1.]), ([0., 1., 1.], [1., 1., 1.]),
+        ([0., 0., 0.], [0., 1., 0.]), ([1., 0., 0.], [1., 1., 0.]), ([0., 0., 1.], [0., 1., 1.]), ([1., 0., 1.], [1., 1., 1.]),
+        ([0., 0., 0.], [0., 0., 1.]), ([1., 0., 0.], [1., 0., 1.]), ([0., 1., 0.], [0., 1., 1.]), ([1., 1., 0.], [1., 1., 1.]),
+    ];
+    let mut xyz: [Vec<Option<f64>>; 3] = [vec![], vec![], vec![]];
+    for (a, b) in edges.iter() {
+        let (a, b) = (corner(*a), corner(*b));
+        for (j, v) in xyz.iter_mut().enumerate() {
+            v.extend([Some(a[j]), Some(b[j]), None]);
+        }
+    }
+    plot.add_trace(json!({
+        "type": "scatter3d",
+        "mode": "lines",
+        "name": "cell",
+        "x": xyz[0], "y": xyz[1], "z": xyz[2],
+        "line": {"color": "black", "width": 2},
+    }));
+
+    let symbols = pos.symbols();
+    for (it, t) in pos.ion_types.iter().enumerate() {
+        let car_pos = pos.car_pos.iter()
+            .zip(symbols.iter())
+            .filter(|(_, s)| *s == t)
+            .map(|(p, _)| *p)
+            .collect::<Vec<_>>();
+        plot.add_trace(json!({
+            "type": "scatter3d",
+            "mode": "markers",
+            "name": t,
+            "x": car_pos.iter().map(|p| p[0]).collect::<Vec<f64>>(),
+            "y": car_pos.iter().map(|p| p[1]).collect::<Vec<f64>>(),
+            "z": car_pos.iter().map(|p| p[2]).collect::<Vec<f64>>(),
+            "marker": {"size": 8, "color": Settings::global().plot.color(it, &COLORS), "line": {"color": "black", "width": 1}},
+        }));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isosurface_bump() {
+        // A single grid point above the level, the surface encloses it at the midpoints of edges
+        let ngrid = [4, 4, 4];
+        let mut data = vec![0.0; 64];
+        data[1 + 4 + 16] = 1.0;   // (1, 1, 1)
+        let cell = [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]];
+
+        let s = Isosurface::from_grid(&data, ngrid, &cell, 0.5, 1);
+        assert!(!s.triangles.is_empty());
+        for v in s.vertices.iter() {
+            let d = [v[0] - 1.0, v[1] - 1.0, v[2] - 1.0];
+            assert!(d.iter().all(|x| x.abs() < 1E-10 || (x.abs() - 0.5).abs() < 1E-10));
+            assert!(d.iter().any(|x| x.abs() > 0.25));
+        }
+
+        // Each edge of the closed surface is shared by exactly two triangles
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for t in s.triangles.iter() {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|n| *n == 2));
+
+        assert!(Isosurface::from_grid(&data, ngrid, &cell, 2.0, 1).triangles.is_empty());
+    }
+
+    #[test]
+    fn test_isosurface_periodic() {
+        // Slab of high values at z = 0 crosses the periodic boundary
+        let ngrid = [2, 2, 4];
+        let data = (0 .. 16).map(|i| if i / 4 == 0 { 1.0 } else { 0.0 }).collect::<Vec<f64>>();
+        let cell = [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 4.0]];
+        let s = Isosurface::from_grid(&data, ngrid, &cell, 0.5, 1);
+        let mut zs = s.vertices.iter().map(|v| v[2]).collect::<Vec<f64>>();
+        zs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        zs.dedup_by(|a, b| (*a - *b).abs() < 1E-10);
+        assert_eq!(zs, vec![0.5, 3.5]);
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::f64::consts::PI;
+use std::collections::HashMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use rayon::prelude::*;
+use itertools::{
+    iproduct,
+    Itertools,
+};
+use rustfft::num_complex::Complex;
+use log::info;
+use crate::procar::Procar;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+use crate::dos::{
+    Smearing,
+    _state_weights,
+};
+use crate::wavecar::Wavecar;
+use crate::format::_calc_inv_3x3;
+use crate::progress::Progress;
+use crate::constants::{
+    HBAR2_ME,
+    DEBYE_PER_E_A,
+};
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+// Transition energy in eV and the index of k-point
+pub type Transition = (f64, usize);
+
+
+// Joint density of states of the vertical transitions from occupied to empty states,
+// JDOS(w) = sum_k w_k sum_{v,c} f_v (1 - f_c) delta(w - (E_c - E_v)), in states/eV per cell.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Jdos {
+    pub energies : Vec<f64>,       // photon energies in eV
+    pub jdos     : Vec<Vec<f64>>,  // [nspin][nedos]
+    pub sigma    : f64,
+    pub smearing : Smearing,
+    pub onset    : Option<Transition>,  // lowest direct transition between mostly occupied and mostly empty states, with the k-point index
+    pub tdm      : Option<Vec<Vec<f64>>>,  // [nspin][nedos], weighted by |d_cv|^2 in Debye^2, see `with_tdm`
+}
+
+impl Jdos {
+    /// The transitions are summed over the k-points in parallel. The occupations of PROCAR
+    /// are normalized to [0, 1], and `scissor` shifts all the transition energies.
+    pub fn from_procar(procar: &Procar, emax: f64, nedos: usize, sigma: f64,
+                       smearing: Smearing, scissor: f64) -> Self {
+        assert!(emax > 0.0, "The maximum photon energy should be positive");
+        assert!(nedos > 1, "NEDOS should be larger than 1");
+        assert!(sigma > 0.0, "Smearing width should be positive");
+
+        let de = emax / (nedos - 1) as f64;
+        let energies = (0 .. nedos).map(|i| de * i as f64).collect::<Vec<f64>>();
+        let (weights, factor) = _state_weights(procar);
+        let fmax = if procar.occupations.iter().any(|o| *o > 1.0 + 1E-6) { 2.0 } else { 1.0 };
+        let occ = |ispin, ik, ib| (procar.occupation(ispin, ik, ib) / fmax).clamp(0.0, 1.0);
+        let cutoff = smearing.cutoff(sigma);
+
+        let nspin = procar.nspin;
+        let nbands = procar.nbands;
+        let (jdos, onsets): (Vec<Vec<f64>>, Vec<Option<Transition>>) = (0 .. nspin)
+            .map(|ispin| {
+                (0 .. procar.nkpts).into_par_iter()
+                    .map(|ik| {
+                        let mut ret = vec![0.0f64; nedos];
+                        let mut onset: Option<Transition> = None;
+                        for iv in 0 .. nbands {
+                            let fv = occ(ispin, ik, iv);
+                            if fv == 0.0 { continue; }
+                            for ic in 0 .. nbands {
+                                let fc = occ(ispin, ik, ic);
+                                let dw = _transition_weight(fv, fc);
+                                let dx = procar.eigval(ispin, ik, ic) - procar.eigval(ispin, ik, iv) + scissor;
+                                if dw == 0.0 || dx <= 0.0 { continue; }
+
+                                if fv > 0.5 && fc < 0.5 && onset.is_none_or(|(e, _)| dx < e) {
+                                    onset = Some((dx, ik));
+                                }
+
+                                let w = weights[ik] * factor * dw;
+                                let lo = ((dx - cutoff) / de).ceil().max(0.0) as usize;
+                                let hi = (((dx + cutoff) / de).floor() as usize).min(nedos - 1);
+                                for (i, v) in ret.iter_mut().enumerate().take(hi + 1).skip(lo) {
+                                    *v += w * smearing.delta(energies[i] - dx, sigma);
+                                }
+                            }
+                        }
+                        (ret, onset)
+                    })
+                    .reduce(|| (vec![0.0f64; nedos], None), |(mut a, oa), (b, ob)| {
+                        a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x += y);
+                        let onset = match (oa, ob) {
+                            (Some(x), Some(y)) => Some(if y.0 < x.0 { y } else { x }),
+                            (x, y) => x.or(y),
+                        };
+                        (a, onset)
+                    })
+            })
+            .unzip();
+
+        let onset = onsets.into_iter()
+            .flatten()
+            .fold(None, |acc: Option<Transition>, x| Some(acc.map_or(x, |a| if x.0 < a.0 { x } else { a })));
+
+        Self {
+            energies,
+            jdos,
+            sigma,
+            smearing,
+            onset,
+            tdm: None,
+        }
+    }
+
+    /// Adds the JDOS weighted by the squared transition dipole moments |d_cv|^2 in Debye^2,
+    /// where d_cv = hbar^2 / (m_e (E_c - E_v)) <c|grad|v> from the plane-wave coefficients of
+    /// WAVECAR, i.e. <c|grad|v> = i sum_G c_c(G)^* c_v(G) (k + G). The one-center terms of PAW
+    /// are left out. WAVECAR should come from the same calculation as PROCAR, the k-points are
+    /// matched by coordinates. Only the bands taking the transitions within the energy grid are
+    /// read, and the k-points are summed in parallel.
+    pub fn with_tdm(mut self, procar: &Procar, wav: &Wavecar, scissor: f64) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if wav.nspin != procar.nspin || wav.nbands != procar.nbands {
+            return Err(err(format!("WAVECAR of ISPIN = {} and NBANDS = {} doesn't match PROCAR of ISPIN = {} and NBANDS = {}",
+                                   wav.nspin, wav.nbands, procar.nspin, procar.nbands)));
+        }
+        let ikpts = wav.match_kpoints(&procar.kpoints)
+            .ok_or_else(|| err("K-points of PROCAR are not found in WAVECAR".to_string()))?;
+
+        let nedos = self.energies.len();
+        let de = self.energies[1] - self.energies[0];
+        let emax = self.energies[nedos - 1];
+        let cutoff = self.smearing.cutoff(self.sigma);
+        let (weights, factor) = _state_weights(procar);
+        let fmax = if procar.occupations.iter().any(|o| *o > 1.0 + 1E-6) { 2.0 } else { 1.0 };
+        let occ = |ispin, ik, ib| (procar.occupation(ispin, ik, ib) / fmax).clamp(0.0, 1.0);
+        let inv = _calc_inv_3x3(&wav.cell);
+        let progress = Progress::new("Calculating transition dipole moments", procar.nspin * procar.nkpts);
+
+        let tdm = (0 .. procar.nspin)
+            .map(|ispin| {
+                (0 .. procar.nkpts).into_par_iter()
+                    .map(|ik| {
+                        let mut ret = vec![0.0f64; nedos];
+                        let transitions = iproduct!(0 .. procar.nbands, 0 .. procar.nbands)
+                            .filter_map(|(iv, ic)| {
+                                let dw = _transition_weight(occ(ispin, ik, iv), occ(ispin, ik, ic));
+                                let e = procar.eigval(ispin, ik, ic) - procar.eigval(ispin, ik, iv);
+                                let dx = e + scissor;
+                                if dw == 0.0 || e < 1E-3 || dx <= 0.0 || dx > emax + cutoff { return None; }
+                                Some((iv, ic, dw, e, dx))
+                            })
+                            .collect::<Vec<_>>();
+                        if transitions.is_empty() {
+                            progress.inc(1);
+                            return ret;
+                        }
+
+                        let ikw = ikpts[ik];
+                        let gvecs = wav.gvectors(ikw);
+                        let kvec = wav.kvecs[ikw];
+                        let kg = gvecs.iter()
+                            .map(|g| {
+                                let q = [0, 1, 2].map(|i| kvec[i] + g[i] as f64);
+                                [0, 1, 2].map(|x| 2.0 * PI * (0 .. 3).map(|j| q[j] * inv[x][j]).sum::<f64>())
+                            })
+                            .collect::<Vec<[f64; 3]>>();
+                        let bands = transitions.iter()
+                            .flat_map(|t| [t.0, t.1])
+                            .unique()
+                            .map(|ib| (ib, wav.read_spinors(ispin, ikw, ib, &gvecs).1))
+                            .collect::<HashMap<_, _>>();
+
+                        for (iv, ic, dw, e, dx) in transitions {
+                            let mut p = [Complex::new(0.0, 0.0); 3];
+                            for (cc, cv) in bands[&ic].iter().zip(bands[&iv].iter()) {
+                                for ((c, v), q) in cc.iter().zip(cv.iter()).zip(kg.iter()) {
+                                    let x = c.conj() * v;
+                                    (0 .. 3).for_each(|j| p[j] += x * q[j]);
+                                }
+                            }
+                            let d2 = (HBAR2_ME / e * DEBYE_PER_E_A).powi(2) * p.iter().map(|x| x.norm_sqr()).sum::<f64>();
+
+                            let w = weights[ik] * factor * dw * d2;
+                            let lo = ((dx - cutoff) / de).ceil().max(0.0) as usize;
+                            let hi = (((dx + cutoff) / de).floor() as usize).min(nedos - 1);
+                            for (i, v) in ret.iter_mut().enumerate().take(hi + 1).skip(lo) {
+                                *v += w * self.smearing.delta(self.energies[i] - dx, self.sigma);
+                            }
+                        }
+                        progress.inc(1);
+                        ret
+                    })
+                    .reduce(|| vec![0.0f64; nedos], |mut a, b| {
+                        a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x += y);
+                        a
+                    })
+            })
+            .collect();
+        progress.finish();
+
+        self.tdm = Some(tdm);
+        Ok(self)
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "jdos.txt")?;
+        info!("Saving joint DOS to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# Joint density of states in states/eV, {:?} smearing of {} eV", self.smearing, self.sigma)?;
+        if self.tdm.is_some() {
+            writeln!(f, "# TDM_JDOS is weighted by the squared transition dipole moments, in Debye^2/eV")?;
+        }
+        let nspin = self.jdos.len();
+        let tdm = self.tdm.as_deref().unwrap_or_default();
+        write!(f, "# {:>10}", "E/eV")?;
+        for ispin in 0 .. nspin {
+            write!(f, " {:>14}", format!("JDOS{}", _spin_suffix(ispin, nspin)))?;
+        }
+        for ispin in 0 .. tdm.len() {
+            write!(f, " {:>14}", format!("TDM_JDOS{}", _spin_suffix(ispin, nspin)))?;
+        }
+        writeln!(f)?;
+        for (i, e) in self.energies.iter().enumerate() {
+            write!(f, "  {:10.5}", e)?;
+            for spin in self.jdos.iter().chain(tdm.iter()) {
+                write!(f, " {:14.6}", spin[i])?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "jdos.html")?;
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Joint density of states",
+                "xaxis": {"title": "Photon energy (eV)"},
+                "yaxis": {"title": "JDOS (states/eV)"},
+                "yaxis2": {"title": "TDM weighted JDOS (Debye^2/eV)", "overlaying": "y", "side": "right"},
+            }));
+        for (ispin, spin) in self.jdos.iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("JDOS{}", _spin_suffix(ispin, self.jdos.len())),
+                "x": self.energies,
+                "y": spin,
+            }));
+        }
+        for (ispin, spin) in self.tdm.iter().flatten().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("TDM_JDOS{}", _spin_suffix(ispin, self.jdos.len())),
+                "yaxis": "y2",
+                "x": self.energies,
+                "y": spin,
+            }));
+        }
+        plot.save_html(&fname)
+    }
+}
+
+
+fn _spin_suffix(ispin: usize, nspin: usize) -> &'static str {
+    match (nspin, ispin) {
+        (2, 0) => "_up",
+        (2, _) => "_dn",
+        _      => "",
+    }
+}
+
+
+// Weight of the transition from a state of occupation fv to a state of occupation fc
+fn _transition_weight(fv: f64, fc: f64) -> f64 {
+    fv * (1.0 - fc)
+}
+
+
+// The spectra are saved by `save_as_txt`, only the onset is listed here
+impl Tabular for Jdos {
+    fn headers(&self) -> Vec<String> {
+        ["onset", "ikpoint", "sigma", "smearing"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.onset.map(|(e, _)| format!("{:.4}", e)).unwrap_or_default(),
+            self.onset.map(|(_, ik)| (ik + 1).to_string()).unwrap_or_default(),
+            format!("{}", self.sigma),
+            format!("{:?}", self.smearing).to_lowercase(),
+        ]]
+    }
+}
+
+impl fmt::Display for Jdos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Joint density of states".bright_green())?;
+        writeln!(f, "  Photon energies: 0 ~ {:.3} eV, {:?} smearing of {} eV",
+                 self.energies.last().unwrap(), self.smearing, self.sigma)?;
+        match self.onset {
+            Some((e, ik)) => writeln!(f, "  Lowest direct transition: {} eV at k-point {}",
+                                      format!("{:.4}", e).bright_yellow(), ik + 1)?,
+            None => writeln!(f, "  No transitions from occupied to empty states found")?,
+        }
+        if self.tdm.is_some() {
+            writeln!(f, "  Weighted by the transition dipole moments of WAVECAR, without the PAW one-center terms")?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_1_SQRT_2;
+    use crate::wavecar::{
+        _gvectors,
+        _ngrid,
+    };
+    use crate::wavecar::tests::_write_wavecar;
+
+    #[test]
+    fn test_jdos() {
+        // Two bands of one spin, one k-point: the transition of 2 eV from the occupied band
+        let procar = Procar {
+            nkpts: 1,
+            nbands: 2,
+            nions: 1,
+            nspin: 1,
+            lncl: false,
+            orbitals: vec!["s".to_string()],
+            kpoints: vec![[0.0, 0.0, 0.0]],
+            weights: vec![1.0],
+            eigvals: vec![-1.0, 1.0],
+            occupations: vec![2.0, 0.0],
+            projections: vec![1.0, 1.0],
+            phases: None,
+        };
+
+        let jdos = Jdos::from_procar(&procar, 4.0, 401, 0.05, Smearing::Gaussian, 0.0);
+        assert_eq!(jdos.onset, Some((2.0, 0)));
+        let peak = (0 .. 401).max_by(|&i, &j| jdos.jdos[0][i].partial_cmp(&jdos.jdos[0][j]).unwrap()).unwrap();
+        assert!((jdos.energies[peak] - 2.0).abs() < 1E-10);
+
+        // Spin degenerate, two electrons take the transition
+        let integral = jdos.jdos[0].iter().sum::<f64>() * 0.01;
+        assert!((integral - 2.0).abs() < 1E-4);
+
+        let jdos = Jdos::from_procar(&procar, 4.0, 401, 0.05, Smearing::Lorentzian, 0.5);
+        assert_eq!(jdos.onset, Some((2.5, 0)));
+        assert_eq!(jdos.rows()[0], vec!["2.5000", "1", "0.05", "lorentzian"]);
+    }
+
+    #[test]
+    fn test_tdm() {
+        // v = (|0> + |G>) / sqrt(2) and c = (|0> - |G>) / sqrt(2) at Gamma with G = (1, 0, 0),
+        // thus <c|grad|v> = -iG / 2 with |G| = 2pi / 3
+        let procar = Procar {
+            nkpts: 1,
+            nbands: 2,
+            nions: 1,
+            nspin: 1,
+            lncl: false,
+            orbitals: vec!["s".to_string()],
+            kpoints: vec![[0.0, 0.0, 0.0]],
+            weights: vec![1.0],
+            eigvals: vec![-1.0, 1.0],
+            occupations: vec![2.0, 0.0],
+            projections: vec![1.0, 1.0],
+            phases: None,
+        };
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        let cell = [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]];
+        let gvecs = _gvectors(&cell, 60.0, _ngrid(&cell, 60.0), &[0.0; 3], None);
+        let band = |sign: f64| gvecs.iter()
+            .map(|g| match g {
+                [0, 0, 0] => Complex::new(FRAC_1_SQRT_2, 0.0),
+                [1, 0, 0] => Complex::new(sign * FRAC_1_SQRT_2, 0.0),
+                _ => Complex::new(0.0, 0.0),
+            })
+            .collect::<Vec<_>>();
+        _write_wavecar(&path, 45210, 60.0, &cell, &[[0.0; 3]], &[vec![vec![-1.0, 1.0]]], &[vec![vec![band(1.0), band(-1.0)]]]);
+        let wav = Wavecar::from_file(&path).unwrap();
+
+        let jdos = Jdos::from_procar(&procar, 4.0, 401, 0.05, Smearing::Gaussian, 0.0)
+            .with_tdm(&procar, &wav, 0.0)
+            .unwrap();
+        let d2 = (HBAR2_ME / 2.0 * DEBYE_PER_E_A * PI / 3.0).powi(2);
+        let integral = jdos.tdm.as_ref().unwrap()[0].iter().sum::<f64>() * 0.01;
+        assert!((integral - 2.0 * d2).abs() < 1E-4 * d2);
+
+        let mut other = procar.clone();
+        other.nbands = 3;
+        assert!(Jdos::from_procar(&procar, 4.0, 401, 0.05, Smearing::Gaussian, 0.0).with_tdm(&other, &wav, 0.0).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use std::io;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::fs;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::format::{
+    Structure,
+    _calc_inv_3x3,
+};
+use crate::chgcar::ChargeDensity;
+use crate::dos::Dos;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+/// Atoms clustered into one layer along the stacking axis.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Layer {
+    pub iatoms      : Vec<usize>,   // starting from 0
+    pub composition : String,       // e.g. "MoS2"
+    pub center      : f64,          // fractional coordinate along the axis, may be out of [0, 1)
+    pub height      : f64,          // center in Angstrom along the normal of the layers
+    pub charge      : Option<f64>,  // electrons within the layer region, from CHGCAR
+}
+
+
+// Spacing of the lattice planes perpendicular to lattice vector `axis`
+pub(crate) fn _plane_spacing(cell: &[[f64; 3]; 3], axis: usize) -> f64 {
+    let inv = _calc_inv_3x3(cell);
+    1.0 / (0 .. 3).map(|j| inv[j][axis].powi(2)).sum::<f64>().sqrt()
+}
+
+
+// Chemical formula of the atoms, in the order of POSCAR, e.g. "MoS2"
+fn _composition(symbols: &[String], iatoms: &[usize]) -> String {
+    let mut counts: Vec<(&str, usize)> = vec![];
+    for &i in iatoms.iter() {
+        match counts.iter_mut().find(|(s, _)| *s == symbols[i]) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((&symbols[i], 1)),
+        }
+    }
+    counts.sort_by_key(|(s, _)| symbols.iter().position(|x| x == s));
+    counts.iter()
+        .map(|(s, n)| if *n == 1 { s.to_string() } else { format!("{}{}", s, n) })
+        .collect()
+}
+
+
+/// Clusters the atoms into layers along lattice vector `axis`, a gap larger than `tolerance`
+/// in Angstrom between the sorted heights starts a new layer. The clustering starts after the
+/// largest gap, usually the vacuum, thus a layer crossing the cell boundary is kept whole.
+/// Layers are sorted from the bottom to the top.
+pub fn cluster_layers(structure: &Structure, axis: usize, tolerance: f64) -> Vec<Layer> {
+    assert!(axis < 3, "Axis should be 0, 1 or 2");
+    let nions = structure.frac_pos.len();
+    assert!(nions > 0, "No atoms in the structure");
+    let spacing = _plane_spacing(&structure.cell, axis);
+    let symbols = structure.ion_types.iter()
+        .zip(structure.ions_per_type.iter())
+        .flat_map(|(s, n)| vec![s.clone(); *n as usize])
+        .collect::<Vec<_>>();
+
+    let mut order = (0 .. nions)
+        .map(|i| (i, structure.frac_pos[i][axis].rem_euclid(1.0)))
+        .collect::<Vec<_>>();
+    order.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    // The gap before order[i], the first one wraps around the boundary
+    let gap = |i: usize| if i == 0 { order[0].1 + 1.0 - order[nions - 1].1 } else { order[i].1 - order[i - 1].1 };
+    let istart = (0 .. nions).max_by(|&a, &b| gap(a).partial_cmp(&gap(b)).unwrap()).unwrap();
+
+    let mut groups: Vec<Vec<(usize, f64)>> = vec![];
+    for n in 0 .. nions {
+        let i = (istart + n) % nions;
+        let frac = if i < istart { order[i].1 + 1.0 } else { order[i].1 };
+        if n == 0 || gap(i) * spacing > tolerance {
+            groups.push(vec![]);
+        }
+        groups.last_mut().unwrap().push((order[i].0, frac));
+    }
+
+    groups.into_iter()
+        .map(|g| {
+            let mut iatoms = g.iter().map(|(i, _)| *i).collect::<Vec<usize>>();
+            iatoms.sort_unstable();
+            let center = g.iter().map(|(_, f)| f).sum::<f64>() / g.len() as f64;
+            Layer {
+                composition: _composition(&symbols, &iatoms),
+                iatoms,
+                center,
+                height: center * spacing,
+                charge: None,
+            }
+        })
+        .collect()
+}
+
+
+/// Layer-resolved charges and DOS of a slab or heterostructure.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LayerAnalysis {
+    pub axis   : usize,
+    pub layers : Vec<Layer>,
+    #[serde(skip)]
+    pub dos    : Option<Dos>,  // projected DOS of each layer, labelled "L1", "L2", ...
+}
+
+impl LayerAnalysis {
+    pub fn new(structure: &Structure, axis: usize, tolerance: f64) -> Self {
+        Self {
+            axis,
+            layers: cluster_layers(structure, axis, tolerance),
+            dos: None,
+        }
+    }
+
+    /// Integrates the first block of CHGCAR over the region of each layer, i.e. the grid planes
+    /// closer to the layer center than to the others, periodic images included.
+    pub fn with_charge(mut self, chgcar: &ChargeDensity) -> Self {
+        let profile = chgcar.planar_average(0, self.axis);
+        let n = profile.len();
+        let mut charges = vec![0.0f64; self.layers.len()];
+        for (j, v) in profile.iter().enumerate() {
+            let frac = j as f64 / n as f64;
+            let ilayer = (0 .. self.layers.len())
+                .min_by(|&a, &b| {
+                    let da = frac - self.layers[a].center;
+                    let db = frac - self.layers[b].center;
+                    (da - da.round()).abs().partial_cmp(&(db - db.round()).abs()).unwrap()
+                })
+                .unwrap();
+            // CHGCAR holds rho * V, each plane holds 1/n of the cell
+            charges[ilayer] += v / n as f64;
+        }
+        for (layer, c) in self.layers.iter_mut().zip(charges) {
+            layer.charge = Some(c);
+        }
+        self
+    }
+
+    pub fn with_dos(mut self, dos: Dos) -> Self {
+        self.dos = Some(dos);
+        self
+    }
+
+    pub fn save_dos_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        match self.dos.as_ref() {
+            Some(dos) => dos.save_as_named_txt(path, "layer_dos.txt"),
+            None => Ok(()),
+        }
+    }
+
+    /// Layer DOS stacked in panels from the bottom layer to the top one, spin down with
+    /// negative values.
+    pub fn save_dos_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let dos = match self.dos.as_ref() {
+            Some(dos) => dos,
+            None => return Ok(()),
+        };
+        let nlayers = dos.pdos.len();
+        let height = 1.0 / nlayers as f64;
+
+        let mut layout = json!({
+            "title": "Layer-resolved density of states",
+            "xaxis": {"title": "E-Ef (eV)"},
+            "showlegend": false,
+            "height": 200 + 120 * nlayers,
+            "shapes": [{
+                "type": "line", "xref": "x", "yref": "paper",
+                "x0": 0.0, "x1": 0.0, "y0": 0.0, "y1": 1.0,
+                "line": {"dash": "dash", "color": "gray", "width": 1},
+            }],
+        });
+        for (il, layer) in self.layers.iter().enumerate().take(nlayers) {
+            let key = if il == 0 { "yaxis".to_string() } else { format!("yaxis{}", il + 1) };
+            layout[key] = json!({
+                "title": format!("L{} {}", il + 1, layer.composition),
+                "domain": [il as f64 * height + 0.01, (il + 1) as f64 * height - 0.01],
+                "anchor": "x",
+            });
+        }
+
+        let mut plot = Plot::new().layout(layout);
+        for (il, (label, p)) in dos.pdos.iter().enumerate() {
+            for (ispin, ps) in p.iter().enumerate() {
+                let sign = if ispin == 1 { -1.0 } else { 1.0 };
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": label,
+                    "x": dos.energies,
+                    "y": ps.iter().map(|v| v * sign).collect::<Vec<f64>>(),
+                    "fill": "tozeroy",
+                    "yaxis": if il == 0 { "y".to_string() } else { format!("y{}", il + 1) },
+                }));
+            }
+        }
+        plot.save_html(&_prepare_fname(path, "layer_dos.html")?)
+    }
+}
+
+impl Tabular for LayerAnalysis {
+    fn headers(&self) -> Vec<String> {
+        ["layer", "composition", "natoms", "center", "height", "charge"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.layers.iter()
+            .enumerate()
+            .map(|(i, l)| vec![
+                (i + 1).to_string(),
+                l.composition.clone(),
+                l.iatoms.len().to_string(),
+                format!("{:.4}", l.center),
+                format!("{:.4}", l.height),
+                l.charge.map(|c| format!("{:.4}", c)).unwrap_or_default(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for LayerAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Layer {:<12} {:>6} {:>8} {:>10} {:>10}  Atoms",
+                                  "Formula", "Natoms", "Center", "Height/A", "Charge/e").bright_green())?;
+        for (i, l) in self.layers.iter().enumerate() {
+            let atoms = l.iatoms.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>().join(" ");
+            writeln!(f, "  {:5} {:<12} {:6} {:8.4} {:10.4} {}  {}",
+                     i + 1, l.composition, l.iatoms.len(), l.center, l.height,
+                     format!("{:>10}", l.charge.map(|c| format!("{:.4}", c)).unwrap_or_default()).bright_yellow(),
+                     atoms)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_layers() {
+        // MoS2-like slab crossing the cell boundary along c, with vacuum in the middle
+        let structure = Structure {
+            cell: [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 20.0]],
+            ion_types: vec!["Mo".to_string(), "S".to_string()],
+            ions_per_type: vec![1, 2],
+            car_pos: vec![[0.0, 0.0, 0.0], [1.5, 1.5, 1.5], [1.5, 1.5, 18.5]],
+            frac_pos: vec![[0.0, 0.0, 0.0], [0.5, 0.5, 0.075], [0.5, 0.5, 0.925]],
+        };
+        let layers = cluster_layers(&structure, 2, 0.5);
+        assert_eq!(layers.len(), 3);
+        assert_eq!(layers[0].iatoms, vec![2]);
+        assert_eq!(layers[1].iatoms, vec![0]);
+        assert!((layers[0].height - 18.5).abs() < 1E-10);
+        assert!((layers[2].height - 21.5).abs() < 1E-10);
+
+        let layers = cluster_layers(&structure, 2, 2.0);
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].composition, "MoS2");
+        assert!((layers[0].center - 1.0).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_layer_charge() {
+        let chgcar = ChargeDensity::parse(r#"unknown system
+   1.00000000000000
+     2.000000    0.000000    0.000000
+     0.000000    2.000000    0.000000
+     0.000000    0.000000    8.000000
+   H
+     2
+Direct
+  0.000000  0.000000  0.200000
+  0.000000  0.000000  0.600000
+
+    1    1    4
+ 0.10000000000E+01 0.20000000000E+01 0.30000000000E+01 0.40000000000E+01
+"#);
+        let layers = LayerAnalysis::new(&chgcar.pos, 2, 0.5).with_charge(&chgcar);
+        assert_eq!(layers.layers.len(), 2);
+        // Planes at 0 and 0.25 are closer to the first layer, 0.5 and 0.75 to the second one
+        assert!((layers.layers[0].charge.unwrap() - 0.75).abs() < 1E-10);
+        assert!((layers.layers[1].charge.unwrap() - 1.75).abs() < 1E-10);
+        assert_eq!(layers.rows()[1][1], "H");
+    }
+}
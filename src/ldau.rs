@@ -0,0 +1,502 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use std::collections::BTreeMap;
+use colored::Colorize;
+use regex::Regex;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::_decompress_gzip;
+use crate::format::Structure;
+use crate::selection::RawSelection;
+use crate::audit::Incar;
+
+
+/// On-site density matrix of one atom printed by LDAU runs (LDAUPRINT=2).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OccupationMatrix {
+    pub atom  : usize,  // starts from 1
+    pub itype : usize,  // starts from 1
+    pub l     : usize,
+    pub spins : Vec<Vec<Vec<f64>>>,  // [nspin][2l+1][2l+1]
+}
+
+impl OccupationMatrix {
+    pub fn trace(&self, ispin: usize) -> f64 {
+        let m = &self.spins[ispin];
+        (0 .. m.len()).map(|i| m[i][i]).sum()
+    }
+
+    /// Occupations of the eigen orbitals in ascending order.
+    pub fn eigenvalues(&self, ispin: usize) -> Vec<f64> {
+        _jacobi_eigenvalues(&self.spins[ispin])
+    }
+
+    /// Difference of the spin up and down occupations, zero for non-spin-polarized runs.
+    pub fn moment(&self) -> f64 {
+        if self.spins.len() == 2 {
+            self.trace(0) - self.trace(1)
+        } else {
+            0.0
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LdauStep {
+    pub step     : usize,  // ionic step, starts from 1
+    pub matrices : Vec<OccupationMatrix>,
+}
+
+
+/// Occupation matrices of the LDAU atoms at the end of each ionic step.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LdauOccupations(pub Vec<LdauStep>);
+
+impl LdauOccupations {
+    /// Gzipped OUTCARs are recognized by the ".gz" extension.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let data = if path.extension().is_some_and(|e| e == "gz") {
+            _decompress_gzip(&data)?
+        } else {
+            data
+        };
+        Ok(Self::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    /// The matrices are printed at each electronic step, only the last ones of each atom before
+    /// "FREE ENERGIE OF THE ION-ELECTRON SYSTEM" are kept. Unfinished ionic steps are dropped.
+    pub fn parse(context: &str) -> Self {
+        let re = Regex::new(r"atom =\s*(\d+)\s+type =\s*(\d+)\s+l =\s*(\d+)").unwrap();
+        let lines = context.lines().collect::<Vec<_>>();
+
+        let mut steps = vec![];
+        let mut current = BTreeMap::<usize, OccupationMatrix>::new();
+        let mut nstep = 0;
+        let mut i = 0;
+        while i < lines.len() {
+            if lines[i].contains("FREE ENERGIE OF THE ION-ELECTRON SYSTEM") {
+                nstep += 1;
+                if !current.is_empty() {
+                    steps.push(LdauStep {
+                        step: nstep,
+                        matrices: std::mem::take(&mut current).into_values().collect(),
+                    });
+                }
+                i += 1;
+                continue;
+            }
+
+            let caps = match re.captures(lines[i]) {
+                Some(caps) => caps,
+                None => { i += 1; continue; },
+            };
+            let atom = caps[1].parse::<usize>().unwrap();
+            let itype = caps[2].parse::<usize>().unwrap();
+            let l = caps[3].parse::<usize>().unwrap();
+            let (spins, next) = _parse_spin_blocks(&lines, i + 1, 2 * l + 1);
+            if !spins.is_empty() {
+                current.insert(atom, OccupationMatrix { atom, itype, l, spins });
+            }
+            i = next;
+        }
+
+        Self(steps)
+    }
+}
+
+
+// Orbitals of the d shell in the order of m = -2 .. 2, as in the occupation matrices of VASP
+pub const D_ORBITALS: [&str; 5] = ["dxy", "dyz", "dz2", "dxz", "x2-y2"];
+
+
+/// Diagonal occupation matrices of the d shells to seed the orbital ordering with the
+/// occupation matrix control of VASP (OCCEXT=1 and the OCCMATRIX file), together with the LDAU
+/// tags of each species.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OccupationControl {
+    pub ion_types : Vec<String>,
+    pub matrices  : Vec<OccupationMatrix>,
+    pub ldaul     : Vec<i32>,        // per species, 2 for the targeted species
+    pub ldauu     : Option<String>,  // copied from INCAR
+    pub ldauj     : Option<String>,
+}
+
+impl OccupationControl {
+    /// Each pattern is (atoms, spin up orbitals, spin down orbitals), the atoms are selected in
+    /// the syntax of `RawSelection::parse_iatoms_in` and the orbitals by the names in
+    /// `D_ORBITALS` or the groups "d", "t2g" and "eg". Empty orbitals occupy nothing.
+    ///
+    /// If INCAR is given, LDAUL should be listed for each species and be 2 for the targeted
+    /// species.
+    pub fn new(structure: &Structure, patterns: &[(String, String, String)], incar: Option<&Incar>) -> io::Result<Self> {
+        let orbitals = D_ORBITALS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let itypes = structure.ions_per_type.iter()
+            .enumerate()
+            .flat_map(|(i, n)| vec![i; *n as usize])
+            .collect::<Vec<usize>>();
+        let symbols = structure.symbols();
+        let ntypes = structure.ion_types.len();
+
+        let occupy = |input: &str| -> Vec<Vec<f64>> {
+            let mut m = vec![vec![0.0; 5]; 5];
+            if !input.trim().is_empty() {
+                RawSelection::parse_iorbits(input, &orbitals).into_iter().for_each(|i| m[i][i] = 1.0);
+            }
+            m
+        };
+
+        let mut matrices: Vec<OccupationMatrix> = vec![];
+        for (atoms, up, down) in patterns.iter() {
+            let iatoms = RawSelection::parse_iatoms_in(atoms, structure);
+            if iatoms.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("No atoms selected by '{}'", atoms)));
+            }
+            for i in iatoms {
+                if matrices.iter().any(|m| m.atom == i + 1) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Atom {} ({}) is targeted by more than one pattern", i + 1, symbols[i])));
+                }
+                matrices.push(OccupationMatrix {
+                    atom: i + 1,
+                    itype: itypes[i] + 1,
+                    l: 2,
+                    spins: vec![occupy(up), occupy(down)],
+                });
+            }
+        }
+        matrices.sort_by_key(|m| m.atom);
+
+        let targeted = (0 .. ntypes)
+            .map(|it| matrices.iter().any(|m| m.itype == it + 1))
+            .collect::<Vec<bool>>();
+        let ldaul = match incar.and_then(|incar| incar.get("LDAUL")) {
+            Some(v) => {
+                let ldaul = v.split_whitespace()
+                    .map(|x| x.parse::<i32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid LDAUL in INCAR: {}", v)))?;
+                if ldaul.len() != ntypes {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("{} values of LDAUL in INCAR, but {} species {:?} in the structure",
+                                ldaul.len(), ntypes, structure.ion_types)));
+                }
+                if let Some(it) = (0 .. ntypes).find(|it| targeted[*it] && ldaul[*it] != 2) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("LDAUL of {} is {} in INCAR, but its d shell is targeted",
+                                structure.ion_types[it], ldaul[it])));
+                }
+                ldaul
+            },
+            None => targeted.iter().map(|t| if *t { 2 } else { -1 }).collect(),
+        };
+
+        Ok(Self {
+            ion_types: structure.ion_types.clone(),
+            matrices,
+            ldaul,
+            ldauu: incar.and_then(|incar| incar.get("LDAUU")).map(|s| s.to_string()),
+            ldauj: incar.and_then(|incar| incar.get("LDAUJ")).map(|s| s.to_string()),
+        })
+    }
+
+    /// Content of OCCMATRIX: the number of atoms, then for each atom the index, l and the
+    /// number of spin components followed by the matrices.
+    pub fn occmatrix(&self) -> String {
+        let mut ret = format!("{}\n", self.matrices.len());
+        for m in self.matrices.iter() {
+            ret += &format!("{} {} {}\n", m.atom, m.l, m.spins.len());
+            for (ispin, spin) in m.spins.iter().enumerate() {
+                ret += &format!("spin component {}\n", ispin + 1);
+                for row in spin.iter() {
+                    ret += &row.iter().map(|x| format!("{:6.3}", x)).collect::<Vec<_>>().join(" ");
+                    ret += "\n";
+                }
+            }
+        }
+        ret
+    }
+
+    /// INCAR tags for the occupation matrix control, the matrices are printed with LDAUPRINT=2
+    /// thus the resulted orbital ordering can be checked by `rsgrad ldau`.
+    pub fn incar_tags(&self) -> String {
+        let ldaul = self.ldaul.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(" ");
+        let mut ret = format!("OCCEXT    = 1\nLDAU      = .TRUE.\nLDAUTYPE  = 2\nLDAUL     = {}\n", ldaul);
+        if let Some(u) = self.ldauu.as_ref() {
+            ret += &format!("LDAUU     = {}\n", u);
+        }
+        if let Some(j) = self.ldauj.as_ref() {
+            ret += &format!("LDAUJ     = {}\n", j);
+        }
+        ret += "LDAUPRINT = 2\nLMAXMIX   = 4\nISPIN     = 2\n";
+        ret
+    }
+}
+
+
+// Reads the "spin component N" blocks following the atom header, returns the matrices and the
+// line index where the parsing stopped.
+fn _parse_spin_blocks(lines: &[&str], mut i: usize, dim: usize) -> (Vec<Vec<Vec<f64>>>, usize) {
+    let mut spins = vec![];
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if line.is_empty() || line.starts_with("onsite density matrix") {
+            i += 1;
+            continue;
+        }
+        if !line.starts_with("spin component") { break; }
+        i += 1;
+
+        let mut rows = vec![];
+        while i < lines.len() && rows.len() < dim {
+            let values = lines[i].split_whitespace()
+                .take(dim)
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>();
+            match values {
+                Ok(v) if v.len() == dim => rows.push(v),
+                _ if lines[i].trim().is_empty() => {},
+                _ => break,
+            }
+            i += 1;
+        }
+        if rows.len() != dim { break; }
+        spins.push(rows);
+    }
+    (spins, i)
+}
+
+
+// Cyclic Jacobi rotations of the symmetrized matrix, small matrices only.
+fn _jacobi_eigenvalues(m: &[Vec<f64>]) -> Vec<f64> {
+    let n = m.len();
+    let mut a = (0 .. n)
+        .map(|i| (0 .. n).map(|j| 0.5 * (m[i][j] + m[j][i])).collect::<Vec<f64>>())
+        .collect::<Vec<_>>();
+
+    for _ in 0 .. 100 {
+        let off: f64 = (0 .. n).flat_map(|i| (0 .. n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off < 1E-24 { break; }
+
+        for p in 0 .. n {
+            for q in p+1 .. n {
+                if a[p][q].abs() < 1E-300 { continue; }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (lo, hi) = a.split_at_mut(q);
+                for (apk, aqk) in lo[p].iter_mut().zip(hi[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = c * x - s * y;
+                    *aqk = s * x + c * y;
+                }
+            }
+        }
+    }
+
+    let mut eigs = (0 .. n).map(|i| a[i][i]).collect::<Vec<f64>>();
+    eigs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    eigs
+}
+
+
+impl Tabular for LdauOccupations {
+    fn headers(&self) -> Vec<String> {
+        ["step", "atom", "type", "l", "spin", "trace", "moment", "eigenvalues"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .flat_map(|s| s.matrices.iter().map(move |m| (s.step, m)))
+            .flat_map(|(step, m)| (0 .. m.spins.len()).map(move |ispin| vec![
+                step.to_string(),
+                m.atom.to_string(),
+                m.itype.to_string(),
+                m.l.to_string(),
+                (ispin + 1).to_string(),
+                format!("{:.4}", m.trace(ispin)),
+                format!("{:.4}", m.moment()),
+                m.eigenvalues(ispin).iter().map(|e| format!("{:.4}", e)).collect::<Vec<_>>().join(" "),
+            ]))
+            .collect()
+    }
+}
+
+impl fmt::Display for LdauOccupations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for s in self.0.iter() {
+            writeln!(f, "{}", format!("# Ionic step {}", s.step).bright_green())?;
+            writeln!(f, "{}", "  Atom Type  l Spin    Trace   Moment  Eigenvalues".bright_green())?;
+            for m in s.matrices.iter() {
+                for ispin in 0 .. m.spins.len() {
+                    let eigs = m.eigenvalues(ispin).iter()
+                        .map(|e| format!("{:7.4}", e))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    writeln!(f, "  {:4} {:4} {:2} {:4} {} {:8.4}  {}",
+                             m.atom, m.itype, m.l, ispin + 1,
+                             format!("{:8.4}", m.trace(ispin)).bright_yellow(), m.moment(), eigs)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tabular for OccupationControl {
+    fn headers(&self) -> Vec<String> {
+        ["atom", "symbol", "up", "down", "nd", "moment"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.matrices.iter()
+            .map(|m| vec![
+                m.atom.to_string(),
+                self.ion_types[m.itype - 1].clone(),
+                _occupied_orbitals(&m.spins[0]),
+                _occupied_orbitals(&m.spins[1]),
+                format!("{:.1}", m.trace(0) + m.trace(1)),
+                format!("{:.1}", m.moment()),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for OccupationControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  Atom Elem  {:<28} {:<28} {:>4} {:>6}", "Up", "Down", "nd", "Moment").bright_green())?;
+        for m in self.matrices.iter() {
+            writeln!(f, "  {:4} {:>4}  {:<28} {:<28} {:4.1} {}",
+                     m.atom, self.ion_types[m.itype - 1],
+                     _occupied_orbitals(&m.spins[0]), _occupied_orbitals(&m.spins[1]),
+                     m.trace(0) + m.trace(1), format!("{:6.1}", m.moment()).bright_yellow())?;
+        }
+        writeln!(f, "{}", "# INCAR tags for the occupation matrix control".bright_green())?;
+        write!(f, "{}", self.incar_tags())
+    }
+}
+
+
+fn _occupied_orbitals(m: &[Vec<f64>]) -> String {
+    let ret = (0 .. m.len())
+        .filter(|i| m[*i][*i] > 0.0)
+        .map(|i| D_ORBITALS[i])
+        .collect::<Vec<_>>()
+        .join(" ");
+    if ret.is_empty() { "-".to_string() } else { ret }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "
+ atom =   1  type =  1  l =  1
+
+ onsite density matrix
+
+ spin component  1
+
+    0.5000   0.1000   0.0000
+    0.1000   0.5000   0.0000
+    0.0000   0.0000   0.9000
+
+ spin component  2
+
+    0.2000   0.0000   0.0000
+    0.0000   0.2000   0.0000
+    0.0000   0.0000   0.2000
+
+ occupancies and eigenvectors
+ o =  0.4000 v =  0.7071 -0.7071  0.0000
+
+  FREE ENERGIE OF THE ION-ELECTRON SYSTEM (eV)
+ atom =   1  type =  1  l =  1
+
+ onsite density matrix
+
+ spin component  1
+
+    1.0000   0.0000   0.0000
+    0.0000   1.0000   0.0000
+    0.0000   0.0000   1.0000
+
+ spin component  2
+
+    0.0000   0.0000   0.0000
+    0.0000   0.0000   0.0000
+    0.0000   0.0000   0.0000
+
+";
+
+    #[test]
+    fn test_parse_ldau() {
+        let occ = LdauOccupations::parse(SAMPLE);
+        assert_eq!(occ.0.len(), 1);
+        assert_eq!(occ.0[0].step, 1);
+
+        let m = &occ.0[0].matrices[0];
+        assert_eq!((m.atom, m.itype, m.l, m.spins.len()), (1, 1, 1, 2));
+        assert!((m.trace(0) - 1.9).abs() < 1E-10);
+        assert!((m.moment() - 1.3).abs() < 1E-10);
+
+        let eigs = m.eigenvalues(0);
+        for (e, r) in eigs.iter().zip([0.4, 0.6, 0.9].iter()) {
+            assert!((e - r).abs() < 1E-10);
+        }
+    }
+
+    #[test]
+    fn test_occupation_control() {
+        let structure = Structure {
+            cell: [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 8.0]],
+            ion_types: vec!["Mn".to_string(), "O".to_string()],
+            ions_per_type: vec![2, 2],
+            car_pos: vec![[0.0; 3]; 4],
+            frac_pos: vec![[0.0; 3]; 4],
+        };
+        let patterns = vec![
+            ("1".to_string(), "t2g dz2".to_string(), "".to_string()),
+            ("2".to_string(), "t2g x2-y2".to_string(), "".to_string()),
+        ];
+        let ctl = OccupationControl::new(&structure, &patterns, None).unwrap();
+        assert_eq!(ctl.ldaul, vec![2, -1]);
+        assert_eq!(ctl.matrices.len(), 2);
+        assert_eq!((ctl.matrices[1].trace(0), ctl.matrices[1].moment()), (4.0, 4.0));
+        assert_eq!(_occupied_orbitals(&ctl.matrices[1].spins[0]), "dxy dyz dxz x2-y2");
+
+        let occmatrix = ctl.occmatrix();
+        let lines = occmatrix.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1 + 2 * 13);
+        assert_eq!(lines[1], "1 2 2");
+        assert_eq!(lines[5], " 0.000  0.000  1.000  0.000  0.000");
+
+        // atoms targeted twice, and LDAUL inconsistent with the species
+        let patterns = vec![
+            ("Mn".to_string(), "d".to_string(), "".to_string()),
+            ("1".to_string(), "eg".to_string(), "".to_string()),
+        ];
+        assert!(OccupationControl::new(&structure, &patterns, None).is_err());
+        let incar = Incar::parse("LDAU = .TRUE.\nLDAUL = -1 -1\n");
+        assert!(OccupationControl::new(&structure, &patterns[.. 1], Some(&incar)).is_err());
+        let incar = Incar::parse("LDAUL = 2 -1\nLDAUU = 4.0 0.0");
+        let ctl = OccupationControl::new(&structure, &patterns[.. 1], Some(&incar)).unwrap();
+        assert!(ctl.incar_tags().contains("LDAUU     = 4.0 0.0\n"));
+    }
+}
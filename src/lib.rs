@@ -1,2 +1,74 @@
 pub mod outcar;
+pub mod vasprun;
 pub mod format;
+pub mod procar;
+pub mod plot;
+pub mod raster;
+pub mod settings;
+pub mod template;
+pub mod progress;
+pub mod cache;
+pub mod timing;
+pub mod spintex;
+pub mod bandchar;
+pub mod selection;
+pub mod dos;
+pub mod chgcar;
+pub mod potalign;
+pub mod fscorr;
+pub mod defect;
+pub mod summary;
+pub mod batch;
+pub mod mag;
+pub mod potcar;
+pub mod bader;
+pub mod chgsphere;
+pub mod corepot;
+pub mod elastic;
+pub mod dielec;
+pub mod eos;
+pub mod convtest;
+pub mod hull;
+pub mod cohp;
+pub mod pcoop;
+pub mod band;
+pub mod isosurface;
+pub mod potline;
+pub mod jdos;
+pub mod photoion;
+pub mod exciton;
+pub mod defpot;
+pub mod bandalign;
+pub mod layers;
+pub mod xas;
+pub mod neighbor;
+pub mod surface;
+pub mod modeproj;
+pub mod ldau;
+pub mod velocity;
+pub mod wannier;
+pub mod skw;
+pub mod chgmath;
+pub mod symmetry;
+pub mod magfield;
+pub mod vtk;
+pub mod pymatgen;
+pub mod audit;
+pub mod status;
+pub mod oszicar;
+pub mod report;
+pub mod pcdat;
+pub mod mlff;
+pub mod siteproj;
+pub mod restart;
+pub mod diffusion;
+pub mod profile;
+pub mod hbond;
+pub mod bondevents;
+pub mod fdphonon;
+pub mod constants;
+pub mod linalg;
+pub mod wavecar;
+pub mod wavdiff;
+pub mod traits;
+pub mod commands;
@@ -0,0 +1,95 @@
+// Dense linear algebra shared by the fitting and analysis modules
+
+
+/// LU factorization with partial pivoting of a square matrix, the factors are reused for
+/// several right-hand sides.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lu {
+    lu   : Vec<Vec<f64>>,  // L below the diagonal with unit diagonal elements, U on and above it
+    perm : Vec<usize>,     // row i of the factors comes from row perm[i] of the matrix
+}
+
+impl Lu {
+    /// Returns None if the matrix is singular, i.e. a pivot is smaller than 1E-12 of the largest
+    /// element in magnitude.
+    pub fn new(mut a: Vec<Vec<f64>>) -> Option<Self> {
+        let n = a.len();
+        assert!(a.iter().all(|row| row.len() == n), "Square matrix expected");
+        let amax = a.iter().flatten().fold(0.0f64, |acc, x| acc.max(x.abs()));
+        let mut perm = (0 .. n).collect::<Vec<usize>>();
+
+        for col in 0 .. n {
+            let pivot = (col .. n)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() <= 1E-12 * amax || !a[pivot][col].is_finite() {
+                return None;
+            }
+            a.swap(col, pivot);
+            perm.swap(col, pivot);
+
+            let (upper, lower) = a.split_at_mut(col + 1);
+            let prow = &upper[col];
+            for row in lower.iter_mut() {
+                let factor = row[col] / prow[col];
+                row[col] = factor;
+                row.iter_mut().zip(prow.iter()).skip(col + 1).for_each(|(x, p)| *x -= factor * p);
+            }
+        }
+        Some(Self { lu: a, perm })
+    }
+
+    pub fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.perm.len();
+        assert_eq!(b.len(), n, "Inconsistent dimensions of the matrix and vector");
+        let mut x = self.perm.iter().map(|&i| b[i]).collect::<Vec<f64>>();
+        for i in 0 .. n {
+            x[i] -= (0 .. i).map(|j| self.lu[i][j] * x[j]).sum::<f64>();
+        }
+        for i in (0 .. n).rev() {
+            x[i] = (x[i] - (i + 1 .. n).map(|j| self.lu[i][j] * x[j]).sum::<f64>()) / self.lu[i][i];
+        }
+        x
+    }
+
+    pub fn inverse(&self) -> Vec<Vec<f64>> {
+        let n = self.perm.len();
+        let columns = (0 .. n)
+            .map(|j| self.solve(&(0 .. n).map(|i| if i == j { 1.0 } else { 0.0 }).collect::<Vec<f64>>()))
+            .collect::<Vec<_>>();
+        (0 .. n).map(|i| columns.iter().map(|c| c[i]).collect()).collect()
+    }
+}
+
+
+/// Solves the linear equations A x = b, None if A is singular.
+pub fn solve(a: Vec<Vec<f64>>, b: &[f64]) -> Option<Vec<f64>> {
+    Lu::new(a).map(|lu| lu.solve(b))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lu() {
+        // needs pivoting since a[0][0] = 0
+        let a = vec![vec![0.0, 2.0, 1.0],
+                     vec![1.0, 1.0, 0.0],
+                     vec![3.0, 0.0, 4.0]];
+        let x = solve(a.clone(), &[6.0, 3.0, 11.0]).unwrap();
+        [1.0, 2.0, 2.0].iter().zip(x.iter()).for_each(|(e, x)| assert!((e - x).abs() < 1E-12));
+
+        let inv = Lu::new(a.clone()).unwrap().inverse();
+        for (i, row) in a.iter().enumerate() {
+            for j in 0 .. 3 {
+                let x = row.iter().zip(inv.iter()).map(|(a, b)| a * b[j]).sum::<f64>();
+                assert!((x - if i == j { 1.0 } else { 0.0 }).abs() < 1E-12);
+            }
+        }
+
+        assert!(solve(vec![vec![1.0, 2.0], vec![2.0, 4.0]], &[1.0, 2.0]).is_none());
+        assert!(solve(vec![vec![0.0; 2]; 2], &[0.0, 0.0]).is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    IonMagnetization,
+};
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IonMoment {
+    pub index    : usize,          // starts from 1
+    pub symbol   : String,
+    pub orbitals : Vec<Vec<f64>>,  // [ncomp][norbits]
+    pub total    : Vec<f64>,       // [ncomp]
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpeciesMoment {
+    pub symbol : String,
+    pub nions  : usize,
+    pub total  : Vec<f64>,  // summed over the ions of this species
+}
+
+
+/// Magnetic moments of ions in muB, 1 component for collinear and 3 (x, y, z) for
+/// non-collinear calculations.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MagReport {
+    pub orbitals : Vec<String>,
+    pub ions     : Vec<IonMoment>,
+    pub species  : Vec<SpeciesMoment>,
+    pub total    : Vec<f64>,
+}
+
+impl MagReport {
+    /// `symbols` are the element symbols of each ion.
+    pub fn new(mag: &IonMagnetization, symbols: &[String]) -> Self {
+        let ncomp = mag.ncomp();
+        let norbits = mag.orbitals.len();
+        assert_eq!(mag.moments[0].len(), symbols.len(), "Inconsistent ion numbers");
+
+        let ions = mag.totals()
+            .into_iter()
+            .enumerate()
+            .map(|(i, total)| IonMoment {
+                index: i + 1,
+                symbol: symbols[i].clone(),
+                orbitals: mag.moments.iter().map(|m| m[i][.. norbits].to_vec()).collect(),
+                total,
+            })
+            .collect::<Vec<_>>();
+
+        let mut species: Vec<SpeciesMoment> = vec![];
+        for ion in ions.iter() {
+            let sp = match species.iter_mut().find(|s| s.symbol == ion.symbol) {
+                Some(sp) => sp,
+                None => {
+                    species.push(SpeciesMoment { symbol: ion.symbol.clone(), nions: 0, total: vec![0.0; ncomp] });
+                    species.last_mut().unwrap()
+                },
+            };
+            sp.nions += 1;
+            sp.total.iter_mut().zip(ion.total.iter()).for_each(|(s, m)| *s += m);
+        }
+
+        let total = (0 .. ncomp)
+            .map(|ic| ions.iter().map(|ion| ion.total[ic]).sum())
+            .collect();
+
+        Self {
+            orbitals: mag.orbitals.clone(),
+            ions,
+            species,
+            total,
+        }
+    }
+
+    /// Returns `None` if the magnetization of ions is absent, e.g. for ISPIN=1 calculations.
+    pub fn from_outcar(outcar: &Outcar) -> Option<Self> {
+        let mag = outcar.ion_magmoms.as_ref()?;
+        let symbols = outcar.ion_types.iter()
+            .zip(outcar.ions_per_type.iter())
+            .flat_map(|(s, n)| vec![s.clone(); *n as usize])
+            .collect::<Vec<_>>();
+        Some(Self::new(mag, &symbols))
+    }
+
+    pub fn is_ncl(&self) -> bool {
+        self.total.len() == 3
+    }
+
+    /// MAGMOM line of INCAR for restarting, ions are in the same order as POSCAR.
+    /// Consecutive equal moments of collinear calculations are merged like "2*0.650".
+    pub fn magmom_line(&self) -> String {
+        let values = self.ions.iter()
+            .map(|ion| ion.total.iter()
+                 .map(|m| format!("{:.3}", m + 0.0))  // avoid "-0.000"
+                 .collect::<Vec<_>>()
+                 .join(" "))
+            .collect::<Vec<_>>();
+
+        let mut items: Vec<(usize, String)> = vec![];
+        for v in values.into_iter() {
+            match items.last_mut() {
+                Some((n, last)) if !self.is_ncl() && *last == v => *n += 1,
+                _ => items.push((1, v)),
+            }
+        }
+
+        let items = items.into_iter()
+            .map(|(n, v)| if n > 1 { format!("{}*{}", n, v) } else { v })
+            .collect::<Vec<_>>();
+        format!("MAGMOM = {}", items.join(" "))
+    }
+
+    fn _component_names(&self) -> Vec<&'static str> {
+        if self.is_ncl() { vec!["mx", "my", "mz"] } else { vec!["tot"] }
+    }
+}
+
+impl Tabular for MagReport {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["index".to_string(), "symbol".to_string()];
+        ret.extend(self._component_names().into_iter().map(|s| s.to_string()));
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.ions.iter()
+            .map(|ion| {
+                let mut row = vec![ion.index.to_string(), ion.symbol.clone()];
+                row.extend(ion.total.iter().map(|m| format!("{:.3}", m)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for MagReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Collinear: moments of each orbital; non-collinear: total moment along each axis
+        let mut header = "  #Ion Elem".to_string();
+        if self.is_ncl() {
+            header += &["mx", "my", "mz", "|m|"].iter().map(|s| format!("{:>8}", s)).collect::<String>();
+        } else {
+            header += &self.orbitals.iter().map(|s| format!("{:>8}", s)).collect::<String>();
+            header += &format!("{:>8}", "tot");
+        }
+        writeln!(f, "{}", header.bright_green())?;
+
+        let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        for ion in self.ions.iter() {
+            let mut line = format!("  {:4} {:>4}", ion.index, ion.symbol);
+            let values = if self.is_ncl() { ion.total.clone() } else { ion.orbitals[0].clone() };
+            line += &values.iter().map(|m| format!("{:8.3}", m)).collect::<String>();
+            let total = if self.is_ncl() { norm(&ion.total) } else { ion.total[0] };
+            line += &format!("{:8.3}", total).bright_yellow().to_string();
+            writeln!(f, "{}", line)?;
+        }
+
+        writeln!(f, "{}", "# Moments per species".bright_green())?;
+        for sp in self.species.iter() {
+            let values = sp.total.iter().map(|m| format!("{:8.3}", m)).collect::<String>();
+            writeln!(f, "  {:>4} x {:<3}{}", sp.symbol, sp.nions, values)?;
+        }
+
+        let values = self.total.iter().map(|m| format!("{:8.3}", m)).collect::<String>();
+        writeln!(f, "{}{}", "# Total moment/muB".bright_green(), values.bright_yellow())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _generate_mag(ncl: bool) -> IonMagnetization {
+        let x = vec![vec![-0.002, -0.039, 0.003, -0.037],
+                     vec![-0.002, -0.039, 0.003, -0.037],
+                     vec![ 0.008,  0.008, 0.634,  0.650]];
+        let zero = vec![vec![0.0; 4]; 3];
+        IonMagnetization {
+            orbitals: vec!["s".to_string(), "p".to_string(), "d".to_string()],
+            moments: if ncl { vec![zero.clone(), zero, x] } else { vec![x] },
+        }
+    }
+
+    #[test]
+    fn test_mag_report() {
+        let symbols = vec!["Se".to_string(), "Se".to_string(), "V".to_string()];
+        let report = MagReport::new(&_generate_mag(false), &symbols);
+        assert_eq!(report.ions[2].orbitals, vec![vec![0.008, 0.008, 0.634]]);
+        assert_eq!(report.species.len(), 2);
+        assert_eq!((report.species[0].nions, report.species[0].total[0]), (2, -0.074));
+        assert!((report.total[0] - 0.576).abs() < 1E-10);
+        assert_eq!(report.magmom_line(), "MAGMOM = 2*-0.037 0.650");
+
+        let report = MagReport::new(&_generate_mag(true), &symbols);
+        assert!(report.is_ncl());
+        assert_eq!(report.magmom_line(), "MAGMOM = 0.000 0.000 -0.037 0.000 0.000 -0.037 0.000 0.000 0.650");
+    }
+
+    #[test]
+    fn test_mag_report_ispin1() {
+        let outcar = Outcar::from_file("tests/OUTCAR_multiple_ionic_steps").unwrap();
+        assert!(outcar.ion_magmoms.is_none());
+        assert!(MagReport::from_outcar(&outcar).is_none());
+    }
+}
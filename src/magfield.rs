@@ -0,0 +1,197 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use log::info;
+use serde_json::json;
+use crate::chgcar::ChargeDensity;
+use crate::format::Structure;
+use crate::plot::Plot;
+use crate::summary::_volume;
+use crate::isosurface::_add_structure_traces;
+
+
+/// Magnetization density of noncollinear calculations as a 3D vector field, downsampled from
+/// the (mx, my, mz) blocks of CHGCAR onto a coarser grid.
+///
+/// Each vector is the average over a box of `stride`^3 grid points, placed at the center of the
+/// box, thus the total moment is kept by the downsampling. The vectors are in μB/Å^3.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MagnetizationField {
+    pub pos     : Structure,
+    pub dims    : [usize; 3],     // x runs fastest
+    pub points  : Vec<[f64; 3]>,  // Cartesian coordinates in Angstrom
+    pub vectors : Vec<[f64; 3]>,
+}
+
+impl MagnetizationField {
+    pub fn from_chgcar(chg: &ChargeDensity, stride: usize) -> io::Result<Self> {
+        if chg.blocks.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Magnetization vectors require 4 blocks of noncollinear CHGCAR, only {} found", chg.blocks.len())));
+        }
+        if stride == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Stride should be positive"));
+        }
+
+        let n = chg.ngrid;
+        let dims = n.map(|x| x.div_ceil(stride));
+        let nboxes = dims.iter().product::<usize>();
+        let mut sums = vec![[0.0f64; 3]; nboxes];
+        let mut counts = vec![0usize; nboxes];
+        for iz in 0 .. n[2] {
+            for iy in 0 .. n[1] {
+                for ix in 0 .. n[0] {
+                    let ibox = ix / stride + dims[0] * (iy / stride + dims[1] * (iz / stride));
+                    let ipoint = chg.index(ix, iy, iz);
+                    for (j, s) in sums[ibox].iter_mut().enumerate() {
+                        *s += chg.blocks[j + 1][ipoint];
+                    }
+                    counts[ibox] += 1;
+                }
+            }
+        }
+
+        let volume = _volume(chg.cell());
+        let vectors = sums.iter().zip(counts.iter())
+            .map(|(s, c)| s.map(|x| x / *c as f64 / volume))
+            .collect();
+
+        // Center of each box, the last boxes are truncated if the grid is not divisible
+        let cell = chg.cell();
+        let center = |i: usize, a: usize| {
+            let start = i * stride;
+            let end = (start + stride).min(n[a]);
+            (start + end - 1) as f64 / 2.0 / n[a] as f64
+        };
+        let points = (0 .. nboxes)
+            .map(|ibox| {
+                let idx = [ibox % dims[0], ibox / dims[0] % dims[1], ibox / (dims[0] * dims[1])];
+                let frac = [0, 1, 2].map(|a| center(idx[a], a));
+                [0, 1, 2].map(|j| (0 .. 3).map(|i| frac[i] * cell[i][j]).sum::<f64>())
+            })
+            .collect();
+
+        Ok(Self {
+            pos: chg.pos.clone(),
+            dims,
+            points,
+            vectors,
+        })
+    }
+
+    pub fn magnitudes(&self) -> Vec<f64> {
+        self.vectors.iter()
+            .map(|v| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt())
+            .collect()
+    }
+
+    /// Writes the field as a legacy VTK structured grid with the vectors and their magnitudes
+    /// as point data, readable by ParaView (e.g. with the Glyph filter).
+    pub fn save_as_vtk(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving magnetization vector field to {:?} ...", path.as_ref());
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(f, "# vtk DataFile Version 3.0")?;
+        writeln!(f, "Magnetization density in muB/A^3, written by rsgrad")?;
+        writeln!(f, "ASCII")?;
+        writeln!(f, "DATASET STRUCTURED_GRID")?;
+        writeln!(f, "DIMENSIONS {} {} {}", self.dims[0], self.dims[1], self.dims[2])?;
+        writeln!(f, "POINTS {} double", self.points.len())?;
+        for p in self.points.iter() {
+            writeln!(f, "{:.6} {:.6} {:.6}", p[0], p[1], p[2])?;
+        }
+        writeln!(f, "POINT_DATA {}", self.vectors.len())?;
+        writeln!(f, "VECTORS magnetization double")?;
+        for v in self.vectors.iter() {
+            writeln!(f, "{:.6e} {:.6e} {:.6e}", v[0], v[1], v[2])?;
+        }
+        writeln!(f, "SCALARS magnitude double 1")?;
+        writeln!(f, "LOOKUP_TABLE default")?;
+        for m in self.magnitudes() {
+            writeln!(f, "{:.6e}", m)?;
+        }
+        f.flush()
+    }
+
+    /// Draws the vectors as cones together with the unit cell and atoms. Vectors shorter than
+    /// `cutoff` times the longest one are skipped to keep the plot readable.
+    pub fn save_as_html(&self, cutoff: f64, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mags = self.magnitudes();
+        let mmax = mags.iter().cloned().fold(0.0f64, f64::max);
+        let selected = (0 .. self.points.len())
+            .filter(|&i| mmax > 0.0 && mags[i] >= cutoff * mmax)
+            .collect::<Vec<_>>();
+        info!("Drawing {} of {} magnetization vectors, the longest is {:.6} μB/Å^3", selected.len(), self.points.len(), mmax);
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Magnetization density",
+                "showlegend": true,
+                "scene": {
+                    "aspectmode": "data",
+                    "xaxis": {"visible": false},
+                    "yaxis": {"visible": false},
+                    "zaxis": {"visible": false},
+                },
+            }));
+
+        let coord = |v: &[[f64; 3]], j: usize| selected.iter().map(|&i| v[i][j]).collect::<Vec<f64>>();
+        plot.add_trace(json!({
+            "type": "cone",
+            "name": "magnetization",
+            "showlegend": true,
+            "x": coord(&self.points, 0),
+            "y": coord(&self.points, 1),
+            "z": coord(&self.points, 2),
+            "u": coord(&self.vectors, 0),
+            "v": coord(&self.vectors, 1),
+            "w": coord(&self.vectors, 2),
+            "anchor": "center",
+            "sizemode": "scaled",
+            "sizeref": 0.8,
+            "colorscale": "Viridis",
+            "colorbar": {"title": "|m| (μB/Å^3)"},
+        }));
+
+        _add_structure_traces(&mut plot, &self.pos);
+        plot.save_html(path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_magnetization_field() {
+        let cell = [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 4.0]];
+        let pos = Structure {
+            cell,
+            ion_types: vec!["Fe".to_string()],
+            ions_per_type: vec![1],
+            car_pos: vec![[0.0; 3]],
+            frac_pos: vec![[0.0; 3]],
+        };
+        let ngrid = [2, 2, 3];
+        let mz = (0 .. 12).map(|i| i as f64 * 16.0).collect::<Vec<f64>>();
+        let chg = ChargeDensity { pos, ngrid, blocks: vec![vec![1.0; 12], vec![16.0; 12], vec![0.0; 12], mz] };
+
+        let field = MagnetizationField::from_chgcar(&chg, 2).unwrap();
+        assert_eq!(field.dims, [1, 1, 2]);
+        // Box of iz = 0, 1 and the truncated box of iz = 2
+        assert_eq!(field.vectors, vec![[1.0, 0.0, 3.5], [1.0, 0.0, 9.5]]);
+        assert_eq!(field.points, vec![[0.5, 0.5, 2.0 / 3.0], [0.5, 0.5, 8.0 / 3.0]]);
+
+        let dir = TempDir::new("rsgrad_magfield").unwrap();
+        let path = dir.path().join("mag.vtk");
+        field.save_as_vtk(&path).unwrap();
+        let vtk = fs::read_to_string(&path).unwrap();
+        assert!(vtk.contains("DIMENSIONS 1 1 2\nPOINTS 2 double\n"));
+        assert!(vtk.contains("VECTORS magnetization double\n1.000000e0 0.000000e0 3.500000e0\n"));
+
+        let collinear = ChargeDensity { blocks: chg.blocks[.. 2].to_vec(), ..chg };
+        assert!(MagnetizationField::from_chgcar(&collinear, 1).is_err());
+    }
+}
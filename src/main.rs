@@ -1,24 +1,81 @@
 use std::io::Result;
-use std::path::PathBuf;
+use std::io::Write;
 use std::time;
-use env_logger;
 use log::{
     info,
-    warn,
     debug,
 };
-use colored::Colorize;
 use structopt::StructOpt;
-use rayon::prelude::*;
-use rsgrad::outcar::Outcar;
-use rsgrad::format::{
-    IonicIterationsFormat,
-    Vibrations,
-    Trajectory,
-    PrintAllVibFreqs,
+use structopt::clap::AppSettings;
+use serde_json::json;
+use rsgrad::cache;
+use rsgrad::progress;
+use rsgrad::timing::TimingSummary;
+use rsgrad::traits::OptProcess;
+use rsgrad::commands::{
+    GlobalOpts,
+    Rlx,
+    Vib,
+    Trj,
+    List,
+    Gap,
+    Spintex,
+    Bandchar,
+    Dos,
+    Potalign,
+    Defect,
+    Batch,
+    Mag,
+    Bader,
+    Chgresample,
+    Chgsphere,
+    Corepot,
+    Elastic,
+    Dielec,
+    Eos,
+    Convtest,
+    Hull,
+    Cohp,
+    Band,
+    Chgview,
+    Uc,
+    Potline,
+    Jdos,
+    Exciton,
+    Defpot,
+    Bandalign,
+    Layers,
+    Xas,
+    Poscar,
+    Neigh,
+    Surface,
+    Modeproj,
+    Ldau,
+    Velocity,
+    Chgmath,
+    Symmetrize,
+    Chgvtk,
+    Pymatgen,
+    Audit,
+    Status,
+    Bluemoon,
+    Pcdat,
+    Mlff,
+    Siteproj,
+    Occmatrix,
+    Restart,
+    Conductivity,
+    Profile,
+    Hbond,
+    Bondevents,
+    Phonondisp,
+    Forcesets,
+    Wavecar,
+    Wav3d,
+    Unk,
+    Wavdiff,
 };
 
-use structopt::clap::AppSettings;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "rsgrad",
@@ -30,262 +87,197 @@ struct Opt {
     #[structopt(subcommand)]
     command: Command,
 
-    #[structopt(default_value = "./OUTCAR")]
-    /// Specify the input OUTCAR file name
-    input: PathBuf,
-}
-
-#[derive(Debug, StructOpt)]
-enum Command {
-    #[structopt(setting = AppSettings::ColoredHelp,
-                setting = AppSettings::ColorAuto)]
-    /// Tracking info associated with relaxation stuff
-    Rlx {
-        #[structopt(short = "e", long = "toten")]
-        /// Prints TOTEN in eV
-        print_energy: bool,
-
-        #[structopt(short = "a", long = "favg")]
-        /// Prints averaged total force in eV/A
-        print_favg: bool,
-
-        #[structopt(short = "x", long = "fmaxis")]
-        /// Prints the axis where the strongest total force component lies on. [XYZ]
-        print_fmax_axis: bool,
-
-        #[structopt(short = "i" ,long = "fmidx")]
-        /// Prints the index of ion with maximum total force load. Starts from 1
-        print_fmax_index: bool,
-
-        #[structopt(short = "v", long = "volume")]
-        /// Prints lattice volume in A^3
-        print_volume: bool,
-
-        #[structopt(long = "no-fmax")]
-        /// Don't print maximum total force in A^3
-        no_print_fmax: bool,
+    #[structopt(flatten)]
+    global: GlobalOpts,
 
-        #[structopt(long = "no-totenz")]
-        /// Don't print TOTEN without entropy in eV
-        no_print_energyz: bool,
+    #[structopt(short, long, parse(from_occurrences))]
+    /// Print more logs, -v for debug and -vv for trace messages. Given before the subcommand
+    verbose: u8,
 
-        #[structopt(long = "no-lgde")]
-        /// Don't print Log10(delta(TOTEN without entropy))
-        no_print_lgde: bool,
+    #[structopt(short, long, parse(from_occurrences))]
+    /// Print fewer logs, -q for warnings and errors only and -qq for errors only, the progress bars
+    /// are hidden as well. Given before the subcommand
+    quiet: u8,
 
-        #[structopt(long = "no-magmom")]
-        /// Don't print total magnetic moment in muB
-        no_print_magmom: bool,
-
-        #[structopt(long = "no-nscf")]
-        /// Don't print number of SCF iteration for each ionic step
-        no_print_nscf: bool,
-
-        #[structopt(long = "no-time")]
-        /// Don't print time elapsed for each ionic step in minutes
-        no_print_time: bool,
-    },
-
-    #[structopt(setting = AppSettings::ColoredHelp,
-                setting = AppSettings::ColorAuto,
-                setting = AppSettings::AllowNegativeNumbers)]
-    /// Tracking info associated with vibration stuff
-    Vib {
-        #[structopt(short, long)]
-        /// Shows vibration modes in brief
-        list: bool,
-
-        #[structopt(short = "x", long)]
-        /// Saves each selected modes to XSF file
-        save_as_xsfs: bool,
-
-        #[structopt(short = "i", long)]
-        /// Selects the indices to operate.
-        ///
-        /// Step indices start from '1', if '0' is given, all the structures will be selected.
-        /// Step indices can be negative, where negative index means counting reversely.
-        /// E.g. "--save-as-poscars -2 -1 1 2 3" means saving the last two and first three
-        /// steps.
-        select_indices: Option<Vec<i32>>,
-
-        #[structopt(long, default_value = ".")]
-        /// Define where the files would be saved
-        save_in: PathBuf,
-    },
-
-    #[structopt(setting = AppSettings::ColoredHelp,
-                setting = AppSettings::ColorAuto,
-                setting = AppSettings::AllowNegativeNumbers)]
-    /// Operations about relaxation/MD trajectory
-    Trj {
-        #[structopt(short = "i", long)]
-        /// Selects the indices to operate.
-        ///
-        /// Step indices start from '1', if '0' is given, all the structures will be selected.
-        /// Step indices can be negative, where negative index means counting reversely.
-        /// E.g. "--save-as-poscars -2 -1 1 2 3" means saving the last two and first three
-        /// steps.
-        select_indices: Option<Vec<i32>>,
-
-        #[structopt(short = "d", long)]
-        /// Saves total trajectory in XDATCAR format
-        save_as_xdatcar: bool,
-
-        #[structopt(short = "p", long)]
-        /// Saves structures of given steps as POSCARs
-        save_as_poscars: bool,
+    #[structopt(long, global = true, default_value = "text", possible_values = &["text", "json"])]
+    /// Format of the logs on stderr, "json" writes one JSON object per line for batch pipelines
+    log_format: String,
+}
 
-        #[structopt(short = "x", long)]
-        /// Saves structures of given steps as XSFs
-        save_as_xsfs: bool,
+impl Opt {
+    // `RSGRAD_LOG` overrides the verbosity set by -v and -q, e.g. "RSGRAD_LOG=rsgrad::procar=debug"
+    fn init_logger(&self) {
+        let level = match self.verbose as i32 - self.quiet as i32 {
+            i32::MIN ..= -2 => "error",
+            -1 => "warn",
+            0  => "info",
+            1  => "debug",
+            _  => "trace",
+        };
+        let env = env_logger::Env::new().filter_or("RSGRAD_LOG", level);
+        let mut builder = env_logger::Builder::from_env(env);
+        if self.log_format == "json" {
+            builder.format(|buf, record| {
+                writeln!(buf, "{}", json!({
+                    "time": buf.timestamp().to_string(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                }))
+            });
+        }
+        builder.init();
+    }
+}
 
-        #[structopt(long, default_value = ".")]
-        /// Defines where the files would be saved
-        save_in: PathBuf,
-    },
+#[derive(Debug, StructOpt)]
+enum Command {
+    Rlx(Rlx),
+    Vib(Vib),
+    Trj(Trj),
+    List(List),
+    Gap(Gap),
+    Spintex(Spintex),
+    Bandchar(Bandchar),
+    Dos(Dos),
+    Potalign(Potalign),
+    Defect(Defect),
+    Batch(Batch),
+    Mag(Mag),
+    Bader(Bader),
+    Chgresample(Chgresample),
+    Chgsphere(Chgsphere),
+    Corepot(Corepot),
+    Elastic(Elastic),
+    Dielec(Dielec),
+    Eos(Eos),
+    Convtest(Convtest),
+    Hull(Hull),
+    Cohp(Cohp),
+    Band(Band),
+    Chgview(Chgview),
+    Uc(Uc),
+    Potline(Potline),
+    Jdos(Jdos),
+    Exciton(Exciton),
+    Defpot(Defpot),
+    Bandalign(Bandalign),
+    Layers(Layers),
+    Xas(Xas),
+    Poscar(Poscar),
+    Neigh(Neigh),
+    Surface(Surface),
+    Modeproj(Modeproj),
+    Ldau(Ldau),
+    Velocity(Velocity),
+    Chgmath(Chgmath),
+    Symmetrize(Symmetrize),
+    Chgvtk(Chgvtk),
+    Pymatgen(Pymatgen),
+    Audit(Audit),
+    Status(Status),
+    Bluemoon(Bluemoon),
+    Pcdat(Pcdat),
+    Mlff(Mlff),
+    Siteproj(Siteproj),
+    Occmatrix(Occmatrix),
+    Restart(Restart),
+    Conductivity(Conductivity),
+    Profile(Profile),
+    Hbond(Hbond),
+    Bondevents(Bondevents),
+    Phonondisp(Phonondisp),
+    Forcesets(Forcesets),
+    Wavecar(Wavecar),
+    Wav3d(Wav3d),
+    Unk(Unk),
+    Wavdiff(Wavdiff),
+}
 
-    #[structopt(setting = AppSettings::ColoredHelp,
-                setting = AppSettings::ColorAuto)]
-    /// Lists the brief info of current OUTCAR
-    List,
+impl Command {
+    fn process(&self, global: &GlobalOpts) -> Result<()> {
+        match self {
+            Command::Rlx(cmd)         => cmd.process(global),
+            Command::Vib(cmd)         => cmd.process(global),
+            Command::Trj(cmd)         => cmd.process(global),
+            Command::List(cmd)        => cmd.process(global),
+            Command::Gap(cmd)         => cmd.process(global),
+            Command::Spintex(cmd)     => cmd.process(global),
+            Command::Bandchar(cmd)    => cmd.process(global),
+            Command::Dos(cmd)         => cmd.process(global),
+            Command::Potalign(cmd)    => cmd.process(global),
+            Command::Defect(cmd)      => cmd.process(global),
+            Command::Batch(cmd)       => cmd.process(global),
+            Command::Mag(cmd)         => cmd.process(global),
+            Command::Bader(cmd)       => cmd.process(global),
+            Command::Chgresample(cmd) => cmd.process(global),
+            Command::Chgsphere(cmd)   => cmd.process(global),
+            Command::Corepot(cmd)     => cmd.process(global),
+            Command::Elastic(cmd)     => cmd.process(global),
+            Command::Dielec(cmd)      => cmd.process(global),
+            Command::Eos(cmd)         => cmd.process(global),
+            Command::Convtest(cmd)    => cmd.process(global),
+            Command::Hull(cmd)        => cmd.process(global),
+            Command::Cohp(cmd)        => cmd.process(global),
+            Command::Band(cmd)        => cmd.process(global),
+            Command::Chgview(cmd)     => cmd.process(global),
+            Command::Uc(cmd)          => cmd.process(global),
+            Command::Potline(cmd)     => cmd.process(global),
+            Command::Jdos(cmd)        => cmd.process(global),
+            Command::Exciton(cmd)     => cmd.process(global),
+            Command::Defpot(cmd)      => cmd.process(global),
+            Command::Bandalign(cmd)   => cmd.process(global),
+            Command::Layers(cmd)      => cmd.process(global),
+            Command::Xas(cmd)         => cmd.process(global),
+            Command::Poscar(cmd)      => cmd.process(global),
+            Command::Neigh(cmd)       => cmd.process(global),
+            Command::Surface(cmd)     => cmd.process(global),
+            Command::Modeproj(cmd)    => cmd.process(global),
+            Command::Ldau(cmd)        => cmd.process(global),
+            Command::Velocity(cmd)    => cmd.process(global),
+            Command::Chgmath(cmd)     => cmd.process(global),
+            Command::Symmetrize(cmd)  => cmd.process(global),
+            Command::Chgvtk(cmd)      => cmd.process(global),
+            Command::Pymatgen(cmd)    => cmd.process(global),
+            Command::Audit(cmd)       => cmd.process(global),
+            Command::Status(cmd)      => cmd.process(global),
+            Command::Bluemoon(cmd)    => cmd.process(global),
+            Command::Pcdat(cmd)       => cmd.process(global),
+            Command::Mlff(cmd)        => cmd.process(global),
+            Command::Siteproj(cmd)    => cmd.process(global),
+            Command::Occmatrix(cmd)   => cmd.process(global),
+            Command::Restart(cmd)     => cmd.process(global),
+            Command::Conductivity(cmd) => cmd.process(global),
+            Command::Profile(cmd)     => cmd.process(global),
+            Command::Hbond(cmd)       => cmd.process(global),
+            Command::Bondevents(cmd)  => cmd.process(global),
+            Command::Phonondisp(cmd)  => cmd.process(global),
+            Command::Forcesets(cmd)   => cmd.process(global),
+            Command::Wavecar(cmd)     => cmd.process(global),
+            Command::Wav3d(cmd)       => cmd.process(global),
+            Command::Unk(cmd)         => cmd.process(global),
+            Command::Wavdiff(cmd)     => cmd.process(global),
+        }
+    }
 }
 
 
 fn main() -> Result<()> {
     let now = time::Instant::now();
 
-    let env = env_logger::Env::new().filter_or("RSGRAD_LOG", "info");
-    env_logger::init_from_env(env);
-
     let opt = Opt::from_args();
+    opt.init_logger();
     debug!("{:?}", opt);
 
-    info!("Parsing input file {:?} ...", &opt.input);
-    let outcar = Outcar::from_file(&opt.input)?;
-
-    let _index_transform_helper = |v: Vec<i32>, len: usize| -> Vec<usize> {
-        if v.contains(&0) {
-            (1..=len).collect()
-        } else {
-            v.into_iter()
-             .map(|i| {
-                 if i < 0 {
-                     i.rem_euclid(len as i32) as usize + 1
-                 } else {
-                     i as usize
-                 }
-             })
-             .collect()
-        }
-    };
-
-    match opt.command {
-        Command::Rlx { print_energy,
-                       print_favg,
-                       print_fmax_axis,
-                       print_fmax_index,
-                       print_volume,
-                       no_print_fmax,
-                       no_print_energyz,
-                       no_print_lgde,
-                       no_print_magmom,
-                       no_print_nscf,
-                       no_print_time } => {
-            let iif = IonicIterationsFormat::from(outcar.ion_iters)
-                .print_energy     (print_energy)
-                .print_energyz    (!no_print_energyz)
-                .print_log10de    (!no_print_lgde)
-                .print_favg       (print_favg)
-                .print_fmax       (!no_print_fmax)
-                .print_fmax_axis  (print_fmax_axis)
-                .print_fmax_index (print_fmax_index)
-                .print_nscf       (!no_print_nscf)
-                .print_time_usage (!no_print_time)
-                .print_magmom     (!no_print_magmom)
-                .print_volume     (print_volume);
-            print!("{}", iif);
-        },
-        Command::Vib { list,
-                       save_as_xsfs,
-                       select_indices,
-                       save_in } => {
-            if list {
-                let paf: PrintAllVibFreqs = Vibrations::from(outcar).into();
-                print!("{}", paf);
-                return Ok(());
-            }
-
-            if save_as_xsfs {
-                let select_indices = select_indices.unwrap_or_default();
-                if select_indices.len() == 0 {
-                    warn!("No modes are selected to operate!");
-                    return Ok(());
-                }
-
-                let vibs = Vibrations::from(outcar);
-                let len = vibs.modes.len();
-
-                let inds: Vec<usize> = _index_transform_helper(select_indices, len);
-
-                inds.par_iter()
-                    .map(|i| {
-                        vibs.save_as_xsf(*i, &save_in)?;
-                        Ok(())
-                    })
-                    .collect::<Result<()>>()?;
-            }
-        },
-        Command::Trj { select_indices,
-                       save_as_xdatcar,
-                       save_as_poscars,
-                       save_as_xsfs,
-                       save_in } => {
-
-            let traj = Trajectory::from(outcar.clone());
-
-            if save_as_xdatcar {
-                traj.save_as_xdatcar(&save_in)?;
-                return Ok(())
-            }
-
-            let select_indices = select_indices.unwrap_or_default();
-            if select_indices.len() == 0 {
-                warn!("No steps are selected to operate !");
-                return Ok(());
-            }
-            let inds = _index_transform_helper(select_indices, traj.0.len());
-
-            if save_as_poscars {
-                inds.par_iter()
-                    .map(|i| {
-                        traj.save_as_poscar(*i, &save_in)?;
-                        Ok(())
-                    })
-                    .collect::<Result<()>>()?;
-            }
-
-            if save_as_xsfs {
-                inds.par_iter()
-                    .map(|i| {
-                        outcar.save_ionic_step_as_xsf(*i, &save_in)?;
-                        Ok(())
-                    })
-                    .collect::<Result<()>>()?;
-            }
-
-        },
-        Command::List => {
-            println!("{:>10} = {:10}", "IBRION".bright_green(), outcar.ibrion);
-            println!("{:>10} = {:10}", "NKPTS".bright_green(), outcar.nkpts);
-            println!("{:>10} = {:10}", "NIONS".bright_green(), outcar.nions);
-            println!("{:>10} = {:10}", "NSW".bright_green(), outcar.ion_iters.len());
-            println!("{:>10} = {:10}", "ISPIN".bright_green(), outcar.ispin);
-            println!("{:>10} = {:>10}", "LSORBIT".bright_green(), outcar.lsorbit);
-            println!("{:>10} = {:10.4}", "EFERMI".bright_green(), outcar.efermi);
-            println!("{:>10} = {:10}", "NBANDS".bright_green(), outcar.nbands);
-        }
+    if opt.global.cache {
+        cache::enable();
+    }
+    if opt.quiet > 0 {
+        progress::hide();
     }
 
-    info!("Time used: {:?}", now.elapsed());
+    opt.command.process(&opt.global)?;
+
+    info!("Time used: {}", TimingSummary::collect(now.elapsed()));
     Ok(())
 }
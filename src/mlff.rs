@@ -0,0 +1,538 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use std::collections::BTreeMap;
+use log::info;
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use crate::outcar::{
+    Mat33,
+    MatX3,
+};
+use crate::plot::Plot;
+use crate::traits::Tabular;
+use crate::constants::EV_PER_A3_TO_GPA;
+
+
+// 1 kB = 0.1 GPa
+const KB_TO_EV_PER_A3: f64 = 0.1 / EV_PER_A3_TO_GPA;
+
+
+// One training structure in ML_AB, with the energies, forces and stress of DFT
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MlConfig {
+    pub system        : String,
+    pub ion_types     : Vec<String>,
+    pub ions_per_type : Vec<usize>,
+    pub ctifor        : Option<f64>,
+    pub cell          : Mat33<f64>,
+    pub positions     : MatX3<f64>,       // Cartesian, in Angstrom
+    pub energy        : f64,              // total energy in eV
+    pub forces        : MatX3<f64>,       // in eV/A
+    pub stress        : Option<[f64; 6]>, // XX YY ZZ XY YZ ZX in kB
+}
+
+impl MlConfig {
+    pub fn nions(&self) -> usize {
+        self.ions_per_type.iter().sum()
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        self.ion_types.iter()
+            .zip(self.ions_per_type.iter())
+            .flat_map(|(s, n)| vec![s.clone(); *n])
+            .collect()
+    }
+}
+
+
+/// Training set of the machine learning force field, i.e. ML_AB of VASP 6. The basis sets
+/// (local reference configurations) are not kept.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MlAb {
+    pub ion_types : Vec<String>,
+    pub configs   : Vec<MlConfig>,
+}
+
+impl MlAb {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Self::parse(&context)
+    }
+
+    /// ML_AB consists of the sections titled after the lines of '*' or '=', with the contents
+    /// after the lines of '-'. Each configuration starts with the "Configuration num." title.
+    pub fn parse(context: &str) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let sections = _sections(context);
+
+        let mut ret = Self::default();
+        let mut iter = sections.iter().peekable();
+        while let Some((title, _)) = iter.peek() {
+            if title.starts_with("Configuration num.") {
+                break;
+            }
+            let (title, body) = iter.next().unwrap();
+            if title.starts_with("The atom types in the data file") {
+                ret.ion_types = body.iter().flat_map(|l| l.split_whitespace()).map(|s| s.to_string()).collect();
+            }
+        }
+
+        while let Some((title, _)) = iter.next() {
+            if !title.starts_with("Configuration num.") {
+                continue;
+            }
+            let mut system = String::new();
+            let mut types = vec![];
+            let mut ctifor = None;
+            let mut cell = None;
+            let mut positions = vec![];
+            let mut energy = None;
+            let mut forces = vec![];
+            let mut stress = None;
+            while let Some((title, body)) = iter.next_if(|(t, _)| !t.starts_with("Configuration num.")) {
+                let numbers = || body.iter()
+                    .map(|l| l.split_whitespace().map(|x| x.parse::<f64>()).collect::<Result<Vec<_>, _>>())
+                    .filter_map(Result::ok)
+                    .filter(|v| !v.is_empty())
+                    .collect::<Vec<_>>();
+                let vectors = || numbers().into_iter()
+                    .filter(|v| v.len() >= 3)
+                    .map(|v| [v[0], v[1], v[2]])
+                    .collect::<Vec<_>>();
+                match title.as_str() {
+                    "System name" => system = body.first().map(|s| s.trim().to_string()).unwrap_or_default(),
+                    "Atom types and atom numbers" => {
+                        types = body.iter()
+                            .filter_map(|l| {
+                                let v = l.split_whitespace().collect::<Vec<_>>();
+                                Some((v.first()?.to_string(), v.get(1)?.parse::<usize>().ok()?))
+                            })
+                            .collect();
+                    },
+                    "CTIFOR" => ctifor = numbers().first().map(|v| v[0]),
+                    t if t.starts_with("Primitive lattice vectors") => {
+                        let v = vectors();
+                        if v.len() == 3 {
+                            cell = Some([v[0], v[1], v[2]]);
+                        }
+                    },
+                    t if t.starts_with("Atomic positions") => positions = vectors(),
+                    t if t.starts_with("Total energy") => energy = numbers().first().map(|v| v[0]),
+                    t if t.starts_with("Forces") => forces = vectors(),
+                    t if t.starts_with("Stress") => {
+                        let v = numbers().concat();
+                        if v.len() == 6 {
+                            stress = Some([v[0], v[1], v[2], v[3], v[4], v[5]]);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            let iconf = ret.configs.len() + 1;
+            let nions = types.iter().map(|(_, n)| n).sum::<usize>();
+            if positions.len() != nions || forces.len() != nions {
+                return Err(err(format!("Inconsistent number of atoms in configuration {} of ML_AB", iconf)));
+            }
+            ret.configs.push(MlConfig {
+                system,
+                ion_types: types.iter().map(|(s, _)| s.clone()).collect(),
+                ions_per_type: types.iter().map(|(_, n)| *n).collect(),
+                ctifor,
+                cell: cell.ok_or_else(|| err(format!("Lattice vectors not found in configuration {} of ML_AB", iconf)))?,
+                positions,
+                energy: energy.ok_or_else(|| err(format!("Total energy not found in configuration {} of ML_AB", iconf)))?,
+                forces,
+                stress,
+            });
+        }
+
+        if ret.configs.is_empty() {
+            return Err(err("No configuration found in ML_AB".to_string()));
+        }
+        Ok(ret)
+    }
+
+    /// Extended XYZ of all the configurations, with the energies, forces and stress (in eV/A^3
+    /// with the sign convention of ASE, i.e. the negative of the pressure) for external training.
+    pub fn save_as_extxyz(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving {} configurations to {:?} ...", self.configs.len(), path.as_ref());
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        for c in self.configs.iter() {
+            let lattice = c.cell.iter()
+                .flat_map(|v| v.iter())
+                .map(|x| format!("{:.8}", x))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let stress = c.stress.map(|s| {
+                let s = s.map(|x| -x * KB_TO_EV_PER_A3);
+                format!(r#" stress="{:.8} {:.8} {:.8} {:.8} {:.8} {:.8} {:.8} {:.8} {:.8}""#,
+                        s[0], s[3], s[5], s[3], s[1], s[4], s[5], s[4], s[2])
+            }).unwrap_or_default();
+            writeln!(f, "{}", c.nions())?;
+            writeln!(f, r#"Lattice="{}" Properties=species:S:1:pos:R:3:forces:R:3 energy={:.8}{} config_type={} pbc="T T T""#,
+                     lattice, c.energy, stress, c.system.replace(char::is_whitespace, "_"))?;
+            for ((s, p), fc) in c.symbols().iter().zip(c.positions.iter()).zip(c.forces.iter()) {
+                writeln!(f, "{:4} {:15.8} {:15.8} {:15.8} {:13.6} {:13.6} {:13.6}",
+                         s, p[0], p[1], p[2], fc[0], fc[1], fc[2])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Records of ML_LOGFILE, keyed by the first word of the lines, e.g. "ERR", "BEEF", "LCONF".
+/// The column names are read from the comment lines like "# ERR nstep (  2) rmse_energy (  3) ...".
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MlLog {
+    pub columns : BTreeMap<String, Vec<String>>,
+    pub records : BTreeMap<String, Vec<Vec<String>>>,
+}
+
+impl MlLog {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    pub fn parse(context: &str) -> Self {
+        let mut ret = Self::default();
+        for line in context.lines() {
+            let v = line.split_whitespace().collect::<Vec<_>>();
+            if v.len() < 3 {
+                continue;
+            }
+            if v[0] == "#" {
+                // "# ERR nstep (  2) rmse_energy (  3) ...", the names are followed by indices
+                if v[1].chars().all(|c| c.is_ascii_uppercase()) && v.get(3).is_some_and(|x| x.starts_with('(')) {
+                    let names = line.split(')')
+                        .filter_map(|s| s.split('(').next())
+                        .filter_map(|s| s.split_whitespace().last())
+                        .filter(|s| *s != v[1])
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>();
+                    ret.columns.insert(v[1].to_string(), names);
+                }
+            } else if v[0].chars().all(|c| c.is_ascii_uppercase()) && v[1].parse::<usize>().is_ok() {
+                ret.records.entry(v[0].to_string())
+                    .or_default()
+                    .push(v[1 ..].iter().map(|s| s.to_string()).collect());
+            }
+        }
+        ret
+    }
+
+    /// Values of the column of the records, given by the name in header or the index if the
+    /// header is missing. Unparsable values are NaN.
+    pub fn column(&self, key: &str, name: &str, default_index: usize) -> Vec<f64> {
+        let index = self.columns.get(key)
+            .and_then(|names| names.iter().position(|n| n == name))
+            .unwrap_or(default_index);
+        self.records.get(key)
+            .map(|recs| recs.iter()
+                 .map(|r| r.get(index).and_then(|x| x.parse().ok()).unwrap_or(f64::NAN))
+                 .collect())
+            .unwrap_or_default()
+    }
+
+    /// Root mean square errors of the training set at each learning step: (nstep, energy per
+    /// atom in eV, force in eV/A, stress in kB)
+    pub fn errors(&self) -> Vec<(usize, f64, f64, f64)> {
+        let nstep = self.column("ERR", "nstep", 0);
+        let e = self.column("ERR", "rmse_energy", 1);
+        let f = self.column("ERR", "rmse_force", 2);
+        let s = self.column("ERR", "rmse_stress", 3);
+        (0 .. nstep.len()).map(|i| (nstep[i] as usize, e[i], f[i], s[i])).collect()
+    }
+
+    /// Number of local reference configurations of each element in the last "LCONF" record.
+    pub fn local_configurations(&self) -> BTreeMap<String, usize> {
+        let key = "LCONF";
+        let (Some(names), Some(recs)) = (self.columns.get(key), self.records.get(key)) else {
+            return BTreeMap::new();
+        };
+        let iel = names.iter().position(|n| n == "el");
+        let inew = names.iter().position(|n| n == "nlrc_new");
+        let (Some(iel), Some(inew)) = (iel, inew) else {
+            return BTreeMap::new();
+        };
+        let mut ret = BTreeMap::new();
+        for r in recs.iter() {
+            if let (Some(el), Some(n)) = (r.get(iel), r.get(inew).and_then(|x| x.parse().ok())) {
+                ret.insert(el.clone(), n);
+            }
+        }
+        ret
+    }
+
+    pub fn save_errors_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let errors = self.errors();
+        let x = errors.iter().map(|e| e.0).collect::<Vec<_>>();
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Errors of the training set",
+                "xaxis": {"title": "Learning step"},
+                "yaxis": {"title": "RMSE", "type": "log"},
+            }));
+        for (i, name) in ["Energy (eV/atom)", "Force (eV/Å)", "Stress (kB)"].iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines+markers",
+                "name": name,
+                "x": x,
+                "y": errors.iter().map(|e| [e.1, e.2, e.3][i]).collect::<Vec<_>>(),
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+
+// Number of configurations and atoms of one element in the training set
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ElementCount {
+    pub element  : String,
+    pub nconfigs : usize,
+    pub natoms   : usize,
+    pub nlrc     : Option<usize>,  // local reference configurations from ML_LOGFILE
+}
+
+// Configurations of one system name
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SystemCount {
+    pub system   : String,
+    pub nconfigs : usize,
+    pub emin     : f64,  // in eV/atom
+    pub emax     : f64,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MlffSummary {
+    pub nconfigs   : usize,
+    pub systems    : Vec<SystemCount>,
+    pub elements   : Vec<ElementCount>,
+    pub last_error : Option<(usize, f64, f64, f64)>,
+}
+
+impl MlffSummary {
+    pub fn new(ab: &MlAb, log: Option<&MlLog>) -> Self {
+        let mut systems = Vec::<SystemCount>::new();
+        for c in ab.configs.iter() {
+            let e = c.energy / c.nions() as f64;
+            match systems.iter_mut().find(|s| s.system == c.system) {
+                Some(s) => {
+                    s.nconfigs += 1;
+                    s.emin = s.emin.min(e);
+                    s.emax = s.emax.max(e);
+                },
+                None => systems.push(SystemCount { system: c.system.clone(), nconfigs: 1, emin: e, emax: e }),
+            }
+        }
+
+        let nlrc = log.map(|l| l.local_configurations()).unwrap_or_default();
+        let mut elements = ab.ion_types.clone();
+        for c in ab.configs.iter() {
+            for t in c.ion_types.iter() {
+                if !elements.contains(t) {
+                    elements.push(t.clone());
+                }
+            }
+        }
+        let elements = elements.into_iter()
+            .map(|el| {
+                let counts = ab.configs.iter()
+                    .filter_map(|c| c.ion_types.iter().position(|t| *t == el).map(|i| c.ions_per_type[i]))
+                    .collect::<Vec<_>>();
+                ElementCount {
+                    nconfigs: counts.len(),
+                    natoms: counts.iter().sum(),
+                    nlrc: nlrc.get(&el).copied(),
+                    element: el,
+                }
+            })
+            .collect();
+
+        Self {
+            nconfigs: ab.configs.len(),
+            systems,
+            elements,
+            last_error: log.and_then(|l| l.errors().last().copied()),
+        }
+    }
+}
+
+impl Tabular for MlffSummary {
+    fn headers(&self) -> Vec<String> {
+        ["element", "nconfigs", "natoms", "nlrc"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.elements.iter()
+            .map(|e| vec![
+                e.element.clone(),
+                e.nconfigs.to_string(),
+                e.natoms.to_string(),
+                e.nlrc.map(|n| n.to_string()).unwrap_or_default(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for MlffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# {} configurations in the training set", self.nconfigs).bright_green())?;
+        writeln!(f, "{}", format!("  {:>16} {:>9} {:>14} {:>14}", "System", "#Configs", "Emin(eV/atom)", "Emax(eV/atom)").bright_green())?;
+        for s in self.systems.iter() {
+            writeln!(f, "  {:>16} {} {:14.6} {:14.6}", s.system, format!("{:9}", s.nconfigs).bright_yellow(), s.emin, s.emax)?;
+        }
+        writeln!(f, "{}", format!("  {:>16} {:>9} {:>14} {:>14}", "Element", "#Configs", "#Atoms", "#LocalRefs").bright_green())?;
+        for e in self.elements.iter() {
+            let nlrc = e.nlrc.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+            writeln!(f, "  {:>16} {:9} {} {:>14}", e.element, e.nconfigs, format!("{:14}", e.natoms).bright_yellow(), nlrc)?;
+        }
+        if let Some((nstep, e, fo, s)) = self.last_error {
+            writeln!(f, "{}", format!("# RMSE at learning step {}: energy {:.6} eV/atom, force {:.6} eV/A, stress {:.4} kB",
+                                      nstep, e, fo, s).bright_green())?;
+        }
+        Ok(())
+    }
+}
+
+
+// Sections of ML_AB as (title, content lines)
+fn _sections(context: &str) -> Vec<(String, Vec<String>)> {
+    let is_sep = |l: &str, c: char| l.len() >= 10 && l.chars().all(|x| x == c);
+    let mut ret = Vec::<(String, Vec<String>)>::new();
+    let mut expect_title = false;
+    let mut in_body = false;
+    for line in context.lines() {
+        let l = line.trim();
+        if is_sep(l, '*') || is_sep(l, '=') {
+            expect_title = true;
+            in_body = false;
+        } else if is_sep(l, '-') {
+            in_body = true;
+        } else if expect_title {
+            ret.push((l.to_string(), vec![]));
+            expect_title = false;
+        } else if in_body {
+            if let Some(last) = ret.last_mut() {
+                last.1.push(l.to_string());
+            }
+        }
+    }
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ML_AB: &str = " 1.0 Version
+**************************************************
+     The number of configurations
+--------------------------------------------------
+          1
+**************************************************
+     The atom types in the data file
+--------------------------------------------------
+     Si   O
+**************************************************
+     Configuration num.      1
+==================================================
+     System name
+--------------------------------------------------
+     SiO2 quartz
+==================================================
+     The number of atom types
+--------------------------------------------------
+       2
+==================================================
+     The number of atoms
+--------------------------------------------------
+         3
+**************************************************
+     Atom types and atom numbers
+--------------------------------------------------
+     Si   1
+     O    2
+==================================================
+     CTIFOR
+--------------------------------------------------
+   2.0000000000000000E-002
+==================================================
+     Primitive lattice vectors (ang.)
+--------------------------------------------------
+   5.0  0.0  0.0
+   0.0  5.0  0.0
+   0.0  0.0  5.0
+==================================================
+     Atomic positions (ang.)
+--------------------------------------------------
+   0.0  0.0  0.0
+   1.6  0.0  0.0
+   0.0  1.6  0.0
+==================================================
+     Total energy (eV)
+--------------------------------------------------
+  -24.0
+==================================================
+     Forces (eV ang.^-1)
+--------------------------------------------------
+   0.1  0.0  0.0
+  -0.1  0.0  0.0
+   0.0  0.0  0.0
+==================================================
+     Stress (kbar)
+--------------------------------------------------
+     XX YY ZZ
+--------------------------------------------------
+   1.0  2.0  3.0
+--------------------------------------------------
+     XY YZ ZX
+--------------------------------------------------
+   4.0  5.0  6.0
+";
+
+    #[test]
+    fn test_parse_ml_ab() {
+        let ab = MlAb::parse(ML_AB).unwrap();
+        assert_eq!(ab.ion_types, vec!["Si", "O"]);
+        assert_eq!(ab.configs.len(), 1);
+        let c = &ab.configs[0];
+        assert_eq!(c.system, "SiO2 quartz");
+        assert_eq!(c.ions_per_type, vec![1, 2]);
+        assert_eq!(c.ctifor, Some(0.02));
+        assert_eq!(c.cell[2], [0.0, 0.0, 5.0]);
+        assert_eq!(c.positions[2], [0.0, 1.6, 0.0]);
+        assert_eq!(c.energy, -24.0);
+        assert_eq!(c.forces[1], [-0.1, 0.0, 0.0]);
+        assert_eq!(c.stress, Some([1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        assert!(MlAb::parse(" 1.0 Version\n").is_err());
+
+        let log = MlLog::parse("\
+# ERR ###############################################################
+# ERR nstep (  2) rmse_energy (  3) rmse_force (  4) rmse_stress (  5)
+# ERR ###############################################################
+ERR              1   1.00000000E-02   2.00000000E-01   3.00000000E+00
+ERR              5   5.00000000E-03   1.00000000E-01   2.00000000E+00
+# LCONF nstep (  2) el (  3) nlrc_old (  4) nlrc_new (  5)
+LCONF            5  Si   0   10
+LCONF            5  O    0   20
+");
+        assert_eq!(log.errors(), vec![(1, 0.01, 0.2, 3.0), (5, 0.005, 0.1, 2.0)]);
+        let summary = MlffSummary::new(&ab, Some(&log));
+        assert_eq!(summary.elements[1].element, "O");
+        assert_eq!(summary.elements[1].natoms, 2);
+        assert_eq!(summary.elements[1].nlrc, Some(20));
+        assert_eq!(summary.systems[0].emin, -8.0);
+        assert_eq!(summary.last_error, Some((5, 0.005, 0.1, 2.0)));
+    }
+}
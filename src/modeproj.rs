@@ -0,0 +1,286 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use log::info;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::outcar::Vibration;
+use crate::constants::{
+    SPEED_OF_LIGHT,
+    AMU_A2_PER_FS2_TO_EV,
+};
+use crate::format::{
+    Structure,
+    Trajectory,
+};
+
+
+// Angular frequency in rad/s of 1 cm-1
+const CM1_TO_RAD_PER_S: f64 = 2.0 * std::f64::consts::PI * SPEED_OF_LIGHT * 1E2;
+// amu * A^2 * s^-2 in eV
+const AMU_A2_PER_S2_TO_EV: f64 = AMU_A2_PER_FS2_TO_EV * 1E-30;
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+/// Displacements of each frame from the reference, with the nearest periodic images.
+pub fn displacements(reference: &Structure, frame: &Structure) -> Vec<[f64; 3]> {
+    frame.frac_pos.iter()
+        .zip(reference.frac_pos.iter())
+        .map(|(p, r)| {
+            let d = [0, 1, 2].map(|i| {
+                let x = p[i] - r[i];
+                x - x.round()
+            });
+            [0, 1, 2].map(|j| (0 .. 3).map(|i| d[i] * frame.cell[i][j]).sum::<f64>())
+        })
+        .collect()
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModeSummary {
+    pub index     : usize,  // starts from 1, in the order of OUTCAR
+    pub freq      : f64,    // in cm-1, negative for imaginary modes
+    pub rms       : f64,    // RMS amplitude, in A*sqrt(amu)
+    pub potential : f64,    // average harmonic potential energy, in eV
+    pub kinetic   : Option<f64>,  // average kinetic energy, in eV
+}
+
+
+/// Trajectory displacements projected onto the mass-weighted normal modes of a phonon
+/// calculation, Q_k(t) = sum_i sqrt(m_i) e_ik . u_i(t). The harmonic energy of each mode is
+/// omega_k^2 Q_k^2 / 2, plus dQ_k/dt^2 / 2 from central differences if the time step is given.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModeProjection {
+    pub modes      : Vec<ModeSummary>,
+    pub amplitudes : Vec<Vec<f64>>,  // [nsteps][nmodes], in A*sqrt(amu)
+    pub potential  : Vec<Vec<f64>>,  // [nsteps][nmodes], in eV
+    pub kinetic    : Option<Vec<Vec<f64>>>,  // [nsteps][nmodes], in eV
+}
+
+impl ModeProjection {
+    /// `imodes` start from 0, `potim` is the time step in fs.
+    pub fn new(reference: &Structure, masses: &[f64], vibs: &[Vibration], imodes: &[usize],
+               traj: &Trajectory, potim: Option<f64>) -> Self {
+        let nions = reference.frac_pos.len();
+        assert_eq!(masses.len(), nions, "Inconsistent numbers of masses and atoms");
+        assert!(traj.0.iter().all(|s| s.frac_pos.len() == nions), "Inconsistent numbers of atoms in the trajectory and phonon calculation");
+
+        // Mass-weighted eigenvectors, the displacements in OUTCAR are divided by sqrt(m)
+        let sqrt_m = masses.iter().map(|m| m.sqrt()).collect::<Vec<f64>>();
+        let eigvecs = imodes.iter()
+            .map(|&k| {
+                let mut e = vibs[k].dxdydz.iter()
+                    .zip(sqrt_m.iter())
+                    .map(|(d, m)| [d[0] * m, d[1] * m, d[2] * m])
+                    .collect::<Vec<[f64; 3]>>();
+                let norm = e.iter().flatten().map(|x| x * x).sum::<f64>().sqrt();
+                e.iter_mut().flatten().for_each(|x| *x /= norm);
+                e
+            })
+            .collect::<Vec<_>>();
+        let omega2 = imodes.iter()
+            .map(|&k| {
+                let w = vibs[k].freq * CM1_TO_RAD_PER_S;
+                if vibs[k].is_imagine { -w * w } else { w * w }
+            })
+            .collect::<Vec<f64>>();
+
+        let amplitudes = traj.0.iter()
+            .map(|frame| {
+                let u = displacements(reference, frame);
+                eigvecs.iter()
+                    .map(|e| {
+                        e.iter().zip(u.iter()).zip(sqrt_m.iter())
+                            .map(|((e, u), m)| m * (e[0] * u[0] + e[1] * u[1] + e[2] * u[2]))
+                            .sum::<f64>()
+                    })
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+
+        let potential = amplitudes.iter()
+            .map(|q| q.iter().zip(omega2.iter()).map(|(q, w2)| 0.5 * w2 * q * q * AMU_A2_PER_S2_TO_EV).collect())
+            .collect::<Vec<Vec<f64>>>();
+
+        let nsteps = amplitudes.len();
+        let kinetic = potim.filter(|_| nsteps > 1).map(|dt| {
+            (0 .. nsteps)
+                .map(|t| {
+                    let (prev, next) = (t.saturating_sub(1), (t + 1).min(nsteps - 1));
+                    let span = (next - prev) as f64 * dt;
+                    amplitudes[next].iter().zip(amplitudes[prev].iter())
+                        .map(|(a, b)| {
+                            let v = (a - b) / span;
+                            0.5 * v * v * AMU_A2_PER_FS2_TO_EV
+                        })
+                        .collect::<Vec<f64>>()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let average = |data: &[Vec<f64>], j: usize| data.iter().map(|x| x[j]).sum::<f64>() / nsteps as f64;
+        let modes = imodes.iter()
+            .enumerate()
+            .map(|(j, &k)| ModeSummary {
+                index: k + 1,
+                freq: if vibs[k].is_imagine { -vibs[k].freq } else { vibs[k].freq },
+                rms: (amplitudes.iter().map(|q| q[j] * q[j]).sum::<f64>() / nsteps as f64).sqrt(),
+                potential: average(&potential, j),
+                kinetic: kinetic.as_ref().map(|k| average(k, j)),
+            })
+            .collect();
+
+        Self {
+            modes,
+            amplitudes,
+            potential,
+            kinetic,
+        }
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let write_table = |name: &str, title: &str, data: &[Vec<f64>]| -> io::Result<()> {
+            let fname = _prepare_fname(path, name)?;
+            info!("Saving {} to {:?} ...", title, &fname);
+            let mut f = fs::File::create(&fname)?;
+            writeln!(f, "# {}", title)?;
+            write!(f, "# {:>6}", "step")?;
+            for m in self.modes.iter() {
+                write!(f, " {:>14}", format!("mode{}", m.index))?;
+            }
+            writeln!(f)?;
+            for (i, row) in data.iter().enumerate() {
+                write!(f, "  {:6}", i + 1)?;
+                for x in row.iter() {
+                    write!(f, " {:14.6e}", x)?;
+                }
+                writeln!(f)?;
+            }
+            Ok(())
+        };
+
+        write_table("mode_amplitudes.txt", "Mode amplitudes in A*sqrt(amu)", &self.amplitudes)?;
+        match self.kinetic.as_ref() {
+            Some(kinetic) => {
+                let total = self.potential.iter().zip(kinetic.iter())
+                    .map(|(p, k)| p.iter().zip(k.iter()).map(|(p, k)| p + k).collect())
+                    .collect::<Vec<Vec<f64>>>();
+                write_table("mode_energies.txt", "Harmonic potential plus kinetic energies of modes in eV", &total)
+            },
+            None => write_table("mode_energies.txt", "Harmonic potential energies of modes in eV", &self.potential),
+        }
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "mode_amplitudes.html")?;
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Mode amplitudes",
+                "xaxis": {"title": "Step"},
+                "yaxis": {"title": "Q (A*sqrt(amu))"},
+            }));
+        let steps = (1 ..= self.amplitudes.len()).collect::<Vec<usize>>();
+        for (j, m) in self.modes.iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("#{} {:.1} cm-1", m.index, m.freq),
+                "x": steps,
+                "y": self.amplitudes.iter().map(|q| q[j]).collect::<Vec<f64>>(),
+            }));
+        }
+        plot.save_html(&fname)
+    }
+}
+
+impl Tabular for ModeProjection {
+    fn headers(&self) -> Vec<String> {
+        ["index", "freq", "rms", "potential", "kinetic"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.modes.iter()
+            .map(|m| vec![
+                m.index.to_string(),
+                format!("{:.4}", m.freq),
+                format!("{:.6}", m.rms),
+                format!("{:.6}", m.potential),
+                m.kinetic.map(|k| format!("{:.6}", k)).unwrap_or_default(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for ModeProjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "  Mode  Freq(cm-1)  RMS(A*sqrt(amu))  <Epot>(eV)  <Ekin>(eV)".bright_green())?;
+        for m in self.modes.iter() {
+            let kinetic = m.kinetic.map(|k| format!("{:11.6}", k)).unwrap_or_else(|| format!("{:>11}", "-"));
+            writeln!(f, "  {:4} {:11.4} {:17.6} {} {}",
+                     m.index, m.freq, m.rms, format!("{:11.6}", m.potential).bright_yellow(), kinetic)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_projection() {
+        // Diatomic with the stretching mode along x
+        let cell = [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]];
+        let structure = |x: f64| Structure {
+            cell,
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![2],
+            car_pos: vec![[-x, 0.0, 0.0], [x, 0.0, 0.0]],
+            frac_pos: vec![[-x / 10.0, 0.0, 0.0], [x / 10.0, 0.0, 0.0]],
+        };
+        let masses = vec![4.0, 4.0];
+        let s = 0.5f64.sqrt() / 2.0;  // normalized eigenvector divided by sqrt(m)
+        let vibs = vec![
+            Vibration::new(1000.0, vec![[-s, 0.0, 0.0], [s, 0.0, 0.0]], false),
+            Vibration::new(10.0, vec![[0.0, s, 0.0], [0.0, s, 0.0]], true),
+        ];
+        let traj = Trajectory(vec![structure(1.0), structure(1.1), structure(0.9)]);
+
+        let proj = ModeProjection::new(&structure(1.0), &masses, &vibs, &[0, 1], &traj, Some(1.0));
+        // Q = sqrt(m) * (0.1 * sqrt(0.5) * 2)
+        let q = 2.0 * 0.1 * 0.5f64.sqrt() * 2.0;
+        assert!(proj.amplitudes[0][0].abs() < 1E-12);
+        assert!((proj.amplitudes[1][0] - q).abs() < 1E-12);
+        assert!((proj.amplitudes[2][0] + q).abs() < 1E-12);
+        assert!(proj.amplitudes[1][1].abs() < 1E-12);
+
+        let w = 1000.0 * CM1_TO_RAD_PER_S;
+        assert!((proj.potential[1][0] / (0.5 * w * w * q * q * AMU_A2_PER_S2_TO_EV) - 1.0).abs() < 1E-10);
+        assert_eq!(proj.modes[1].freq, -10.0);
+        assert!((proj.modes[0].rms - q * (2.0f64 / 3.0).sqrt()).abs() < 1E-12);
+
+        // Central difference in the middle, dQ/dt = (-q - 0) / 2fs
+        let kinetic = proj.kinetic.as_ref().unwrap();
+        assert!((kinetic[1][0] / (0.125 * q * q * AMU_A2_PER_FS2_TO_EV) - 1.0).abs() < 1E-10);
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::str::FromStr;
+use std::collections::BTreeMap;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::format::Structure;
+
+
+// Covalent radii in Angstrom from B. Cordero et al., Dalton Trans. 2832 (2008), the low spin
+// values for Mn, Fe and Co and sp3 for C
+const COVALENT_RADII: &[(&str, f64)] = &[
+    ("H",  0.31), ("He", 0.28), ("Li", 1.28), ("Be", 0.96), ("B",  0.84), ("C",  0.76),
+    ("N",  0.71), ("O",  0.66), ("F",  0.57), ("Ne", 0.58), ("Na", 1.66), ("Mg", 1.41),
+    ("Al", 1.21), ("Si", 1.11), ("P",  1.07), ("S",  1.05), ("Cl", 1.02), ("Ar", 1.06),
+    ("K",  2.03), ("Ca", 1.76), ("Sc", 1.70), ("Ti", 1.60), ("V",  1.53), ("Cr", 1.39),
+    ("Mn", 1.39), ("Fe", 1.32), ("Co", 1.26), ("Ni", 1.24), ("Cu", 1.32), ("Zn", 1.22),
+    ("Ga", 1.22), ("Ge", 1.20), ("As", 1.19), ("Se", 1.20), ("Br", 1.20), ("Kr", 1.16),
+    ("Rb", 2.20), ("Sr", 1.95), ("Y",  1.90), ("Zr", 1.75), ("Nb", 1.64), ("Mo", 1.54),
+    ("Tc", 1.47), ("Ru", 1.46), ("Rh", 1.42), ("Pd", 1.39), ("Ag", 1.45), ("Cd", 1.44),
+    ("In", 1.42), ("Sn", 1.39), ("Sb", 1.39), ("Te", 1.38), ("I",  1.39), ("Xe", 1.40),
+    ("Cs", 2.44), ("Ba", 2.15), ("La", 2.07), ("Ce", 2.04), ("Pr", 2.03), ("Nd", 2.01),
+    ("Pm", 1.99), ("Sm", 1.98), ("Eu", 1.98), ("Gd", 1.96), ("Tb", 1.94), ("Dy", 1.92),
+    ("Ho", 1.92), ("Er", 1.89), ("Tm", 1.90), ("Yb", 1.87), ("Lu", 1.87), ("Hf", 1.75),
+    ("Ta", 1.70), ("W",  1.62), ("Re", 1.51), ("Os", 1.44), ("Ir", 1.41), ("Pt", 1.36),
+    ("Au", 1.36), ("Hg", 1.32), ("Tl", 1.45), ("Pb", 1.46), ("Bi", 1.48), ("Po", 1.40),
+    ("At", 1.50), ("Rn", 1.50), ("Fr", 2.60), ("Ra", 2.21), ("Ac", 2.15), ("Th", 2.06),
+    ("Pa", 2.00), ("U",  1.96), ("Np", 1.90), ("Pu", 1.87), ("Am", 1.80), ("Cm", 1.69),
+];
+
+
+/// Covalent radius of the element, suffixes of POTCAR like "Fe_pv" are ignored.
+pub fn covalent_radius(symbol: &str) -> Option<f64> {
+    let symbol = symbol.split(['_', '/']).next().unwrap_or(symbol);
+    COVALENT_RADII.iter()
+        .find(|(s, _)| *s == symbol)
+        .map(|(_, r)| *r)
+}
+
+
+// Bond cutoff of an element pair given by the user, e.g. "Ti-O=2.5"
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairCutoff {
+    pub a      : String,
+    pub b      : String,
+    pub cutoff : f64,
+}
+
+impl FromStr for PairCutoff {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid pair cutoff '{}', should be like \"Ti-O=2.5\"", s);
+        let (pair, cutoff) = s.split_once('=').ok_or_else(err)?;
+        let (a, b) = pair.split_once('-').ok_or_else(err)?;
+        let cutoff = cutoff.parse::<f64>().map_err(|_| err())?;
+        if a.is_empty() || b.is_empty() || cutoff < 0.0 {
+            return Err(err());
+        }
+        Ok(Self { a: a.to_string(), b: b.to_string(), cutoff })
+    }
+}
+
+
+/// Bond cutoffs of element pairs, the sum of covalent radii times `scale` if not given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cutoffs {
+    pub scale : f64,
+    pub pairs : Vec<PairCutoff>,
+}
+
+impl Cutoffs {
+    pub fn cutoff(&self, a: &str, b: &str) -> f64 {
+        if let Some(p) = self.pairs.iter().find(|p| (p.a == a && p.b == b) || (p.a == b && p.b == a)) {
+            return p.cutoff;
+        }
+        let radius = |s: &str| covalent_radius(s)
+            .unwrap_or_else(|| panic!("Covalent radius of '{}' not found, give the cutoffs of its pairs explicitly", s));
+        (radius(a) + radius(b)) * self.scale
+    }
+
+    /// Cutoffs between each two species of the structure, [ntypes][ntypes].
+    pub fn matrix(&self, ion_types: &[String]) -> Vec<Vec<f64>> {
+        ion_types.iter()
+            .map(|a| ion_types.iter().map(|b| self.cutoff(a, b)).collect())
+            .collect()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Neighbor {
+    pub index    : usize,     // starts from 0
+    pub image    : [i32; 3],  // lattice translation of the neighbor
+    pub distance : f64,
+}
+
+
+/// Neighbors within `rmax` of each atom including periodic images, found with cell lists. The
+/// cell is divided into bins no thinner than `rmax`, so only the atoms in adjacent bins are
+/// checked, and small cells are handled by looping over more images.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NeighborList {
+    pub rmax      : f64,
+    pub neighbors : Vec<Vec<Neighbor>>,  // sorted by distance
+}
+
+impl NeighborList {
+    pub fn new(structure: &Structure, rmax: f64) -> Self {
+        assert!(rmax > 0.0, "Cutoff radius should be positive");
+        let cell = &structure.cell;
+        let frac = structure.frac_pos.iter()
+            .map(|p| [p[0].rem_euclid(1.0), p[1].rem_euclid(1.0), p[2].rem_euclid(1.0)])
+            .collect::<Vec<[f64; 3]>>();
+
+        // Heights of the cell perpendicular to each pair of lattice vectors
+        let cross = |a: &[f64; 3], b: &[f64; 3]| [a[1]*b[2] - a[2]*b[1], a[2]*b[0] - a[0]*b[2], a[0]*b[1] - a[1]*b[0]];
+        let norm = |v: [f64; 3]| (v[0]*v[0] + v[1]*v[1] + v[2]*v[2]).sqrt();
+        let volume = cross(&cell[0], &cell[1]).iter().zip(cell[2].iter()).map(|(x, y)| x * y).sum::<f64>().abs();
+        let heights = [
+            volume / norm(cross(&cell[1], &cell[2])),
+            volume / norm(cross(&cell[2], &cell[0])),
+            volume / norm(cross(&cell[0], &cell[1])),
+        ];
+        let nbins = heights.map(|h| ((h / rmax).floor() as i32).max(1));
+        let reach = [0, 1, 2].map(|i| (rmax * nbins[i] as f64 / heights[i]).ceil() as i32);
+
+        let bin_of = |p: &[f64; 3]| [0, 1, 2].map(|i| ((p[i] * nbins[i] as f64) as i32).min(nbins[i] - 1));
+        let mut bins: BTreeMap<[i32; 3], Vec<usize>> = BTreeMap::new();
+        for (i, p) in frac.iter().enumerate() {
+            bins.entry(bin_of(p)).or_default().push(i);
+        }
+
+        let neighbors = frac.iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let b = bin_of(p);
+                let mut ret = vec![];
+                for da in -reach[0] ..= reach[0] {
+                    for db in -reach[1] ..= reach[1] {
+                        for dc in -reach[2] ..= reach[2] {
+                            let shifted = [b[0] + da, b[1] + db, b[2] + dc];
+                            let bin = [0, 1, 2].map(|k| shifted[k].rem_euclid(nbins[k]));
+                            let image = [0, 1, 2].map(|k| shifted[k].div_euclid(nbins[k]));
+                            let members = match bins.get(&bin) {
+                                Some(members) => members,
+                                None => continue,
+                            };
+                            for &j in members {
+                                if i == j && image == [0; 3] { continue; }
+                                let d = [0, 1, 2].map(|k| frac[j][k] + image[k] as f64 - p[k]);
+                                let distance = norm([0, 1, 2].map(|k| (0 .. 3).map(|l| d[l] * cell[l][k]).sum::<f64>()));
+                                if distance < rmax {
+                                    // images relative to the positions given in the structure
+                                    let image = [0, 1, 2].map(|k| image[k]
+                                        + (structure.frac_pos[i][k] - p[k]).round() as i32
+                                        - (structure.frac_pos[j][k] - frac[j][k]).round() as i32);
+                                    ret.push(Neighbor { index: j, image, distance });
+                                }
+                            }
+                        }
+                    }
+                }
+                ret.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+                ret
+            })
+            .collect();
+
+        Self { rmax, neighbors }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Coordination {
+    pub index     : usize,                // starts from 1
+    pub symbol    : String,
+    pub bonds     : Vec<Neighbor>,        // neighbors within the pair cutoffs
+    pub species   : Vec<(String, usize)>, // number of bonded neighbors of each species
+}
+
+impl Coordination {
+    pub fn cn(&self) -> usize {
+        self.bonds.len()
+    }
+
+    pub fn shortest(&self) -> Option<f64> {
+        self.bonds.first().map(|b| b.distance)
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        if self.bonds.is_empty() { return None; }
+        Some(self.bonds.iter().map(|b| b.distance).sum::<f64>() / self.bonds.len() as f64)
+    }
+
+    fn _species_string(&self) -> String {
+        self.species.iter()
+            .map(|(s, n)| format!("{}{}", s, n))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+
+// Bond lengths of one species pair over the selected atoms
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BondStatistics {
+    pub pair   : String,
+    pub cutoff : f64,
+    pub count  : usize,
+    pub min    : f64,
+    pub mean   : f64,
+    pub max    : f64,
+}
+
+
+/// Coordination numbers and bond lengths of the selected atoms.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CoordinationAnalysis {
+    pub atoms : Vec<Coordination>,
+    pub pairs : Vec<BondStatistics>,
+}
+
+impl CoordinationAnalysis {
+    /// `iatoms` start from 0, all the atoms are counted as neighbors.
+    pub fn new(structure: &Structure, iatoms: &[usize], cutoffs: &Cutoffs) -> Self {
+        let symbols = structure.symbols();
+        let itype = structure.ions_per_type.iter()
+            .enumerate()
+            .flat_map(|(t, n)| vec![t; *n as usize])
+            .collect::<Vec<usize>>();
+        let matrix = cutoffs.matrix(&structure.ion_types);
+        let rmax = matrix.iter().flatten().cloned().fold(0.0, f64::max);
+        let list = if rmax > 0.0 {
+            NeighborList::new(structure, rmax)
+        } else {
+            NeighborList { rmax, neighbors: vec![vec![]; symbols.len()] }
+        };
+
+        let atoms = iatoms.iter()
+            .map(|&i| {
+                let bonds = list.neighbors[i].iter()
+                    .filter(|n| n.distance < matrix[itype[i]][itype[n.index]])
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let species = structure.ion_types.iter()
+                    .enumerate()
+                    .map(|(t, s)| (s.clone(), bonds.iter().filter(|n| itype[n.index] == t).count()))
+                    .filter(|(_, n)| *n > 0)
+                    .collect();
+                Coordination {
+                    index: i + 1,
+                    symbol: symbols[i].clone(),
+                    bonds,
+                    species,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut lengths: BTreeMap<(usize, usize), Vec<f64>> = BTreeMap::new();
+        for c in atoms.iter() {
+            for n in c.bonds.iter() {
+                let (a, b) = (itype[c.index - 1], itype[n.index]);
+                lengths.entry((a.min(b), a.max(b))).or_default().push(n.distance);
+            }
+        }
+        let pairs = lengths.into_iter()
+            .map(|((a, b), v)| BondStatistics {
+                pair: format!("{}-{}", structure.ion_types[a], structure.ion_types[b]),
+                cutoff: matrix[a][b],
+                count: v.len(),
+                min: v.iter().cloned().fold(f64::INFINITY, f64::min),
+                mean: v.iter().sum::<f64>() / v.len() as f64,
+                max: v.iter().cloned().fold(0.0, f64::max),
+            })
+            .collect();
+
+        Self { atoms, pairs }
+    }
+}
+
+
+// Only the per-atom coordinations are written as CSV, the pair statistics are in the text output
+impl Tabular for CoordinationAnalysis {
+    fn headers(&self) -> Vec<String> {
+        ["index", "symbol", "cn", "neighbors", "shortest", "average"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.atoms.iter()
+            .map(|c| vec![
+                c.index.to_string(),
+                c.symbol.clone(),
+                c.cn().to_string(),
+                c._species_string(),
+                c.shortest().map(|x| format!("{:.4}", x)).unwrap_or_default(),
+                c.average().map(|x| format!("{:.4}", x)).unwrap_or_default(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for CoordinationAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "  #Ion Elem   CN  Shortest/A  Average/A  Neighbors".bright_green())?;
+        for c in self.atoms.iter() {
+            let fmt_len = |x: Option<f64>| x.map(|x| format!("{:11.4}", x)).unwrap_or_else(|| format!("{:>11}", "-"));
+            writeln!(f, "  {:4} {:>4} {} {} {}  {}",
+                     c.index, c.symbol, format!("{:4}", c.cn()).bright_yellow(),
+                     fmt_len(c.shortest()), fmt_len(c.average()), c._species_string())?;
+        }
+
+        if !self.pairs.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{}", "  Pair      Cutoff/A  Count      Min/A     Mean/A      Max/A".bright_green())?;
+            for p in self.pairs.iter() {
+                writeln!(f, "  {:8} {:9.3} {:6} {:10.4} {} {:10.4}",
+                         p.pair, p.cutoff, p.count, p.min, format!("{:10.4}", p.mean).bright_yellow(), p.max)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbor_list() {
+        // Rock salt NaCl in the conventional cell, 6 Cl at a/2 around each Na
+        let a = 5.64;
+        let frac_pos = vec![
+            [0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0],
+            [0.5, 0.5, 0.5], [0.5, 0.0, 0.0], [0.0, 0.5, 0.0], [0.0, 0.0, 0.5],
+        ];
+        let structure = Structure {
+            cell: [[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]],
+            ion_types: vec!["Na".to_string(), "Cl".to_string()],
+            ions_per_type: vec![4, 4],
+            car_pos: frac_pos.iter().map(|p| [p[0] * a, p[1] * a, p[2] * a]).collect(),
+            frac_pos,
+        };
+
+        // 6 Cl at a/2 and 12 Na at a/sqrt(2), cutoff larger than the cell to check the images
+        let list = NeighborList::new(&structure, 4.0);
+        assert_eq!(list.neighbors[0].len(), 18);
+        assert!((list.neighbors[0][0].distance - a / 2.0).abs() < 1E-10);
+        let list = NeighborList::new(&structure, 6.0);
+        assert_eq!(list.neighbors[0].len(), 6 + 12 + 8 + 6);
+
+        assert_eq!("Na-Cl=3.0".parse::<PairCutoff>().unwrap().cutoff, 3.0);
+        assert!("Na=3.0".parse::<PairCutoff>().is_err());
+        let cutoffs = Cutoffs { scale: 1.2, pairs: vec!["Cl-Na=3.0".parse().unwrap()] };
+        let analysis = CoordinationAnalysis::new(&structure, &[0, 4], &cutoffs);
+        assert_eq!(analysis.atoms[0].cn(), 6);
+        assert_eq!(analysis.atoms[1].species, vec![("Na".to_string(), 6)]);
+        assert_eq!(analysis.pairs.len(), 1);
+        assert_eq!(analysis.pairs[0].pair, "Na-Cl");
+        assert_eq!(analysis.pairs[0].count, 12);
+        assert_eq!(covalent_radius("Fe_pv"), Some(1.32));
+    }
+}
@@ -0,0 +1,224 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use colored::Colorize;
+use crate::outcar::IonicIteration;
+use crate::format::ScfHistory;
+use crate::traits::Tabular;
+
+
+// One electronic step, e.g.
+// "RMM:   6    -0.24001150E+02   -0.19213E-01   -0.54437E-02  3148   0.113E+00    0.352E+00"
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScfStep {
+    pub algo  : String,       // "DAV", "RMM", "CG", ...
+    pub n     : i32,
+    pub e     : f64,
+    pub de    : f64,
+    pub deps  : f64,
+    pub ncg   : i32,
+    pub rms   : f64,
+    pub rms_c : Option<f64>,  // charge density residual, missing in the first steps
+}
+
+
+// One ionic step, e.g.
+// "   1 F= -.24002006E+02 E0= -.24001849E+02  d E =-.240020E+02  mag=     1.9999"
+// "   1 T=   300. E= -.41296011E+03 F= -.41300623E+03 E0= -.41299957E+03  EK= 0.46124E-01 ..."
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OszicarStep {
+    pub nstep       : i32,
+    pub toten       : f64,           // F, free energy TOTEN
+    pub toten_z     : f64,           // E0, energy without entropy (sigma -> 0)
+    pub de          : f64,           // energy change from the last ionic step
+    pub temperature : Option<f64>,   // T of molecular dynamics
+    pub etotal      : Option<f64>,   // E of molecular dynamics, including the kinetic energy
+    pub magmom      : Option<Vec<f64>>,
+    pub scf         : Vec<ScfStep>,
+}
+
+
+/// Data of OSZICAR, which is written after each electronic step and much smaller than OUTCAR,
+/// thus it is still useful when OUTCAR is not available or truncated.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Oszicar {
+    pub ionic_steps : Vec<OszicarStep>,
+    pub pending     : Vec<ScfStep>,  // electronic steps of the running ionic step
+}
+
+impl Oszicar {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    /// Lines not recognized (headers, "bond charge predicted", etc.) are skipped.
+    pub fn parse(context: &str) -> Self {
+        let mut ret = Self::default();
+        for line in context.lines() {
+            if let Some(step) = _parse_scf_line(line) {
+                ret.pending.push(step);
+            } else if let Some(mut step) = _parse_ionic_line(line) {
+                step.scf = std::mem::take(&mut ret.pending);
+                ret.ionic_steps.push(step);
+            }
+        }
+        ret
+    }
+
+    /// Ionic steps whose energies differ from the ones in OUTCAR by more than `tol` in eV,
+    /// as (step starting from 1, TOTEN in OSZICAR, TOTEN in OUTCAR). The energies in OSZICAR
+    /// have 8 significant digits only, thus the tolerance is enlarged for large energies.
+    pub fn energy_mismatches(&self, iters: &[IonicIteration], tol: f64) -> Vec<(usize, f64, f64)> {
+        self.ionic_steps.iter()
+            .zip(iters.iter())
+            .enumerate()
+            .filter(|(_, (o, it))| {
+                let tol = tol + 1E-7 * it.toten.abs();
+                (o.toten - it.toten).abs() > tol || (o.toten_z - it.toten_z).abs() > tol
+            })
+            .map(|(i, (o, it))| (i + 1, o.toten, it.toten))
+            .collect()
+    }
+}
+
+impl From<&Oszicar> for ScfHistory {
+    fn from(oszicar: &Oszicar) -> Self {
+        Self(oszicar.ionic_steps.iter()
+             .map(|s| s.scf.iter().map(|e| e.de).collect())
+             .collect())
+    }
+}
+
+impl Tabular for Oszicar {
+    fn headers(&self) -> Vec<String> {
+        ["nstep", "toten", "toten_z", "de", "nscf", "temperature", "magmom"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.ionic_steps.iter()
+            .map(|s| vec![
+                s.nstep.to_string(),
+                s.toten.to_string(),
+                s.toten_z.to_string(),
+                s.de.to_string(),
+                s.scf.len().to_string(),
+                s.temperature.map(|t| t.to_string()).unwrap_or_default(),
+                s.magmom.as_ref().map(|m| m.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")).unwrap_or_default(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for Oszicar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>5} {:>16} {:>16} {:>11} {:>5} {:>8}  {}",
+                                  "#Step", "TOTEN/eV", "TOTEN_z/eV", "LgdE", "#SCF", "T/K", "Mag/muB").bright_green())?;
+        for s in self.ionic_steps.iter() {
+            let mag = s.magmom.as_ref()
+                .map(|m| m.iter().map(|x| format!("{:8.4}", x)).collect::<Vec<_>>().join(" "))
+                .unwrap_or_else(|| "-".to_string());
+            let temperature = s.temperature.map(|t| format!("{:8.1}", t)).unwrap_or_else(|| format!("{:>8}", "-"));
+            writeln!(f, "  {:5} {} {:16.8} {:11.3} {:5} {}  {}",
+                     s.nstep, format!("{:16.8}", s.toten).bright_yellow(), s.toten_z,
+                     s.de.abs().log10(), s.scf.len(), temperature, mag)?;
+        }
+        if !self.pending.is_empty() {
+            writeln!(f, "{}", format!("# {} electronic steps in the running ionic step, last dE = {:.3E} eV",
+                                      self.pending.len(), self.pending.last().unwrap().de).bright_green())?;
+        }
+        Ok(())
+    }
+}
+
+
+fn _parse_scf_line(line: &str) -> Option<ScfStep> {
+    let (algo, rest) = line.split_once(':')?;
+    let algo = algo.trim();
+    if algo.is_empty() || algo.len() > 3 || !algo.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let v = rest.split_whitespace().collect::<Vec<_>>();
+    if v.len() < 6 {
+        return None;
+    }
+    Some(ScfStep {
+        algo: algo.to_string(),
+        n: v[0].parse().ok()?,
+        e: v[1].parse().ok()?,
+        de: v[2].parse().ok()?,
+        deps: v[3].parse().ok()?,
+        ncg: v[4].parse().ok()?,
+        rms: v[5].parse().ok()?,
+        rms_c: v.get(6).and_then(|x| x.parse().ok()),
+    })
+}
+
+fn _parse_ionic_line(line: &str) -> Option<OszicarStep> {
+    let nstep = line.split_whitespace().next()?.parse().ok()?;
+    let magmom = line.split_once("mag=").map(|(_, m)| {
+        m.split_whitespace().filter_map(|x| x.parse().ok()).collect::<Vec<f64>>()
+    });
+    Some(OszicarStep {
+        nstep,
+        toten: _value_after(line, " F=")?,
+        toten_z: _value_after(line, " E0=")?,
+        de: _value_after(line, " d E =").unwrap_or(0.0),
+        temperature: _value_after(line, " T="),
+        etotal: _value_after(line, " E="),
+        magmom,
+        scf: vec![],
+    })
+}
+
+// The value may be glued to the key, e.g. "d E =-.240020E+02"
+fn _value_after(line: &str, key: &str) -> Option<f64> {
+    let (_, rest) = line.split_once(key)?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_oszicar() {
+        let txt = "\
+       N       E                     dE             d eps       ncg     rms          rms(c)
+DAV:   1     0.425437171237E+03    0.42544E+03   -0.21160E+04  2984   0.101E+03
+RMM:   2    -0.24001150E+02   -0.19213E-01   -0.54437E-02  3148   0.113E+00    0.352E+00
+   1 F= -.24002006E+02 E0= -.24001849E+02  d E =-.240020E+02  mag=     0.0000     0.0000     1.9999
+  bond charge predicted
+DAV:   1    -0.24101150E+02   -0.10000E+00   -0.54437E-02  3148   0.113E+00
+   2 T=   300. E= -.41296011E+03 F= -.41300623E+03 E0= -.41299957E+03  EK= 0.46124E-01 SP= 0.00E+00 SK= 0.00E+00
+RMM:   1    -0.24001150E+02   -0.19213E-01   -0.54437E-02  3148   0.113E+00    0.352E+00
+";
+        let o = Oszicar::parse(txt);
+        assert_eq!(o.ionic_steps.len(), 2);
+        assert_eq!(o.pending.len(), 1);
+
+        let s = &o.ionic_steps[0];
+        assert_eq!(s.nstep, 1);
+        assert_eq!(s.toten, -24.002006);
+        assert_eq!(s.toten_z, -24.001849);
+        assert_eq!(s.de, -24.0020);
+        assert_eq!(s.magmom, Some(vec![0.0, 0.0, 1.9999]));
+        assert_eq!(s.temperature, None);
+        assert_eq!(s.scf.len(), 2);
+        assert_eq!(s.scf[0].algo, "DAV");
+        assert_eq!(s.scf[0].rms_c, None);
+        assert_eq!(s.scf[1].ncg, 3148);
+        assert_eq!(s.scf[1].rms_c, Some(0.352));
+
+        let s = &o.ionic_steps[1];
+        assert_eq!(s.temperature, Some(300.0));
+        assert_eq!(s.etotal, Some(-412.96011));
+        assert_eq!(s.toten, -413.00623);
+        assert_eq!(s.magmom, None);
+        assert_eq!(ScfHistory::from(&o).0, vec![vec![425.44, -0.019213], vec![-0.1]]);
+    }
+}
@@ -2,11 +2,20 @@ pub type MatX3<T> = Vec<[T;3]>;  // Nx3 matrix
 pub type Mat33<T> = [[T;3];3];   // 3x3 matrix
 
 use std::io;
+use std::io::BufRead;
 use std::path::Path;
 use std::fs;
 use rayon;
+use rayon::prelude::*;
 use regex::Regex;
-use itertools::multizip;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use log::info;
+use crate::cache;
+use crate::vasprun;
+use crate::raster::_crc32;
 
 // DONE ISPIN
 // DONE ions per type
@@ -22,35 +31,73 @@ use itertools::multizip;
 // DONE LSORBIT
 // DONE IBRION
 // DONE ion masses
+// DONE magnetization per ion
 
 
-#[derive(Clone, PartialEq, Debug)]
+// Number of ionic steps parsed in parallel at once
+const NSTEPS_PER_BATCH: usize = 64;
+
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct IonicIteration {
-    pub nscf      : i32,
-    pub toten     : f64,
-    pub toten_z   : f64,
-    pub cputime   : f64,
-    pub stress    : f64,
-    pub magmom    : Option<Vec<f64>>,  // differs when ISPIN=1,2 and ncl versions
-    pub positions : MatX3<f64>,
-    pub forces    : MatX3<f64>,
-    pub cell      : Mat33<f64>,
+    pub nscf          : i32,
+    pub scf_de        : Vec<f64>,          // energy change of each SCF step in eV
+    pub toten         : f64,
+    pub toten_z       : f64,
+    pub cputime       : f64,
+    pub stress        : f64,               // external pressure in kB
+    pub stress_tensor : [f64; 6],          // XX YY ZZ XY YZ ZX in kB
+    pub magmom        : Option<Vec<f64>>,  // differs when ISPIN=1,2 and ncl versions
+    pub positions     : MatX3<f64>,
+    pub forces        : MatX3<f64>,
+    pub cell          : Mat33<f64>,
+    pub nelect        : f64,                  // integrated number of electrons of the last SCF step
+    pub energy_terms  : Option<EnergyTerms>,  // of the last SCF step
 }
 
 impl IonicIteration {
-    pub fn new(nscf: i32, toten: f64, toten_z: f64, cputime: f64,
-               stress: f64, magmom: Option<Vec<f64>>, positions: MatX3<f64>,
-               forces: MatX3<f64>, cell: Mat33<f64>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(nscf: i32, scf_de: Vec<f64>, toten: f64, toten_z: f64, cputime: f64,
+               stress: f64, stress_tensor: [f64; 6], magmom: Option<Vec<f64>>,
+               positions: MatX3<f64>, forces: MatX3<f64>, cell: Mat33<f64>,
+               nelect: f64, energy_terms: Option<EnergyTerms>) -> Self {
         Self {
-            nscf, toten, toten_z, cputime, stress,
-            magmom, positions, forces, cell
+            nscf, scf_de, toten, toten_z, cputime, stress, stress_tensor,
+            magmom, positions, forces, cell, nelect, energy_terms
         }
     }
     // The parsing process is done within `impl Outcar`
 }
 
 
-#[derive(Clone, PartialEq, Debug)]
+/// Contributions to the free energy printed at each SCF step, all in eV.
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EnergyTerms {
+    pub pscenc            : f64,              // alpha Z
+    pub tewen             : f64,              // Ewald energy
+    pub denc              : f64,              // -1/2 Hartree energy
+    pub exhf              : f64,              // -exchange
+    pub xcenc             : f64,              // -V(xc)+E(xc)
+    pub paw_dc            : [f64; 2],         // PAW double counting
+    pub eentro            : f64,              // entropy T*S
+    pub ebands            : f64,              // eigenvalues
+    pub eatom             : f64,              // atomic energy
+    pub ediel_sol         : f64,              // solvation, zero if not printed
+    pub dipole_correction : Option<f64>,      // dipol+quadrupol energy correction, LDIPOL=.TRUE. only
+    pub dipole_moment     : Option<[f64; 3]>, // in e*A, LDIPOL=.TRUE. only
+}
+
+impl EnergyTerms {
+    /// Sum of the terms, equals to TOTEN
+    pub fn total(&self) -> f64 {
+        self.pscenc + self.tewen + self.denc + self.exhf + self.xcenc + self.paw_dc[0] + self.paw_dc[1]
+            + self.eentro + self.ebands + self.eatom + self.ediel_sol
+    }
+    // The parsing process is done within `impl Outcar`
+}
+
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Vibration {
     pub freq       : f64,  // in THz
     pub dxdydz     : MatX3<f64>,
@@ -65,7 +112,47 @@ impl Vibration {
 }
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IonMagnetization {
+    pub orbitals : Vec<String>,         // 's', 'p', 'd' (and 'f'), 'tot' excluded
+    pub moments  : Vec<Vec<Vec<f64>>>,  // [ncomp][nions][norbits + 1], the last column is 'tot'
+}
+
+impl IonMagnetization {
+    /// Number of components: 1 for collinear and 3 (x, y, z) for non-collinear calculations
+    pub fn ncomp(&self) -> usize {
+        self.moments.len()
+    }
+
+    /// Total moment of each ion in muB, with `ncomp` components
+    pub fn totals(&self) -> Vec<Vec<f64>> {
+        let nions = self.moments[0].len();
+        (0 .. nions)
+            .map(|i| self.moments.iter().map(|m| *m[i].last().unwrap()).collect())
+            .collect()
+    }
+    // The parsing process is done within `impl Outcar`
+}
+
+
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Dielectric {
+    pub epsilon      : Option<Mat33<f64>>,       // electronic part, including local field effects in DFT
+    pub epsilon_ion  : Option<Mat33<f64>>,       // ionic contribution
+    pub born_charges : Option<Vec<Mat33<f64>>>,  // [nions], in |e|
+    pub piezo        : Option<[[f64; 6]; 3]>,    // electronic part in C/m^2, columns are XX YY ZZ XY YZ ZX
+    pub piezo_ion    : Option<[[f64; 6]; 3]>,    // ionic contribution in C/m^2
+}
+
+impl Dielectric {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+    // The parsing process is done within `impl Outcar`
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Outcar {
     pub lsorbit       : bool,
     pub ispin         : i32,
@@ -73,103 +160,338 @@ pub struct Outcar {
     pub nions         : i32,
     pub nkpts         : i32,
     pub nbands        : i32,
+    pub encut         : f64,  // in eV
     pub efermi        : f64,
     pub cell          : Mat33<f64>,
     pub ions_per_type : Vec<i32>,
     pub ion_types     : Vec<String>,
     pub ion_masses    : Vec<f64>,  // .len() == nions
+    pub zvals         : Vec<f64>,  // valence electrons of each type, .len() == ion_types.len()
+    pub nelect        : f64,       // NELECT, total number of electrons
     pub ion_iters     : Vec<IonicIteration>,
     pub vib           : Option<Vec<Vibration>>, // .len() == degrees of freedom
+    pub eigvals       : Vec<f64>,  // [nspin][nkpts][nbands] of the last complete block, empty if not found
+    pub occupations   : Vec<f64>,  // same layout as eigvals
+    pub ion_magmoms   : Option<IonMagnetization>,  // of the last ionic step, None if ISPIN=1
+    pub core_pots     : Vec<f64>,  // electrostatic potentials at ion cores of the last ionic step, empty if not found
+    pub elastic       : Option<[[f64; 6]; 6]>,  // TOTAL ELASTIC MODULI in kBar, in the order of XX YY ZZ XY YZ ZX
+    pub dielectric    : Option<Dielectric>,  // from LEPSILON or LCALCEPS calculations
 }
 
 
 impl Outcar {
+    /// Number of electrons of the neutral cell, from ZVAL of each type.
+    pub fn neutral_nelect(&self) -> f64 {
+        self.zvals.iter()
+            .zip(self.ions_per_type.iter())
+            .map(|(z, n)| z * *n as f64)
+            .sum()
+    }
+
+    /// Gzipped OUTCARs are recognized by the ".gz" extension, and vasprun.xml (or
+    /// vasprun.xml.gz) by the ".xml" one, see `crate::vasprun`. The parsed result is cached if
+    /// enabled, see `crate::cache`. Concatenated runs are merged, see `from_reader_run`.
     pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
-        let context: String = fs::read_to_string(path)?;
+        Self::from_file_run(path, None)
+    }
+
+    /// Same as `from_file`, with only the `run`-th run (starting from 1) of concatenated
+    /// OUTCARs parsed if given.
+    pub fn from_file_run(path: &(impl AsRef<Path> + ?Sized), run: Option<usize>) -> io::Result<Self> {
+        let kind = match run {
+            Some(n) => format!("outcar-run{}", n),
+            None => "outcar".to_string(),
+        };
+        cache::load_or_parse(path.as_ref(), &kind, |p| {
+            let is_xml = |p: &Path| p.extension().is_some_and(|e| e == "xml");
+            if p.extension().is_some_and(|e| e == "gz") {
+                let data = _decompress_gzip(&fs::read(p)?)?;
+                if is_xml(&p.with_extension("")) {
+                    return Self::_from_vasprun_run(&String::from_utf8_lossy(&data), run);
+                }
+                return Self::from_reader_run(io::Cursor::new(data), run);
+            }
+            if is_xml(p) {
+                return Self::_from_vasprun_run(&String::from_utf8_lossy(&fs::read(p)?), run);
+            }
+            let f = fs::File::open(p)?;
+            Self::from_reader_run(io::BufReader::new(f), run)
+        })
+    }
+
+    // vasprun.xml holds only one run, it's encoded in ISO-8859-1 but almost always in ASCII
+    fn _from_vasprun_run(context: &str, run: Option<usize>) -> io::Result<Self> {
+        if let Some(n) = run.filter(|n| *n != 1) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Run {} not found, only one run in vasprun.xml", n)));
+        }
+        vasprun::outcar_from_vasprun(context)
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        Self::from_reader_run(reader, None)
+    }
+
+    /// OUTCAR is read line by line and split into ionic steps at the "LOOP+" lines. The header
+    /// is parsed from the first step, then the steps are parsed in parallel batch by batch and
+    /// dropped once parsed, thus the memory usage doesn't grow with the length of OUTCAR.
+    ///
+    /// Continuation jobs may leave several runs in one file, each starting with the " vasp."
+    /// header line. The ionic steps of all the runs are merged in order if `run` is None, with
+    /// the unfinished step at the end of each run dropped and the header taken from the first
+    /// run; runs with different NIONS can't be merged. Otherwise only the `run`-th run,
+    /// starting from 1, is parsed.
+    pub fn from_reader_run(mut reader: impl BufRead, run: Option<usize>) -> io::Result<Self> {
+        let mut ret: Option<Self> = None;
+        let mut efermi: Option<f64> = None;
+        let mut ndof: Option<i32> = None;
+
+        let mut chunk = String::new();
+        let mut batch = Vec::<String>::with_capacity(NSTEPS_PER_BATCH);
+        let mut irun = 0usize;
+        let mut check_nions = false;
+        let selected = |irun: usize| run.is_none_or(|n| n == irun.max(1));  // no header line, one run
+
+        loop {
+            let start = chunk.len();
+            if reader.read_line(&mut chunk)? == 0 { break; }
+
+            if chunk[start ..].starts_with(" vasp.") {
+                if irun > 0 && selected(irun) {
+                    // The selected run ends, its remaining text is parsed as the tail below
+                    if run.is_some() { chunk.truncate(start); break; }
+                    if let Some(outcar) = ret.as_mut() {
+                        outcar.parse_batch(&batch, &mut efermi, &mut ndof);
+                        outcar.parse_tail(&chunk[.. start], &mut efermi, &mut ndof);
+                    }
+                    batch.clear();
+                }
+                irun += 1;
+                check_nions = ret.is_some();
+                chunk.replace_range(.. start, "");
+                continue;
+            }
+            if !chunk[start ..].contains("LOOP+:") { continue; }
+
+            // One ionic step is complete
+            if !selected(irun) {
+                chunk.clear();
+                continue;
+            }
+            match ret.as_ref() {
+                None => ret = Some(Self::parse_header(&chunk)),
+                Some(merged) if check_nions => {
+                    let (nions, merged) = (Self::parse_nions(&chunk), merged.nions);
+                    if nions != merged {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("Run {} has {} ions while the previous runs have {}, select one run by `--run` instead", irun, nions, merged)));
+                    }
+                    check_nions = false;
+                },
+                _ => {},
+            }
+            batch.push(std::mem::take(&mut chunk));
 
+            if batch.len() == NSTEPS_PER_BATCH {
+                let outcar = ret.as_mut().unwrap();
+                outcar.parse_batch(&batch, &mut efermi, &mut ndof);
+                batch.clear();
+            }
+        }
+
+        if let Some(n) = run {
+            if n == 0 || n > irun.max(1) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Run {} not found, {} runs in OUTCAR", n, irun.max(1))));
+            }
+        }
+        if irun > 1 && run.is_none() {
+            info!("{} concatenated runs found in OUTCAR, their ionic steps are merged.", irun);
+        }
+
+        // The remaining text is either an unfinished ionic step or the tail of OUTCAR
+        let mut outcar = ret.unwrap_or_else(|| Self::parse_header(&chunk));
+        outcar.parse_batch(&batch, &mut efermi, &mut ndof);
+        outcar.parse_tail(&chunk, &mut efermi, &mut ndof);
+
+        outcar.efermi = efermi.expect("Fermi level info not found");
+        Ok(outcar)
+    }
+
+    // Quantities which don't change during the calculation
+    fn parse_header(context: &str) -> Self {
         let mut lsorbit         = false;
         let mut ispin           = 0i32;
         let mut ibrion          = 0i32;
         let mut nions           = 0i32;
         let (mut nkpts, mut nbands) = (0i32, 0i32);
-        let mut efermi          = 0.0f64;
+        let mut encut           = 0.0f64;
         let mut cell            = [[0.0f64; 3]; 3];
-        let mut ext_pressure    = vec![0.0f64; 0];
         let mut ions_per_type   = vec![0i32; 0];
-        let mut ion_types       = vec![String::new();0];
+        let mut ion_types       = Vec::<String>::new();
         let mut ion_masses      = vec![0.0f64; 0];
-
-        let mut nscfv          = vec![0i32; 0];
-        let mut totenv         = vec![0.0f64; 0];
-        let mut toten_zv       = vec![0.0f64; 0];
-        let mut magmomv        = vec![Some(vec![0.0f64; 0]); 0];
-        let mut cputimev       = vec![0.0f64; 0];
-        let (mut posv, mut forcev) = (vec![vec![[0.0f64; 3];0]; 0], vec![vec![[0.0f64; 3];0]; 0]);
-        let mut cellv          = vec![[[0.0f64; 3]; 3]; 0];
+        let mut zvals           = vec![0.0f64; 0];
+        let mut nelect          = 0.0f64;
 
         rayon::scope(|s| {
-            s.spawn(|_| { lsorbit         = Self::parse_lsorbit(&context) });
-            s.spawn(|_| { ispin           = Self::parse_ispin(&context) });
-            s.spawn(|_| { ibrion          = Self::parse_ibrion(&context) });
-            s.spawn(|_| { nions           = Self::parse_nions(&context) });
+            s.spawn(|_| { lsorbit         = Self::parse_lsorbit(context) });
+            s.spawn(|_| { ispin           = Self::parse_ispin(context) });
+            s.spawn(|_| { ibrion          = Self::parse_ibrion(context) });
+            s.spawn(|_| { nions           = Self::parse_nions(context) });
             s.spawn(|_| {
-                let (_nkpts, _nbands) = Self::parse_nkpts_nbands(&context);
+                let (_nkpts, _nbands) = Self::parse_nkpts_nbands(context);
                 nkpts = _nkpts;
                 nbands = _nbands;
             });
-            s.spawn(|_| { efermi          = Self::parse_efermi(&context) });
-            s.spawn(|_| { cell            = Self::parse_cell(&context) });
-            s.spawn(|_| { ext_pressure    = Self::parse_stress(&context) });
-            s.spawn(|_| { ions_per_type   = Self::parse_ions_per_type(&context) });
-            s.spawn(|_| { ion_types       = Self::parse_ion_types(&context) });
-            s.spawn(|_| { ion_masses      = Self::parse_ion_masses(&context) });
-
-            s.spawn(|_| { nscfv          = Self::parse_nscfs(&context) });
-            s.spawn(|_| { totenv         = Self::parse_toten(&context) });
-            s.spawn(|_| { toten_zv       = Self::parse_toten_z(&context) });
-            s.spawn(|_| { magmomv        = Self::parse_magmoms(&context) });
-            s.spawn(|_| { cputimev       = Self::parse_cputime(&context) });
-            s.spawn(|_| {
-                let (_posv, _forcev) = Self::parse_posforce(&context);
-                posv = _posv;
-                forcev = _forcev;
-            });
-            s.spawn(|_| { cellv          = Self::parse_opt_cells(&context) });
+            s.spawn(|_| { encut           = Self::parse_encut(context) });
+            s.spawn(|_| { cell            = Self::parse_cell(context) });
+            s.spawn(|_| { ions_per_type   = Self::parse_ions_per_type(context) });
+            s.spawn(|_| { ion_types       = Self::parse_ion_types(context) });
+            s.spawn(|_| { ion_masses      = Self::parse_ion_masses(context) });
+            s.spawn(|_| { zvals           = Self::parse_zvals(context) });
+            s.spawn(|_| { nelect          = Self::parse_nelect(context) });
         });
 
-        // Do some check
-        let len = totenv.len();
-        assert_eq!(nscfv.len()    , len, "Init failed due to incomplete OUTCAR");
-        assert_eq!(toten_zv.len() , len, "Init failed due to incomplete OUTCAR");
-        assert_eq!(cputimev.len() , len, "Init failed due to incomplete OUTCAR");
-        assert_eq!(posv.len()     , len, "Init failed due to incomplete OUTCAR");
-        assert_eq!(forcev.len()   , len, "Init failed due to incomplete OUTCAR");
-        assert_eq!(cellv.len()    , len, "Init failed due to incomplete OUTCAR");
-
-        let ion_iters = multizip((nscfv, totenv, toten_zv, magmomv, cputimev, ext_pressure, posv, forcev, cellv))
-            .map(|(iscf, e, ez, mag, cpu, stress, pos, f, cell)| {
-                IonicIteration::new(iscf, e, ez, cpu, stress, mag, pos, f, cell)
-            })
-            .collect::<Vec<IonicIteration>>();
-
-        let vib = Self::parse_viberations(&context);
-
-        Ok(
-            Self {
-                lsorbit,
-                ispin,
-                ibrion,
-                nions,
-                nkpts,
-                nbands,
-                efermi,
-                cell,
-                ions_per_type,
-                ion_types,
-                ion_masses,
-                ion_iters,
-                vib
-            }
+        Self {
+            lsorbit,
+            ispin,
+            ibrion,
+            nions,
+            nkpts,
+            nbands,
+            encut,
+            efermi: 0.0,
+            cell,
+            ions_per_type,
+            ion_types,
+            ion_masses,
+            zvals,
+            nelect,
+            ion_iters: vec![],
+            vib: None,
+            eigvals: vec![],
+            occupations: vec![],
+            ion_magmoms: None,
+            core_pots: vec![],
+            elastic: None,
+            dielectric: None,
+        }
+    }
+
+    // Parses complete ionic steps, E-fermi and band energies are updated by the last step
+    // containing them.
+    fn parse_batch(&mut self, batch: &[String], efermi: &mut Option<f64>, ndof: &mut Option<i32>) {
+        let steps = batch.par_iter()
+            .map(|c| (Self::parse_ionic_step(c), Self::_parse_dof(c)))
+            .collect::<Vec<_>>();
+        for (it, dof) in steps.into_iter() {
+            self.ion_iters.push(it);
+            *ndof = ndof.or(dof);
+        }
+
+        if let Some(c) = batch.iter().rev().find(|c| c.contains(" E-fermi :")) {
+            *efermi = Some(Self::parse_efermi(c));
+        }
+        for c in batch.iter().rev() {
+            if self.update_eigenvalues(c) { break; }
+        }
+        if let Some(c) = batch.iter().rev().find(|c| c.contains("2PiTHz")) {
+            self.update_vibrations(c, *ndof);
+        }
+        for c in batch.iter().rev() {
+            if self.update_ion_magmoms(c) { break; }
+        }
+        for c in batch.iter().rev() {
+            if self.update_core_pots(c) { break; }
+        }
+        if let Some(c) = batch.iter().rev().find(|c| c.contains("TOTAL ELASTIC MODULI")) {
+            self.elastic = Self::parse_elastic_moduli(c);
+        }
+        batch.iter().for_each(|c| self.update_dielectric(c));
+    }
+
+    fn parse_tail(&mut self, context: &str, efermi: &mut Option<f64>, ndof: &mut Option<i32>) {
+        if context.contains(" E-fermi :") {
+            *efermi = Some(Self::parse_efermi(context));
+        }
+        self.update_eigenvalues(context);
+
+        *ndof = ndof.or_else(|| Self::_parse_dof(context));
+        if context.contains("2PiTHz") {
+            self.update_vibrations(context, *ndof);
+        }
+        self.update_ion_magmoms(context);
+        self.update_core_pots(context);
+        if context.contains("TOTAL ELASTIC MODULI") {
+            self.elastic = Self::parse_elastic_moduli(context);
+        }
+        self.update_dielectric(context);
+    }
+
+    // The electronic and ionic parts may be printed in different ionic steps, each of them is
+    // updated by the last one found.
+    fn update_dielectric(&mut self, context: &str) {
+        let found = Self::parse_dielectric(context, self.nions as usize);
+        if found.is_empty() { return; }
+
+        let d = self.dielectric.get_or_insert_with(Dielectric::default);
+        d.epsilon      = found.epsilon.or(d.epsilon);
+        d.epsilon_ion  = found.epsilon_ion.or(d.epsilon_ion);
+        d.born_charges = found.born_charges.or_else(|| d.born_charges.take());
+        d.piezo        = found.piezo.or(d.piezo);
+        d.piezo_ion    = found.piezo_ion.or(d.piezo_ion);
+    }
+
+    // Returns true if complete magnetization blocks are found in `context`
+    fn update_ion_magmoms(&mut self, context: &str) -> bool {
+        match Self::parse_ion_magnetization(context, self.nions as usize) {
+            Some(m) => { self.ion_magmoms = Some(m); true },
+            None => false,
+        }
+    }
+
+    // Returns true if a complete block of core potentials is found in `context`
+    fn update_core_pots(&mut self, context: &str) -> bool {
+        match Self::parse_core_potentials(context, self.nions as usize) {
+            Some(v) => { self.core_pots = v; true },
+            None => false,
+        }
+    }
+
+    fn update_vibrations(&mut self, context: &str, ndof: Option<i32>) {
+        if let Some(ndof) = ndof {
+            self.vib = Self::parse_viberations(context, &self.ion_masses, ndof as usize);
+        }
+    }
+
+    // Returns true if a complete block of band energies is found in `context`
+    fn update_eigenvalues(&mut self, context: &str) -> bool {
+        let (eigvals, occupations) = Self::parse_eigenvalues(context, self.ispin as usize,
+                                                             self.nkpts as usize, self.nbands as usize);
+        if eigvals.is_empty() { return false; }
+        self.eigvals = eigvals;
+        self.occupations = occupations;
+        true
+    }
+
+    // All the quantities of one ionic step, `context` ends with the "LOOP+" line
+    fn parse_ionic_step(context: &str) -> IonicIteration {
+        const ERRMSG: &str = "Init failed due to incomplete OUTCAR";
+        let (mut posv, mut forcev) = Self::parse_posforce(context);
+        IonicIteration::new(
+            Self::parse_nscfs(context).pop().expect(ERRMSG),
+            Self::parse_scf_de(context),
+            Self::parse_toten(context).pop().expect(ERRMSG),
+            Self::parse_toten_z(context).pop().expect(ERRMSG),
+            Self::parse_cputime(context).pop().expect(ERRMSG),
+            Self::parse_stress(context).pop().expect(ERRMSG),
+            Self::parse_stress_tensor(context).pop().expect(ERRMSG),
+            Self::parse_magmoms(context).pop().expect(ERRMSG),
+            posv.pop().expect(ERRMSG),
+            forcev.pop().expect(ERRMSG),
+            Self::parse_step_cell(context),
+            Self::parse_step_nelect(context).pop().expect(ERRMSG),
+            Self::parse_energy_terms(context),
         )
     }
 
@@ -211,6 +533,49 @@ impl Outcar {
             .collect()
     }
 
+    // The last "Free energy of the ion-electron system" block, and the dipole corrections
+    // printed before it
+    fn parse_energy_terms(context: &str) -> Option<EnergyTerms> {
+        let pos = context.rfind("Free energy of the ion-electron system")?;
+        let mut terms = EnergyTerms::default();
+        for line in context[pos ..].lines().skip(2) {
+            if line.contains("TOTEN") { break; }
+            let values = match line.split_once('=') {
+                Some((_, v)) => v.split_whitespace()
+                    .filter_map(|x| x.parse::<f64>().ok())
+                    .collect::<Vec<f64>>(),
+                None => continue,
+            };
+            let value = match values.first() {
+                Some(v) => *v,
+                None => continue,
+            };
+            if line.contains("PSCENC") { terms.pscenc = value; }
+            else if line.contains("TEWEN") { terms.tewen = value; }
+            else if line.contains("DENC") { terms.denc = value; }
+            else if line.contains("EXHF") { terms.exhf = value; }
+            else if line.contains("XCENC") { terms.xcenc = value; }
+            else if line.contains("PAW double counting") { terms.paw_dc = [value, values.get(1).copied().unwrap_or(0.0)]; }
+            else if line.contains("EENTRO") { terms.eentro = value; }
+            else if line.contains("EBANDS") { terms.ebands = value; }
+            else if line.contains("EATOM") { terms.eatom = value; }
+            else if line.contains("Ediel_sol") { terms.ediel_sol = value; }
+        }
+
+        let context = &context[.. pos];
+        terms.dipole_correction = Regex::new(r"dipol\+quadrupol energy correction\s+(\S+)")
+            .unwrap()
+            .captures_iter(context)
+            .last()
+            .and_then(|x| x[1].parse::<f64>().ok());
+        terms.dipole_moment = Regex::new(r"dipolmoment\s+(\S+)\s+(\S+)\s+(\S+)")
+            .unwrap()
+            .captures_iter(context)
+            .last()
+            .and_then(|x| Some([x[1].parse().ok()?, x[2].parse().ok()?, x[3].parse().ok()?]));
+        Some(terms)
+    }
+
     fn parse_toten_z(context: &str) -> Vec<f64> {
         Regex::new(r"energy  without entropy=\s+(?:\S+)  energy\(sigma->0\) =\s+(\S+)")
             .unwrap()
@@ -320,6 +685,42 @@ impl Outcar {
             .expect("Cannot parse E-fermi as float value")
     }
 
+    // Band energies and occupations following the last complete ' E-fermi :' line
+    fn parse_eigenvalues(context: &str, nspin: usize, nkpts: usize, nbands: usize) -> (Vec<f64>, Vec<f64>) {
+        let n = nspin * nkpts * nbands;
+
+        for (pos, _) in context.rmatch_indices(" E-fermi :") {
+            let mut eigvals = Vec::<f64>::with_capacity(n);
+            let mut occupations = Vec::<f64>::with_capacity(n);
+
+            for line in context[pos ..].lines().skip(1) {
+                if line.trim_start().starts_with("----") || eigvals.len() == n { break; }
+                let v = line.split_whitespace().collect::<Vec<&str>>();
+                if v.len() != 3 || v[0].parse::<usize>().is_err() { continue; }
+                if let (Ok(e), Ok(o)) = (v[1].parse::<f64>(), v[2].parse::<f64>()) {
+                    eigvals.push(e);
+                    occupations.push(o);
+                }
+            }
+
+            if eigvals.len() == n {
+                return (eigvals, occupations);
+            }
+        }
+        (vec![], vec![])
+    }
+
+    /// Band energy of `ispin`, `ikpoint` and `iband`, all the indices start from 0
+    pub fn eigval(&self, ispin: usize, ikpoint: usize, iband: usize) -> f64 {
+        let (nkpts, nbands) = (self.nkpts as usize, self.nbands as usize);
+        self.eigvals[(ispin * nkpts + ikpoint) * nbands + iband]
+    }
+
+    pub fn occupation(&self, ispin: usize, ikpoint: usize, iband: usize) -> f64 {
+        let (nkpts, nbands) = (self.nkpts as usize, self.nbands as usize);
+        self.occupations[(ispin * nkpts + ikpoint) * nbands + iband]
+    }
+
     fn parse_nkpts_nbands(context: &str) -> (i32, i32) {
         let v = Regex::new(r"NKPTS = \s*(\d+) .* NBANDS= \s*(\d+)")
             .unwrap()
@@ -357,18 +758,12 @@ impl Outcar {
         [v[0], v[1], v[2]]
     }
 
-    fn parse_opt_cells(context: &str) -> Vec<Mat33<f64>> {
-        let skip_cnt: usize = 1 +
-            context.find(" old parameters").is_some() as usize +
-            context.find("Primitive cell").is_some() as usize;
-
-        Regex::new(r"direct lattice vectors")
-            .unwrap()
-            .find_iter(context)
-            .map(|x| x.start())
-            .skip(skip_cnt)
-            .map(|x| Self::parse_cell(&context[x..]))
-            .collect()
+    // The lattice vectors printed in the header are skipped since they are followed by
+    // the ones of the ionic step
+    fn parse_step_cell(context: &str) -> Mat33<f64> {
+        let pos = context.rfind("direct lattice vectors")
+            .expect("Lattice vectors info not found in current OUTCAR");
+        Self::parse_cell(&context[pos..])
     }
 
     fn parse_ions_per_type(context: &str) -> Vec<i32> {
@@ -428,6 +823,20 @@ impl Outcar {
             .expect("Cannot parse number of SCF iterations in current OUTCAR")
     }
 
+    fn parse_scf_de(context: &str) -> Vec<f64> {
+        Regex::new(r"total energy-change \(2\. order\) :\s*([-+]?\d+\.\d+E[-+]\d+)")
+            .unwrap()
+            .captures_iter(context)
+            .map(|x| {
+                x.get(1)
+                 .unwrap()
+                 .as_str()
+                 .parse::<f64>()
+                 .expect("Cannot parse SCF energy change as float value")
+            })
+            .collect()
+    }
+
     fn parse_stress(context: &str) -> Vec<f64> {
         Regex::new(r"external pressure = \s*(\S+) kB")
             .unwrap()
@@ -442,6 +851,180 @@ impl Outcar {
             .collect()
     }
 
+    fn parse_stress_tensor(context: &str) -> Vec<[f64; 6]> {
+        // Components may be glued together for large stress, hence no mandatory spaces
+        Regex::new(r"in kB((?:\s*-?\d+\.\d+){6})")
+            .unwrap()
+            .captures_iter(context)
+            .map(|x| {
+                let v = Regex::new(r"-?\d+\.\d+")
+                    .unwrap()
+                    .find_iter(x.get(1).unwrap().as_str())
+                    .map(|m| m.as_str().parse::<f64>()
+                         .expect("Cannot parse stress tensor as float values"))
+                    .collect::<Vec<f64>>();
+                [v[0], v[1], v[2], v[3], v[4], v[5]]
+            })
+            .collect()
+    }
+
+    // The last "magnetization (x)" block, followed by the (y) and (z) ones for non-collinear
+    // calculations. Returns None if not found or incomplete.
+    fn parse_ion_magnetization(context: &str, nions: usize) -> Option<IonMagnetization> {
+        let context = &context[context.rfind(" magnetization (x)")? ..];
+        let mut orbitals = vec![];
+        let mut moments = vec![];
+
+        for axis in ["x", "y", "z"] {
+            let start = match context.find(&format!(" magnetization ({})", axis)) {
+                Some(p) => p,
+                None => break,
+            };
+            let mut lines = context[start ..].lines()
+                .skip_while(|l| !l.starts_with("# of ion"));
+
+            // "# of ion       s       p       d       tot"
+            orbitals = lines.next()?
+                .split_whitespace()
+                .skip(3)
+                .filter(|x| *x != "tot")
+                .map(|x| x.to_owned())
+                .collect::<Vec<_>>();
+
+            let block = lines.skip(1)
+                .take(nions)
+                .map(|l| {
+                    l.split_whitespace()
+                     .skip(1)
+                     .map(|x| x.parse::<f64>().ok())
+                     .collect::<Option<Vec<f64>>>()
+                     .filter(|v| v.len() == orbitals.len() + 1)
+                })
+                .collect::<Option<Vec<_>>>()?;
+            if block.len() != nions { return None; }
+            moments.push(block);
+        }
+
+        Some(IonMagnetization { orbitals, moments })
+    }
+
+    // The last "average (electrostatic) potential at core" block. Returns None if not found
+    // or incomplete.
+    fn parse_core_potentials(context: &str, nions: usize) -> Option<Vec<f64>> {
+        let context = &context[context.rfind("average (electrostatic) potential at core")? ..];
+
+        // "       1 -60.7404       2 -60.7404       3 -48.8189", the index and value may be
+        // glued together like "  12-100.1234"
+        let re = Regex::new(r"(\d+)\s*(-?\d+\.\d+)").unwrap();
+        let ret = context.lines()
+            .skip(3)
+            .take_while(|l| !l.trim().is_empty())
+            .flat_map(|l| re.captures_iter(l)
+                      .map(|c| c.get(2).unwrap()
+                           .as_str()
+                           .parse::<f64>()
+                           .expect("Cannot parse core potential as float value"))
+                      .collect::<Vec<f64>>())
+            .collect::<Vec<f64>>();
+
+        if ret.len() == nions { Some(ret) } else { None }
+    }
+
+    // The last "TOTAL ELASTIC MODULI (kBar)" block, including the contributions from ionic
+    // relaxation. Returns None if not found or incomplete.
+    fn parse_elastic_moduli(context: &str) -> Option<[[f64; 6]; 6]> {
+        let context = &context[context.rfind("TOTAL ELASTIC MODULI")? ..];
+
+        // " XX        2645.5461   1081.0808   1081.0808      0.0000      0.0000      0.0000"
+        let rows = context.lines()
+            .skip(3)
+            .take(6)
+            .map(|l| {
+                l.split_whitespace()
+                 .skip(1)
+                 .map(|x| x.parse::<f64>().ok())
+                 .collect::<Option<Vec<f64>>>()
+                 .filter(|v| v.len() == 6)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if rows.len() != 6 { return None; }
+
+        let mut ret = [[0.0f64; 6]; 6];
+        for (r, row) in ret.iter_mut().zip(rows.iter()) {
+            r.copy_from_slice(row);
+        }
+        Some(ret)
+    }
+
+    fn parse_dielectric(context: &str, nions: usize) -> Dielectric {
+        if !context.contains("DIELECTRIC TENSOR")
+            && !context.contains("BORN EFFECTIVE CHARGES")
+            && !context.contains("PIEZOELECTRIC TENSOR") {
+            return Dielectric::default();
+        }
+
+        let lines = context.lines().collect::<Vec<_>>();
+        let to_mat33 = |v: Vec<Vec<f64>>| -> Option<Mat33<f64>> {
+            if v.iter().any(|r| r.len() != 3) { return None; }
+            Some([[v[0][0], v[0][1], v[0][2]],
+                  [v[1][0], v[1][1], v[1][2]],
+                  [v[2][0], v[2][1], v[2][2]]])
+        };
+        let to_mat36 = |v: Vec<Vec<f64>>| -> Option<[[f64; 6]; 3]> {
+            if v.iter().any(|r| r.len() != 6) { return None; }
+            let mut ret = [[0.0f64; 6]; 3];
+            ret.iter_mut().zip(v.iter()).for_each(|(r, x)| r.copy_from_slice(x));
+            Some(ret)
+        };
+
+        let epsilon = Self::_parse_tensor_rows(&lines, 3, |l| {
+            l.contains("MACROSCOPIC STATIC DIELECTRIC TENSOR (including local field effects in DFT)")
+        }).and_then(to_mat33);
+        let epsilon_ion = Self::_parse_tensor_rows(&lines, 3, |l| {
+            l.contains("MACROSCOPIC STATIC DIELECTRIC TENSOR IONIC CONTRIBUTION")
+        }).and_then(to_mat33);
+        let piezo = Self::_parse_tensor_rows(&lines, 3, |l| {
+            l.contains("PIEZOELECTRIC TENSOR") && l.contains("(C/m^2)") && !l.contains("IONIC")
+        }).and_then(to_mat36);
+        let piezo_ion = Self::_parse_tensor_rows(&lines, 3, |l| {
+            l.contains("PIEZOELECTRIC TENSOR IONIC CONTR") && l.contains("(C/m^2)")
+        }).and_then(to_mat36);
+
+        // "ion    1" followed by 3 rows like "    1     2.54085     0.00000    -0.00000"
+        let born_charges = Self::_parse_tensor_rows(&lines, nions * 4, |l| {
+            l.contains("BORN EFFECTIVE CHARGES")
+        }).and_then(|rows| {
+            rows.chunks(4)
+                .map(|c| to_mat33(c[1 ..].iter().map(|r| r.get(1 ..).unwrap_or(&[]).to_vec()).collect()))
+                .collect::<Option<Vec<_>>>()
+        });
+
+        Dielectric {
+            epsilon,
+            epsilon_ion,
+            born_charges,
+            piezo,
+            piezo_ion,
+        }
+    }
+
+    // Numbers in the `nrows` lines after the dashed line following the last title line,
+    // non-numeric labels like "x" and "ion" are dropped. Returns None if not found or incomplete.
+    fn _parse_tensor_rows(lines: &[&str], nrows: usize, is_title: impl Fn(&str) -> bool) -> Option<Vec<Vec<f64>>> {
+        let ititle = lines.iter().rposition(|l| is_title(l))?;
+        let rows = lines[ititle + 1 ..].iter()
+            .skip_while(|l| !l.trim_start().starts_with("---"))
+            .skip(1)
+            .take(nrows)
+            .map(|l| {
+                l.split_whitespace()
+                 .filter_map(|x| x.parse::<f64>().ok())
+                 .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+        if rows.len() == nrows { Some(rows) } else { None }
+    }
+
     fn parse_ibrion(context: &str) -> i32 {
         Regex::new(r"IBRION = \s*(\S+) ")
             .unwrap()
@@ -454,6 +1037,18 @@ impl Outcar {
             .expect("Cannot parse IBRION value")
     }
 
+    fn parse_encut(context: &str) -> f64 {
+        Regex::new(r"ENCUT  =\s*(\S+) eV")
+            .unwrap()
+            .captures(context)
+            .expect("ENCUT line not found")
+            .get(1)
+            .unwrap()
+            .as_str()
+            .parse::<f64>()
+            .expect("Cannot parse ENCUT as float value")
+    }
+
     fn parse_lsorbit(context: &str) -> bool {
         match Regex::new(r"LSORBIT\s*=\s*([TF])")
             .unwrap()
@@ -482,21 +1077,44 @@ impl Outcar {
             .collect::<Vec<f64>>();
 
         ions_per_type.into_iter()
-            .zip(masses_per_type.into_iter())
+            .zip(masses_per_type)
             .fold(vec![], |mut acc, (n, m): (i32, f64)| {
                 acc.extend(vec![m; n as usize]);
                 acc
             })
     }
 
-    fn parse_viberations(context: &str) -> Option<Vec<Vibration>> {
-        let massess_sqrt = Self::parse_ion_masses(context)
+    fn parse_zvals(context: &str) -> Vec<f64> {
+        Regex::new(r"POMASS = \s*\S+; ZVAL\s*=\s*(\S+)")
+            .unwrap()
+            .captures_iter(context)
+            .map(|x| x[1].parse::<f64>().expect("Cannot parse ZVAL as float value"))
+            .collect()
+    }
+
+    fn parse_nelect(context: &str) -> f64 {
+        Regex::new(r"NELECT =\s*(\S+)")
+            .unwrap()
+            .captures(context)
+            .expect("Cannot find NELECT")[1]
+            .parse::<f64>()
+            .expect("Cannot parse NELECT as float value")
+    }
+
+    fn parse_step_nelect(context: &str) -> Vec<f64> {
+        Regex::new(r"number of electron\s+(\S+)")
+            .unwrap()
+            .captures_iter(context)
+            .map(|x| x[1].parse::<f64>().expect("Cannot parse number of electrons as float value"))
+            .collect()
+    }
+
+    fn parse_viberations(context: &str, ion_masses: &[f64], ndof: usize) -> Option<Vec<Vibration>> {
+        let massess_sqrt = ion_masses
             .iter()
             .map(|x| x.sqrt())
             .collect::<Vec<_>>();
 
-        let ndof = Self::_parse_dof(context)? as usize;
-
         let mut vibs = Regex::new(r"(?m) .* 2PiTHz.* cm-1")
             .unwrap()
             .find_iter(context)
@@ -582,7 +1200,48 @@ impl Outcar {
 }
 
 
+// Decompresses the first member of gzip data, see RFC 1952
+pub(crate) fn _decompress_gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if data.len() < 18 || data[0 .. 3] != [0x1f, 0x8b, 8] {
+        return Err(invalid("Invalid gzip header"));
+    }
+
+    // Optional fields after the 10-byte header: extra, file name, comment and header CRC
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        let xlen = *data.get(pos).ok_or_else(|| invalid("Truncated gzip header"))? as usize
+            + ((*data.get(pos + 1).ok_or_else(|| invalid("Truncated gzip header"))? as usize) << 8);
+        pos += 2 + xlen;
+    }
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            pos += data.get(pos ..).and_then(|d| d.iter().position(|b| *b == 0))
+                .ok_or_else(|| invalid("Truncated gzip header"))? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+
+    let body = data.get(pos .. data.len() - 8).ok_or_else(|| invalid("Truncated gzip data"))?;
+    let ret = miniz_oxide::inflate::decompress_to_vec(body)
+        .map_err(|e| invalid(&format!("Invalid gzip data: {:?}", e.status)))?;
+
+    // CRC32 and size modulo 2^32 of the uncompressed data in the trailer
+    let t = &data[data.len() - 8 ..];
+    let crc = u32::from_le_bytes([t[0], t[1], t[2], t[3]]);
+    let size = u32::from_le_bytes([t[4], t[5], t[6], t[7]]);
+    if _crc32(ret.iter()) != crc || ret.len() as u32 != size {
+        return Err(invalid("Corrupted or truncated gzip data, the CRC32 or size in the trailer mismatches"));
+    }
+    Ok(ret)
+}
+
+
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::useless_conversion)]
 mod tests{
     use super::*;
 
@@ -665,6 +1324,36 @@ mod tests{
         assert_eq!(Outcar::parse_toten_z(&input), output);
     }
 
+    #[test]
+    fn test_parse_energy_terms() {
+        let input = r#"
+ dipolmoment           0.000000      0.000000     -0.052310 electrons x Angstroem
+ dipol+quadrupol energy correction            0.001234 eV
+
+ Free energy of the ion-electron system (eV)
+  ---------------------------------------------------
+  alpha Z        PSCENC =       197.80471723
+  Ewald energy   TEWEN  =     -1611.40102037
+  -Hartree energ DENC   =     -2633.63931743
+  -exchange      EXHF   =         0.00000000
+  -V(xc)+E(xc)   XCENC  =       422.84404219
+  PAW double counting   =      3472.70548416    -3490.92647004
+  entropy T*S    EENTRO =        -0.00460937
+  eigenvalues    EBANDS =      -384.43928015
+  atomic energy  EATOM  =      4708.07116389
+  ---------------------------------------------------
+  free energy    TOTEN  =       681.01471011 eV
+"#;
+        let terms = Outcar::parse_energy_terms(input).unwrap();
+        assert_eq!(terms.tewen, -1611.40102037);
+        assert_eq!(terms.paw_dc, [3472.70548416, -3490.92647004]);
+        assert_eq!(terms.ediel_sol, 0.0);
+        assert_eq!(terms.dipole_correction, Some(0.001234));
+        assert_eq!(terms.dipole_moment, Some([0.0, 0.0, -0.052310]));
+        assert!((terms.total() - 681.01471011).abs() < 1E-6);
+        assert_eq!(Outcar::parse_energy_terms("  free energy    TOTEN  =  1.0 eV"), None);
+    }
+
     #[test]
     #[should_panic(expected = "Cannot parse TOTENZ as float value")]
     fn test_parse_toten_z_fail() {
@@ -815,6 +1504,52 @@ mod tests{
         assert_eq!(Outcar::parse_efermi(&input), output);
     }
 
+    #[test]
+    fn test_parse_eigenvalues() {
+        let input = r#"
+ E-fermi :  -2.2691     XC(G=0):  -3.2554     alpha+bet : -2.9112
+
+
+ spin component 1
+
+ k-point     1 :       0.0000    0.0000    0.0000
+  band No.  band energies     occupation
+      1     -16.8270      1.00000
+      2      -1.3295      0.00000
+
+ k-point     2 :       0.1111    0.0000    0.0000
+  band No.  band energies     occupation
+      1     -16.7270      1.00000
+      2      -1.2295      0.00000
+
+ spin component 2
+
+ k-point     1 :       0.0000    0.0000    0.0000
+  band No.  band energies     occupation
+      1     -16.5270      1.00000
+      2      -1.0295      0.00000
+
+ k-point     2 :       0.1111    0.0000    0.0000
+  band No.  band energies     occupation
+      1     -16.4270      1.00000
+      2      -0.9295      0.00000
+
+
+--------------------------------------------------------------------------------------------------------
+ E-fermi :  -2.2691     XC(G=0):  -3.2554     alpha+bet : -2.9112
+
+ spin component 1
+
+ k-point     1 :       0.0000    0.0000    0.0000
+  band No.  band energies     occupation
+      1     -16.8270      1.00000
+"#;
+        let (eigvals, occupations) = Outcar::parse_eigenvalues(input, 2, 2, 2);
+        assert_eq!(eigvals, vec![-16.8270, -1.3295, -16.7270, -1.2295, -16.5270, -1.0295, -16.4270, -0.9295]);
+        assert_eq!(occupations, vec![1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(Outcar::parse_eigenvalues(input, 2, 3, 2), (vec![], vec![]));
+    }
+
     #[test]
     fn test_parse_nkpts_nbands() {
         let input = r#"
@@ -841,7 +1576,7 @@ mod tests{
     }
 
     #[test]
-    fn test_parse_opt_cells() {
+    fn test_parse_step_cell() {
         let input = r#"
       direct lattice vectors                 reciprocal lattice vectors
      6.000000000  0.000000000  0.000000000     0.166666667  0.000000000  0.000000000
@@ -872,10 +1607,10 @@ mod tests{
      0.000000000  7.000000000  0.000000000     0.000000000  0.142857143  0.000000000
      0.000000000  0.000000000  8.000000000     0.000000000  0.000000000  0.125000000
 --"#;
-        let output = vec![ [[6.0, 0.0, 0.0],
-                            [0.0, 7.0, 0.0],
-                            [0.0, 0.0, 8.0]]; 2];
-        assert_eq!(Outcar::parse_opt_cells(&input), output);
+        let output = [[6.0, 0.0, 0.0],
+                      [0.0, 7.0, 0.0],
+                      [0.0, 0.0, 8.0]];
+        assert_eq!(Outcar::parse_step_cell(input), output);
     }
 
     #[test]
@@ -963,6 +1698,17 @@ mod tests{
         assert_eq!(Outcar::parse_nscfs(&input), output);
     }
 
+    #[test]
+    fn test_parse_scf_de() {
+        let input = r#"
+  total energy-change (2. order) : 0.4569933E+03  (-0.2182409E+04)
+  number of electron     176.0000000 magnetization
+--
+  total energy-change (2. order) :-0.4439876E+03  (-0.3940939E+03)
+  number of electron     176.0000000 magnetization"#;
+        assert_eq!(Outcar::parse_scf_de(input), vec![456.9933, -443.9876]);
+    }
+
     #[test]
     fn test_parse_stress() {
         let input = r#"
@@ -978,6 +1724,157 @@ mod tests{
         assert_eq!(Outcar::parse_stress(&input), output);
     }
 
+    #[test]
+    fn test_parse_elastic_moduli() {
+        let input = r#"
+ TOTAL ELASTIC MODULI (kBar)
+ Direction    XX          YY          ZZ          XY          YZ          ZX
+ --------------------------------------------------------------------------------
+ XX        2645.5461   1081.0808   1081.0808      0.0000      0.0000      0.0000
+ YY        1081.0808   2645.5461   1081.0808      0.0000      0.0000      0.0000
+ ZZ        1081.0808   1081.0808   2645.5461      0.0000      0.0000      0.0000
+ XY           0.0000      0.0000      0.0000    759.3497      0.0000      0.0000
+ YZ           0.0000      0.0000      0.0000      0.0000    759.3497      0.0000
+ ZX           0.0000      0.0000      0.0000      0.0000      0.0000    759.3497
+ --------------------------------------------------------------------------------
+"#;
+        let moduli = Outcar::parse_elastic_moduli(input).unwrap();
+        assert_eq!(moduli[0], [2645.5461, 1081.0808, 1081.0808, 0.0, 0.0, 0.0]);
+        assert_eq!(moduli[5][5], 759.3497);
+        assert_eq!(Outcar::parse_elastic_moduli(&input[.. 400]), None);
+        assert_eq!(Outcar::parse_elastic_moduli(""), None);
+    }
+
+    #[test]
+    fn test_parse_dielectric() {
+        let input = r#"
+ MACROSCOPIC STATIC DIELECTRIC TENSOR (including local field effects in DFT)
+ ------------------------------------------------------
+           7.079     0.000     0.000
+           0.000     7.079     0.000
+           0.000     0.000     8.125
+ ------------------------------------------------------
+
+ PIEZOELECTRIC TENSOR (including local field effects)  for field in x, y, z        (C/m^2)
+                XX          YY          ZZ          XY          YZ          ZX
+  ---------------------------------------------------------------------------------
+  x      0.00000     0.00000     0.00000     0.00000    -0.28120     0.00000
+  y      0.00000     0.00000     0.00000     0.00000     0.00000    -0.28120
+  z     -0.10000    -0.10000     0.53000     0.00000     0.00000     0.00000
+  ---------------------------------------------------------------------------------
+
+ BORN EFFECTIVE CHARGES (including local field effects) (in |e|, cummulative output)
+ ---------------------------------------------------------------------------------
+ ion    1
+    1     2.54085     0.00000    -0.00000
+    2     0.00000     2.54085     0.00000
+    3    -0.00000     0.00000     2.71000
+ ion    2
+    1    -2.54085     0.00000     0.00000
+    2     0.00000    -2.54085     0.00000
+    3     0.00000     0.00000    -2.71000
+"#;
+        let output = Outcar::parse_dielectric(input, 2);
+        assert_eq!(output.epsilon, Some([[7.079, 0.0, 0.0], [0.0, 7.079, 0.0], [0.0, 0.0, 8.125]]));
+        assert_eq!(output.epsilon_ion, None);
+        assert_eq!(output.piezo.unwrap()[2], [-0.1, -0.1, 0.53, 0.0, 0.0, 0.0]);
+        assert_eq!(output.piezo_ion, None);
+        let born = output.born_charges.unwrap();
+        assert_eq!(born.len(), 2);
+        assert_eq!(born[1][2], [0.0, 0.0, -2.71]);
+
+        assert_eq!(Outcar::parse_dielectric(input, 3).born_charges, None);
+        assert!(Outcar::parse_dielectric("", 2).is_empty());
+    }
+
+    #[test]
+    fn test_parse_core_potentials() {
+        let input = r#"
+ average (electrostatic) potential at core
+  the test charge radii are     1.0808  1.2064
+  (the norm of the test charge is              1.0000)
+       1 -60.7404       2 -60.7404       3 -48.8189       4-100.1234       5 -48.8189
+       6 -48.8189
+ 
+ 
+ E-fermi :  -2.2691     XC(G=0):  -3.2554     alpha+bet : -2.9112
+"#;
+        let output = vec![-60.7404, -60.7404, -48.8189, -100.1234, -48.8189, -48.8189];
+        assert_eq!(Outcar::parse_core_potentials(input, 6), Some(output));
+        assert_eq!(Outcar::parse_core_potentials(input, 7), None);
+        assert_eq!(Outcar::parse_core_potentials("", 6), None);
+    }
+
+    #[test]
+    fn test_parse_ion_magnetization() {
+        let input = r#"
+ magnetization (x)
+ 
+# of ion       s       p       d       tot
+------------------------------------------
+    1       -0.002  -0.039   0.003  -0.037
+    2        0.008   0.008   0.634   0.650
+--------------------------------------------------
+tot          0.006  -0.031   0.637   0.613
+ 
+"#;
+        let output = IonMagnetization {
+            orbitals: vec!["s".to_string(), "p".to_string(), "d".to_string()],
+            moments: vec![vec![vec![-0.002, -0.039, 0.003, -0.037],
+                               vec![ 0.008,  0.008, 0.634,  0.650]]],
+        };
+        assert_eq!(Outcar::parse_ion_magnetization(input, 2), Some(output));
+        assert_eq!(Outcar::parse_ion_magnetization(input, 3), None);
+        assert_eq!(Outcar::parse_ion_magnetization("", 2), None);
+
+        let input = r#"
+ magnetization (x)
+ 
+# of ion       s       p       d       tot
+------------------------------------------
+    1       -0.000  -0.000   0.000  -0.000
+--------------------------------------------------
+tot         -0.000  -0.000   0.000  -0.000
+ 
+
+
+ magnetization (y)
+ 
+# of ion       s       p       d       tot
+------------------------------------------
+    1        0.000   0.100  -0.000   0.100
+--------------------------------------------------
+tot          0.000   0.100  -0.000   0.100
+ 
+
+
+ magnetization (z)
+ 
+# of ion       s       p       d       tot
+------------------------------------------
+    1       -0.002  -0.038   0.632   0.592
+--------------------------------------------------
+tot         -0.002  -0.038   0.632   0.592
+ 
+"#;
+        let mag = Outcar::parse_ion_magnetization(input, 1).unwrap();
+        assert_eq!(mag.ncomp(), 3);
+        assert_eq!(mag.totals(), vec![vec![-0.0, 0.1, 0.592]]);
+    }
+
+    #[test]
+    fn test_parse_stress_tensor() {
+        let input = r#"
+  in kB      -6.78636    -7.69902    -4.03340     0.00000     0.00000     0.00000
+  external pressure =       -6.17 kB  Pullay stress =        0.00 kB
+--
+  in kB   -1234.56789-12345.67890    -4.01885    -1.10430     0.00000     0.00000
+  external pressure =       -7.03 kB  Pullay stress =        0.00 kB"#;
+        let output = vec![[-6.78636, -7.69902, -4.03340, 0.0, 0.0, 0.0],
+                          [-1234.56789, -12345.67890, -4.01885, -1.10430, 0.0, 0.0]];
+        assert_eq!(Outcar::parse_stress_tensor(input), output);
+    }
+
     #[test]
     fn test_parse_ibrion() {
         let input = r#"
@@ -991,6 +1888,16 @@ mod tests{
         assert_eq!(Outcar::parse_ibrion(&input), output);
     }
 
+    #[test]
+    fn test_parse_encut() {
+        let input = r#"
+ Electronic Relaxation 1
+   ENCUT  =  400.0 eV  29.40 Ry    5.42 a.u.  12.26 12.26 12.26*2*pi/ulx,y,z
+   ENINI  =  400.0     initial cutoff
+"#;
+        assert_eq!(Outcar::parse_encut(input), 400.0);
+    }
+
     #[test]
     fn test_parse_lsorbit() {
         let input = r#"
@@ -1116,6 +2023,19 @@ mod tests{
         assert_eq!(Outcar::parse_ion_masses(&input), output);
     }
 
+    #[test]
+    fn test_parse_zvals_nelect() {
+        let input = r#"
+   POMASS =   10.811; ZVAL   =    3.000    mass and valenz
+   POMASS =   14.001; ZVAL   =    5.000    mass and valenz
+   NELECT =     127.0000    total number of electrons
+ number of electron     127.0000001 magnetization
+ number of electron     126.9999213 magnetization "#;
+        assert_eq!(Outcar::parse_zvals(input), vec![3.0, 5.0]);
+        assert_eq!(Outcar::parse_nelect(input), 127.0);
+        assert_eq!(Outcar::parse_step_nelect(input), vec![127.0000001, 126.9999213]);
+    }
+
     #[test]
     fn test_parse_dof() {
         let input = r#"
@@ -1265,7 +2185,9 @@ mod tests{
                  .collect::<Vec<_>>()
         );
 
-        assert_eq!(Outcar::parse_viberations(&input), output);
+        let masses = Outcar::parse_ion_masses(input);
+        let ndof = Outcar::_parse_dof(input).unwrap() as usize;
+        assert_eq!(Outcar::parse_viberations(&input, &masses, ndof), output);
 
 
         let input = r#"
@@ -1290,6 +2212,28 @@ mod tests{
   LATTYP: Found a simple orthorhombic cell.
 "#;
         let output = None;
-        assert_eq!(Outcar::parse_viberations(&input), output);
+        let masses = Outcar::parse_ion_masses(input);
+        let ndof = Outcar::_parse_dof(input).unwrap() as usize;
+        assert_eq!(Outcar::parse_viberations(&input, &masses, ndof), output);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let text = b"  POSITION    TOTAL-FORCE (eV/Angst)\n";
+        let mut gz = vec![0x1f, 0x8b, 8, 0x08, 0, 0, 0, 0, 0, 3];
+        gz.extend(b"OUTCAR\0");
+        gz.extend(miniz_oxide::deflate::compress_to_vec(text, 6));
+        gz.extend(_crc32(text.iter()).to_le_bytes());
+        gz.extend((text.len() as u32).to_le_bytes());
+        assert_eq!(_decompress_gzip(&gz).unwrap(), text.to_vec());
+        assert!(_decompress_gzip(&text[..]).is_err());
+
+        let mut corrupted = gz.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(_decompress_gzip(&corrupted).is_err());
+        corrupted = gz.clone();
+        corrupted[gz.len() - 8] ^= 1;
+        assert!(_decompress_gzip(&corrupted).is_err());
+        assert!(_decompress_gzip(&gz[.. gz.len() - 3]).is_err());
     }
 }
@@ -0,0 +1,319 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use serde::Serialize;
+use rayon::prelude::*;
+use serde_json::json;
+use colored::Colorize;
+use crate::format::{
+    Structure,
+    Trajectory,
+};
+use crate::fscorr::_reciprocal;
+use crate::summary::_volume;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+/// Averaged pair correlation function g(r) written in PCDAT by MD runs. The distances are at
+/// the centers of the bins, in Angstrom.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pcdat {
+    pub r       : Vec<f64>,
+    pub gr      : Vec<Vec<f64>>,  // total g(r) first, followed by the partial ones if written
+    pub nblocks : usize,          // number of blocks written, only the last one is kept
+}
+
+impl Pcdat {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Self::parse(&context)
+    }
+
+    /// The header has 12 lines, where line 8 is the number of bins (NPACO), line 9 the length
+    /// scale and line 10 the bin width, both in meters. The g(r) of each bin follows, one line
+    /// per bin.
+    pub fn parse(context: &str) -> io::Result<Self> {
+        let err = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let lines = context.lines().collect::<Vec<_>>();
+        if lines.len() < 13 {
+            return Err(err("Incomplete header in PCDAT"));
+        }
+        let first = |i: usize| lines[i].split_whitespace().next().and_then(|x| x.parse::<f64>().ok());
+        let npaco = first(7).ok_or_else(|| err("Cannot read the number of bins in PCDAT"))? as usize;
+        let scale = first(8).ok_or_else(|| err("Cannot read the length scale in PCDAT"))?;
+        let width = first(9).ok_or_else(|| err("Cannot read the bin width in PCDAT"))?;
+        if npaco == 0 || scale <= 0.0 || width <= 0.0 {
+            return Err(err("Invalid header of PCDAT"));
+        }
+
+        let rows = lines[12 ..].iter()
+            .map(|l| l.split_whitespace().map(|x| x.parse::<f64>()).collect::<Result<Vec<_>, _>>())
+            .take_while(|r| r.as_ref().is_ok_and(|v| !v.is_empty()))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let nblocks = rows.len() / npaco;
+        if nblocks == 0 {
+            return Err(err("Incomplete g(r) data in PCDAT"));
+        }
+
+        let last = &rows[(nblocks - 1) * npaco .. nblocks * npaco];
+        let ncols = last.iter().map(|r| r.len()).min().unwrap_or(0);
+        let gr = (0 .. ncols)
+            .map(|c| last.iter().map(|r| r[c]).collect())
+            .collect();
+        let dr = width / scale;
+        let r = (0 .. npaco).map(|i| (i as f64 + 0.5) * dr).collect();
+
+        Ok(Self { r, gr, nblocks })
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum RdfNorm {
+    Raw,   // as is
+    Tail,  // averaged to 1 in the last 20% of the range
+    Peak,  // the highest peak scaled to 1
+}
+
+impl FromStr for RdfNorm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "raw"  => Ok(Self::Raw),
+            "tail" => Ok(Self::Tail),
+            "peak" => Ok(Self::Peak),
+            _ => Err(format!("Invalid normalization '{}', should be raw, tail or peak", s)),
+        }
+    }
+}
+
+impl RdfNorm {
+    pub fn apply(&self, gr: &[f64]) -> Vec<f64> {
+        let factor = match self {
+            RdfNorm::Raw => 1.0,
+            RdfNorm::Tail => {
+                let tail = &gr[gr.len() * 4 / 5 ..];
+                tail.len() as f64 / tail.iter().sum::<f64>()
+            },
+            RdfNorm::Peak => 1.0 / gr.iter().cloned().fold(f64::MIN, f64::max),
+        };
+        if factor.is_finite() {
+            gr.iter().map(|g| g * factor).collect()
+        } else {
+            gr.to_vec()
+        }
+    }
+}
+
+
+/// Total g(r) averaged over the frames of the trajectory on the uniform bins centered at `r`,
+/// which start from 0. The frames are processed in parallel.
+pub fn rdf_from_trajectory(traj: &[Structure], r: &[f64]) -> Vec<f64> {
+    let nbins = r.len();
+    if nbins < 2 || traj.is_empty() {
+        return vec![0.0; nbins];
+    }
+    let dr = r[1] - r[0];
+    let rmax = r[nbins - 1] + dr / 2.0;
+
+    let frame = |s: &Structure| {
+        let mut hist = vec![0.0; nbins];
+        let n = s.frac_pos.len();
+        let density = (n * n.saturating_sub(1)) as f64 / _volume(&s.cell).abs();
+
+        // Images within rmax along each direction, from the interplanar spacings, the
+        // fractional differences are wrapped into [-0.5, 0.5] first
+        let rec = _reciprocal(&s.cell);
+        let nimg = rec.iter()
+            .map(|b| (rmax * (b[0] * b[0] + b[1] * b[1] + b[2] * b[2]).sqrt() / (2.0 * std::f64::consts::PI) + 0.5).ceil() as i32)
+            .collect::<Vec<_>>();
+
+        for i in 0 .. n {
+            for j in 0 .. n {
+                let d = [0, 1, 2].map(|k| {
+                    let x = s.frac_pos[j][k] - s.frac_pos[i][k];
+                    x - x.round()
+                });
+                for ia in -nimg[0] ..= nimg[0] {
+                    for ib in -nimg[1] ..= nimg[1] {
+                        for ic in -nimg[2] ..= nimg[2] {
+                            if i == j && ia == 0 && ib == 0 && ic == 0 {
+                                continue;
+                            }
+                            let f = [d[0] + ia as f64, d[1] + ib as f64, d[2] + ic as f64];
+                            let v = [0, 1, 2].map(|k| f[0] * s.cell[0][k] + f[1] * s.cell[1][k] + f[2] * s.cell[2][k]);
+                            let dist = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+                            let ibin = (dist / dr) as usize;
+                            if ibin < nbins {
+                                hist[ibin] += 1.0;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (hist, density)
+    };
+    let (mut hist, density) = traj.par_iter()
+        .map(frame)
+        .reduce(|| (vec![0.0; nbins], 0.0), |(mut a, da), (b, db)| {
+            a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x += y);
+            (a, da + db)
+        });
+
+    for (h, x) in hist.iter_mut().zip(r.iter()) {
+        let shell = 4.0 * std::f64::consts::PI * x * x * dr;
+        *h /= shell * density;
+    }
+    hist
+}
+
+
+/// g(r) of PCDAT, and the one calculated from XDATCAR for comparison if available.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairCorrelation {
+    pub r       : Vec<f64>,
+    pub pcdat   : Vec<f64>,
+    pub xdatcar : Option<Vec<f64>>,
+}
+
+impl PairCorrelation {
+    pub fn new(pcdat: &Pcdat, traj: Option<&Trajectory>, norm: RdfNorm) -> Self {
+        let xdatcar = traj.map(|t| norm.apply(&rdf_from_trajectory(&t.0, &pcdat.r)));
+        Self {
+            r: pcdat.r.clone(),
+            pcdat: norm.apply(&pcdat.gr[0]),
+            xdatcar,
+        }
+    }
+
+    /// Root mean square and maximum of the differences between the two g(r)
+    pub fn difference(&self) -> Option<(f64, f64)> {
+        let other = self.xdatcar.as_ref()?;
+        let diffs = self.pcdat.iter().zip(other.iter()).map(|(a, b)| (a - b).abs()).collect::<Vec<_>>();
+        let rms = (diffs.iter().map(|d| d * d).sum::<f64>() / diffs.len() as f64).sqrt();
+        Some((rms, diffs.iter().cloned().fold(0.0, f64::max)))
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Pair correlation function",
+                "xaxis": {"title": "r (Å)"},
+                "yaxis": {"title": "g(r)"},
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": "PCDAT",
+            "x": self.r,
+            "y": self.pcdat,
+        }));
+        if let Some(g) = self.xdatcar.as_ref() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": "XDATCAR",
+                "x": self.r,
+                "y": g,
+                "line": {"dash": "dash"},
+            }));
+        }
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for PairCorrelation {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["r".to_string(), "pcdat".to_string()];
+        if self.xdatcar.is_some() {
+            ret.push("xdatcar".to_string());
+        }
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        (0 .. self.r.len())
+            .map(|i| {
+                let mut row = vec![self.r[i].to_string(), self.pcdat[i].to_string()];
+                if let Some(g) = self.xdatcar.as_ref() {
+                    row.push(g[i].to_string());
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for PairCorrelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((rms, max)) = self.difference() {
+            writeln!(f, "{}", format!("# Difference between PCDAT and XDATCAR: RMS {:.4}, max {:.4}", rms, max).bright_green())?;
+        }
+        let xdatcar = if self.xdatcar.is_some() { format!(" {:>10}", "XDATCAR") } else { String::new() };
+        writeln!(f, "{}", format!("  {:>8} {:>10}{}", "r(A)", "PCDAT", xdatcar).bright_green())?;
+        for (i, r) in self.r.iter().enumerate() {
+            let other = self.xdatcar.as_ref().map(|g| format!(" {:10.4}", g[i])).unwrap_or_default();
+            writeln!(f, "  {:8.4} {}{}", r, format!("{:10.4}", self.pcdat[i]).bright_yellow(), other)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pcdat() {
+        let mut txt = "       2       2       1       4
+  0.1000000E-28
+  0.4000000E-09  0.4000000E-09  0.4000000E-09
+  0.1000000E-14
+ CAR
+ test system
+ unknown
+       4
+  0.1000000E-09
+  0.5000000E-10
+  0.0000000E+00
+  0.0000000E+00
+".to_string();
+        for g in ["0.0", "0.5", "2.0", "1.0", "0.0", "1.0", "3.0", "1.0"] {
+            txt += &format!("  {}\n", g);
+        }
+        let p = Pcdat::parse(&txt).unwrap();
+        assert_eq!(p.nblocks, 2);
+        assert_eq!(p.r, vec![0.25, 0.75, 1.25, 1.75]);
+        assert_eq!(p.gr, vec![vec![0.0, 1.0, 3.0, 1.0]]);
+        assert_eq!(RdfNorm::Peak.apply(&p.gr[0]), vec![0.0, 1.0 / 3.0, 1.0, 1.0 / 3.0]);
+        assert!(Pcdat::parse("1\n2\n").is_err());
+    }
+
+    #[test]
+    fn test_rdf_from_trajectory() {
+        // Simple cubic lattice in a doubled cell, 6 neighbors at a and 12 at sqrt(2)a
+        let a = 2.05;
+        let s = Structure {
+            cell: [[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, 2.0 * a]],
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![2],
+            car_pos: vec![[0.0; 3], [0.0, 0.0, a]],
+            frac_pos: vec![[0.0; 3], [0.0, 0.0, 0.5]],
+        };
+        let dr = 0.1;
+        let r = (0 .. 40).map(|i| (i as f64 + 0.5) * dr).collect::<Vec<_>>();
+        let g = rdf_from_trajectory(&[s], &r);
+        // Neighbors per atom: g * shell * density / N
+        let count = |ibin: usize| g[ibin] * 4.0 * std::f64::consts::PI * r[ibin].powi(2) * dr * (2.0 / (2.0 * a.powi(3))) / 2.0;
+        assert!((count(20) - 6.0).abs() < 1E-8);
+        assert!((count(28) - 12.0).abs() < 1E-8);
+        assert_eq!(g[10], 0.0);
+    }
+}
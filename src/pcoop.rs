@@ -0,0 +1,425 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+use colored::Colorize;
+use log::{
+    info,
+    warn,
+};
+use crate::format::Structure;
+use crate::procar::Procar;
+use crate::selection::{
+    RawSelection,
+    Selection,
+};
+use crate::dos::{
+    Smearing,
+    _selected_weight,
+    _state_weights,
+};
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+// Pair of atom groups as written by the user, e.g.
+//
+// [[pairs]]
+// label   = "Ti-O"
+// atoms1  = "1"
+// atoms2  = "5..6"
+// orbits1 = "dxy dyz dxz"   # optional, empty means all orbitals
+// orbits2 = "px py pz"
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RawPair {
+    pub label   : String,
+    pub atoms1  : String,
+    pub atoms2  : String,
+    #[serde(default)]
+    pub orbits1 : String,
+    #[serde(default)]
+    pub orbits2 : String,
+}
+
+// Parsed pair, indices start from 0
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairSelection {
+    pub label  : String,
+    pub first  : Selection,
+    pub second : Selection,
+}
+
+impl RawPair {
+    /// `input` is like "1:5..6", the atoms at both sides of ':' follow the syntax of `--atoms`.
+    pub fn from_cli(input: &str) -> Self {
+        let (a, b) = input.split_once(':')
+            .unwrap_or_else(|| panic!("Invalid atom pair '{}', should be like '1:5..6'", input));
+        Self {
+            label: input.replace(' ', "_"),
+            atoms1: a.to_string(),
+            atoms2: b.to_string(),
+            orbits1: String::new(),
+            orbits2: String::new(),
+        }
+    }
+
+    pub fn parse(&self, nions: usize, orbitals: &[String]) -> PairSelection {
+        assert!(!self.atoms1.trim().is_empty() && !self.atoms2.trim().is_empty(),
+                "Atoms of pair '{}' should not be empty", self.label);
+        PairSelection {
+            label: self.label.clone(),
+            first: RawSelection::new(&self.label, &self.atoms1, &self.orbits1).parse(nions, orbitals),
+            second: RawSelection::new(&self.label, &self.atoms2, &self.orbits2).parse(nions, orbitals),
+        }
+    }
+
+    /// Same as `parse`, except that the atoms can also be selected by element symbols and
+    /// spatial filters.
+    pub fn parse_in(&self, structure: &Structure, orbitals: &[String]) -> PairSelection {
+        assert!(!self.atoms1.trim().is_empty() && !self.atoms2.trim().is_empty(),
+                "Atoms of pair '{}' should not be empty", self.label);
+        PairSelection {
+            label: self.label.clone(),
+            first: RawSelection::new(&self.label, &self.atoms1, &self.orbits1).parse_in(structure, orbitals),
+            second: RawSelection::new(&self.label, &self.atoms2, &self.orbits2).parse_in(structure, orbitals),
+        }
+    }
+
+    /// Whether the atoms are selected by element symbols or spatial filters, which need the
+    /// structure to parse.
+    pub fn needs_structure(&self) -> bool {
+        RawSelection::new(&self.label, &self.atoms1, "").needs_structure()
+            || RawSelection::new(&self.label, &self.atoms2, "").needs_structure()
+    }
+}
+
+
+// Overlap indicator of one state between the two groups of a pair. With the phase factors of
+// LORBIT=12, it is 2*Re(conj(sum_A c) * sum_B c) which is positive for bonding and negative for
+// antibonding states. Otherwise only 2*sqrt(w_A * w_B) is available, which is never negative.
+fn _pair_weight(procar: &Procar, ispin: usize, ikpoint: usize, iband: usize, pair: &PairSelection) -> f64 {
+    let norbits = procar.norbits();
+    match procar.band_phases(ispin, ikpoint, iband) {
+        Some(phases) => {
+            let sum = |sel: &Selection| {
+                sel.iatoms.iter()
+                    .flat_map(|ia| sel.iorbits.iter().map(move |io| phases[ia * norbits + io]))
+                    .fold([0.0, 0.0], |acc, c| [acc[0] + c[0], acc[1] + c[1]])
+            };
+            let (a, b) = (sum(&pair.first), sum(&pair.second));
+            2.0 * (a[0] * b[0] + a[1] * b[1])
+        },
+        None => {
+            let icomp = if procar.lncl { 0 } else { ispin };
+            let wa = _selected_weight(procar, icomp, ikpoint, iband, &pair.first);
+            let wb = _selected_weight(procar, icomp, ikpoint, iband, &pair.second);
+            2.0 * (wa * wb).sqrt()
+        },
+    }
+}
+
+
+// Contribution of one state to a pair
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairState {
+    pub ispin  : usize,  // all the indices start from 1
+    pub ikpt   : usize,
+    pub iband  : usize,
+    pub energy : f64,    // relative to E-fermi
+    pub weight : f64,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairIntegral {
+    pub label   : String,
+    pub ispin   : usize,  // starts from 1
+    pub bonding : f64,    // integrated overlap of occupied states
+}
+
+
+/// Approximate crystal orbital overlap populations of atom pairs from PROCAR, as a substitute
+/// of LOBSTER. The overlap integrals are neglected, and the signs of the contributions are only
+/// available with the phase factors of LORBIT=12.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pcoop {
+    pub signed    : bool,
+    pub energies  : Vec<f64>,                      // relative to E-fermi
+    pub curves    : Vec<(String, Vec<Vec<f64>>)>,  // (label, [nspin][nedos])
+    pub states    : Vec<(String, Vec<PairState>)>,
+    pub integrals : Vec<PairIntegral>,
+}
+
+impl Pcoop {
+    /// The energy grid and smearing are the same as `Dos::from_procar`.
+    pub fn from_procar(procar: &Procar, efermi: f64, emin: f64, emax: f64,
+                       nedos: usize, sigma: f64, pairs: &[PairSelection]) -> Self {
+        assert!(emin < emax, "Invalid energy range, emin should be less than emax");
+        assert!(nedos > 1, "NEDOS should be larger than 1");
+        assert!(sigma > 0.0, "Smearing width should be positive");
+
+        let signed = procar.phases.is_some();
+        if !signed {
+            warn!("No phase factors found in PROCAR, the pair overlaps are unsigned. Use LORBIT=12 for bonding/antibonding characters.");
+        }
+
+        let de = (emax - emin) / (nedos - 1) as f64;
+        let energies = (0 .. nedos).map(|i| emin + de * i as f64).collect::<Vec<f64>>();
+        let (weights, factor) = _state_weights(procar);
+        let cutoff = Smearing::Gaussian.cutoff(sigma);
+        let nspin = procar.nspin;
+
+        let mut curves = vec![];
+        let mut states = vec![];
+        let mut integrals = vec![];
+        for pair in pairs.iter() {
+            let mut curve = vec![vec![0.0f64; nedos]; nspin];
+            let mut pstates = vec![];
+            for (ispin, cs) in curve.iter_mut().enumerate() {
+                let mut bonding = 0.0;
+                for (ik, wk) in weights.iter().enumerate() {
+                    for ib in 0 .. procar.nbands {
+                        let e = procar.eigval(ispin, ik, ib) - efermi;
+                        let w = _pair_weight(procar, ispin, ik, ib, pair);
+                        pstates.push(PairState { ispin: ispin + 1, ikpt: ik + 1, iband: ib + 1, energy: e, weight: w });
+                        // Occupations of PROCAR are 1 for fully occupied states
+                        bonding += wk * factor * w * procar.occupation(ispin, ik, ib);
+
+                        if e < emin - cutoff || e > emax + cutoff { continue; }
+                        for (c, x) in cs.iter_mut().zip(energies.iter()) {
+                            if (x - e).abs() <= cutoff {
+                                *c += wk * factor * w * Smearing::Gaussian.delta(x - e, sigma);
+                            }
+                        }
+                    }
+                }
+                integrals.push(PairIntegral { label: pair.label.clone(), ispin: ispin + 1, bonding });
+            }
+            curves.push((pair.label.clone(), curve));
+            states.push((pair.label.clone(), pstates));
+        }
+
+        Self {
+            signed,
+            energies,
+            curves,
+            states,
+            integrals,
+        }
+    }
+
+    /// Saves the energy-resolved curves to `pcoop.txt`, and the contributions of each k-point
+    /// and band to `pcoop_states.txt`.
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "pcoop.txt")?;
+        info!("Saving pair overlaps to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        let nspin = self.curves.first().map_or(1, |c| c.1.len());
+        let spin_suffix = |ispin: usize| -> &str {
+            match (nspin, ispin) {
+                (1, _) => "",
+                (_, 0) => "_up",
+                _      => "_dn",
+            }
+        };
+
+        write!(f, "# {:>10}", "E-Ef/eV")?;
+        for (label, _) in self.curves.iter() {
+            for ispin in 0 .. nspin {
+                write!(f, " {:>14}", format!("{}{}", label, spin_suffix(ispin)))?;
+            }
+        }
+        writeln!(f)?;
+        for (i, e) in self.energies.iter().enumerate() {
+            write!(f, "  {:10.5}", e)?;
+            for (_, c) in self.curves.iter() {
+                for cs in c.iter() {
+                    write!(f, " {:14.6}", cs[i])?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        let fname = _prepare_fname(path, "pcoop_states.txt")?;
+        info!("Saving k- and band-resolved pair overlaps to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+        writeln!(f, "# {:>12} {:>4} {:>5} {:>5} {:>10} {:>12}", "label", "spin", "ikpt", "iband", "E-Ef/eV", "weight")?;
+        for (label, states) in self.states.iter() {
+            for s in states.iter() {
+                writeln!(f, "  {:>12} {:4} {:5} {:5} {:10.5} {:12.6}", label, s.ispin, s.ikpt, s.iband, s.energy, s.weight)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spin down components are plotted with dashed lines.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "pcoop.html")?;
+        let ytitle = if self.signed { "pCOOP (bonding > 0)" } else { "Pair overlap (unsigned)" };
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Approximate crystal orbital overlap population",
+                "xaxis": {"title": "E-Ef (eV)"},
+                "yaxis": {"title": ytitle},
+                "shapes": [{
+                    "type": "line", "xref": "x", "yref": "paper",
+                    "x0": 0.0, "x1": 0.0, "y0": 0.0, "y1": 1.0,
+                    "line": {"dash": "dash", "color": "gray", "width": 1},
+                }],
+            }));
+
+        for (label, c) in self.curves.iter() {
+            let nspin = c.len();
+            for (ispin, cs) in c.iter().enumerate() {
+                let suffix = match (nspin, ispin) {
+                    (1, _) => "",
+                    (_, 0) => " up",
+                    _      => " down",
+                };
+                plot.add_trace(json!({
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": format!("{}{}", label, suffix),
+                    "x": self.energies,
+                    "y": cs,
+                    "line": {"dash": if ispin == 1 { "dash" } else { "solid" }},
+                }));
+            }
+        }
+
+        plot.save_html(&fname)
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+impl Tabular for Pcoop {
+    fn headers(&self) -> Vec<String> {
+        ["label", "spin", "bonding"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.integrals.iter()
+            .map(|p| vec![p.label.clone(), p.ispin.to_string(), format!("{:.5}", p.bonding)])
+            .collect()
+    }
+}
+
+impl fmt::Display for Pcoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = if self.signed {
+            "# Integrated pair overlaps of occupied states, positive for net bonding"
+        } else {
+            "# Integrated pair overlaps of occupied states, unsigned without phase factors"
+        };
+        writeln!(f, "{}", title.bright_green())?;
+        writeln!(f, "{}", format!("  {:>12} {:>4} {:>10}", "Label", "Spin", "Overlap").bright_green())?;
+        for p in self.integrals.iter() {
+            writeln!(f, "  {:>12} {:4} {}", p.label, p.ispin, format!("{:10.5}", p.bonding).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _generate_procar(with_phases: bool) -> Procar {
+        let input = r#"PROCAR lm decomposed + phase
+# of k-points:    1         # of bands:   2         # of ions:   2
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -2.00000000 # occ.  1.00000000
+
+ion      s      p    tot
+    1  0.250  0.000  0.250
+    2  0.000  0.250  0.250
+tot    0.250  0.250  0.500
+ion          s             p
+    1  0.500  0.000   0.000  0.000   charge 0.250
+    2  0.000  0.000   0.500  0.000   charge 0.250
+charge 0.250  0.250  0.500
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s      p    tot
+    1  0.250  0.000  0.250
+    2  0.000  0.250  0.250
+tot    0.250  0.250  0.500
+ion          s             p
+    1  0.500  0.000   0.000  0.000   charge 0.250
+    2  0.000  0.000  -0.500  0.000   charge 0.250
+charge 0.250  0.250  0.500
+
+"#;
+        if with_phases {
+            Procar::parse(input)
+        } else {
+            let input = input.split('\n')
+                .filter(|l| !(l.contains("charge") || l.starts_with("ion          s")))
+                .filter(|l| !(l.starts_with("    1  0.500") || l.starts_with("    2  0.000  0.000")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Procar::parse(&input)
+        }
+    }
+
+    #[test]
+    fn test_pcoop() {
+        let procar = _generate_procar(true);
+        let pairs = vec![RawPair::from_cli("1:2").parse(procar.nions, &procar.orbitals)];
+        let p = Pcoop::from_procar(&procar, 0.0, -4.0, 4.0, 801, 0.1, &pairs);
+        assert!(p.signed);
+
+        // Bonding combination below E-fermi and antibonding one above
+        let states = &p.states[0].1;
+        assert!((states[0].weight - 0.5).abs() < 1E-10);
+        assert!((states[1].weight + 0.5).abs() < 1E-10);
+
+        // Two electrons in the bonding state
+        assert!((p.integrals[0].bonding - 1.0).abs() < 1E-10);
+        let de = p.energies[1] - p.energies[0];
+        let area = p.curves[0].1[0].iter().sum::<f64>() * de;
+        assert!(area.abs() < 1E-4);
+
+        let procar = _generate_procar(false);
+        assert!(procar.phases.is_none());
+        let p = Pcoop::from_procar(&procar, 0.0, -4.0, 4.0, 801, 0.1, &pairs);
+        assert!(!p.signed);
+        assert!((p.states[0].1[1].weight - 0.5).abs() < 1E-10);
+        assert_eq!(p.rows()[0], vec!["1:2", "1", "1.00000"]);
+    }
+}
@@ -0,0 +1,538 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use serde_json::{
+    json,
+    Value,
+};
+use log::{
+    info,
+    warn,
+};
+use crate::settings::Settings;
+use crate::raster::Raster;
+use crate::timing::{
+    self,
+    Stage,
+};
+
+
+const PLOTLY_CDN: &str = "https://cdn.plot.ly/plotly-2.35.2.min.js";
+
+// Default colorway of plotly
+const COLORWAY: [&str; 10] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+                              "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf"];
+
+// Size of static images in inches, and the margins of the plotting area in points
+const FIG_WIDTH: f64  = 6.4;
+const FIG_HEIGHT: f64 = 4.8;
+const MARGIN: [f64; 4] = [60.0, 15.0, 35.0, 45.0];  // left, right, top, bottom
+const LEGEND_WIDTH: f64 = 90.0;
+
+
+// Thin wrapper around plotly.js, the traces and layout are written into a standalone HTML file
+// and rendered by the browser. See https://plotly.com/javascript/reference/ for available keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plot {
+    pub traces : Vec<Value>,
+    pub layout : Value,
+}
+
+impl Default for Plot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plot {
+    pub fn new() -> Self {
+        Self {
+            traces: vec![],
+            layout: json!({}),
+        }
+    }
+
+    pub fn add_trace(&mut self, trace: Value) {
+        self.traces.push(trace);
+    }
+
+    pub fn layout(mut self, layout: Value) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    // Layout with the plot settings of the user applied
+    fn _themed_layout(&self) -> Value {
+        let mut layout = self.layout.clone();
+        Settings::global().plot.apply(&mut layout);
+        layout
+    }
+
+    pub fn to_html(&self) -> String {
+        format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8" />
+<title>rsgrad</title>
+<script src="{}"></script>
+</head>
+<body>
+<div id="rsgrad-plot" style="width:100%;height:95vh;"></div>
+<script>
+Plotly.newPlot("rsgrad-plot", {}, {});
+</script>
+</body>
+</html>
+"#, PLOTLY_CDN, Value::Array(self.traces.clone()), self._themed_layout())
+    }
+
+    pub fn save_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let path = path.as_ref();
+        info!("Saving plot to {:?} ...", path);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        timing::timed(Stage::Plot, || write!(f, "{}", self.to_html()))
+    }
+
+    // Size of the static image in points, and the font of the layout
+    fn _frame(&self) -> _Frame {
+        let layout = self._themed_layout();
+        let [fig_width, fig_height] = Settings::global().plot.figsize.unwrap_or([FIG_WIDTH, FIG_HEIGHT]);
+        let font_family = layout["font"]["family"].as_str().unwrap_or("Arial, sans-serif").to_string();
+        let font_size = layout["font"]["size"].as_f64().unwrap_or(11.0);
+        _Frame {
+            layout,
+            width: fig_width * 72.0,
+            height: fig_height * 72.0,
+            font_family,
+            font_size,
+        }
+    }
+
+    // Draws the 2D scatter traces and line shapes of the static image, other trace types are
+    // skipped
+    fn _draw(&self, frame: &_Frame, canvas: &mut impl Canvas) {
+        let layout = &frame.layout;
+        let colorway = layout["colorway"].as_array()
+            .map(|c| c.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| COLORWAY.to_vec());
+        let font_size = frame.font_size;
+
+        let scatters = self.traces.iter()
+            .filter(|t| t["type"].as_str().is_none_or(|t| t == "scatter"))
+            .collect::<Vec<_>>();
+        if scatters.len() < self.traces.len() {
+            warn!("{} traces other than 2D scatter are skipped in the static image.", self.traces.len() - scatters.len());
+        }
+
+        if !layout["yaxis2"].is_null() {
+            warn!("Subplots are drawn in a single panel in the static image.");
+        }
+
+        let legends = scatters.iter()
+            .enumerate()
+            .filter(|(_, t)| t["showlegend"].as_bool().unwrap_or(true) && t["name"].is_string())
+            .map(|(i, t)| (i, t["name"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        let showlegend = layout["showlegend"].as_bool().unwrap_or(scatters.len() > 1) && !legends.is_empty();
+
+        let (width, height) = (frame.width, frame.height);
+        let x0 = MARGIN[0];
+        let x1 = width - MARGIN[1] - if showlegend { LEGEND_WIDTH } else { 0.0 };
+        let y0 = MARGIN[2];
+        let y1 = height - MARGIN[3];
+
+        let (xmin, xmax) = _axis_range(&layout["xaxis"], &scatters, "x", 0.0);
+        let (ymin, ymax) = _axis_range(&layout["yaxis"], &scatters, "y", 0.05);
+        let sx = |x: f64| x0 + (x - xmin) / (xmax - xmin) * (x1 - x0);
+        let sy = |y: f64| y1 - (y - ymin) / (ymax - ymin) * (y1 - y0);
+
+        // Ticks, grid lines and axis titles
+        let solid = Value::Null;
+        for (axis, along_x) in [(&layout["xaxis"], true), (&layout["yaxis"], false)] {
+            let (lo, hi) = if along_x { (xmin, xmax) } else { (ymin, ymax) };
+            for (v, label) in _axis_ticks(axis, lo, hi) {
+                if along_x {
+                    let x = sx(v);
+                    canvas.line((x, y0), (x, y1), "#e5e5e5", 1.0, &solid);
+                    canvas.text((x, y1 + 14.0), &label, Anchor::Middle, None, false);
+                } else {
+                    let y = sy(v);
+                    canvas.line((x0, y), (x1, y), "#e5e5e5", 1.0, &solid);
+                    canvas.text((x0 - 5.0, y + 4.0), &label, Anchor::End, None, false);
+                }
+            }
+        }
+        if let Some(title) = _title(&layout["xaxis"]) {
+            canvas.text(((x0 + x1) / 2.0, height - 10.0), title, Anchor::Middle, Some(font_size + 2.0), false);
+        }
+        if let Some(title) = _title(&layout["yaxis"]) {
+            canvas.text((15.0, (y0 + y1) / 2.0), title, Anchor::Middle, Some(font_size + 2.0), true);
+        }
+        if let Some(title) = _title(layout) {
+            canvas.text(((x0 + x1) / 2.0, 22.0), title, Anchor::Middle, Some(font_size + 4.0), false);
+        }
+
+        canvas.clip(Some([x0, y0, x1 - x0, y1 - y0]));
+        for (i, t) in scatters.iter().enumerate() {
+            let color = _color(t, i, &colorway);
+            let mode = t["mode"].as_str().unwrap_or("lines");
+            let xs = t["x"].as_array().cloned().unwrap_or_default();
+            let ys = t["y"].as_array().cloned().unwrap_or_default();
+            let points = xs.iter().zip(ys.iter())
+                .map(|(x, y)| x.as_f64().zip(y.as_f64()).map(|(x, y)| (sx(x), sy(y))))
+                .collect::<Vec<_>>();
+            let segments = points.split(|p| p.is_none())
+                .filter(|s| s.len() > 1)
+                .map(|s| s.iter().flatten().copied().collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+
+            if t["fill"].as_str() == Some("tozeroy") {
+                for seg in segments.iter() {
+                    let mut polygon = vec![(seg[0].0, sy(0.0))];
+                    polygon.extend(seg.iter().copied());
+                    polygon.push((seg[seg.len() - 1].0, sy(0.0)));
+                    canvas.fill(&polygon, color, 0.3);
+                }
+            }
+            if mode.contains("lines") {
+                canvas.path(&segments, color, t["line"]["width"].as_f64().unwrap_or(1.5), &t["line"]["dash"]);
+            }
+            if mode.contains("markers") {
+                // Marker sizes are either one number or one for each point
+                let size = |j: usize| -> f64 {
+                    let s = &t["marker"]["size"];
+                    s.as_f64().or_else(|| s[j].as_f64()).unwrap_or(6.0)
+                };
+                let opacity = t["marker"]["opacity"].as_f64().unwrap_or(1.0);
+                for (j, p) in points.iter().enumerate() {
+                    if let Some(p) = p {
+                        let r = size(j) / 2.0;
+                        if r <= 0.0 { continue; }
+                        canvas.circle(*p, r, color, opacity);
+                    }
+                }
+            }
+        }
+
+        for shape in layout["shapes"].as_array().into_iter().flatten() {
+            if shape["type"].as_str() != Some("line") {
+                continue;
+            }
+            let px = |key: &str| {
+                let v = shape[key].as_f64().unwrap_or(0.0);
+                if shape["xref"].as_str() == Some("paper") { x0 + v * (x1 - x0) } else { sx(v) }
+            };
+            let py = |key: &str| {
+                let v = shape[key].as_f64().unwrap_or(0.0);
+                if shape["yref"].as_str() == Some("paper") { y1 - v * (y1 - y0) } else { sy(v) }
+            };
+            canvas.line((px("x0"), py("y0")), (px("x1"), py("y1")),
+                        shape["line"]["color"].as_str().unwrap_or("black"),
+                        shape["line"]["width"].as_f64().unwrap_or(1.0),
+                        &shape["line"]["dash"]);
+        }
+        canvas.clip(None);
+        canvas.rect([x0, y0, x1 - x0, y1 - y0]);
+
+        if showlegend {
+            for (j, (i, name)) in legends.iter().enumerate() {
+                let y = y0 + 10.0 + 15.0 * j as f64;
+                let color = _color(scatters[*i], *i, &colorway);
+                canvas.line((x1 + 8.0, y), (x1 + 28.0, y), color, 2.0, &solid);
+                canvas.text((x1 + 32.0, y + 4.0), name, Anchor::Start, None, false);
+            }
+        }
+    }
+
+    /// Renders the 2D scatter traces and line shapes into a static SVG image, other trace types
+    /// are skipped. The image is 6.4 x 4.8 inches unless `figsize` is set in the plot settings,
+    /// and `dpi` sets its size in pixels.
+    pub fn to_svg(&self, dpi: f64) -> String {
+        let frame = self._frame();
+        let mut svg = _SvgCanvas(format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" viewBox="0 0 {} {}" font-family="{}" font-size="{}">
+<rect width="100%" height="100%" fill="white"/>
+"#, frame.width / 72.0 * dpi, frame.height / 72.0 * dpi, frame.width, frame.height,
+            _escape(&frame.font_family), frame.font_size));
+        self._draw(&frame, &mut svg);
+        svg.0 + "</svg>\n"
+    }
+
+    /// Renders the plot like `to_svg` into a PNG image of `dpi`, with the text in a built-in
+    /// bitmap font.
+    pub fn to_png(&self, dpi: f64) -> Vec<u8> {
+        let frame = self._frame();
+        let mut raster = Raster::new(frame.width, frame.height, dpi, frame.font_size);
+        self._draw(&frame, &mut raster);
+        raster.to_png()
+    }
+
+    /// Saves the plot as a static image, SVG or PNG by the extension of `path`.
+    pub fn save_image(&self, path: &(impl AsRef<Path> + ?Sized), dpi: f64) -> io::Result<()> {
+        let path = path.as_ref();
+        let ext = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        info!("Saving image to {:?} ...", path);
+        match ext.as_deref() {
+            Some("svg") => timing::timed(Stage::Plot, || fs::write(path, self.to_svg(dpi))),
+            Some("png") => timing::timed(Stage::Plot, || fs::write(path, self.to_png(dpi))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Cannot save {:?}: only SVG and PNG images are supported", path))),
+        }
+    }
+}
+
+
+// Layout of the static image with its size in points
+struct _Frame {
+    layout      : Value,
+    width       : f64,
+    height      : f64,
+    font_family : String,
+    font_size   : f64,
+}
+
+
+// Horizontal alignment of text to its position, like "text-anchor" of SVG
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+
+// Drawing primitives of the static images, in points from the top-left corner. Text is placed
+// by its baseline, and vertical text reads from bottom to top.
+pub(crate) trait Canvas {
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), color: &str, width: f64, dash: &Value);
+    fn path(&mut self, segments: &[Vec<(f64, f64)>], color: &str, width: f64, dash: &Value);
+    fn fill(&mut self, polygon: &[(f64, f64)], color: &str, opacity: f64);
+    fn circle(&mut self, center: (f64, f64), r: f64, color: &str, opacity: f64);
+    fn text(&mut self, at: (f64, f64), text: &str, anchor: Anchor, size: Option<f64>, vertical: bool);
+    fn rect(&mut self, area: [f64; 4]);          // black frame of x, y, width and height
+    fn clip(&mut self, area: Option<[f64; 4]>);  // clips the following drawings until None
+}
+
+
+struct _SvgCanvas(String);
+
+impl Canvas for _SvgCanvas {
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), color: &str, width: f64, dash: &Value) {
+        self.0 += &format!("<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"{}\" stroke-width=\"{}\"{}/>\n",
+                           from.0, from.1, to.0, to.1, color, width, _dasharray(dash));
+    }
+
+    fn path(&mut self, segments: &[Vec<(f64, f64)>], color: &str, width: f64, dash: &Value) {
+        let mut d = String::new();
+        for seg in segments.iter() {
+            for (j, (x, y)) in seg.iter().enumerate() {
+                d += &format!("{}{:.2},{:.2}", if j == 0 { "M" } else { "L" }, x, y);
+            }
+        }
+        self.0 += &format!("<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{}/>\n",
+                           d, color, width, _dasharray(dash));
+    }
+
+    fn fill(&mut self, polygon: &[(f64, f64)], color: &str, opacity: f64) {
+        let mut d = String::new();
+        for (j, (x, y)) in polygon.iter().enumerate() {
+            d += &format!("{}{:.2},{:.2}", if j == 0 { "M" } else { "L" }, x, y);
+        }
+        self.0 += &format!("<path d=\"{}Z\" fill=\"{}\" fill-opacity=\"{}\" stroke=\"none\"/>\n", d, color, opacity);
+    }
+
+    fn circle(&mut self, center: (f64, f64), r: f64, color: &str, opacity: f64) {
+        self.0 += &format!("<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"{}\" fill-opacity=\"{}\"/>\n",
+                           center.0, center.1, r, color, opacity);
+    }
+
+    fn text(&mut self, at: (f64, f64), text: &str, anchor: Anchor, size: Option<f64>, vertical: bool) {
+        let anchor = match anchor {
+            Anchor::Start  => "start",
+            Anchor::Middle => "middle",
+            Anchor::End    => "end",
+        };
+        let size = size.map(|s| format!(" font-size=\"{}\"", s)).unwrap_or_default();
+        if vertical {
+            self.0 += &format!("<text transform=\"translate({},{}) rotate(-90)\" text-anchor=\"{}\"{}>{}</text>\n",
+                               at.0, at.1, anchor, size, _escape(text));
+        } else {
+            self.0 += &format!("<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"{}\"{}>{}</text>\n",
+                               at.0, at.1, anchor, size, _escape(text));
+        }
+    }
+
+    fn rect(&mut self, area: [f64; 4]) {
+        self.0 += &format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>\n",
+                           area[0], area[1], area[2], area[3]);
+    }
+
+    fn clip(&mut self, area: Option<[f64; 4]>) {
+        match area {
+            Some(a) => self.0 += &format!("<defs><clipPath id=\"area\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath></defs>\n<g clip-path=\"url(#area)\">\n",
+                                          a[0], a[1], a[2], a[3]),
+            None => self.0 += "</g>\n",
+        }
+    }
+}
+
+
+// Axis title, either a string or {"text": ...}
+fn _title(v: &Value) -> Option<&str> {
+    v["title"].as_str().or_else(|| v["title"]["text"].as_str())
+}
+
+
+fn _escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+fn _color<'a>(trace: &'a Value, i: usize, colorway: &[&'a str]) -> &'a str {
+    trace["line"]["color"].as_str()
+        .or_else(|| trace["marker"]["color"].as_str())
+        .unwrap_or(colorway[i % colorway.len()])
+}
+
+
+// Dash and gap lengths of the dashed lines in points
+pub(crate) fn _dash_pattern(dash: &Value) -> Option<(f64, f64)> {
+    match dash.as_str() {
+        Some("dash") => Some((6.0, 4.0)),
+        Some("dot")  => Some((2.0, 3.0)),
+        _ => None,
+    }
+}
+
+
+fn _dasharray(dash: &Value) -> String {
+    _dash_pattern(dash)
+        .map(|(on, off)| format!(" stroke-dasharray=\"{},{}\"", on, off))
+        .unwrap_or_default()
+}
+
+
+// Data range of the axis, unless given by "range". Filled traces include zero.
+fn _axis_range(axis: &Value, traces: &[&Value], key: &str, pad: f64) -> (f64, f64) {
+    if let Some(r) = axis["range"].as_array() {
+        if let (Some(a), Some(b)) = (r.first().and_then(Value::as_f64), r.get(1).and_then(Value::as_f64)) {
+            return (a, b);
+        }
+    }
+
+    let (mut lo, mut hi) = (f64::INFINITY, f64::NEG_INFINITY);
+    for t in traces.iter() {
+        for v in t[key].as_array().into_iter().flatten().filter_map(Value::as_f64) {
+            lo = lo.min(v);
+            hi = hi.max(v);
+        }
+        if key == "y" && t["fill"].as_str() == Some("tozeroy") {
+            lo = lo.min(0.0);
+            hi = hi.max(0.0);
+        }
+    }
+    if !lo.is_finite() || !hi.is_finite() {
+        return (0.0, 1.0);
+    }
+    if hi - lo < 1E-12 {
+        return (lo - 0.5, hi + 0.5);
+    }
+    let d = (hi - lo) * pad;
+    (lo - d, hi + d)
+}
+
+
+// Tick positions and labels, from "tickvals" and "ticktext" if given, or at most 7 round numbers
+fn _axis_ticks(axis: &Value, lo: f64, hi: f64) -> Vec<(f64, String)> {
+    if axis["showticklabels"].as_bool() == Some(false) {
+        return vec![];
+    }
+    if let Some(vals) = axis["tickvals"].as_array() {
+        let texts = axis["ticktext"].as_array();
+        return vals.iter()
+            .enumerate()
+            .filter_map(|(i, v)| {
+                let v = v.as_f64()?;
+                let text = texts.and_then(|t| t.get(i)).and_then(Value::as_str)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| v.to_string());
+                Some((v, text))
+            })
+            .collect();
+    }
+
+    let raw = (hi - lo) / 7.0;
+    let mag = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|m| m * mag)
+        .find(|s| *s >= raw)
+        .unwrap();
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let first = (lo / step).ceil() as i64;
+    let last = (hi / step).floor() as i64;
+    (first ..= last)
+        .map(|i| {
+            let v = i as f64 * step;
+            (v, format!("{:.*}", decimals, if v.abs() < step * 1E-6 { 0.0 } else { v }))
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_html() {
+        let mut plot = Plot::new()
+            .layout(json!({"title": "test"}));
+        plot.add_trace(json!({"x": [1, 2], "y": [3, 4], "type": "scatter"}));
+        let html = plot.to_html();
+        assert!(html.contains(r#"Plotly.newPlot("rsgrad-plot", [{"type":"scatter","x":[1,2],"y":[3,4]}], {"title":"test"});"#));
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let mut plot = Plot::new()
+            .layout(json!({"title": "a < b", "xaxis": {"tickvals": [1.0, 2.0], "ticktext": ["Γ", "X"]}}));
+        plot.add_trace(json!({"x": [1.0, 1.5, 2.0], "y": [0.0, null, 1.0], "type": "scatter", "mode": "lines+markers"}));
+        plot.add_trace(json!({"x": [0.0], "y": [0.0], "z": [0.0], "type": "scatter3d"}));
+        let svg = plot.to_svg(200.0);
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="1280" height="960""#));
+        assert!(svg.contains("a &lt; b"));
+        assert!(svg.contains(">Γ</text>") && svg.contains(">X</text>"));
+        assert_eq!(svg.matches("<circle").count(), 2);
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn test_to_png() {
+        let mut plot = Plot::new()
+            .layout(json!({"title": "test", "shapes": [{"type": "line", "x0": 0.0, "x1": 1.0, "y0": 0.5, "y1": 0.5, "line": {"dash": "dash"}}]}));
+        plot.add_trace(json!({"x": [0.0, 1.0], "y": [0.0, 1.0], "type": "scatter", "fill": "tozeroy"}));
+        let png = plot.to_png(100.0);
+        assert_eq!(&png[.. 8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[16 .. 24], [0, 0, 2, 128, 0, 0, 1, 224]);  // 640 x 480
+
+        let dir = tempdir::TempDir::new("rsgrad_plot").unwrap();
+        plot.save_image(&dir.path().join("plot.PNG"), 100.0).unwrap();
+        assert_eq!(fs::read(dir.path().join("plot.PNG")).unwrap(), png);
+        assert!(plot.save_image(&dir.path().join("plot.jpg"), 100.0).is_err());
+    }
+
+    #[test]
+    fn test_axis_ticks() {
+        let ticks = _axis_ticks(&json!({}), -2.3, 7.9);
+        assert_eq!(ticks.iter().map(|t| t.1.as_str()).collect::<Vec<_>>(), vec!["-2", "0", "2", "4", "6"]);
+        let ticks = _axis_ticks(&json!({}), 0.0, 0.45);
+        assert_eq!(ticks[1], (0.1, "0.1".to_string()));
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use log::info;
+use crate::outcar::Mat33;
+use crate::chgcar::ChargeDensity;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+fn _check_consistency(host: &ChargeDensity, defect: &ChargeDensity) {
+    assert_eq!(host.ngrid, defect.ngrid, "Inconsistent grid sizes of host and defect LOCPOTs");
+    let same_cell = host.cell().iter().flatten()
+        .zip(defect.cell().iter().flatten())
+        .all(|(a, b)| (a - b).abs() < 1E-4);
+    assert!(same_cell, "Inconsistent lattice vectors of host and defect LOCPOTs");
+}
+
+
+fn _frac_to_cart(cell: &Mat33<f64>, frac: &[f64; 3]) -> [f64; 3] {
+    let mut ret = [0.0f64; 3];
+    for (f, a) in frac.iter().zip(cell.iter()) {
+        for j in 0 .. 3 {
+            ret[j] += f * a[j];
+        }
+    }
+    ret
+}
+
+
+// Distance between two points in fractional coordinates, with the minimum image convention
+pub(crate) fn _periodic_distance(cell: &Mat33<f64>, a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let d = [
+        a[0] - b[0] - (a[0] - b[0]).round(),
+        a[1] - b[1] - (a[1] - b[1]).round(),
+        a[2] - b[2] - (a[2] - b[2]).round(),
+    ];
+    let c = _frac_to_cart(cell, &d);
+    (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt()
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+// Potential alignment from the planar averaged potentials, sampled in the window centered
+// at the plane farthest from the defect.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlanarAlignment {
+    pub axis   : usize,     // 0, 1, 2 for a, b, c respectively
+    pub coords : Vec<f64>,  // in Angstrom along the axis
+    pub host   : Vec<f64>,
+    pub defect : Vec<f64>,
+    pub center : f64,       // fractional coordinate of defect along the axis
+    pub width  : f64,       // width of sampling window in Angstrom
+    pub dv     : f64,       // averaged V_defect - V_host in the sampling window
+}
+
+impl PlanarAlignment {
+    /// Only the first block of each LOCPOT is used, i.e. the spin up potential of ISPIN=2.
+    pub fn new(host: &ChargeDensity, defect: &ChargeDensity, axis: usize, center: f64, width: f64) -> Self {
+        assert!(axis < 3, "Axis should be 0, 1 or 2");
+        _check_consistency(host, defect);
+
+        let a = host.cell()[axis];
+        let length = (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
+        let n = host.ngrid[axis];
+        let coords = (0 .. n).map(|i| i as f64 / n as f64 * length).collect::<Vec<f64>>();
+        let vhost = host.planar_average(0, axis);
+        let vdefect = defect.planar_average(0, axis);
+
+        let mut ret = Self {
+            axis,
+            coords,
+            host: vhost,
+            defect: vdefect,
+            center,
+            width,
+            dv: 0.0,
+        };
+        let window = ret.window();
+        ret.dv = window.iter().map(|&i| ret.defect[i] - ret.host[i]).sum::<f64>() / window.len() as f64;
+        ret
+    }
+
+    /// Indices of the planes in the sampling window, at least one plane is selected.
+    pub fn window(&self) -> Vec<usize> {
+        let n = self.coords.len();
+        let length = self.length();
+
+        // Distance from each plane to the plane farthest from the defect
+        let far = self.center + 0.5;
+        let dists = (0 .. n)
+            .map(|i| {
+                let d = i as f64 / n as f64 - far;
+                (d - d.round()).abs() * length
+            })
+            .collect::<Vec<f64>>();
+
+        let mut inds = (0 .. n).filter(|&i| dists[i] <= self.width / 2.0).collect::<Vec<usize>>();
+        if inds.is_empty() {
+            let inearest = (0 .. n)
+                .min_by(|&i, &j| dists[i].partial_cmp(&dists[j]).unwrap())
+                .unwrap();
+            inds.push(inearest);
+        }
+        inds
+    }
+
+    // Length of lattice vector along the axis
+    pub fn length(&self) -> f64 {
+        self.coords.len() as f64 * (self.coords[1] - self.coords[0])
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "potalign_planar.txt")?;
+        info!("Saving planar averaged potentials to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# Planar averaged potentials along axis {}, dV = {:.6} eV",
+                 ["a", "b", "c"][self.axis], self.dv)?;
+        writeln!(f, "# {:>10} {:>14} {:>14} {:>14}", "x/A", "V_host/eV", "V_defect/eV", "dV/eV")?;
+        for (x, h, d) in itertools::multizip((&self.coords, &self.host, &self.defect)) {
+            writeln!(f, "  {:10.5} {:14.6} {:14.6} {:14.6}", x, h, d, d - h)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "potalign_planar.html")?;
+        let far = (self.center + 0.5).rem_euclid(1.0) * self.length();
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Potential alignment, dV = {:.4} eV", self.dv),
+                "xaxis": {"title": format!("{} (A)", ["a", "b", "c"][self.axis])},
+                "yaxis": {"title": "Potential (eV)"},
+                "yaxis2": {"title": "dV (eV)", "overlaying": "y", "side": "right"},
+                "shapes": [{
+                    "type": "rect", "xref": "x", "yref": "paper",
+                    "x0": far - self.width / 2.0, "x1": far + self.width / 2.0, "y0": 0.0, "y1": 1.0,
+                    "fillcolor": "gray", "opacity": 0.2, "line": {"width": 0},
+                }],
+            }));
+        plot.add_trace(json!({"type": "scatter", "mode": "lines", "name": "host", "x": self.coords, "y": self.host}));
+        plot.add_trace(json!({"type": "scatter", "mode": "lines", "name": "defect", "x": self.coords, "y": self.defect}));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": "dV",
+            "x": self.coords,
+            "y": self.defect.iter().zip(self.host.iter()).map(|(d, h)| d - h).collect::<Vec<f64>>(),
+            "yaxis": "y2",
+        }));
+        plot.save_html(&fname)
+    }
+}
+
+// The planar averaged potentials are saved by `save_as_txt`, only the alignment is listed here
+impl Tabular for PlanarAlignment {
+    fn headers(&self) -> Vec<String> {
+        ["axis", "center", "width", "dv"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            ["a", "b", "c"][self.axis].to_string(),
+            format!("{:.4}", self.center),
+            format!("{:.3}", self.width),
+            format!("{:.6}", self.dv),
+        ]]
+    }
+}
+
+impl fmt::Display for PlanarAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Planar averaged potential alignment".bright_green())?;
+        writeln!(f, "  Axis: {}  Window width: {:.3} A  Defect at: {:.4} (frac)",
+                 ["a", "b", "c"][self.axis], self.width, self.center)?;
+        writeln!(f, "  dV = V_defect - V_host = {} eV", format!("{:.6}", self.dv).bright_yellow())
+    }
+}
+
+
+// Potential alignment from the potentials averaged in spheres centered at the sites far
+// from the defect.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SphericalAlignment {
+    pub radius : f64,                      // radius of spheres in Angstrom
+    pub rmin   : f64,                      // sites closer than rmin to the defect are excluded
+    pub sites  : Vec<(usize, f64, f64)>,   // (ion index starts from 0, distance to defect, dV)
+    pub dv     : f64,
+    pub stddev : f64,
+}
+
+impl SphericalAlignment {
+    /// `center` is the fractional coordinate of the defect, the sites are taken from the host.
+    /// Only the first block of each LOCPOT is used.
+    pub fn new(host: &ChargeDensity, defect: &ChargeDensity, center: &[f64; 3], rmin: f64, radius: f64) -> Self {
+        assert!(radius > 0.0, "Radius of sphere should be positive");
+        _check_consistency(host, defect);
+
+        let cell = host.cell();
+        let (vhost, vdefect) = (&host.blocks[0], &defect.blocks[0]);
+
+        let sphere_average = |site: &[f64; 3]| -> f64 {
+            let indices = host.sphere_indices(site, radius);
+            indices.iter().map(|&i| vdefect[i] - vhost[i]).sum::<f64>() / indices.len() as f64
+        };
+
+        let sites = host.pos.frac_pos.iter()
+            .enumerate()
+            .map(|(i, p)| (i, _periodic_distance(cell, p, center), p))
+            .filter(|(_, d, _)| *d >= rmin)
+            .map(|(i, d, p)| (i, d, sphere_average(p)))
+            .collect::<Vec<_>>();
+        assert!(!sites.is_empty(), "No sites are found farther than {} A from the defect", rmin);
+
+        let nsites = sites.len() as f64;
+        let dv = sites.iter().map(|s| s.2).sum::<f64>() / nsites;
+        let stddev = (sites.iter().map(|s| (s.2 - dv).powi(2)).sum::<f64>() / nsites).sqrt();
+
+        Self {
+            radius,
+            rmin,
+            sites,
+            dv,
+            stddev,
+        }
+    }
+}
+
+impl Tabular for SphericalAlignment {
+    fn headers(&self) -> Vec<String> {
+        ["ion", "distance", "dv"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.sites.iter()
+            .map(|(i, d, v)| vec![(i + 1).to_string(), format!("{:.4}", d), format!("{:.6}", v)])
+            .collect()
+    }
+}
+
+impl fmt::Display for SphericalAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Spherical averaged potential alignment".bright_green())?;
+        writeln!(f, "  Sphere radius: {:.3} A  Sites farther than {:.3} A: {}",
+                 self.radius, self.rmin, self.sites.len())?;
+        writeln!(f, "{}", format!("  {:>6} {:>12} {:>12}", "#Ion", "Distance/A", "dV/eV").bright_green())?;
+        for (i, d, v) in self.sites.iter() {
+            writeln!(f, "  {:6} {:12.4} {:12.6}", i + 1, d, v)?;
+        }
+        writeln!(f, "  dV = V_defect - V_host = {} +/- {:.6} eV",
+                 format!("{:.6}", self.dv).bright_yellow(), self.stddev)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Structure;
+
+    // Potential of a 4x4x4 cubic cell with two atoms, generated by the given function
+    fn _generate_locpot(f: impl Fn(usize, usize, usize) -> f64) -> ChargeDensity {
+        let pos = Structure {
+            cell: [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.0]],
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![2],
+            car_pos: vec![[0.0, 0.0, 0.0], [0.0, 0.0, 2.0]],
+            frac_pos: vec![[0.0, 0.0, 0.0], [0.0, 0.0, 0.5]],
+        };
+        let mut block = vec![];
+        for iz in 0 .. 4 {
+            for iy in 0 .. 4 {
+                for ix in 0 .. 4 {
+                    block.push(f(ix, iy, iz));
+                }
+            }
+        }
+        ChargeDensity { pos, ngrid: [4, 4, 4], blocks: vec![block] }
+    }
+
+    #[test]
+    fn test_planar_alignment() {
+        let host = _generate_locpot(|_, _, _| 1.0);
+        let defect = _generate_locpot(|_, _, iz| if iz == 0 { -3.0 } else { 1.5 });
+        let pa = PlanarAlignment::new(&host, &defect, 2, 0.0, 2.0);
+        assert_eq!(pa.coords, vec![0.0, 1.0, 2.0, 3.0]);
+        assert!((pa.dv - 0.5).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_spherical_alignment() {
+        let host = _generate_locpot(|_, _, _| 0.0);
+        let defect = _generate_locpot(|_, _, iz| iz as f64);
+        let sa = SphericalAlignment::new(&host, &defect, &[0.0, 0.0, 0.0], 1.0, 0.5);
+        assert_eq!(sa.sites.len(), 1);
+        assert_eq!(sa.sites[0].0, 1);
+        assert!((sa.sites[0].1 - 2.0).abs() < 1E-10);
+        assert!((sa.dv - 2.0).abs() < 1E-10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Inconsistent grid sizes")]
+    fn test_alignment_fail() {
+        let host = _generate_locpot(|_, _, _| 0.0);
+        let mut defect = host.clone();
+        defect.ngrid = [2, 8, 4];
+        PlanarAlignment::new(&host, &defect, 2, 0.0, 2.0);
+    }
+}
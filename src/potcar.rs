@@ -0,0 +1,137 @@
+use std::io;
+use std::path::Path;
+use std::fs;
+use regex::Regex;
+use crate::format::Structure;
+
+
+// Header info of one element in POTCAR, the pseudopotential data are not parsed
+#[derive(Clone, Debug, PartialEq)]
+pub struct PotcarHeader {
+    pub titel  : String,  // e.g. "PAW_PBE Fe_pv 02Aug2007"
+    pub symbol : String,  // element symbol, e.g. "Fe"
+    pub pomass : f64,
+    pub zval   : f64,
+    pub enmax  : f64,     // in eV
+    pub rwigs  : f64,     // Wigner-Seitz radius in Angstrom
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Potcar(pub Vec<PotcarHeader>);
+
+impl Potcar {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    /// Each element starts with the "TITEL" line and ends with "End of Dataset"
+    pub fn parse(context: &str) -> Self {
+        let re_titel = Regex::new(r"TITEL  = (.+)").unwrap();
+        let re_pomass = Regex::new(r"POMASS =\s*(\S+);").unwrap();
+        let re_zval = Regex::new(r"ZVAL   =\s*(\S+)").unwrap();
+        let re_enmax = Regex::new(r"ENMAX  =\s*(\S+);").unwrap();
+        // "RWIGS  =    1.630; RWIGS  =    0.863    wigner-seitz radius (au A)", the latter is in Angstrom
+        let re_rwigs = Regex::new(r"RWIGS  =\s*\S+; RWIGS  =\s*(\S+)").unwrap();
+
+        let parse = |re: &Regex, c: &str, name: &str| -> f64 {
+            re.captures(c)
+              .unwrap_or_else(|| panic!("{} not found in POTCAR", name))
+              .get(1)
+              .unwrap()
+              .as_str()
+              .parse::<f64>()
+              .unwrap_or_else(|_| panic!("Cannot parse {} as float value", name))
+        };
+
+        let headers = context.split("End of Dataset")
+            .filter(|c| c.contains("TITEL"))
+            .map(|c| {
+                let titel = re_titel.captures(c)
+                    .unwrap()
+                    .get(1)
+                    .unwrap()
+                    .as_str()
+                    .trim()
+                    .to_string();
+                let symbol = titel.split_whitespace()
+                    .nth(1)
+                    .and_then(|s| s.split('_').next())
+                    .expect("Cannot find element symbol in TITEL of POTCAR")
+                    .to_string();
+                PotcarHeader {
+                    titel,
+                    symbol,
+                    pomass: parse(&re_pomass, c, "POMASS"),
+                    zval: parse(&re_zval, c, "ZVAL"),
+                    enmax: parse(&re_enmax, c, "ENMAX"),
+                    rwigs: parse(&re_rwigs, c, "RWIGS"),
+                }
+            })
+            .collect::<Vec<_>>();
+        assert!(!headers.is_empty(), "No element found in POTCAR");
+
+        Self(headers)
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        self.0.iter().map(|h| h.symbol.clone()).collect()
+    }
+
+    pub fn zvals(&self) -> Vec<f64> {
+        self.0.iter().map(|h| h.zval).collect()
+    }
+
+    pub fn rwigs(&self) -> Vec<f64> {
+        self.0.iter().map(|h| h.rwigs).collect()
+    }
+
+    /// POMASS of each atom in the structure, the elements are matched by their symbols.
+    pub fn masses_of(&self, structure: &Structure) -> io::Result<Vec<f64>> {
+        structure.ion_types.iter()
+            .zip(structure.ions_per_type.iter())
+            .map(|(t, n)| self.0.iter()
+                 .find(|h| &h.symbol == t)
+                 .map(|h| vec![h.pomass; *n as usize])
+                 .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                     format!("Element {} not found in POTCAR, available elements: {:?}", t, self.symbols()))))
+            .collect::<io::Result<Vec<_>>>()
+            .map(|m| m.concat())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_potcar() {
+        let input = r#"  PAW_PBE Se 06Sep2000
+ 6.00000000000000000
+ parameters from PSCTR are:
+   VRHFIN =Se: s2p4
+   TITEL  = PAW_PBE Se 06Sep2000
+   POMASS =   78.960; ZVAL   =    6.000    mass and valenz
+   RWIGS  =    2.200; RWIGS  =    1.164    wigner-seitz radius (au A)
+   ENMAX  =  211.555; ENMIN  =  158.666 eV
+ End of Dataset
+  PAW_PBE V_sv 07Sep2000
+ 13.0000000000000000
+ parameters from PSCTR are:
+   TITEL  = PAW_PBE V_sv 07Sep2000
+   POMASS =   50.941; ZVAL   =   13.000    mass and valenz
+   RWIGS  =    2.500; RWIGS  =    1.323    wigner-seitz radius (au A)
+   ENMAX  =  263.673; ENMIN  =  197.755 eV
+ End of Dataset
+"#;
+        let potcar = Potcar::parse(input);
+        assert_eq!(potcar.symbols(), vec!["Se", "V"]);
+        assert_eq!(potcar.zvals(), vec![6.0, 13.0]);
+        assert_eq!(potcar.0[1].titel, "PAW_PBE V_sv 07Sep2000");
+        assert_eq!(potcar.0[1].enmax, 263.673);
+        assert_eq!(potcar.0[0].pomass, 78.96);
+        assert_eq!(potcar.rwigs(), vec![1.164, 1.323]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use log::info;
+use crate::chgcar::ChargeDensity;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+// Local potential along the segment between two points in fractional coordinates, which needn't
+// be aligned with lattice vectors. The potentials are relative to the vacuum level if given.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PotentialLine {
+    pub start  : [f64; 3],       // fractional coordinates
+    pub end    : [f64; 3],
+    pub dists  : Vec<f64>,       // distance to `start` in Angstrom
+    pub values : Vec<f64>,       // in eV
+    pub vacuum : Option<f64>,    // vacuum level subtracted from the values, in eV
+    pub efermi : Option<f64>,
+}
+
+impl PotentialLine {
+    /// Samples the first block of LOCPOT at `npoints` evenly spaced points by trilinear
+    /// interpolation, both ends included.
+    pub fn new(locpot: &ChargeDensity, start: [f64; 3], end: [f64; 3], npoints: usize) -> Self {
+        assert!(npoints >= 2, "At least two points are needed for the line profile");
+        let cell = locpot.cell();
+        let d = [0, 1, 2].map(|a| end[a] - start[a]);
+        let dcart = [0, 1, 2].map(|j| d[0] * cell[0][j] + d[1] * cell[1][j] + d[2] * cell[2][j]);
+        let length = dcart.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+        let (dists, values) = (0 .. npoints)
+            .map(|i| {
+                let t = i as f64 / (npoints - 1) as f64;
+                let frac = [0, 1, 2].map(|a| start[a] + t * d[a]);
+                (t * length, locpot.interpolate(0, &frac))
+            })
+            .unzip();
+
+        Self {
+            start,
+            end,
+            dists,
+            values,
+            vacuum: None,
+            efermi: None,
+        }
+    }
+
+    /// Shifts the potentials to make the vacuum level zero.
+    pub fn with_vacuum(mut self, vacuum: f64) -> Self {
+        let shift = vacuum - self.vacuum.unwrap_or(0.0);
+        self.values.iter_mut().for_each(|v| *v -= shift);
+        self.vacuum = Some(vacuum);
+        self
+    }
+
+    pub fn with_efermi(mut self, efermi: f64) -> Self {
+        self.efermi = Some(efermi);
+        self
+    }
+
+    pub fn length(&self) -> f64 {
+        *self.dists.last().unwrap()
+    }
+
+    /// Work function V_vacuum - E_fermi, available if both are known.
+    pub fn work_function(&self) -> Option<f64> {
+        Some(self.vacuum? - self.efermi?)
+    }
+
+    pub fn range(&self) -> (f64, f64) {
+        self.values.iter().fold((f64::MAX, f64::MIN), |(a, b), x| (a.min(*x), b.max(*x)))
+    }
+
+    fn _ylabel(&self) -> &'static str {
+        if self.vacuum.is_some() { "V - V_vac" } else { "V" }
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "potline.txt")?;
+        info!("Saving potential line profile to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# Local potential from {:?} to {:?} (fractional)", self.start, self.end)?;
+        if let Some(vac) = self.vacuum {
+            writeln!(f, "# Relative to the vacuum level {:.6} eV", vac)?;
+        }
+        writeln!(f, "# {:>10} {:>14}", "x/A", format!("{}/eV", self._ylabel()))?;
+        for (x, v) in self.dists.iter().zip(self.values.iter()) {
+            writeln!(f, "  {:10.5} {:14.6}", x, v)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "potline.html")?;
+        let mut shapes = vec![];
+        if let Some(efermi) = self.efermi {
+            let y = efermi - self.vacuum.unwrap_or(0.0);
+            shapes.push(json!({
+                "type": "line", "xref": "paper", "yref": "y", "x0": 0.0, "x1": 1.0, "y0": y, "y1": y,
+                "line": {"color": "gray", "dash": "dash", "width": 1},
+            }));
+        }
+
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Local potential from ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+                                 self.start[0], self.start[1], self.start[2], self.end[0], self.end[1], self.end[2]),
+                "xaxis": {"title": "Distance (A)"},
+                "yaxis": {"title": format!("{} (eV)", self._ylabel())},
+                "shapes": shapes,
+            }));
+        plot.add_trace(json!({"type": "scatter", "mode": "lines", "name": "LOCPOT", "x": self.dists, "y": self.values}));
+        plot.save_html(&fname)
+    }
+}
+
+// The profile is saved by `save_as_txt`, only the summary is listed here
+impl Tabular for PotentialLine {
+    fn headers(&self) -> Vec<String> {
+        ["length", "vmin", "vmax", "vacuum", "efermi", "work_function"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let opt = |x: Option<f64>| x.map(|x| format!("{:.6}", x)).unwrap_or_default();
+        let (vmin, vmax) = self.range();
+        vec![vec![
+            format!("{:.5}", self.length()),
+            format!("{:.6}", vmin),
+            format!("{:.6}", vmax),
+            opt(self.vacuum),
+            opt(self.efermi),
+            opt(self.work_function()),
+        ]]
+    }
+}
+
+impl fmt::Display for PotentialLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (vmin, vmax) = self.range();
+        writeln!(f, "{}", "# Local potential line profile".bright_green())?;
+        writeln!(f, "  From: ({:.4}, {:.4}, {:.4})  To: ({:.4}, {:.4}, {:.4})  Length: {:.4} A",
+                 self.start[0], self.start[1], self.start[2], self.end[0], self.end[1], self.end[2], self.length())?;
+        writeln!(f, "  {} range: {:.6} ~ {:.6} eV", self._ylabel(), vmin, vmax)?;
+        if let Some(vac) = self.vacuum {
+            writeln!(f, "  Vacuum level: {:.6} eV", vac)?;
+        }
+        if let Some(wf) = self.work_function() {
+            writeln!(f, "  Work function: {} eV", format!("{:.6}", wf).bright_yellow())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_potential_line() {
+        let locpot = ChargeDensity::parse(r#"unknown system
+   1.00000000000000
+     4.000000    0.000000    0.000000
+     0.000000    4.000000    0.000000
+     0.000000    0.000000    8.000000
+   H
+     1
+Direct
+  0.000000  0.000000  0.000000
+
+    2    2    4
+ 0.10000000000E+01 0.10000000000E+01 0.10000000000E+01 0.10000000000E+01 0.20000000000E+01
+ 0.20000000000E+01 0.20000000000E+01 0.20000000000E+01 0.30000000000E+01 0.30000000000E+01
+ 0.30000000000E+01 0.30000000000E+01 0.40000000000E+01 0.40000000000E+01 0.40000000000E+01
+ 0.40000000000E+01
+"#);
+        // Diagonal line across the cell, not aligned with any lattice vector
+        let line = PotentialLine::new(&locpot, [0.0, 0.0, 0.0], [0.5, 0.5, 0.5], 5);
+        assert!((line.length() - (4.0f64 + 4.0 + 16.0).sqrt()).abs() < 1E-10);
+        assert_eq!(line.values.len(), 5);
+        assert!((line.values[0] - 1.0).abs() < 1E-10);
+        assert!((line.values[4] - 3.0).abs() < 1E-10);
+
+        let line = line.with_vacuum(4.0).with_efermi(-1.0);
+        assert!((line.values[0] + 3.0).abs() < 1E-10);
+        assert_eq!(line.work_function(), Some(5.0));
+        assert_eq!(line.rows()[0][5], "5.000000");
+    }
+}
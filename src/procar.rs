@@ -0,0 +1,680 @@
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::fs;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use crate::outcar::MatX3;
+use crate::progress::Progress;
+use crate::cache;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Procar {
+    pub nkpts       : usize,
+    pub nbands      : usize,
+    pub nions       : usize,
+    pub nspin       : usize,        // number of spin channels of eigenvalues, 1 or 2
+    pub lncl        : bool,         // whether the projections carry tot, mx, my and mz components
+    pub orbitals    : Vec<String>,  // orbital names from the header, 'tot' excluded
+    pub kpoints     : MatX3<f64>,   // in fractional coordinates of reciprocal lattice
+    pub weights     : Vec<f64>,
+    pub eigvals     : Vec<f64>,     // [nspin][nkpts][nbands]
+    pub occupations : Vec<f64>,     // [nspin][nkpts][nbands]
+    pub projections : Vec<f64>,     // [ncomp][nkpts][nbands][nions][norbits]
+    pub phases      : Option<Vec<[f64; 2]>>,  // (re, im) of LORBIT=12, [nspin][nkpts][nbands][nions][norbits]
+}
+
+
+impl Procar {
+    /// The parsed result is cached if enabled, see `crate::cache`.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        cache::load_or_parse(path.as_ref(), "procar", Self::_parse_file)
+    }
+
+    fn _parse_file(path: &Path) -> io::Result<Self> {
+        let f = fs::File::open(path)?;
+        // The mapped file is only read, and PROCAR is not supposed to be modified during parsing
+        let mmap = unsafe { Mmap::map(&f)? };
+        Ok(Self::parse_bytes(&mmap))
+    }
+
+    pub fn parse(context: &str) -> Self {
+        Self::parse_bytes(context.as_bytes())
+    }
+
+    /// Reads the orbital names from the header of the first band only, without parsing the
+    /// whole file.
+    pub fn read_orbitals(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Vec<String>> {
+        let f = io::BufReader::new(fs::File::open(path)?);
+        for line in f.lines() {
+            let line = line?;
+            if line.starts_with("ion ") {
+                return Ok(Self::parse_orbitals(&line));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Orbital header not found in PROCAR"))
+    }
+
+    /// Spin headers and k-point lines are located by substring search, then the k-point
+    /// blocks are parsed in parallel.
+    pub fn parse_bytes(context: &[u8]) -> Self {
+        let context = std::str::from_utf8(context).expect("Invalid characters found in PROCAR");
+
+        let headers = _find_lines(context, "# of k-points:");
+        assert!(!headers.is_empty(), "PROCAR header not found");
+        assert!(headers.len() <= 2, "Too many spin channels in PROCAR");
+
+        let (nkpts, nbands, nions) = Self::parse_dimensions(&context[headers[0]..]);
+        let nspin = headers.len();
+
+        // Each k-point block ends at the next k-point or spin header
+        let kpt_pos = _find_lines(context, " k-point ");
+        let kpt_blocks = kpt_pos.iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let next_kpt = kpt_pos.get(i + 1).copied().unwrap_or(context.len());
+                let next_header = headers.iter().copied().find(|h| h > p).unwrap_or(context.len());
+                &context[*p .. next_kpt.min(next_header)]
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(kpt_blocks.len(), nspin * nkpts, "Inconsistent number of k-points in PROCAR");
+
+        let orbitals = Self::parse_orbitals(kpt_blocks[0]);
+        let norbits = orbitals.len();
+
+        let progress = Progress::new("Parsing PROCAR", kpt_blocks.len());
+        let parsed = kpt_blocks.par_iter()
+            .map(|c| {
+                let ret = Self::parse_kpoint_block(c, nbands, nions, norbits);
+                progress.inc(1);
+                ret
+            })
+            .collect::<Vec<_>>();
+        progress.finish();
+
+        let mut kpoints     = vec![];
+        let mut weights     = vec![];
+        let mut eigvals     = Vec::with_capacity(nspin * nkpts * nbands);
+        let mut occupations = Vec::with_capacity(nspin * nkpts * nbands);
+        let mut blocks      = Vec::with_capacity(nspin * nkpts * nbands);
+        let mut band_phases = Vec::with_capacity(nspin * nkpts * nbands);
+
+        for (i, (k, w, bands)) in parsed.into_iter().enumerate() {
+            if i < nkpts {
+                kpoints.push(k);
+                weights.push(w);
+            }
+            for (eig, occ, band_blocks, phase) in bands.into_iter() {
+                eigvals.push(eig);
+                occupations.push(occ);
+                blocks.push(band_blocks);
+                band_phases.push(phase);
+            }
+        }
+        // Non-collinear PROCARs contain tot, mx, my and mz blocks for each band
+        let ncomp_per_band = blocks[0].len();
+        let lncl = match ncomp_per_band {
+            1 => false,
+            4 => true,
+            _ => panic!("Invalid number of projection blocks per band in PROCAR: {}", ncomp_per_band),
+        };
+        assert!(!(lncl && nspin == 2), "Non-collinear PROCAR should contain only one spin channel");
+        assert!(blocks.iter().all(|b| b.len() == ncomp_per_band),
+                "Inconsistent projection blocks in PROCAR");
+
+        let ncomp = if lncl { 4 } else { nspin };
+        let blocksize = nions * norbits;
+        let mut projections = vec![0.0f64; ncomp * nkpts * nbands * blocksize];
+        for (i, band_blocks) in blocks.into_iter().enumerate() {
+            // i = (ispin * nkpts + ik) * nbands + ib
+            let ispin = i / (nkpts * nbands);
+            let ikb   = i % (nkpts * nbands);
+            for (ic, block) in band_blocks.into_iter().enumerate() {
+                let icomp = if lncl { ic } else { ispin };
+                let offset = (icomp * nkpts * nbands + ikb) * blocksize;
+                projections[offset .. offset + blocksize].copy_from_slice(&block);
+            }
+        }
+
+        // Phase factors are only available with LORBIT=12, and then for every band
+        let phases = if band_phases.iter().all(|p| p.is_some()) {
+            Some(band_phases.into_iter().flatten().flatten().collect::<Vec<_>>())
+        } else {
+            assert!(band_phases.iter().all(|p| p.is_none()), "Inconsistent phase factors in PROCAR");
+            None
+        };
+
+        Self {
+            nkpts,
+            nbands,
+            nions,
+            nspin,
+            lncl,
+            orbitals,
+            kpoints,
+            weights,
+            eigvals,
+            occupations,
+            projections,
+            phases,
+        }
+    }
+
+    /// Number of projection components: 4 (tot, mx, my, mz) for non-collinear
+    /// calculations, otherwise the number of spin channels.
+    pub fn ncomp(&self) -> usize {
+        if self.lncl { 4 } else { self.nspin }
+    }
+
+    pub fn norbits(&self) -> usize {
+        self.orbitals.len()
+    }
+
+    // All the indices below start from 0
+    pub fn eigval(&self, ispin: usize, ikpoint: usize, iband: usize) -> f64 {
+        self.eigvals[(ispin * self.nkpts + ikpoint) * self.nbands + iband]
+    }
+
+    pub fn occupation(&self, ispin: usize, ikpoint: usize, iband: usize) -> f64 {
+        self.occupations[(ispin * self.nkpts + ikpoint) * self.nbands + iband]
+    }
+
+    /// Projections of one band onto all ions and orbitals, row-major in [nions][norbits]
+    pub fn band_projections(&self, icomp: usize, ikpoint: usize, iband: usize) -> &[f64] {
+        let blocksize = self.nions * self.norbits();
+        let offset = ((icomp * self.nkpts + ikpoint) * self.nbands + iband) * blocksize;
+        &self.projections[offset .. offset + blocksize]
+    }
+
+    pub fn projection(&self, icomp: usize, ikpoint: usize, iband: usize, iion: usize, iorbit: usize) -> f64 {
+        self.band_projections(icomp, ikpoint, iband)[iion * self.norbits() + iorbit]
+    }
+
+    /// Complex phase factors (re, im) of one band, row-major in [nions][norbits].
+    /// Returns `None` if PROCAR is not written with LORBIT=12.
+    pub fn band_phases(&self, ispin: usize, ikpoint: usize, iband: usize) -> Option<&[[f64; 2]]> {
+        let blocksize = self.nions * self.norbits();
+        let offset = ((ispin * self.nkpts + ikpoint) * self.nbands + iband) * blocksize;
+        self.phases.as_ref().map(|p| &p[offset .. offset + blocksize])
+    }
+
+    /// Expectation values of <sigma_x>, <sigma_y> and <sigma_z> for each k-point of
+    /// the selected band, summed over all ions and orbitals.
+    pub fn spin_texture(&self, iband: usize) -> MatX3<f64> {
+        assert!(self.lncl, "Spin texture is only available for non-collinear PROCAR");
+        assert!(iband < self.nbands, "Band index out of bound.");
+        (0 .. self.nkpts)
+            .map(|ik| {
+                let s = |icomp: usize| self.band_projections(icomp, ik, iband).iter().sum::<f64>();
+                [s(1), s(2), s(3)]
+            })
+            .collect()
+    }
+
+    /// Indices of the zero-weight k-points, if PROCAR also contains k-points with nonzero weight,
+    /// which is the case of band structures from hybrid functional or meta-GGA calculations.
+    /// Returns an empty vector for ordinary calculations.
+    pub fn zero_weight_kpoints(&self) -> Vec<usize> {
+        let izero = (0 .. self.nkpts)
+            .filter(|&ik| self.weights[ik] == 0.0)
+            .collect::<Vec<usize>>();
+        if izero.len() == self.nkpts { vec![] } else { izero }
+    }
+
+    /// Copies the data of selected k-points into a new `Procar`, in the order of `ikpoints`.
+    pub fn select_kpoints(&self, ikpoints: &[usize]) -> Self {
+        assert!(ikpoints.iter().all(|&ik| ik < self.nkpts), "K-point index out of bound.");
+        let nbands = self.nbands;
+        let eigvals = (0 .. self.nspin)
+            .flat_map(|ispin| ikpoints.iter().flat_map(move |&ik| (0 .. nbands).map(move |ib| (ispin, ik, ib))))
+            .map(|(ispin, ik, ib)| self.eigval(ispin, ik, ib))
+            .collect();
+        let occupations = (0 .. self.nspin)
+            .flat_map(|ispin| ikpoints.iter().flat_map(move |&ik| (0 .. nbands).map(move |ib| (ispin, ik, ib))))
+            .map(|(ispin, ik, ib)| self.occupation(ispin, ik, ib))
+            .collect();
+        let projections = (0 .. self.ncomp())
+            .flat_map(|icomp| ikpoints.iter().flat_map(move |&ik| (0 .. nbands).map(move |ib| (icomp, ik, ib))))
+            .flat_map(|(icomp, ik, ib)| self.band_projections(icomp, ik, ib).iter().cloned())
+            .collect::<Vec<f64>>();
+        let phases = self.phases.as_ref().map(|_| {
+            (0 .. self.nspin)
+                .flat_map(|ispin| ikpoints.iter().flat_map(move |&ik| (0 .. nbands).map(move |ib| (ispin, ik, ib))))
+                .flat_map(|(ispin, ik, ib)| self.band_phases(ispin, ik, ib).unwrap().iter().cloned())
+                .collect::<Vec<_>>()
+        });
+
+        Self {
+            nkpts: ikpoints.len(),
+            nbands,
+            nions: self.nions,
+            nspin: self.nspin,
+            lncl: self.lncl,
+            orbitals: self.orbitals.clone(),
+            kpoints: ikpoints.iter().map(|&ik| self.kpoints[ik]).collect(),
+            weights: ikpoints.iter().map(|&ik| self.weights[ik]).collect(),
+            eigvals,
+            occupations,
+            projections,
+            phases,
+        }
+    }
+
+    /// Scissor operator, rigidly shifts all the eigenvalues above `efermi` by `shift` in eV.
+    pub fn apply_scissor(&mut self, efermi: f64, shift: f64) {
+        self.eigvals.iter_mut()
+            .filter(|e| **e > efermi)
+            .for_each(|e| *e += shift);
+    }
+
+    /// Band gap in eV between the highest eigenvalue below `efermi` and the lowest one above,
+    /// None if all the states are at one side. Metals give small or vanishing values.
+    pub fn band_gap(&self, efermi: f64) -> Option<f64> {
+        let vbm = self.eigvals.iter().cloned().filter(|e| *e <= efermi).fold(None, |acc: Option<f64>, e| Some(acc.map_or(e, |a| a.max(e))))?;
+        let cbm = self.eigvals.iter().cloned().filter(|e| *e > efermi).fold(None, |acc: Option<f64>, e| Some(acc.map_or(e, |a| a.min(e))))?;
+        Some(cbm - vbm)
+    }
+
+    /// Highest eigenvalue of the states more than half occupied among the selected k-points,
+    /// used as E-fermi of insulators when OUTCAR is not available.
+    pub fn highest_occupied(&self, ikpoints: &[usize]) -> Option<f64> {
+        (0 .. self.nspin)
+            .flat_map(|ispin| ikpoints.iter().flat_map(move |&ik| (0 .. self.nbands).map(move |ib| (ispin, ik, ib))))
+            .filter(|&(ispin, ik, ib)| self.occupation(ispin, ik, ib) > 0.5)
+            .map(|(ispin, ik, ib)| self.eigval(ispin, ik, ib))
+            .fold(None, |acc: Option<f64>, e| Some(acc.map_or(e, |a| a.max(e))))
+    }
+
+    fn parse_dimensions(context: &str) -> (usize, usize, usize) {
+        // "# of k-points:  165         # of bands:   28         # of ions:   2"
+        let v = context.lines()
+            .find(|l| l.starts_with("# of k-points:"))
+            .expect("Cannot find the dimensions of PROCAR")
+            .split(':')
+            .skip(1)
+            .map(|x| {
+                x.split_whitespace()
+                 .next()
+                 .and_then(|n| n.parse::<usize>().ok())
+                 .expect("Cannot find the dimensions of PROCAR")
+            })
+            .collect::<Vec<usize>>();
+        assert_eq!(v.len(), 3, "Cannot find the dimensions of PROCAR");
+        (v[0], v[1], v[2])
+    }
+
+    fn parse_orbitals(context: &str) -> Vec<String> {
+        let line = context.lines()
+            .find(|l| l.starts_with("ion "))
+            .expect("Orbital header not found in PROCAR");
+        line.split_whitespace()
+            .skip(1)
+            .filter(|x| *x != "tot")
+            .map(|x| x.to_owned())
+            .collect()
+    }
+
+    fn parse_kpoint_line(line: &str) -> ([f64; 3], f64) {
+        // Coordinates may be glued together, e.g. " k-point    3 :   -0.50000000-0.50000000 0.00000000"
+        let mut parts = line.split(':')
+            .nth(1)
+            .expect("Invalid k-point line in PROCAR")
+            .split("weight");
+        let coords = parts.next().unwrap();
+
+        let mut v = vec![];
+        for token in coords.split_whitespace() {
+            let bytes = token.as_bytes();
+            let mut start = 0;
+            for i in 1 .. bytes.len() {
+                if bytes[i] == b'-' && !matches!(bytes[i - 1], b'E' | b'e') {
+                    v.push(&token[start .. i]);
+                    start = i;
+                }
+            }
+            v.push(&token[start ..]);
+        }
+        let v = v.into_iter()
+            .map(|x| x.parse::<f64>().expect("Cannot parse k-point coordinates as float values"))
+            .collect::<Vec<f64>>();
+        assert_eq!(v.len(), 3, "Invalid k-point line in PROCAR");
+
+        let weight = parts.next()
+            .and_then(|x| x.split('=').nth(1))
+            .expect("Cannot find k-point weight in PROCAR")
+            .trim()
+            .parse::<f64>()
+            .expect("Cannot parse k-point weight as float value");
+
+        ([v[0], v[1], v[2]], weight)
+    }
+
+    // Coordinates, weight and the (energy, occupation, projection blocks, phase factors) of all
+    // the bands, each block is row-major [nions][norbits]. The lines are parsed in one pass.
+    #[allow(clippy::type_complexity)]
+    fn parse_kpoint_block(context: &str, nbands: usize, nions: usize, norbits: usize)
+        -> ([f64; 3], f64, Vec<(f64, f64, Vec<Vec<f64>>, Option<Vec<[f64; 2]>>)>) {
+        let mut lines = context.lines();
+        let (k, w) = Self::parse_kpoint_line(lines.next().unwrap());
+
+        let blocksize = nions * norbits;
+        let mut bands: Vec<(f64, f64, Vec<Vec<f64>>, Option<Vec<[f64; 2]>>)> = Vec::with_capacity(nbands);
+        let mut current = Vec::with_capacity(blocksize);
+        let mut nphase_rows = 0;
+        let mut state = BlockState::Header;
+
+        for line in lines {
+            if line.starts_with("band ") {
+                let (eig, occ) = Self::parse_band_line(line);
+                bands.push((eig, occ, vec![], None));
+                state = BlockState::Header;
+                continue;
+            }
+
+            match state {
+                BlockState::Header => if line.starts_with("ion") { state = BlockState::Projections; },
+                BlockState::Projections => {
+                    if line.starts_with("tot") {
+                        assert_eq!(current.len(), blocksize, "Incomplete projection block in PROCAR");
+                        bands.last_mut()
+                            .expect("Projections found before band line in PROCAR")
+                            .2.push(current);
+                        current = Vec::with_capacity(blocksize);
+                    } else if line.starts_with("ion") {
+                        // Phase factors of LORBIT=12 follow the second 'ion' header
+                        bands.last_mut().unwrap().3 = Some(vec![[0.0; 2]; blocksize]);
+                        nphase_rows = 0;
+                        state = BlockState::Phases;
+                    } else if !line.trim().is_empty() {
+                        let len = current.len();
+                        _parse_row(line.as_bytes(), &mut current);
+                        assert!(current.len() - len >= norbits, "Incomplete projection row in PROCAR");
+                        current.truncate(len + norbits);  // 'tot' column is dropped
+                    }
+                },
+                BlockState::Phases => {
+                    if !line.starts_with(' ') || line.trim().is_empty() {
+                        continue;  // 'tot' and 'charge' lines
+                    }
+                    let phases = bands.last_mut().unwrap().3.as_mut().unwrap();
+                    Self::parse_phase_row(line, nphase_rows, norbits, phases);
+                    nphase_rows += 1;
+                },
+            }
+        }
+        assert_eq!(bands.len(), nbands, "Inconsistent number of bands in PROCAR");
+
+        (k, w, bands)
+    }
+
+    // Since vasp 5.4.4 each ion has one row of (re, im) pairs, trailed by the charge:
+    //     "    1 -0.131  0.040   0.000  0.000 ...   charge 0.019"
+    // Older versions write the real and imaginary parts in two rows with the same ion index.
+    fn parse_phase_row(line: &str, irow: usize, norbits: usize, phases: &mut [[f64; 2]]) {
+        let mut tokens = line.split_whitespace();
+        let iion = tokens.next()
+            .and_then(|x| x.parse::<usize>().ok())
+            .expect("Cannot parse ion index of phase factors in PROCAR");
+        assert!(iion >= 1 && iion * norbits <= phases.len(), "Ion index of phase factors out of bound in PROCAR");
+
+        let v = tokens.map_while(_parse_decimal).collect::<Vec<f64>>();
+        let row = &mut phases[(iion - 1) * norbits .. iion * norbits];
+        if v.len() >= 2 * norbits {
+            for (p, c) in row.iter_mut().zip(v.chunks_exact(2)) {
+                *p = [c[0], c[1]];
+            }
+        } else {
+            assert!(v.len() >= norbits, "Incomplete phase factors row in PROCAR");
+            let ipart = irow % 2;  // real part first
+            for (p, x) in row.iter_mut().zip(v.iter()) {
+                p[ipart] = *x;
+            }
+        }
+    }
+
+    fn parse_band_line(line: &str) -> (f64, f64) {
+        // "band     1 # energy  -12.04287893 # occ.  1.00000000"
+        let v = line.split_whitespace()
+            .collect::<Vec<&str>>();
+        assert!(v.len() >= 8 && v[3] == "energy" && v[6] == "occ.", "Invalid band line in PROCAR");
+        let parse = |x: &str| x.parse::<f64>().expect("Cannot parse band energy or occupation as float value");
+        (parse(v[4]), parse(v[7]))
+    }
+}
+
+
+// Parsing state inside one band
+#[derive(Clone, Copy, PartialEq)]
+enum BlockState {
+    Header,       // before the 'ion' header
+    Projections,
+    Phases,       // phase factors of LORBIT=12
+}
+
+
+// Offsets of the lines starting with `pat`
+fn _find_lines(context: &str, pat: &str) -> Vec<usize> {
+    let mut ret = vec![];
+    let mut offset = 0;
+    for line in context.split('\n') {
+        if line.starts_with(pat) {
+            ret.push(offset);
+        }
+        offset += line.len() + 1;
+    }
+    ret
+}
+
+
+// Parses the numbers of one projection row, the leading ion index is skipped. Tokens are
+// scanned and parsed in one pass over the bytes.
+fn _parse_row(line: &[u8], out: &mut Vec<f64>) {
+    let mut i = 0;
+    let mut first = true;
+    while i < line.len() {
+        if line[i] == b' ' { i += 1; continue; }
+        let start = i;
+        while i < line.len() && line[i] != b' ' { i += 1; }
+        if first { first = false; continue; }
+
+        let token = std::str::from_utf8(&line[start .. i]).ok();
+        out.push(token.and_then(_parse_decimal).expect("Cannot parse projections as float values"));
+    }
+}
+
+
+// Fast path for the fixed point numbers like "-0.123", other formats are left to `str::parse`.
+// The result is identical to `str::parse` since both the integer and the power of 10 are exact.
+fn _parse_decimal(s: &str) -> Option<f64> {
+    let bytes = s.as_bytes();
+    let (neg, digits) = match bytes.first() {
+        Some(b'-') => (true, &bytes[1..]),
+        _          => (false, bytes),
+    };
+
+    const POW10: [f64; 16] = [1E0, 1E1, 1E2, 1E3, 1E4, 1E5, 1E6, 1E7,
+                              1E8, 1E9, 1E10, 1E11, 1E12, 1E13, 1E14, 1E15];
+    let mut mantissa = 0u64;
+    let mut nfrac: Option<usize> = None;
+    for (i, c) in digits.iter().enumerate() {
+        match c {
+            b'0' ..= b'9' => mantissa = mantissa * 10 + (c - b'0') as u64,
+            b'.' if nfrac.is_none() => nfrac = Some(digits.len() - i - 1),
+            _ => return s.parse::<f64>().ok(),
+        }
+    }
+
+    match nfrac {
+        Some(n) if n <= 15 && digits.len() <= 17 => {
+            let x = mantissa as f64 / POW10[n];
+            Some(if neg { -x } else { x })
+        },
+        _ => s.parse::<f64>().ok(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dimensions() {
+        let input = r#"PROCAR lm decomposed
+# of k-points:  165         # of bands:   28         # of ions:   2
+"#;
+        assert_eq!(Procar::parse_dimensions(input), (165, 28, 2));
+    }
+
+    #[test]
+    fn test_parse_kpoint_line() {
+        let input = " k-point     3 :   -0.50000000-0.25000000 0.00000000     weight = 0.00606061";
+        assert_eq!(Procar::parse_kpoint_line(input), ([-0.5, -0.25, 0.0], 0.00606061));
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        for x in ["0.123", "-0.500", "12.04287893", "1.0E-03", "7", "0.000"].iter() {
+            assert_eq!(_parse_decimal(x), x.parse::<f64>().ok());
+        }
+        assert_eq!(_parse_decimal("-"), None);
+        assert_eq!(_parse_decimal("***"), None);
+    }
+
+    #[test]
+    fn test_parse_band_line() {
+        let input = "band     1 # energy  -12.04287893 # occ.  1.00000000";
+        assert_eq!(Procar::parse_band_line(input), (-12.04287893, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot parse band energy or occupation as float value")]
+    fn test_parse_band_line_fail() {
+        let input = "band     1 # energy  ************ # occ.  1.00000000";
+        Procar::parse_band_line(input);
+    }
+
+    #[test]
+    fn test_parse_collinear() {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    1         # of bands:   2         # of ions:   2
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -5.00000000 # occ.  1.00000000
+
+ion      s      p      d    tot
+    1  0.100  0.200  0.000  0.300
+    2  0.300  0.000  0.100  0.400
+tot    0.400  0.200  0.100  0.700
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s      p      d    tot
+    1  0.000  0.500  0.000  0.500
+    2  0.000  0.000  0.200  0.200
+tot    0.000  0.500  0.200  0.700
+
+"#;
+        let procar = Procar::parse(input);
+        assert_eq!(procar.nspin, 1);
+        assert!(!procar.lncl);
+        assert_eq!(procar.orbitals, vec!["s", "p", "d"]);
+        assert_eq!(procar.eigvals, vec![-5.0, 2.0]);
+        assert_eq!(procar.occupations, vec![1.0, 0.0]);
+        assert_eq!(procar.band_projections(0, 0, 1), &[0.0, 0.5, 0.0, 0.0, 0.0, 0.2]);
+        assert_eq!(procar.projection(0, 0, 0, 1, 2), 0.1);
+    }
+
+    #[test]
+    fn test_zero_weight_kpoints() {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    2         # of bands:   2         # of ions:   1
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -5.00000000 # occ.  1.00000000
+
+ion      s      p    tot
+    1  0.100  0.200  0.300
+tot    0.100  0.200  0.300
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s      p    tot
+    1  0.000  0.500  0.500
+tot    0.000  0.500  0.500
+
+ k-point     2 :    0.50000000 0.00000000 0.00000000     weight = 0.00000000
+
+band     1 # energy   -4.00000000 # occ.  0.00000000
+
+ion      s      p    tot
+    1  0.300  0.100  0.400
+tot    0.300  0.100  0.400
+
+band     2 # energy    3.00000000 # occ.  0.00000000
+
+ion      s      p    tot
+    1  0.000  0.600  0.600
+tot    0.000  0.600  0.600
+
+"#;
+        let procar = Procar::parse(input);
+        assert_eq!(procar.zero_weight_kpoints(), vec![1]);
+        assert_eq!(procar.highest_occupied(&[0]), Some(-5.0));
+
+        let mut shifted = procar.clone();
+        shifted.apply_scissor(0.0, 1.5);
+        assert_eq!(shifted.eigvals, vec![-5.0, 3.5, -4.0, 4.5]);
+        assert_eq!(procar.band_gap(0.0), Some(6.0));
+        assert_eq!(shifted.band_gap(0.0), Some(7.5));
+
+        let path = procar.select_kpoints(&[1]);
+        assert_eq!(path.nkpts, 1);
+        assert_eq!(path.kpoints, vec![[0.5, 0.0, 0.0]]);
+        assert_eq!(path.eigvals, vec![-4.0, 3.0]);
+        assert_eq!(path.band_projections(0, 0, 1), &[0.0, 0.6]);
+        assert_eq!(path.zero_weight_kpoints(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_parse_phases() {
+        let input = r#"PROCAR lm decomposed + phase
+# of k-points:    1         # of bands:   1         # of ions:   2
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -5.00000000 # occ.  1.00000000
+
+ion      s      p      d    tot
+    1  0.100  0.200  0.000  0.300
+    2  0.300  0.000  0.100  0.400
+tot    0.400  0.200  0.100  0.700
+ion          s             p             d
+    1  0.300 -0.100   0.400  0.200   0.000  0.000   charge 0.300
+    2 -0.500  0.200   0.000  0.000   0.300  0.100   charge 0.400
+charge 0.400  0.200  0.100  0.700
+
+"#;
+        let procar = Procar::parse(input);
+        assert_eq!(procar.band_projections(0, 0, 0), &[0.1, 0.2, 0.0, 0.3, 0.0, 0.1]);
+        assert_eq!(procar.band_phases(0, 0, 0).unwrap(),
+                   &[[0.3, -0.1], [0.4, 0.2], [0.0, 0.0], [-0.5, 0.2], [0.0, 0.0], [0.3, 0.1]]);
+
+        // Real and imaginary parts in separate rows, written by vasp before 5.4.4
+        let input = input.replace("    1  0.300 -0.100   0.400  0.200   0.000  0.000   charge 0.300\n",
+                                  "    1  0.300  0.400  0.000\n    1 -0.100  0.200  0.000\n")
+                         .replace("    2 -0.500  0.200   0.000  0.000   0.300  0.100   charge 0.400\n",
+                                  "    2 -0.500  0.000  0.300\n    2  0.200  0.000  0.100\n");
+        assert_eq!(Procar::parse(&input).phases, procar.phases);
+
+        let procar = Procar::parse(&input[.. input.find("ion          s").unwrap()]);
+        assert_eq!(procar.band_phases(0, 0, 0), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::io;
+use std::fmt;
+use std::path::Path;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::format::Trajectory;
+use crate::layers::_plane_spacing;
+use crate::summary::_volume;
+use crate::constants::{
+    BOLTZMANN,
+    AMU_A2_PER_FS2_TO_EV,
+};
+
+
+// amu/A^3 in g/cm^3
+const AMU_PER_A3_TO_G_PER_CM3: f64 = 1.660_539_066_6;
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProfileBin {
+    pub center       : f64,   // fractional coordinate along the axis
+    pub height       : f64,   // in Angstrom along the normal of the lattice planes
+    pub natoms       : f64,   // average number of atoms in the bin
+    pub density      : f64,   // number density in 1/A^3
+    pub mass_density : f64,   // in g/cm^3
+    pub temperature  : f64,   // in K, NaN if no atom ever visits the bin
+}
+
+
+/// Temperature and density profiles along a lattice vector over an MD trajectory, as in the
+/// non-equilibrium MD of thermal transport. The velocities are the central differences of the
+/// unwrapped positions, with the velocity of the center of mass removed per frame, and the
+/// kinetic temperature of a bin is sum(m v^2) / (3 N kB) over all its atoms and frames. Thus the
+/// first and last frames only serve the finite differences.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Profile {
+    pub axis     : usize,
+    pub nframes  : usize,   // frames with velocities
+    pub bins     : Vec<ProfileBin>,
+    pub fit      : (f64, f64),   // range of the gradient fit, in fractional coordinates
+    pub gradient : f64,     // dT/dz of the linear fit, in K/A
+}
+
+impl Profile {
+    /// `masses` are the masses of all the atoms in amu, only the atoms `iatoms` are binned,
+    /// `dt` is the time between frames in fs.
+    pub fn new(traj: &Trajectory, masses: &[f64], iatoms: &[usize], dt: f64,
+               axis: usize, nbins: usize, fit: (f64, f64)) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        assert!(axis < 3, "Axis should be 0, 1 or 2");
+        if traj.0.len() < 3 {
+            return Err(err(format!("At least 3 frames are required for the velocities, got {}", traj.0.len())));
+        }
+        let natoms = traj.0[0].car_pos.len();
+        if masses.len() != natoms {
+            return Err(err(format!("Got {} masses, but {} atoms in the trajectory", masses.len(), natoms)));
+        }
+        if let Some(i) = iatoms.iter().find(|i| **i >= natoms) {
+            return Err(err(format!("Atom index {} out of bound, {} atoms found", i + 1, natoms)));
+        }
+        if nbins == 0 {
+            return Err(err("At least one bin is required".to_string()));
+        }
+        if !(0.0 <= fit.0 && fit.0 < fit.1 && fit.1 <= 1.0) {
+            return Err(err(format!("Invalid fitting range {:?}, expected 0 <= start < end <= 1", fit)));
+        }
+
+        let unwrapped = traj.unwrapped();
+        let total_mass = masses.iter().sum::<f64>();
+        let nframes = traj.0.len() - 2;
+
+        let mut counts = vec![0.0; nbins];
+        let mut mass = vec![0.0; nbins];
+        let mut twice_ke = vec![0.0; nbins];   // sum of m v^2, in eV
+        let mut volume = 0.0;
+        let mut spacing = 0.0;
+        for t in 1 ..= nframes {
+            let (prev, next) = (&unwrapped.0[t - 1].car_pos, &unwrapped.0[t + 1].car_pos);
+            let vel = prev.iter()
+                .zip(next.iter())
+                .map(|(a, b)| [0, 1, 2].map(|k| (b[k] - a[k]) / (2.0 * dt)))
+                .collect::<Vec<_>>();
+            let vcom = [0, 1, 2].map(|k| vel.iter().zip(masses.iter()).map(|(v, m)| m * v[k]).sum::<f64>() / total_mass);
+
+            let frame = &traj.0[t];
+            volume += _volume(&frame.cell).abs();
+            spacing += _plane_spacing(&frame.cell, axis);
+            for &i in iatoms.iter() {
+                let x = frame.frac_pos[i][axis].rem_euclid(1.0);
+                let ibin = ((x * nbins as f64) as usize).min(nbins - 1);
+                let v2 = (0 .. 3).map(|k| (vel[i][k] - vcom[k]).powi(2)).sum::<f64>();
+                counts[ibin] += 1.0;
+                mass[ibin] += masses[i];
+                twice_ke[ibin] += masses[i] * v2 * AMU_A2_PER_FS2_TO_EV;
+            }
+        }
+        let bin_volume = volume / nframes as f64 / nbins as f64;
+        let spacing = spacing / nframes as f64;
+
+        let bins = (0 .. nbins)
+            .map(|ib| {
+                let center = (ib as f64 + 0.5) / nbins as f64;
+                let natoms = counts[ib] / nframes as f64;
+                ProfileBin {
+                    center,
+                    height: center * spacing,
+                    natoms,
+                    density: natoms / bin_volume,
+                    mass_density: mass[ib] / nframes as f64 / bin_volume * AMU_PER_A3_TO_G_PER_CM3,
+                    temperature: if counts[ib] > 0.0 { twice_ke[ib] / (3.0 * counts[ib] * BOLTZMANN) } else { f64::NAN },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (x, y): (Vec<f64>, Vec<f64>) = bins.iter()
+            .filter(|b| fit.0 <= b.center && b.center <= fit.1 && !b.temperature.is_nan())
+            .map(|b| (b.height, b.temperature))
+            .unzip();
+        if x.len() < 2 {
+            return Err(err(format!("Too few occupied bins in the fitting range {:?} for the gradient", fit)));
+        }
+        let n = x.len() as f64;
+        let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+        let gradient = x.iter().zip(y.iter()).map(|(a, b)| (a - mx) * (b - my)).sum::<f64>()
+            / x.iter().map(|a| (a - mx).powi(2)).sum::<f64>();
+
+        Ok(Self {
+            axis,
+            nframes,
+            bins,
+            fit,
+            gradient,
+        })
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let axis = ["a", "b", "c"][self.axis];
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Profiles along {}", axis),
+                "xaxis": {"title": "Height (A)"},
+                "yaxis": {"title": "Temperature (K)", "domain": [0.52, 1.0]},
+                "yaxis2": {"title": "Density (g/cm^3)", "domain": [0.0, 0.48]},
+            }));
+        let x = self.bins.iter().map(|b| b.height).collect::<Vec<f64>>();
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines+markers",
+            "name": "Temperature",
+            "x": x,
+            "y": self.bins.iter().map(|b| if b.temperature.is_nan() { None } else { Some(b.temperature) }).collect::<Vec<_>>(),
+        }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines+markers",
+            "name": "Density",
+            "x": x,
+            "y": self.bins.iter().map(|b| b.mass_density).collect::<Vec<f64>>(),
+            "yaxis": "y2",
+        }));
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for Profile {
+    fn headers(&self) -> Vec<String> {
+        ["center", "height", "natoms", "density", "mass_density", "temperature"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.bins.iter()
+            .map(|b| vec![
+                format!("{:.4}", b.center),
+                format!("{:.4}", b.height),
+                format!("{:.4}", b.natoms),
+                format!("{:.6}", b.density),
+                format!("{:.4}", b.mass_density),
+                format!("{:.2}", b.temperature),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Profiles along {} averaged over {} frames",
+                                  ["a", "b", "c"][self.axis], self.nframes).bright_green())?;
+        writeln!(f, "{}", format!("  {:>8} {:>10} {:>8} {:>12} {:>10} {:>10}",
+                                  "center", "height/A", "natoms", "n/A^-3", "rho/g/cc", "T/K").bright_green())?;
+        for b in self.bins.iter() {
+            writeln!(f, "  {:8.4} {:10.4} {:8.3} {:12.6} {:10.4} {}",
+                     b.center, b.height, b.natoms, b.density, b.mass_density,
+                     format!("{:10.2}", b.temperature).bright_yellow())?;
+        }
+        writeln!(f, "{}{}", format!("# dT/dz in [{}, {}]: ", self.fit.0, self.fit.1).bright_green(),
+                 format!("{:.4} K/A", self.gradient).bright_yellow())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Structure;
+
+    #[test]
+    fn test_temperature_profile() {
+        // Two H atoms at z = 0.25 and 0.75 moving along x oppositely at 0.01 A/fs, thus the
+        // center of mass keeps still. The second one crosses the cell boundary.
+        let frames = (0 .. 5)
+            .map(|t| {
+                let t = t as f64;
+                let car_pos = vec![[1.0 + 0.01 * t, 0.0, 2.5], [0.02 - 0.01 * t, 0.0, 7.5]];
+                Structure {
+                    cell: [[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]],
+                    ion_types: vec!["H".to_string()],
+                    ions_per_type: vec![2],
+                    frac_pos: car_pos.iter().map(|p| [(p[0] / 10.0).rem_euclid(1.0), p[1] / 10.0, p[2] / 10.0]).collect(),
+                    car_pos,
+                }
+            })
+            .collect();
+        let traj = Trajectory(frames);
+        let profile = Profile::new(&traj, &[1.0, 1.0], &[0, 1], 1.0, 2, 4, (0.0, 1.0)).unwrap();
+        assert_eq!(profile.nframes, 3);
+        assert_eq!(profile.bins.len(), 4);
+
+        let expected = 1.0 * 0.01f64.powi(2) * AMU_A2_PER_FS2_TO_EV / (3.0 * BOLTZMANN);
+        assert!((profile.bins[1].temperature - expected).abs() < 1E-6);
+        assert!((profile.bins[3].temperature - expected).abs() < 1E-6);
+        assert!(profile.bins[0].temperature.is_nan());
+        assert!((profile.bins[1].natoms - 1.0).abs() < 1E-10);
+        assert!((profile.bins[1].density - 1.0 / 250.0).abs() < 1E-10);
+        assert!((profile.bins[1].height - 3.75).abs() < 1E-10);
+        assert!(profile.gradient.abs() < 1E-6);
+
+        assert!(Profile::new(&traj, &[1.0], &[0, 1], 1.0, 2, 4, (0.0, 1.0)).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use indicatif::{
+    ProgressBar,
+    ProgressDrawTarget,
+    ProgressStyle,
+};
+
+
+const TEMPLATE: &str = "{msg} [{bar:30}] {pos}/{len} ({percent}%) {elapsed_precise}";
+
+static HIDDEN: AtomicBool = AtomicBool::new(false);
+
+
+/// Hides all the progress bars created afterwards, used by `-q`.
+pub fn hide() {
+    HIDDEN.store(true, Ordering::Relaxed);
+}
+
+pub fn hidden() -> bool {
+    HIDDEN.load(Ordering::Relaxed)
+}
+
+
+// Progress bar of long-running operations, drawn on stderr by indicatif. It is shared by reference
+// across the rayon workers, and hidden automatically if stderr is not a terminal, thus the logs
+// redirected to files or pipes are kept clean.
+pub struct Progress {
+    bar: ProgressBar,
+}
+
+impl Progress {
+    pub fn new(message: &str, total: usize) -> Self {
+        let target = if hidden() || 0 == total {
+            ProgressDrawTarget::hidden()
+        } else {
+            ProgressDrawTarget::stderr()
+        };
+        Self::with_target(message, total, target)
+    }
+
+    /// Progress counted without drawing anything.
+    pub fn hidden(message: &str, total: usize) -> Self {
+        Self::with_target(message, total, ProgressDrawTarget::hidden())
+    }
+
+    fn with_target(message: &str, total: usize, target: ProgressDrawTarget) -> Self {
+        let style = ProgressStyle::with_template(TEMPLATE)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        let bar = ProgressBar::with_draw_target(Some(total as u64), target)
+            .with_style(style)
+            .with_message(message.to_string());
+        Self { bar }
+    }
+
+    pub fn inc(&self, n: usize) {
+        self.bar.inc(n as u64);
+    }
+
+    pub fn position(&self) -> usize {
+        self.bar.position() as usize
+    }
+
+    /// Clears the bar, the elapsed time is returned in seconds.
+    pub fn finish(self) -> f64 {
+        self.bar.finish_and_clear();
+        self.bar.elapsed().as_secs_f64()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress::hidden("Testing", 1000);
+        (0 .. 1000).into_par_iter().for_each(|_| progress.inc(1));
+        assert_eq!(progress.position(), 1000);
+        assert!(progress.finish() >= 0.0);
+    }
+}
@@ -0,0 +1,232 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use log::info;
+use serde_json::{
+    json,
+    Value,
+};
+use crate::outcar::{
+    Outcar,
+    Mat33,
+};
+use crate::format::Structure;
+use crate::band::BandStructure;
+use crate::dos::Dos;
+use crate::fscorr::_reciprocal;
+use crate::summary::_volume;
+
+
+// Dictionaries following the `as_dict()` of pymatgen, thus the JSON files can be loaded by
+// `from_dict()` (or `MontyDecoder`) of the corresponding classes without custom glue. Spins
+// are keyed by "1" and "-1" as `Spin.up` and `Spin.down` of pymatgen.
+
+/// `pymatgen.core.lattice.Lattice`, with the lattice parameters in Angstrom and degrees.
+pub fn lattice_to_pymatgen(cell: &Mat33<f64>) -> Value {
+    let norm = |a: &[f64; 3]| (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
+    let angle = |a: &[f64; 3], b: &[f64; 3]| {
+        ((a[0] * b[0] + a[1] * b[1] + a[2] * b[2]) / (norm(a) * norm(b))).clamp(-1.0, 1.0).acos().to_degrees()
+    };
+    json!({
+        "@module": "pymatgen.core.lattice",
+        "@class": "Lattice",
+        "matrix": cell,
+        "pbc": [true, true, true],
+        "a": norm(&cell[0]),
+        "b": norm(&cell[1]),
+        "c": norm(&cell[2]),
+        "alpha": angle(&cell[1], &cell[2]),
+        "beta": angle(&cell[0], &cell[2]),
+        "gamma": angle(&cell[0], &cell[1]),
+        "volume": _volume(cell).abs(),
+    })
+}
+
+/// `pymatgen.core.structure.Structure`
+pub fn structure_to_pymatgen(structure: &Structure) -> Value {
+    let sites = structure.symbols().iter()
+        .zip(structure.frac_pos.iter().zip(structure.car_pos.iter()))
+        .map(|(s, (abc, xyz))| json!({
+            "species": [{"element": s, "occu": 1}],
+            "abc": abc,
+            "xyz": xyz,
+            "label": s,
+            "properties": {},
+        }))
+        .collect::<Vec<_>>();
+    json!({
+        "@module": "pymatgen.core.structure",
+        "@class": "Structure",
+        "charge": 0,
+        "lattice": lattice_to_pymatgen(&structure.cell),
+        "properties": {},
+        "sites": sites,
+    })
+}
+
+/// `pymatgen.io.vasp.outputs.Outcar`, with the quantities parsed by rsgrad. The ionic steps are
+/// in the layout of `Vasprun.ionic_steps`, the stress tensors are in kB.
+pub fn outcar_to_pymatgen(outcar: &Outcar) -> Value {
+    let ionic_steps = outcar.ion_iters.iter()
+        .enumerate()
+        .map(|(i, it)| {
+            let s = it.stress_tensor;
+            json!({
+                "e_fr_energy": it.toten,
+                "e_0_energy": it.toten_z,
+                "forces": it.forces,
+                "stress": [[s[0], s[3], s[5]], [s[3], s[1], s[4]], [s[5], s[4], s[2]]],
+                "structure": structure_to_pymatgen(&outcar.get_structure_cloned(i + 1)),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Orbital moments of each ion, vectors for noncollinear calculations
+    let magnetization = outcar.ion_magmoms.as_ref().map(|m| {
+        let labels = m.orbitals.iter().cloned().chain(std::iter::once("tot".to_string())).collect::<Vec<_>>();
+        (0 .. m.moments[0].len())
+            .map(|ion| {
+                labels.iter()
+                    .enumerate()
+                    .map(|(io, l)| {
+                        let v = m.moments.iter().map(|comp| comp[ion][io]).collect::<Vec<f64>>();
+                        (l.clone(), if v.len() == 1 { json!(v[0]) } else { json!(v) })
+                    })
+                    .collect::<serde_json::Map<String, Value>>()
+            })
+            .collect::<Vec<_>>()
+    });
+    let total_magnetization = outcar.ion_iters.last()
+        .and_then(|it| it.magmom.as_ref())
+        .map(|m| if m.len() == 1 { json!(m[0]) } else { json!(m) });
+
+    let last = outcar.ion_iters.last();
+    json!({
+        "@module": "pymatgen.io.vasp.outputs",
+        "@class": "Outcar",
+        "efermi": outcar.efermi,
+        "nelect": outcar.nelect,
+        "final_energy": last.map(|it| it.toten_z),
+        "final_fr_energy": last.map(|it| it.toten),
+        "final_energy_per_atom": last.map(|it| it.toten_z / outcar.nions as f64),
+        "magnetization": magnetization.unwrap_or_default(),
+        "total_magnetization": total_magnetization,
+        "electrostatic_potential": outcar.core_pots,
+        "is_stopped": false,
+        "ionic_steps": ionic_steps,
+    })
+}
+
+/// `pymatgen.electronic_structure.bandstructure.BandStructureSymmLine`, the lattice is required
+/// for the reciprocal lattice (with 2pi). The energies are absolute, as in pymatgen.
+pub fn band_structure_to_pymatgen(bands: &BandStructure, structure: &Structure) -> Value {
+    let spin_keys = ["1", "-1"];
+    let energies = bands.eigvals.iter()
+        .zip(spin_keys.iter())
+        .map(|(spin, key)| {
+            let spin = spin.iter()
+                .map(|band| band.iter().map(|e| e + bands.efermi).collect::<Vec<f64>>())
+                .collect::<Vec<_>>();
+            (key.to_string(), json!(spin))
+        })
+        .collect::<serde_json::Map<String, Value>>();
+
+    // Labels joined by '|' at discontinuities belong to the two ends of the jump
+    let mut labels_dict = serde_json::Map::new();
+    for (ik, label) in bands.ticks.iter() {
+        for (i, l) in label.split('|').enumerate() {
+            if let Some(k) = bands.kpoints.get(ik + i) {
+                labels_dict.insert(_pymatgen_label(l), json!(k));
+            }
+        }
+    }
+
+    json!({
+        "@module": "pymatgen.electronic_structure.bandstructure",
+        "@class": "BandStructureSymmLine",
+        "lattice_rec": lattice_to_pymatgen(&_reciprocal(&structure.cell)),
+        "efermi": bands.efermi,
+        "kpoints": bands.kpoints,
+        "bands": energies,
+        "labels_dict": labels_dict,
+        "is_spin_polarized": bands.eigvals.len() == 2,
+        "projections": {},
+        "structure": structure_to_pymatgen(structure),
+    })
+}
+
+/// `pymatgen.electronic_structure.dos.Dos` of the total DOS, with absolute energies. The
+/// projected DOS of the selections are appended as "pdos" keyed by the labels, in the same
+/// layout as "densities", which is ignored by pymatgen.
+pub fn dos_to_pymatgen(dos: &Dos) -> Value {
+    let densities = |curves: &[Vec<f64>]| curves.iter()
+        .zip(["1", "-1"].iter())
+        .map(|(c, key)| (key.to_string(), json!(c)))
+        .collect::<serde_json::Map<String, Value>>();
+    json!({
+        "@module": "pymatgen.electronic_structure.dos",
+        "@class": "Dos",
+        "efermi": dos.efermi,
+        "energies": dos.energies.iter().map(|e| e + dos.efermi).collect::<Vec<f64>>(),
+        "densities": densities(&dos.total),
+        "pdos": dos.pdos.iter()
+            .map(|(label, curves)| (label.clone(), Value::Object(densities(curves))))
+            .collect::<serde_json::Map<String, Value>>(),
+    })
+}
+
+pub fn save_pymatgen_json(value: &Value, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+    info!("Saving pymatgen compatible JSON to {:?} ...", path.as_ref());
+    let mut f = fs::File::create(path)?;
+    writeln!(f, "{}", serde_json::to_string(value)?)
+}
+
+// High symmetry points are written as "\\Gamma" etc. in pymatgen
+fn _pymatgen_label(label: &str) -> String {
+    match label.trim().to_uppercase().as_str() {
+        "G" | "GAMMA" | "\\GAMMA" | "Γ" => "\\Gamma".to_string(),
+        _ => label.trim().to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structure_to_pymatgen() {
+        let structure = Structure {
+            cell: [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 4.0]],
+            ion_types: vec!["Ti".to_string(), "O".to_string()],
+            ions_per_type: vec![1, 1],
+            car_pos: vec![[0.0; 3], [1.5, 1.5, 2.0]],
+            frac_pos: vec![[0.0; 3], [0.5, 0.5, 0.5]],
+        };
+        let d = structure_to_pymatgen(&structure);
+        assert_eq!(d["@class"], "Structure");
+        assert_eq!(d["lattice"]["c"], 4.0);
+        assert_eq!(d["lattice"]["gamma"], 90.0);
+        assert_eq!(d["lattice"]["volume"], 36.0);
+        assert_eq!(d["sites"][1]["species"][0]["element"], "O");
+        assert_eq!(d["sites"][1]["abc"], json!([0.5, 0.5, 0.5]));
+
+        let bands = BandStructure {
+            kpoints: vec![[0.0; 3], [0.5, 0.0, 0.0], [0.5, 0.5, 0.0], [0.0; 3]],
+            kdist: vec![0.0, 1.0, 1.0, 2.0],
+            efermi: 1.0,
+            eigvals: vec![vec![vec![-1.0, 0.0, 0.5, -1.0]]],
+            order: vec![],
+            ticks: vec![(0, "G".to_string()), (1, "X|M".to_string()), (3, "G".to_string())],
+            projections: vec![],
+        };
+        let d = band_structure_to_pymatgen(&bands, &structure);
+        assert_eq!(d["bands"]["1"], json!([[0.0, 1.0, 1.5, 0.0]]));
+        assert_eq!(d["labels_dict"]["\\Gamma"], json!([0.0, 0.0, 0.0]));
+        assert_eq!(d["labels_dict"]["M"], json!([0.5, 0.5, 0.0]));
+        assert_eq!(d["is_spin_polarized"], false);
+        let b = d["lattice_rec"]["a"].as_f64().unwrap();
+        assert!((b - 2.0 * std::f64::consts::PI / 3.0).abs() < 1E-10);
+    }
+}
@@ -0,0 +1,529 @@
+// Software rasterizer of the static images in PNG format. The primitives of `plot::Canvas` are
+// drawn with anti-aliasing, and the text in a built-in 5x7 bitmap font scaled by whole pixels,
+// so no system fonts or external tools are needed.
+
+use serde_json::Value;
+use crate::plot::{
+    Anchor,
+    Canvas,
+    _dash_pattern,
+};
+
+
+// Glyphs of 5 columns and 8 rows, the 7th row sits on the baseline and the 8th is the descender
+const FONT: [(char, [&str; 8]); 103] = [
+    (' ',  [".....", ".....", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('!',  ["..#..", "..#..", "..#..", "..#..", "..#..", ".....", "..#..", "....."]),
+    ('"',  [".#.#.", ".#.#.", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('#',  [".#.#.", ".#.#.", "#####", ".#.#.", "#####", ".#.#.", ".#.#.", "....."]),
+    ('$',  ["..#..", ".####", "#.#..", ".###.", "..#.#", "####.", "..#..", "....."]),
+    ('%',  ["##...", "##..#", "...#.", "..#..", ".#...", "#..##", "...##", "....."]),
+    ('&',  [".##..", "#..#.", "#.#..", ".#...", "#.#.#", "#..#.", ".##.#", "....."]),
+    ('\'', ["..#..", "..#..", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('(',  ["...#.", "..#..", ".#...", ".#...", ".#...", "..#..", "...#.", "....."]),
+    (')',  [".#...", "..#..", "...#.", "...#.", "...#.", "..#..", ".#...", "....."]),
+    ('*',  [".....", "..#..", "#.#.#", ".###.", "#.#.#", "..#..", ".....", "....."]),
+    ('+',  [".....", "..#..", "..#..", "#####", "..#..", "..#..", ".....", "....."]),
+    (',',  [".....", ".....", ".....", ".....", ".....", "..#..", "..#..", ".#..."]),
+    ('-',  [".....", ".....", ".....", "#####", ".....", ".....", ".....", "....."]),
+    ('.',  [".....", ".....", ".....", ".....", ".....", ".....", "..#..", "....."]),
+    ('/',  [".....", "....#", "...#.", "..#..", ".#...", "#....", ".....", "....."]),
+    ('0',  [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###.", "....."]),
+    ('1',  ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###.", "....."]),
+    ('2',  [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####", "....."]),
+    ('3',  ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###.", "....."]),
+    ('4',  ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#.", "....."]),
+    ('5',  ["#####", "#....", "####.", "....#", "....#", "#...#", ".###.", "....."]),
+    ('6',  ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###.", "....."]),
+    ('7',  ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#...", "....."]),
+    ('8',  [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###.", "....."]),
+    ('9',  [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##..", "....."]),
+    (':',  [".....", ".....", "..#..", ".....", ".....", "..#..", ".....", "....."]),
+    (';',  [".....", ".....", "..#..", ".....", ".....", "..#..", "..#..", ".#..."]),
+    ('<',  ["...#.", "..#..", ".#...", "#....", ".#...", "..#..", "...#.", "....."]),
+    ('=',  [".....", ".....", "#####", ".....", "#####", ".....", ".....", "....."]),
+    ('>',  [".#...", "..#..", "...#.", "....#", "...#.", "..#..", ".#...", "....."]),
+    ('?',  [".###.", "#...#", "....#", "...#.", "..#..", ".....", "..#..", "....."]),
+    ('@',  [".###.", "#...#", "....#", ".##.#", "#.#.#", "#.#.#", ".###.", "....."]),
+    ('A',  [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#", "....."]),
+    ('B',  ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####.", "....."]),
+    ('C',  [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###.", "....."]),
+    ('D',  ["###..", "#..#.", "#...#", "#...#", "#...#", "#..#.", "###..", "....."]),
+    ('E',  ["#####", "#....", "#....", "####.", "#....", "#....", "#####", "....."]),
+    ('F',  ["#####", "#....", "#....", "####.", "#....", "#....", "#....", "....."]),
+    ('G',  [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####", "....."]),
+    ('H',  ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#", "....."]),
+    ('I',  [".###.", "..#..", "..#..", "..#..", "..#..", "..#..", ".###.", "....."]),
+    ('J',  ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##..", "....."]),
+    ('K',  ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#", "....."]),
+    ('L',  ["#....", "#....", "#....", "#....", "#....", "#....", "#####", "....."]),
+    ('M',  ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#", "....."]),
+    ('N',  ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#", "....."]),
+    ('O',  [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###.", "....."]),
+    ('P',  ["####.", "#...#", "#...#", "####.", "#....", "#....", "#....", "....."]),
+    ('Q',  [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#", "....."]),
+    ('R',  ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#", "....."]),
+    ('S',  [".####", "#....", "#....", ".###.", "....#", "....#", "####.", "....."]),
+    ('T',  ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#..", "....."]),
+    ('U',  ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###.", "....."]),
+    ('V',  ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#..", "....."]),
+    ('W',  ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#.", "....."]),
+    ('X',  ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#", "....."]),
+    ('Y',  ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#..", "....."]),
+    ('Z',  ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####", "....."]),
+    ('[',  [".###.", ".#...", ".#...", ".#...", ".#...", ".#...", ".###.", "....."]),
+    ('\\', [".....", "#....", ".#...", "..#..", "...#.", "....#", ".....", "....."]),
+    (']',  [".###.", "...#.", "...#.", "...#.", "...#.", "...#.", ".###.", "....."]),
+    ('^',  ["..#..", ".#.#.", "#...#", ".....", ".....", ".....", ".....", "....."]),
+    ('_',  [".....", ".....", ".....", ".....", ".....", ".....", ".....", "#####"]),
+    ('`',  [".#...", "..#..", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('a',  [".....", ".....", ".###.", "....#", ".####", "#...#", ".####", "....."]),
+    ('b',  ["#....", "#....", "#.##.", "##..#", "#...#", "#...#", "####.", "....."]),
+    ('c',  [".....", ".....", ".###.", "#....", "#....", "#...#", ".###.", "....."]),
+    ('d',  ["....#", "....#", ".##.#", "#..##", "#...#", "#...#", ".####", "....."]),
+    ('e',  [".....", ".....", ".###.", "#...#", "#####", "#....", ".###.", "....."]),
+    ('f',  ["..##.", ".#..#", ".#...", "###..", ".#...", ".#...", ".#...", "....."]),
+    ('g',  [".....", ".....", ".####", "#...#", "#...#", ".####", "....#", ".###."]),
+    ('h',  ["#....", "#....", "#.##.", "##..#", "#...#", "#...#", "#...#", "....."]),
+    ('i',  ["..#..", ".....", ".##..", "..#..", "..#..", "..#..", ".###.", "....."]),
+    ('j',  ["...#.", ".....", "..##.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('k',  ["#....", "#....", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "....."]),
+    ('l',  [".##..", "..#..", "..#..", "..#..", "..#..", "..#..", ".###.", "....."]),
+    ('m',  [".....", ".....", "##.#.", "#.#.#", "#.#.#", "#...#", "#...#", "....."]),
+    ('n',  [".....", ".....", "#.##.", "##..#", "#...#", "#...#", "#...#", "....."]),
+    ('o',  [".....", ".....", ".###.", "#...#", "#...#", "#...#", ".###.", "....."]),
+    ('p',  [".....", ".....", "####.", "#...#", "#...#", "####.", "#....", "#...."]),
+    ('q',  [".....", ".....", ".####", "#...#", "#...#", ".####", "....#", "....#"]),
+    ('r',  [".....", ".....", "#.##.", "##..#", "#....", "#....", "#....", "....."]),
+    ('s',  [".....", ".....", ".####", "#....", ".###.", "....#", "####.", "....."]),
+    ('t',  [".#...", ".#...", "###..", ".#...", ".#...", ".#..#", "..##.", "....."]),
+    ('u',  [".....", ".....", "#...#", "#...#", "#...#", "#..##", ".##.#", "....."]),
+    ('v',  [".....", ".....", "#...#", "#...#", "#...#", ".#.#.", "..#..", "....."]),
+    ('w',  [".....", ".....", "#...#", "#...#", "#.#.#", "#.#.#", ".#.#.", "....."]),
+    ('x',  [".....", ".....", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "....."]),
+    ('y',  [".....", ".....", "#...#", "#...#", "#...#", ".####", "....#", ".###."]),
+    ('z',  [".....", ".....", "#####", "...#.", "..#..", ".#...", "#####", "....."]),
+    ('{',  ["...#.", "..#..", "..#..", ".#...", "..#..", "..#..", "...#.", "....."]),
+    ('|',  ["..#..", "..#..", "..#..", "..#..", "..#..", "..#..", "..#..", "....."]),
+    ('}',  [".#...", "..#..", "..#..", "...#.", "..#..", "..#..", ".#...", "....."]),
+    ('~',  [".....", ".....", ".#...", "#.#.#", "...#.", ".....", ".....", "....."]),
+    ('Γ',  ["#####", "#....", "#....", "#....", "#....", "#....", "#....", "....."]),
+    ('Δ',  ["..#..", "..#..", ".#.#.", ".#.#.", "#...#", "#...#", "#####", "....."]),
+    ('Λ',  ["..#..", "..#..", ".#.#.", ".#.#.", "#...#", "#...#", "#...#", "....."]),
+    ('Π',  ["#####", ".#.#.", ".#.#.", ".#.#.", ".#.#.", ".#.#.", ".#.#.", "....."]),
+    ('Σ',  ["#####", "#....", ".#...", "..#..", ".#...", "#....", "#####", "....."]),
+    ('Ω',  [".###.", "#...#", "#...#", "#...#", ".#.#.", ".#.#.", "##.##", "....."]),
+    ('Å',  ["..#..", ".#.#.", ".###.", "#...#", "#####", "#...#", "#...#", "....."]),
+    ('μ',  [".....", ".....", "#...#", "#...#", "#...#", "##..#", "#.##.", "#...."]),
+];
+
+// Drawn for the characters missing in the font
+const MISSING: [&str; 8] = ["#####", "#...#", "#...#", "#...#", "#...#", "#...#", "#####", "....."];
+
+
+/// RGB image of the plots, drawn in points and stored in pixels.
+#[derive(Clone, Debug)]
+pub struct Raster {
+    width     : usize,
+    height    : usize,
+    dpi       : f64,
+    font_size : f64,            // in points, of the text without explicit sizes
+    pixels    : Vec<[f32; 3]>,  // row by row from the top
+    clip      : [f64; 4],       // x0, y0, x1 and y1 in pixels
+}
+
+impl Raster {
+    /// White image of `width` x `height` in points at `dpi`.
+    pub fn new(width: f64, height: f64, dpi: f64, font_size: f64) -> Self {
+        let scale = dpi / 72.0;
+        let w = (width * scale).round().max(1.0) as usize;
+        let h = (height * scale).round().max(1.0) as usize;
+        Self {
+            width: w,
+            height: h,
+            dpi,
+            font_size,
+            pixels: vec![[1.0; 3]; w * h],
+            clip: [0.0, 0.0, w as f64, h as f64],
+        }
+    }
+
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+
+    /// Color of pixel (`i`, `j`) from the top-left corner, in 0 ~ 255.
+    pub fn pixel(&self, i: usize, j: usize) -> [u8; 3] {
+        let p = self.pixels[j * self.width + i];
+        [_to_u8(p[0]), _to_u8(p[1]), _to_u8(p[2])]
+    }
+
+    /// Encodes the image in PNG, 8-bit RGB with the resolution of `dpi` recorded.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            raw.push(0);  // no filter
+            row.iter().for_each(|p| raw.extend(p.iter().map(|&c| _to_u8(c))));
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);  // bit depth, truecolor, deflate, no filter, no interlace
+
+        let ppm = ((self.dpi / 0.0254).round() as u32).to_be_bytes();  // pixels per meter
+        let mut phys = Vec::with_capacity(9);
+        phys.extend(ppm);
+        phys.extend(ppm);
+        phys.push(1);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        _write_chunk(&mut png, b"IHDR", &header);
+        _write_chunk(&mut png, b"pHYs", &phys);
+        _write_chunk(&mut png, b"IDAT", &miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6));
+        _write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn _scale(&self) -> f64 {
+        self.dpi / 72.0
+    }
+
+    // Blends `color` with coverage `alpha` into pixel (i, j) inside the clip area
+    fn _blend(&mut self, i: i64, j: i64, color: [f32; 3], alpha: f64) {
+        if alpha <= 0.0 || i < 0 || j < 0 || i as usize >= self.width || j as usize >= self.height {
+            return;
+        }
+        let (x, y) = (i as f64 + 0.5, j as f64 + 0.5);
+        if x < self.clip[0] || x > self.clip[2] || y < self.clip[1] || y > self.clip[3] {
+            return;
+        }
+        let a = alpha.min(1.0) as f32;
+        let p = &mut self.pixels[j as usize * self.width + i as usize];
+        p.iter_mut()
+            .zip(color.iter())
+            .for_each(|(p, c)| *p = *p * (1.0 - a) + c * a);
+    }
+
+    // Segment from `a` to `b` with round caps, all in pixels. Dashes of `dash` start at `offset`
+    // along the line.
+    fn _segment(&mut self, a: (f64, f64), b: (f64, f64), color: [f32; 3], width: f64,
+                dash: Option<(f64, f64)>, offset: f64) {
+        let hw = (width / 2.0).max(0.5);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len2 = dx * dx + dy * dy;
+        let len = len2.sqrt();
+        let imin = (a.0.min(b.0) - hw - 1.0).floor().max(0.0) as i64;
+        let imax = (a.0.max(b.0) + hw + 1.0).ceil().min(self.width as f64) as i64;
+        let jmin = (a.1.min(b.1) - hw - 1.0).floor().max(0.0) as i64;
+        let jmax = (a.1.max(b.1) + hw + 1.0).ceil().min(self.height as f64) as i64;
+
+        for j in jmin .. jmax {
+            for i in imin .. imax {
+                let (px, py) = (i as f64 + 0.5, j as f64 + 0.5);
+                let t = if len2 > 0.0 {
+                    (((px - a.0) * dx + (py - a.1) * dy) / len2).clamp(0.0, 1.0)
+                } else { 0.0 };
+                if let Some((on, off)) = dash {
+                    if (offset + t * len).rem_euclid(on + off) > on {
+                        continue;
+                    }
+                }
+                let d = (px - a.0 - t * dx).hypot(py - a.1 - t * dy);
+                let coverage = (hw + 0.5 - d).clamp(0.0, 1.0) * width.min(1.0);
+                self._blend(i, j, color, coverage);
+            }
+        }
+    }
+
+    // Square of `size` pixels in the glyphs, at `u` along and `v` below the text from `origin`,
+    // rotated counterclockwise for the vertical text
+    fn _glyph_block(&mut self, origin: (f64, f64), u: f64, v: f64, size: f64, vertical: bool, color: [f32; 3]) {
+        let (x, y) = if vertical { (origin.0 + v, origin.1 - u - size) } else { (origin.0 + u, origin.1 + v) };
+        let (x, y) = (x.round() as i64, y.round() as i64);
+        let n = size as i64;
+        for j in y .. y + n {
+            for i in x .. x + n {
+                self._blend(i, j, color, 1.0);
+            }
+        }
+    }
+}
+
+
+impl Canvas for Raster {
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), color: &str, width: f64, dash: &Value) {
+        let s = self._scale();
+        let dash = _dash_pattern(dash).map(|(on, off)| (on * s, off * s));
+        self._segment((from.0 * s, from.1 * s), (to.0 * s, to.1 * s), _parse_color(color), width * s, dash, 0.0);
+    }
+
+    fn path(&mut self, segments: &[Vec<(f64, f64)>], color: &str, width: f64, dash: &Value) {
+        let s = self._scale();
+        let dash = _dash_pattern(dash).map(|(on, off)| (on * s, off * s));
+        let color = _parse_color(color);
+        for seg in segments.iter() {
+            let mut offset = 0.0;
+            for pair in seg.windows(2) {
+                let a = (pair[0].0 * s, pair[0].1 * s);
+                let b = (pair[1].0 * s, pair[1].1 * s);
+                self._segment(a, b, color, width * s, dash, offset);
+                offset += (b.0 - a.0).hypot(b.1 - a.1);
+            }
+        }
+    }
+
+    fn fill(&mut self, polygon: &[(f64, f64)], color: &str, opacity: f64) {
+        if polygon.len() < 3 {
+            return;
+        }
+        const NSUB: usize = 4;  // sub-scanlines per row for anti-aliasing
+        let s = self._scale();
+        let color = _parse_color(color);
+        let points = polygon.iter().map(|(x, y)| (x * s, y * s)).collect::<Vec<_>>();
+        let edges = points.iter()
+            .zip(points.iter().cycle().skip(1))
+            .collect::<Vec<_>>();
+        let jmin = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min).floor().max(0.0) as usize;
+        let jmax = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max).ceil().min(self.height as f64) as usize;
+
+        let mut coverage = vec![0.0; self.width + 1];
+        let mut crossings = vec![];
+        for j in jmin .. jmax {
+            coverage.iter_mut().for_each(|c| *c = 0.0);
+            for sub in 0 .. NSUB {
+                let y = j as f64 + (sub as f64 + 0.5) / NSUB as f64;
+                crossings.clear();
+                for (a, b) in edges.iter() {
+                    if (a.1 <= y) != (b.1 <= y) {
+                        crossings.push(a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1));
+                    }
+                }
+                crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+                // Even-odd rule, the partially covered pixels at both ends get the fractions
+                for span in crossings.chunks_exact(2) {
+                    let xa = span[0].clamp(0.0, self.width as f64);
+                    let xb = span[1].clamp(0.0, self.width as f64);
+                    let first = xa.floor() as usize;
+                    let last = (xb.ceil() as usize).min(self.width);
+                    for (i, c) in coverage.iter_mut().enumerate().take(last).skip(first) {
+                        let overlap = xb.min(i as f64 + 1.0) - xa.max(i as f64);
+                        *c += overlap.max(0.0) / NSUB as f64;
+                    }
+                }
+            }
+            for (i, c) in coverage.iter().enumerate().filter(|(_, c)| **c > 0.0) {
+                self._blend(i as i64, j as i64, color, c * opacity);
+            }
+        }
+    }
+
+    fn circle(&mut self, center: (f64, f64), r: f64, color: &str, opacity: f64) {
+        let s = self._scale();
+        let (cx, cy, r) = (center.0 * s, center.1 * s, r * s);
+        let color = _parse_color(color);
+        for j in (cy - r - 1.0).floor() as i64 .. (cy + r + 1.0).ceil() as i64 {
+            for i in (cx - r - 1.0).floor() as i64 .. (cx + r + 1.0).ceil() as i64 {
+                let d = (i as f64 + 0.5 - cx).hypot(j as f64 + 0.5 - cy);
+                self._blend(i, j, color, (r + 0.5 - d).clamp(0.0, 1.0) * opacity);
+            }
+        }
+    }
+
+    fn text(&mut self, at: (f64, f64), text: &str, anchor: Anchor, size: Option<f64>, vertical: bool) {
+        // Capitals are 7 pixels tall in the font and about 0.7 of the font size
+        let s = self._scale();
+        let block = (size.unwrap_or(self.font_size) * s * 0.1).round().max(1.0);
+        let nchars = text.chars().count() as f64;
+        let width = (6.0 * nchars - 1.0).max(0.0) * block;
+        let start = match anchor {
+            Anchor::Start  => 0.0,
+            Anchor::Middle => -width / 2.0,
+            Anchor::End    => -width,
+        };
+        let origin = (at.0 * s, at.1 * s);
+        let black = [0.0; 3];
+        for (k, c) in text.chars().enumerate() {
+            let glyph = FONT.iter()
+                .find(|g| g.0 == c)
+                .map(|g| &g.1)
+                .unwrap_or(&MISSING);
+            for (row, line) in glyph.iter().enumerate() {
+                for (col, _) in line.chars().enumerate().filter(|(_, p)| *p == '#') {
+                    let u = start + (6.0 * k as f64 + col as f64) * block;
+                    let v = (row as f64 - 7.0) * block;
+                    self._glyph_block(origin, u, v, block, vertical, black);
+                }
+            }
+        }
+    }
+
+    fn rect(&mut self, area: [f64; 4]) {
+        let [x, y, w, h] = area;
+        let solid = Value::Null;
+        self.line((x, y), (x + w, y), "black", 1.0, &solid);
+        self.line((x + w, y), (x + w, y + h), "black", 1.0, &solid);
+        self.line((x + w, y + h), (x, y + h), "black", 1.0, &solid);
+        self.line((x, y + h), (x, y), "black", 1.0, &solid);
+    }
+
+    fn clip(&mut self, area: Option<[f64; 4]>) {
+        let s = self._scale();
+        self.clip = match area {
+            Some([x, y, w, h]) => [x * s, y * s, (x + w) * s, (y + h) * s],
+            None => [0.0, 0.0, self.width as f64, self.height as f64],
+        };
+    }
+}
+
+
+fn _to_u8(c: f32) -> u8 {
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+
+// Colors like "#1f77b4", "#f00", "rgb(31, 119, 180)" and the basic CSS names, black otherwise
+fn _parse_color(color: &str) -> [f32; 3] {
+    let color = color.trim().to_lowercase();
+    let hex = |s: &str| u8::from_str_radix(s, 16).ok().map(|v| v as f32 / 255.0);
+    if let Some(h) = color.strip_prefix('#') {
+        let rgb = match h.len() {
+            6 => [hex(&h[0..2]), hex(&h[2..4]), hex(&h[4..6])],
+            3 => [hex(&h[0..1].repeat(2)), hex(&h[1..2].repeat(2)), hex(&h[2..3].repeat(2))],
+            _ => [None; 3],
+        };
+        if let [Some(r), Some(g), Some(b)] = rgb {
+            return [r, g, b];
+        }
+    }
+    if let Some(args) = color.strip_prefix("rgba(").or_else(|| color.strip_prefix("rgb(")) {
+        let v = args.trim_end_matches(')')
+            .split(',')
+            .take(3)
+            .filter_map(|x| x.trim().parse::<f32>().ok())
+            .collect::<Vec<_>>();
+        if v.len() == 3 {
+            return [v[0] / 255.0, v[1] / 255.0, v[2] / 255.0];
+        }
+    }
+    let hex = match color.as_str() {
+        "white"                  => "#ffffff",
+        "gray" | "grey"          => "#808080",
+        "lightgray" | "lightgrey" => "#d3d3d3",
+        "darkgray" | "darkgrey"  => "#a9a9a9",
+        "silver"                 => "#c0c0c0",
+        "red"                    => "#ff0000",
+        "green"                  => "#008000",
+        "lime"                   => "#00ff00",
+        "blue"                   => "#0000ff",
+        "navy"                   => "#000080",
+        "orange"                 => "#ffa500",
+        "purple"                 => "#800080",
+        "brown"                  => "#a52a2a",
+        "pink"                   => "#ffc0cb",
+        "olive"                  => "#808000",
+        "cyan"                   => "#00ffff",
+        "teal"                   => "#008080",
+        "magenta"                => "#ff00ff",
+        "yellow"                 => "#ffff00",
+        "maroon"                 => "#800000",
+        _                        => return [0.0; 3],
+    };
+    _parse_color(hex)
+}
+
+
+fn _write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(_crc32(kind.iter().chain(data.iter())).to_be_bytes());
+}
+
+
+// CRC-32 with the polynomial 0xEDB88320 of ISO 3309, of the PNG chunks and gzip members
+pub(crate) fn _crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    !bytes.fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+const CRC_TABLE: [u32; 256] = _crc_table();
+
+const fn _crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { (c >> 1) ^ 0xEDB8_8320 } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use itertools::Itertools;
+    use serde_json::json;
+
+    #[test]
+    fn test_font() {
+        for (c, glyph) in FONT.iter() {
+            assert!(glyph.iter().all(|row| row.len() == 5 && row.chars().all(|p| p == '#' || p == '.')), "{}", c);
+        }
+        assert_eq!(FONT.iter().map(|g| g.0).unique().count(), FONT.len());
+        assert_eq!(_crc32(b"IEND".iter()), 0xAE42_6082);
+        assert_eq!(_parse_color("#1f77b4"), [31.0 / 255.0, 119.0 / 255.0, 180.0 / 255.0]);
+        assert_eq!(_parse_color("#f00"), [1.0, 0.0, 0.0]);
+        assert_eq!(_parse_color("rgba(0, 0, 255, 0.5)"), [0.0, 0.0, 1.0]);
+        assert_eq!(_parse_color("gray"), _parse_color("#808080"));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut r = Raster::new(100.0, 50.0, 144.0, 10.0);
+        assert_eq!((r.width(), r.height()), (200, 100));
+        let solid = Value::Null;
+
+        r.line((10.0, 10.0), (90.0, 10.0), "#ff0000", 2.0, &solid);
+        assert_eq!(r.pixel(100, 19), [255, 0, 0]);
+        assert_eq!(r.pixel(100, 30), [255, 255, 255]);
+
+        // Dashes of 6 and gaps of 4 points, i.e. 12 and 8 pixels
+        r.line((0.0, 20.0), (100.0, 20.0), "black", 1.0, &json!("dash"));
+        assert_eq!(r.pixel(5, 39), [0, 0, 0]);
+        assert_eq!(r.pixel(15, 39), [255, 255, 255]);
+
+        // Half transparent blue square, clipped at the right half
+        r.clip(Some([0.0, 0.0, 40.0, 50.0]));
+        r.fill(&[(30.0, 30.0), (50.0, 30.0), (50.0, 40.0), (30.0, 40.0)], "blue", 0.5);
+        r.circle((80.0, 40.0), 5.0, "black", 1.0);
+        r.clip(None);
+        assert_eq!(r.pixel(70, 70), [128, 128, 255]);
+        assert_eq!(r.pixel(90, 70), [255, 255, 255]);
+        assert_eq!(r.pixel(160, 80), [255, 255, 255]);
+
+        r.text((60.0, 48.0), "Γ", Anchor::Start, None, false);
+        assert_eq!(r.pixel(121, 83), [0, 0, 0]);
+
+        let png = r.to_png();
+        assert_eq!(&png[.. 8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12 .. 16], b"IHDR");
+        assert_eq!(&png[16 .. 24], [0, 0, 0, 200, 0, 0, 0, 100]);
+        assert_eq!(&png[png.len() - 12 ..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        // Chunks: IHDR of 13 bytes and pHYs of 9, then IDAT
+        let idat = 8 + 25 + 21;
+        let len = u32::from_be_bytes(png[idat .. idat + 4].try_into().unwrap()) as usize;
+        assert_eq!(&png[idat + 4 .. idat + 8], b"IDAT");
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&png[idat + 8 .. idat + 8 + len]).unwrap();
+        assert_eq!(raw.len(), (200 * 3 + 1) * 100);
+        assert_eq!(&raw[19 * 601 + 1 + 300 .. 19 * 601 + 1 + 303], [255, 0, 0]);
+    }
+}
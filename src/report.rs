@@ -0,0 +1,318 @@
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+
+
+// One coordinate in ICONST, e.g. "R 1 5 0" for the distance between atoms 1 and 5
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Coordinate {
+    pub kind   : String,    // R, A, T, M, B, S, C, ...
+    pub atoms  : Vec<i32>,  // atom indices or coefficients, as written in ICONST
+    pub status : i32,       // 0 for constrained, 5 for metadynamics, 7 for monitored only
+}
+
+impl Coordinate {
+    pub fn label(&self) -> String {
+        let atoms = self.atoms.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+        format!("{}({})", self.kind, atoms)
+    }
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Iconst(pub Vec<Coordinate>);
+
+impl Iconst {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    /// The first item of each line is the type of the coordinate, and the last one is STATUS.
+    /// Empty lines and comments starting with '#' or '!' are skipped.
+    pub fn parse(context: &str) -> Self {
+        let coords = context.lines()
+            .map(|l| l.split(['#', '!']).next().unwrap_or("").trim())
+            .filter(|l| !l.is_empty())
+            .filter_map(|l| {
+                let v = l.split_whitespace().collect::<Vec<_>>();
+                if v.len() < 2 {
+                    return None;
+                }
+                Some(Coordinate {
+                    kind: v[0].to_uppercase(),
+                    atoms: v[1 .. v.len() - 1].iter().filter_map(|x| x.parse().ok()).collect(),
+                    status: v[v.len() - 1].parse().ok()?,
+                })
+            })
+            .collect();
+        Self(coords)
+    }
+}
+
+
+// Blue moon quantities of one constraint, "b_m>" line of REPORT
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BlueMoonTerms {
+    pub lambda : f64,  // Lagrange multiplier
+    pub zinv   : f64,  // |Z|^(-1/2)
+    pub gkt    : f64,  // G*kT
+    pub term   : f64,  // |Z|^(-1/2) * (lambda + G*kT)
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReportStep {
+    pub coords    : Vec<f64>,            // "cc>" values of all the coordinates in ICONST
+    pub bluemoon  : Vec<BlueMoonTerms>,  // "b_m>" of the constrained coordinates
+    pub metadyn   : Vec<f64>,            // "mt>" values of the collective variables
+}
+
+
+/// REPORT file written by the MD runs with ICONST, one step per "MD step No." block.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Report(pub Vec<ReportStep>);
+
+impl Report {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        Ok(Self::parse(&context))
+    }
+
+    pub fn parse(context: &str) -> Self {
+        let mut steps = Vec::<ReportStep>::new();
+        let mut cur = ReportStep::default();
+        for line in context.lines() {
+            let s = line.trim();
+            if s.starts_with("MD step No.") {
+                if cur != ReportStep::default() {
+                    steps.push(std::mem::take(&mut cur));
+                }
+            } else if let Some(v) = s.strip_prefix("cc>") {
+                // Index, value and velocity of the coordinate, some versions print the type too
+                let nums = _numbers(v);
+                if nums.len() >= 2 {
+                    cur.coords.push(nums[1]);
+                }
+            } else if let Some(v) = s.strip_prefix("b_m>") {
+                let nums = _numbers(v);
+                if nums.len() >= 4 {
+                    cur.bluemoon.push(BlueMoonTerms { lambda: nums[0], zinv: nums[1], gkt: nums[2], term: nums[3] });
+                }
+            } else if let Some(v) = s.strip_prefix("mt>") {
+                let nums = _numbers(v);
+                if nums.len() >= 2 {
+                    cur.metadyn.push(nums[1]);
+                }
+            }
+        }
+        if cur != ReportStep::default() {
+            steps.push(cur);
+        }
+        Self(steps)
+    }
+}
+
+
+// Free energy gradient of one constrained coordinate
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FreeEnergyGradient {
+    pub label    : String,
+    pub value    : f64,  // averaged value of the coordinate
+    pub gradient : f64,  // dA/dxi in eV/unit of the coordinate
+    pub error    : f64,  // standard error from block averaging
+}
+
+
+/// Blue moon ensemble averages of the constrained coordinates:
+///
+///   dA/dxi = <|Z|^(-1/2) (lambda + G kT)> / <|Z|^(-1/2)>
+///
+/// The first `skip` steps are dropped for equilibration, the errors are estimated by the
+/// spread of the estimates of `nblocks` consecutive blocks.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BlueMoon {
+    pub nsteps    : usize,
+    pub gradients : Vec<FreeEnergyGradient>,
+}
+
+impl BlueMoon {
+    pub fn new(report: &Report, iconst: &Iconst, skip: usize, nblocks: usize) -> Self {
+        let steps = report.0.get(skip ..).unwrap_or(&[]);
+        let constrained = iconst.0.iter()
+            .enumerate()
+            .filter(|(_, c)| c.status == 0)
+            .collect::<Vec<_>>();
+        let nconst = steps.iter().map(|s| s.bluemoon.len()).min().unwrap_or(0);
+
+        let gradients = (0 .. nconst)
+            .map(|i| {
+                let (label, icoord) = match constrained.get(i) {
+                    Some((ic, c)) => (c.label(), Some(*ic)),
+                    None => (format!("#{}", i + 1), None),
+                };
+                let values = steps.iter()
+                    .filter_map(|s| icoord.and_then(|ic| s.coords.get(ic)))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let terms = steps.iter().map(|s| &s.bluemoon[i]).collect::<Vec<_>>();
+                let gradient = |t: &[&BlueMoonTerms]| {
+                    t.iter().map(|x| x.term).sum::<f64>() / t.iter().map(|x| x.zinv).sum::<f64>()
+                };
+
+                let nblocks = nblocks.clamp(1, terms.len().max(1));
+                let size = terms.len() / nblocks;
+                let error = if nblocks > 1 && size > 0 {
+                    let blocks = terms.chunks(size).take(nblocks).map(gradient).collect::<Vec<_>>();
+                    let mean = blocks.iter().sum::<f64>() / nblocks as f64;
+                    let var = blocks.iter().map(|b| (b - mean).powi(2)).sum::<f64>() / (nblocks - 1) as f64;
+                    (var / nblocks as f64).sqrt()
+                } else {
+                    f64::NAN
+                };
+
+                FreeEnergyGradient {
+                    label,
+                    value: values.iter().sum::<f64>() / values.len() as f64,
+                    gradient: gradient(&terms),
+                    error,
+                }
+            })
+            .collect();
+
+        Self { nsteps: steps.len(), gradients }
+    }
+}
+
+impl Tabular for BlueMoon {
+    fn headers(&self) -> Vec<String> {
+        ["label", "value", "gradient", "error"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.gradients.iter()
+            .map(|g| vec![g.label.clone(), g.value.to_string(), g.gradient.to_string(), g.error.to_string()])
+            .collect()
+    }
+}
+
+impl fmt::Display for BlueMoon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Blue moon averages over {} steps", self.nsteps).bright_green())?;
+        writeln!(f, "{}", format!("  {:>16} {:>12} {:>14} {:>12}", "Coordinate", "<xi>", "dA/dxi(eV)", "Error").bright_green())?;
+        for g in self.gradients.iter() {
+            writeln!(f, "  {:>16} {:12.5} {} {:12.5}",
+                     g.label, g.value, format!("{:14.5}", g.gradient).bright_yellow(), g.error)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Plots the coordinates of ICONST and the collective variables of metadynamics against the
+/// MD steps, and the running average of dA/dxi of the constrained coordinates.
+pub fn save_report_as_html(report: &Report, iconst: &Iconst, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+    let mut plot = Plot::new()
+        .layout(json!({
+            "title": "Constrained MD",
+            "xaxis": {"title": "MD step"},
+            "yaxis": {"title": "Coordinate", "domain": [0.52, 1.0]},
+            "yaxis2": {"title": "dA/dxi (eV)", "domain": [0.0, 0.48]},
+        }));
+    let x = (1 ..= report.0.len()).collect::<Vec<_>>();
+    let ncoords = report.0.iter().map(|s| s.coords.len()).min().unwrap_or(0);
+    for i in 0 .. ncoords {
+        let name = iconst.0.get(i).map(|c| c.label()).unwrap_or_else(|| format!("#{}", i + 1));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": name,
+            "x": x,
+            "y": report.0.iter().map(|s| s.coords[i]).collect::<Vec<_>>(),
+        }));
+    }
+    let ncvs = report.0.iter().map(|s| s.metadyn.len()).min().unwrap_or(0);
+    for i in 0 .. ncvs {
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": format!("CV {}", i + 1),
+            "x": x,
+            "y": report.0.iter().map(|s| s.metadyn[i]).collect::<Vec<_>>(),
+        }));
+    }
+
+    let nconst = report.0.iter().map(|s| s.bluemoon.len()).min().unwrap_or(0);
+    let labels = iconst.0.iter().filter(|c| c.status == 0).map(|c| c.label()).collect::<Vec<_>>();
+    for i in 0 .. nconst {
+        let (mut num, mut den) = (0.0, 0.0);
+        let running = report.0.iter()
+            .map(|s| {
+                num += s.bluemoon[i].term;
+                den += s.bluemoon[i].zinv;
+                num / den
+            })
+            .collect::<Vec<_>>();
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "name": format!("dA/dxi {}", labels.get(i).cloned().unwrap_or_else(|| format!("#{}", i + 1))),
+            "x": x,
+            "y": running,
+            "yaxis": "y2",
+        }));
+    }
+    plot.save_html(path)
+}
+
+
+fn _numbers(s: &str) -> Vec<f64> {
+    s.split_whitespace().filter_map(|x| x.parse().ok()).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bluemoon() {
+        let iconst = Iconst::parse("R 1 5 0\nA 2 1 3 7  # monitored\n");
+        assert_eq!(iconst.0.len(), 2);
+        assert_eq!(iconst.0[0].label(), "R(1,5)");
+        assert_eq!(iconst.0[1].status, 7);
+
+        let block = |lambda: f64, zinv: f64| format!("
+ ==========================================
+    MD step No.     1
+ ==========================================
+  >Const_coord
+   cc>    1    1.50000    0.00000
+   cc>    2  109.00000    0.00000
+  >Blue_moon
+       lambda        |z|^(-1/2)    GkT           |z|^(-1/2)*(lambda+GkT)
+   b_m>  {:12.5E}  {:12.5E}  0.00000E+00  {:12.5E}
+", lambda, zinv, lambda * zinv);
+        let text = [block(9.0, 1.0), block(1.0, 1.0), block(2.0, 2.0), block(4.0, 2.0)].concat();
+        let report = Report::parse(&text);
+        assert_eq!(report.0.len(), 4);
+        assert_eq!(report.0[1].coords, vec![1.5, 109.0]);
+        assert_eq!(report.0[2].bluemoon[0].zinv, 2.0);
+
+        let bm = BlueMoon::new(&report, &iconst, 1, 1);
+        assert_eq!(bm.nsteps, 3);
+        assert_eq!(bm.gradients[0].label, "R(1,5)");
+        assert_eq!(bm.gradients[0].value, 1.5);
+        assert!((bm.gradients[0].gradient - 13.0 / 5.0).abs() < 1E-10);
+
+        let bm = BlueMoon::new(&report, &iconst, 0, 2);
+        assert!((bm.gradients[0].gradient - 22.0 / 6.0).abs() < 1E-10);
+        assert!((bm.gradients[0].error - 1.0).abs() < 1E-10);
+    }
+}
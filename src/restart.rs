@@ -0,0 +1,346 @@
+use std::io;
+use std::io::BufRead;
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use log::{
+    info,
+    warn,
+};
+use serde::Serialize;
+use colored::Colorize;
+use vasp_poscar::Poscar;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    _decompress_gzip,
+};
+use crate::format::{
+    Structure,
+    Trajectory,
+    _car_to_frac,
+};
+use crate::status::{
+    JobProgress,
+    JobState,
+};
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IncarTag {
+    pub tag    : String,
+    pub value  : String,
+    pub reason : String,
+}
+
+
+/// Last geometry of a relaxation to restart from, read from OUTCAR or XDATCAR whichever has
+/// more ionic steps, thus it also works for the runs crashed before writing CONTCAR.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Restart {
+    pub dir       : PathBuf,
+    pub state     : JobState,
+    pub ibrion    : i32,
+    pub source    : String,        // "OUTCAR" or "XDATCAR"
+    pub step      : usize,         // ionic step of the geometry, starts from 1
+    #[serde(skip)]
+    pub structure : Structure,
+    #[serde(skip)]
+    pub dynamics  : Option<Vec<[bool; 3]>>,  // selective dynamics of the original POSCAR
+    pub wavecar   : bool,          // non-empty WAVECAR in `dir`
+    pub chgcar    : bool,          // non-empty CHGCAR in `dir`
+    pub tags      : Vec<IncarTag>,
+}
+
+impl Restart {
+    /// `outcar` and `xdatcar` are the file names in `dir`.
+    pub fn from_dir(dir: &Path, outcar: &str, xdatcar: &str) -> io::Result<Self> {
+        let outcar_path = dir.join(outcar);
+        let progress = match JobProgress::from_file(&outcar_path) {
+            Ok(p) => p,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => JobProgress::default(),
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                warn!("{}, the job state is unknown.", e);
+                JobProgress::default()
+            },
+            Err(e) => return Err(e),
+        };
+
+        // OUTCAR of crashed runs may be unreadable, XDATCAR is the backup
+        let from_outcar = match _outcar_geometries(&outcar_path) {
+            Ok(frames) => frames,
+            Err(e) => {
+                if outcar_path.is_file() {
+                    warn!("Cannot read the ionic steps of {:?}: {}", &outcar_path, e);
+                }
+                vec![]
+            },
+        };
+        let from_xdatcar = Trajectory::from_xdatcar(&dir.join(xdatcar))
+            .map(|t| t.0)
+            .unwrap_or_default();
+        info!("Found {} ionic steps in {:?} and {} in {:?}", from_outcar.len(), outcar, from_xdatcar.len(), xdatcar);
+
+        let (source, mut frames) = if from_outcar.len() >= from_xdatcar.len() {
+            (outcar, from_outcar)
+        } else {
+            (xdatcar, from_xdatcar)
+        };
+        let step = frames.len();
+        let structure = frames.pop()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                format!("No ionic steps found in {:?} or {:?} of {:?}", outcar, xdatcar, dir)))?;
+
+        let nions = structure.car_pos.len();
+        let dynamics = Poscar::from_path(dir.join("POSCAR"))
+            .ok()
+            .and_then(|p| p.into_raw().dynamics)
+            .filter(|d| d.len() == nions);
+
+        let non_empty = |name: &str| fs::metadata(dir.join(name)).is_ok_and(|m| m.len() > 0);
+        let mut ret = Self {
+            dir: dir.to_path_buf(),
+            state: progress.state(),
+            ibrion: progress.ibrion,
+            source: source.to_string(),
+            step,
+            structure,
+            dynamics,
+            wavecar: non_empty("WAVECAR"),
+            chgcar: non_empty("CHGCAR"),
+            tags: vec![],
+        };
+        ret.recommend(ret.wavecar, ret.chgcar);
+        Ok(ret)
+    }
+
+    /// Recommends ISTART and ICHARG by the WAVECAR and CHGCAR available to the next run.
+    pub fn recommend(&mut self, wavecar: bool, chgcar: bool) {
+        let tag = |tag: &str, value: &str, reason: &str| IncarTag {
+            tag: tag.to_string(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+        };
+        self.tags = if wavecar {
+            vec![tag("ISTART", "1", "continue from the wave functions in WAVECAR"),
+                 tag("ICHARG", "0", "charge density from the wave functions")]
+        } else if chgcar {
+            vec![tag("ISTART", "0", "no WAVECAR available"),
+                 tag("ICHARG", "1", "continue from the charge density in CHGCAR")]
+        } else {
+            vec![tag("ISTART", "0", "no WAVECAR available"),
+                 tag("ICHARG", "2", "no CHGCAR available, start from the atomic charge densities")]
+        };
+    }
+
+    /// The geometry as POSCAR, with the selective dynamics of the original POSCAR kept.
+    pub fn poscar(&self) -> Poscar {
+        let mut raw = Poscar::from(self.structure.clone()).into_raw();
+        raw.comment = format!("Restarted from {} step {}", self.source, self.step);
+        raw.dynamics = self.dynamics.clone();
+        raw.validate().unwrap()
+    }
+
+    pub fn save_poscar(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Writing the geometry of {} step {} to {:?} ...", self.source, self.step, path.as_ref());
+        fs::write(path, format!("{:.9}", self.poscar()))
+    }
+
+    /// Creates the next run directory, e.g. "run01" -> "run02", with `files` copied and the
+    /// geometry written as POSCAR. The non-empty WAVECAR and CHGCAR are copied if
+    /// `wavefunctions` is set.
+    pub fn prepare_next(&self, files: &[String], wavefunctions: bool) -> io::Result<PathBuf> {
+        let next = next_run_dir(&self.dir)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Cannot number the next run of {:?}, its name should end with digits like \"run01\"", self.dir)))?;
+        if next.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", next)));
+        }
+        fs::create_dir_all(&next)?;
+
+        let mut files = files.to_vec();
+        if wavefunctions && self.wavecar {
+            files.push("WAVECAR".to_string());
+        }
+        if wavefunctions && self.chgcar {
+            files.push("CHGCAR".to_string());
+        }
+        for fname in files.iter().filter(|f| f.as_str() != "POSCAR") {
+            let src = self.dir.join(fname);
+            if src.is_file() {
+                info!("Copying {:?} to {:?} ...", &src, &next);
+                fs::copy(&src, next.join(fname))?;
+            } else {
+                warn!("{:?} not found, skipped.", &src);
+            }
+        }
+        self.save_poscar(&next.join("POSCAR"))?;
+        Ok(next)
+    }
+}
+
+impl Tabular for Restart {
+    fn headers(&self) -> Vec<String> {
+        ["tag", "value", "reason"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.tags.iter()
+            .map(|t| vec![t.tag.clone(), t.value.clone(), t.reason.clone()])
+            .collect()
+    }
+}
+
+impl fmt::Display for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Geometry of {} step {} in {:?}, the job is {}",
+                                  self.source, self.step, self.dir, self.state).bright_green())?;
+        writeln!(f, "{}", "# Recommended INCAR tags for the next run".bright_green())?;
+        for t in self.tags.iter() {
+            writeln!(f, "{} = {}    # {}", t.tag.bright_yellow(), t.value, t.reason)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Geometries of the complete ionic steps of OUTCAR. Only the species, lattice vectors and
+// positions are read line by line, thus unlike `Outcar::from_file` it doesn't require the
+// Fermi level or the other parts missing in OUTCARs truncated anywhere.
+fn _outcar_geometries(path: &Path) -> io::Result<Vec<Structure>> {
+    if path.extension().is_some_and(|e| e == "xml") {
+        return Ok(Trajectory::from(Outcar::from_file(path)?).0);
+    }
+    if path.extension().is_some_and(|e| e == "gz") {
+        let data = _decompress_gzip(&fs::read(path)?)?;
+        return _outcar_geometries_from_reader(io::Cursor::new(data));
+    }
+    _outcar_geometries_from_reader(io::BufReader::new(fs::File::open(path)?))
+}
+
+fn _outcar_geometries_from_reader(reader: impl BufRead) -> io::Result<Vec<Structure>> {
+    let row = |line: &str| line.split_whitespace()
+        .map(|x| x.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>();
+
+    let mut potcars = vec![];
+    let mut ions_per_type = vec![];
+    let mut cell = None;
+    let mut frames = vec![];
+    let mut lines = reader.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.starts_with(" POTCAR:") {
+            potcars.extend(line.split_whitespace().nth(2).map(|s| s.to_string()));
+        } else if let Some(counts) = line.trim_start().strip_prefix("ions per type =") {
+            ions_per_type = counts.split_whitespace()
+                .map(|x| x.parse::<i32>().ok())
+                .collect::<Option<Vec<i32>>>()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Cannot parse ions per type: {:?}", counts)))?;
+        } else if line.contains("direct lattice vectors") {
+            let mut vecs = vec![];
+            for l in lines.by_ref().take(3) {
+                vecs.extend(row(&l?).filter(|v| v.len() == 6).map(|v| [v[0], v[1], v[2]]));
+            }
+            cell = match vecs[..] {
+                [a, b, c] => Some([a, b, c]),
+                _ => break,
+            };
+        } else if line.starts_with(" POSITION") && line.contains("TOTAL-FORCE") {
+            // The POTCAR lines are printed twice in the header
+            let ion_types = potcars[.. potcars.len() / 2].to_vec();
+            if ion_types.is_empty() || ion_types.len() != ions_per_type.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "Element symbols or ions per type not found in the header"));
+            }
+            let nions = ions_per_type.iter().sum::<i32>() as usize;
+            let mut car_pos = vec![];
+            let mut complete = false;
+            for l in lines.by_ref().skip(1) {
+                let l = l?;
+                if l.starts_with(" ----") { complete = true; break; }
+                match row(&l) {
+                    Some(v) if v.len() == 6 => car_pos.push([v[0], v[1], v[2]]),
+                    _ => break,
+                }
+            }
+            let cell = match cell {
+                Some(cell) if complete && car_pos.len() == nions => cell,
+                _ => break,
+            };
+            frames.push(Structure {
+                cell,
+                ion_types,
+                ions_per_type: ions_per_type.clone(),
+                frac_pos: _car_to_frac(&cell, &car_pos),
+                car_pos,
+            });
+        }
+    }
+    Ok(frames)
+}
+
+
+/// Increments the trailing digits of the directory name, keeping the width, e.g. "run09" ->
+/// "run10" and "relax/2" -> "relax/3". Returns None if the name doesn't end with digits.
+pub fn next_run_dir(dir: &Path) -> Option<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() || dir == Path::new(".") {
+        dir.canonicalize().ok()?
+    } else {
+        dir.to_path_buf()
+    };
+    let name = dir.file_name()?.to_str()?;
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &name[prefix.len() ..];
+    if digits.is_empty() {
+        return None;
+    }
+    let n = digits.parse::<usize>().ok()? + 1;
+    Some(dir.with_file_name(format!("{}{:0width$}", prefix, n, width = digits.len())))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_next_run_dir() {
+        assert_eq!(next_run_dir(Path::new("calc/run01")), Some(PathBuf::from("calc/run02")));
+        assert_eq!(next_run_dir(Path::new("run09")), Some(PathBuf::from("run10")));
+        assert_eq!(next_run_dir(Path::new("relax/2")), Some(PathBuf::from("relax/3")));
+        assert_eq!(next_run_dir(Path::new("relax")), None);
+    }
+
+    #[test]
+    fn test_truncated_outcar() {
+        let dir = TempDir::new("rsgrad_restart").unwrap();
+        let outcar = fs::read_to_string("tests/OUTCAR_another_rlx").unwrap();
+        let lines = outcar.lines().collect::<Vec<_>>();
+        fs::copy("tests/XDATCAR_another_rlx", dir.path().join("XDATCAR")).unwrap();
+
+        // Truncated before the Fermi level of the first step, only XDATCAR is readable
+        fs::write(dir.path().join("OUTCAR"), lines[.. 1000].join("\n")).unwrap();
+        let plan = Restart::from_dir(dir.path(), "OUTCAR", "XDATCAR").unwrap();
+        assert_eq!((plan.source.as_str(), plan.step), ("XDATCAR", 5));
+
+        // Truncated within the positions of the third step
+        fs::write(dir.path().join("OUTCAR"), lines[.. 136985].join("\n")).unwrap();
+        let frames = _outcar_geometries(&dir.path().join("OUTCAR")).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].car_pos.len(), 7);
+        assert_eq!(frames[1].ion_types, vec!["Li", "Ni", "O"]);
+
+        fs::write(dir.path().join("OUTCAR"), outcar).unwrap();
+        let plan = Restart::from_dir(dir.path(), "OUTCAR", "XDATCAR").unwrap();
+        assert_eq!((plan.source.as_str(), plan.step), ("OUTCAR", 5));
+        let expected = Trajectory::from(Outcar::from_file("tests/OUTCAR_another_rlx").unwrap()).0;
+        assert_eq!(plan.structure, expected[4]);
+    }
+}
@@ -0,0 +1,412 @@
+use serde::Deserialize;
+use itertools::Itertools;
+use crate::format::{
+    Structure,
+    _car_to_frac,
+};
+
+
+// Selection of atoms and orbitals as written by the user, e.g.
+//
+// [[pdos]]
+// label  = "Ti-d"
+// atoms  = "1..4 -1"           # or element symbols and spatial filters like "Ti z>10", which require POSCAR
+// orbits = "dxy dyz dz2 dxz x2-y2"  # or groups like "d", see ORBITAL_GROUPS
+//
+// The shorthand "Ti:d" selects the d orbitals of all Ti atoms, see `RawSelection::from_shorthand`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RawSelection {
+    pub label  : String,
+    #[serde(default)]
+    pub atoms  : String,  // empty means all atoms
+    #[serde(default)]
+    pub orbits : String,  // empty means all orbitals
+}
+
+
+// Atoms with z coordinates closer than this, in Angstrom, are in the same layer
+const LAYER_TOLERANCE: f64 = 0.5;
+
+
+// Orbital groups expanded to the orbital names in the header of PROCAR, the names absent in
+// PROCAR are skipped. "x2-y2" is named "dx2" in PROCAR of some VASP versions.
+const ORBITAL_GROUPS: [(&str, &[&str]); 5] = [
+    ("p",   &["px", "py", "pz"]),
+    ("d",   &["dxy", "dyz", "dz2", "dxz", "x2-y2", "dx2"]),
+    ("t2g", &["dxy", "dyz", "dxz"]),
+    ("eg",  &["dz2", "x2-y2", "dx2"]),
+    ("f",   &["fy3x2", "fxyz", "fyz2", "fz3", "fxz2", "fzx2", "fx3"]),
+];
+
+
+// Parsed selection, indices start from 0
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    pub label   : String,
+    pub iatoms  : Vec<usize>,
+    pub iorbits : Vec<usize>,
+}
+
+
+impl RawSelection {
+    pub fn new(label: &str, atoms: &str, orbits: &str) -> Self {
+        Self {
+            label: label.to_owned(),
+            atoms: atoms.to_owned(),
+            orbits: orbits.to_owned(),
+        }
+    }
+
+    /// Parses the shorthand like "Fe:d", i.e. the atoms and orbitals separated by the last ':'.
+    /// Without orbitals, e.g. "Fe" or "layer:1", only the atoms are selected.
+    pub fn from_shorthand(input: &str) -> Self {
+        let (atoms, orbits) = match input.rsplit_once(':') {
+            Some((a, o)) if o.starts_with(|c: char| c.is_ascii_alphabetic()) => (a, o),
+            _ => (input, ""),
+        };
+        Self::new(&input.replace(' ', "_"), atoms, orbits)
+    }
+
+    pub fn parse(&self, nions: usize, orbitals: &[String]) -> Selection {
+        Selection {
+            label: self.label.clone(),
+            iatoms: Self::parse_iatoms(&self.atoms, nions),
+            iorbits: Self::parse_iorbits(&self.orbits, orbitals),
+        }
+    }
+
+    /// Same as `parse`, except that the atoms can also be selected by element symbols and
+    /// spatial filters, see `parse_iatoms_in`.
+    pub fn parse_in(&self, structure: &Structure, orbitals: &[String]) -> Selection {
+        Selection {
+            label: self.label.clone(),
+            iatoms: Self::parse_iatoms_in(&self.atoms, structure),
+            iorbits: Self::parse_iorbits(&self.orbits, orbitals),
+        }
+    }
+
+    /// Whether the atoms are selected by element symbols or spatial filters, which need the
+    /// structure to parse.
+    pub fn needs_structure(&self) -> bool {
+        _needs_structure(&self.atoms)
+    }
+
+    /// Atom indices start from '1', negative index means counting reversely, and ranges
+    /// like "1..4" are inclusive. Tokens are separated by white spaces or commas.
+    /// Returned indices start from 0, sorted and deduplicated.
+    pub fn parse_iatoms(input: &str, nions: usize) -> Vec<usize> {
+        Self::_parse_iatoms(input, nions, None)
+    }
+
+    /// Same as `parse_iatoms`, with the atoms resolved against the structure additionally by
+    /// - element symbols, e.g. "O Ti" selects all the O and Ti atoms;
+    /// - coordinates, e.g. "z>10" or "fx<=0.5" in Cartesian (Angstrom) or fractional coordinates;
+    /// - layers along z, e.g. "layer:1..2" or "layer:-1", counted from the bottom starting from 1
+    ///   or from the top with negative indices. Atoms within 0.5 Angstrom in z share one layer;
+    /// - distances in Angstrom to an atom or a Cartesian point with periodic images, e.g. "d<3@5"
+    ///   selects the atoms within the sphere of 3 Angstrom around atom 5, "d>6@0/0/12.5" the
+    ///   atoms outside the sphere around (0, 0, 12.5).
+    ///
+    /// Coordinates and layers filter the atoms selected by the other tokens, or all the atoms
+    /// if there are no other tokens, e.g. "O z>10" selects the O atoms above 10 Angstrom.
+    pub fn parse_iatoms_in(input: &str, structure: &Structure) -> Vec<usize> {
+        Self::_parse_iatoms(input, structure.car_pos.len(), Some(structure))
+    }
+
+    fn _parse_iatoms(input: &str, nions: usize, structure: Option<&Structure>) -> Vec<usize> {
+        let to_index = |x: &str| -> usize {
+            let i = x.parse::<i32>().expect("Cannot parse atom index as integer value");
+            assert!(i != 0 && i.unsigned_abs() as usize <= nions, "Atom index out of bound.");
+            if i < 0 {
+                (i + nions as i32) as usize
+            } else {
+                i as usize - 1
+            }
+        };
+        let require = |token: &str| -> &Structure {
+            structure.unwrap_or_else(|| panic!("Atom selection '{}' requires the structure, e.g. POSCAR", token))
+        };
+
+        let (filters, tokens): (Vec<&str>, Vec<&str>) = input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty())
+            .partition(|x| _is_filter(x));
+
+        let mut ret = tokens.iter()
+            .flat_map(|&token| {
+                if _needs_structure(token) {
+                    let symbols = require(token).symbols();
+                    let ret = symbols.iter()
+                        .enumerate()
+                        .filter(|(_, s)| s.as_str() == token)
+                        .map(|(i, _)| i)
+                        .collect::<Vec<usize>>();
+                    assert!(!ret.is_empty(), "Element '{}' not found, available elements: {:?}", token, symbols.iter().unique().collect::<Vec<_>>());
+                    return ret;
+                }
+                if let Some((beg, end)) = token.split_once("..") {
+                    let (beg, end) = (to_index(beg), to_index(end));
+                    assert!(beg <= end, "Invalid atom index range: {}", token);
+                    (beg ..= end).collect::<Vec<usize>>()
+                } else {
+                    vec![to_index(token)]
+                }
+            })
+            .collect::<Vec<usize>>();
+
+        if tokens.is_empty() {
+            ret = (0 .. nions).collect();
+        }
+        for token in filters {
+            let keep = _filter_atoms(token, require(token));
+            ret.retain(|i| keep[*i]);
+        }
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+
+    /// Orbital names should be the ones in the header of PROCAR, e.g. "s px dxy", or the groups
+    /// "p", "d", "t2g", "eg" and "f". "all" or empty input selects all the orbitals. Returned
+    /// indices start from 0.
+    pub fn parse_iorbits(input: &str, orbitals: &[String]) -> Vec<usize> {
+        let mut ret = input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty())
+            .flat_map(|token| {
+                if token == "all" {
+                    return (0 .. orbitals.len()).collect::<Vec<usize>>();
+                }
+                // Names in PROCAR take precedence, e.g. "p" of LORBIT=10
+                if let Some(i) = orbitals.iter().position(|o| o == token) {
+                    return vec![i];
+                }
+                let ret = ORBITAL_GROUPS.iter()
+                    .find(|(name, _)| *name == token)
+                    .map(|(_, members)| {
+                        orbitals.iter()
+                            .enumerate()
+                            .filter(|(_, o)| members.contains(&o.as_str()))
+                            .map(|(i, _)| i)
+                            .collect::<Vec<usize>>()
+                    })
+                    .unwrap_or_default();
+                assert!(!ret.is_empty(), "Orbital '{}' not found, available orbitals: {:?}", token, orbitals);
+                ret
+            })
+            .collect::<Vec<usize>>();
+
+        if input.trim().is_empty() {
+            ret = (0 .. orbitals.len()).collect();
+        }
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+}
+
+
+// Atom tokens starting with a letter are element symbols or spatial filters
+fn _needs_structure(input: &str) -> bool {
+    input.split(|c: char| c.is_whitespace() || c == ',')
+        .any(|x| x.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+
+// Spatial filters like "z>10", "fx<=0.5", "d<3@5" or "layer:1..2"
+fn _is_filter(token: &str) -> bool {
+    token.starts_with("layer:") || token.contains(['<', '>'])
+}
+
+
+// Whether each atom passes the spatial filter
+fn _filter_atoms(token: &str, structure: &Structure) -> Vec<bool> {
+    if let Some(layers) = token.strip_prefix("layer:") {
+        let layer_of = _layers(structure);
+        let nlayers = layer_of.iter().max().map_or(0, |n| n + 1);
+        let to_index = |x: &str| -> usize {
+            let i = x.parse::<i32>().unwrap_or_else(|_| panic!("Cannot parse layer index in '{}'", token));
+            assert!(i != 0 && i.unsigned_abs() as usize <= nlayers, "Layer index out of bound, only {} layers found.", nlayers);
+            if i < 0 { (i + nlayers as i32) as usize } else { i as usize - 1 }
+        };
+        let (beg, end) = match layers.split_once("..") {
+            Some((beg, end)) => (to_index(beg), to_index(end)),
+            None => (to_index(layers), to_index(layers)),
+        };
+        return layer_of.iter().map(|l| (beg ..= end).contains(l)).collect();
+    }
+
+    let op = ["<=", ">=", "<", ">"].iter()
+        .find(|op| token.contains(*op))
+        .unwrap();
+    let (axis, value) = token.split_once(op).unwrap();
+    let (value, center) = match value.split_once('@') {
+        Some((value, center)) if axis == "d" => (value, Some(_parse_center(center, token, structure))),
+        _ => (value, None),
+    };
+    let value = value.parse::<f64>()
+        .unwrap_or_else(|_| panic!("Cannot parse coordinate in '{}'", token));
+    let coords = match (axis, center) {
+        ("d", Some(center)) => structure.frac_pos.iter()
+            .map(|p| _periodic_distance(structure, p, &center))
+            .collect::<Vec<f64>>(),
+        ("x" | "y" | "z", _) => structure.car_pos.iter()
+            .map(|p| p[(axis.as_bytes()[0] - b'x') as usize])
+            .collect(),
+        ("fx" | "fy" | "fz", _) => structure.frac_pos.iter()
+            .map(|p| p[(axis.as_bytes()[1] - b'x') as usize])
+            .collect(),
+        _ => panic!("Invalid coordinate '{}' in '{}', should be x, y, z, fx, fy, fz or d@center", axis, token),
+    };
+    coords.into_iter()
+        .map(|x| {
+            match *op {
+                "<=" => x <= value,
+                ">=" => x >= value,
+                "<"  => x < value,
+                _    => x > value,
+            }
+        })
+        .collect()
+}
+
+
+// Center of the distance filter in fractional coordinates, either an atom index starting from 1
+// or Cartesian coordinates separated by '/'
+fn _parse_center(center: &str, token: &str, structure: &Structure) -> [f64; 3] {
+    let nions = structure.frac_pos.len();
+    if let Ok(i) = center.parse::<i32>() {
+        assert!(i != 0 && i.unsigned_abs() as usize <= nions, "Atom index out of bound in '{}'.", token);
+        let i = if i < 0 { (i + nions as i32) as usize } else { i as usize - 1 };
+        return structure.frac_pos[i];
+    }
+
+    let v = center.split('/')
+        .map(|x| x.parse::<f64>().unwrap_or_else(|_| panic!("Cannot parse the center in '{}'", token)))
+        .collect::<Vec<f64>>();
+    assert_eq!(v.len(), 3, "The center in '{}' should be an atom index or x/y/z", token);
+    _car_to_frac(&structure.cell, &vec![[v[0], v[1], v[2]]])[0]
+}
+
+
+// Distance of the nearest periodic images in Angstrom, the minimum image convention is exact
+// for cells that are not too skewed
+fn _periodic_distance(structure: &Structure, a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let d = [0, 1, 2].map(|i| {
+        let x = a[i] - b[i];
+        x - x.round()
+    });
+    (0 .. 3).map(|j| {
+        let x = (0 .. 3).map(|i| d[i] * structure.cell[i][j]).sum::<f64>();
+        x * x
+    })
+    .sum::<f64>()
+    .sqrt()
+}
+
+
+
+// Layer index of each atom along z starting from 0 at the bottom, a gap larger than
+// LAYER_TOLERANCE in the sorted z coordinates starts a new layer.
+fn _layers(structure: &Structure) -> Vec<usize> {
+    let mut order = (0 .. structure.car_pos.len()).collect::<Vec<usize>>();
+    order.sort_by(|a, b| structure.car_pos[*a][2].partial_cmp(&structure.car_pos[*b][2]).unwrap());
+
+    let mut ret = vec![0; order.len()];
+    let mut layer = 0;
+    for (i, w) in order.iter().enumerate() {
+        if i > 0 && structure.car_pos[*w][2] - structure.car_pos[order[i - 1]][2] > LAYER_TOLERANCE {
+            layer += 1;
+        }
+        ret[*w] = layer;
+    }
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iatoms() {
+        assert_eq!(RawSelection::parse_iatoms("1..3 5, -1", 8), vec![0, 1, 2, 4, 7]);
+        assert_eq!(RawSelection::parse_iatoms("-2..-1 2", 8), vec![1, 6, 7]);
+        assert_eq!(RawSelection::parse_iatoms("", 3), vec![0, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Atom index out of bound.")]
+    fn test_parse_iatoms_fail() {
+        RawSelection::parse_iatoms("1 9", 8);
+    }
+
+    #[test]
+    fn test_parse_iorbits() {
+        let orbitals = ["s", "py", "pz", "px", "dxy"].iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(RawSelection::parse_iorbits("px py", &orbitals), vec![1, 3]);
+        assert_eq!(RawSelection::parse_iorbits("all", &orbitals), vec![0, 1, 2, 3, 4]);
+        assert_eq!(RawSelection::parse_iorbits("", &orbitals), vec![0, 1, 2, 3, 4]);
+
+        let orbitals = ["s", "py", "pz", "px", "dxy", "dyz", "dz2", "dxz", "x2-y2"].iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(RawSelection::parse_iorbits("s p", &orbitals), vec![0, 1, 2, 3]);
+        assert_eq!(RawSelection::parse_iorbits("t2g", &orbitals), vec![4, 5, 7]);
+        assert_eq!(RawSelection::parse_iorbits("eg", &orbitals), vec![6, 8]);
+        assert_eq!(RawSelection::parse_iorbits("d", &orbitals), vec![4, 5, 6, 7, 8]);
+
+        let orbitals = vec!["s".to_string(), "p".to_string(), "d".to_string()];
+        assert_eq!(RawSelection::parse_iorbits("d", &orbitals), vec![2]);
+    }
+
+    fn _generate_structure() -> Structure {
+        // Fe at the bottom layer, O in two layers above
+        Structure {
+            cell: [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 20.0]],
+            ion_types: vec!["Fe".to_string(), "O".to_string()],
+            ions_per_type: vec![2, 3],
+            car_pos: vec![[0.0, 0.0, 5.0], [1.5, 1.5, 5.2], [0.0, 0.0, 7.0], [1.5, 1.5, 7.1], [0.0, 1.5, 11.0]],
+            frac_pos: vec![[0.0, 0.0, 0.25], [0.5, 0.5, 0.26], [0.0, 0.0, 0.35], [0.5, 0.5, 0.355], [0.0, 0.5, 0.55]],
+        }
+    }
+
+    #[test]
+    fn test_parse_iatoms_in() {
+        let s = _generate_structure();
+        assert_eq!(RawSelection::parse_iatoms_in("O 1", &s), vec![0, 2, 3, 4]);
+        assert_eq!(RawSelection::parse_iatoms_in("z>10", &s), vec![4]);
+        assert_eq!(RawSelection::parse_iatoms_in("O fz<0.5", &s), vec![2, 3]);
+        assert_eq!(RawSelection::parse_iatoms_in("layer:1", &s), vec![0, 1]);
+        assert_eq!(RawSelection::parse_iatoms_in("layer:2..-1 fx>=0.5", &s), vec![3]);
+        assert_eq!(RawSelection::parse_iatoms_in("d<2.2@3", &s), vec![0, 2, 3]);
+        assert_eq!(RawSelection::parse_iatoms_in("d<=1@3/0/7", &s), vec![2]);
+        assert_eq!(RawSelection::parse_iatoms_in("Fe d>0.1@1", &s), vec![1]);
+
+        let raw = RawSelection::from_shorthand("Fe:d");
+        assert_eq!(raw, RawSelection::new("Fe:d", "Fe", "d"));
+        assert!(raw.needs_structure());
+        let orbitals = vec!["s".to_string(), "p".to_string(), "d".to_string()];
+        let sel = raw.parse_in(&s, &orbitals);
+        assert_eq!(sel.iatoms, vec![0, 1]);
+        assert_eq!(sel.iorbits, vec![2]);
+
+        assert_eq!(RawSelection::from_shorthand("layer:1..2"), RawSelection::new("layer:1..2", "layer:1..2", ""));
+        assert_eq!(RawSelection::from_shorthand("layer:-1:p"), RawSelection::new("layer:-1:p", "layer:-1", "p"));
+    }
+
+    #[test]
+    #[should_panic(expected = "requires the structure")]
+    fn test_parse_iatoms_without_structure() {
+        RawSelection::parse_iatoms("z>10", 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "Orbital 'f' not found")]
+    fn test_parse_iorbits_fail() {
+        let orbitals = vec!["s".to_string(), "p".to_string()];
+        RawSelection::parse_iorbits("f", &orbitals);
+    }
+}
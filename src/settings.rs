@@ -0,0 +1,154 @@
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::OnceLock;
+use serde::Deserialize;
+use serde_json::{
+    json,
+    Value,
+};
+use log::{
+    info,
+    warn,
+};
+
+
+// User settings read from `$XDG_CONFIG_HOME/rsgrad.toml` or `~/.config/rsgrad.toml`, e.g.
+//
+// [plot]
+// colorway    = ["#0072b2", "#d55e00", "#009e73"]  # colors of traces without explicit colors
+// font_family = "Helvetica"
+// font_size   = 14
+// figsize     = [3.5, 2.6]                          # figure size in inches
+//
+// [plot.template.layout]                            # plotly template, applied under the defaults of each command
+// plot_bgcolor = "white"
+// xaxis        = { showgrid = false, ticks = "outside" }
+//
+// All the keys are optional.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub plot : PlotSettings,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PlotSettings {
+    pub colorway    : Vec<String>,
+    pub font_family : Option<String>,
+    pub font_size   : Option<f64>,
+    pub figsize     : Option<[f64; 2]>,
+    pub template    : Option<toml::Value>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+impl Settings {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let context = fs::read_to_string(path)?;
+        toml::from_str(&context)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Default location of the settings file.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|d| PathBuf::from(d).join(".config")))
+            .map(|d| d.join("rsgrad.toml"))
+    }
+
+    /// Settings of the current user, read once. Defaults are used if the file is absent or broken.
+    pub fn global() -> &'static Self {
+        SETTINGS.get_or_init(|| {
+            let path = match Self::default_path() {
+                Some(p) if p.is_file() => p,
+                _ => return Self::default(),
+            };
+            info!("Reading settings file {:?} ...", &path);
+            Self::from_file(&path).unwrap_or_else(|e| {
+                warn!("Cannot read settings file {:?}: {}, defaults are used.", &path, e);
+                Self::default()
+            })
+        })
+    }
+}
+
+impl PlotSettings {
+    /// Merges the house style into the layout of a plot, keys set by the command are kept.
+    pub fn apply(&self, layout: &mut Value) {
+        if !layout.is_object() {
+            *layout = json!({});
+        }
+        if !self.colorway.is_empty() && layout["colorway"].is_null() {
+            layout["colorway"] = json!(self.colorway);
+        }
+        if let Some(family) = self.font_family.as_ref() {
+            if layout["font"]["family"].is_null() {
+                layout["font"]["family"] = json!(family);
+            }
+        }
+        if let Some(size) = self.font_size {
+            if layout["font"]["size"].is_null() {
+                layout["font"]["size"] = json!(size);
+            }
+        }
+        if let Some([w, h]) = self.figsize {
+            if layout["width"].is_null() && layout["height"].is_null() {
+                layout["width"] = json!(w * 96.0);
+                layout["height"] = json!(h * 96.0);
+            }
+        }
+        if let Some(template) = self.template.as_ref() {
+            if layout["template"].is_null() {
+                layout["template"] = serde_json::to_value(template).unwrap_or(Value::Null);
+            }
+        }
+    }
+
+    /// The i-th color of the colorway, or of `fallback` if no colorway is set.
+    pub fn color(&self, i: usize, fallback: &[&str]) -> String {
+        if self.colorway.is_empty() {
+            fallback[i % fallback.len()].to_string()
+        } else {
+            self.colorway[i % self.colorway.len()].clone()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_plot_settings() {
+        let settings: Settings = toml::from_str(r#"
+            [plot]
+            colorway = ["red", "blue"]
+            font_size = 14
+            figsize = [3.5, 2.5]
+
+            [plot.template.layout]
+            plot_bgcolor = "white"
+        "#).unwrap();
+
+        let mut layout = json!({"title": "test", "font": {"size": 10}});
+        settings.plot.apply(&mut layout);
+        assert_eq!(layout["colorway"], json!(["red", "blue"]));
+        assert_eq!(layout["font"]["size"], json!(10));
+        assert_eq!(layout["width"], json!(336.0));
+        assert_eq!(layout["template"]["layout"]["plot_bgcolor"], json!("white"));
+        assert_eq!(settings.plot.color(3, &["black"]), "blue");
+
+        let mut layout = json!({"title": "test"});
+        Settings::default().plot.apply(&mut layout);
+        assert_eq!(layout, json!({"title": "test"}));
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use colored::Colorize;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::procar::Procar;
+use crate::dos::_state_weights;
+
+
+// Angular momentum channels, the orbitals of PROCAR are merged by their first letter,
+// except "x2-y2" which is a d orbital.
+const SHELLS: [&str; 4] = ["s", "p", "d", "f"];
+
+fn _shell_of(orbital: &str) -> Option<usize> {
+    if orbital == "x2-y2" {
+        return Some(2);
+    }
+    let c = orbital.chars().next()?.to_string();
+    SHELLS.iter().position(|s| *s == c)
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SiteCharge {
+    pub index   : usize,      // starts from 1
+    pub symbol  : String,
+    pub charges : Vec<f64>,   // electrons of each shell in `SiteProjection::shells`
+    pub total   : f64,
+    pub moment  : Vec<f64>,   // empty for ISPIN=1, [mz] for ISPIN=2, [mx, my, mz] for non-collinear
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpeciesCharge {
+    pub symbol : String,
+    pub nions  : usize,
+    pub total  : f64,
+    pub moment : Vec<f64>,
+}
+
+
+/// Electrons and local moments inside the projection spheres of each ion, integrated over the
+/// occupied states of PROCAR with the occupations and k-point weights. The charges are resolved
+/// by the angular momentum like the l-decomposed charges of DOSCAR, but they do not sum up to
+/// the number of valence electrons because of the interstitial region and the spilling.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SiteProjection {
+    pub shells  : Vec<String>,
+    pub ions    : Vec<SiteCharge>,
+    pub species : Vec<SpeciesCharge>,
+    pub total   : f64,
+    pub moment  : Vec<f64>,
+}
+
+impl SiteProjection {
+    /// `symbols` are the element symbols of each ion, the ions are numbered if None.
+    pub fn from_procar(procar: &Procar, symbols: Option<&[String]>) -> Self {
+        let norbits = procar.norbits();
+        let ishells = procar.orbitals.iter().map(|o| _shell_of(o)).collect::<Vec<_>>();
+        let present = (0 .. SHELLS.len())
+            .filter(|l| ishells.contains(&Some(*l)))
+            .collect::<Vec<_>>();
+        let (weights, factor) = _state_weights(procar);
+
+        // Occupation weighted projections [ncomp][nions][norbits]
+        let ncomp = procar.ncomp();
+        let mut acc = vec![vec![0.0; procar.nions * norbits]; ncomp];
+        for (icomp, acc) in acc.iter_mut().enumerate() {
+            let ispin = if procar.lncl { 0 } else { icomp };
+            for (ik, wk) in weights.iter().enumerate() {
+                for ib in 0 .. procar.nbands {
+                    let w = wk * procar.occupation(ispin, ik, ib) * factor;
+                    if w == 0.0 {
+                        continue;
+                    }
+                    acc.iter_mut()
+                        .zip(procar.band_projections(icomp, ik, ib).iter())
+                        .for_each(|(a, p)| *a += w * p);
+                }
+            }
+        }
+
+        let ions = (0 .. procar.nions)
+            .map(|iion| {
+                let row = |icomp: usize| &acc[icomp][iion * norbits .. (iion + 1) * norbits];
+                let charge_row = if procar.lncl || procar.nspin == 1 {
+                    row(0).to_vec()
+                } else {
+                    row(0).iter().zip(row(1).iter()).map(|(u, d)| u + d).collect()
+                };
+                let charges = present.iter()
+                    .map(|l| charge_row.iter()
+                         .zip(ishells.iter())
+                         .filter(|(_, il)| **il == Some(*l))
+                         .map(|(c, _)| c)
+                         .sum::<f64>())
+                    .collect::<Vec<f64>>();
+                let moment = if procar.lncl {
+                    (1 .. 4).map(|ic| row(ic).iter().sum()).collect()
+                } else if procar.nspin == 2 {
+                    vec![row(0).iter().sum::<f64>() - row(1).iter().sum::<f64>()]
+                } else {
+                    vec![]
+                };
+                SiteCharge {
+                    index: iion + 1,
+                    symbol: symbols.map(|s| s[iion].clone()).unwrap_or_else(|| format!("#{}", iion + 1)),
+                    total: charge_row.iter().sum(),
+                    charges,
+                    moment,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let nmoment = ions.first().map(|ion| ion.moment.len()).unwrap_or(0);
+        let mut species: Vec<SpeciesCharge> = vec![];
+        if symbols.is_some() {
+            for ion in ions.iter() {
+                let sp = match species.iter_mut().find(|s| s.symbol == ion.symbol) {
+                    Some(sp) => sp,
+                    None => {
+                        species.push(SpeciesCharge { symbol: ion.symbol.clone(), nions: 0, total: 0.0, moment: vec![0.0; nmoment] });
+                        species.last_mut().unwrap()
+                    },
+                };
+                sp.nions += 1;
+                sp.total += ion.total;
+                sp.moment.iter_mut().zip(ion.moment.iter()).for_each(|(s, m)| *s += m);
+            }
+        }
+
+        let total = ions.iter().map(|ion| ion.total).sum();
+        let moment = (0 .. nmoment)
+            .map(|ic| ions.iter().map(|ion| ion.moment[ic]).sum())
+            .collect();
+
+        Self {
+            shells: present.into_iter().map(|l| SHELLS[l].to_string()).collect(),
+            ions,
+            species,
+            total,
+            moment,
+        }
+    }
+
+    fn _moment_names(&self) -> Vec<&'static str> {
+        match self.moment.len() {
+            1 => vec!["mag"],
+            3 => vec!["mx", "my", "mz"],
+            _ => vec![],
+        }
+    }
+}
+
+impl Tabular for SiteProjection {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["index".to_string(), "symbol".to_string()];
+        ret.extend(self.shells.iter().cloned());
+        ret.push("tot".to_string());
+        ret.extend(self._moment_names().into_iter().map(|s| s.to_string()));
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.ions.iter()
+            .map(|ion| {
+                let mut row = vec![ion.index.to_string(), ion.symbol.clone()];
+                row.extend(ion.charges.iter().map(|c| format!("{:.4}", c)));
+                row.push(format!("{:.4}", ion.total));
+                row.extend(ion.moment.iter().map(|m| format!("{:.4}", m)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SiteProjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut header = "  #Ion Elem".to_string();
+        header += &self.shells.iter().map(|s| format!("{:>9}", s)).collect::<String>();
+        header += &format!("{:>9}", "tot");
+        header += &self._moment_names().iter().map(|s| format!("{:>9}", s)).collect::<String>();
+        writeln!(f, "{}", header.bright_green())?;
+
+        for ion in self.ions.iter() {
+            let mut line = format!("  {:4} {:>4}", ion.index, ion.symbol);
+            line += &ion.charges.iter().map(|c| format!("{:9.4}", c)).collect::<String>();
+            line += &format!("{:9.4}", ion.total).bright_yellow().to_string();
+            line += &ion.moment.iter().map(|m| format!("{:9.4}", m)).collect::<String>();
+            writeln!(f, "{}", line)?;
+        }
+
+        if !self.species.is_empty() {
+            writeln!(f, "{}", "# Electrons per species".bright_green())?;
+            for sp in self.species.iter() {
+                let values = sp.moment.iter().map(|m| format!("{:9.4}", m)).collect::<String>();
+                writeln!(f, "  {:>4} x {:<3}{:9.4}{}", sp.symbol, sp.nions, sp.total, values)?;
+            }
+        }
+
+        let values = self.moment.iter().map(|m| format!("{:9.4}", m)).collect::<String>();
+        writeln!(f, "{}{}{}", "# Total projected electrons".bright_green(),
+                 format!("{:9.4}", self.total).bright_yellow(), values)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_projection() {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    2         # of bands:   2         # of ions:   2
+
+ k-point     1 :    0.00000000 0.00000000 0.00000000     weight = 0.25000000
+
+band     1 # energy   -2.00000000 # occ.  1.00000000
+
+ion      s     py     pz     px    dxy    dyz    dz2    dxz  x2-y2    tot
+    1  0.200  0.100  0.000  0.100  0.000  0.000  0.000  0.000  0.200  0.600
+    2  0.300  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.300
+tot    0.500  0.100  0.000  0.100  0.000  0.000  0.000  0.000  0.200  0.900
+
+band     2 # energy    1.00000000 # occ.  0.00000000
+
+ion      s     py     pz     px    dxy    dyz    dz2    dxz  x2-y2    tot
+    1  0.500  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.500
+    2  0.500  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.500
+tot    1.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  1.000
+
+ k-point     2 :    0.50000000 0.00000000 0.00000000     weight = 0.75000000
+
+band     1 # energy   -1.00000000 # occ.  0.50000000
+
+ion      s     py     pz     px    dxy    dyz    dz2    dxz  x2-y2    tot
+    1  0.000  0.000  0.000  0.000  0.400  0.000  0.000  0.000  0.000  0.400
+    2  0.400  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.400
+tot    0.400  0.000  0.000  0.000  0.400  0.000  0.000  0.000  0.000  0.800
+
+band     2 # energy    2.00000000 # occ.  0.00000000
+
+ion      s     py     pz     px    dxy    dyz    dz2    dxz  x2-y2    tot
+    1  0.500  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.500
+    2  0.500  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.500
+tot    1.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  0.000  1.000
+
+"#;
+        let procar = Procar::parse(input);
+        let symbols = vec!["Ti".to_string(), "O".to_string()];
+        let proj = SiteProjection::from_procar(&procar, Some(&symbols));
+        assert_eq!(proj.shells, vec!["s", "p", "d"]);
+
+        // 2 electrons per state: 2 * (0.25 * 1.0 * x + 0.75 * 0.5 * y)
+        let close = |a: f64, b: f64| (a - b).abs() < 1E-10;
+        let ti = &proj.ions[0];
+        assert!(close(ti.charges[0], 0.1));
+        assert!(close(ti.charges[1], 0.1));
+        assert!(close(ti.charges[2], 0.1 + 0.3));
+        assert!(close(ti.total, 0.6));
+        assert!(ti.moment.is_empty());
+        assert!(close(proj.ions[1].total, 0.15 + 0.3));
+        assert_eq!(proj.species.len(), 2);
+        assert!(close(proj.total, 1.05));
+
+        let proj = SiteProjection::from_procar(&procar, None);
+        assert_eq!(proj.ions[1].symbol, "#2");
+        assert!(proj.species.is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+use std::io;
+use std::fmt;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use colored::Colorize;
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::outcar::Mat33;
+use crate::wannier::_symmetric_eigenvalues;
+use crate::symmetry::Rotation;
+use crate::constants::HBAR2_ME;
+use crate::linalg::{
+    Lu,
+    solve,
+};
+
+
+
+/// Shankland-Koelling-Wood interpolation of band energies with symmetrized plane waves (stars),
+/// which passes through the energies of the given k-points exactly and minimizes the roughness
+/// of the interpolated bands (Pickett, Krakauer and Allen, PRB 38, 2721 (1988)).
+///
+/// The factorization of the fitting matrix is shared by all the bands of the same k-points.
+#[derive(Clone, Debug)]
+pub struct SkwFit {
+    pub stars : Vec<Vec<[i32; 3]>>,  // lattice vectors of each star, the first one is R = 0
+    rho       : Vec<f64>,            // roughness of each star
+    svals     : Vec<Vec<f64>>,       // [nkpts][nstars], star functions at the k-points
+    lu        : Lu,                  // factorization of the fitting matrix
+}
+
+impl SkwFit {
+    /// `kpoints` in fractional coordinates, `cell` is the real-space lattice (rows), and
+    /// `lpfac` is the number of stars per k-point, usually 5 ~ 10.
+    pub fn new(kpoints: &[[f64; 3]], cell: &Mat33<f64>, ops: &[Rotation], lpfac: usize) -> io::Result<Self> {
+        let nkpts = kpoints.len();
+        if nkpts < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "At least 2 k-points are required for the interpolation"));
+        }
+        let stars = _generate_stars(cell, ops, nkpts * lpfac.max(1));
+        let length = |r: &[i32; 3]| {
+            let v = [0, 1, 2].map(|j| (0 .. 3).map(|i| r[i] as f64 * cell[i][j]).sum::<f64>());
+            (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+        };
+        let rmin = length(&stars[1][0]);
+        let rho = stars.iter()
+            .map(|s| {
+                let x = (length(&s[0]) / rmin).powi(2);
+                (1.0 - 0.75 * x).powi(2) + 0.75 * x.powi(3)
+            })
+            .collect::<Vec<f64>>();
+
+        let svals = kpoints.par_iter()
+            .map(|k| stars.iter().map(|s| _star_function(s, k)).collect::<Vec<f64>>())
+            .collect::<Vec<_>>();
+
+        // H_ij = sum_m (S_m(k_i) - S_m(k_N)) (S_m(k_j) - S_m(k_N)) / rho_m, m > 0
+        let last = &svals[nkpts - 1];
+        let ds = svals[.. nkpts - 1].iter()
+            .map(|s| (1 .. stars.len()).map(|m| s[m] - last[m]).collect::<Vec<f64>>())
+            .collect::<Vec<_>>();
+        let h = (0 .. nkpts - 1)
+            .into_par_iter()
+            .map(|i| (0 .. nkpts - 1).map(|j| {
+                ds[i].iter().zip(ds[j].iter()).zip(rho[1 ..].iter())
+                    .map(|((a, b), r)| a * b / r)
+                    .sum::<f64>()
+            }).collect::<Vec<f64>>())
+            .collect::<Vec<_>>();
+        let lu = Lu::new(h).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            "Singular fitting matrix, the k-points may contain equivalent ones"))?;
+
+        Ok(Self { stars, rho, svals, lu })
+    }
+
+    /// Fits the energies of one band at the k-points.
+    pub fn fit(&self, energies: &[f64]) -> SkwBand {
+        let n = self.svals.len();
+        assert_eq!(energies.len(), n, "Inconsistent number of energies and k-points");
+        let de = energies[.. n - 1].iter().map(|e| e - energies[n - 1]).collect::<Vec<f64>>();
+        let lambda = self.lu.solve(&de);
+
+        let last = &self.svals[n - 1];
+        let coeffs = (1 .. self.stars.len())
+            .map(|m| {
+                lambda.iter().zip(self.svals.iter())
+                    .map(|(l, s)| l * (s[m] - last[m]))
+                    .sum::<f64>() / self.rho[m]
+            })
+            .collect::<Vec<f64>>();
+        let c0 = energies[n - 1] - coeffs.iter().zip(last[1 ..].iter()).map(|(c, s)| c * s).sum::<f64>();
+
+        // Only one of R and -R is kept, the stars always contain both
+        let terms = self.stars[1 ..].iter()
+            .zip(coeffs.iter())
+            .flat_map(|(star, c)| {
+                let w = 2.0 * c / star.len() as f64;
+                star.iter()
+                    .filter(|r| r.iter().find(|x| **x != 0).is_some_and(|x| *x > 0))
+                    .map(move |r| (r.map(|x| x as f64), w))
+            })
+            .collect();
+        SkwBand { c0, terms }
+    }
+}
+
+
+/// Interpolated band, E(k) = c0 + sum w cos(2pi k.R) with `k` in fractional coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkwBand {
+    pub c0    : f64,
+    pub terms : Vec<([f64; 3], f64)>,  // (R, w)
+}
+
+impl SkwBand {
+    pub fn energy(&self, k: &[f64; 3]) -> f64 {
+        self.c0 + self.terms.iter()
+            .map(|(r, w)| w * (2.0 * PI * (k[0] * r[0] + k[1] * r[1] + k[2] * r[2])).cos())
+            .sum::<f64>()
+    }
+
+    /// Energy, gradient and Hessian with respect to the fractional coordinates.
+    pub fn derivatives(&self, k: &[f64; 3]) -> (f64, [f64; 3], Mat33<f64>) {
+        let mut e = self.c0;
+        let mut g = [0.0f64; 3];
+        let mut h = [[0.0f64; 3]; 3];
+        for (r, w) in self.terms.iter() {
+            let phase = 2.0 * PI * (k[0] * r[0] + k[1] * r[1] + k[2] * r[2]);
+            let (s, c) = phase.sin_cos();
+            e += w * c;
+            for i in 0 .. 3 {
+                g[i] -= w * s * 2.0 * PI * r[i];
+                for j in 0 .. 3 {
+                    h[i][j] -= w * c * 4.0 * PI * PI * r[i] * r[j];
+                }
+            }
+        }
+        (e, g, h)
+    }
+
+    /// Maximum (or minimum) on the dense grid of `dims`, refined off the grid by damped Newton
+    /// steps from the best grid points and `starts`.
+    pub fn extremum(&self, maximum: bool, dims: [usize; 3], starts: &[[f64; 3]]) -> ([f64; 3], f64) {
+        let sign = if maximum { 1.0 } else { -1.0 };
+        let grid = (0 .. dims[0] * dims[1] * dims[2])
+            .into_par_iter()
+            .map(|i| {
+                let idx = [i / (dims[1] * dims[2]), i / dims[2] % dims[1], i % dims[2]];
+                let k = [0, 1, 2].map(|j| idx[j] as f64 / dims[j] as f64);
+                (k, sign * self.energy(&k))
+            })
+            .collect::<Vec<_>>();
+        let best = |mut points: Vec<([f64; 3], f64)>| {
+            points.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            points.into_iter().take(4).map(|(k, _)| k).collect::<Vec<_>>()
+        };
+        let candidates = best(grid).into_iter()
+            .chain(best(starts.iter().map(|k| (*k, sign * self.energy(k))).collect()))
+            .collect::<Vec<_>>();
+
+        candidates.iter()
+            .map(|k| self._refine(*k, sign))
+            .max_by(|a, b| (sign * a.1).partial_cmp(&(sign * b.1)).unwrap())
+            .unwrap()
+    }
+
+    // Maximizes sign * E with Levenberg-Marquardt damped Newton steps
+    fn _refine(&self, mut k: [f64; 3], sign: f64) -> ([f64; 3], f64) {
+        let mut f = sign * self.energy(&k);
+        for _ in 0 .. 100 {
+            let (_, g, h) = self.derivatives(&k);
+            let g = g.map(|x| sign * x);
+            let scale = (0 .. 3).map(|i| h[i][i].abs()).fold(1E-6, f64::max);
+            let step = [0.0, 1E-4, 1E-3, 1E-2, 1E-1, 1.0, 1E1, 1E2].iter()
+                .filter_map(|mu| {
+                    let a = [0, 1, 2].map(|i| [0, 1, 2].map(|j| {
+                        -sign * h[i][j] + if i == j { mu * scale } else { 0.0 }
+                    }));
+                    let d = solve(a.iter().map(|r| r.to_vec()).collect(), &g)?;
+                    let knew = [0, 1, 2].map(|i| k[i] + d[i]);
+                    let fnew = sign * self.energy(&knew);
+                    if fnew > f { Some((knew, fnew, d)) } else { None }
+                })
+                .next();
+            match step {
+                Some((knew, fnew, d)) => {
+                    k = knew;
+                    f = fnew;
+                    if d.iter().all(|x| x.abs() < 1E-9) { break; }
+                },
+                None => break,
+            }
+        }
+        (k.map(|x| x - x.floor()), sign * f)
+    }
+
+    /// Effective masses in m_e along the principal axes at `k`, from the Hessian in Cartesian
+    /// coordinates. Negative for maxima, infinite along flat directions.
+    pub fn effective_masses(&self, k: &[f64; 3], cell: &Mat33<f64>) -> [f64; 3] {
+        let (_, _, h) = self.derivatives(k);
+        let hcart = [0, 1, 2].map(|a| [0, 1, 2].map(|b| {
+            (0 .. 3).flat_map(|i| (0 .. 3).map(move |j| (i, j)))
+                .map(|(i, j)| cell[i][a] * h[i][j] * cell[j][b])
+                .sum::<f64>() / (4.0 * PI * PI)
+        }));
+        let eigs = _symmetric_eigenvalues(hcart.iter().map(|r| r.to_vec()).collect());
+        let mut masses = [0, 1, 2].map(|i| if eigs[i].abs() < 1E-6 { f64::INFINITY.copysign(eigs[i]) } else { HBAR2_ME / eigs[i] });
+        masses.sort_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap());
+        masses
+    }
+}
+
+
+fn _star_function(star: &[[i32; 3]], k: &[f64; 3]) -> f64 {
+    star.iter()
+        .map(|r| (2.0 * PI * (k[0] * r[0] as f64 + k[1] * r[1] as f64 + k[2] * r[2] as f64)).cos())
+        .sum::<f64>() / star.len() as f64
+}
+
+
+// At least `nstars` stars of the lattice vectors in ascending lengths, R = 0 first
+fn _generate_stars(cell: &Mat33<f64>, ops: &[Rotation], nstars: usize) -> Vec<Vec<[i32; 3]>> {
+    let volume = crate::summary::_volume(cell).abs();
+    let recip = crate::fscorr::_reciprocal(cell);
+    let norm = |v: &[f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    let cart = |r: &[i32; 3]| [0, 1, 2].map(|j| (0 .. 3).map(|i| r[i] as f64 * cell[i][j]).sum::<f64>());
+
+    // Stars have at most |G| members, some spare points for the partially filled shells
+    let mut npoints = (2 * nstars * ops.len()) as f64;
+    loop {
+        let rmax = (3.0 * volume * npoints / (4.0 * PI)).cbrt();
+        let nmax = [0, 1, 2].map(|i| (rmax * norm(&recip[i]) / (2.0 * PI)).ceil() as i32);
+        let mut points = vec![];
+        for i in -nmax[0] ..= nmax[0] {
+            for j in -nmax[1] ..= nmax[1] {
+                for k in -nmax[2] ..= nmax[2] {
+                    let r = [i, j, k];
+                    let len = norm(&cart(&r));
+                    if len <= rmax { points.push((len, r)); }
+                }
+            }
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut visited = HashSet::new();
+        let mut stars = vec![];
+        for (_, r) in points.iter() {
+            if visited.contains(r) { continue; }
+            let mut star = vec![];
+            for w in ops.iter() {
+                let rr = [0, 1, 2].map(|i| (0 .. 3).map(|j| w[i][j] * r[j]).sum::<i32>());
+                if visited.insert(rr) {
+                    star.push(rr);
+                }
+            }
+            stars.push(star);
+        }
+
+        if stars.len() >= nstars {
+            stars.truncate(nstars.max(2));
+            return stars;
+        }
+        npoints *= 2.0;
+    }
+}
+
+
+/// Band edge located by the interpolation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BandExtremum {
+    pub ispin  : usize,     // starts from 1
+    pub iband  : usize,     // starts from 1
+    pub kpoint : [f64; 3],  // fractional coordinates
+    pub energy : f64,
+    pub masses : [f64; 3],  // effective masses of the carriers in m_e, positive for holes at VBM
+}
+
+
+/// Band gap from the interpolated bands, compared with the one on the explicit k-points.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InterpolatedGap {
+    pub vbm      : BandExtremum,
+    pub cbm      : BandExtremum,
+    pub gap      : f64,
+    pub direct   : bool,
+    pub mesh_gap : f64,
+}
+
+impl InterpolatedGap {
+    pub fn new(vbm: BandExtremum, cbm: BandExtremum, mesh_gap: f64) -> Self {
+        let direct = (0 .. 3).all(|i| {
+            let d = vbm.kpoint[i] - cbm.kpoint[i];
+            (d - d.round()).abs() < 1E-3
+        });
+        Self {
+            gap: (cbm.energy - vbm.energy).max(0.0),
+            direct,
+            vbm,
+            cbm,
+            mesh_gap,
+        }
+    }
+}
+
+impl Tabular for InterpolatedGap {
+    fn headers(&self) -> Vec<String> {
+        ["edge", "spin", "band", "kx", "ky", "kz", "energy", "m1", "m2", "m3"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        [("VBM", &self.vbm), ("CBM", &self.cbm)].iter()
+            .map(|(name, e)| {
+                let mut row = vec![name.to_string(), e.ispin.to_string(), e.iband.to_string()];
+                row.extend(e.kpoint.iter().map(|k| format!("{:.5}", k)));
+                row.push(format!("{:.4}", e.energy));
+                row.extend(e.masses.iter().map(|m| format!("{:.4}", m)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for InterpolatedGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", "# Interpolated band edges, effective masses in m_e".bright_green())?;
+        for (name, e) in [("VBM", &self.vbm), ("CBM", &self.cbm)].iter() {
+            writeln!(f, "{:>10} = {:10.4}  at ({:8.5} {:8.5} {:8.5}), spin {} band {}, m* = {:.3} {:.3} {:.3}",
+                     name.bright_green(), e.energy, e.kpoint[0], e.kpoint[1], e.kpoint[2],
+                     e.ispin, e.iband, e.masses[0], e.masses[1], e.masses[2])?;
+        }
+        writeln!(f, "{:>10} = {}  ({}, {:.4} on the mesh)", "Gap".bright_green(),
+                 format!("{:10.4}", self.gap).bright_yellow(),
+                 if self.gap <= 0.0 { "metallic" } else if self.direct { "direct" } else { "indirect" },
+                 self.mesh_gap)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Structure;
+    use crate::symmetry::point_group;
+
+    #[test]
+    fn test_skw_interpolation() {
+        // Simple cubic lattice of 3 A with one atom, full Oh group
+        let cell = [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]];
+        let structure = Structure {
+            cell,
+            ion_types: vec!["H".to_string()],
+            ions_per_type: vec![1],
+            car_pos: vec![[0.0; 3]],
+            frac_pos: vec![[0.0; 3]],
+        };
+        let ops = point_group(Some(&structure), 0.01);
+        assert_eq!(ops.len(), 48);
+        assert_eq!(point_group(None, 0.01).len(), 2);
+
+        // Tight-binding band, E = -cos(2pi kx) - cos(2pi ky) - cos(2pi kz), on the irreducible
+        // part of a 4x4x4 mesh
+        let band = |k: &[f64; 3]| -k.iter().map(|x| (2.0 * PI * x).cos()).sum::<f64>();
+        let mut kpoints = vec![];
+        for i in 0 ..= 2 {
+            for j in 0 ..= i {
+                for l in 0 ..= j {
+                    kpoints.push([i as f64 / 4.0, j as f64 / 4.0, l as f64 / 4.0]);
+                }
+            }
+        }
+        let energies = kpoints.iter().map(band).collect::<Vec<f64>>();
+        let fit = SkwFit::new(&kpoints, &cell, &ops, 5).unwrap();
+        let skw = fit.fit(&energies);
+        for (k, e) in kpoints.iter().zip(energies.iter()) {
+            assert!((skw.energy(k) - e).abs() < 1E-8);
+        }
+        let k = [0.1, 0.2, 0.35];
+        assert!((skw.energy(&k) - band(&k)).abs() < 5E-3);
+
+        // Minimum at Gamma, the mass is hbar^2 / (m_e * 4pi^2 * a^2 / (2pi)^2) along all directions
+        let (kmin, emin) = skw.extremum(false, [5, 5, 5], &kpoints);
+        assert!((emin + 3.0).abs() < 1E-8);
+        assert!(kmin.iter().all(|x| x.min(1.0 - x) < 1E-4));
+        let masses = skw.effective_masses(&kmin, &cell);
+        assert!(masses.iter().all(|m| (m / (HBAR2_ME / 9.0) - 1.0).abs() < 0.05));
+
+        // Maximum at R, off the 5x5x5 grid
+        let (kmax, emax) = skw.extremum(true, [5, 5, 5], &[]);
+        assert!((emax - 3.0).abs() < 1E-8);
+        assert!(kmax.iter().all(|x| (x - 0.5).abs() < 1E-4));
+    }
+}
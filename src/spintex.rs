@@ -0,0 +1,190 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde_json::json;
+use log::info;
+use crate::outcar::{
+    Mat33,
+    MatX3,
+};
+use crate::procar::Procar;
+use crate::format::_calc_inv_3x3;
+use crate::plot::Plot;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpinTexture {
+    pub iband     : usize,        // starts from 1
+    pub lcart     : bool,         // whether the k-points are in cartesian coordinates
+    pub kpoints   : MatX3<f64>,
+    pub spins     : MatX3<f64>,   // <sigma_x>, <sigma_y>, <sigma_z>
+    pub energies  : Vec<f64>,
+}
+
+impl SpinTexture {
+    /// `iband` starts from 1. If `cell` is given, k-points are converted to cartesian
+    /// coordinates in 1/Angstrom, with the 2pi factor excluded.
+    pub fn from_procar(procar: &Procar, iband: usize, cell: Option<&Mat33<f64>>) -> Self {
+        assert!(1 <= iband && iband <= procar.nbands, "Band index out of bound.");
+        let spins = procar.spin_texture(iband - 1);
+        let energies = (0 .. procar.nkpts)
+            .map(|ik| procar.eigval(0, ik, iband - 1))
+            .collect::<Vec<f64>>();
+
+        let kpoints = match cell {
+            Some(cell) => {
+                // Reciprocal lattice vectors are the columns of inv(cell)
+                let inv = _calc_inv_3x3(cell);
+                procar.kpoints.iter()
+                    .map(|k| {
+                        [
+                            k[0] * inv[0][0] + k[1] * inv[0][1] + k[2] * inv[0][2],
+                            k[0] * inv[1][0] + k[1] * inv[1][1] + k[2] * inv[1][2],
+                            k[0] * inv[2][0] + k[1] * inv[2][1] + k[2] * inv[2][2],
+                        ]
+                    })
+                    .collect()
+            },
+            None => procar.kpoints.clone(),
+        };
+
+        Self {
+            iband,
+            lcart: cell.is_some(),
+            kpoints,
+            spins,
+            energies,
+        }
+    }
+
+    fn _prepare_fname(&self, path: &(impl AsRef<Path> + ?Sized), ext: &str) -> io::Result<PathBuf> {
+        let mut fname = PathBuf::new();
+        fname.push(path);
+        if !fname.is_dir() {
+            fs::create_dir_all(&fname)?;
+        }
+        fname.push(format!("spintex_band_{:04}.{}", self.iband, ext));
+        Ok(fname)
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = self._prepare_fname(path, "txt")?;
+        info!("Saving spin texture of band #{} to {:?} ...", self.iband, &fname);
+
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# Spin texture of band #{}, k-points in {} coordinates",
+                 self.iband, if self.lcart { "cartesian (1/A)" } else { "fractional" })?;
+        writeln!(f, "# {:>12} {:>12} {:>12} {:>12} {:>9} {:>9} {:>9}",
+                 "kx", "ky", "kz", "E/eV", "sx", "sy", "sz")?;
+        for (k, e, s) in itertools::multizip((&self.kpoints, &self.energies, &self.spins)) {
+            writeln!(f, "  {:12.8} {:12.8} {:12.8} {:12.6} {:9.4} {:9.4} {:9.4}",
+                     k[0], k[1], k[2], e, s[0], s[1], s[2])?;
+        }
+        Ok(())
+    }
+
+    /// In-plane components are drawn as arrows over the kx-ky plane, and the out-of-plane
+    /// component is shown as the color of markers.
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = self._prepare_fname(path, "html")?;
+
+        let kx = self.kpoints.iter().map(|k| k[0]).collect::<Vec<f64>>();
+        let ky = self.kpoints.iter().map(|k| k[1]).collect::<Vec<f64>>();
+        let sz = self.spins.iter().map(|s| s[2]).collect::<Vec<f64>>();
+
+        // Scale the arrows to make the longest one comparable to the k-point spacing
+        let smax = self.spins.iter()
+            .map(|s| (s[0] * s[0] + s[1] * s[1]).sqrt())
+            .fold(0.0f64, f64::max);
+        let kspan = {
+            let span = |v: &[f64]| v.iter().cloned().fold(f64::MIN, f64::max) - v.iter().cloned().fold(f64::MAX, f64::min);
+            span(&kx).max(span(&ky))
+        };
+        let scale = if smax > 0.0 {
+            kspan / (self.kpoints.len() as f64).sqrt().max(1.0) / smax * 0.8
+        } else { 0.0 };
+
+        // Each arrow is a line segment, separated by nulls
+        let mut ax: Vec<Option<f64>> = vec![];
+        let mut ay: Vec<Option<f64>> = vec![];
+        for (k, s) in self.kpoints.iter().zip(self.spins.iter()) {
+            ax.extend(&[Some(k[0]), Some(k[0] + s[0] * scale), None]);
+            ay.extend(&[Some(k[1]), Some(k[1] + s[1] * scale), None]);
+        }
+
+        let unit = if self.lcart { " (1/A)" } else { " (frac)" };
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Spin texture of band #{}", self.iband),
+                "xaxis": {"title": format!("kx{}", unit)},
+                "yaxis": {"title": format!("ky{}", unit), "scaleanchor": "x"},
+                "showlegend": false,
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines",
+            "x": ax,
+            "y": ay,
+            "line": {"color": "black", "width": 1.5},
+            "hoverinfo": "skip",
+        }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "markers",
+            "x": kx,
+            "y": ky,
+            "marker": {
+                "color": sz,
+                "colorscale": "RdBu",
+                "cmin": -1.0,
+                "cmax": 1.0,
+                "size": 8,
+                "colorbar": {"title": "sz"},
+            },
+        }));
+        plot.save_html(&fname)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cartesian_kpoints() {
+        let input = r#"PROCAR lm decomposed
+# of k-points:    1         # of bands:   1         # of ions:   1
+
+ k-point     1 :    0.50000000 0.25000000 0.00000000     weight = 1.00000000
+
+band     1 # energy   -1.00000000 # occ.  1.00000000
+
+ion      s    tot
+    1  1.000  1.000
+tot    1.000  1.000
+    1  0.100  0.100
+tot    0.100  0.100
+    1  0.200  0.200
+tot    0.200  0.200
+    1  0.300  0.300
+tot    0.300  0.300
+
+"#;
+        let procar = Procar::parse(input);
+        let cell = [[2.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 10.0]];
+        let st = SpinTexture::from_procar(&procar, 1, Some(&cell));
+        assert_eq!(st.kpoints, vec![[0.25, 0.0625, 0.0]]);
+        assert_eq!(st.spins, vec![[0.1, 0.2, 0.3]]);
+        assert_eq!(st.energies, vec![-1.0]);
+    }
+}
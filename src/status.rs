@@ -0,0 +1,443 @@
+use std::io;
+use std::io::{
+    BufRead,
+    Read,
+    Seek,
+    SeekFrom,
+};
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::time::SystemTime;
+use serde::Serialize;
+use colored::Colorize;
+use log::warn;
+use crate::traits::Tabular;
+use crate::oszicar::Oszicar;
+
+
+// Only the header and the tail of long OUTCARs are read for the job progress
+const HEAD_BYTES: u64 = 4 << 20;
+const TAIL_BYTES: u64 = 8 << 20;
+
+
+/// Progress of a calculation scanned from OUTCAR line by line, only the lines with the
+/// parameters, iteration headers, energy changes and timings are looked at, thus it is much
+/// cheaper than parsing the whole OUTCAR.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobProgress {
+    pub ediff     : f64,
+    pub nsw       : i32,
+    pub nelm      : i32,
+    pub ibrion    : i32,
+    pub ionic_step: usize,         // current ionic step, from "Iteration  N(  M)"
+    pub scf_step  : usize,         // current electronic step in this ionic step
+    pub last_de   : Option<f64>,   // the latest "total energy-change (2. order)"
+    pub nloops    : usize,         // finished ionic steps, i.e. number of "LOOP+"
+    pub loop_cpu  : f64,
+    pub loop_real : f64,
+    pub cpu_time  : Option<f64>,   // "Total CPU time used (sec)" of finished jobs
+    pub elapsed   : Option<f64>,   // "Elapsed time (sec)" of finished jobs
+    pub reached   : bool,          // "reached required accuracy"
+}
+
+impl JobProgress {
+    fn new() -> Self {
+        Self {
+            ediff: 1E-4,
+            nelm: 60,
+            ibrion: -1,
+            ..Default::default()
+        }
+    }
+
+    /// Scans the whole OUTCAR given by `reader`.
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut ret = Self::new();
+        ret.scan(reader, false)?;
+        Ok(ret)
+    }
+
+    /// Reads the INCAR tags from the header of OUTCAR and the progress from its last 8 MiB,
+    /// thus the cost doesn't grow with the length of OUTCAR. The times of the finished ionic
+    /// steps are extrapolated from the ones in the tail. The current ionic and electronic
+    /// steps and the energy change are taken from OSZICAR next to OUTCAR if it exists.
+    ///
+    /// Only plain OUTCAR is supported, the gzipped one and vasprun.xml are rejected.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::_from_file(path, HEAD_BYTES, TAIL_BYTES)
+    }
+
+    fn _from_file(path: &Path, head_bytes: u64, tail_bytes: u64) -> io::Result<Self> {
+        if path.extension().is_some_and(|e| e == "gz" || e == "xml") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Only plain OUTCAR is scanned for the job progress, {:?} is not supported", path)));
+        }
+
+        let mut f = fs::File::open(path)?;
+        let len = f.metadata()?.len();
+        let mut ret = if len <= head_bytes + tail_bytes {
+            Self::from_reader(io::BufReader::new(f))?
+        } else {
+            let mut ret = Self::new();
+            ret.scan(io::BufReader::new((&mut f).take(head_bytes)), true)?;
+
+            f.seek(SeekFrom::Start(len - tail_bytes))?;
+            let mut tail = io::BufReader::new(f);
+            tail.read_until(b'\n', &mut vec![])?;  // the partial line
+            if let Some(finished) = ret.scan(tail, false)? {
+                let scale = finished as f64 / ret.nloops as f64;
+                ret.loop_cpu *= scale;
+                ret.loop_real *= scale;
+                ret.nloops = finished;
+            }
+            ret
+        };
+
+        let oszicar = path.with_file_name("OSZICAR");
+        if oszicar.is_file() {
+            ret.update_from_oszicar(&Oszicar::from_file(&oszicar)?);
+        }
+        Ok(ret)
+    }
+
+    // Scans the lines of `reader`, stops at the first electronic step if `head` is set.
+    // Returns the ionic step finished by the last "LOOP+".
+    fn scan(&mut self, mut reader: impl BufRead, head: bool) -> io::Result<Option<usize>> {
+        let mut finished = None;
+
+        // Bytes instead of String, the OUTCAR of a running job may end with a partial line
+        let mut buf = Vec::<u8>::new();
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            let line = String::from_utf8_lossy(&buf);
+            let s = line.trim();
+
+            if s.starts_with("LOOP+:") {
+                let t = _times(s);
+                self.nloops += 1;
+                self.loop_cpu += t.0;
+                self.loop_real += t.1;
+                finished = Some(self.ionic_step);
+            } else if s.starts_with("------") && s.contains("Iteration") {
+                if head { break; }
+                if let Some((i, j)) = _iteration(s) {
+                    self.ionic_step = i;
+                    self.scf_step = j;
+                }
+            } else if let Some(v) = s.strip_prefix("total energy-change (2. order) :") {
+                self.last_de = v.split('(').next().and_then(|x| x.trim().parse().ok());
+            } else if s.starts_with("reached required accuracy") {
+                self.reached = true;
+            } else if let Some(v) = s.strip_prefix("Total CPU time used (sec):") {
+                self.cpu_time = v.trim().parse().ok();
+            } else if let Some(v) = s.strip_prefix("Elapsed time (sec):") {
+                self.elapsed = v.trim().parse().ok();
+            } else if let Some(v) = s.strip_prefix("EDIFF  =") {
+                self.ediff = _first_number(v).unwrap_or(self.ediff);
+            } else if let Some(v) = s.strip_prefix("NSW    =") {
+                self.nsw = _first_number(v).map(|x| x as i32).unwrap_or(self.nsw);
+            } else if let Some(v) = s.strip_prefix("NELM   =") {
+                self.nelm = _first_number(v.split(';').next().unwrap_or(v)).map(|x| x as i32).unwrap_or(self.nelm);
+            } else if let Some(v) = s.strip_prefix("IBRION =") {
+                self.ibrion = _first_number(v).map(|x| x as i32).unwrap_or(self.ibrion);
+            }
+            buf.clear();
+        }
+        Ok(finished)
+    }
+
+    // OSZICAR is written after each electronic step, thus it's more up to date than OUTCAR
+    fn update_from_oszicar(&mut self, oszicar: &Oszicar) {
+        let (step, scf) = if oszicar.pending.is_empty() {
+            match oszicar.ionic_steps.last() {
+                Some(last) => (oszicar.ionic_steps.len(), &last.scf),
+                None => return,
+            }
+        } else {
+            (oszicar.ionic_steps.len() + 1, &oszicar.pending)
+        };
+        self.ionic_step = step;
+        self.scf_step = scf.len();
+        self.last_de = scf.last().map(|s| s.de).or(self.last_de);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    pub fn state(&self) -> JobState {
+        if self.ionic_step == 0 {
+            return JobState::NotStarted;
+        }
+        if !self.is_finished() {
+            return JobState::Running;
+        }
+        let scf_converged = self.last_de.is_some_and(|de| de.abs() < self.ediff)
+            && (self.scf_step as i32) < self.nelm;
+        match self.ibrion {
+            // Molecular dynamics runs all the NSW steps without criterion of the ionic steps
+            0 if self.nsw > 0 => JobState::Finished,
+            // Relaxations stop before NSW steps only when EDIFFG is reached
+            1 ..= 3 if self.nsw > 0 => if self.reached { JobState::Converged } else { JobState::Unconverged },
+            _ => if scf_converged { JobState::Converged } else { JobState::Unconverged },
+        }
+    }
+
+    /// Estimated remaining time in seconds from the average time of the finished ionic steps.
+    /// For relaxations it's the upper bound, as they may converge before NSW.
+    pub fn eta(&self) -> Option<f64> {
+        if self.is_finished() || self.nloops == 0 || self.nsw <= 0 {
+            return None;
+        }
+        let remaining = (self.nsw as usize).saturating_sub(self.nloops);
+        Some(self.loop_real / self.nloops as f64 * remaining as f64)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Missing,      // no OUTCAR in the directory
+    NotStarted,
+    Running,
+    Converged,
+    Unconverged,
+    Finished,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JobState::Missing     => "missing",
+            JobState::NotStarted  => "not started",
+            JobState::Running     => "running",
+            JobState::Converged   => "converged",
+            JobState::Unconverged => "unconverged",
+            JobState::Finished    => "finished",
+        };
+        f.pad(s)
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct JobStatus {
+    pub dir        : PathBuf,
+    pub state      : JobState,
+    pub ionic_step : usize,
+    pub nsw        : i32,
+    pub scf_step   : usize,
+    pub last_de    : Option<f64>,
+    pub cpu_time   : f64,          // in seconds
+    pub elapsed    : f64,          // in seconds
+    pub idle       : Option<f64>,  // seconds since the last modification of OUTCAR
+    pub eta        : Option<f64>,  // in seconds, for the unfinished jobs
+}
+
+impl JobStatus {
+    pub fn from_dir(dir: &Path, outcar: &str) -> io::Result<Self> {
+        let path = dir.join(outcar);
+        if !path.is_file() {
+            warn!("{:?} not found.", &path);
+            return Ok(Self {
+                dir: dir.to_path_buf(),
+                state: JobState::Missing,
+                ionic_step: 0,
+                nsw: 0,
+                scf_step: 0,
+                last_de: None,
+                cpu_time: 0.0,
+                elapsed: 0.0,
+                idle: None,
+                eta: None,
+            });
+        }
+
+        let idle = fs::metadata(&path)?
+            .modified().ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .map(|d| d.as_secs_f64());
+        let p = JobProgress::from_file(&path)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            state: p.state(),
+            ionic_step: p.ionic_step,
+            nsw: p.nsw,
+            scf_step: p.scf_step,
+            last_de: p.last_de,
+            cpu_time: p.cpu_time.unwrap_or(p.loop_cpu),
+            elapsed: p.elapsed.unwrap_or(p.loop_real),
+            idle: if p.is_finished() { None } else { idle },
+            eta: p.eta(),
+        })
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatusTable(pub Vec<JobStatus>);
+
+impl Tabular for StatusTable {
+    fn headers(&self) -> Vec<String> {
+        ["dir", "state", "ionic_step", "nsw", "scf_step", "last_de", "cpu_time", "elapsed", "idle", "eta"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let opt = |v: Option<f64>| v.map(|x| x.to_string()).unwrap_or_default();
+        self.0.iter()
+            .map(|j| vec![
+                j.dir.display().to_string(),
+                j.state.to_string(),
+                j.ionic_step.to_string(),
+                j.nsw.to_string(),
+                j.scf_step.to_string(),
+                opt(j.last_de),
+                j.cpu_time.to_string(),
+                j.elapsed.to_string(),
+                opt(j.idle),
+                opt(j.eta),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for StatusTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>11} {:>9} {:>5} {:>11} {:>10} {:>10} {:>8} {:>10}  Directory",
+                                  "State", "Ionic", "SCF", "dE(eV)", "CPU", "Elapsed", "Idle", "ETA").bright_green())?;
+        for j in self.0.iter() {
+            let ionic = if j.nsw > 0 { format!("{}/{}", j.ionic_step, j.nsw) } else { j.ionic_step.to_string() };
+            let de = j.last_de.map(|x| format!("{:11.3E}", x)).unwrap_or_else(|| format!("{:>11}", "-"));
+            let line = format!("  {:>11} {:>9} {:>5} {} {:>10} {:>10} {:>8} {:>10}  {}",
+                               j.state, ionic, j.scf_step, de,
+                               _duration(Some(j.cpu_time)), _duration(Some(j.elapsed)),
+                               _duration(j.idle), _duration(j.eta), j.dir.display());
+            match j.state {
+                JobState::Converged | JobState::Finished => writeln!(f, "{}", line.bright_yellow())?,
+                JobState::Unconverged | JobState::Missing => writeln!(f, "{}", line.bright_red())?,
+                _ => writeln!(f, "{}", line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// "LOOP+:  cpu time  109.68: real time  146.91"
+fn _times(line: &str) -> (f64, f64) {
+    let nums = line.split(':')
+        .filter_map(|s| s.split_whitespace().last().and_then(|x| x.parse::<f64>().ok()))
+        .collect::<Vec<_>>();
+    (nums.first().copied().unwrap_or(0.0), nums.get(1).copied().unwrap_or(0.0))
+}
+
+// "----- Iteration    1(  11)  -----"
+fn _iteration(line: &str) -> Option<(usize, usize)> {
+    let s = line.trim_matches('-').trim().strip_prefix("Iteration")?;
+    let (i, j) = s.split_once('(')?;
+    let j = j.split(')').next()?;
+    Some((i.trim().parse().ok()?, j.trim().parse().ok()?))
+}
+
+fn _first_number(s: &str) -> Option<f64> {
+    s.split_whitespace().next()?.parse().ok()
+}
+
+fn _duration(secs: Option<f64>) -> String {
+    match secs {
+        None => "-".to_string(),
+        Some(s) => {
+            let s = s.round() as u64;
+            if s >= 86400 {
+                format!("{}d{:02}h", s / 86400, s % 86400 / 3600)
+            } else {
+                format!("{}:{:02}:{:02}", s / 3600, s % 3600 / 60, s % 60)
+            }
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const HEAD: &str = "   NELM   =     60;   NELMIN=  2; NELMDL= -5     # of ELM steps
+   EDIFF  = 0.1E-05   stopping-criterion for ELM
+   EDIFFG = -.1E-01   stopping-criterion for IOM
+   NSW    =    100    number of steps for IOM
+   IBRION =      1    ionic relax: 0-MD 1-quasi-New 2-CG
+----------------------------------------- Iteration    1(   1)  ---------------------------------------
+ total energy-change (2. order) : 0.6810147E+03  (-0.5383683E+04)
+----------------------------------------- Iteration    1(   2)  ---------------------------------------
+ total energy-change (2. order) :-0.4000000E-06  (-0.8535429E+03)
+     LOOP+:  cpu time  100.00: real time  120.00
+----------------------------------------- Iteration    2(   1)  ---------------------------------------
+ total energy-change (2. order) :-0.8806071E+03  (-0.8535429E+03)
+     LOOP+:  cpu time   80.00: real time   80.00
+----------------------------------------- Iteration    3(   1)  ---------------------";
+
+    #[test]
+    fn test_job_progress() {
+        let p = JobProgress::from_reader(HEAD.as_bytes()).unwrap();
+        assert_eq!(p.ediff, 1E-6);
+        assert_eq!((p.nsw, p.nelm, p.ibrion), (100, 60, 1));
+        assert_eq!((p.ionic_step, p.scf_step, p.nloops), (3, 1, 2));
+        assert_eq!(p.last_de, Some(-880.6071));
+        assert_eq!(p.state(), JobState::Running);
+        assert_eq!(p.eta(), Some(100.0 * 98.0));
+
+        let text = format!("{}\n reached required accuracy - stopping structural energy minimisation
+                  Total CPU time used (sec):      354.671
+                         Elapsed time (sec):      490.223\n", HEAD);
+        let p = JobProgress::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(p.state(), JobState::Converged);
+        assert_eq!(p.elapsed, Some(490.223));
+        assert_eq!(p.eta(), None);
+
+        assert_eq!(JobProgress::from_reader("".as_bytes()).unwrap().state(), JobState::NotStarted);
+        assert_eq!(JobStatus::from_dir(Path::new("tests"), "OUTCAR").unwrap().state, JobState::Missing);
+        assert_eq!(_duration(Some(3725.0)), "1:02:05");
+        assert_eq!(_duration(Some(90000.0)), "1d01h");
+    }
+
+    #[test]
+    fn test_job_progress_from_file() {
+        let dir = TempDir::new("rsgrad_status").unwrap();
+        let path = dir.path().join("OUTCAR");
+        let mut text = HEAD.lines().take(5).collect::<Vec<_>>().join("\n");
+        for i in 1 ..= 50 {
+            text += &format!("\n----------------------------------------- Iteration {:4}(   1)  -----------
+ total energy-change (2. order) :-0.{:02}00000E-06  (-0.8535429E+03)
+     LOOP+:  cpu time   10.00: real time   20.00", i, i);
+        }
+        fs::write(&path, &text).unwrap();
+
+        // Only the first and last 500 bytes are read
+        let p = JobProgress::_from_file(&path, 500, 500).unwrap();
+        assert!(p.nloops == 50 && text.len() > 1000);
+        assert_eq!((p.ediff, p.nsw, p.ibrion), (1E-6, 100, 1));
+        assert_eq!((p.ionic_step, p.scf_step, p.last_de), (50, 1, Some(-5E-7)));
+        assert!((p.loop_cpu - 500.0).abs() < 1E-9 && (p.loop_real - 1000.0).abs() < 1E-9);
+        assert_eq!(p.state(), JobState::Running);
+
+        fs::write(dir.path().join("OSZICAR"), "\
+DAV:   1     0.425437171237E+03    0.42544E+03   -0.21160E+04  2984   0.101E+03
+   1 F= -.24002006E+02 E0= -.24001849E+02  d E =-.240020E+02
+DAV:   1    -0.24101150E+02   -0.10000E+00   -0.54437E-02  3148   0.113E+00
+RMM:   2    -0.24101150E+02   -0.20000E-02   -0.54437E-02  3148   0.113E+00
+").unwrap();
+        let p = JobProgress::_from_file(&path, 500, 500).unwrap();
+        assert_eq!((p.ionic_step, p.scf_step, p.last_de), (2, 2, Some(-0.002)));
+
+        let err = JobProgress::from_file(Path::new("OUTCAR.gz")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
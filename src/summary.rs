@@ -0,0 +1,297 @@
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use colored::Colorize;
+use vasp_poscar::Poscar;
+use crate::traits::Tabular;
+use crate::outcar::{
+    Outcar,
+    Mat33,
+    MatX3,
+};
+
+
+// One-line summaries of OUTCAR, used by `rsgrad gap` and `rsgrad batch`
+pub trait Summary: Serialize + Sized {
+    /// `dir` is the directory containing the OUTCAR, where POSCAR is looked for if needed.
+    fn from_outcar(outcar: &Outcar, dir: &Path) -> Self;
+    fn csv_header() -> Vec<&'static str>;
+    fn csv_row(&self) -> Vec<String>;
+}
+
+
+// Each summary is one row of the table
+impl<T: Summary> Tabular for T {
+    fn headers(&self) -> Vec<String> {
+        T::csv_header().into_iter().map(|h| h.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![self.csv_row()]
+    }
+}
+
+
+pub(crate) fn _volume(cell: &Mat33<f64>) -> f64 {
+    let c = cell;
+    c[0][0] * (c[1][1] * c[2][2] - c[2][1] * c[1][2])
+        - c[0][1] * (c[1][0] * c[2][2] - c[1][2] * c[2][0])
+        + c[0][2] * (c[1][0] * c[2][1] - c[1][1] * c[2][0])
+}
+
+
+// Maximum force on the relaxed ions, fixed directions in POSCAR are excluded
+pub(crate) fn _fmax(forces: &MatX3<f64>, dynamics: &[[bool; 3]]) -> f64 {
+    forces.iter()
+        .zip(dynamics.iter())
+        .map(|(f, d)| {
+            let d = [d[0] as i32 as f64, d[1] as i32 as f64, d[2] as i32 as f64];
+            (f[0] * f[0] * d[0] + f[1] * f[1] * d[1] + f[2] * f[2] * d[2]).sqrt()
+        })
+        .fold(0.0f64, f64::max)
+}
+
+
+pub(crate) fn _read_dynamics(dir: &Path, nions: usize) -> Vec<[bool; 3]> {
+    Poscar::from_path(dir.join("POSCAR"))
+        .ok()
+        .and_then(|p| p.into_raw().dynamics)
+        .filter(|d| d.len() == nions)
+        .unwrap_or_else(|| vec![[true; 3]; nions])
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BriefInfo {
+    pub ibrion  : i32,
+    pub nkpts   : i32,
+    pub nions   : i32,
+    pub nsw     : usize,  // number of ionic steps found in OUTCAR
+    pub ispin   : i32,
+    pub lsorbit : bool,
+    pub efermi  : f64,
+    pub nbands  : i32,
+}
+
+impl Summary for BriefInfo {
+    fn from_outcar(outcar: &Outcar, _dir: &Path) -> Self {
+        Self {
+            ibrion: outcar.ibrion,
+            nkpts: outcar.nkpts,
+            nions: outcar.nions,
+            nsw: outcar.ion_iters.len(),
+            ispin: outcar.ispin,
+            lsorbit: outcar.lsorbit,
+            efermi: outcar.efermi,
+            nbands: outcar.nbands,
+        }
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["ibrion", "nkpts", "nions", "nsw", "ispin", "lsorbit", "efermi", "nbands"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            self.ibrion.to_string(),
+            self.nkpts.to_string(),
+            self.nions.to_string(),
+            self.nsw.to_string(),
+            self.ispin.to_string(),
+            self.lsorbit.to_string(),
+            format!("{:.4}", self.efermi),
+            self.nbands.to_string(),
+        ]
+    }
+}
+
+impl fmt::Display for BriefInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>10} = {:10}", "IBRION".bright_green(), self.ibrion)?;
+        writeln!(f, "{:>10} = {:10}", "NKPTS".bright_green(), self.nkpts)?;
+        writeln!(f, "{:>10} = {:10}", "NIONS".bright_green(), self.nions)?;
+        writeln!(f, "{:>10} = {:10}", "NSW".bright_green(), self.nsw)?;
+        writeln!(f, "{:>10} = {:10}", "ISPIN".bright_green(), self.ispin)?;
+        writeln!(f, "{:>10} = {:>10}", "LSORBIT".bright_green(), self.lsorbit)?;
+        writeln!(f, "{:>10} = {:10.4}", "EFERMI".bright_green(), self.efermi)?;
+        writeln!(f, "{:>10} = {:10}", "NBANDS".bright_green(), self.nbands)
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RlxSummary {
+    pub nsteps  : usize,
+    pub nscf    : i32,    // SCF steps of the last ionic step
+    pub toten   : f64,
+    pub toten_z : f64,
+    pub fmax    : f64,
+    pub volume  : f64,
+    pub efermi  : f64,
+}
+
+impl Summary for RlxSummary {
+    fn from_outcar(outcar: &Outcar, dir: &Path) -> Self {
+        let it = outcar.ion_iters.last().expect("No ionic steps found in OUTCAR");
+        let dynamics = _read_dynamics(dir, it.forces.len());
+        Self {
+            nsteps: outcar.ion_iters.len(),
+            nscf: it.nscf,
+            toten: it.toten,
+            toten_z: it.toten_z,
+            fmax: _fmax(&it.forces, &dynamics),
+            volume: _volume(&it.cell),
+            efermi: outcar.efermi,
+        }
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["nsteps", "nscf", "toten", "toten_z", "fmax", "volume", "efermi"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            self.nsteps.to_string(),
+            self.nscf.to_string(),
+            format!("{:.6}", self.toten),
+            format!("{:.6}", self.toten_z),
+            format!("{:.4}", self.fmax),
+            format!("{:.4}", self.volume),
+            format!("{:.4}", self.efermi),
+        ]
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BandGap {
+    pub vbm    : f64,
+    pub cbm    : f64,
+    pub gap    : f64,    // zero for metals
+    pub direct : bool,
+    pub ik_vbm : usize,  // starts from 1
+    pub ik_cbm : usize,  // starts from 1
+}
+
+impl BandGap {
+    /// States with occupation larger than half of the maximum occupation are treated as occupied.
+    pub fn from_eigenvalues(eigvals: &[f64], occupations: &[f64], nspin: usize,
+                            nkpts: usize, nbands: usize, maxocc: f64) -> Self {
+        assert_eq!(eigvals.len(), nspin * nkpts * nbands, "Inconsistent number of eigenvalues");
+        let mut vbm = (f64::MIN, 0usize);
+        let mut cbm = (f64::MAX, 0usize);
+
+        for (i, (e, o)) in eigvals.iter().zip(occupations.iter()).enumerate() {
+            let ik = i / nbands % nkpts;
+            if *o > 0.5 * maxocc {
+                if *e > vbm.0 { vbm = (*e, ik); }
+            } else if *e < cbm.0 {
+                cbm = (*e, ik);
+            }
+        }
+        assert!(vbm.0 > f64::MIN && cbm.0 < f64::MAX, "Cannot find both occupied and unoccupied states");
+
+        Self {
+            vbm: vbm.0,
+            cbm: cbm.0,
+            gap: (cbm.0 - vbm.0).max(0.0),
+            direct: vbm.1 == cbm.1,
+            ik_vbm: vbm.1 + 1,
+            ik_cbm: cbm.1 + 1,
+        }
+    }
+}
+
+impl Summary for BandGap {
+    fn from_outcar(outcar: &Outcar, _dir: &Path) -> Self {
+        assert!(!outcar.eigvals.is_empty(), "Band energies not found in OUTCAR");
+        let maxocc = if outcar.ispin == 1 && !outcar.lsorbit { 2.0 } else { 1.0 };
+        Self::from_eigenvalues(&outcar.eigvals, &outcar.occupations, outcar.ispin as usize,
+                               outcar.nkpts as usize, outcar.nbands as usize, maxocc)
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["vbm", "cbm", "gap", "direct", "ik_vbm", "ik_cbm"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            format!("{:.4}", self.vbm),
+            format!("{:.4}", self.cbm),
+            format!("{:.4}", self.gap),
+            self.direct.to_string(),
+            self.ik_vbm.to_string(),
+            self.ik_cbm.to_string(),
+        ]
+    }
+}
+
+impl fmt::Display for BandGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>10} = {:10.4}  at k-point #{}", "VBM".bright_green(), self.vbm, self.ik_vbm)?;
+        writeln!(f, "{:>10} = {:10.4}  at k-point #{}", "CBM".bright_green(), self.cbm, self.ik_cbm)?;
+        writeln!(f, "{:>10} = {}  ({})", "Gap".bright_green(),
+                 format!("{:10.4}", self.gap).bright_yellow(),
+                 if self.gap <= 0.0 { "metallic" } else if self.direct { "direct" } else { "indirect" })
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MagSummary {
+    pub ispin   : i32,
+    pub lsorbit : bool,
+    pub magmom  : Vec<f64>,  // total magnetization of the last ionic step, 3 components for NCL
+}
+
+impl Summary for MagSummary {
+    fn from_outcar(outcar: &Outcar, _dir: &Path) -> Self {
+        let it = outcar.ion_iters.last().expect("No ionic steps found in OUTCAR");
+        Self {
+            ispin: outcar.ispin,
+            lsorbit: outcar.lsorbit,
+            magmom: it.magmom.clone().unwrap_or_default(),
+        }
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["ispin", "lsorbit", "magmom"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            self.ispin.to_string(),
+            self.lsorbit.to_string(),
+            self.magmom.iter().map(|m| format!("{:.4}", m)).collect::<Vec<_>>().join(" "),
+        ]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_gap() {
+        // 2 k-points, 3 bands, VBM at k2 and CBM at k1
+        let eigvals = [-3.0, -1.0, 2.0,
+                       -2.5, -0.5, 1.5];
+        let occupations = [2.0, 2.0, 0.0,
+                           2.0, 2.0, 0.0];
+        let gap = BandGap::from_eigenvalues(&eigvals, &occupations, 1, 2, 3, 2.0);
+        assert_eq!(gap, BandGap { vbm: -0.5, cbm: 1.5, gap: 2.0, direct: true, ik_vbm: 2, ik_cbm: 2 });
+
+        let eigvals = [-3.0, -1.0, 0.5,
+                       -2.5, -0.5, 1.5];
+        let gap = BandGap::from_eigenvalues(&eigvals, &occupations, 1, 2, 3, 2.0);
+        assert_eq!((gap.gap, gap.direct, gap.ik_vbm, gap.ik_cbm), (1.0, false, 2, 1));
+    }
+
+    #[test]
+    fn test_fmax() {
+        let forces = vec![[3.0, 4.0, 0.0], [0.0, 0.0, 10.0]];
+        assert_eq!(_fmax(&forces, &[[true; 3], [true; 3]]), 10.0);
+        assert_eq!(_fmax(&forces, &[[true; 3], [true, true, false]]), 5.0);
+    }
+}
@@ -0,0 +1,258 @@
+use std::io;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::collections::BTreeMap;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use crate::traits::Tabular;
+use crate::plot::Plot;
+use crate::outcar::Outcar;
+use crate::format::Structure;
+use crate::batch::BatchRecord;
+use crate::summary::Summary;
+use crate::layers::cluster_layers;
+use crate::constants::E_CHARGE;
+
+
+// eV/A^2 to J/m^2
+const EV_PER_A2_TO_J_PER_M2: f64 = E_CHARGE * 1E20;
+
+
+// Final energy and structure of one slab or bulk calculation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SlabRecord {
+    pub nions     : usize,
+    pub toten_z   : f64,
+    #[serde(skip)]
+    pub structure : Structure,
+}
+
+impl Summary for SlabRecord {
+    fn from_outcar(outcar: &Outcar, _dir: &Path) -> Self {
+        assert!(!outcar.ion_iters.is_empty(), "No ionic steps found in OUTCAR");
+        let structure = outcar.get_structure_cloned(outcar.ion_iters.len());
+        Self {
+            nions: structure.car_pos.len(),
+            toten_z: outcar.ion_iters.last().unwrap().toten_z,
+            structure,
+        }
+    }
+
+    fn csv_header() -> Vec<&'static str> {
+        vec!["nions", "toten_z"]
+    }
+
+    fn csv_row(&self) -> Vec<String> {
+        vec![self.nions.to_string(), format!("{:.6}", self.toten_z)]
+    }
+}
+
+impl SlabRecord {
+    /// Number of bulk cells in the slab, None if the slab is not stoichiometric.
+    pub fn units_of(&self, bulk: &SlabRecord) -> Option<f64> {
+        let count = |s: &Structure| s.ion_types.iter()
+            .zip(s.ions_per_type.iter())
+            .fold(BTreeMap::new(), |mut acc, (t, n)| {
+                *acc.entry(t.clone()).or_insert(0) += *n;
+                acc
+            });
+        let (slab, bulk) = (count(&self.structure), count(&bulk.structure));
+        if slab.keys().ne(bulk.keys()) { return None; }
+
+        let ratios = slab.iter()
+            .map(|(t, n)| *n as f64 / bulk[t] as f64)
+            .collect::<Vec<f64>>();
+        if ratios.iter().all(|r| (r - ratios[0]).abs() < 1E-8) {
+            Some(ratios[0])
+        } else {
+            None
+        }
+    }
+
+    /// Area of the cell surface perpendicular to `axis`, in A^2.
+    pub fn area(&self, axis: usize) -> f64 {
+        let (a, b) = (&self.structure.cell[(axis + 1) % 3], &self.structure.cell[(axis + 2) % 3]);
+        let c = [a[1]*b[2] - a[2]*b[1], a[2]*b[0] - a[0]*b[2], a[0]*b[1] - a[1]*b[0]];
+        (c[0]*c[0] + c[1]*c[1] + c[2]*c[2]).sqrt()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SurfacePoint {
+    pub dir     : PathBuf,
+    pub nions   : usize,
+    pub nlayers : usize,
+    pub units   : f64,  // number of bulk cells in the slab
+    pub area    : f64,  // in A^2
+    pub energy  : f64,  // surface energy, in eV/A^2
+    pub de      : f64,  // difference to the thickest slab, in meV/A^2
+}
+
+impl SurfacePoint {
+    pub fn energy_si(&self) -> f64 {
+        self.energy * EV_PER_A2_TO_J_PER_M2
+    }
+}
+
+
+/// Surface energies of symmetric slabs, E_surf = (E_slab - n * E_bulk) / 2A, where n is the
+/// number of bulk cells in the slab from the compositions. The slabs are sorted by the number
+/// of atoms, and compared to the thickest one for the convergence with thickness.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SurfaceEnergy {
+    pub bulk_energy : f64,  // energy of the bulk cell, in eV
+    pub points      : Vec<SurfacePoint>,
+    pub skipped     : Vec<PathBuf>,  // slabs with compositions not matching the bulk
+}
+
+impl SurfaceEnergy {
+    /// Failed records are skipped. `tolerance` is the gap in Angstrom separating the layers.
+    pub fn new(bulk: &SlabRecord, records: &[BatchRecord<SlabRecord>], axis: usize, tolerance: f64) -> Self {
+        let mut skipped = vec![];
+        let mut points = records.iter()
+            .filter_map(|r| r.data.as_ref().map(|d| (r.dir.clone(), d)))
+            .filter_map(|(dir, d)| {
+                let units = match d.units_of(bulk) {
+                    Some(units) => units,
+                    None => {
+                        skipped.push(dir);
+                        return None;
+                    },
+                };
+                let area = d.area(axis);
+                Some(SurfacePoint {
+                    dir,
+                    nions: d.nions,
+                    nlayers: cluster_layers(&d.structure, axis, tolerance).len(),
+                    units,
+                    area,
+                    energy: (d.toten_z - units * bulk.toten_z) / (2.0 * area),
+                    de: 0.0,
+                })
+            })
+            .collect::<Vec<_>>();
+        points.sort_by_key(|p| p.nions);
+
+        if let Some(eref) = points.last().map(|p| p.energy) {
+            for p in points.iter_mut() {
+                p.de = (p.energy - eref) * 1000.0;
+            }
+        }
+
+        Self {
+            bulk_energy: bulk.toten_z,
+            points,
+            skipped,
+        }
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": "Surface energy convergence",
+                "xaxis": {"title": "Number of layers"},
+                "yaxis": {"title": "Surface energy (J/m^2)"},
+            }));
+        plot.add_trace(json!({
+            "type": "scatter",
+            "mode": "lines+markers",
+            "name": "E_surf",
+            "x": self.points.iter().map(|p| p.nlayers).collect::<Vec<_>>(),
+            "y": self.points.iter().map(|p| p.energy_si()).collect::<Vec<_>>(),
+            "text": self.points.iter().map(|p| p.dir.display().to_string()).collect::<Vec<_>>(),
+        }));
+        plot.save_html(path)
+    }
+}
+
+impl Tabular for SurfaceEnergy {
+    fn headers(&self) -> Vec<String> {
+        ["dir", "nions", "nlayers", "units", "area", "energy", "energy_si", "de"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.points.iter()
+            .map(|p| vec![
+                p.dir.display().to_string(),
+                p.nions.to_string(),
+                p.nlayers.to_string(),
+                format!("{:.3}", p.units),
+                format!("{:.4}", p.area),
+                format!("{:.6}", p.energy),
+                format!("{:.4}", p.energy_si()),
+                format!("{:.3}", p.de),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for SurfaceEnergy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Bulk energy: {:.6} eV", self.bulk_energy).bright_green())?;
+        writeln!(f, "{}", "  NIons Layers   Units    Area(A^2)  E_surf(eV/A^2)  E_surf(J/m^2)  dE(meV/A^2)  Directory".bright_green())?;
+        for p in self.points.iter() {
+            writeln!(f, "  {:5} {:6} {:7.3} {:12.4} {:15.6} {} {:12.3}  {}",
+                     p.nions, p.nlayers, p.units, p.area, p.energy,
+                     format!("{:14.4}", p.energy_si()).bright_yellow(), p.de, p.dir.display())?;
+        }
+        for dir in self.skipped.iter() {
+            writeln!(f, "{}", format!("# Skipped {}: composition is not a multiple of the bulk", dir.display()).bright_red())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _record(nlayers: usize, toten_z: f64) -> SlabRecord {
+        // Two atoms per layer, layers 2 A apart along c
+        let car_pos = (0 .. nlayers)
+            .flat_map(|i| vec![[0.0, 0.0, 2.0 * i as f64], [1.5, 1.5, 2.0 * i as f64]])
+            .collect::<Vec<_>>();
+        let c = 30.0;
+        SlabRecord {
+            nions: car_pos.len(),
+            toten_z,
+            structure: Structure {
+                cell: [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, c]],
+                ion_types: vec!["Fe".to_string()],
+                ions_per_type: vec![car_pos.len() as i32],
+                frac_pos: car_pos.iter().map(|p| [p[0] / 3.0, p[1] / 3.0, p[2] / c]).collect(),
+                car_pos,
+            },
+        }
+    }
+
+    #[test]
+    fn test_surface_energy() {
+        let bulk = _record(1, -16.0);
+        let record = |dir: &str, data| BatchRecord { dir: PathBuf::from(dir), error: None, data: Some(data) };
+        let records = vec![
+            record("l5", _record(5, -5.0 * 16.0 + 2.0 * 9.0 * 0.11)),
+            record("l3", _record(3, -3.0 * 16.0 + 2.0 * 9.0 * 0.1)),
+        ];
+
+        let surface = SurfaceEnergy::new(&bulk, &records, 2, 0.5);
+        assert_eq!(surface.points.len(), 2);
+        assert_eq!(surface.points[0].dir, PathBuf::from("l3"));
+        assert_eq!(surface.points[0].nlayers, 3);
+        assert_eq!(surface.points[1].units, 5.0);
+        assert!((surface.points[0].energy - 0.1).abs() < 1E-10);
+        assert!((surface.points[0].de + 10.0).abs() < 1E-8);
+        assert!((surface.points[1].energy_si() - 0.11 * EV_PER_A2_TO_J_PER_M2).abs() < 1E-10);
+
+        let mut slab = _record(2, 0.0);
+        slab.structure.ion_types = vec!["Fe".to_string(), "O".to_string()];
+        slab.structure.ions_per_type = vec![3, 1];
+        assert_eq!(slab.units_of(&bulk), None);
+    }
+}
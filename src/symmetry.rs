@@ -0,0 +1,384 @@
+use std::fmt;
+use colored::Colorize;
+use log::warn;
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::format::Structure;
+use crate::chgcar::ChargeDensity;
+use crate::outcar::{
+    Mat33,
+    MatX3,
+};
+use crate::format::_calc_inv_3x3;
+
+
+/// Rotation in fractional coordinates, x' = W x.
+pub type Rotation = [[i32; 3]; 3];
+
+
+/// Space group operation x' = W x + t in fractional coordinates.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SymOp {
+    pub rotation    : Rotation,
+    pub translation : [f64; 3],
+}
+
+impl SymOp {
+    pub fn apply(&self, x: &[f64; 3]) -> [f64; 3] {
+        let w = &self.rotation;
+        [0, 1, 2].map(|i| (0 .. 3).map(|j| w[i][j] as f64 * x[j]).sum::<f64>() + self.translation[i])
+    }
+
+    /// Rotation in Cartesian coordinates, R = A^T W A^-T with the lattice vectors A in rows.
+    pub fn cart_rotation(&self, cell: &Mat33<f64>) -> Mat33<f64> {
+        let inv = _calc_inv_3x3(cell);
+        let w = &self.rotation;
+        [0, 1, 2].map(|a| [0, 1, 2].map(|b| {
+            (0 .. 3).flat_map(|i| (0 .. 3).map(move |j| (i, j)))
+                .map(|(i, j)| cell[i][a] * w[i][j] as f64 * inv[b][j])
+                .sum::<f64>()
+        }))
+    }
+}
+
+
+/// Space group operations of the crystal, including the pure translations of supercells.
+/// Atoms are matched within `symprec` in Angstrom, and the translations are refined by the
+/// average displacements of the matched atoms.
+pub fn space_group(structure: &Structure, symprec: f64) -> Vec<SymOp> {
+    let species = _species(structure);
+    let pos = &structure.frac_pos;
+    let identity = SymOp { rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]], translation: [0.0; 3] };
+    if pos.is_empty() { return vec![identity]; }
+
+    let mut ops = vec![];
+    for w in _lattice_rotations(&structure.cell) {
+        let r0 = SymOp { rotation: w, translation: [0.0; 3] }.apply(&pos[0]);
+        for j in (0 .. pos.len()).filter(|&j| species[j] == species[0]) {
+            let op = SymOp { rotation: w, translation: [0, 1, 2].map(|i| pos[j][i] - r0[i]) };
+            let perm = match atom_mapping(&op, structure, symprec) {
+                Some(perm) => perm,
+                None => continue,
+            };
+
+            // Average the residual displacements into the translation
+            let shift = (0 .. 3)
+                .map(|a| {
+                    pos.iter().zip(perm.iter())
+                        .map(|(x, &p)| {
+                            let d = pos[p][a] - op.apply(x)[a];
+                            d - d.round()
+                        })
+                        .sum::<f64>() / pos.len() as f64
+                })
+                .collect::<Vec<f64>>();
+            let translation = [0, 1, 2].map(|i| {
+                let t = op.translation[i] + shift[i];
+                let t = t - t.floor();
+                if t > 1.0 - 1E-8 { 0.0 } else { t }
+            });
+            let op = SymOp { rotation: w, translation };
+            if !ops.iter().any(|o: &SymOp| o.rotation == op.rotation && _same_frac(&o.translation, &op.translation, 1E-6)) {
+                ops.push(op);
+            }
+        }
+    }
+
+    if ops.is_empty() { vec![identity] } else { ops }
+}
+
+
+/// Rotations of the space group with the time reversal symmetry (-I) always included, used for
+/// the symmetry of band energies. Only the identity and -I are returned without `structure`.
+pub fn point_group(structure: Option<&Structure>, symprec: f64) -> Vec<Rotation> {
+    let mut ops = vec![];
+    match structure {
+        Some(s) => for op in space_group(s, symprec) {
+            if !ops.contains(&op.rotation) { ops.push(op.rotation); }
+        },
+        None => ops.push([[1, 0, 0], [0, 1, 0], [0, 0, 1]]),
+    }
+    for w in ops.clone() {
+        let inv = w.map(|row| row.map(|x| -x));
+        if !ops.contains(&inv) {
+            ops.push(inv);
+        }
+    }
+    ops
+}
+
+
+/// Index of the atom that each atom is moved onto by `op`, None if any atom is not matched by
+/// an atom of the same species within `symprec` in Angstrom.
+pub fn atom_mapping(op: &SymOp, structure: &Structure, symprec: f64) -> Option<Vec<usize>> {
+    let species = _species(structure);
+    let cell = &structure.cell;
+    let pos = &structure.frac_pos;
+    pos.iter().zip(species.iter())
+        .map(|(x, s)| {
+            let y = op.apply(x);
+            (0 .. pos.len()).find(|&j| species[j] == *s && _distance(&y, &pos[j], cell) < symprec)
+        })
+        .collect()
+}
+
+
+// Integer matrices W preserving the metric, W^T G W = G with G = A A^T
+fn _lattice_rotations(cell: &Mat33<f64>) -> Vec<Rotation> {
+    let g = [0, 1, 2].map(|i| [0, 1, 2].map(|j| (0 .. 3).map(|k| cell[i][k] * cell[j][k]).sum::<f64>()));
+    let gmax = g.iter().flatten().fold(0.0f64, |acc, x| acc.max(x.abs()));
+
+    (0 .. 3i32.pow(9))
+        .map(|mut n| {
+            let mut w = [[0i32; 3]; 3];
+            for x in w.iter_mut().flatten() {
+                *x = n % 3 - 1;
+                n /= 3;
+            }
+            w
+        })
+        .filter(|w| {
+            (0 .. 3).all(|i| (0 .. 3).all(|j| {
+                let gw = (0 .. 3).flat_map(|k| (0 .. 3).map(move |l| (k, l)))
+                    .map(|(k, l)| w[k][i] as f64 * g[k][l] * w[l][j] as f64)
+                    .sum::<f64>();
+                (gw - g[i][j]).abs() < 1E-3 * gmax
+            }))
+        })
+        .collect()
+}
+
+fn _species(structure: &Structure) -> Vec<usize> {
+    structure.ions_per_type.iter()
+        .enumerate()
+        .flat_map(|(i, n)| vec![i; *n as usize])
+        .collect()
+}
+
+// Cartesian distance between the periodic images of fractional coordinates
+fn _distance(a: &[f64; 3], b: &[f64; 3], cell: &Mat33<f64>) -> f64 {
+    let d = [0, 1, 2].map(|i| {
+        let d = a[i] - b[i];
+        d - d.round()
+    });
+    let v = [0, 1, 2].map(|j| (0 .. 3).map(|i| d[i] * cell[i][j]).sum::<f64>());
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+fn _same_frac(a: &[f64; 3], b: &[f64; 3], tol: f64) -> bool {
+    (0 .. 3).all(|i| {
+        let d = a[i] - b[i];
+        (d - d.round()).abs() < tol
+    })
+}
+
+
+/// Averages the volumetric data over the images of each grid point under `ops`. Operations
+/// commensurate with the grid map the points directly, the others by trilinear interpolation.
+/// Only the blocks in `iblocks` are symmetrized, the others are kept.
+pub fn symmetrize_grid(chg: &ChargeDensity, ops: &[SymOp], iblocks: &[usize]) -> ChargeDensity {
+    let n = chg.ngrid;
+    // Grid index maps i' = M i + s, with M_ab = W_ab N_a / N_b
+    let maps = ops.iter()
+        .map(|op| {
+            let w = &op.rotation;
+            let mut m = [[0i64; 3]; 3];
+            for a in 0 .. 3 {
+                for b in 0 .. 3 {
+                    let x = w[a][b] as i64 * n[a] as i64;
+                    if x % n[b] as i64 != 0 { return None; }
+                    m[a][b] = x / n[b] as i64;
+                }
+            }
+            let s = [0, 1, 2].map(|a| op.translation[a] * n[a] as f64);
+            if s.iter().any(|x| (x - x.round()).abs() > 1E-3) { return None; }
+            Some((m, s.map(|x| x.round() as i64)))
+        })
+        .collect::<Vec<_>>();
+    let ninterp = maps.iter().filter(|m| m.is_none()).count();
+    if ninterp > 0 {
+        warn!("{} of {} symmetry operations are not commensurate with the grid {:?}, interpolated trilinearly.",
+              ninterp, ops.len(), n);
+    }
+
+    let blocks = chg.blocks.iter()
+        .enumerate()
+        .map(|(iblock, block)| {
+            if !iblocks.contains(&iblock) { return block.clone(); }
+            (0 .. chg.npoints())
+                .into_par_iter()
+                .map(|ipoint| {
+                    let idx = [ipoint % n[0], ipoint / n[0] % n[1], ipoint / (n[0] * n[1])];
+                    ops.iter().zip(maps.iter())
+                        .map(|(op, map)| match map {
+                            Some((m, s)) => {
+                                let j = [0, 1, 2].map(|a| {
+                                    let x = (0 .. 3).map(|b| m[a][b] * idx[b] as i64).sum::<i64>() + s[a];
+                                    x.rem_euclid(n[a] as i64) as usize
+                                });
+                                block[chg.index(j[0], j[1], j[2])]
+                            },
+                            None => {
+                                let frac = [0, 1, 2].map(|a| idx[a] as f64 / n[a] as f64);
+                                chg.interpolate(iblock, &op.apply(&frac))
+                            },
+                        })
+                        .sum::<f64>() / ops.len() as f64
+                })
+                .collect()
+        })
+        .collect();
+
+    ChargeDensity {
+        pos: chg.pos.clone(),
+        ngrid: n,
+        blocks,
+    }
+}
+
+
+/// Forces averaged over the symmetry equivalent atoms, F_i = 1/|G| sum_g R_g F_{g^-1(i)}.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SymmetrizedForces {
+    pub nops      : usize,
+    pub symbols   : Vec<String>,
+    pub forces    : MatX3<f64>,  // symmetrized, in eV/A
+    pub residuals : Vec<f64>,    // |F_original - F_symmetrized| of each atom
+}
+
+impl SymmetrizedForces {
+    pub fn new(structure: &Structure, forces: &[[f64; 3]], ops: &[SymOp], symprec: f64) -> Self {
+        let nions = forces.len();
+        let mut sym = vec![[0.0f64; 3]; nions];
+        let mut nops = 0;
+        for op in ops.iter() {
+            let perm = match atom_mapping(op, structure, symprec) {
+                Some(perm) => perm,
+                None => continue,
+            };
+            nops += 1;
+            let r = op.cart_rotation(&structure.cell);
+            for (i, f) in forces.iter().enumerate() {
+                for a in 0 .. 3 {
+                    sym[perm[i]][a] += (0 .. 3).map(|b| r[a][b] * f[b]).sum::<f64>();
+                }
+            }
+        }
+        let sym = sym.into_iter()
+            .map(|f| f.map(|x| x / nops.max(1) as f64))
+            .collect::<Vec<_>>();
+        let residuals = forces.iter().zip(sym.iter())
+            .map(|(a, b)| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt())
+            .collect();
+
+        Self {
+            nops,
+            symbols: structure.symbols(),
+            forces: sym,
+            residuals,
+        }
+    }
+}
+
+impl Tabular for SymmetrizedForces {
+    fn headers(&self) -> Vec<String> {
+        ["atom", "element", "fx", "fy", "fz", "residual"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.forces.iter().zip(self.residuals.iter()).zip(self.symbols.iter())
+            .enumerate()
+            .map(|(i, ((f, r), s))| vec![
+                (i + 1).to_string(),
+                s.clone(),
+                format!("{:.6}", f[0]),
+                format!("{:.6}", f[1]),
+                format!("{:.6}", f[2]),
+                format!("{:.6}", r),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for SymmetrizedForces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Forces symmetrized by {} operations, in eV/A", self.nops).bright_green())?;
+        writeln!(f, "{}", "  Atom Element          Fx          Fy          Fz    Residual".bright_green())?;
+        for (i, ((force, r), s)) in self.forces.iter().zip(self.residuals.iter()).zip(self.symbols.iter()).enumerate() {
+            writeln!(f, "  {:4} {:>7} {:11.6} {:11.6} {:11.6} {}", i + 1, s, force[0], force[1], force[2],
+                     format!("{:11.6}", r).bright_yellow())?;
+        }
+        let rmax = self.residuals.iter().cloned().fold(0.0, f64::max);
+        writeln!(f, "  Maximum residual: {:.6} eV/A", rmax)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cs at the origin of a 4x4x8 A cell, with the Cl atoms at z = 1/4 and 3/4 slightly displaced
+    fn _structure() -> Structure {
+        let cell = [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 8.0]];
+        let frac_pos = vec![[0.0, 0.0, 0.0], [0.5, 0.5, 0.25], [0.5, 0.5, 0.7501]];
+        let car_pos = frac_pos.iter().map(|x| [x[0] * 4.0, x[1] * 4.0, x[2] * 8.0]).collect();
+        Structure {
+            cell,
+            ion_types: vec!["Cs".to_string(), "Cl".to_string()],
+            ions_per_type: vec![1, 2],
+            car_pos,
+            frac_pos,
+        }
+    }
+
+    #[test]
+    fn test_space_group() {
+        let s = _structure();
+        // 16 operations of 4/mmm
+        let ops = space_group(&s, 0.01);
+        assert_eq!(ops.len(), 16);
+        assert!(ops.iter().all(|o| o.translation.iter().all(|t| t.abs() < 1E-3)));
+        // Only 4mm is left if the displacement of Cl is resolved
+        assert_eq!(space_group(&s, 1E-4).len(), 8);
+        assert_eq!(point_group(Some(&s), 0.01).len(), 16);
+        assert_eq!(point_group(None, 0.01).len(), 2);
+
+        let mirror = ops.iter().find(|o| o.rotation == [[1, 0, 0], [0, 1, 0], [0, 0, -1]] && o.translation[2].abs() < 1E-3).unwrap();
+        assert_eq!(atom_mapping(mirror, &s, 0.01), Some(vec![0, 2, 1]));
+        let r = mirror.cart_rotation(&s.cell);
+        assert_eq!(r, [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]);
+
+        // Forces on Cl along z are opposite, the lateral noise is removed
+        let forces = [[0.0, 0.0, 0.0], [0.01, 0.0, 0.11], [0.0, 0.0, -0.09]];
+        let sym = SymmetrizedForces::new(&s, &forces, &ops, 0.01);
+        assert_eq!(sym.nops, 16);
+        assert!((sym.forces[1][2] - 0.1).abs() < 1E-10);
+        assert!((sym.forces[2][2] + 0.1).abs() < 1E-10);
+        assert!(sym.forces[1][0].abs() < 1E-10);
+        assert!((sym.residuals[1] - (0.0002f64).sqrt()).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_symmetrize_grid() {
+        let s = _structure();
+        let ops = space_group(&s, 0.01);
+        let ngrid = [4, 4, 8];
+        let block = (0 .. 128).map(|i| i as f64).collect::<Vec<f64>>();
+        let chg = ChargeDensity { pos: s, ngrid, blocks: vec![block.clone(), block] };
+        let sym = symmetrize_grid(&chg, &ops, &[0]);
+        assert_eq!(sym.blocks[1], chg.blocks[1]);
+
+        // Total is kept, and the result is invariant under the operations
+        let total = |b: &[f64]| b.iter().sum::<f64>();
+        assert!((total(&sym.blocks[0]) - total(&chg.blocks[0])).abs() < 1E-8);
+        let again = symmetrize_grid(&sym, &ops, &[0]);
+        for (a, b) in again.blocks[0].iter().zip(sym.blocks[0].iter()) {
+            assert!((a - b).abs() < 1E-10);
+        }
+        // Mirror z -> -z maps iz to (8 - iz) % 8
+        assert!((sym.blocks[0][sym.index(1, 2, 3)] - sym.blocks[0][sym.index(1, 2, 5)]).abs() < 1E-10);
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::fs;
+use std::path::Path;
+use itertools::Itertools;
+use log::info;
+use vasp_poscar::Poscar;
+use crate::procar::Procar;
+use crate::format::Structure;
+
+
+// Commands configured by TOML files generate commented templates with `--gen-template`
+pub trait ConfigTemplate {
+    /// Fully commented config, which parses as is. The examples are filled with the orbitals
+    /// and atoms of the context if available.
+    fn template(ctx: &TemplateContext) -> String;
+}
+
+
+// Calculation files found in the working directory, used to fill the examples of templates
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TemplateContext {
+    pub orbitals : Option<Vec<String>>,  // orbital names in PROCAR
+    pub symbols  : Option<Vec<String>>,  // element symbols of each atom in POSCAR
+}
+
+impl TemplateContext {
+    /// Missing or broken files are skipped silently.
+    pub fn inspect(procar: &(impl AsRef<Path> + ?Sized), poscar: &(impl AsRef<Path> + ?Sized)) -> Self {
+        let orbitals = Procar::read_orbitals(procar).ok();
+        let symbols = Poscar::from_path(poscar).ok()
+            .map(|p| Structure::from(p).symbols());
+        Self { orbitals, symbols }
+    }
+
+    /// Element symbols in the order of POSCAR.
+    pub fn elements(&self) -> Vec<String> {
+        self.symbols.iter()
+            .flatten()
+            .unique()
+            .cloned()
+            .collect()
+    }
+
+    /// Comment lines listing the available orbitals and atoms, e.g. "# Atoms in POSCAR: Fe 1..2, O 3..5".
+    pub fn comments(&self) -> String {
+        let mut ret = String::new();
+        if let Some(orbitals) = self.orbitals.as_ref() {
+            ret += &format!("# Orbitals in PROCAR: {}\n", orbitals.join(" "));
+            ret += "# Orbital groups: p d t2g eg f, \"all\" or empty selects all the orbitals\n";
+        }
+        if let Some(symbols) = self.symbols.as_ref() {
+            let ranges = symbols.iter()
+                .enumerate()
+                .group_by(|(_, s)| s.as_str())
+                .into_iter()
+                .map(|(s, group)| {
+                    let idx = group.map(|(i, _)| i + 1).collect::<Vec<_>>();
+                    if idx.len() == 1 {
+                        format!("{} {}", s, idx[0])
+                    } else {
+                        format!("{} {}..{}", s, idx[0], idx[idx.len() - 1])
+                    }
+                })
+                .collect::<Vec<_>>();
+            ret += &format!("# Atoms in POSCAR: {}\n", ranges.join(", "));
+        }
+        ret
+    }
+
+    /// An example projection as (atoms, orbitals): the first element and its d or p orbitals.
+    pub fn example_selection(&self) -> (String, String) {
+        let atoms = self.elements().first().cloned().unwrap_or_else(|| "1".to_string());
+        let orbits = match self.orbitals.as_ref() {
+            Some(o) if o.iter().any(|x| x == "d" || x == "dxy") => "d",
+            Some(o) if o.iter().any(|x| x == "p" || x == "px") => "p",
+            _ => "",
+        };
+        (atoms, orbits.to_string())
+    }
+}
+
+
+/// Writes the template to `path`, existing files are never overwritten.
+pub fn save_template<T: ConfigTemplate>(path: &(impl AsRef<Path> + ?Sized), ctx: &TemplateContext) -> io::Result<()> {
+    let path = path.as_ref();
+    if path.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+            format!("{:?} exists, remove it or specify another file name to generate the template", path)));
+    }
+    info!("Saving config template to {:?} ...", path);
+    fs::write(path, T::template(ctx))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_context() {
+        let ctx = TemplateContext {
+            orbitals: Some(["s", "py", "pz", "px", "dxy"].iter().map(|x| x.to_string()).collect()),
+            symbols: Some(["Fe", "Fe", "O", "O", "O", "H"].iter().map(|x| x.to_string()).collect()),
+        };
+        assert_eq!(ctx.elements(), vec!["Fe", "O", "H"]);
+        assert!(ctx.comments().contains("# Atoms in POSCAR: Fe 1..2, O 3..5, H 6\n"));
+        assert_eq!(ctx.example_selection(), ("Fe".to_string(), "d".to_string()));
+        assert_eq!(TemplateContext::default().example_selection(), ("1".to_string(), "".to_string()));
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+
+// Time spent in each stage of a command, reported when rsgrad exits. Parsing input files and
+// saving plots are timed where they happen, the rest is counted as computing. Stages running
+// in parallel threads are summed, thus they may add up to more than the wall time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Parse,
+    Plot,
+}
+
+static ELAPSED: Mutex<[Duration; 2]> = Mutex::new([Duration::ZERO; 2]);
+
+
+pub fn timed<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let now = Instant::now();
+    let ret = f();
+    let dt = now.elapsed();
+    if let Ok(mut elapsed) = ELAPSED.lock() {
+        elapsed[stage as usize] += dt;
+    }
+    ret
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimingSummary {
+    pub parse   : Duration,
+    pub compute : Duration,
+    pub plot    : Duration,
+    pub total   : Duration,
+}
+
+impl TimingSummary {
+    /// Summary of the stages timed so far, given the total wall time.
+    pub fn collect(total: Duration) -> Self {
+        let [parse, plot] = ELAPSED.lock().map(|e| *e).unwrap_or_default();
+        Self {
+            parse,
+            compute: total.saturating_sub(parse + plot),
+            plot,
+            total,
+        }
+    }
+}
+
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parse {:.3?}, compute {:.3?}, plot {:.3?}, total {:.3?}",
+               self.parse, self.compute, self.plot, self.total)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_summary() {
+        let x = timed(Stage::Plot, || {
+            std::thread::sleep(Duration::from_millis(10));
+            42
+        });
+        assert_eq!(x, 42);
+
+        let summary = TimingSummary::collect(Duration::from_secs(3600));
+        assert!(summary.plot >= Duration::from_millis(10));
+        assert_eq!(summary.parse + summary.compute + summary.plot, summary.total);
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use serde::Serialize;
+use log::info;
+use crate::commands::GlobalOpts;
+
+
+// Each subcommand is processed by its own struct
+pub trait OptProcess {
+    fn process(&self, global: &GlobalOpts) -> io::Result<()>;
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "json"  => Ok(Self::Json),
+            "csv"   => Ok(Self::Csv),
+            _       => Err(format!("Invalid output format '{}', should be table, json or csv", s)),
+        }
+    }
+}
+
+
+// Data which can be written as rows of CSV
+pub trait Tabular {
+    fn headers(&self) -> Vec<String>;
+    fn rows(&self) -> Vec<Vec<String>>;
+
+    fn to_csv(&self) -> String {
+        let mut ret = self.headers().iter()
+            .map(|h| csv_escape(h))
+            .collect::<Vec<_>>()
+            .join(",");
+        ret.push('\n');
+        for row in self.rows() {
+            ret += &row.iter().map(|v| csv_escape(v)).collect::<Vec<_>>().join(",");
+            ret.push('\n');
+        }
+        ret
+    }
+}
+
+
+pub(crate) fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+
+/// Prints `data` to stdout as colored table, JSON or CSV.
+pub fn print_formatted<T>(data: &T, format: OutputFormat) -> io::Result<()>
+where T: fmt::Display + Serialize + Tabular {
+    match format {
+        OutputFormat::Table => print!("{}", data),
+        OutputFormat::Json  => {
+            let s = serde_json::to_string_pretty(data)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            println!("{}", s);
+        },
+        OutputFormat::Csv   => print!("{}", data.to_csv()),
+    }
+    Ok(())
+}
+
+
+/// Saves `data` to `path` as JSON or CSV, the colored table is written as CSV.
+pub fn save_formatted<T>(data: &T, format: OutputFormat, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()>
+where T: Serialize + Tabular {
+    info!("Saving results to {:?} ...", path.as_ref());
+    let s = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(data)
+            .map_err(|e| io::Error::other(e.to_string()))? + "\n",
+        OutputFormat::Table | OutputFormat::Csv => data.to_csv(),
+    };
+    fs::write(path, s)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct _Table;
+
+    impl Tabular for _Table {
+        fn headers(&self) -> Vec<String> {
+            vec!["a".to_string(), "b,c".to_string()]
+        }
+
+        fn rows(&self) -> Vec<Vec<String>> {
+            vec![vec!["1".to_string(), "x\"y".to_string()]]
+        }
+    }
+
+    #[test]
+    fn test_to_csv() {
+        assert_eq!(_Table.to_csv(), "a,\"b,c\"\n1,\"x\"\"y\"\n");
+    }
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!("JSON".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use std::io;
+use log::warn;
+use crate::outcar::{
+    Outcar,
+    IonicIteration,
+    EnergyTerms,
+    Mat33,
+    MatX3,
+};
+
+
+// Elements skipped when building the tree, the projected eigenvalues and DOS take most of
+// vasprun.xml but are not needed here
+const SKIPPED: [&str; 2] = ["projected", "partial"];
+
+
+// Element of the XML tree, the text and attributes are borrowed from the source. Only the
+// first text chunk is kept, which is the whole content of the leaf elements.
+#[derive(Debug, Default)]
+struct _Node<'a> {
+    name     : &'a str,
+    attrs    : Vec<(&'a str, &'a str)>,
+    text     : &'a str,
+    children : Vec<_Node<'a>>,
+}
+
+impl<'a> _Node<'a> {
+    fn attr(&self, key: &str) -> Option<&'a str> {
+        self.attrs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+
+    fn child(&self, name: &str) -> Option<&_Node<'a>> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s _Node<'a>> + 's {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    // Direct child like `<varray name="forces">`
+    fn named<'s>(&'s self, tag: &'s str, name: &str) -> Option<&'s _Node<'a>> {
+        self.children(tag).find(|c| c.attr("name") == Some(name))
+    }
+
+    // Same as `named`, searched through the nested `<separator>`s of `<parameters>`
+    fn find_named<'s>(&'s self, tag: &'s str, name: &str) -> Option<&'s _Node<'a>> {
+        self.named(tag, name)
+            .or_else(|| self.children.iter().find_map(|c| c.find_named(tag, name)))
+    }
+
+    fn floats(&self) -> io::Result<Vec<f64>> {
+        _parse_floats(self.text)
+    }
+
+    // Rows of `<varray>`, e.g. positions and forces
+    fn rows(&self) -> io::Result<MatX3<f64>> {
+        self.children("v")
+            .map(|v| match v.floats()?[..] {
+                [x, y, z] => Ok([x, y, z]),
+                _ => Err(_invalid(format!("Invalid row of {:?}: {:?}", self.attr("name"), v.text))),
+            })
+            .collect()
+    }
+}
+
+
+fn _invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+fn _parse_floats(text: &str) -> io::Result<Vec<f64>> {
+    text.split_whitespace()
+        .map(|s| s.parse::<f64>().map_err(|_| _invalid(format!("Cannot parse {:?} as float value", s))))
+        .collect()
+}
+
+
+// Parses the start tag `<name key="value" ...>` without the angle brackets
+fn _parse_tag(tag: &str) -> io::Result<_Node<'_>> {
+    let tag = tag.trim_end_matches('/');
+    let (name, mut rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+    let mut attrs = vec![];
+    while let Some((key, value)) = rest.split_once('=') {
+        let value = value.trim_start();
+        let quote = value.chars().next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| _invalid(format!("Unquoted attribute in tag <{}>", tag)))?;
+        let end = value[1 ..].find(quote)
+            .ok_or_else(|| _invalid(format!("Unclosed attribute in tag <{}>", tag)))?;
+        attrs.push((key.trim(), &value[1 .. end + 1]));
+        rest = &value[end + 2 ..];
+    }
+    Ok(_Node { name, attrs, ..Default::default() })
+}
+
+
+// A minimal XML parser enough for vasprun.xml: no entities, CDATA or namespaces. Elements
+// left open at the end are closed, thus vasprun.xml of running jobs can be read.
+fn _parse_xml(context: &str) -> io::Result<_Node<'_>> {
+    let mut stack = vec![_Node::default()];
+    let mut pos = 0;
+
+    while let Some(offset) = context[pos ..].find('<') {
+        let start = pos + offset;
+        let text = context[pos .. start].trim();
+        let node = stack.last_mut().unwrap();
+        if !text.is_empty() && node.text.is_empty() {
+            node.text = text;
+        }
+
+        let rest = &context[start ..];
+        let skip_to = |pat: &str| rest.find(pat).map(|i| start + i + pat.len()).unwrap_or(context.len());
+        if rest.starts_with("<!--") {
+            pos = skip_to("-->");
+        } else if rest.starts_with("<?") {
+            pos = skip_to("?>");
+        } else if rest.starts_with("<!") {
+            pos = skip_to(">");
+        } else if let Some(rest) = rest.strip_prefix("</") {
+            let end = rest.find('>').ok_or_else(|| _invalid("Unclosed end tag".to_string()))?;
+            let name = rest[.. end].trim();
+            if stack.len() < 2 || stack.last().unwrap().name != name {
+                return Err(_invalid(format!("Unexpected end tag </{}>", name)));
+            }
+            let node = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(node);
+            pos = start + end + 3;
+        } else {
+            let end = rest.find('>').ok_or_else(|| _invalid("Unclosed start tag".to_string()))?;
+            let tag = &rest[1 .. end];
+            let node = _parse_tag(tag)?;
+            pos = start + end + 1;
+            if tag.ends_with('/') {
+                stack.last_mut().unwrap().children.push(node);
+            } else if SKIPPED.contains(&node.name) {
+                pos = context[pos ..].find(&format!("</{}>", node.name))
+                    .map(|i| pos + i)
+                    .unwrap_or(context.len());
+                stack.push(node);
+            } else {
+                stack.push(node);
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        let node = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(node);
+    }
+    Ok(stack.pop().unwrap())
+}
+
+
+fn _parse_cell(structure: &_Node) -> io::Result<Mat33<f64>> {
+    let basis = structure.child("crystal")
+        .and_then(|c| c.named("varray", "basis"))
+        .ok_or_else(|| _invalid("Lattice vectors not found in <structure>".to_string()))?
+        .rows()?;
+    match basis[..] {
+        [a, b, c] => Ok([a, b, c]),
+        _ => Err(_invalid(format!("{} lattice vectors found in <structure>", basis.len()))),
+    }
+}
+
+
+// Fractional positions are transformed to Cartesian ones, the same as OUTCAR
+fn _parse_positions(structure: &_Node, cell: &Mat33<f64>) -> io::Result<MatX3<f64>> {
+    let frac = structure.named("varray", "positions")
+        .ok_or_else(|| _invalid("Positions not found in <structure>".to_string()))?
+        .rows()?;
+    Ok(frac.into_iter()
+        .map(|f| {
+            let mut r = [0.0; 3];
+            for (x, a) in f.iter().zip(cell.iter()) {
+                r.iter_mut().zip(a.iter()).for_each(|(r, a)| *r += x * a);
+            }
+            r
+        })
+        .collect())
+}
+
+
+fn _energy(energy: &_Node, name: &str) -> Option<f64> {
+    energy.named("i", name)?.text.parse().ok()
+}
+
+
+// Terms of the last SCF step, in the same way as "Free energy of the ion-electron system"
+// of OUTCAR
+fn _parse_energy_terms(energy: &_Node) -> EnergyTerms {
+    let term = |name: &str| _energy(energy, name).unwrap_or(0.0);
+    EnergyTerms {
+        pscenc    : term("alphaZ"),
+        tewen     : term("ewald"),
+        denc      : term("hartreedc"),
+        xcenc     : term("XCdc"),
+        paw_dc    : [term("pawpsdc"), term("pawaedc")],
+        eentro    : term("eentropy"),
+        ebands    : term("bandstr"),
+        eatom     : term("atom"),
+        ediel_sol : term("Ediel_sol"),
+        ..Default::default()
+    }
+}
+
+
+// One `<calculation>`, None if it's not finished yet
+fn _parse_ionic_step(calc: &_Node, nelect: f64) -> io::Result<Option<IonicIteration>> {
+    let (structure, energy, forces) = match (calc.child("structure"), calc.child("energy"), calc.named("varray", "forces")) {
+        (Some(s), Some(e), Some(f)) => (s, e, f),
+        _ => return Ok(None),
+    };
+    let cell = _parse_cell(structure)?;
+    let positions = _parse_positions(structure, &cell)?;
+    let forces = forces.rows()?;
+
+    let toten = _energy(energy, "e_fr_energy")
+        .ok_or_else(|| _invalid("Free energy not found in <calculation>".to_string()))?;
+    let toten_z = _energy(energy, "e_0_energy").unwrap_or(toten);
+
+    // Energy changes of the SCF steps, the first one is the energy itself like OSZICAR
+    let scf_energies = calc.children("scstep")
+        .filter_map(|s| _energy(s.child("energy")?, "e_fr_energy"))
+        .collect::<Vec<f64>>();
+    let scf_de = scf_energies.iter()
+        .scan(0.0, |last, e| { let de = e - *last; *last = *e; Some(de) })
+        .collect::<Vec<f64>>();
+    let energy_terms = calc.children("scstep").last()
+        .and_then(|s| s.child("energy"))
+        .map(_parse_energy_terms);
+
+    let (stress, stress_tensor) = match calc.named("varray", "stress") {
+        Some(s) => {
+            let s = s.rows()?;
+            if s.len() != 3 {
+                return Err(_invalid(format!("{} rows of stress tensor found in <calculation>", s.len())));
+            }
+            ((s[0][0] + s[1][1] + s[2][2]) / 3.0,
+             [s[0][0], s[1][1], s[2][2], s[0][1], s[1][2], s[2][0]])
+        },
+        None => (0.0, [0.0; 6]),
+    };
+    let cputime = calc.children("time")
+        .find(|t| t.attr("name") == Some("totalsc"))
+        .and_then(|t| t.floats().ok()?.last().copied())
+        .unwrap_or(0.0);
+
+    Ok(Some(IonicIteration::new(scf_de.len() as i32, scf_de, toten, toten_z, cputime,
+                                stress, stress_tensor, None, positions, forces, cell,
+                                nelect, energy_terms)))
+}
+
+
+// Eigenvalues and occupations in the layout of [nspin][nkpts][nbands]
+fn _parse_eigenvalues(calc: &_Node) -> io::Result<(Vec<f64>, Vec<f64>)> {
+    let mut eigvals = vec![];
+    let mut occupations = vec![];
+    let spins = calc.child("eigenvalues")
+        .and_then(|e| e.child("array"))
+        .and_then(|a| a.child("set"));
+    for row in spins.iter()
+        .flat_map(|s| s.children("set"))
+        .flat_map(|s| s.children("set"))
+        .flat_map(|k| k.children("r")) {
+        match row.floats()?[..] {
+            [e, o, ..] => { eigvals.push(e); occupations.push(o); },
+            _ => return Err(_invalid(format!("Invalid row of eigenvalues: {:?}", row.text))),
+        }
+    }
+    Ok((eigvals, occupations))
+}
+
+
+/// Reads vasprun.xml into `Outcar`, which serves as a fallback when OUTCAR is not available.
+/// The magnetic moments, vibrations, elastic moduli, dielectric properties and potentials at
+/// the ion cores are not written to vasprun.xml and thus left empty. Unfinished ionic steps
+/// of running jobs are dropped.
+pub fn outcar_from_vasprun(context: &str) -> io::Result<Outcar> {
+    let root = _parse_xml(context)?;
+    let modeling = root.child("modeling")
+        .ok_or_else(|| _invalid("<modeling> not found, not a vasprun.xml file".to_string()))?;
+
+    let params = modeling.child("parameters")
+        .ok_or_else(|| _invalid("<parameters> not found in vasprun.xml".to_string()))?;
+    let param = |name: &str| params.find_named("i", name)
+        .or_else(|| modeling.child("incar")?.named("i", name))
+        .map(|i| i.text.trim());
+    let int_param = |name: &str, default: i32| param(name)
+        .and_then(|v| v.parse::<i32>().ok())
+        .unwrap_or(default);
+    let float_param = |name: &str| param(name)
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    let lsorbit = param("LSORBIT") == Some("T");
+    let ispin   = int_param("ISPIN", 1);
+    let ibrion  = int_param("IBRION", -1);
+    let nbands  = int_param("NBANDS", 0);
+    let encut   = float_param("ENCUT");
+    let nelect  = float_param("NELECT");
+
+    let nkpts = modeling.child("kpoints")
+        .and_then(|k| k.named("varray", "kpointlist"))
+        .map(|k| k.children("v").count() as i32)
+        .unwrap_or(0);
+
+    // Fields of <array name="atomtypes">: atomspertype, element, mass, valence, pseudopotential
+    let atomtypes = modeling.child("atominfo")
+        .and_then(|a| a.named("array", "atomtypes"))
+        .and_then(|a| a.child("set"))
+        .ok_or_else(|| _invalid("Atom types not found in vasprun.xml".to_string()))?;
+    let mut ions_per_type = vec![];
+    let mut ion_types = vec![];
+    let mut ion_masses = vec![];
+    let mut zvals = vec![];
+    for rc in atomtypes.children("rc") {
+        let c = rc.children("c").map(|c| c.text.trim()).collect::<Vec<&str>>();
+        if c.len() < 4 {
+            return Err(_invalid(format!("Invalid row of atom types: {:?}", c)));
+        }
+        let parse_err = |s: &str| _invalid(format!("Cannot parse {:?} of atom types", s));
+        let n = c[0].parse::<i32>().map_err(|_| parse_err(c[0]))?;
+        let mass = c[2].parse::<f64>().map_err(|_| parse_err(c[2]))?;
+        ions_per_type.push(n);
+        ion_types.push(c[1].to_string());
+        ion_masses.extend(std::iter::repeat_n(mass, n as usize));
+        zvals.push(c[3].parse::<f64>().map_err(|_| parse_err(c[3]))?);
+    }
+    let nions = ions_per_type.iter().sum::<i32>();
+
+    let cell = modeling.children("structure")
+        .find(|s| s.attr("name") == Some("initialpos"))
+        .map(_parse_cell)
+        .transpose()?
+        .unwrap_or_default();
+
+    let calcs = modeling.children("calculation").collect::<Vec<_>>();
+    let mut ion_iters = vec![];
+    for calc in calcs.iter() {
+        match _parse_ionic_step(calc, nelect)? {
+            Some(step) => ion_iters.push(step),
+            None => break,
+        }
+    }
+
+    let (eigvals, occupations) = match calcs.iter().rev().find(|c| c.child("eigenvalues").is_some()) {
+        Some(c) => _parse_eigenvalues(c)?,
+        None => (vec![], vec![]),
+    };
+    let efermi = calcs.iter().rev()
+        .find_map(|c| c.child("dos")?.named("i", "efermi")?.text.trim().parse::<f64>().ok())
+        .unwrap_or_else(|| {
+            warn!("Fermi level not found in vasprun.xml, which is written at the end of the job, 0.0 is used.");
+            0.0
+        });
+
+    Ok(Outcar {
+        lsorbit, ispin, ibrion, nions, nkpts, nbands, encut, efermi, cell,
+        ions_per_type, ion_types, ion_masses, zvals, nelect, ion_iters,
+        vib: None,
+        eigvals,
+        occupations,
+        ion_magmoms: None,
+        core_pots: vec![],
+        elastic: None,
+        dielectric: None,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VASPRUN: &str = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<modeling>
+ <generator>
+  <i name="program" type="string">vasp </i>
+  <i name="version" type="string">6.3.0  </i>
+ </generator>
+ <incar>
+  <i type="string" name="PREC">accurate</i>
+  <i name="ISPIN">      2</i>
+ </incar>
+ <kpoints>
+  <varray name="kpointlist" >
+   <v>       0.00000000       0.00000000       0.00000000 </v>
+   <v>       0.50000000       0.00000000       0.00000000 </v>
+  </varray>
+ </kpoints>
+ <parameters>
+  <separator name="electronic" >
+   <i name="ENCUT">    400.00000000</i>
+   <i name="NELECT">      9.00000000</i>
+   <i type="int" name="NBANDS">     8</i>
+   <separator name="electronic spin" >
+    <i type="int" name="ISPIN">     2</i>
+    <i type="logical" name="LSORBIT"> F  </i>
+   </separator>
+  </separator>
+  <separator name="ionic" >
+   <i type="int" name="IBRION">     2</i>
+  </separator>
+ </parameters>
+ <atominfo>
+  <atoms>       2 </atoms>
+  <types>       2 </types>
+  <array name="atomtypes" >
+   <dimension dim="1">type</dimension>
+   <field type="int">atomspertype</field>
+   <field type="string">element</field>
+   <field>mass</field>
+   <field>valence</field>
+   <field type="string">pseudopotential</field>
+   <set>
+    <rc><c>   1</c><c>Li</c><c>      6.94100000</c><c>      3.00000000</c><c>  PAW_PBE Li_sv 10Sep2004 </c></rc>
+    <rc><c>   1</c><c>F </c><c>     18.99800000</c><c>      7.00000000</c><c>  PAW_PBE F 08Apr2002  </c></rc>
+   </set>
+  </array>
+ </atominfo>
+ <structure name="initialpos" >
+  <crystal>
+   <varray name="basis" >
+    <v>       4.00000000       0.00000000       0.00000000 </v>
+    <v>       0.00000000       4.00000000       0.00000000 </v>
+    <v>       0.00000000       0.00000000       4.00000000 </v>
+   </varray>
+  </crystal>
+  <varray name="positions" >
+   <v>       0.00000000       0.00000000       0.00000000 </v>
+   <v>       0.50000000       0.50000000       0.50000000 </v>
+  </varray>
+ </structure>
+ <calculation>
+  <scstep>
+   <time name="dav">    0.10    0.12</time>
+   <energy>
+    <i name="e_fr_energy">    -10.00000000 </i>
+    <i name="e_wo_entrp">    -10.00000000 </i>
+    <i name="e_0_energy">    -10.00000000 </i>
+   </energy>
+  </scstep>
+  <scstep>
+   <energy>
+    <i name="alphaZ">     10.00000000 </i>
+    <i name="ewald">    -50.00000000 </i>
+    <i name="hartreedc">    -20.00000000 </i>
+    <i name="XCdc">      5.00000000 </i>
+    <i name="pawpsdc">     30.00000000 </i>
+    <i name="pawaedc">    -31.00000000 </i>
+    <i name="eentropy">     -0.01000000 </i>
+    <i name="bandstr">    -15.00000000 </i>
+    <i name="atom">     60.00000000 </i>
+    <i name="e_fr_energy">    -11.01000000 </i>
+    <i name="e_wo_entrp">    -11.00000000 </i>
+    <i name="e_0_energy">    -11.00500000 </i>
+   </energy>
+  </scstep>
+  <structure>
+   <crystal>
+    <varray name="basis" >
+     <v>       4.00000000       0.00000000       0.00000000 </v>
+     <v>       0.00000000       4.00000000       0.00000000 </v>
+     <v>       0.00000000       0.00000000       4.00000000 </v>
+    </varray>
+   </crystal>
+   <varray name="positions" >
+    <v>       0.00000000       0.00000000       0.00000000 </v>
+    <v>       0.50000000       0.50000000       0.50000000 </v>
+   </varray>
+  </structure>
+  <varray name="forces" >
+   <v>       0.10000000       0.00000000       0.00000000 </v>
+   <v>      -0.10000000       0.00000000       0.00000000 </v>
+  </varray>
+  <varray name="stress" >
+   <v>      -3.00000000       0.50000000       0.00000000 </v>
+   <v>       0.50000000      -3.00000000       0.00000000 </v>
+   <v>       0.00000000       0.00000000      -6.00000000 </v>
+  </varray>
+  <energy>
+   <i name="e_fr_energy">    -11.01000000 </i>
+   <i name="e_wo_entrp">    -11.00000000 </i>
+   <i name="e_0_energy">    -11.00500000 </i>
+  </energy>
+  <time name="totalsc">    1.50    1.60</time>
+  <eigenvalues>
+   <array>
+    <dimension dim="1">band</dimension>
+    <set>
+     <set comment="spin 1">
+      <set comment="kpoint 1">
+       <r>   -5.0000    1.0000 </r>
+       <r>    2.0000    0.0000 </r>
+      </set>
+     </set>
+    </set>
+   </array>
+  </eigenvalues>
+  <projected>
+   <array>
+    <set>
+     <r>    0.1000  0.2000 </r>
+    </set>
+   </array>
+  </projected>
+  <dos>
+   <i name="efermi">      1.23450000 </i>
+   <partial>
+    <array>
+     <set>
+      <r>    0.1000  0.2000 </r>
+     </set>
+    </array>
+   </partial>
+  </dos>
+ </calculation>
+ <calculation>
+  <scstep>
+   <energy>
+    <i name="e_fr_energy">    -11.02000000 </i>
+"#;
+
+    #[test]
+    fn test_parse_xml() {
+        let root = _parse_xml("<?xml version=\"1.0\"?><a x=\"1\" y='2 3'><!-- <b> --><b/><c> text </c></a>").unwrap();
+        let a = root.child("a").unwrap();
+        assert_eq!(a.attrs, vec![("x", "1"), ("y", "2 3")]);
+        assert_eq!(a.children.len(), 2);
+        assert_eq!(a.child("c").unwrap().text, "text");
+        assert!(_parse_xml("<a></b>").is_err());
+    }
+
+    #[test]
+    fn test_outcar_from_vasprun() {
+        let outcar = outcar_from_vasprun(VASPRUN).unwrap();
+        assert_eq!((outcar.ispin, outcar.ibrion, outcar.nbands, outcar.nkpts, outcar.nions), (2, 2, 8, 2, 2));
+        assert!(!outcar.lsorbit);
+        assert_eq!(outcar.encut, 400.0);
+        assert_eq!(outcar.nelect, 9.0);
+        assert_eq!(outcar.efermi, 1.2345);
+        assert_eq!(outcar.ion_types, vec!["Li", "F"]);
+        assert_eq!(outcar.ions_per_type, vec![1, 1]);
+        assert_eq!(outcar.ion_masses, vec![6.941, 18.998]);
+        assert_eq!(outcar.neutral_nelect(), 10.0);
+        assert_eq!(outcar.cell[1], [0.0, 4.0, 0.0]);
+
+        // The unfinished second step is dropped
+        assert_eq!(outcar.ion_iters.len(), 1);
+        let step = &outcar.ion_iters[0];
+        assert_eq!(step.nscf, 2);
+        assert!((step.scf_de[1] + 1.01).abs() < 1E-9);
+        assert_eq!((step.toten, step.toten_z, step.cputime), (-11.01, -11.005, 1.6));
+        assert_eq!(step.stress, -4.0);
+        assert_eq!(step.stress_tensor, [-3.0, -3.0, -6.0, 0.5, 0.0, 0.0]);
+        assert_eq!(step.positions[1], [2.0, 2.0, 2.0]);
+        assert_eq!(step.forces[0], [0.1, 0.0, 0.0]);
+        assert!((step.energy_terms.as_ref().unwrap().total() + 11.01).abs() < 1E-9);
+
+        assert_eq!(outcar.eigvals, vec![-5.0, 2.0]);
+        assert_eq!(outcar.occupations, vec![1.0, 0.0]);
+
+        assert!(outcar_from_vasprun("<a></a>").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fmt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use colored::Colorize;
+use log::info;
+use serde::Serialize;
+use crate::traits::Tabular;
+use crate::procar::Procar;
+use crate::dos::_state_weights;
+use crate::outcar::Mat33;
+use crate::constants::{
+    HBAR,
+    BOLTZMANN,
+};
+
+
+
+
+/// Indices of the k-points on a complete uniform grid, e.g. from ISYM=0 or -1 calculations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KGrid {
+    pub dims    : [usize; 3],
+    pub indices : Vec<[usize; 3]>,  // grid indices of each k-point
+    pub lookup  : Vec<usize>,       // k-point index of each grid point, in the order of (i0, i1, i2)
+}
+
+impl KGrid {
+    /// None if the k-points are not a complete uniform grid.
+    pub fn from_kpoints(kpoints: &[[f64; 3]]) -> Option<Self> {
+        let wrap = |x: f64| {
+            let x = x - x.floor();
+            if x > 1.0 - 1E-5 { 0.0 } else { x }
+        };
+        let mut dims = [0usize; 3];
+        let mut origin = [0.0f64; 3];
+        for i in 0 .. 3 {
+            let mut values = kpoints.iter().map(|k| wrap(k[i])).collect::<Vec<f64>>();
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            values.dedup_by(|a, b| (*a - *b).abs() < 1E-5);
+            dims[i] = values.len();
+            origin[i] = values[0];
+        }
+
+        let n = dims[0] * dims[1] * dims[2];
+        if n != kpoints.len() { return None; }
+
+        let mut lookup = vec![usize::MAX; n];
+        let mut indices = Vec::with_capacity(kpoints.len());
+        for (ik, k) in kpoints.iter().enumerate() {
+            let mut idx = [0usize; 3];
+            for i in 0 .. 3 {
+                let x = wrap(k[i] - origin[i]) * dims[i] as f64;
+                if (x - x.round()).abs() > 1E-3 { return None; }
+                idx[i] = x.round() as usize % dims[i];
+            }
+            let flat = (idx[0] * dims[1] + idx[1]) * dims[2] + idx[2];
+            if lookup[flat] != usize::MAX { return None; }
+            lookup[flat] = ik;
+            indices.push(idx);
+        }
+        Some(Self { dims, indices, lookup })
+    }
+
+    // K-point index of the grid point shifted by `step` along `axis`, periodically
+    fn neighbour(&self, ik: usize, axis: usize, step: isize) -> usize {
+        let mut idx = self.indices[ik];
+        let n = self.dims[axis] as isize;
+        idx[axis] = (idx[axis] as isize + step).rem_euclid(n) as usize;
+        self.lookup[(idx[0] * self.dims[1] + idx[1]) * self.dims[2] + idx[2]]
+    }
+}
+
+
+/// Group velocities v = dE/dk / hbar of all the states, by central differences on the uniform
+/// grid. Bands are matched by their indices, thus the velocities near band crossings are
+/// smeared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupVelocities {
+    pub velocities : Vec<[f64; 3]>,  // in m/s, Cartesian, in the layout of `Procar::eigvals`
+}
+
+impl GroupVelocities {
+    /// `cell` is the real-space lattice vectors (rows) in Angstrom.
+    pub fn from_procar(procar: &Procar, grid: &KGrid, cell: &Mat33<f64>) -> Self {
+        let mut velocities = vec![[0.0f64; 3]; procar.eigvals.len()];
+        for ispin in 0 .. procar.nspin {
+            for ik in 0 .. procar.nkpts {
+                for ib in 0 .. procar.nbands {
+                    // dE/dk in fractional coordinates, zero along axes with less than 3 k-points
+                    let gfrac = [0, 1, 2].map(|axis| {
+                        let n = grid.dims[axis];
+                        if n < 3 { return 0.0; }
+                        let (kp, km) = (grid.neighbour(ik, axis, 1), grid.neighbour(ik, axis, -1));
+                        (procar.eigval(ispin, kp, ib) - procar.eigval(ispin, km, ib)) * n as f64 / 2.0
+                    });
+                    // k_cart = k_frac * B with B = 2pi A^-T, thus dE/dk_cart = A^T dE/dk_frac / 2pi
+                    let gcart = [0, 1, 2].map(|c| {
+                        (0 .. 3).map(|i| cell[i][c] * gfrac[i]).sum::<f64>() / (2.0 * std::f64::consts::PI)
+                    });
+                    // eV*A / (eV*s) -> m/s
+                    velocities[(ispin * procar.nkpts + ik) * procar.nbands + ib] = gcart.map(|g| g / HBAR * 1E-10);
+                }
+            }
+        }
+        Self { velocities }
+    }
+}
+
+
+// Fermi-Dirac occupation and its negative energy derivative in 1/eV
+fn _fermi_dirac(e: f64, efermi: f64, kt: f64) -> (f64, f64) {
+    let x = (e - efermi) / kt;
+    if x.abs() > 200.0 {
+        return (if x > 0.0 { 0.0 } else { 1.0 }, 0.0);
+    }
+    let f = 1.0 / (x.exp() + 1.0);
+    (f, f * (1.0 - f) / kt)
+}
+
+
+/// Carrier density and mobility tensor of the electrons in the states above E-fermi, or the
+/// holes below, in the constant relaxation time approximation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Mobility {
+    pub carrier  : String,       // "electron" or "hole"
+    pub density  : f64,          // in cm^-3
+    pub tensor   : Mat33<f64>,   // in cm^2/(V*s)
+}
+
+
+/// Velocities at E-fermi and mobilities estimated by the Boltzmann transport theory.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VelocityReport {
+    pub efermi         : f64,
+    pub temperature    : f64,       // in K
+    pub fermi_velocity : Vec<f64>,  // [nspin], average |v| weighted by -df/dE, in m/s
+    pub tau            : Option<f64>,  // relaxation time in fs
+    pub mobilities     : Vec<Mobility>,
+    #[serde(skip)]
+    pub states         : Vec<(usize, usize, usize, f64, [f64; 3])>,  // (ispin, ik, ib, E-Ef, v) near E-fermi
+}
+
+impl VelocityReport {
+    /// States within `window` eV around E-fermi are kept for the velocity distribution.
+    /// Mobilities are estimated only if `tau` in fs is given.
+    pub fn new(procar: &Procar, vel: &GroupVelocities, cell: &Mat33<f64>, efermi: f64,
+               temperature: f64, window: f64, tau: Option<f64>) -> Self {
+        assert!(temperature > 0.0, "Temperature should be positive");
+        let kt = BOLTZMANN * temperature;
+        let (weights, factor) = _state_weights(procar);
+        let volume = {
+            let (a, b, c) = (cell[0], cell[1], cell[2]);
+            (a[0] * (b[1] * c[2] - b[2] * c[1])
+           - a[1] * (b[0] * c[2] - b[2] * c[0])
+           + a[2] * (b[0] * c[1] - b[1] * c[0])).abs()
+        };
+
+        let mut fermi_velocity = vec![];
+        let mut states = vec![];
+        // [electron, hole] of (number of carriers per cell, sum of w * (-df/dE) * v_a * v_b)
+        let mut carriers = [(0.0f64, [[0.0f64; 3]; 3]); 2];
+        for ispin in 0 .. procar.nspin {
+            let (mut vsum, mut wsum) = (0.0, 0.0);
+            for (ik, wk) in weights.iter().enumerate() {
+                for ib in 0 .. procar.nbands {
+                    let e = procar.eigval(ispin, ik, ib);
+                    let v = vel.velocities[(ispin * procar.nkpts + ik) * procar.nbands + ib];
+                    let (f, dfde) = _fermi_dirac(e, efermi, kt);
+                    let w = wk * factor;
+                    let vnorm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+                    vsum += w * dfde * vnorm;
+                    wsum += w * dfde;
+                    if (e - efermi).abs() <= window {
+                        states.push((ispin, ik, ib, e - efermi, v));
+                    }
+
+                    let (ic, n) = if e > efermi { (0, f) } else { (1, 1.0 - f) };
+                    carriers[ic].0 += w * n;
+                    for a in 0 .. 3 {
+                        for b in 0 .. 3 {
+                            carriers[ic].1[a][b] += w * dfde * v[a] * v[b];
+                        }
+                    }
+                }
+            }
+            fermi_velocity.push(if wsum > 0.0 { vsum / wsum } else { 0.0 });
+        }
+
+        // mu = e * tau * sum(-df/dE v v) / n, with energies in eV the charge cancels, m^2 -> cm^2
+        let mobilities = match tau {
+            Some(tau) => carriers.iter()
+                .zip(["electron", "hole"].iter())
+                .filter(|((n, _), _)| *n > 0.0)
+                .map(|((n, s), carrier)| Mobility {
+                    carrier: carrier.to_string(),
+                    density: n / volume * 1E24,
+                    tensor: s.map(|row| row.map(|x| tau * 1E-15 * x / n * 1E4)),
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        Self {
+            efermi,
+            temperature,
+            fermi_velocity,
+            tau,
+            mobilities,
+            states,
+        }
+    }
+
+    /// Saves the velocities of the states near E-fermi as velocities.txt.
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "velocities.txt")?;
+        info!("Saving group velocities near E-fermi to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        writeln!(f, "# E-fermi = {:.6} eV, velocities in m/s", self.efermi)?;
+        writeln!(f, "# {:>4} {:>6} {:>6} {:>10} {:>13} {:>13} {:>13} {:>13}",
+                 "spin", "kpt", "band", "E-Ef/eV", "vx", "vy", "vz", "|v|")?;
+        for (ispin, ik, ib, e, v) in self.states.iter() {
+            let vnorm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+            writeln!(f, "  {:4} {:6} {:6} {:10.5} {:13.5E} {:13.5E} {:13.5E} {:13.5E}",
+                     ispin + 1, ik + 1, ib + 1, e, v[0], v[1], v[2], vnorm)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tabular for VelocityReport {
+    fn headers(&self) -> Vec<String> {
+        ["carrier", "density", "mu_xx", "mu_yy", "mu_zz", "mu_xy", "mu_yz", "mu_zx"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.mobilities.iter()
+            .map(|m| {
+                let t = &m.tensor;
+                let mut row = vec![m.carrier.clone(), format!("{:.6E}", m.density)];
+                row.extend([t[0][0], t[1][1], t[2][2], t[0][1], t[1][2], t[2][0]].iter().map(|x| format!("{:.4}", x)));
+                row
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for VelocityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# E-fermi = {:.4} eV, T = {} K", self.efermi, self.temperature).bright_green())?;
+        for (ispin, v) in self.fermi_velocity.iter().enumerate() {
+            writeln!(f, "  Fermi velocity of spin {}: {} m/s", ispin + 1, format!("{:.4E}", v).bright_yellow())?;
+        }
+        writeln!(f, "  {} states near E-fermi are saved in velocities.txt", self.states.len())?;
+
+        if let Some(tau) = self.tau {
+            writeln!(f, "{}", format!("# Mobilities with tau = {} fs, in cm^2/(V*s)", tau).bright_green())?;
+            writeln!(f, "{}", "  Carrier   Density/cm^-3      mu_xx      mu_yy      mu_zz      mu_xy      mu_yz      mu_zx".bright_green())?;
+            for m in self.mobilities.iter() {
+                let t = &m.tensor;
+                writeln!(f, "  {:8} {:15.4E} {} {} {} {:10.2} {:10.2} {:10.2}",
+                         m.carrier, m.density,
+                         format!("{:10.2}", t[0][0]).bright_yellow(),
+                         format!("{:10.2}", t[1][1]).bright_yellow(),
+                         format!("{:10.2}", t[2][2]).bright_yellow(),
+                         t[0][1], t[1][2], t[2][0])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One band E = -cos(2pi k_x) on a 1D grid of 8 k-points, in a cubic cell of 2 A
+    fn _cosine_procar(n: usize) -> Procar {
+        let mut input = format!("PROCAR lm decomposed\n# of k-points:  {}         # of bands:   1         # of ions:   1\n\n", n);
+        for ik in 0 .. n {
+            let k = ik as f64 / n as f64;
+            let e = -(2.0 * std::f64::consts::PI * k).cos();
+            input += &format!(" k-point {:5} :    {:.8} 0.00000000 0.00000000     weight = {:.8}\n\n", ik + 1, k, 1.0 / n as f64);
+            input += &format!("band     1 # energy {:13.8} # occ.  1.00000000\n\n", e);
+            input += "ion      s    tot\n    1  1.000  1.000\ntot    1.000  1.000\n\n";
+        }
+        Procar::parse(&input)
+    }
+
+    #[test]
+    fn test_group_velocities() {
+        let n = 64;
+        let procar = _cosine_procar(n);
+        let grid = KGrid::from_kpoints(&procar.kpoints).unwrap();
+        assert_eq!(grid.dims, [n, 1, 1]);
+        assert!(KGrid::from_kpoints(&procar.kpoints[1 ..]).is_none());
+
+        let cell = [[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]];
+        let vel = GroupVelocities::from_procar(&procar, &grid, &cell);
+        // dE/dk_cart = a * sin(2pi k), maximal at k = 1/4
+        let v = vel.velocities[n / 4];
+        assert!((v[0] * HBAR / 1E-10 - 2.0).abs() < 1E-2);
+        assert_eq!(v[1], 0.0);
+
+        let report = VelocityReport::new(&procar, &vel, &cell, 0.0, 300.0, 0.1, Some(10.0));
+        assert!(report.fermi_velocity[0] > 0.9 * v[0]);
+        assert_eq!(report.mobilities.len(), 2);
+        assert!(report.mobilities[0].tensor[0][0] > 0.0);
+        assert_eq!(report.mobilities[0].tensor[1][1], 0.0);
+    }
+}
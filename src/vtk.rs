@@ -0,0 +1,250 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use log::info;
+use crate::chgcar::ChargeDensity;
+use crate::format::{
+    Structure,
+    ELEMENTS,
+};
+use crate::summary::_volume;
+
+
+/// Default names of the blocks in VASP volumetric data, following CHGCAR of ISPIN = 1, 2 and
+/// noncollinear calculations.
+pub fn block_names(nblocks: usize) -> Vec<String> {
+    match nblocks {
+        1 => vec!["total".to_string()],
+        2 => vec!["total".to_string(), "magnetization".to_string()],
+        4 => ["total", "mx", "my", "mz"].iter().map(|s| s.to_string()).collect(),
+        _ => (0 .. nblocks).map(|i| format!("block{}", i)).collect(),
+    }
+}
+
+
+/// Named scalar arrays sharing the grid of one structure, saved as VTK files for ParaView.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumetricArrays {
+    pub pos    : Structure,
+    pub ngrid  : [usize; 3],
+    pub arrays : Vec<(String, Vec<f64>)>,  // x runs fastest
+}
+
+impl VolumetricArrays {
+    pub fn new(pos: Structure, ngrid: [usize; 3]) -> Self {
+        Self { pos, ngrid, arrays: vec![] }
+    }
+
+    pub fn push(&mut self, name: &str, data: Vec<f64>) {
+        assert_eq!(data.len(), self.ngrid.iter().product::<usize>(), "Inconsistent size of array {}", name);
+        self.arrays.push((name.to_string(), data));
+    }
+
+    /// Adds all the blocks of `chg`, named by `block_names` and prefixed by `prefix` if given.
+    /// With `spin_resolved`, the total and magnetization blocks of ISPIN = 2 are converted into
+    /// the spin up and spin down ones. With `per_volume`, the values are divided by the cell
+    /// volume, e.g. e/Å^3 for CHGCAR.
+    pub fn push_density(&mut self, chg: &ChargeDensity, prefix: Option<&str>, spin_resolved: bool, per_volume: bool) {
+        let scale = if per_volume { 1.0 / _volume(chg.cell()) } else { 1.0 };
+        let named = |name: &str| match prefix {
+            Some(p) => format!("{}.{}", p, name),
+            None => name.to_string(),
+        };
+
+        if spin_resolved && chg.blocks.len() == 2 {
+            let (tot, mag) = (&chg.blocks[0], &chg.blocks[1]);
+            self.push(&named("up"), tot.iter().zip(mag.iter()).map(|(t, m)| (t + m) / 2.0 * scale).collect());
+            self.push(&named("down"), tot.iter().zip(mag.iter()).map(|(t, m)| (t - m) / 2.0 * scale).collect());
+            return;
+        }
+        for (name, block) in block_names(chg.blocks.len()).iter().zip(chg.blocks.iter()) {
+            self.push(&named(name), block.iter().map(|x| x * scale).collect());
+        }
+    }
+
+    /// Saves as legacy VTK (*.vtk) or XML image data (*.vti) by the extension of `path`.
+    pub fn save(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        if path.as_ref().extension().is_some_and(|e| e.eq_ignore_ascii_case("vti")) {
+            self.save_as_vti(path)
+        } else {
+            self.save_as_vtk(path)
+        }
+    }
+
+    /// Legacy VTK structured grid with explicit points, valid for any cell shape.
+    pub fn save_as_vtk(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving {} arrays to {:?} ...", self.arrays.len(), path.as_ref());
+        let n = self.ngrid;
+        let cell = &self.pos.cell;
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(f, "# vtk DataFile Version 3.0")?;
+        writeln!(f, "Volumetric data written by rsgrad")?;
+        writeln!(f, "ASCII")?;
+        writeln!(f, "DATASET STRUCTURED_GRID")?;
+        writeln!(f, "DIMENSIONS {} {} {}", n[0], n[1], n[2])?;
+        writeln!(f, "POINTS {} double", n.iter().product::<usize>())?;
+        for iz in 0 .. n[2] {
+            for iy in 0 .. n[1] {
+                for ix in 0 .. n[0] {
+                    let frac = [ix as f64 / n[0] as f64, iy as f64 / n[1] as f64, iz as f64 / n[2] as f64];
+                    let p = [0, 1, 2].map(|j| (0 .. 3).map(|i| frac[i] * cell[i][j]).sum::<f64>());
+                    writeln!(f, "{:.6} {:.6} {:.6}", p[0], p[1], p[2])?;
+                }
+            }
+        }
+        writeln!(f, "POINT_DATA {}", n.iter().product::<usize>())?;
+        for (name, data) in self.arrays.iter() {
+            writeln!(f, "SCALARS {} double 1", _vtk_name(name))?;
+            writeln!(f, "LOOKUP_TABLE default")?;
+            for chunk in data.chunks(6) {
+                writeln!(f, "{}", chunk.iter().map(|x| format!("{:.6e}", x)).collect::<Vec<_>>().join(" "))?;
+            }
+        }
+        f.flush()
+    }
+
+    /// XML image data, much lighter than the structured grid. Non-orthogonal cells are
+    /// described by the `Direction` attribute, which requires ParaView 5.10 or newer.
+    pub fn save_as_vti(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        info!("Saving {} arrays to {:?} ...", self.arrays.len(), path.as_ref());
+        let n = self.ngrid;
+        let cell = &self.pos.cell;
+        let lengths = cell.map(|a| (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt());
+        let spacing = [0, 1, 2].map(|i| lengths[i] / n[i] as f64);
+        // Row major, the columns are the unit vectors of the axes
+        let direction = (0 .. 3)
+            .flat_map(|r| (0 .. 3).map(move |c| (r, c)))
+            .map(|(r, c)| format!("{:.10}", cell[c][r] / lengths[c]))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        let extent = format!("0 {} 0 {} 0 {}", n[0] - 1, n[1] - 1, n[2] - 1);
+        writeln!(f, r#"<?xml version="1.0"?>"#)?;
+        writeln!(f, r#"<VTKFile type="ImageData" version="1.0" byte_order="LittleEndian">"#)?;
+        writeln!(f, r#"  <ImageData WholeExtent="{}" Origin="0 0 0" Spacing="{:.10} {:.10} {:.10}" Direction="{}">"#,
+                 extent, spacing[0], spacing[1], spacing[2], direction)?;
+        writeln!(f, r#"    <Piece Extent="{}">"#, extent)?;
+        match self.arrays.first() {
+            Some((name, _)) => writeln!(f, r#"      <PointData Scalars="{}">"#, _vtk_name(name))?,
+            None => writeln!(f, "      <PointData>")?,
+        }
+        for (name, data) in self.arrays.iter() {
+            writeln!(f, r#"        <DataArray type="Float64" Name="{}" format="ascii">"#, _vtk_name(name))?;
+            for chunk in data.chunks(6) {
+                writeln!(f, "          {}", chunk.iter().map(|x| format!("{:.6e}", x)).collect::<Vec<_>>().join(" "))?;
+            }
+            writeln!(f, "        </DataArray>")?;
+        }
+        writeln!(f, "      </PointData>")?;
+        writeln!(f, "    </Piece>")?;
+        writeln!(f, "  </ImageData>")?;
+        writeln!(f, "</VTKFile>")?;
+        f.flush()
+    }
+}
+
+
+/// Saves the atoms as vertices and the unit cell as lines in legacy VTK polydata, with the
+/// atomic numbers as point data (0 for the cell corners). Glyph the points by spheres in
+/// ParaView to draw the atoms.
+pub fn save_structure_vtk(pos: &Structure, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+    info!("Saving structure to {:?} ...", path.as_ref());
+    let cell = &pos.cell;
+    let natoms = pos.car_pos.len();
+    let corners = (0 .. 8)
+        .map(|i| {
+            let frac = [(i & 1) as f64, (i >> 1 & 1) as f64, (i >> 2 & 1) as f64];
+            [0, 1, 2].map(|j| (0 .. 3).map(|k| frac[k] * cell[k][j]).sum::<f64>())
+        })
+        .collect::<Vec<_>>();
+    // Pairs of corners differing by one bit
+    let edges = (0 .. 8usize)
+        .flat_map(|i| vec![1, 2, 4].into_iter().filter(move |b| i & b == 0).map(move |b| (i, i | b)))
+        .collect::<Vec<_>>();
+    let numbers = pos.symbols().iter()
+        .map(|s| ELEMENTS.iter().position(|e| e == s).unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    let mut f = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(f, "# vtk DataFile Version 3.0")?;
+    writeln!(f, "Structure written by rsgrad")?;
+    writeln!(f, "ASCII")?;
+    writeln!(f, "DATASET POLYDATA")?;
+    writeln!(f, "POINTS {} double", natoms + 8)?;
+    for p in pos.car_pos.iter().chain(corners.iter()) {
+        writeln!(f, "{:.6} {:.6} {:.6}", p[0], p[1], p[2])?;
+    }
+    writeln!(f, "VERTICES {} {}", natoms, natoms * 2)?;
+    for i in 0 .. natoms {
+        writeln!(f, "1 {}", i)?;
+    }
+    writeln!(f, "LINES {} {}", edges.len(), edges.len() * 3)?;
+    for (a, b) in edges.iter() {
+        writeln!(f, "2 {} {}", natoms + a, natoms + b)?;
+    }
+    writeln!(f, "POINT_DATA {}", natoms + 8)?;
+    writeln!(f, "SCALARS atomic_number int 1")?;
+    writeln!(f, "LOOKUP_TABLE default")?;
+    for z in numbers.iter().chain([0; 8].iter()) {
+        writeln!(f, "{}", z)?;
+    }
+    f.flush()
+}
+
+
+// Names in legacy VTK cannot contain spaces
+fn _vtk_name(name: &str) -> String {
+    name.replace(char::is_whitespace, "_")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_vtk_export() {
+        let pos = Structure {
+            cell: [[2.0, 0.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.0, 2.0]],
+            ion_types: vec!["Fe".to_string(), "O".to_string()],
+            ions_per_type: vec![1, 1],
+            car_pos: vec![[0.0; 3], [1.5, 1.0, 1.0]],
+            frac_pos: vec![[0.0; 3], [0.5, 0.5, 0.5]],
+        };
+        let ngrid = [2, 1, 1];
+        let chg = ChargeDensity { pos: pos.clone(), ngrid, blocks: vec![vec![8.0, 4.0], vec![4.0, 0.0]] };
+
+        let mut arrays = VolumetricArrays::new(pos.clone(), ngrid);
+        arrays.push_density(&chg, None, false, true);
+        arrays.push_density(&chg, Some("B"), true, false);
+        let names = arrays.arrays.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["total", "magnetization", "B.up", "B.down"]);
+        assert_eq!(arrays.arrays[0].1, vec![1.0, 0.5]);
+        assert_eq!(arrays.arrays[2].1, vec![6.0, 2.0]);
+        assert_eq!(arrays.arrays[3].1, vec![2.0, 2.0]);
+
+        let dir = TempDir::new("rsgrad_vtk").unwrap();
+        let path = dir.path().join("chg.vtk");
+        arrays.save(&path).unwrap();
+        let vtk = fs::read_to_string(&path).unwrap();
+        assert!(vtk.contains("POINTS 2 double\n0.000000 0.000000 0.000000\n1.000000 0.000000 0.000000\n"));
+        assert!(vtk.contains("SCALARS B.down double 1\nLOOKUP_TABLE default\n2.000000e0 2.000000e0\n"));
+
+        let path = dir.path().join("chg.vti");
+        arrays.save(&path).unwrap();
+        let vti = fs::read_to_string(&path).unwrap();
+        assert!(vti.contains(r#"WholeExtent="0 1 0 0 0 0""#));
+        assert!(vti.contains(r#"Spacing="1.0000000000 2.2360679775 2.0000000000""#));
+        assert!(vti.contains(r#"Direction="1.0000000000 0.4472135955 0.0000000000 0.0000000000 0.8944271910 0.0000000000"#));
+
+        let path = dir.path().join("pos.vtk");
+        save_structure_vtk(&pos, &path).unwrap();
+        let vtk = fs::read_to_string(&path).unwrap();
+        assert!(vtk.contains("POINTS 10 double\n"));
+        assert!(vtk.contains("LINES 12 36\n"));
+        assert!(vtk.contains("LOOKUP_TABLE default\n26\n8\n0\n"));
+    }
+}
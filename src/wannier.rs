@@ -0,0 +1,488 @@
+use std::io;
+use std::io::{
+    Seek,
+    SeekFrom,
+    Write,
+};
+use std::fs;
+use std::path::Path;
+use std::convert::TryFrom;
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use crate::band::{
+    KpathSegments,
+    _pretty_label,
+};
+
+
+/// Real-space Hamiltonian of maximally localized Wannier functions in wannier90_hr.dat.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WannierHamiltonian {
+    pub num_wann : usize,
+    pub rvecs    : Vec<[i32; 3]>,
+    pub degens   : Vec<usize>,          // degeneracies of the Wigner-Seitz points
+    pub hoppings : Vec<Vec<[f64; 2]>>,  // [nrpts][num_wann * num_wann], (re, im) of H_mn(R) at m * num_wann + n
+}
+
+impl WannierHamiltonian {
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// The header line is followed by num_wann, nrpts, the degeneracies of the R points, and the
+    /// lines of "R1 R2 R3 m n Re Im".
+    pub fn parse(context: &str) -> io::Result<Self> {
+        let err = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid wannier90_hr.dat: {}", msg));
+        let mut tokens = context.lines().skip(1).flat_map(|l| l.split_whitespace());
+        let mut next_usize = |name: &str| -> io::Result<usize> {
+            tokens.next()
+                .and_then(|t| t.parse::<usize>().ok())
+                .ok_or_else(|| err(&format!("cannot read {}", name)))
+        };
+        let num_wann = next_usize("num_wann")?;
+        let nrpts = next_usize("nrpts")?;
+        let degens = (0 .. nrpts)
+            .map(|_| next_usize("degeneracies"))
+            .collect::<io::Result<Vec<_>>>()?;
+        if degens.contains(&0) { return Err(err("zero degeneracy found")); }
+
+        // 15 degeneracies per line
+        let nlines = nrpts * num_wann * num_wann;
+        let lines = context.lines()
+            .skip(3 + nrpts.div_ceil(15))
+            .filter(|l| !l.trim().is_empty())
+            .take(nlines)
+            .collect::<Vec<_>>();
+        if lines.len() != nlines {
+            return Err(err(&format!("{} hopping lines expected, but {} found", nlines, lines.len())));
+        }
+
+        let mut rvecs = Vec::with_capacity(nrpts);
+        let mut hoppings = vec![vec![[0.0f64; 2]; num_wann * num_wann]; nrpts];
+        for (i, line) in lines.iter().enumerate() {
+            let v = line.split_whitespace().collect::<Vec<_>>();
+            let r = [v[0], v[1], v[2]].map(|x| x.parse::<i32>());
+            let (m, n) = (v[3].parse::<usize>(), v[4].parse::<usize>());
+            let (re, im) = (v[5].parse::<f64>(), v[6].parse::<f64>());
+            let (r, m, n, re, im) = match (r, m, n, re, im) {
+                ([Ok(r0), Ok(r1), Ok(r2)], Ok(m), Ok(n), Ok(re), Ok(im)) => ([r0, r1, r2], m, n, re, im),
+                _ => return Err(err(&format!("cannot parse line \"{}\"", line.trim()))),
+            };
+            if m < 1 || m > num_wann || n < 1 || n > num_wann {
+                return Err(err(&format!("orbital index out of bound in line \"{}\"", line.trim())));
+            }
+
+            let irpt = i / (num_wann * num_wann);
+            if i % (num_wann * num_wann) == 0 {
+                rvecs.push(r);
+            }
+            hoppings[irpt][(m - 1) * num_wann + (n - 1)] = [re, im];
+        }
+
+        Ok(Self { num_wann, rvecs, degens, hoppings })
+    }
+
+    /// H_mn(k) = sum_R exp(i 2pi k.R) H_mn(R) / deg(R), `k` in fractional coordinates.
+    pub fn hamiltonian(&self, k: &[f64; 3]) -> Vec<[f64; 2]> {
+        let mut h = vec![[0.0f64; 2]; self.num_wann * self.num_wann];
+        for ((r, deg), hop) in self.rvecs.iter().zip(self.degens.iter()).zip(self.hoppings.iter()) {
+            let phase = 2.0 * std::f64::consts::PI * (k[0] * r[0] as f64 + k[1] * r[1] as f64 + k[2] * r[2] as f64);
+            let (c, s) = (phase.cos() / *deg as f64, phase.sin() / *deg as f64);
+            for (hk, hr) in h.iter_mut().zip(hop.iter()) {
+                hk[0] += hr[0] * c - hr[1] * s;
+                hk[1] += hr[0] * s + hr[1] * c;
+            }
+        }
+        h
+    }
+
+    /// Interpolated eigenvalues in ascending order.
+    pub fn eigenvalues(&self, k: &[f64; 3]) -> Vec<f64> {
+        _hermitian_eigenvalues(&self.hamiltonian(k), self.num_wann)
+    }
+
+    /// Eigenvalues at each k-point, [nkpts][num_wann].
+    pub fn interpolate(&self, kpoints: &[[f64; 3]]) -> Vec<Vec<f64>> {
+        kpoints.par_iter()
+            .map(|k| self.eigenvalues(k))
+            .collect()
+    }
+}
+
+
+/// K-path in the `kpoint_path` block of wannier90.win, e.g. "G 0.0 0.0 0.0  X 0.5 0.0 0.0" per
+/// line, with `bands_num_points` k-points (100 by default) per segment including both ends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WinKpath {
+    pub npoints : usize,
+    pub labels  : Vec<[String; 2]>,
+    pub ends    : Vec<[[f64; 3]; 2]>,  // fractional coordinates of the start and end points
+}
+
+impl WinKpath {
+    /// Returns None if the file doesn't exist or contains no k-path.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    /// Keywords are case-insensitive, and comments start with '!' or '#'.
+    pub fn parse(context: &str) -> Option<Self> {
+        let lines = context.lines()
+            .map(|l| l.split(['!', '#']).next().unwrap().trim())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+
+        let npoints = lines.iter()
+            .map(|l| l.replace(['=', ':'], " "))
+            .find_map(|l| {
+                let mut it = l.split_whitespace();
+                match it.next() {
+                    Some(key) if key.eq_ignore_ascii_case("bands_num_points") => it.next()?.parse::<usize>().ok(),
+                    _ => None,
+                }
+            })
+            .unwrap_or(100)
+            .max(2);
+
+        let begin = lines.iter().position(|l| {
+            let l = l.to_lowercase();
+            l.starts_with("begin") && l.contains("kpoint_path")
+        })?;
+        let segments = lines[begin + 1 ..].iter()
+            .take_while(|l| !l.to_lowercase().starts_with("end"))
+            .map(|l| {
+                let v = l.split_whitespace().collect::<Vec<_>>();
+                if v.len() != 8 { return None; }
+                let coord = |s: &[&str]| -> Option<[f64; 3]> {
+                    Some([s[0].parse().ok()?, s[1].parse().ok()?, s[2].parse().ok()?])
+                };
+                Some(([v[0].to_string(), v[4].to_string()], [coord(&v[1 .. 4])?, coord(&v[5 .. 8])?]))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if segments.is_empty() { return None; }
+
+        let (labels, ends) = segments.into_iter().unzip();
+        Some(Self { npoints, labels, ends })
+    }
+
+    /// K-points along the path in fractional coordinates.
+    pub fn kpoints(&self) -> Vec<[f64; 3]> {
+        let n = self.npoints;
+        self.ends.iter()
+            .flat_map(|[a, b]| {
+                (0 .. n).map(move |i| {
+                    let t = i as f64 / (n - 1) as f64;
+                    [0, 1, 2].map(|j| a[j] + (b[j] - a[j]) * t)
+                })
+            })
+            .collect()
+    }
+
+    /// Labels of the path in the layout of line-mode KPOINTS.
+    pub fn to_segments(&self) -> KpathSegments {
+        KpathSegments {
+            npoints: self.npoints,
+            labels: self.labels.iter()
+                .map(|[a, b]| [_pretty_label(a), _pretty_label(b)])
+                .collect(),
+        }
+    }
+}
+
+
+/// Periodic parts of the Bloch functions at one k-point in the UNK format of Wannier90, i.e. a
+/// Fortran unformatted file of the header record "ngx ngy ngz ik nbnd" followed by one record of
+/// complex values for each band, or two records of the up and down components of spinors. The
+/// records have the same length, thus the bands can be written in any order.
+pub struct UnkFile {
+    file    : fs::File,
+    npoints : usize,
+    nbands  : usize,
+    nspinor : usize,
+}
+
+impl UnkFile {
+    /// "UNK00001.1" for k-point 1 of spin up, "UNK00001.NC" for non-collinear calculations,
+    /// where `ikpt` and `ispin` start from 0.
+    pub fn fname(ikpt: usize, ispin: usize, ncl: bool) -> String {
+        if ncl {
+            format!("UNK{:05}.NC", ikpt + 1)
+        } else {
+            format!("UNK{:05}.{}", ikpt + 1, ispin + 1)
+        }
+    }
+
+    pub fn create(path: &(impl AsRef<Path> + ?Sized), ngrid: [usize; 3], ikpt: usize, nbands: usize, nspinor: usize) -> io::Result<Self> {
+        let npoints = ngrid.iter().product::<usize>();
+        if i32::try_from(npoints * 16).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Grid {:?} is too large for the records of UNK files", ngrid)));
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        let header = [ngrid[0], ngrid[1], ngrid[2], ikpt + 1, nbands]
+            .iter()
+            .flat_map(|&n| (n as i32).to_le_bytes())
+            .collect::<Vec<u8>>();
+        _write_record(&mut file, &header)?;
+        let ret = Self { file, npoints, nbands, nspinor };
+        ret.file.set_len(ret._band_offset(nbands))?;
+        Ok(ret)
+    }
+
+    /// Writes the `iband`th band of this file (starting from 0), the grids of the spinor
+    /// components are indexed by x first.
+    pub fn write_band(&mut self, iband: usize, grids: &[Vec<Complex<f64>>]) -> io::Result<()> {
+        assert!(iband < self.nbands, "Band index out of bound.");
+        assert_eq!(grids.len(), self.nspinor, "Inconsistent number of spinor components");
+        let offset = self._band_offset(iband);
+        self.file.seek(SeekFrom::Start(offset))?;
+
+        let mut buf = Vec::with_capacity(self.npoints * 16);
+        for grid in grids.iter() {
+            assert_eq!(grid.len(), self.npoints, "Inconsistent grid size");
+            buf.clear();
+            grid.iter().for_each(|c| {
+                buf.extend(c.re.to_le_bytes());
+                buf.extend(c.im.to_le_bytes());
+            });
+            _write_record(&mut self.file, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn _band_offset(&self, iband: usize) -> u64 {
+        (20 + 8 + (self.npoints * 16 + 8) * self.nspinor * iband) as u64
+    }
+}
+
+
+// A record of Fortran unformatted sequential files, enclosed by its length in 4 bytes
+fn _write_record(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = (data.len() as i32).to_le_bytes();
+    w.write_all(&len)?;
+    w.write_all(data)?;
+    w.write_all(&len)
+}
+
+
+// The n x n Hermitian matrix H = A + iB has the same eigenvalues as the real symmetric matrix
+// [[A, -B], [B, A]], each of them doubled.
+fn _hermitian_eigenvalues(h: &[[f64; 2]], n: usize) -> Vec<f64> {
+    let mut a = vec![vec![0.0f64; 2 * n]; 2 * n];
+    for i in 0 .. n {
+        for j in 0 .. n {
+            let [re, im] = h[i * n + j];
+            a[i][j] = re;
+            a[i + n][j + n] = re;
+            a[i][j + n] = -im;
+            a[i + n][j] = im;
+        }
+    }
+    let eigs = _symmetric_eigenvalues(a);
+    eigs.into_iter().step_by(2).collect()
+}
+
+
+// Householder reduction to the tridiagonal form followed by the implicit QL iterations,
+// eigenvalues only, in ascending order.
+#[allow(clippy::needless_range_loop)]
+pub(crate) fn _symmetric_eigenvalues(mut a: Vec<Vec<f64>>) -> Vec<f64> {
+    let n = a.len();
+    if n == 0 { return vec![]; }
+    let mut d = vec![0.0f64; n];
+    let mut e = vec![0.0f64; n];
+
+    for i in (1 .. n).rev() {
+        let l = i - 1;
+        let mut h = 0.0;
+        let scale: f64 = (0 ..= l).map(|k| a[i][k].abs()).sum();
+        if l == 0 || scale == 0.0 {
+            e[i] = a[i][l];
+        } else {
+            for k in 0 ..= l {
+                a[i][k] /= scale;
+                h += a[i][k] * a[i][k];
+            }
+            let f = a[i][l];
+            let g = if f >= 0.0 { -h.sqrt() } else { h.sqrt() };
+            e[i] = scale * g;
+            h -= f * g;
+            a[i][l] = f - g;
+            let mut f = 0.0;
+            for j in 0 ..= l {
+                let mut g = 0.0;
+                for k in 0 ..= j { g += a[j][k] * a[i][k]; }
+                for k in j + 1 ..= l { g += a[k][j] * a[i][k]; }
+                e[j] = g / h;
+                f += e[j] * a[i][j];
+            }
+            let hh = f / (h + h);
+            for j in 0 ..= l {
+                let f = a[i][j];
+                let g = e[j] - hh * f;
+                e[j] = g;
+                for k in 0 ..= j { a[j][k] -= f * e[k] + g * a[i][k]; }
+            }
+        }
+        d[i] = h;
+    }
+    for i in 0 .. n { d[i] = a[i][i]; }
+
+    for i in 1 .. n { e[i - 1] = e[i]; }
+    e[n - 1] = 0.0;
+    for l in 0 .. n {
+        for _ in 0 .. 60 {
+            let mut m = l;
+            while m + 1 < n {
+                let dd = d[m].abs() + d[m + 1].abs();
+                if e[m].abs() <= f64::EPSILON * dd { break; }
+                m += 1;
+            }
+            if m == l { break; }
+
+            let mut g = (d[l + 1] - d[l]) / (2.0 * e[l]);
+            let mut r = g.hypot(1.0);
+            g = d[m] - d[l] + e[l] / (g + if g >= 0.0 { r } else { -r });
+            let (mut s, mut c, mut p) = (1.0f64, 1.0f64, 0.0f64);
+            let mut underflow = false;
+            for i in (l .. m).rev() {
+                let f = s * e[i];
+                let b = c * e[i];
+                r = f.hypot(g);
+                e[i + 1] = r;
+                if r == 0.0 {
+                    d[i + 1] -= p;
+                    e[m] = 0.0;
+                    underflow = true;
+                    break;
+                }
+                s = f / r;
+                c = g / r;
+                g = d[i + 1] - p;
+                r = (d[i] - g) * s + 2.0 * c * b;
+                p = s * r;
+                d[i + 1] = g + p;
+                g = c * r - b;
+            }
+            if underflow { continue; }
+            d[l] -= p;
+            e[l] = g;
+            e[m] = 0.0;
+        }
+    }
+
+    d.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    d
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two orbitals per cell: on-site energies of +1 and -1 coupled by 0.5i, and a nearest
+    // neighbour hopping of -1 for the first orbital along x.
+    const HR: &str = "written on 15Oct2026 at 12:00:00
+          2
+          3
+    1    2    1
+   -1    0    0    1    1   -1.00000000    0.00000000
+   -1    0    0    2    1    0.00000000    0.00000000
+   -1    0    0    1    2    0.00000000    0.00000000
+   -1    0    0    2    2    0.00000000    0.00000000
+    0    0    0    1    1    1.00000000    0.00000000
+    0    0    0    2    1    0.00000000   -0.50000000
+    0    0    0    1    2    0.00000000    0.50000000
+    0    0    0    2    2   -1.00000000    0.00000000
+    1    0    0    1    1   -1.00000000    0.00000000
+    1    0    0    2    1    0.00000000    0.00000000
+    1    0    0    1    2    0.00000000    0.00000000
+    1    0    0    2    2    0.00000000    0.00000000
+";
+
+    #[test]
+    fn test_wannier_hamiltonian() {
+        let ham = WannierHamiltonian::parse(HR).unwrap();
+        assert_eq!(ham.num_wann, 2);
+        assert_eq!(ham.rvecs, vec![[-1, 0, 0], [0, 0, 0], [1, 0, 0]]);
+        assert_eq!(ham.degens, vec![1, 2, 1]);
+        assert_eq!(ham.hoppings[1][1], [0.0, 0.5]);
+
+        // The on-site terms are halved by the degeneracy of 2, H_11(k) = 0.5 - 2cos(2pi k),
+        // H_22(k) = -0.5 and H_12(k) = 0.25i
+        for k in [0.0, 0.125, 0.3] {
+            let eps = 0.5 - 2.0 * (2.0 * std::f64::consts::PI * k).cos();
+            let (avg, diff) = ((eps - 0.5) / 2.0, (eps + 0.5) / 2.0);
+            let r = (diff * diff + 0.0625).sqrt();
+            let eigs = ham.eigenvalues(&[k, 0.0, 0.0]);
+            assert!((eigs[0] - (avg - r)).abs() < 1E-10);
+            assert!((eigs[1] - (avg + r)).abs() < 1E-10);
+        }
+        assert!(WannierHamiltonian::parse(&HR[.. HR.len() - 60]).is_err());
+
+        let eigs = _symmetric_eigenvalues(vec![vec![2.0, 1.0, 0.0], vec![1.0, 2.0, 1.0], vec![0.0, 1.0, 2.0]]);
+        let s = 2.0f64.sqrt();
+        for (e, r) in eigs.iter().zip([2.0 - s, 2.0, 2.0 + s].iter()) {
+            assert!((e - r).abs() < 1E-12);
+        }
+    }
+
+    #[test]
+    fn test_win_kpath() {
+        let input = "num_wann = 2
+Bands_Num_Points : 5   ! per segment
+begin kpoint_path
+G 0.0 0.0 0.0  X 0.5 0.0 0.0
+X 0.5 0.0 0.0  M 0.5 0.5 0.0
+end kpoint_path
+";
+        let kpath = WinKpath::parse(input).unwrap();
+        assert_eq!(kpath.npoints, 5);
+        let kpoints = kpath.kpoints();
+        assert_eq!(kpoints.len(), 10);
+        assert_eq!(kpoints[2], [0.25, 0.0, 0.0]);
+        assert_eq!(kpoints[9], [0.5, 0.5, 0.0]);
+        assert_eq!(kpath.to_segments().ticks(), vec![(0, "Γ".to_string()), (4, "X".to_string()), (9, "M".to_string())]);
+        assert_eq!(WinKpath::parse("num_wann = 2\n"), None);
+    }
+
+    #[test]
+    fn test_unk_file() {
+        use std::convert::TryInto;
+
+        assert_eq!(UnkFile::fname(11, 1, false), "UNK00012.2");
+        assert_eq!(UnkFile::fname(0, 0, true), "UNK00001.NC");
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("UNK00003.NC");
+        let ngrid = [2, 3, 1];
+        let band = |ib: usize, is: usize| (0 .. 6).map(|i| Complex::new((ib * 10 + is) as f64, i as f64)).collect::<Vec<_>>();
+        let mut unk = UnkFile::create(&path, ngrid, 2, 3, 2).unwrap();
+        for ib in [2, 0, 1] {
+            unk.write_band(ib, &[band(ib, 0), band(ib, 1)]).unwrap();
+        }
+        drop(unk);
+
+        // Read as Fortran records
+        let bytes = fs::read(&path).unwrap();
+        let mut records = vec![];
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let len = i32::from_le_bytes(bytes[pos .. pos + 4].try_into().unwrap()) as usize;
+            assert_eq!(&bytes[pos .. pos + 4], &bytes[pos + 4 + len .. pos + 8 + len]);
+            records.push(&bytes[pos + 4 .. pos + 4 + len]);
+            pos += len + 8;
+        }
+        assert_eq!(records.len(), 1 + 3 * 2);
+        let header = records[0].chunks(4).map(|b| i32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(header, vec![2, 3, 1, 3, 3]);
+        let values = records[1 + 2 * 2 + 1].chunks(8).map(|b| f64::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(&values[.. 4], &[21.0, 0.0, 21.0, 1.0]);
+    }
+}
@@ -0,0 +1,303 @@
+use std::io;
+use std::fmt;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::Serialize;
+use colored::Colorize;
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use crate::traits::Tabular;
+use crate::wavecar::Wavecar;
+
+
+/// Changes of one state between two WAVECARs, the indices start from 1 and the k-point is the
+/// one of the first WAVECAR.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StateDiff {
+    pub spin         : usize,
+    pub kpoint       : usize,
+    pub band         : usize,
+    pub eig_a        : f64,
+    pub eig_b        : f64,
+    pub de           : f64,
+    pub overlap      : f64,    // |<a_n|b_n>|^2
+    pub best_band    : usize,  // band of the second WAVECAR overlapping most with a_n
+    pub best_overlap : f64,
+    pub subspace     : f64,    // sum of |<a_n|b_m>|^2 over the bands b_m degenerate with b_n
+}
+
+
+/// Band-by-band comparison of two WAVECARs of the same lattice, e.g. of different ENCUT or
+/// k-meshes. The k-points are matched by their coordinates, and the plane-wave coefficients by
+/// the Miller indices of G, thus the coefficients beyond the smaller ENCUT are left out of the
+/// overlaps. The overlaps within the degenerate subspace are not affected by the arbitrary
+/// rotations among the degenerate states.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WavecarDiff {
+    pub unmatched : usize,           // k-points of the first WAVECAR not found in the second one
+    pub states    : Vec<StateDiff>,  // the most changed states first
+}
+
+impl WavecarDiff {
+    /// Compares the selected bands of the two WAVECARs, the best matches are looked for among
+    /// the same bands of the second WAVECAR. States whose eigenvalues differ less than
+    /// `degen_tol` (in eV) are taken as degenerate.
+    pub fn compare(a: &Wavecar, b: &Wavecar, ispins: &[usize], ibands: &[usize], degen_tol: f64) -> io::Result<Self> {
+        let err = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if a.nspin != b.nspin {
+            return Err(err(format!("Different ISPIN of the two WAVECARs: {} and {}", a.nspin, b.nspin)));
+        }
+        if a.cell.iter().flatten().zip(b.cell.iter().flatten()).any(|(x, y)| (x - y).abs() > 1E-4) {
+            return Err(err("The two WAVECARs are of different lattices".to_string()));
+        }
+        let ibands = ibands.iter().copied().filter(|&ib| ib < b.nbands).collect::<Vec<_>>();
+
+        let kpairs = (0 .. a.nkpts)
+            .filter_map(|ika| {
+                (0 .. b.nkpts)
+                    .find(|&ikb| (0 .. 3).all(|i| (a.kvecs[ika][i] - b.kvecs[ikb][i]).abs() < 1E-4))
+                    .map(|ikb| (ika, ikb))
+            })
+            .collect::<Vec<_>>();
+        if kpairs.is_empty() {
+            return Err(err("No common k-points found in the two WAVECARs".to_string()));
+        }
+        let unmatched = a.nkpts - kpairs.len();
+
+        let mut states = kpairs.par_iter()
+            .map(|&(ika, ikb)| -> io::Result<Vec<StateDiff>> {
+                let ga = a.gvectors(ika);
+                let gb = b.gvectors(ikb);
+                let read = |wav: &Wavecar, ispin: usize, ikpt: usize, g: &[[i32; 3]]| ibands.iter()
+                    .map(|&ib| wav.read_spinors(ispin, ikpt, ib, g))
+                    .collect::<Vec<_>>();
+
+                let mut ret = vec![];
+                for &ispin in ispins.iter() {
+                    let bands_a = read(a, ispin, ika, &ga);
+                    let bands_b = read(b, ispin, ikb, &gb);
+                    if bands_a[0].1.len() != bands_b[0].1.len() {
+                        return Err(err("Different numbers of spinor components of the two WAVECARs".to_string()));
+                    }
+                    let pairs = _common_gvectors(&bands_a[0].0, &bands_b[0].0);
+                    let overlaps = bands_a.iter()
+                        .map(|(_, ca)| bands_b.iter().map(|(_, cb)| _overlap(ca, cb, &pairs).norm_sqr()).collect::<Vec<_>>())
+                        .collect::<Vec<_>>();
+
+                    for (i, &ib) in ibands.iter().enumerate() {
+                        let (eig_a, eig_b) = (a.eigval(ispin, ika, ib), b.eigval(ispin, ikb, ib));
+                        let (best, best_overlap) = overlaps[i].iter()
+                            .copied()
+                            .enumerate()
+                            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+                            .unwrap();
+                        let subspace = ibands.iter()
+                            .zip(overlaps[i].iter())
+                            .filter(|(&jb, _)| (b.eigval(ispin, ikb, jb) - eig_b).abs() < degen_tol)
+                            .map(|(_, o)| o)
+                            .sum::<f64>();
+                        ret.push(StateDiff {
+                            spin: ispin + 1,
+                            kpoint: ika + 1,
+                            band: ib + 1,
+                            eig_a,
+                            eig_b,
+                            de: eig_b - eig_a,
+                            overlap: overlaps[i][i],
+                            best_band: ibands[best] + 1,
+                            best_overlap,
+                            subspace,
+                        });
+                    }
+                }
+                Ok(ret)
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        states.sort_by(|x, y| x.subspace.partial_cmp(&y.subspace).unwrap()
+                       .then(y.de.abs().partial_cmp(&x.de.abs()).unwrap()));
+        Ok(Self { unmatched, states })
+    }
+}
+
+
+// Pairs of the indices of the same G vectors in the two sets
+pub(crate) fn _common_gvectors(ga: &[[i32; 3]], gb: &[[i32; 3]]) -> Vec<(usize, usize)> {
+    let index = gb.iter().enumerate().map(|(i, g)| (*g, i)).collect::<HashMap<_, _>>();
+    ga.iter().enumerate()
+        .filter_map(|(i, g)| index.get(g).map(|&j| (i, j)))
+        .collect()
+}
+
+// <a|b> summed over the spinor components
+pub(crate) fn _overlap(ca: &[Vec<Complex<f64>>], cb: &[Vec<Complex<f64>>], pairs: &[(usize, usize)]) -> Complex<f64> {
+    ca.iter().zip(cb.iter())
+        .flat_map(|(a, b)| pairs.iter().map(move |&(i, j)| a[i].conj() * b[j]))
+        .fold(Complex::new(0.0, 0.0), |acc, x| acc + x)
+}
+
+
+impl Tabular for WavecarDiff {
+    fn headers(&self) -> Vec<String> {
+        ["spin", "kpoint", "band", "eig_a", "eig_b", "de", "overlap", "best_band", "best_overlap", "subspace"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.states.iter()
+            .map(|s| vec![
+                s.spin.to_string(),
+                s.kpoint.to_string(),
+                s.band.to_string(),
+                s.eig_a.to_string(),
+                s.eig_b.to_string(),
+                s.de.to_string(),
+                s.overlap.to_string(),
+                s.best_band.to_string(),
+                s.best_overlap.to_string(),
+                s.subspace.to_string(),
+            ])
+            .collect()
+    }
+}
+
+impl fmt::Display for WavecarDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("  {:>4} {:>6} {:>5} {:>11} {:>11} {:>10} {:>8} {:>5} {:>8} {:>8}",
+                                  "Spin", "Kpoint", "Band", "E_a(eV)", "E_b(eV)", "dE(eV)",
+                                  "Overlap", "Best", "Overlap", "Subspace").bright_green())?;
+        for s in self.states.iter() {
+            let line = format!("  {:>4} {:>6} {:>5} {:11.5} {:11.5} {:10.5} {:8.4} {:>5} {:8.4} {:8.4}",
+                               s.spin, s.kpoint, s.band, s.eig_a, s.eig_b, s.de,
+                               s.overlap, s.best_band, s.best_overlap, s.subspace);
+            if s.subspace < 0.9 {
+                writeln!(f, "{}", line.bright_red())?;
+            } else {
+                writeln!(f, "{}", line)?;
+            }
+        }
+        if self.unmatched > 0 {
+            writeln!(f, "  {} k-points of the first WAVECAR are not found in the second one", self.unmatched)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Comparisons of the reference WAVECAR with several others, e.g. of increasing ENCUT.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WavdiffEntry {
+    pub wavecar : PathBuf,
+    pub diff    : WavecarDiff,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WavdiffTable(pub Vec<WavdiffEntry>);
+
+impl Tabular for WavdiffTable {
+    fn headers(&self) -> Vec<String> {
+        let mut ret = vec!["wavecar".to_string()];
+        ret.extend(self.0.first().map(|e| e.diff.headers()).unwrap_or_default());
+        ret
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.0.iter()
+            .flat_map(|e| e.diff.rows().into_iter().map(move |row| {
+                let mut ret = vec![e.wavecar.display().to_string()];
+                ret.extend(row);
+                ret
+            }))
+            .collect()
+    }
+}
+
+impl fmt::Display for WavdiffTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in self.0.iter() {
+            writeln!(f, "{}", format!("# {}", e.wavecar.display()).bright_green())?;
+            write!(f, "{}", e.diff)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wavecar::{
+        _gvectors,
+        _ngrid,
+    };
+    use crate::wavecar::tests::{
+        _coeffs,
+        _write_wavecar,
+    };
+
+    #[test]
+    fn test_compare() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let (path_a, path_b) = (dir.path().join("WAVECAR_a"), dir.path().join("WAVECAR_b"));
+        let cell = [[3.0, 0.0, 0.0], [0.0, 3.5, 0.0], [0.0, 0.0, 4.0]];
+        let kvecs = [[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]];
+        let eigvals = vec![vec![vec![-1.0, 2.0, 2.0, 3.0]; 2]];
+        let coeffs = vec![(0 .. 2).map(|k| {
+            let n = _gvecs(&cell, 80.0, &kvecs[k]).len();
+            _orthonormalize((0 .. 4).map(|b| _coeffs(n, (k * 4 + b) as f64)).collect(), &[])
+        }).collect::<Vec<_>>()];
+        _write_wavecar(&path_a, 45210, 80.0, &cell, &kvecs, &eigvals, &coeffs);
+        let a = Wavecar::from_file(&path_a).unwrap();
+
+        // Larger ENCUT with the same coefficients, only the Gamma point, and the degenerate
+        // bands 2 and 3 rotated by 45 degrees, band 4 replaced and shifted.
+        let gb = _gvecs(&cell, 120.0, &kvecs[0]);
+        let ga = a.gvectors(0);
+        let expand = |c: &[Complex<f64>]| gb.iter()
+            .map(|g| ga.iter().position(|x| x == g).map(|i| c[i]).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let c = &coeffs[0][0];
+        let s = std::f64::consts::FRAC_1_SQRT_2;
+        let rotated = [c[1].iter().zip(c[2].iter()).map(|(x, y)| (x + y) * s).collect::<Vec<_>>(),
+                       c[1].iter().zip(c[2].iter()).map(|(x, y)| (x - y) * s).collect::<Vec<_>>()];
+        let mut bands_b = vec![expand(&c[0]), expand(&rotated[0]), expand(&rotated[1])];
+        let others = bands_b.iter().cloned().chain([expand(&c[3])]).collect::<Vec<_>>();
+        bands_b.extend(_orthonormalize(vec![_coeffs(gb.len(), 42.0)], &others));
+        let eigvals_b = vec![vec![vec![-1.0, 2.0, 2.0, 3.5]]];
+        _write_wavecar(&path_b, 45210, 120.0, &cell, &kvecs[.. 1], &eigvals_b, &[vec![bands_b]]);
+        let b = Wavecar::from_file(&path_b).unwrap();
+
+        let diff = WavecarDiff::compare(&a, &b, &[0], &[0, 1, 2, 3], 1E-3).unwrap();
+        assert_eq!(diff.unmatched, 1);
+        assert_eq!(diff.states.len(), 4);
+        let state = |band: usize| diff.states.iter().find(|s| s.band == band).unwrap();
+        assert!((state(1).overlap - 1.0).abs() < 1E-10);
+        assert!((state(2).overlap - 0.5).abs() < 1E-10);
+        assert!((state(2).subspace - 1.0).abs() < 1E-10);
+        assert!((state(3).subspace - 1.0).abs() < 1E-10);
+        assert_eq!(diff.states[0].band, 4);
+        assert!((diff.states[0].de - 0.5).abs() < 1E-12);
+        assert!(diff.states[0].subspace < 1E-10);
+    }
+
+    fn _gvecs(cell: &[[f64; 3]; 3], encut: f64, k: &[f64; 3]) -> Vec<[i32; 3]> {
+        _gvectors(cell, encut, _ngrid(cell, encut), k, None)
+    }
+
+    // Gram-Schmidt of the bands, orthogonal to `others` which are orthonormal
+    fn _orthonormalize(bands: Vec<Vec<Complex<f64>>>, others: &[Vec<Complex<f64>>]) -> Vec<Vec<Complex<f64>>> {
+        let mut ret: Vec<Vec<Complex<f64>>> = vec![];
+        for mut c in bands {
+            for o in others.iter().chain(ret.iter()) {
+                let p = o.iter().zip(c.iter()).fold(Complex::new(0.0, 0.0), |acc, (x, y)| acc + x.conj() * y);
+                c.iter_mut().zip(o.iter()).for_each(|(y, x)| *y -= p * x);
+            }
+            let norm = c.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+            ret.push(c.into_iter().map(|x| x / norm).collect());
+        }
+        ret
+    }
+}
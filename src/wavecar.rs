@@ -0,0 +1,951 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::convert::{
+    TryFrom,
+    TryInto,
+};
+use std::path::Path;
+use std::collections::HashMap;
+use std::sync::{
+    mpsc,
+    Arc,
+    Mutex,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::thread;
+use std::str::FromStr;
+use std::f64::consts::{
+    PI,
+    FRAC_1_SQRT_2,
+};
+use memmap2::Mmap;
+use rustfft::{
+    Fft,
+    FftPlanner,
+};
+use rustfft::num_complex::Complex;
+use rayon::prelude::*;
+use itertools::Itertools;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use colored::Colorize;
+use crate::outcar::Mat33;
+use crate::format::_calc_inv_3x3;
+use crate::progress::Progress;
+use crate::traits::OutputFormat;
+use crate::cache;
+
+
+/// Precision of the plane-wave coefficients, given by the tag in the first record. The tags
+/// 45200 and 45210 are written by VASP6, 53300 and 53310 by VASP5, with the same layout of
+/// records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    Single,  // complex64 coefficients, 45200 or 53300
+    Double,  // complex128 coefficients, 45210 or 53310
+}
+
+impl Precision {
+    pub fn from_tag(tag: i64) -> Option<Self> {
+        match tag {
+            45200 | 53300 => Some(Self::Single),
+            45210 | 53310 => Some(Self::Double),
+            _ => None,
+        }
+    }
+
+    /// Size of one complex coefficient in bytes
+    pub fn nbytes(&self) -> usize {
+        match self {
+            Self::Single => 8,
+            Self::Double => 16,
+        }
+    }
+}
+
+
+/// Direction of the half grid of G vectors in Gamma-only WAVECAR. VASP keeps the G vectors
+/// with positive component along this axis, the ties broken by the next axes. Both the x- and
+/// z-direction schemes are used by different builds of VASP, the y one is rare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "x" => Ok(Self::X),
+            "y" => Ok(Self::Y),
+            "z" => Ok(Self::Z),
+            _   => Err(format!("Invalid axis '{}', should be x, y or z", s)),
+        }
+    }
+}
+
+
+/// Plane-wave basis of WAVECAR, told by the number of plane waves at the first k-point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WavecarType {
+    Standard,
+    NonCollinear,     // spinors, the coefficients of the two components follow one another
+    GammaHalf(Axis),  // Gamma-only, half of the G vectors are stored
+}
+
+
+// One line of the band energy table, indices start from 1
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EigRow {
+    pub spin       : usize,
+    pub kpoint     : usize,
+    pub band       : usize,
+    pub eigval     : f64,
+    pub occupation : f64,
+}
+
+
+// WAVECAR is a file of fixed-length direct access records without record markers, all the
+// numbers in the headers are stored as f64:
+//   record 0: record length in bytes, ISPIN and the precision tag
+//   record 1: NKPTS, NBANDS, ENCUT, lattice vectors and E-fermi (absent before VASP 5.4)
+// then for each spin and k-point, one record of NPLW, the k-vector and (Re eig, Im eig, occ)
+// of each band, followed by NBANDS records of the plane-wave coefficients.
+//
+// Only the headers are parsed, the coefficients are read from the mapped file on demand.
+#[derive(Debug)]
+pub struct Wavecar {
+    mmap            : Mmap,
+    pub reclen      : u64,
+    pub nspin       : usize,
+    pub tag         : i64,
+    pub precision   : Precision,
+    pub nkpts       : usize,
+    pub nbands      : usize,
+    pub encut       : f64,
+    pub cell        : Mat33<f64>,
+    pub efermi      : f64,
+    pub wavetype    : WavecarType,
+    pub ngrid       : [usize; 3],      // range of G vectors, -ngrid/2 ..= ngrid/2
+    pub nplws       : Vec<usize>,      // [nkpts]
+    pub kvecs       : Vec<[f64; 3]>,   // [nkpts], fractional
+    pub eigvals     : Vec<f64>,        // [nspin][nkpts][nbands]
+    pub occupations : Vec<f64>,        // [nspin][nkpts][nbands]
+}
+
+impl Wavecar {
+    /// The parsed headers are cached if enabled, see `crate::cache`.
+    pub fn from_file(path: &(impl AsRef<Path> + ?Sized)) -> io::Result<Self> {
+        let f = fs::File::open(path)?;
+        // The mapped file is only read, and WAVECAR is not supposed to be modified meanwhile
+        let mmap = unsafe { Mmap::map(&f)? };
+        let h = cache::load_or_parse_stamped(path.as_ref(), "wavecar", |_| _parse_header(&mmap))?;
+        Ok(Self {
+            mmap,
+            reclen: h.reclen,
+            nspin: h.nspin,
+            tag: h.tag,
+            precision: h.precision,
+            nkpts: h.nkpts,
+            nbands: h.nbands,
+            encut: h.encut,
+            cell: h.cell,
+            efermi: h.efermi,
+            wavetype: h.wavetype,
+            ngrid: h.ngrid,
+            nplws: h.nplws,
+            kvecs: h.kvecs,
+            eigvals: h.eigvals,
+            occupations: h.occupations,
+        })
+    }
+
+    /// Sets the direction of the half grid for Gamma-only WAVECAR, which cannot be told from
+    /// the file. Other WAVECARs are unchanged.
+    pub fn with_gamma_half(mut self, axis: Axis) -> Self {
+        if let WavecarType::GammaHalf(_) = self.wavetype {
+            self.wavetype = WavecarType::GammaHalf(axis);
+        }
+        self
+    }
+
+    /// G vectors of the coefficients stored at `ikpt` in the order of VASP, i.e. x runs
+    /// fastest. Non-collinear WAVECAR has the same G vectors for both spinor components.
+    pub fn gvectors(&self, ikpt: usize) -> Vec<[i32; 3]> {
+        let half = match self.wavetype {
+            WavecarType::GammaHalf(axis) => Some(axis),
+            _ => None,
+        };
+        let ret = _gvectors(&self.cell, self.encut, self.ngrid, &self.kvecs[ikpt], half);
+        let ncomp = if self.wavetype == WavecarType::NonCollinear { 2 } else { 1 };
+        assert_eq!(ret.len() * ncomp, self.nplws[ikpt],
+                   "Number of plane waves at k-point {} does not match the G vectors within ENCUT", ikpt + 1);
+        ret
+    }
+
+    /// Spinor components of one band on the full set of G vectors, i.e. [ncomp][ngvecs] with
+    /// the G vectors returned. For Gamma-only WAVECAR, the other half is reconstructed by
+    /// c(-G) = c*(G), and the coefficients of G != 0 are divided by sqrt(2) since VASP stores
+    /// them scaled to normalize the half set.
+    pub fn read_spinors(&self, ispin: usize, ikpt: usize, iband: usize, gvecs: &[[i32; 3]]) -> (Vec<[i32; 3]>, Vec<Vec<Complex<f64>>>) {
+        let coeffs = self.read_coeffs(ispin, ikpt, iband);
+        match self.wavetype {
+            WavecarType::Standard => (gvecs.to_vec(), vec![coeffs]),
+            WavecarType::NonCollinear => {
+                let (up, dn) = coeffs.split_at(gvecs.len());
+                (gvecs.to_vec(), vec![up.to_vec(), dn.to_vec()])
+            },
+            WavecarType::GammaHalf(_) => {
+                let (g, c) = _unfold_gamma(gvecs, &coeffs);
+                (g, vec![c])
+            },
+        }
+    }
+
+    /// Indices of the given k-points (fractional) in WAVECAR, None if any of them is not found.
+    pub fn match_kpoints(&self, kpoints: &[[f64; 3]]) -> Option<Vec<usize>> {
+        kpoints.iter()
+            .map(|k| (0 .. self.nkpts).find(|&i| (0 .. 3).all(|j| (self.kvecs[i][j] - k[j]).abs() < 1E-4)))
+            .collect()
+    }
+
+    pub fn eigval(&self, ispin: usize, ikpt: usize, iband: usize) -> f64 {
+        self.eigvals[(ispin * self.nkpts + ikpt) * self.nbands + iband]
+    }
+
+    pub fn occupation(&self, ispin: usize, ikpt: usize, iband: usize) -> f64 {
+        self.occupations[(ispin * self.nkpts + ikpt) * self.nbands + iband]
+    }
+
+    /// Writes the energies and occupations of the selected states line by line as a table, CSV or
+    /// JSON array, without building the whole table in memory. Indices start from 0.
+    pub fn write_eigs(&self, w: &mut impl Write, ispins: &[usize], ikpts: &[usize], ibands: &[usize],
+                      format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Table => writeln!(w, "{}", format!("{:>5} {:>7} {:>6} {:>12} {:>10}",
+                                                             "Spin", "Kpoint", "Band", "E/eV", "Occ").bright_green())?,
+            OutputFormat::Csv   => writeln!(w, "spin,kpoint,band,eigval,occupation")?,
+            OutputFormat::Json  => write!(w, "[")?,
+        }
+
+        let mut first = true;
+        for &ispin in ispins.iter() {
+            for &ikpt in ikpts.iter() {
+                for &iband in ibands.iter() {
+                    let row = EigRow {
+                        spin: ispin + 1,
+                        kpoint: ikpt + 1,
+                        band: iband + 1,
+                        eigval: self.eigval(ispin, ikpt, iband),
+                        occupation: self.occupation(ispin, ikpt, iband),
+                    };
+                    match format {
+                        OutputFormat::Table => writeln!(w, "{:5} {:7} {:6} {:12.6} {:10.6}",
+                                                        row.spin, row.kpoint, row.band, row.eigval, row.occupation)?,
+                        OutputFormat::Csv   => writeln!(w, "{},{},{},{},{}",
+                                                        row.spin, row.kpoint, row.band, row.eigval, row.occupation)?,
+                        OutputFormat::Json  => {
+                            let s = serde_json::to_string(&row).map_err(|e| io::Error::other(e.to_string()))?;
+                            write!(w, "{}\n  {}", if first { "" } else { "," }, s)?;
+                        },
+                    }
+                    first = false;
+                }
+            }
+        }
+
+        if format == OutputFormat::Json {
+            writeln!(w, "\n]")?;
+        }
+        Ok(())
+    }
+
+    /// Plane-wave coefficients of one band, indices start from 0.
+    pub fn read_coeffs(&self, ispin: usize, ikpt: usize, iband: usize) -> Vec<Complex<f64>> {
+        assert!(ispin < self.nspin && ikpt < self.nkpts && iband < self.nbands,
+                "Band index out of bound: spin {} k-point {} band {}", ispin, ikpt, iband);
+        // Checked against the file size when parsing the headers
+        let offset = self._band_offset(ispin, ikpt, iband)
+            .and_then(|offset| usize::try_from(offset).ok())
+            .unwrap();
+        let nplw = self.nplws[ikpt];
+        let bytes = &self.mmap[offset .. offset + nplw * self.precision.nbytes()];
+
+        match self.precision {
+            Precision::Single => bytes.chunks_exact(8)
+                .map(|c| Complex::new(f32::from_le_bytes(c[0 .. 4].try_into().unwrap()) as f64,
+                                      f32::from_le_bytes(c[4 .. 8].try_into().unwrap()) as f64))
+                .collect(),
+            Precision::Double => bytes.chunks_exact(16)
+                .map(|c| Complex::new(f64::from_le_bytes(c[0 .. 8].try_into().unwrap()),
+                                      f64::from_le_bytes(c[8 .. 16].try_into().unwrap())))
+                .collect(),
+        }
+    }
+
+    /// Transforms the selected (ispin, ikpt, iband) states to real space by `nworkers` threads,
+    /// and passes them to `f` in the order of completion as [ncomp][npoints] grids, see
+    /// `RealspaceFft::transform`. Each worker owns its FFT plans and scratch buffers. The grids
+    /// are sent through a channel bounded by `nworkers` and recycled after `f` returns, thus at
+    /// most 2 * nworkers + 1 states are held in memory however many bands are transformed.
+    pub fn for_each_realspace<F>(&self, states: &[(usize, usize, usize)], ngrid: [usize; 3],
+                                 nworkers: usize, mut f: F) -> io::Result<()>
+    where F: FnMut((usize, usize, usize), &[Vec<Complex<f64>>]) -> io::Result<()> {
+        let ikpts = states.iter().map(|s| s.1).unique().collect::<Vec<_>>();
+        let gvecs = ikpts.par_iter()
+            .map(|&ik| (ik, self.gvectors(ik)))
+            .collect::<HashMap<_, _>>();
+        for i in 0 .. 3 {
+            let gmax = gvecs.values().flatten().map(|g| g[i].unsigned_abs() as usize).max().unwrap_or(0);
+            if ngrid[i] < 2 * gmax + 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Grid {:?} is too coarse for the plane waves within ENCUT, at least {} points are required along axis {}",
+                            ngrid, 2 * gmax + 1, i + 1)));
+            }
+        }
+
+        let nworkers = nworkers.clamp(1, states.len().max(1));
+        let next = AtomicUsize::new(0);
+        let pool = Mutex::new(Vec::<Vec<Vec<Complex<f64>>>>::new());
+        let progress = Progress::new("Transforming wavefunctions", states.len());
+        let (tx, rx) = mpsc::sync_channel(nworkers);
+
+        let ret = thread::scope(|scope| {
+            for _ in 0 .. nworkers {
+                let tx = tx.clone();
+                let (next, pool, gvecs) = (&next, &pool, &gvecs);
+                scope.spawn(move || {
+                    let mut fft = RealspaceFft::new(ngrid);
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= states.len() {
+                            break;
+                        }
+                        let (ispin, ikpt, iband) = states[i];
+                        let (g, spinors) = self.read_spinors(ispin, ikpt, iband, &gvecs[&ikpt]);
+                        let mut grids = pool.lock().unwrap().pop().unwrap_or_default();
+                        grids.resize_with(spinors.len(), Vec::new);
+                        for (c, grid) in spinors.iter().zip(grids.iter_mut()) {
+                            fft.transform(&g, c, grid);
+                        }
+                        // The receiver is dropped if `f` fails
+                        if tx.send((states[i], grids)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            let ret = rx.iter().try_for_each(|(state, grids)| {
+                f(state, &grids)?;
+                pool.lock().unwrap().push(grids);
+                progress.inc(1);
+                Ok(())
+            });
+            // Unblocks the workers waiting on the full channel
+            drop(rx);
+            ret
+        });
+        progress.finish();
+        ret
+    }
+
+    fn _header_offset(&self, ispin: usize, ikpt: usize) -> Option<u64> {
+        _record_offset(self.reclen, self.nkpts, self.nbands, ispin, ikpt, None)
+    }
+
+    fn _band_offset(&self, ispin: usize, ikpt: usize, iband: usize) -> Option<u64> {
+        _record_offset(self.reclen, self.nkpts, self.nbands, ispin, ikpt, Some(iband))
+    }
+}
+
+
+/// Real-space quantities written from the grids of `RealspaceFft::transform`, i.e. sqrt(V) times
+/// the periodic part of the Bloch function. The density ones integrate to one electron as CHGCAR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum WavMode {
+    Abs2,  // |psi|^2 * V, summed over the spinor components
+    Re,    // Re(psi) * sqrt(V)
+    Im,    // Im(psi) * sqrt(V)
+    Arg,   // arg(psi) in radians
+    Up,    // |psi_up|^2 * V of non-collinear states
+    Down,  // |psi_dn|^2 * V of non-collinear states
+}
+
+impl FromStr for WavMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "abs2"        => Ok(Self::Abs2),
+            "re"          => Ok(Self::Re),
+            "im"          => Ok(Self::Im),
+            "arg"         => Ok(Self::Arg),
+            "up"          => Ok(Self::Up),
+            "dn" | "down" => Ok(Self::Down),
+            _ => Err(format!("Invalid mode '{}', should be abs2, re, im, arg, up or dn", s)),
+        }
+    }
+}
+
+impl WavMode {
+    /// Pairs of the file name suffix and values, one for each spinor component for re, im and
+    /// arg of non-collinear states, empty for the spinor-resolved modes of collinear states.
+    pub fn values(&self, grids: &[Vec<Complex<f64>>]) -> Vec<(String, Vec<f64>)> {
+        let ncl = grids.len() == 2;
+        let each = |name: &str, f: fn(&Complex<f64>) -> f64| grids.iter()
+            .zip(["up", "dn"])
+            .map(|(g, spinor)| {
+                let suffix = if ncl { format!("{}_{}", name, spinor) } else { name.to_string() };
+                (suffix, g.iter().map(f).collect())
+            })
+            .collect::<Vec<_>>();
+
+        match self {
+            Self::Abs2 => {
+                let mut rho = vec![0.0; grids[0].len()];
+                for g in grids.iter() {
+                    rho.iter_mut().zip(g.iter()).for_each(|(x, c)| *x += c.norm_sqr());
+                }
+                vec![("abs2".to_string(), rho)]
+            },
+            Self::Re   => each("re", |c| c.re),
+            Self::Im   => each("im", |c| c.im),
+            Self::Arg  => each("arg", |c| c.arg()),
+            Self::Up | Self::Down if !ncl => vec![],
+            Self::Up   => vec![("up".to_string(), grids[0].iter().map(|c| c.norm_sqr()).collect())],
+            Self::Down => vec![("dn".to_string(), grids[1].iter().map(|c| c.norm_sqr()).collect())],
+        }
+    }
+}
+
+
+/// Inverse FFT of plane-wave coefficients onto a real-space grid. The FFT plans and scratch
+/// buffers are created once and reused for all the bands transformed by one worker.
+pub struct RealspaceFft {
+    ngrid   : [usize; 3],
+    ffts    : [Arc<dyn Fft<f64>>; 3],
+    line    : Vec<Complex<f64>>,
+    scratch : Vec<Complex<f64>>,
+}
+
+impl RealspaceFft {
+    pub fn new(ngrid: [usize; 3]) -> Self {
+        let mut planner = FftPlanner::<f64>::new();
+        let ffts = ngrid.map(|n| planner.plan_fft_inverse(n));
+        let nscratch = ffts.iter().map(|f| f.get_inplace_scratch_len()).max().unwrap_or(0);
+        Self {
+            ngrid,
+            ffts,
+            line: vec![Complex::new(0.0, 0.0); ngrid.iter().copied().max().unwrap_or(0)],
+            scratch: vec![Complex::new(0.0, 0.0); nscratch],
+        }
+    }
+
+    /// Fills `grid` with sum_G c(G) exp(iG.r) at the grid points, x runs fastest. It is
+    /// sqrt(V) * psi(r) for the normalized coefficients, i.e. the periodic part of the Bloch
+    /// function without exp(ik.r), and |grid|^2 is the density scaled by the cell volume as in
+    /// CHGCAR. The grid has to hold the G vectors, i.e. ngrid >= 2 * max(|G|) + 1 on each axis.
+    pub fn transform(&mut self, gvecs: &[[i32; 3]], coeffs: &[Complex<f64>], grid: &mut Vec<Complex<f64>>) {
+        let [nx, ny, nz] = self.ngrid;
+        grid.clear();
+        grid.resize(nx * ny * nz, Complex::new(0.0, 0.0));
+        for (g, c) in gvecs.iter().zip(coeffs.iter()) {
+            let [ix, iy, iz] = [0, 1, 2].map(|i| g[i].rem_euclid(self.ngrid[i] as i32) as usize);
+            grid[ix + nx * (iy + ny * iz)] += c;
+        }
+
+        // The lines along x are contiguous, the ones along y and z are copied out and back
+        self.ffts[0].process_with_scratch(grid, &mut self.scratch);
+        for (axis, n, stride) in [(1, ny, nx), (2, nz, nx * ny)] {
+            let starts = (0 .. grid.len()).filter(|i| (i / stride) % n == 0);
+            for start in starts {
+                let line = &mut self.line[.. n];
+                line.iter_mut().enumerate().for_each(|(k, x)| *x = grid[start + k * stride]);
+                self.ffts[axis].process_with_scratch(line, &mut self.scratch);
+                line.iter().enumerate().for_each(|(k, x)| grid[start + k * stride] = *x);
+            }
+        }
+    }
+}
+
+
+// Headers of WAVECAR, cached as the parsed data
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct _WavecarHeader {
+    reclen      : u64,
+    nspin       : usize,
+    tag         : i64,
+    precision   : Precision,
+    nkpts       : usize,
+    nbands      : usize,
+    encut       : f64,
+    cell        : Mat33<f64>,
+    efermi      : f64,
+    wavetype    : WavecarType,
+    ngrid       : [usize; 3],
+    nplws       : Vec<usize>,
+    kvecs       : Vec<[f64; 3]>,
+    eigvals     : Vec<f64>,
+    occupations : Vec<f64>,
+}
+
+
+fn _parse_header(mmap: &[u8]) -> io::Result<_WavecarHeader> {
+    let err = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid WAVECAR: {}", msg));
+
+    let head = _read_f64s(mmap, 0, 3).ok_or_else(|| err("file too short".to_string()))?;
+    let (reclen, nspin, tag) = (head[0] as u64, head[1] as usize, head[2].round() as i64);
+    let precision = Precision::from_tag(tag)
+        .ok_or_else(|| err(format!("unknown precision tag {}", tag)))?;
+    if reclen < 8 * 13 || !(nspin == 1 || nspin == 2) {
+        return Err(err(format!("record length {} and ISPIN {} are not supported", reclen, nspin)));
+    }
+
+    let rec1 = _read_f64s(mmap, reclen, 13).ok_or_else(|| err("file too short".to_string()))?;
+    let (nkpts, nbands, encut) = (rec1[0] as usize, rec1[1] as usize, rec1[2]);
+    let cell = [[rec1[3], rec1[4], rec1[5]],
+                [rec1[6], rec1[7], rec1[8]],
+                [rec1[9], rec1[10], rec1[11]]];
+    // Zero if not written, the rest of records are padded with zeros
+    let efermi = rec1[12];
+    let header_len = nbands.checked_mul(24).and_then(|n| n.checked_add(32));
+    if nkpts == 0 || nbands == 0 || header_len.is_none_or(|n| n as u64 > reclen) {
+        return Err(err(format!("NKPTS = {} and NBANDS = {} do not fit the record length {}", nkpts, nbands, reclen)));
+    }
+
+    // Avoids allocating for the corrupted numbers of k-points and bands
+    if _record_offset(reclen, nkpts, nbands, nspin - 1, nkpts - 1, None).is_none_or(|o| o >= mmap.len() as u64) {
+        return Err(err("file is truncated".to_string()));
+    }
+
+    let mut header = _WavecarHeader {
+        reclen,
+        nspin,
+        tag,
+        precision,
+        nkpts,
+        nbands,
+        encut,
+        cell,
+        efermi,
+        wavetype: WavecarType::Standard,
+        ngrid: _ngrid(&cell, encut),
+        nplws: vec![0; nkpts],
+        kvecs: vec![[0.0; 3]; nkpts],
+        eigvals: vec![0.0; nspin * nkpts * nbands],
+        occupations: vec![0.0; nspin * nkpts * nbands],
+    };
+
+    for ispin in 0 .. nspin {
+        for ikpt in 0 .. nkpts {
+            let v = _record_offset(reclen, nkpts, nbands, ispin, ikpt, None)
+                .and_then(|offset| _read_f64s(mmap, offset, 4 + 3 * nbands))
+                .ok_or_else(|| err(format!("header of spin {} k-point {} is out of the file", ispin + 1, ikpt + 1)))?;
+            let nplw = v[0] as usize;
+            if nplw.checked_mul(precision.nbytes()).is_none_or(|n| n as u64 > reclen) {
+                return Err(err(format!("{} plane waves do not fit the record length {}", nplw, reclen)));
+            }
+            if ispin > 0 && nplw != header.nplws[ikpt] {
+                return Err(err(format!("inconsistent number of plane waves at k-point {}", ikpt + 1)));
+            }
+            header.nplws[ikpt] = nplw;
+            header.kvecs[ikpt] = [v[1], v[2], v[3]];
+
+            let start = (ispin * nkpts + ikpt) * nbands;
+            for (ib, band) in v[4 ..].chunks(3).enumerate() {
+                header.eigvals[start + ib] = band[0];
+                header.occupations[start + ib] = band[2];
+            }
+        }
+    }
+
+    // Only the last record is checked, the offsets of the others are smaller
+    let end = _record_offset(reclen, nkpts, nbands, nspin - 1, nkpts - 1, Some(nbands - 1))
+        .and_then(|offset| offset.checked_add((header.nplws[nkpts - 1] * precision.nbytes()) as u64));
+    if end.is_none_or(|end| end > mmap.len() as u64) {
+        return Err(err("file is truncated".to_string()));
+    }
+
+    // The x-direction scheme is assumed for Gamma-only WAVECAR, see `with_gamma_half`
+    let nfull = _gvectors(&header.cell, header.encut, header.ngrid, &header.kvecs[0], None).len();
+    header.wavetype = match header.nplws[0] {
+        n if n == nfull => WavecarType::Standard,
+        n if n == 2 * nfull => WavecarType::NonCollinear,
+        n if n == nfull.div_ceil(2) && header.kvecs[0] == [0.0; 3] => WavecarType::GammaHalf(Axis::X),
+        n => return Err(err(format!("{} plane waves at the first k-point do not match the {} ones within ENCUT", n, nfull))),
+    };
+    Ok(header)
+}
+
+
+// Byte offset of the record of `iband` at (ispin, ikpt), or the header record of the k-point if
+// `iband` is None. Large WAVECARs have more than 2^31 records and offsets beyond 2^63 are not
+// impossible for corrupted headers, thus all the arithmetic is checked in 64 bits.
+fn _record_offset(reclen: u64, nkpts: usize, nbands: usize, ispin: usize, ikpt: usize, iband: Option<usize>) -> Option<u64> {
+    let ik = (ispin as u64).checked_mul(nkpts as u64)?.checked_add(ikpt as u64)?;
+    let irec = ik.checked_mul((nbands as u64).checked_add(1)?)?
+        .checked_add(2)?
+        .checked_add(iband.map_or(Some(0), |ib| (ib as u64).checked_add(1))?)?;
+    irec.checked_mul(reclen)
+}
+
+
+// Constants of VASP, the G vectors within ENCUT have to be exactly the ones of VASP
+const RYTOEV : f64 = 13.605826;
+const AUTOA  : f64 = 0.529177249;
+
+
+// Number of G vectors along each axis enclosing the sphere of ENCUT
+pub(crate) fn _ngrid(cell: &Mat33<f64>, encut: f64) -> [usize; 3] {
+    cell.map(|a| {
+        let norm = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let gmax = (encut / RYTOEV).sqrt() / (2.0 * PI / (norm / AUTOA));
+        2 * gmax.ceil().max(0.0) as usize + 1
+    })
+}
+
+
+// Whether G is kept in the half grid along `axis`: the component along `axis` is positive, or
+// zero with the ties broken by the other two axes. The y scheme is the x one with x and y
+// swapped, same as the z scheme with x and z swapped.
+fn _filter_fft_grid(g: &[i32; 3], axis: Axis) -> bool {
+    let (i, j, k) = match axis {
+        Axis::X => (0, 1, 2),
+        Axis::Y => (1, 0, 2),
+        Axis::Z => (2, 1, 0),
+    };
+    g[i] > 0 || (g[i] == 0 && g[j] > 0) || (g[i] == 0 && g[j] == 0 && g[k] >= 0)
+}
+
+
+// G vectors with the kinetic energy of k+G below ENCUT, z runs slowest and x fastest, and the
+// components count 0, 1, ..., n/2, -n/2, ..., -1 along each axis, as VASP does.
+pub(crate) fn _gvectors(cell: &Mat33<f64>, encut: f64, ngrid: [usize; 3], kvec: &[f64; 3], half: Option<Axis>) -> Vec<[i32; 3]> {
+    let inv = _calc_inv_3x3(cell);
+    let freqs = ngrid.map(|n| {
+        (0 .. n as i32).map(|i| if i <= n as i32 / 2 { i } else { i - n as i32 }).collect::<Vec<i32>>()
+    });
+    let hsqdtm = RYTOEV * AUTOA * AUTOA;
+
+    let mut ret = vec![];
+    for &gz in freqs[2].iter() {
+        for &gy in freqs[1].iter() {
+            for &gx in freqs[0].iter() {
+                let g = [gx, gy, gz];
+                if half.is_some_and(|axis| !_filter_fft_grid(&g, axis)) {
+                    continue;
+                }
+                let q = [0, 1, 2].map(|i| g[i] as f64 + kvec[i]);
+                let cart = [0, 1, 2].map(|x| (0 .. 3).map(|j| q[j] * inv[x][j]).sum::<f64>() * 2.0 * PI);
+                if hsqdtm * cart.iter().map(|x| x * x).sum::<f64>() < encut {
+                    ret.push(g);
+                }
+            }
+        }
+    }
+    ret
+}
+
+
+// The other half of Gamma-only coefficients, see `Wavecar::read_spinors`
+fn _unfold_gamma(gvecs: &[[i32; 3]], coeffs: &[Complex<f64>]) -> (Vec<[i32; 3]>, Vec<Complex<f64>>) {
+    let mut g = gvecs.to_vec();
+    let mut c = coeffs.to_vec();
+    for (gv, cv) in gvecs.iter().zip(coeffs.iter()) {
+        if *gv != [0, 0, 0] {
+            g.push(gv.map(|x| -x));
+            c.push(cv.conj() * FRAC_1_SQRT_2);
+        }
+    }
+    gvecs.iter().zip(c.iter_mut())
+        .filter(|(gv, _)| **gv != [0, 0, 0])
+        .for_each(|(_, cv)| *cv *= FRAC_1_SQRT_2);
+    (g, c)
+}
+
+
+fn _read_f64s(bytes: &[u8], offset: u64, n: usize) -> Option<Vec<f64>> {
+    let start = usize::try_from(offset).ok()?;
+    let chunk = bytes.get(start .. start.checked_add(n * 8)?)?;
+    Some(chunk.chunks_exact(8)
+         .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+         .collect())
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes a WAVECAR of the given bands, the coefficients are indexed by [ispin][ikpt][iband].
+    pub(crate) fn _write_wavecar(path: &Path, tag: i64, encut: f64, cell: &Mat33<f64>,
+                                 kvecs: &[[f64; 3]], eigvals: &[Vec<Vec<f64>>],
+                                 coeffs: &[Vec<Vec<Vec<Complex<f64>>>>]) {
+        let nspin = coeffs.len();
+        let nkpts = kvecs.len();
+        let nbands = coeffs[0][0].len();
+        let precision = Precision::from_tag(tag).unwrap();
+        let nplw_max = coeffs.iter().flatten().flatten().map(|c| c.len()).max().unwrap();
+        let reclen = (nplw_max * precision.nbytes()).max(8 * (4 + 3 * nbands)).max(8 * 13);
+
+        let mut buf = vec![];
+        let mut record = |values: Vec<u8>| {
+            let mut values = values;
+            values.resize(reclen, 0);
+            buf.extend(values);
+        };
+        let f64s = |v: &[f64]| v.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+
+        record(f64s(&[reclen as f64, nspin as f64, tag as f64]));
+        let mut rec1 = vec![nkpts as f64, nbands as f64, encut];
+        rec1.extend(cell.iter().flatten());
+        rec1.push(1.5);
+        record(f64s(&rec1));
+
+        for ispin in 0 .. nspin {
+            for ikpt in 0 .. nkpts {
+                let bands = &coeffs[ispin][ikpt];
+                let mut header = vec![bands[0].len() as f64, kvecs[ikpt][0], kvecs[ikpt][1], kvecs[ikpt][2]];
+                for (ib, e) in eigvals[ispin][ikpt].iter().enumerate() {
+                    header.extend([*e, 0.0, if ib == 0 { 1.0 } else { 0.0 }]);
+                }
+                record(f64s(&header));
+                for band in bands.iter() {
+                    let bytes = match precision {
+                        Precision::Single => band.iter()
+                            .flat_map(|c| [(c.re as f32).to_le_bytes(), (c.im as f32).to_le_bytes()].concat())
+                            .collect(),
+                        Precision::Double => band.iter()
+                            .flat_map(|c| [c.re.to_le_bytes(), c.im.to_le_bytes()].concat())
+                            .collect(),
+                    };
+                    record(bytes);
+                }
+            }
+        }
+        fs::File::create(path).unwrap().write_all(&buf).unwrap();
+    }
+
+    pub(crate) fn _coeffs(nplw: usize, seed: f64) -> Vec<Complex<f64>> {
+        let c = (0 .. nplw)
+            .map(|i| Complex::new((seed + i as f64).sin(), (seed * 2.0 + i as f64 * 0.7).cos()))
+            .collect::<Vec<_>>();
+        let norm = c.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+        c.into_iter().map(|x| x / norm).collect()
+    }
+
+    #[test]
+    fn test_record_offset() {
+        // The first band of spin 2 at the last k-point, beyond 2^31 records
+        let (reclen, nkpts, nbands) = (1u64 << 20, 4000, 1_000_000);
+        let irec = 2 + (nkpts as u128 * 2 - 1) * (nbands as u128 + 1) + 1;
+        assert!(irec > 1 << 31);
+        assert_eq!(_record_offset(reclen, nkpts, nbands, 1, nkpts - 1, Some(0)).map(|x| x as u128), Some(irec * reclen as u128));
+        assert_eq!(_record_offset(reclen, nkpts, nbands, 0, 0, None), Some(2 * reclen));
+        assert_eq!(_record_offset(u64::MAX / 2, nkpts, nbands, 0, 0, Some(0)), None);
+    }
+
+    #[test]
+    fn test_read_wavecar() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        let cell = [[3.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 5.0]];
+        let kvecs = [[0.0, 0.0, 0.0], [0.25, 0.0, 0.5]];
+        let eigvals = vec![vec![vec![-1.0, 2.0, 3.5], vec![-0.5, 2.5, 4.0]],
+                           vec![vec![-1.1, 2.1, 3.6], vec![-0.6, 2.6, 4.1]]];
+        let nplws = kvecs.iter()
+            .map(|k| _gvectors(&cell, 100.0, _ngrid(&cell, 100.0), k, None).len())
+            .collect::<Vec<_>>();
+        let coeffs = (0 .. 2)
+            .map(|s| (0 .. 2).map(|k| (0 .. 3).map(|b| _coeffs(nplws[k], (s * 6 + k * 3 + b) as f64)).collect()).collect())
+            .collect::<Vec<Vec<Vec<_>>>>();
+
+        for tag in [45200, 45210, 53300, 53310] {
+            _write_wavecar(&path, tag, 100.0, &cell, &kvecs, &eigvals, &coeffs);
+            let wav = Wavecar::from_file(&path).unwrap();
+            assert_eq!((wav.nspin, wav.nkpts, wav.nbands, wav.tag), (2, 2, 3, tag));
+            assert_eq!((wav.encut, wav.efermi), (100.0, 1.5));
+            assert_eq!(wav.cell, cell);
+            assert_eq!(wav.nplws, nplws);
+            assert_eq!(wav.wavetype, WavecarType::Standard);
+            assert_eq!(wav.kvecs[1], [0.25, 0.0, 0.5]);
+            assert_eq!(wav.eigval(1, 0, 2), 3.6);
+            assert_eq!(wav.occupation(0, 1, 0), 1.0);
+
+            // Checksums of the coefficients, single precision ones are rounded
+            let thr = if wav.precision == Precision::Single { 1E-6 } else { 1E-14 };
+            let c = wav.read_coeffs(1, 1, 2);
+            let expected = &coeffs[1][1][2];
+            let sum = |v: &[Complex<f64>]| v.iter().fold(Complex::new(0.0, 0.0), |acc, x| acc + x);
+            assert!((sum(&c) - sum(expected)).norm() < thr * nplws[1] as f64);
+            assert!((c.iter().map(|x| x.norm_sqr()).sum::<f64>() - 1.0).abs() < thr * nplws[1] as f64);
+            assert!(c.iter().zip(expected.iter()).all(|(a, b)| (a - b).norm() < thr));
+        }
+
+        // Streamed table of the selected states
+        let wav = Wavecar::from_file(&path).unwrap();
+        let mut buf = vec![];
+        wav.write_eigs(&mut buf, &[1], &[0, 1], &[2], OutputFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "spin,kpoint,band,eigval,occupation\n2,1,3,3.6,0\n2,2,3,4.1,0\n");
+        let mut buf = vec![];
+        wav.write_eigs(&mut buf, &[0], &[1], &[0, 1], OutputFormat::Json).unwrap();
+        let rows = serde_json::from_slice::<serde_json::Value>(&buf).unwrap();
+        assert_eq!(rows[1]["eigval"], 2.5);
+
+        // Headers survive the round trip through the cache
+        let header = _parse_header(&fs::read(&path).unwrap()).unwrap();
+        let cbor = serde_cbor::to_vec(&header).unwrap();
+        assert_eq!(serde_cbor::from_slice::<_WavecarHeader>(&cbor).unwrap(), header);
+
+        // Truncated file and unknown tag
+        _write_wavecar(&path, 45210, 100.0, &cell, &kvecs, &eigvals, &coeffs);
+        let bytes = fs::read(&path).unwrap();
+        let reclen = Wavecar::from_file(&path).unwrap().reclen as usize;
+        fs::write(&path, &bytes[.. bytes.len() - reclen]).unwrap();
+        assert!(Wavecar::from_file(&path).is_err());
+        let mut bytes = bytes;
+        bytes[16 .. 24].copy_from_slice(&12345.0f64.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(Wavecar::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_gvectors() {
+        let cell = [[3.0, 0.0, 0.0], [-1.5, 2.6, 0.0], [0.0, 0.0, 5.0]];
+        let ngrid = _ngrid(&cell, 150.0);
+        let full = _gvectors(&cell, 150.0, ngrid, &[0.0; 3], None);
+        assert_eq!(&full[.. 2], &[[0, 0, 0], [1, 0, 0]]);
+        assert!(full.iter().all(|g| full.contains(&g.map(|x| -x))));
+        assert!(full.iter().all(|g| (0 .. 3).all(|i| g[i].unsigned_abs() as usize <= ngrid[i] / 2)));
+
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let half = _gvectors(&cell, 150.0, ngrid, &[0.0; 3], Some(axis));
+            assert_eq!(half.len(), full.len().div_ceil(2));
+            assert!(half.iter().all(|g| *g == [0, 0, 0] || !half.contains(&g.map(|x| -x))));
+            assert!(half.iter().all(|g| full.contains(g)));
+        }
+        let yhalf = _gvectors(&cell, 150.0, ngrid, &[0.0; 3], Some(Axis::Y));
+        assert!(yhalf.iter().all(|g| g[1] > 0 || (g[1] == 0 && g[0] > 0) || (g[1] == 0 && g[0] == 0 && g[2] >= 0)));
+
+        // The unfolded coefficients are normalized and of a real function
+        let c = _coeffs(yhalf.len(), 0.3);
+        let (g, c) = _unfold_gamma(&yhalf, &c);
+        assert_eq!(g.len(), full.len());
+        assert!((c.iter().map(|x| x.norm_sqr()).sum::<f64>() - 1.0).abs() < 1E-12);
+        let i = g.iter().position(|x| *x == [1, 1, 0]).unwrap();
+        let j = g.iter().position(|x| *x == [-1, -1, 0]).unwrap();
+        assert!((c[i] - c[j].conj()).norm() < 1E-14);
+    }
+
+    #[test]
+    fn test_wavecar_type() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        let cell = [[4.0, 0.0, 0.0], [0.0, 4.0, 0.0], [0.0, 0.0, 4.5]];
+        let ngrid = _ngrid(&cell, 120.0);
+        let nfull = _gvectors(&cell, 120.0, ngrid, &[0.0; 3], None).len();
+        let eigvals = vec![vec![vec![0.0, 1.0]]];
+
+        // Gamma-only, the y-direction scheme has to be given
+        let coeffs = vec![vec![vec![_coeffs(nfull.div_ceil(2), 0.1), _coeffs(nfull.div_ceil(2), 0.2)]]];
+        _write_wavecar(&path, 45200, 120.0, &cell, &[[0.0; 3]], &eigvals, &coeffs);
+        let wav = Wavecar::from_file(&path).unwrap();
+        assert_eq!(wav.wavetype, WavecarType::GammaHalf(Axis::X));
+        let wav = wav.with_gamma_half("y".parse().unwrap());
+        let gvecs = wav.gvectors(0);
+        assert!(gvecs.iter().all(|g| _filter_fft_grid(g, Axis::Y)));
+        let (g, c) = wav.read_spinors(0, 0, 1, &gvecs);
+        assert_eq!((g.len(), c.len(), c[0].len()), (nfull, 1, nfull));
+
+        // Non-collinear, two components on the same G vectors
+        let coeffs = vec![vec![vec![_coeffs(2 * nfull, 0.1), _coeffs(2 * nfull, 0.2)]]];
+        _write_wavecar(&path, 45210, 120.0, &cell, &[[0.0; 3]], &eigvals, &coeffs);
+        let wav = Wavecar::from_file(&path).unwrap();
+        assert_eq!(wav.wavetype, WavecarType::NonCollinear);
+        let gvecs = wav.gvectors(0);
+        let (_, c) = wav.read_spinors(0, 0, 0, &gvecs);
+        assert_eq!((c.len(), c[1].len()), (2, nfull));
+        assert_eq!(c[1][0], coeffs[0][0][0][nfull]);
+
+        // Neither of them
+        let coeffs = vec![vec![vec![_coeffs(nfull + 3, 0.1), _coeffs(nfull + 3, 0.2)]]];
+        _write_wavecar(&path, 45210, 120.0, &cell, &[[0.0; 3]], &eigvals, &coeffs);
+        assert!(Wavecar::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_realspace() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("WAVECAR");
+        let cell = [[3.0, 0.0, 0.0], [0.0, 3.5, 0.0], [0.0, 0.0, 4.0]];
+        let kvecs = [[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]];
+        let nplws = kvecs.iter()
+            .map(|k| _gvectors(&cell, 80.0, _ngrid(&cell, 80.0), k, None).len())
+            .collect::<Vec<_>>();
+        let eigvals = vec![vec![vec![0.0, 1.0, 2.0]; 2]];
+        let coeffs = vec![(0 .. 2).map(|k| (0 .. 3).map(|b| _coeffs(nplws[k], (k * 3 + b) as f64)).collect()).collect::<Vec<_>>()];
+        _write_wavecar(&path, 45210, 80.0, &cell, &kvecs, &eigvals, &coeffs);
+        let wav = Wavecar::from_file(&path).unwrap();
+        let ngrid = wav.ngrid;
+
+        // Direct sum at a few grid points
+        let gvecs = wav.gvectors(1);
+        let c = wav.read_coeffs(0, 1, 2);
+        let mut grid = vec![];
+        RealspaceFft::new(ngrid).transform(&gvecs, &c, &mut grid);
+        for (ix, iy, iz) in [(0, 0, 0), (1, 2, 3), (ngrid[0] - 1, 4, ngrid[2] - 2)] {
+            let r = [ix as f64 / ngrid[0] as f64, iy as f64 / ngrid[1] as f64, iz as f64 / ngrid[2] as f64];
+            let expected = gvecs.iter().zip(c.iter())
+                .map(|(g, c)| c * Complex::from_polar(1.0, 2.0 * PI * (0 .. 3).map(|i| g[i] as f64 * r[i]).sum::<f64>()))
+                .fold(Complex::new(0.0, 0.0), |acc, x| acc + x);
+            assert!((grid[ix + ngrid[0] * (iy + ngrid[1] * iz)] - expected).norm() < 1E-10);
+        }
+
+        // All the states are passed once and normalized, i.e. the mean of |psi|^2 * V is 1
+        let states = (0 .. 2).flat_map(|k| (0 .. 3).map(move |b| (0, k, b))).collect::<Vec<_>>();
+        let mut seen = vec![];
+        wav.for_each_realspace(&states, ngrid, 3, |state, grids| {
+            assert_eq!(grids.len(), 1);
+            let mean = grids[0].iter().map(|x| x.norm_sqr()).sum::<f64>() / grids[0].len() as f64;
+            assert!((mean - 1.0).abs() < 1E-10);
+            seen.push(state);
+            Ok(())
+        }).unwrap();
+        seen.sort();
+        assert_eq!(seen, states);
+
+        // Errors of the callback stop the workers, too coarse grids are refused
+        let mut count = 0;
+        let ret = wav.for_each_realspace(&states, ngrid, 2, |_, _| {
+            count += 1;
+            Err(io::Error::other("stop"))
+        });
+        assert!(ret.is_err());
+        assert_eq!(count, 1);
+        assert!(wav.for_each_realspace(&states, [ngrid[0] / 2, ngrid[1], ngrid[2]], 2, |_, _| Ok(())).is_err());
+
+        // Modes of collinear and non-collinear grids
+        let grids = vec![vec![Complex::new(1.0, 1.0), Complex::new(0.0, -2.0)]];
+        assert_eq!(WavMode::Abs2.values(&grids), vec![("abs2".to_string(), vec![2.0, 4.0])]);
+        assert_eq!(WavMode::Im.values(&grids), vec![("im".to_string(), vec![1.0, -2.0])]);
+        assert!(WavMode::Up.values(&grids).is_empty());
+        let grids = vec![grids[0].clone(), vec![Complex::new(3.0, 0.0), Complex::new(0.0, 0.0)]];
+        assert_eq!(WavMode::Abs2.values(&grids)[0].1, vec![11.0, 4.0]);
+        assert_eq!(WavMode::Down.values(&grids), vec![("dn".to_string(), vec![9.0, 0.0])]);
+        let args = WavMode::Arg.values(&grids);
+        assert_eq!((args[0].0.as_str(), args[1].0.as_str()), ("arg_up", "arg_dn"));
+        assert!((args[0].1[0] - PI / 4.0).abs() < 1E-12);
+        assert_eq!("down".parse::<WavMode>(), Ok(WavMode::Down));
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::str::FromStr;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use serde::Serialize;
+use serde_json::json;
+use colored::Colorize;
+use log::info;
+use crate::procar::Procar;
+use crate::selection::Selection;
+use crate::plot::Plot;
+use crate::traits::Tabular;
+use crate::dos::{
+    Smearing,
+    _selected_weight,
+    _state_weights,
+};
+
+
+// Absorption edges, the final states are selected by the dipole selection rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Edge {
+    K,    // 1s -> p
+    L1,   // 2s -> p
+    L23,  // 2p -> s + d
+}
+
+impl FromStr for Edge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "k"   => Ok(Self::K),
+            "l1"  => Ok(Self::L1),
+            "l23" => Ok(Self::L23),
+            _ => Err(format!("Invalid edge '{}', should be k, l1 or l23", s)),
+        }
+    }
+}
+
+impl Edge {
+    /// Orbitals of the final states allowed by the dipole selection rule.
+    pub fn orbits(&self) -> &'static str {
+        match self {
+            Self::K | Self::L1 => "p",
+            Self::L23          => "s d",
+        }
+    }
+}
+
+
+/// Lifetime broadening growing linearly with the energy above E-fermi, i.e. the FWHM of the
+/// Lorentzian is `gamma0 + slope * (E - Ef)`, capped by `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Broadening {
+    pub gamma0 : f64,
+    pub slope  : f64,
+    pub max    : f64,
+}
+
+impl Broadening {
+    pub fn fwhm(&self, e: f64) -> f64 {
+        (self.gamma0 + self.slope * e.max(0.0)).min(self.max)
+    }
+}
+
+
+fn _prepare_fname(path: &(impl AsRef<Path> + ?Sized), name: &str) -> io::Result<PathBuf> {
+    let mut fname = PathBuf::new();
+    fname.push(path);
+    if !fname.is_dir() {
+        fs::create_dir_all(&fname)?;
+    }
+    fname.push(name);
+    Ok(fname)
+}
+
+
+// Unoccupied DOS projected onto the absorber atoms and the selected orbitals, broadened by
+// the energy dependent lifetime. It approximates the shapes of XAS and ELNES near the edge
+// within the ground state, core-hole effects are included only if PROCAR comes from a
+// calculation with the core hole, e.g. ICORELEVEL=2.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct XasSpectrum {
+    pub label      : String,
+    pub energies   : Vec<f64>,       // relative to E-fermi
+    pub intensity  : Vec<Vec<f64>>,  // [nspin][nedos]
+    pub broadening : Broadening,
+    pub onset      : Option<f64>,    // lowest unoccupied state with the selected projection, relative to E-fermi
+}
+
+impl XasSpectrum {
+    pub fn from_procar(procar: &Procar, efermi: f64, emin: f64, emax: f64, nedos: usize,
+                       broadening: Broadening, sel: &Selection) -> Self {
+        assert!(emin < emax, "Invalid energy range, emin should be less than emax");
+        assert!(nedos > 1, "NEDOS should be larger than 1");
+        assert!(broadening.gamma0 > 0.0, "Lifetime broadening should be positive");
+
+        let de = (emax - emin) / (nedos - 1) as f64;
+        let energies = (0 .. nedos).map(|i| emin + de * i as f64).collect::<Vec<f64>>();
+        let (weights, factor) = _state_weights(procar);
+        let fmax = if procar.occupations.iter().any(|o| *o > 1.0 + 1E-6) { 2.0 } else { 1.0 };
+
+        let mut intensity = vec![vec![0.0f64; nedos]; procar.nspin];
+        let mut onset: Option<f64> = None;
+        for (ispin, spin) in intensity.iter_mut().enumerate() {
+            let icomp = if procar.lncl { 0 } else { ispin };
+            for (ik, wk) in weights.iter().enumerate() {
+                for ib in 0 .. procar.nbands {
+                    let empty = 1.0 - (procar.occupation(ispin, ik, ib) / fmax).clamp(0.0, 1.0);
+                    let proj = _selected_weight(procar, icomp, ik, ib, sel);
+                    if empty < 1E-6 || proj < 1E-6 { continue; }
+
+                    let e = procar.eigval(ispin, ik, ib) - efermi;
+                    if empty > 0.5 && onset.is_none_or(|o| e < o) {
+                        onset = Some(e);
+                    }
+
+                    let hwhm = 0.5 * broadening.fwhm(e);
+                    let w = wk * factor * empty * proj;
+                    for (x, v) in energies.iter().zip(spin.iter_mut()) {
+                        *v += w * Smearing::Lorentzian.delta(x - e, hwhm);
+                    }
+                }
+            }
+        }
+
+        Self {
+            label: sel.label.clone(),
+            energies,
+            intensity,
+            broadening,
+            onset,
+        }
+    }
+
+    pub fn save_as_txt(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "xas.txt")?;
+        info!("Saving broadened unoccupied PDOS to {:?} ...", &fname);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&fname)?;
+
+        let nspin = self.intensity.len();
+        writeln!(f, "# Unoccupied PDOS of {}, Lorentzian FWHM = {} + {} * (E-Ef) eV, at most {} eV",
+                 self.label, self.broadening.gamma0, self.broadening.slope, self.broadening.max)?;
+        write!(f, "# {:>10}", "E-Ef/eV")?;
+        for ispin in 0 .. nspin {
+            write!(f, " {:>14}", format!("intensity{}", _spin_suffix(nspin, ispin)))?;
+        }
+        writeln!(f)?;
+        for (i, e) in self.energies.iter().enumerate() {
+            write!(f, "  {:10.5}", e)?;
+            for spin in self.intensity.iter() {
+                write!(f, " {:14.6}", spin[i])?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_as_html(&self, path: &(impl AsRef<Path> + ?Sized)) -> io::Result<()> {
+        let fname = _prepare_fname(path, "xas.html")?;
+        let nspin = self.intensity.len();
+        let mut plot = Plot::new()
+            .layout(json!({
+                "title": format!("Unoccupied PDOS of {}", self.label),
+                "xaxis": {"title": "E-Ef (eV)"},
+                "yaxis": {"title": "Intensity (states/eV)"},
+                "shapes": [{
+                    "type": "line", "xref": "x", "yref": "paper",
+                    "x0": 0.0, "x1": 0.0, "y0": 0.0, "y1": 1.0,
+                    "line": {"dash": "dash", "color": "gray", "width": 1},
+                }],
+            }));
+        for (ispin, spin) in self.intensity.iter().enumerate() {
+            plot.add_trace(json!({
+                "type": "scatter",
+                "mode": "lines",
+                "name": format!("{}{}", self.label, _spin_suffix(nspin, ispin)),
+                "x": self.energies,
+                "y": spin,
+            }));
+        }
+        plot.save_html(&fname)
+    }
+}
+
+
+fn _spin_suffix(nspin: usize, ispin: usize) -> &'static str {
+    match (nspin, ispin) {
+        (2, 0) => "_up",
+        (2, _) => "_dn",
+        _      => "",
+    }
+}
+
+
+// The spectra are saved by `save_as_txt`, only the summary is listed here
+impl Tabular for XasSpectrum {
+    fn headers(&self) -> Vec<String> {
+        ["label", "onset", "gamma0", "slope", "max"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.label.clone(),
+            self.onset.map(|e| format!("{:.4}", e)).unwrap_or_default(),
+            format!("{}", self.broadening.gamma0),
+            format!("{}", self.broadening.slope),
+            format!("{}", self.broadening.max),
+        ]]
+    }
+}
+
+impl fmt::Display for XasSpectrum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", format!("# Unoccupied PDOS of {}", self.label).bright_green())?;
+        writeln!(f, "  Lorentzian FWHM: {} + {} * (E-Ef) eV, at most {} eV",
+                 self.broadening.gamma0, self.broadening.slope, self.broadening.max)?;
+        match self.onset {
+            Some(e) => writeln!(f, "  Lowest unoccupied state: {} eV above E-fermi", format!("{:.4}", e).bright_yellow()),
+            None => writeln!(f, "  No unoccupied states projected onto the selection"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xas_spectrum() {
+        // One occupied and one empty band with p character at a single k-point
+        let procar = Procar {
+            nkpts: 1,
+            nbands: 2,
+            nions: 1,
+            nspin: 1,
+            lncl: false,
+            orbitals: vec!["s".to_string(), "px".to_string(), "py".to_string(), "pz".to_string()],
+            kpoints: vec![[0.0, 0.0, 0.0]],
+            weights: vec![1.0],
+            eigvals: vec![-1.0, 2.0],
+            occupations: vec![2.0, 0.0],
+            projections: vec![1.0, 0.0, 0.0, 0.0,
+                              0.2, 0.3, 0.3, 0.2],
+            phases: None,
+        };
+        let sel = Selection { label: "1:p".to_string(), iatoms: vec![0], iorbits: vec![1, 2, 3] };
+        let broadening = Broadening { gamma0: 0.2, slope: 0.1, max: 1.0 };
+        assert!((broadening.fwhm(2.0) - 0.4).abs() < 1E-12);
+        assert_eq!(broadening.fwhm(20.0), 1.0);
+
+        let xas = XasSpectrum::from_procar(&procar, 0.0, -5.0, 15.0, 4001, broadening, &sel);
+        assert_eq!(xas.onset, Some(2.0));
+        let peak = (0 .. 4001).max_by(|&i, &j| xas.intensity[0][i].partial_cmp(&xas.intensity[0][j]).unwrap()).unwrap();
+        assert!((xas.energies[peak] - 2.0).abs() < 1E-8);
+
+        // Height of the Lorentzian with 2 electrons * 0.8 of p character
+        let height = 2.0 * 0.8 / (std::f64::consts::PI * 0.2);
+        assert!((xas.intensity[0][peak] - height).abs() < 1E-8);
+        assert_eq!("L23".parse::<Edge>().unwrap().orbits(), "s d");
+    }
+}
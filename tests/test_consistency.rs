@@ -17,9 +17,10 @@ fn test_normal_outcar() -> io::Result<()> {
     let fname = get_fpath_in_current_dir!("OUTCAR_multiple_ionic_steps");
     let outcar = Outcar::from_file(&fname)?;
 
-    assert_eq!(outcar.lsorbit, false);
+    assert!(!outcar.lsorbit);
     assert_eq!(outcar.ispin, 1);
     assert_eq!(outcar.ibrion, 1);
+    assert_eq!(outcar.encut, 400.0);
     assert_eq!(outcar.nions, 32);
     assert_eq!(outcar.nkpts, 20);
     assert_eq!(outcar.nbands, 81);
@@ -33,11 +34,12 @@ fn test_normal_outcar() -> io::Result<()> {
     assert_eq!(outcar.ion_iters.len(), 5);
     assert_eq!(outcar.vib, None);
     outcar.ion_iters.iter()
-                    .zip(vec![14i32, 8, 7, 8, 7].iter())
+                    .zip([14i32, 8, 7, 8, 7].iter())
                     .for_each(|(x, y)| assert_eq!(&x.nscf, y));
+    assert!(outcar.ion_iters.iter().all(|x| x.scf_de.len() == x.nscf as usize));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-253.61858820,
+                    .zip([-253.61858820,
                               -253.61023247,
                               -253.61629491,
                               -253.58960211,
@@ -45,7 +47,7 @@ fn test_normal_outcar() -> io::Result<()> {
                     .for_each(|(x, y)| assert_eq!(&x.toten, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-253.61858820,
+                    .zip([-253.61858820,
                               -253.61023247,
                               -253.61629491,
                               -253.58960211,
@@ -53,7 +55,7 @@ fn test_normal_outcar() -> io::Result<()> {
                     .for_each(|(x, y)| assert_eq!(&x.toten_z, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-18.05, 21.53, -2.72, -5.24, -0.30].iter())
+                    .zip([-18.05, 21.53, -2.72, -5.24, -0.30].iter())
                     .for_each(|(x, y)| assert_eq!(&x.stress, y));
 
     assert_eq!(&outcar.ion_iters.last().unwrap().cell, &[[7.494265554, 0.000000000, -0.000000000],
@@ -66,6 +68,9 @@ fn test_normal_outcar() -> io::Result<()> {
                .forces.last().unwrap(), &[-0.000716, -0.000716, -0.000716]);
 
     assert!(outcar.ion_iters.iter().all(|i| i.magmom.is_none()));
+    assert_eq!(outcar.ion_magmoms, None);
+    assert_eq!(outcar.elastic, None);
+    assert_eq!(outcar.dielectric, None);
     Ok(())
 }
 
@@ -75,7 +80,7 @@ fn test_unfinished_outcar() -> io::Result<()> {
     let fname = get_fpath_in_current_dir!("OUTCAR_unfinished");
     let outcar = Outcar::from_file(&fname)?;
 
-    assert_eq!(outcar.lsorbit, false);
+    assert!(!outcar.lsorbit);
     assert_eq!(outcar.ispin, 1);
     assert_eq!(outcar.ibrion, 1);
     assert_eq!(outcar.nions, 32);
@@ -92,19 +97,19 @@ fn test_unfinished_outcar() -> io::Result<()> {
     assert_eq!(outcar.vib, None);
 
     outcar.ion_iters.iter()
-                    .zip(vec![14i32].iter())
+                    .zip([14i32].iter())
                     .for_each(|(x, y)| assert_eq!(&x.nscf, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-253.61858820].iter())
+                    .zip([-253.61858820].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-253.61858820].iter())
+                    .zip([-253.61858820].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten_z, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-18.05].iter())
+                    .zip([-18.05].iter())
                     .for_each(|(x, y)| assert_eq!(&x.stress, y));
 
     assert_eq!(&outcar.ion_iters.last().unwrap().cell, &[[7.519999981,         0.0,         0.0],
@@ -117,6 +122,7 @@ fn test_unfinished_outcar() -> io::Result<()> {
                .forces.last().unwrap(), &[0.0; 3]);
 
     assert!(outcar.ion_iters.iter().all(|i| i.magmom.is_none()));
+    assert_eq!(outcar.ion_magmoms, None);
     Ok(())
 }
 
@@ -126,7 +132,7 @@ fn test_ispin2_outcar() -> io::Result<()> {
     let fname = get_fpath_in_current_dir!("OUTCAR_ispin2");
     let outcar = Outcar::from_file(&fname)?;
 
-    assert_eq!(outcar.lsorbit, false);
+    assert!(!outcar.lsorbit);
     assert_eq!(outcar.ispin, 2);
     assert_eq!(outcar.ibrion, 1);
     assert_eq!(outcar.nions, 3);
@@ -141,25 +147,29 @@ fn test_ispin2_outcar() -> io::Result<()> {
     assert_eq!(outcar.ion_masses, vec![78.96, 78.96, 50.941]);
     assert_eq!(outcar.ion_iters.len(), 3);
     assert_eq!(outcar.vib, None);
+    assert_eq!(outcar.eigvals.len(), 2 * 41 * 16);
+    assert_eq!(outcar.eigval(0, 0, 0), -16.8270);
+    assert_eq!(outcar.eigval(1, 0, 1), -15.9328);
+    assert_eq!(outcar.occupation(0, 0, 9), 0.0);
 
     outcar.ion_iters.iter()
-                    .zip(vec![27i32, 6, 4].iter())
+                    .zip([27i32, 6, 4].iter())
                     .for_each(|(x, y)| assert_eq!(&x.nscf, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-18.95794080,
+                    .zip([-18.95794080,
                               -18.95854979,
                               -18.95862392].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-18.95729223,
+                    .zip([-18.95729223,
                               -18.95789288,
                               -18.95796667].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten_z, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-0.68, -1.59, -1.61].iter())
+                    .zip([-0.68, -1.59, -1.61].iter())
                     .for_each(|(x, y)| assert_eq!(&x.stress, y));
 
     assert_eq!(outcar.ion_iters.last().unwrap()
@@ -168,11 +178,16 @@ fn test_ispin2_outcar() -> io::Result<()> {
                .forces.last().unwrap(), &[0.000000, 0.00000 , -0.000349]);
 
     outcar.ion_iters.iter()
-                    .zip(vec![Some(vec![0.6003306]),
+                    .zip([Some(vec![0.6003306]),
                               Some(vec![0.5997977]),
                               Some(vec![0.5995733])].iter())
                     .for_each(|(x, y)| assert_eq!(&x.magmom, y));
 
+    let mag = outcar.ion_magmoms.as_ref().unwrap();
+    assert_eq!(mag.orbitals, vec!["s", "p", "d"]);
+    assert_eq!(mag.totals(), vec![vec![-0.037], vec![-0.037], vec![0.650]]);
+    assert_eq!(outcar.core_pots, vec![-60.7404, -60.7404, -48.8189]);
+
     Ok(())
 }
 
@@ -182,7 +197,7 @@ fn test_ncl_outcar() -> io::Result<()> {
     let fname = get_fpath_in_current_dir!("OUTCAR_ncl");
     let outcar = Outcar::from_file(&fname)?;
 
-    assert_eq!(outcar.lsorbit, true);
+    assert!(outcar.lsorbit);
     assert_eq!(outcar.ispin, 1);
     assert_eq!(outcar.ibrion, -1);
     assert_eq!(outcar.nions, 3);
@@ -199,19 +214,19 @@ fn test_ncl_outcar() -> io::Result<()> {
     assert_eq!(outcar.vib, None);
 
     outcar.ion_iters.iter()
-                    .zip(vec![31i32].iter())
+                    .zip([31i32].iter())
                     .for_each(|(x, y)| assert_eq!(&x.nscf, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-19.00260977].iter())
+                    .zip([-19.00260977].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-19.00194579].iter())
+                    .zip([-19.00194579].iter())
                     .for_each(|(x, y)| assert_eq!(&x.toten_z, y));
 
     outcar.ion_iters.iter()
-                    .zip(vec![-1.77].iter())
+                    .zip([-1.77].iter())
                     .for_each(|(x, y)| assert_eq!(&x.stress, y));
 
     assert_eq!(outcar.ion_iters.last().unwrap().positions, vec![[1.90969, -0.00000, 2.55994],
@@ -223,8 +238,13 @@ fn test_ncl_outcar() -> io::Result<()> {
                                                              [ 0.000006, -0.000003, -0.000618]]);
 
     outcar.ion_iters.iter()
-                    .zip(vec![Some(vec![ 0.0000227, -0.0001244,  0.5998908])].iter())
+                    .zip([Some(vec![ 0.0000227, -0.0001244,  0.5998908])].iter())
                     .for_each(|(x, y)| assert_eq!(&x.magmom, y));
+
+    let mag = outcar.ion_magmoms.as_ref().unwrap();
+    assert_eq!(mag.ncomp(), 3);
+    assert_eq!(mag.totals()[2], vec![0.0, -0.0, 0.648]);
+    assert_eq!(outcar.core_pots, vec![-60.7254, -60.7254, -48.8031]);
     Ok(())
 }
 
@@ -233,7 +253,7 @@ fn test_vib_outcar() -> io::Result<()> {
     let fname = get_fpath_in_current_dir!("OUTCAR_vibrations");
     let outcar = Outcar::from_file(&fname)?;
 
-    assert_eq!(outcar.lsorbit, false);
+    assert!(!outcar.lsorbit);
     assert_eq!(outcar.ispin, 1);
     assert_eq!(outcar.ibrion, 5);
     assert_eq!(outcar.nions, 4);
@@ -249,17 +269,17 @@ fn test_vib_outcar() -> io::Result<()> {
     assert_eq!(outcar.ion_iters.len(), 25);
 
     outcar.vib.as_ref().unwrap().iter()
-                                .zip(vec![3627.910256, 3620.673620, 3431.763448,
+                                .zip([3627.910256, 3620.673620, 3431.763448,
                                           1551.740811, 1537.186276,  388.963336,
                                            370.876616,  370.090822,    0.658347,
                                              0.752260,    1.873335,  702.438182].iter())
                                 .for_each(|(x, y)| assert_eq!(&x.freq, y));
 
     outcar.vib.as_ref().unwrap().iter()
-                                .zip(vec![false; 9].iter().chain(vec![true; 3].iter()))
+                                .zip([false; 9].iter().chain([true; 3].iter()))
                                 .for_each(|(x, y)| assert_eq!(&x.is_imagine, y));
 
-    outcar.ion_iters.iter().zip(vec![-6.17, -7.03, -5.27, -6.69, -5.65,
+    outcar.ion_iters.iter().zip([-6.17, -7.03, -5.27, -6.69, -5.65,
                                      -6.18, -6.18, -6.18, -6.18, -5.11,
                                      -7.16, -6.18, -6.18, -5.27, -7.03,
                                      -6.68, -5.65, -6.18, -6.18, -6.13,
@@ -276,3 +296,29 @@ fn test_vib_outcar() -> io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_concatenated_outcar() -> io::Result<()> {
+    let first = std::fs::read_to_string(get_fpath_in_current_dir!("OUTCAR_unfinished"))?;
+    let second = std::fs::read_to_string(get_fpath_in_current_dir!("OUTCAR_multiple_ionic_steps"))?;
+    let other = std::fs::read_to_string(get_fpath_in_current_dir!("OUTCAR_ispin2"))?;
+    let concat = first.clone() + &second;
+
+    let run1 = Outcar::from_reader_run(io::Cursor::new(first.as_bytes()), None)?;
+    let run2 = Outcar::from_reader_run(io::Cursor::new(second.as_bytes()), None)?;
+    let merged = Outcar::from_reader_run(io::Cursor::new(concat.as_bytes()), None)?;
+    assert_eq!(merged.ion_iters.len(), run1.ion_iters.len() + run2.ion_iters.len());
+    assert_eq!(merged.ion_iters.last(), run2.ion_iters.last());
+    assert_eq!(merged.efermi, run2.efermi);
+
+    let selected = Outcar::from_reader_run(io::Cursor::new(concat.as_bytes()), Some(2))?;
+    assert_eq!(selected, run2);
+    let selected = Outcar::from_reader_run(io::Cursor::new(concat.as_bytes()), Some(1))?;
+    assert_eq!(selected.ion_iters, run1.ion_iters);
+    assert!(Outcar::from_reader_run(io::Cursor::new(concat.as_bytes()), Some(3)).is_err());
+
+    let mixed = second + &other;
+    assert!(Outcar::from_reader_run(io::Cursor::new(mixed.as_bytes()), None).is_err());
+    assert!(Outcar::from_reader_run(io::Cursor::new(mixed.as_bytes()), Some(2)).is_ok());
+    Ok(())
+}
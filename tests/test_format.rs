@@ -5,6 +5,9 @@ use rsgrad::{
     outcar::Outcar,
     format::Trajectory,
     format::Vibrations,
+    format::IonicIterationsFormat,
+    format::PrintAllVibFreqs,
+    traits::Tabular,
 };
 
 use vasp_poscar::Poscar;
@@ -72,3 +75,38 @@ fn test_save_as_single_xsf() -> io::Result<()> {
 
     Ok(())
 }
+
+
+#[test]
+fn test_ionic_iterations_serialize() -> io::Result<()> {
+    let fname = get_fpath_in_current_dir!("OUTCAR_another_rlx");
+    let outcar = Outcar::from_file(&fname)?;
+    let iif = IonicIterationsFormat::from(outcar.ion_iters);
+
+    let steps = iif.steps();
+    let json = serde_json::to_value(&iif).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), steps.len());
+    assert_eq!(json[0]["step"], 1);
+    assert_eq!(json[0]["toten_z"], steps[0].toten_z);
+    assert_eq!(json[0]["stress"][0], -8.87214);
+    assert_eq!(json[0]["nelect"], 44.9999986);
+    assert!((steps[0].volume - steps[0].a * steps[0].b * steps[0].c).abs() > 1.0);  // non-orthogonal cell
+
+    let csv = iif.to_csv();
+    assert_eq!(csv.lines().count(), steps.len() + 1);
+    assert!(csv.starts_with("step,toten,toten_z,"));
+    Ok(())
+}
+
+#[test]
+fn test_vib_freqs_serialize() -> io::Result<()> {
+    let fname = get_fpath_in_current_dir!("OUTCAR_vibrations");
+    let outcar = Outcar::from_file(&fname)?;
+    let paf: PrintAllVibFreqs = Vibrations::from(outcar).into();
+
+    let json = serde_json::to_value(&paf).unwrap();
+    assert_eq!(json[0]["index"], 1);
+    assert_eq!(json[0]["is_imagine"], false);
+    assert_eq!(paf.rows()[0], vec!["1", "3627.91026", "false"]);
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+use std::io;
+use rsgrad::procar::Procar;
+
+macro_rules! get_fpath_in_current_dir {
+    ($fname:expr) => {{
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push($fname);
+        path
+    }}
+}
+
+#[test]
+fn test_ncl_procar() -> io::Result<()> {
+    let fname = get_fpath_in_current_dir!("PROCAR_ncl");
+    let procar = Procar::from_file(&fname)?;
+
+    assert_eq!(procar.nkpts, 4);
+    assert_eq!(procar.nbands, 2);
+    assert_eq!(procar.nions, 2);
+    assert_eq!(procar.nspin, 1);
+    assert!(procar.lncl);
+    assert_eq!(procar.ncomp(), 4);
+    assert_eq!(procar.orbitals, vec!["s", "py", "pz", "px", "dxy", "dyz", "dz2", "dxz", "x2-y2"]);
+    assert_eq!(procar.kpoints[3], [0.5, 0.5, 0.0]);
+    assert_eq!(procar.weights, vec![0.25; 4]);
+    assert_eq!(procar.eigval(0, 1, 1), 1.25);
+    assert_eq!(procar.occupation(0, 1, 1), 0.0);
+    assert_eq!(procar.projection(0, 0, 0, 0, 3), 0.1);
+
+    let st = procar.spin_texture(0);
+    assert_eq!(st.len(), 4);
+    assert!((st[0][0] - (-0.176)).abs() < 1E-6);
+    assert!((st[0][2] - (-0.075)).abs() < 1E-6);
+    Ok(())
+}